@@ -0,0 +1,591 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! Command line arguments for pkba
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use packybara::db::add::versionpins::ConflictPolicy;
+use packybara::db::config::PackratConfigError;
+use packybara::db::find_all::changes::ChangeAction;
+use packybara::io::render::Format;
+use packybara::types::{IdType, LongIdType};
+use packybara::{
+    AsOf, LtreeSearchMode, OrderChangeBy, OrderDirection, OrderLevelBy, OrderPlatformBy,
+    OrderRevisionBy, OrderRoleBy, PackratConfig, SearchAttribute, SearchMode,
+};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "pkba", about = "Interact with the packrat database")]
+pub struct Pb {
+    /// Set the log level (error, warn, info, debug or trace)
+    #[structopt(short = "L", long = "loglevel")]
+    pub loglevel: Option<String>,
+    /// The database host. Overrides PACKRAT_HOST and the config file
+    #[structopt(long)]
+    pub host: Option<String>,
+    /// The database port. Overrides PACKRAT_PORT and the config file
+    #[structopt(long)]
+    pub port: Option<u16>,
+    /// The database user. Overrides PACKRAT_USER and the config file
+    #[structopt(long)]
+    pub user: Option<String>,
+    /// The database name. Overrides PACKRAT_DBNAME and the config file
+    #[structopt(long)]
+    pub dbname: Option<String>,
+    /// The output format of find (table, json, jsonl, csv, tsv or yaml)
+    #[structopt(short = "f", long, default_value = "table")]
+    pub format: Format,
+    #[structopt(subcommand)]
+    pub crud: PbCrud,
+}
+
+impl Pb {
+    /// Load the PackratConfig, applying the connection arguments
+    /// supplied on the command line
+    pub fn config(&self) -> Result<PackratConfig, PackratConfigError> {
+        let mut config = PackratConfig::load()?;
+        if let Some(ref host) = self.host {
+            config.host = host.clone();
+        }
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+        if let Some(ref user) = self.user {
+            config.user = user.clone();
+        }
+        if let Some(ref dbname) = self.dbname {
+            config.dbname = dbname.clone();
+        }
+        Ok(config)
+    }
+}
+
+#[derive(StructOpt, Debug)]
+pub enum PbCrud {
+    /// Search the database
+    #[structopt(name = "find")]
+    Find {
+        #[structopt(subcommand)]
+        cmd: PbFind,
+    },
+    /// Add entities to the database
+    #[structopt(name = "add")]
+    Add {
+        #[structopt(subcommand)]
+        cmd: PbAdd,
+    },
+    /// Update entities in the database
+    #[structopt(name = "update")]
+    Update {
+        #[structopt(subcommand)]
+        cmd: PbUpdate,
+    },
+    /// Export data from the database
+    #[structopt(name = "export")]
+    Export {
+        #[structopt(subcommand)]
+        cmd: PbExport,
+    },
+    /// Import data into the database
+    #[structopt(name = "import")]
+    Import {
+        #[structopt(subcommand)]
+        cmd: PbImport,
+    },
+    /// Apply any pending schema migrations to the database
+    #[structopt(name = "migrate")]
+    Migrate {
+        /// Report the schema version, and the pending migrations, without
+        /// applying them
+        #[structopt(long)]
+        check: bool,
+        /// Record the migrations up to this version as applied, without
+        /// running them, to adopt a database created before migrations existed
+        #[structopt(long, conflicts_with = "check")]
+        baseline: Option<i32>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum PbFind {
+    /// Find the versionpin which best matches the supplied coordinates
+    #[structopt(name = "versionpin")]
+    VersionPin {
+        /// The name of the package
+        package: String,
+        #[structopt(flatten)]
+        coords: CoordsArgs,
+        /// Resolve against the state as of a revision id or date (YYYY-MM-DD[ HH:MM:SS])
+        #[structopt(long = "as-of", parse(try_from_str = parse_as_of))]
+        as_of: Option<AsOf>,
+    },
+    /// Find the versionpins for a package which match the supplied coordinates
+    #[structopt(name = "versionpins")]
+    VersionPins {
+        /// The name of the package
+        package: String,
+        #[structopt(flatten)]
+        coords: CoordsArgs,
+        /// Comma separated list of attributes to order by
+        #[structopt(short = "o", long = "order-by", use_delimiter = true)]
+        order_by: Option<Vec<SearchAttribute>>,
+        /// The direction to order by (asc or desc)
+        #[structopt(long = "order-direction")]
+        order_direction: Option<OrderDirection>,
+        /// Resolve against the state as of a revision id or date (YYYY-MM-DD[ HH:MM:SS])
+        #[structopt(long = "as-of", parse(try_from_str = parse_as_of))]
+        as_of: Option<AsOf>,
+    },
+    /// Find all versionpins which match the supplied criteria
+    #[structopt(name = "all-versionpins")]
+    AllVersionPins {
+        /// The name of the package
+        #[structopt(short = "P", long)]
+        package: Option<String>,
+        /// The version of the package
+        #[structopt(short = "V", long)]
+        version: Option<String>,
+        #[structopt(flatten)]
+        coords: CoordsArgs,
+        /// Only return versionpins at the facility level when the level is facility
+        #[structopt(long = "isolate-facility")]
+        isolate_facility: bool,
+        /// The manner in which to search the levels (ancestor, descendant or exact)
+        #[structopt(long = "search-mode")]
+        search_mode: Option<LtreeSearchMode>,
+        /// Comma separated list of attributes to order by
+        #[structopt(short = "o", long = "order-by", use_delimiter = true)]
+        order_by: Option<Vec<SearchAttribute>>,
+        /// The direction to order by (asc or desc)
+        #[structopt(long = "order-direction")]
+        order_direction: Option<OrderDirection>,
+        /// The maximum number of results to return
+        #[structopt(long)]
+        limit: Option<IdType>,
+        /// Resolve against the state as of a revision id or date (YYYY-MM-DD[ HH:MM:SS])
+        #[structopt(long = "as-of", parse(try_from_str = parse_as_of))]
+        as_of: Option<AsOf>,
+    },
+    /// Find the withs of a versionpin, given its id
+    #[structopt(name = "versionpin-withs")]
+    VersionPinWiths {
+        /// The id of the versionpin
+        versionpin_id: IdType,
+    },
+    /// Find the distinct coordinates of versionpins
+    #[structopt(name = "pins")]
+    Pins {
+        #[structopt(flatten)]
+        coords: CoordsArgs,
+        /// The manner in which to search the levels (ancestor, descendant or exact)
+        #[structopt(long = "search-mode")]
+        search_mode: Option<LtreeSearchMode>,
+        /// Comma separated list of attributes to order by
+        #[structopt(short = "o", long = "order-by", use_delimiter = true)]
+        order_by: Option<Vec<SearchAttribute>>,
+        /// The direction to order by (asc or desc)
+        #[structopt(long = "order-direction")]
+        order_direction: Option<OrderDirection>,
+        /// The maximum number of results to return
+        #[structopt(long)]
+        limit: Option<IdType>,
+    },
+    /// Find the pkgcoords which match the supplied criteria
+    #[structopt(name = "pkgcoords")]
+    PkgCoords {
+        /// The name of the package
+        #[structopt(short = "P", long)]
+        package: Option<String>,
+        #[structopt(flatten)]
+        coords: CoordsArgs,
+        /// The manner in which to search (equal, like, ancestor, descendant or exact)
+        #[structopt(long = "search-mode", parse(try_from_str = SearchMode::try_from_str))]
+        search_mode: Option<SearchMode>,
+        /// Comma separated list of attributes to order by
+        #[structopt(short = "o", long = "order-by")]
+        order_by: Option<String>,
+    },
+    /// Find the withs of the versionpins for a package
+    #[structopt(name = "withs")]
+    Withs {
+        /// The name of the package
+        package: String,
+        #[structopt(flatten)]
+        coords: CoordsArgs,
+        /// Comma separated list of attributes to order by
+        #[structopt(short = "o", long = "order-by", use_delimiter = true)]
+        order_by: Option<Vec<SearchAttribute>>,
+        /// The direction to order by (asc or desc)
+        #[structopt(long = "order-direction")]
+        order_direction: Option<OrderDirection>,
+    },
+    /// Find revisions
+    #[structopt(name = "revisions")]
+    Revisions {
+        /// The id of the revision
+        #[structopt(long)]
+        id: Option<IdType>,
+        /// The transaction id of the revision
+        #[structopt(short = "t", long = "transaction-id")]
+        transaction_id: Option<LongIdType>,
+        /// The author of the revision
+        #[structopt(short = "a", long)]
+        author: Option<String>,
+        /// Comma separated list of attributes to order by
+        #[structopt(short = "o", long = "order-by", use_delimiter = true)]
+        order_by: Option<Vec<OrderRevisionBy>>,
+        /// The direction to order by (asc or desc)
+        #[structopt(long = "order-direction")]
+        order_direction: Option<OrderDirection>,
+        /// The maximum number of results to return
+        #[structopt(long)]
+        limit: Option<IdType>,
+    },
+    /// Find the changes made to versionpins
+    #[structopt(name = "changes")]
+    Changes {
+        /// The transaction id
+        transaction_id: Option<LongIdType>,
+        /// The package whose changes are returned
+        #[structopt(short = "P", long)]
+        package: Option<String>,
+        #[structopt(flatten)]
+        coords: CoordsArgs,
+        /// The manner in which to search the coords (ancestor, descendant or exact)
+        #[structopt(long = "search-mode")]
+        search_mode: Option<LtreeSearchMode>,
+        /// The action (insert, update or delete)
+        #[structopt(long)]
+        action: Option<ChangeAction>,
+        /// The author of the revisions
+        #[structopt(short = "a", long)]
+        author: Option<String>,
+        /// Return changes made at, or after, the date (YYYY-MM-DD or YYYY-MM-DD HH:MM:SS)
+        #[structopt(long, parse(try_from_str = parse_datetime))]
+        since: Option<DateTime<Local>>,
+        /// Return changes made before the date (YYYY-MM-DD or YYYY-MM-DD HH:MM:SS)
+        #[structopt(long, parse(try_from_str = parse_datetime))]
+        until: Option<DateTime<Local>>,
+        /// Comma separated list of attributes to order by
+        #[structopt(short = "o", long = "order-by", use_delimiter = true)]
+        order_by: Option<Vec<OrderChangeBy>>,
+        /// The direction to order by (asc or desc)
+        #[structopt(long = "order-direction")]
+        order_direction: Option<OrderDirection>,
+        /// The maximum number of results to return
+        #[structopt(long)]
+        limit: Option<IdType>,
+    },
+    /// Find levels
+    #[structopt(name = "levels")]
+    Levels {
+        /// The level to search below
+        #[structopt(short = "l", long)]
+        level: Option<String>,
+        /// The show to search within
+        #[structopt(long)]
+        show: Option<String>,
+        /// The maximum depth of the levels returned
+        #[structopt(long)]
+        depth: Option<u8>,
+        /// Comma separated list of attributes to order by
+        #[structopt(short = "o", long = "order-by", use_delimiter = true)]
+        order_by: Option<Vec<OrderLevelBy>>,
+        /// The direction to order by (asc or desc)
+        #[structopt(long = "order-direction")]
+        order_direction: Option<OrderDirection>,
+        /// The maximum number of results to return
+        #[structopt(long)]
+        limit: Option<IdType>,
+    },
+    /// Find roles
+    #[structopt(name = "roles")]
+    Roles {
+        /// The role to search for
+        #[structopt(short = "r", long)]
+        role: Option<String>,
+        /// The category of roles (role, subrole or any)
+        #[structopt(long)]
+        category: Option<String>,
+        /// Comma separated list of attributes to order by
+        #[structopt(short = "o", long = "order-by", use_delimiter = true)]
+        order_by: Option<Vec<OrderRoleBy>>,
+        /// The direction to order by (asc or desc)
+        #[structopt(long = "order-direction")]
+        order_direction: Option<OrderDirection>,
+        /// The maximum number of results to return
+        #[structopt(long)]
+        limit: Option<IdType>,
+    },
+    /// Find platforms
+    #[structopt(name = "platforms")]
+    Platforms {
+        /// The platform to search for
+        name: Option<String>,
+        /// Comma separated list of attributes to order by
+        #[structopt(short = "o", long = "order-by", use_delimiter = true)]
+        order_by: Option<Vec<OrderPlatformBy>>,
+        /// The direction to order by (asc or desc)
+        #[structopt(long = "order-direction")]
+        order_direction: Option<OrderDirection>,
+        /// The maximum number of results to return
+        #[structopt(long)]
+        limit: Option<IdType>,
+    },
+    /// Find sites
+    #[structopt(name = "sites")]
+    Sites {
+        /// The site to search for
+        name: Option<String>,
+    },
+    /// Find packages
+    #[structopt(name = "packages")]
+    Packages,
+    /// Find distributions
+    #[structopt(name = "distributions")]
+    Distributions {
+        /// The name of the package
+        #[structopt(short = "P", long)]
+        package: Option<String>,
+        /// The version of the package
+        #[structopt(short = "V", long)]
+        version: Option<String>,
+        /// The direction to order by (asc or desc)
+        #[structopt(long = "order-direction")]
+        order_direction: Option<OrderDirection>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum PbAdd {
+    /// Add packages
+    #[structopt(name = "packages")]
+    Packages {
+        /// The names of the packages
+        #[structopt(required = true)]
+        names: Vec<String>,
+        #[structopt(flatten)]
+        revision: RevisionArgs,
+    },
+    /// Add levels (eg dev01.rd.9999)
+    #[structopt(name = "levels")]
+    Levels {
+        /// The names of the levels
+        #[structopt(required = true)]
+        names: Vec<String>,
+        #[structopt(flatten)]
+        revision: RevisionArgs,
+    },
+    /// Add roles
+    #[structopt(name = "roles")]
+    Roles {
+        /// The names of the roles
+        #[structopt(required = true)]
+        names: Vec<String>,
+        #[structopt(flatten)]
+        revision: RevisionArgs,
+    },
+    /// Add platforms
+    #[structopt(name = "platforms")]
+    Platforms {
+        /// The names of the platforms
+        #[structopt(required = true)]
+        names: Vec<String>,
+        #[structopt(flatten)]
+        revision: RevisionArgs,
+    },
+    /// Add sites
+    #[structopt(name = "sites")]
+    Sites {
+        /// The names of the sites
+        #[structopt(required = true)]
+        names: Vec<String>,
+        #[structopt(flatten)]
+        revision: RevisionArgs,
+    },
+    /// Add distributions (eg maya-2018.sp3)
+    #[structopt(name = "distributions")]
+    Distributions {
+        /// The names of the distributions
+        #[structopt(required = true)]
+        names: Vec<String>,
+        #[structopt(flatten)]
+        revision: RevisionArgs,
+    },
+    /// Set the withs of a versionpin, replacing any existing withs
+    #[structopt(name = "withs")]
+    Withs {
+        /// The id of the versionpin
+        versionpin_id: IdType,
+        /// The names of the with packages, in order
+        #[structopt(required = true)]
+        withs: Vec<String>,
+        #[structopt(flatten)]
+        revision: RevisionArgs,
+    },
+    /// Add versionpins for a distribution at every combination of the
+    /// supplied levels, roles, platforms and sites
+    #[structopt(name = "versionpins")]
+    VersionPins {
+        /// The distribution (eg maya-2018.sp3)
+        distribution: String,
+        /// Comma separated list of levels
+        #[structopt(short = "l", long, use_delimiter = true, default_value = "facility")]
+        levels: Vec<String>,
+        /// Comma separated list of roles
+        #[structopt(short = "r", long, use_delimiter = true, default_value = "any")]
+        roles: Vec<String>,
+        /// Comma separated list of platforms
+        #[structopt(short = "p", long, use_delimiter = true, default_value = "any")]
+        platforms: Vec<String>,
+        /// Comma separated list of sites
+        #[structopt(short = "s", long, use_delimiter = true, default_value = "any")]
+        sites: Vec<String>,
+        /// What to do at coords which are already pinned (skip, overwrite
+        /// or fail). Required, as no policy suits every use
+        #[structopt(long = "on-conflict")]
+        on_conflict: ConflictPolicy,
+        #[structopt(flatten)]
+        revision: RevisionArgs,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum PbUpdate {
+    /// Change the distribution and/or pkgcoord of a versionpin
+    #[structopt(name = "versionpin")]
+    VersionPin {
+        /// The id of the versionpin
+        versionpin_id: IdType,
+        /// The new distribution (eg maya-2018.sp3)
+        #[structopt(short = "d", long, conflicts_with = "distribution-id")]
+        distribution: Option<String>,
+        /// The id of the new distribution
+        #[structopt(long = "distribution-id")]
+        distribution_id: Option<IdType>,
+        /// The id of the new pkgcoord
+        #[structopt(long = "pkgcoord-id")]
+        pkgcoord_id: Option<IdType>,
+        #[structopt(flatten)]
+        revision: RevisionArgs,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum PbExport {
+    /// Export the versionpins of a show to a packages.xml file
+    #[structopt(name = "packagesxml")]
+    PackagesXml {
+        /// The show to export
+        show: String,
+        /// The path of the packages.xml file to write
+        output: String,
+    },
+    /// Export the contents of the database to a json or toml snapshot
+    #[structopt(name = "snapshot")]
+    Snapshot {
+        /// The path of the snapshot to write, ending in .json or .toml
+        output: String,
+        /// Include the revisions in the snapshot
+        #[structopt(long)]
+        revisions: bool,
+    },
+    /// Export the contents of the database to an SQLite file
+    #[structopt(name = "sqlite")]
+    Sqlite {
+        /// The path of the SQLite file to write, which must not exist
+        output: String,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum PbImport {
+    /// Import the versionpins and withs of a packages.xml file
+    #[structopt(name = "packagesxml")]
+    PackagesXml {
+        /// The path of the packages.xml file to read
+        input: String,
+        /// Create the packages, distributions, levels, roles, platforms and sites which do not exist
+        #[structopt(long = "create-missing")]
+        create_missing: bool,
+        /// Report what the import would do, without changing the database
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
+        #[structopt(flatten)]
+        revision: RevisionArgs,
+    },
+    /// Restore a json or toml snapshot into an empty database
+    #[structopt(name = "snapshot")]
+    Snapshot {
+        /// The path of the snapshot to read, ending in .json or .toml
+        input: String,
+        #[structopt(flatten)]
+        revision: RevisionArgs,
+    },
+}
+
+// The level, role, platform and site used to search
+#[derive(StructOpt, Debug)]
+pub struct CoordsArgs {
+    /// The level (eg dev01.rd.9999)
+    #[structopt(short = "l", long)]
+    pub level: Option<String>,
+    /// The role (eg model)
+    #[structopt(short = "r", long)]
+    pub role: Option<String>,
+    /// The platform (eg cent7_64)
+    #[structopt(short = "p", long)]
+    pub platform: Option<String>,
+    /// The site (eg portland)
+    #[structopt(short = "s", long)]
+    pub site: Option<String>,
+}
+
+// The author and comment recorded with the revision
+#[derive(StructOpt, Debug)]
+pub struct RevisionArgs {
+    /// The author of the revision. Defaults to the current user
+    #[structopt(short = "a", long)]
+    pub author: Option<String>,
+    /// A comment describing the revision
+    #[structopt(short = "c", long)]
+    pub comment: String,
+}
+
+impl RevisionArgs {
+    /// Retrieve the author, falling back on the current user
+    pub fn author(&self) -> String {
+        self.author.clone().unwrap_or_else(whoami::username)
+    }
+}
+
+/// Parse a revision id, or a date in the form `YYYY-MM-DD` or
+/// `YYYY-MM-DD HH:MM:SS`, interpreted in the local timezone
+pub fn parse_as_of(input: &str) -> Result<AsOf, String> {
+    if let Ok(revision) = input.parse::<IdType>() {
+        return Ok(AsOf::from(revision));
+    }
+    parse_datetime(input)
+        .map(AsOf::from)
+        .map_err(|_| format!("'{}' is neither a revision id nor a date", input))
+}
+
+/// Parse an rfc3339 datetime, or a date in the form `YYYY-MM-DD` or
+/// `YYYY-MM-DD HH:MM:SS`, interpreted in the local timezone
+pub fn parse_datetime(input: &str) -> Result<DateTime<Local>, String> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(input) {
+        return Ok(datetime.with_timezone(&Local));
+    }
+    let naive = NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDate::parse_from_str(input, "%Y-%m-%d").map(|d| d.and_hms(0, 0, 0)))
+        .map_err(|_| format!("'{}' is not a date", input))?;
+    Local
+        .from_local_datetime(&naive)
+        .single()
+        .ok_or_else(|| format!("'{}' is an ambiguous local time", input))
+}
@@ -0,0 +1,99 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+use crate::args::PbAdd;
+use packybara::packrat::{Client, PackratDb};
+use packybara::traits::{PBAdd, TransactionHandler};
+use packybara::Distribution;
+use std::error::Error;
+
+/// Run the supplied add subcommand within a single transaction, committing
+/// it as a new revision and printing the number of entities added
+pub fn run(client: &mut Client, cmd: PbAdd) -> Result<(), Box<dyn Error>> {
+    let mut db = PackratDb::new(client);
+    let tx = db.transaction();
+    let results = match cmd {
+        PbAdd::Packages {
+            mut names,
+            revision,
+        } => PackratDb::add_packages(tx)
+            .packages(&mut names)
+            .create()?
+            .commit(&revision.author(), &revision.comment)?,
+        PbAdd::Levels {
+            mut names,
+            revision,
+        } => PackratDb::add_levels(tx)
+            .levels(&mut names)
+            .create()?
+            .commit(&revision.author(), &revision.comment)?,
+        PbAdd::Roles {
+            mut names,
+            revision,
+        } => PackratDb::add_roles(tx)
+            .roles(&mut names)
+            .create()?
+            .commit(&revision.author(), &revision.comment)?,
+        PbAdd::Platforms {
+            mut names,
+            revision,
+        } => PackratDb::add_platforms(tx)
+            .platforms(&mut names)
+            .create()?
+            .commit(&revision.author(), &revision.comment)?,
+        PbAdd::Sites {
+            mut names,
+            revision,
+        } => PackratDb::add_sites(tx)
+            .sites(&mut names)
+            .create()?
+            .commit(&revision.author(), &revision.comment)?,
+        PbAdd::Distributions {
+            mut names,
+            revision,
+        } => PackratDb::add_distributions(tx)
+            .distributions(&mut names)
+            .create()?
+            .commit(&revision.author(), &revision.comment)?,
+        PbAdd::Withs {
+            versionpin_id,
+            withs,
+            revision,
+        } => PackratDb::add_withs(tx)
+            .create(versionpin_id, withs)?
+            .commit(&revision.author(), &revision.comment)?,
+        PbAdd::VersionPins {
+            distribution,
+            mut levels,
+            mut roles,
+            mut platforms,
+            mut sites,
+            on_conflict,
+            revision,
+        } => {
+            let distribution = Distribution::new(distribution)?;
+            let mut pins = PackratDb::add_versionpins(
+                tx,
+                distribution.package().to_string(),
+                distribution.version().to_string(),
+                on_conflict,
+            )
+            .levels(&mut levels)
+            .roles(&mut roles)
+            .platforms(&mut platforms)
+            .sites(&mut sites)
+            .create()?;
+            for outcome in pins.outcomes() {
+                println!("{}", outcome);
+            }
+            pins.commit(&revision.author(), &revision.comment)?
+        }
+    };
+    println!("{}", results);
+    Ok(())
+}
@@ -0,0 +1,30 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+use crate::args::PbExport;
+use packybara::packrat::{Client, PackratDb};
+use packybara::traits::PBExport;
+use std::error::Error;
+
+/// Run the supplied export subcommand
+pub fn run(client: &mut Client, cmd: PbExport) -> Result<(), Box<dyn Error>> {
+    let mut db = PackratDb::new(client);
+    match cmd {
+        PbExport::PackagesXml { show, output } => {
+            db.export_packages(&show, &output)?;
+        }
+        PbExport::Snapshot { output, revisions } => {
+            db.snapshot().revisions(revisions).take()?.write(&output)?;
+        }
+        PbExport::Sqlite { output } => {
+            let cnt = db.export_sqlite().export(&output)?;
+            println!("Exported {} rows to {}", cnt, output);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,650 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+use crate::args::PbFind;
+use packybara::db::find::{
+    pins::FindPins, versionpin::FindVersionPin, versionpins::FindVersionPins, withs::FindWiths,
+};
+use packybara::db::find_all::{
+    changes::FindAllChanges, distributions::FindAllDistributions, levels::FindAllLevels,
+    packages::FindAllPackages, pkgcoords::FindAllPkgCoords, platforms::FindAllPlatforms,
+    revisions::FindAllRevisions, roles::FindAllRoles, sites::FindAllSites,
+    versionpin_withs::FindAllWiths, versionpins::FindAllVersionPins,
+};
+use packybara::io::render::{Format, Render};
+use packybara::packrat::{Client, PackratDb};
+use packybara::traits::PBFind;
+use std::error::Error;
+
+/// The find builder of a subcommand, configured from its arguments
+pub enum Finder<'a, C> {
+    VersionPin(FindVersionPin<'a, C>),
+    VersionPins(FindVersionPins<'a, C>),
+    AllVersionPins(FindAllVersionPins<'a, C>),
+    VersionPinWiths(FindAllWiths<'a, C>),
+    Pins(FindPins<'a, C>),
+    PkgCoords(FindAllPkgCoords<'a, C>),
+    Withs(FindWiths<'a, C>),
+    Revisions(FindAllRevisions<'a, C>),
+    Changes(FindAllChanges<'a, C>),
+    Levels(FindAllLevels<'a, C>),
+    Roles(FindAllRoles<'a, C>),
+    Platforms(FindAllPlatforms<'a, C>),
+    Sites(FindAllSites<'a, C>),
+    Packages(FindAllPackages<'a, C>),
+    Distributions(FindAllDistributions<'a, C>),
+}
+
+/// Configure the find builder of the supplied subcommand
+pub fn finder<'a, D: PBFind>(db: &'a mut D, cmd: &'a PbFind) -> Finder<'a, D::Client> {
+    match cmd {
+        PbFind::VersionPin {
+            package,
+            coords,
+            as_of,
+        } => {
+            let mut builder = db.find_versionpin(package);
+            builder
+                .level_opt(coords.level.as_deref())
+                .role_opt(coords.role.as_deref())
+                .platform_opt(coords.platform.as_deref())
+                .site_opt(coords.site.as_deref())
+                .as_of_opt(as_of.clone());
+            Finder::VersionPin(builder)
+        }
+        PbFind::VersionPins {
+            package,
+            coords,
+            order_by,
+            order_direction,
+            as_of,
+        } => {
+            let mut builder = db.find_versionpins(package);
+            builder
+                .level_opt(coords.level.as_deref())
+                .role_opt(coords.role.as_deref())
+                .platform_opt(coords.platform.as_deref())
+                .site_opt(coords.site.as_deref())
+                .as_of_opt(as_of.clone());
+            if let Some(order_by) = order_by {
+                builder.order_by(order_by.clone());
+            }
+            if let Some(order_direction) = order_direction {
+                builder.order_direction(*order_direction);
+            }
+            Finder::VersionPins(builder)
+        }
+        PbFind::AllVersionPins {
+            package,
+            version,
+            coords,
+            isolate_facility,
+            search_mode,
+            order_by,
+            order_direction,
+            limit,
+            as_of,
+        } => {
+            let mut builder = db.find_all_versionpins();
+            builder
+                .package_opt(package.as_deref())
+                .version_opt(version.as_deref())
+                .level_opt(coords.level.as_deref())
+                .role_opt(coords.role.as_deref())
+                .platform_opt(coords.platform.as_deref())
+                .site_opt(coords.site.as_deref())
+                .isolate_facility(*isolate_facility)
+                .search_mode_opt(*search_mode)
+                .order_by_opt(order_by.clone())
+                .order_direction_opt(*order_direction)
+                .as_of_opt(as_of.clone());
+            if let Some(limit) = limit {
+                builder.limit(*limit);
+            }
+            Finder::AllVersionPins(builder)
+        }
+        PbFind::VersionPinWiths { versionpin_id } => {
+            Finder::VersionPinWiths(db.find_all_versionpin_withs(*versionpin_id))
+        }
+        PbFind::Pins {
+            coords,
+            search_mode,
+            order_by,
+            order_direction,
+            limit,
+        } => {
+            let mut builder = db.find_pins();
+            builder
+                .level_opt(coords.level.as_deref())
+                .role_opt(coords.role.as_deref())
+                .platform_opt(coords.platform.as_deref())
+                .site_opt(coords.site.as_deref());
+            if let Some(search_mode) = search_mode {
+                builder.search_mode(*search_mode);
+            }
+            if let Some(order_by) = order_by {
+                builder.order_by(order_by.clone());
+            }
+            if let Some(order_direction) = order_direction {
+                builder.order_direction(*order_direction);
+            }
+            if let Some(limit) = limit {
+                builder.limit(*limit);
+            }
+            Finder::Pins(builder)
+        }
+        PbFind::PkgCoords {
+            package,
+            coords,
+            search_mode,
+            order_by,
+        } => {
+            let mut builder = db.find_pkgcoords();
+            builder
+                .package_opt(package.as_deref())
+                .level_opt(coords.level.as_deref())
+                .role_opt(coords.role.as_deref())
+                .platform_opt(coords.platform.as_deref())
+                .site_opt(coords.site.as_deref())
+                .order_by_opt(order_by.as_deref());
+            if let Some(search_mode) = search_mode {
+                builder.search_mode(*search_mode);
+            }
+            Finder::PkgCoords(builder)
+        }
+        PbFind::Withs {
+            package,
+            coords,
+            order_by,
+            order_direction,
+        } => {
+            let mut builder = db.find_withs(package);
+            builder
+                .level_opt(coords.level.as_deref())
+                .role_opt(coords.role.as_deref())
+                .platform_opt(coords.platform.as_deref())
+                .site_opt(coords.site.as_deref())
+                .order_by_opt(order_by.clone())
+                .order_direction_opt(*order_direction);
+            Finder::Withs(builder)
+        }
+        PbFind::Revisions {
+            id,
+            transaction_id,
+            author,
+            order_by,
+            order_direction,
+            limit,
+        } => {
+            let mut builder = db.find_all_revisions();
+            builder
+                .id_opt(*id)
+                .transaction_id_opt(*transaction_id)
+                .author_opt(author.as_deref())
+                .order_direction_opt(*order_direction)
+                .limit_opt(*limit);
+            if let Some(order_by) = order_by {
+                builder.order_by(order_by.clone());
+            }
+            Finder::Revisions(builder)
+        }
+        PbFind::Changes {
+            transaction_id,
+            package,
+            coords,
+            search_mode,
+            action,
+            author,
+            since,
+            until,
+            order_by,
+            order_direction,
+            limit,
+        } => {
+            let mut builder = db.find_all_changes();
+            builder
+                .transaction_id_opt(*transaction_id)
+                .package_opt(package.as_deref())
+                .level_opt(coords.level.as_deref())
+                .role_opt(coords.role.as_deref())
+                .platform_opt(coords.platform.as_deref())
+                .site_opt(coords.site.as_deref())
+                .action_opt(action.clone())
+                .author_opt(author.as_deref())
+                .since_opt(*since)
+                .until_opt(*until)
+                .order_direction_opt(*order_direction)
+                .limit_opt(*limit);
+            if let Some(search_mode) = search_mode {
+                builder.search_mode(*search_mode);
+            }
+            if let Some(order_by) = order_by {
+                builder.order_by(order_by.clone());
+            }
+            Finder::Changes(builder)
+        }
+        PbFind::Levels {
+            level,
+            show,
+            depth,
+            order_by,
+            order_direction,
+            limit,
+        } => {
+            let mut builder = db.find_all_levels();
+            builder
+                .level_opt(level.as_deref())
+                .show_opt(show.as_deref())
+                .depth_opt(*depth);
+            if let Some(order_by) = order_by {
+                builder.order_by(order_by.clone());
+            }
+            if let Some(order_direction) = order_direction {
+                builder.order_direction(*order_direction);
+            }
+            if let Some(limit) = limit {
+                builder.limit(*limit);
+            }
+            Finder::Levels(builder)
+        }
+        PbFind::Roles {
+            role,
+            category,
+            order_by,
+            order_direction,
+            limit,
+        } => {
+            let mut builder = db.find_all_roles();
+            builder
+                .role_opt(role.as_deref())
+                .category_opt(category.as_deref());
+            if let Some(order_by) = order_by {
+                builder.order_by(order_by.clone());
+            }
+            if let Some(order_direction) = order_direction {
+                builder.order_direction(*order_direction);
+            }
+            if let Some(limit) = limit {
+                builder.limit(*limit);
+            }
+            Finder::Roles(builder)
+        }
+        PbFind::Platforms {
+            name,
+            order_by,
+            order_direction,
+            limit,
+        } => {
+            let mut builder = db.find_all_platforms();
+            builder.name_opt(name.as_deref());
+            if let Some(order_by) = order_by {
+                builder.order_by(order_by.clone());
+            }
+            if let Some(order_direction) = order_direction {
+                builder.order_direction(*order_direction);
+            }
+            if let Some(limit) = limit {
+                builder.limit(*limit);
+            }
+            Finder::Platforms(builder)
+        }
+        PbFind::Sites { name } => {
+            let mut builder = db.find_all_sites();
+            builder.name_opt(name.as_deref());
+            Finder::Sites(builder)
+        }
+        PbFind::Packages => Finder::Packages(db.find_all_packages()),
+        PbFind::Distributions {
+            package,
+            version,
+            order_direction,
+        } => {
+            let mut builder = db.find_all_distributions();
+            builder
+                .package_opt(package.as_deref())
+                .version_opt(version.as_deref())
+                .order_direction_opt(*order_direction);
+            Finder::Distributions(builder)
+        }
+    }
+}
+
+/// Run the supplied find subcommand, printing the results in the supplied format
+pub fn run(client: &mut Client, cmd: PbFind, format: Format) -> Result<(), Box<dyn Error>> {
+    let mut db = PackratDb::new(client);
+    let mut stdout = std::io::stdout();
+    match finder(&mut db, &cmd) {
+        Finder::VersionPin(mut builder) => [builder.query()?].render(format, &mut stdout)?,
+        Finder::VersionPins(mut builder) => builder.query()?.render(format, &mut stdout)?,
+        Finder::AllVersionPins(mut builder) => builder.query()?.render(format, &mut stdout)?,
+        Finder::VersionPinWiths(mut builder) => builder.query()?.render(format, &mut stdout)?,
+        Finder::Pins(mut builder) => builder.query()?.render(format, &mut stdout)?,
+        Finder::PkgCoords(mut builder) => builder.query()?.render(format, &mut stdout)?,
+        Finder::Withs(mut builder) => builder.query()?.render(format, &mut stdout)?,
+        Finder::Revisions(mut builder) => builder.query()?.render(format, &mut stdout)?,
+        Finder::Changes(mut builder) => builder.query()?.render(format, &mut stdout)?,
+        Finder::Levels(mut builder) => builder.query()?.render(format, &mut stdout)?,
+        Finder::Roles(mut builder) => builder.query()?.render(format, &mut stdout)?,
+        Finder::Platforms(mut builder) => builder.query()?.render(format, &mut stdout)?,
+        Finder::Sites(mut builder) => builder.query()?.render(format, &mut stdout)?,
+        Finder::Packages(mut builder) => builder.query()?.render(format, &mut stdout)?,
+        Finder::Distributions(mut builder) => builder.query()?.render(format, &mut stdout)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone};
+    use packybara::db::memory::MemoryStore;
+    use packybara::db::query::{QueryParam, SelectQuery};
+    use structopt::StructOpt;
+
+    // the select statement of the find subcommand parsed from args
+    fn select(args: &[&str]) -> SelectQuery {
+        let cmd = PbFind::from_iter(args);
+        let mut store = MemoryStore::new();
+        let mut db = PackratDb::new(&mut store);
+        match finder(&mut db, &cmd) {
+            Finder::VersionPin(builder) => builder.select(),
+            Finder::VersionPins(builder) => builder.select(),
+            Finder::AllVersionPins(builder) => builder.select(),
+            Finder::VersionPinWiths(builder) => builder.select(),
+            Finder::Pins(builder) => builder.select(),
+            Finder::PkgCoords(builder) => builder.select(),
+            Finder::Withs(builder) => builder.select(),
+            Finder::Revisions(builder) => builder.select(),
+            Finder::Changes(builder) => builder.select(),
+            Finder::Levels(builder) => builder.select(),
+            Finder::Roles(builder) => builder.select(),
+            Finder::Platforms(builder) => builder.select(),
+            Finder::Sites(builder) => builder.select(),
+            Finder::Packages(builder) => builder.select(),
+            Finder::Distributions(builder) => builder.select(),
+        }
+    }
+
+    #[test]
+    fn find_versionpin_select() {
+        let query = select(&["find", "versionpin", "maya", "-l", "dev01", "-r", "model"]);
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT versionpin_id, distribution, level_name, role_name, site_name, platform_name, withs \
+             FROM find_distribution_and_withs($1, role => $2, platform => $3, level => $4, site => $5)"
+        );
+        assert_eq!(ps, &["maya", "model", "any", "dev01", "any"]);
+    }
+    #[test]
+    fn find_versionpins_select() {
+        let query = select(&[
+            "find",
+            "versionpins",
+            "maya",
+            "-s",
+            "portland",
+            "-o",
+            "level,role",
+            "--order-direction",
+            "desc",
+        ]);
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT versionpin_id, distribution, level_name, role_name, site_name, platform_name, withs \
+             FROM search_distributions($1, role => $2, platform => $3, level => $4, site => $5) \
+             ORDER BY level DESC, role DESC"
+        );
+        assert_eq!(ps, &["maya", "any", "any", "facility", "portland"]);
+    }
+    #[test]
+    fn find_all_versionpins_select() {
+        let query = select(&[
+            "find",
+            "all-versionpins",
+            "-P",
+            "maya",
+            "-l",
+            "dev01",
+            "--isolate-facility",
+            "--search-mode",
+            "descendant",
+            "-o",
+            "package",
+            "--limit",
+            "5",
+        ]);
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT id, distribution_id, pkgcoord_id, distribution, level_name, role_name, site_name, platform_name, withs \
+             FROM findall_versionpins(role => $1, platform => $2, level => $3, site => $4, search_mode => $5, \
+             package_name => $6) WHERE level_name <> 'facility' ORDER BY distribution LIMIT 5"
+        );
+        assert_eq!(ps, &["any", "any", "dev01", "any", "descendant", "maya"]);
+    }
+    #[test]
+    fn find_versionpin_withs_select() {
+        let query = select(&["find", "versionpin-withs", "12"]);
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT id, versionpin, package, pinorder FROM withpackage \
+             WHERE versionpin = $1 ORDER BY pinorder"
+        );
+        assert_eq!(ps, &[QueryParam::Int(12)]);
+    }
+    #[test]
+    fn find_pins_select() {
+        let query = select(&[
+            "find",
+            "pins",
+            "-l",
+            "dev01",
+            "--search-mode",
+            "exact",
+            "-o",
+            "level",
+            "--limit",
+            "3",
+        ]);
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT DISTINCT role, level, platform, site FROM versionpin_view \
+             WHERE level_path = text2ltree($1) ORDER BY level LIMIT 3"
+        );
+        assert_eq!(ps, &["facility.dev01"]);
+    }
+    #[test]
+    fn find_pkgcoords_select() {
+        let query = select(&[
+            "find",
+            "pkgcoords",
+            "-P",
+            "maya",
+            "-r",
+            "fx%",
+            "-o",
+            "package,level",
+        ]);
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT pkgcoord_id, package, level_name, role_name, platform_name, site_name \
+             FROM pkgcoord_view WHERE package = $1 AND text2ltree($2) <@ level AND role_name LIKE $3 \
+             AND text2ltree($4) <@ platform AND text2ltree($5) <@ site ORDER BY package, level"
+        );
+        assert_eq!(ps, &["maya", "facility", "fx%", "any", "any"]);
+    }
+    #[test]
+    fn find_withs_select() {
+        let query = select(&["find", "withs", "maya", "-r", "model", "-o", "site"]);
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT versionpin_id, distribution, level_name, role_name, site_name, platform_name \
+             FROM find_distribution_withs($1, role => $2, platform => $3, level => $4, site => $5) \
+             ORDER BY site_name"
+        );
+        assert_eq!(ps, &["maya", "model", "any", "facility", "any"]);
+    }
+    #[test]
+    fn find_revisions_select() {
+        let query = select(&[
+            "find",
+            "revisions",
+            "-a",
+            "jgerber",
+            "-o",
+            "datetime",
+            "--order-direction",
+            "desc",
+        ]);
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT id, transaction_id, author, datetime, comment FROM revision_view \
+             WHERE author = $1 ORDER BY datetime DESC"
+        );
+        assert_eq!(ps, &["jgerber"]);
+    }
+    #[test]
+    fn find_changes_select_filters() {
+        let query = select(&[
+            "find",
+            "changes",
+            "-P",
+            "maya",
+            "-l",
+            "dev01",
+            "--search-mode",
+            "descendant",
+            "--action",
+            "update",
+            "-a",
+            "jgerber",
+            "--since",
+            "2020-03-01",
+            "--until",
+            "2020-03-08 12:00:00",
+        ]);
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT event_id, id, transaction_id, action, level_name, role_name, platform_name, \
+             site_name, package, old, new, author, comment, datetime, old_withs, new_withs \
+             FROM vpin_change_view WHERE package = $1 AND text2ltree($2) @> level AND action = $3 \
+             AND author = $4 AND datetime >= $5 AND datetime < $6 ORDER BY event_id ASC"
+        );
+        assert_eq!(
+            ps,
+            &[
+                QueryParam::from("maya"),
+                QueryParam::from("facility.dev01"),
+                QueryParam::from("UPDATE"),
+                QueryParam::from("jgerber"),
+                QueryParam::DateTime(Local.ymd(2020, 3, 1).and_hms(0, 0, 0)),
+                QueryParam::DateTime(Local.ymd(2020, 3, 8).and_hms(12, 0, 0)),
+            ]
+        );
+    }
+    #[test]
+    fn find_changes_select_order_and_limit() {
+        let query = select(&[
+            "find",
+            "changes",
+            "3",
+            "-o",
+            "author,datetime",
+            "--order-direction",
+            "desc",
+            "--limit",
+            "10",
+        ]);
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT event_id, id, transaction_id, action, level_name, role_name, platform_name, \
+             site_name, package, old, new, author, comment, datetime, old_withs, new_withs \
+             FROM vpin_change_view WHERE transaction_id = $1 \
+             ORDER BY author DESC, datetime DESC, event_id DESC LIMIT 10"
+        );
+        assert_eq!(ps, &[QueryParam::BigInt(3)]);
+    }
+    #[test]
+    fn find_levels_select() {
+        let query = select(&[
+            "find", "levels", "--show", "dev01", "--depth", "1", "-o", "show",
+        ]);
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT DISTINCT name, show FROM level_view WHERE name <> 'any' \
+             AND show = $1 AND nlevel(path) = $2 ORDER BY show"
+        );
+        assert_eq!(ps, &[QueryParam::from("dev01"), QueryParam::Int(2)]);
+    }
+    #[test]
+    fn find_roles_select() {
+        let query = select(&["find", "roles", "--category", "subrole", "-o", "category"]);
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT DISTINCT name, category FROM role_view WHERE name <> 'any' \
+             AND category = $1 ORDER BY category"
+        );
+        assert_eq!(ps, &["subrole"]);
+    }
+    #[test]
+    fn find_platforms_select() {
+        let query = select(&["find", "platforms", "-o", "name"]);
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT DISTINCT name FROM platform_view ORDER BY name"
+        );
+        assert!(ps.is_empty());
+    }
+    #[test]
+    fn find_sites_select() {
+        let query = select(&["find", "sites", "port%"]);
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT DISTINCT name FROM site_view WHERE name <> 'any' AND name LIKE $1 ORDER BY name"
+        );
+        assert_eq!(ps, &["port%"]);
+    }
+    #[test]
+    fn find_packages_select() {
+        let query = select(&["find", "packages"]);
+        let (qs, ps) = query.to_sql();
+        assert_eq!(qs.as_str(), "SELECT name FROM package ORDER BY name");
+        assert!(ps.is_empty());
+    }
+    #[test]
+    fn find_distributions_select() {
+        let query = select(&[
+            "find",
+            "distributions",
+            "-P",
+            "maya",
+            "--order-direction",
+            "asc",
+        ]);
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT distribution_id, package, version_name FROM distribution_view \
+             WHERE package = $1 ORDER BY package ASC, version ASC"
+        );
+        assert_eq!(ps, &["maya"]);
+    }
+}
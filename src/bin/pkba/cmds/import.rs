@@ -0,0 +1,45 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+use crate::args::PbImport;
+use packybara::db::snapshot::Snapshot;
+use packybara::io::read_xml;
+use packybara::packrat::{Client, PackratDb};
+use std::error::Error;
+
+/// Run the supplied import subcommand, printing the report of what was
+/// (or, for a dry run, would be) changed
+pub fn run(client: &mut Client, cmd: PbImport) -> Result<(), Box<dyn Error>> {
+    let mut db = PackratDb::new(client);
+    match cmd {
+        PbImport::PackagesXml {
+            input,
+            create_missing,
+            dry_run,
+            revision,
+        } => {
+            let pins = read_xml(&input)?;
+            let mut import = db.import_packages_xml(pins);
+            import.create_missing(create_missing);
+            let plan = if dry_run {
+                import.plan()?
+            } else {
+                import.import(&revision.author(), &revision.comment)?
+            };
+            println!("{}", plan);
+        }
+        PbImport::Snapshot { input, revision } => {
+            let snapshot = Snapshot::read(&input)?;
+            let results = db
+                .restore_snapshot(snapshot)
+                .restore(&revision.author(), &revision.comment)?;
+            println!("{}", results);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,44 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+use packybara::db::migrate::{self, SCHEMA_VERSION};
+use packybara::packrat::{Client, PackratDb};
+use std::error::Error;
+
+/// Apply the pending schema migrations, printing those applied. If `check`
+/// is true, print the schema version and the pending migrations instead. If
+/// `baseline` is supplied, record the migrations up to that version as applied
+/// without running them.
+pub fn run(client: &mut Client, check: bool, baseline: Option<i32>) -> Result<(), Box<dyn Error>> {
+    let mut db = PackratDb::new(client);
+    if let Some(version) = baseline {
+        for migration in db.baseline(version)? {
+            println!("recorded {}", migration);
+        }
+        return Ok(());
+    }
+    if check {
+        let version = db.schema_version()?;
+        match version {
+            Some(version) => println!("schema version {} (expected {})", version, SCHEMA_VERSION),
+            None => println!("no schema (expected version {})", SCHEMA_VERSION),
+        }
+        for migration in migrate::pending(version) {
+            println!("pending {}", migration);
+        }
+        return Ok(());
+    }
+    let applied = db.migrate()?;
+    if applied.is_empty() {
+        println!("schema is up to date at version {}", SCHEMA_VERSION);
+    }
+    for migration in applied {
+        println!("applied {}", migration);
+    }
+    Ok(())
+}
@@ -0,0 +1,6 @@
+pub mod add;
+pub mod export;
+pub mod find;
+pub mod import;
+pub mod migrate;
+pub mod update;
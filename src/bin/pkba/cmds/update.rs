@@ -0,0 +1,56 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+use crate::args::PbUpdate;
+use packybara::db::update::versionpins::VersionPinChange;
+use packybara::packrat::{Client, PackratDb};
+use packybara::traits::{PBFind, PBUpdate, TransactionHandler};
+use packybara::Distribution;
+use std::error::Error;
+
+/// Run the supplied update subcommand within a single transaction, committing
+/// it as a new revision and printing the number of entities updated
+pub fn run(client: &mut Client, cmd: PbUpdate) -> Result<(), Box<dyn Error>> {
+    let mut db = PackratDb::new(client);
+    match cmd {
+        PbUpdate::VersionPin {
+            versionpin_id,
+            distribution,
+            distribution_id,
+            pkgcoord_id,
+            revision,
+        } => {
+            // look up the id of the distribution by name before starting the transaction
+            let distribution_id = match distribution {
+                Some(distribution) => {
+                    let distribution = Distribution::new(distribution)?;
+                    let found = db
+                        .find_all_distributions()
+                        .package(distribution.package())
+                        .version(distribution.version())
+                        .query()?;
+                    match found.first() {
+                        Some(row) => Some(row.id),
+                        None => {
+                            return Err(format!("No such distribution: {}", distribution).into())
+                        }
+                    }
+                }
+                None => distribution_id,
+            };
+            let change = VersionPinChange::new(versionpin_id, distribution_id, pkgcoord_id);
+            let tx = db.transaction();
+            let results = PackratDb::update_versionpins(tx)
+                .change(change)
+                .update()?
+                .commit(&revision.author(), &revision.comment)?;
+            println!("{}", results);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,53 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! pkba - query and modify the packrat database from the command line.
+//!
+//! The connection is configured via `PackratConfig::load` (the packrat config
+//! file, `PACKRAT_*` environment variables and `.pgpass`), and may be
+//! overridden with `--host`, `--port`, `--user` and `--dbname`.
+//!
+//! ```bash
+//! pkba find versionpin maya -l dev01 -r model
+//! pkba --format csv find all-versionpins -P maya
+//! pkba add versionpins maya-2018.sp3 -l dev01 -r model,anim -c "pin maya for dev01"
+//! pkba export packagesxml dev01 ./packages.xml
+//! pkba export sqlite ./packrat.sqlite
+//! pkba import packagesxml ./packages.xml --dry-run -c "restore dev01"
+//! pkba migrate --check
+//! pkba migrate --baseline 3
+//! ```
+use structopt::StructOpt;
+
+mod args;
+mod cmds;
+
+use args::{Pb, PbCrud};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let opt = Pb::from_args();
+    if let Some(ref level) = opt.loglevel {
+        std::env::set_var("RUST_LOG", level);
+    }
+    env_logger::init();
+
+    let config = opt.config()?;
+    // the schema may only be checked once it has been migrated
+    let mut client = match opt.crud {
+        PbCrud::Migrate { .. } => config.connect_unchecked()?,
+        _ => config.connect()?,
+    };
+    match opt.crud {
+        PbCrud::Find { cmd } => cmds::find::run(&mut client, cmd, opt.format),
+        PbCrud::Add { cmd } => cmds::add::run(&mut client, cmd),
+        PbCrud::Update { cmd } => cmds::update::run(&mut client, cmd),
+        PbCrud::Export { cmd } => cmds::export::run(&mut client, cmd),
+        PbCrud::Import { cmd } => cmds::import::run(&mut client, cmd),
+        PbCrud::Migrate { check, baseline } => cmds::migrate::run(&mut client, check, baseline),
+    }
+}
@@ -16,8 +16,10 @@
 use crate::coords_error::*;
 use crate::{Level, Platform, Role, Site};
 use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
 use std::convert::{From, TryInto};
 use std::fmt;
+use std::str::FromStr;
 /// CoordsBuilder follows the builder pattern to allow
 /// a fluent style api for setting the coordinates on a
 /// per component basis.
@@ -157,11 +159,103 @@ pub struct Coords {
 
 impl fmt::Display for Coords {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "(level:'{}' role:'{}' platform:'{}' site:'{}')",
-            self.level, self.role, self.platform, self.site
-        )
+        f.write_str(&Coords::display_parts(
+            &self.level,
+            &self.role,
+            &self.platform,
+            &self.site,
+        ))
+    }
+}
+
+// The keys recognized in the coords notation. A key may be abbreviated
+// to any non-empty prefix (eg `l`, `lev`, `level`).
+const COORDS_KEYS: [&str; 4] = ["level", "role", "platform", "site"];
+
+// Expand a (possibly abbreviated) key into its full name
+fn expand_key(key: &str, input: &str) -> CoordsResult<&'static str> {
+    COORDS_KEYS
+        .iter()
+        .find(|k| !key.is_empty() && k.starts_with(key))
+        .copied()
+        .ok_or_else(|| CoordsError::UnknownCoordsKey {
+            key: key.to_string(),
+            input: input.to_string(),
+        })
+}
+
+impl FromStr for Coords {
+    type Err = CoordsError;
+
+    /// Parse coords from the notation `level:<level>.role:<role>.platform:<platform>.site:<site>`.
+    /// Each key is optional, may appear in any order, and may be abbreviated
+    /// (eg `l:dev01.r:model`). Missing keys default to facility / any.
+    /// Because levels themselves contain periods, a period separated token
+    /// without a key is appended to the value of the preceding key.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim();
+        let mut values: [Option<String>; 4] = [None, None, None, None];
+        let mut current: Option<usize> = None;
+        if !input.is_empty() {
+            for token in input.split('.') {
+                let mut pieces = token.splitn(2, ':');
+                let first = pieces.next().unwrap_or("");
+                match pieces.next() {
+                    Some(value) => {
+                        let key = expand_key(first, input)?;
+                        let idx = COORDS_KEYS.iter().position(|k| *k == key).unwrap();
+                        if values[idx].is_some() {
+                            return DuplicateCoordsKey { key, input }.fail();
+                        }
+                        values[idx] = Some(value.to_string());
+                        current = Some(idx);
+                    }
+                    None => match current {
+                        Some(idx) => {
+                            let value = values[idx].get_or_insert_with(String::new);
+                            value.push('.');
+                            value.push_str(first);
+                        }
+                        None => {
+                            return MissingCoordsKey {
+                                value: first,
+                                input,
+                            }
+                            .fail()
+                        }
+                    },
+                }
+            }
+        }
+        let [level, role, platform, site] = values;
+        let mut builder = Coords::new();
+        if let Some(level) = level {
+            builder.level(level.as_str()).context(InvalidCoordsValue {
+                key: "level",
+                value: level.as_str(),
+            })?;
+        }
+        if let Some(role) = role {
+            builder.role(role.as_str()).context(InvalidCoordsValue {
+                key: "role",
+                value: role.as_str(),
+            })?;
+        }
+        if let Some(platform) = platform {
+            builder
+                .platform(platform.as_str())
+                .context(InvalidCoordsValue {
+                    key: "platform",
+                    value: platform.as_str(),
+                })?;
+        }
+        if let Some(site) = site {
+            builder.site(site.as_str()).context(InvalidCoordsValue {
+                key: "site",
+                value: site.as_str(),
+            })?;
+        }
+        Ok(builder.build())
     }
 }
 impl Coords {
@@ -211,6 +305,22 @@ impl Coords {
             site,
         }
     }
+    /// Format the parts of a Coords in the notation used to display it
+    /// (eg `level:dev01.role:model.platform:any.site:any`). Useful when
+    /// reporting parts which may not form a valid Coords.
+    pub fn display_parts<L, R, P, S>(level: L, role: R, platform: P, site: S) -> String
+    where
+        L: fmt::Display,
+        R: fmt::Display,
+        P: fmt::Display,
+        S: fmt::Display,
+    {
+        format!(
+            "level:{}.role:{}.platform:{}.site:{}",
+            level, role, platform, site
+        )
+    }
+
     /// Get the level from the Coords
     pub fn level(&self) -> &Level {
         &self.level
@@ -235,7 +345,20 @@ impl Coords {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::str::FromStr;
+
+    #[test]
+    fn display_parts_matches_display() {
+        let coords = Coords::try_from_parts("dev01", "model", "cent7_64", "portland").unwrap();
+        assert_eq!(
+            Coords::display_parts("dev01", "model", "cent7_64", "portland"),
+            coords.to_string()
+        );
+        assert_eq!(
+            Coords::display_parts("dev01", "model beta", "any", "any"),
+            "level:dev01.role:model beta.platform:any.site:any"
+        );
+    }
+
     #[test]
     fn can_construct_from_strs() {
         let pin = Coords::try_from_parts("dev01", "model", "cent7_64", "portland").unwrap();
@@ -290,4 +413,67 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn can_parse_abbreviated_unordered_keys() {
+        let coords = Coords::from_str("s:portland.l:dev01.rd.0001.r:model_beta").unwrap();
+        let expected =
+            Coords::try_from_parts("dev01.rd.0001", "model_beta", "any", "portland").unwrap();
+        assert_eq!(coords, expected);
+    }
+
+    #[test]
+    fn can_parse_empty_string_as_defaults() {
+        let coords = Coords::from_str("").unwrap();
+        assert_eq!(coords, Coords::new().build());
+    }
+
+    #[test]
+    fn can_round_trip_through_display() {
+        let coords = Coords::try_from_parts("dev01.rd", "model", "cent7_64", "portland").unwrap();
+        assert_eq!(
+            coords.to_string(),
+            "level:dev01.rd.role:model.platform:cent7_64.site:portland"
+        );
+        assert_eq!(Coords::from_str(&coords.to_string()).unwrap(), coords);
+    }
+
+    #[test]
+    fn parse_unknown_key_is_err() {
+        let coords = Coords::from_str("l:dev01.show:dev01");
+        match coords {
+            Err(CoordsError::UnknownCoordsKey { key, .. }) => assert_eq!(key, "show"),
+            _ => panic!("expected UnknownCoordsKey. got {:?}", coords),
+        }
+    }
+
+    #[test]
+    fn parse_duplicate_key_is_err() {
+        let coords = Coords::from_str("l:dev01.lev:dev02");
+        match coords {
+            Err(CoordsError::DuplicateCoordsKey { key, .. }) => assert_eq!(key, "level"),
+            _ => panic!("expected DuplicateCoordsKey. got {:?}", coords),
+        }
+    }
+
+    #[test]
+    fn parse_value_without_key_is_err() {
+        let coords = Coords::from_str("dev01.r:model");
+        match coords {
+            Err(CoordsError::MissingCoordsKey { value, .. }) => assert_eq!(value, "dev01"),
+            _ => panic!("expected MissingCoordsKey. got {:?}", coords),
+        }
+    }
+
+    #[test]
+    fn parse_bad_value_is_err() {
+        let coords = Coords::from_str("l:dev01.p:amiga");
+        match coords {
+            Err(CoordsError::InvalidCoordsValue { key, value, .. }) => {
+                assert_eq!(key, "platform");
+                assert_eq!(value, "amiga");
+            }
+            _ => panic!("expected InvalidCoordsValue. got {:?}", coords),
+        }
+    }
 }
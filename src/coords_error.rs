@@ -44,6 +44,23 @@ pub enum CoordsError {
     /// DistributionConstructionError
     #[snafu(display("Error distribution. Problem: {}", problem))]
     DistributionConstructionError { problem: String },
+    /// UnknownCoordsKey
+    #[snafu(display("Unknown key '{}' in coords string '{}'", key, input))]
+    UnknownCoordsKey { key: String, input: String },
+    /// DuplicateCoordsKey
+    #[snafu(display("Key '{}' supplied more than once in coords string '{}'", key, input))]
+    DuplicateCoordsKey { key: String, input: String },
+    /// MissingCoordsKey
+    #[snafu(display("Value '{}' lacks a key in coords string '{}'", value, input))]
+    MissingCoordsKey { value: String, input: String },
+    /// InvalidCoordsValue
+    #[snafu(display("Invalid {} '{}'. Error: {}", key, value, source))]
+    InvalidCoordsValue {
+        key: &'static str,
+        value: String,
+        #[snafu(source(from(CoordsError, Box::new)))]
+        source: Box<CoordsError>,
+    },
 }
 
 pub type CoordsResult<T, E = CoordsError> = std::result::Result<T, E>;
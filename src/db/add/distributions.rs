@@ -0,0 +1,358 @@
+use crate::coords_error::CoordsError;
+use crate::db::async_packrat::AsyncTransaction;
+use crate::db::memory::{MemoryStoreError, MemoryTransaction};
+use crate::db::plan::{self, ChangePlan, EntityKind};
+use crate::db::prepared::Prepared;
+use crate::traits::{AsyncTransactionHandler, MemoryTransactionHandler, TransactionHandler};
+use crate::Distribution;
+use itertools::Itertools;
+use postgres::Transaction;
+use snafu::{ResultExt, Snafu};
+use std::marker::PhantomData;
+
+/// Error type returned from AddDistributions
+#[derive(Debug, Snafu)]
+pub enum AddDistributionsError {
+    /// When constructing a query, postgres has thrown an error
+    #[snafu(display("Postgres Error: {} {:#?}", msg, source))]
+    TokioPostgresError {
+        msg: &'static str,
+        source: tokio_postgres::error::Error,
+    },
+    #[snafu(display("No distribution names supplied"))]
+    NoDistributionNamesError,
+    #[snafu(display("Invalid distribution {}: {}", distribution, source))]
+    InvalidDistributionError {
+        distribution: String,
+        source: CoordsError,
+    },
+    /// The MemoryStore has rejected a change
+    #[snafu(display("MemoryStore Error: {}", source))]
+    MemoryError { source: MemoryStoreError },
+}
+
+/// Responsible for creating distributions. Note that the package
+/// of each distribution must already exist in the database.
+pub struct AddDistributions<'a, T = Transaction<'a>> {
+    tx: Option<T>,
+    names: Vec<String>,
+    result_cnt: u64,
+    phantom: PhantomData<&'a ()>,
+}
+
+impl<'a> TransactionHandler<'a> for AddDistributions<'a> {
+    type Error = tokio_postgres::error::Error;
+    fn tx(&mut self) -> Option<&mut Transaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> Transaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> AsyncTransactionHandler<'a> for AddDistributions<'a, AsyncTransaction<'a>> {
+    type Error = tokio_postgres::error::Error;
+    fn tx(&mut self) -> Option<&mut AsyncTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> AsyncTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> MemoryTransactionHandler<'a> for AddDistributions<'a, MemoryTransaction<'a>> {
+    fn tx(&mut self) -> Option<&mut MemoryTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> MemoryTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a, T> AddDistributions<'a, T> {
+    /// new up an AddDistributions instance
+    ///
+    /// # Arguments
+    ///
+    /// * `tx` - A Transaction instance
+    pub fn new(tx: T) -> Self {
+        Self {
+            tx: Some(tx),
+            names: Vec::new(),
+            result_cnt: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Add a distribution name (eg `maya-2020.1`) to the list of
+    /// distributions we wish to create.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - A distribution we wish to create in the db. Validation
+    ///   is deferred until `create` is called.
+    ///
+    /// # Returns
+    /// * Self
+    pub fn distribution<I: Into<String>>(mut self, name: I) -> Self {
+        self.names.push(name.into());
+        self
+    }
+
+    /// Add a vector of distribution names to the list of distributions
+    /// we wish to create in the database.
+    ///
+    /// # Arguments
+    /// * `names` - A list of distributions we wish to create in the db.
+    ///
+    /// # Returns
+    /// * Self
+    pub fn distributions(mut self, names: &mut Vec<String>) -> Self {
+        self.names.append(names);
+        self
+    }
+
+    // validate the distribution names, splitting them into package and version
+    fn parts(&self) -> Result<Vec<(String, String)>, AddDistributionsError> {
+        let names = self.names.iter().unique().cloned().collect::<Vec<String>>();
+        if names.is_empty() {
+            return Err(AddDistributionsError::NoDistributionNamesError);
+        }
+        let mut distributions = Vec::new();
+        for name in &names {
+            let distribution =
+                Distribution::new(name.as_str()).context(InvalidDistributionError {
+                    distribution: name.as_str(),
+                })?;
+            distributions.push(distribution);
+        }
+        Ok(distributions
+            .iter()
+            .map(|d| (d.package().to_string(), d.version().to_string()))
+            .collect())
+    }
+
+    // build the insert statement
+    fn prepare(&self) -> Result<Prepared, AddDistributionsError> {
+        let parts = self.parts()?;
+        let mut insert_str = "INSERT INTO distribution (package, version) VALUES ".to_string();
+        let prepared = (0..parts.len())
+            .map(|x| format!(" (${}, text2ltree(${}))", 2 * x + 1, 2 * x + 2))
+            .collect::<Vec<_>>();
+        let prepared = prepared.join(",");
+        insert_str.push_str(prepared.as_str());
+        insert_str.push_str(" ON CONFLICT DO NOTHING");
+        let mut prepared = Prepared::new(insert_str);
+        for (package, version) in parts {
+            prepared.push(package);
+            prepared.push(version);
+        }
+        Ok(prepared)
+    }
+}
+
+impl<'a> AddDistributions<'a> {
+    /// Create previously registered distribution(s) in the database. Each
+    /// name is validated via `Distribution::new` before anything is inserted.
+    ///
+    /// # Arguments
+    /// None
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(AddDistributionsError)
+    pub fn create(mut self) -> Result<Self, AddDistributionsError> {
+        let prepared = self.prepare()?;
+        self.result_cnt = prepared
+            .execute(self.tx().unwrap())
+            .context(TokioPostgresError {
+                msg: "failed to add distributions",
+            })?;
+        Ok(self)
+    }
+
+    /// Create the distributions within the transaction, as `create` does, and
+    /// report the distributions added before rolling the transaction back.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddDistributionsError)
+    pub fn preview(mut self) -> Result<ChangePlan, AddDistributionsError> {
+        let prepared = self.prepare()?;
+        let plan = plan::preview_added(self.tx().unwrap(), prepared, EntityKind::Distribution)
+            .context(TokioPostgresError {
+                msg: "failed to add distributions",
+            })?;
+        self.rollback().context(TokioPostgresError {
+            msg: "failed to roll back transaction",
+        })?;
+        Ok(plan)
+    }
+}
+
+impl<'a> AddDistributions<'a, AsyncTransaction<'a>> {
+    /// Create previously registered distribution(s) in the database. Each
+    /// name is validated via `Distribution::new` before anything is inserted.
+    ///
+    /// # Arguments
+    /// None
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(AddDistributionsError)
+    pub async fn create(mut self) -> Result<Self, AddDistributionsError> {
+        let prepared = self.prepare()?;
+        self.result_cnt =
+            prepared
+                .execute_async(self.tx().unwrap())
+                .await
+                .context(TokioPostgresError {
+                    msg: "failed to add distributions",
+                })?;
+        Ok(self)
+    }
+
+    /// Create the distributions within the transaction, as `create` does, and
+    /// report the distributions added before rolling the transaction back.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddDistributionsError)
+    pub async fn preview(mut self) -> Result<ChangePlan, AddDistributionsError> {
+        let prepared = self.prepare()?;
+        let plan =
+            plan::preview_added_async(self.tx().unwrap(), prepared, EntityKind::Distribution)
+                .await
+                .context(TokioPostgresError {
+                    msg: "failed to add distributions",
+                })?;
+        self.rollback().await.context(TokioPostgresError {
+            msg: "failed to roll back transaction",
+        })?;
+        Ok(plan)
+    }
+}
+
+impl<'a> AddDistributions<'a, MemoryTransaction<'a>> {
+    /// Create previously registered distribution(s) in the database. Each
+    /// name is validated via `Distribution::new` before anything is inserted.
+    ///
+    /// # Arguments
+    /// None
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(AddDistributionsError)
+    pub fn create(mut self) -> Result<Self, AddDistributionsError> {
+        let parts = self.parts()?;
+        self.result_cnt = self
+            .tx()
+            .unwrap()
+            .add_distributions(&parts)
+            .context(MemoryError)?;
+        Ok(self)
+    }
+
+    /// Create the distributions within the transaction, as `create` does, and
+    /// report the distributions added before discarding the transaction.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddDistributionsError)
+    pub fn preview(mut self) -> Result<ChangePlan, AddDistributionsError> {
+        let distributions = self
+            .parts()?
+            .into_iter()
+            .map(|(package, version)| Distribution::from_parts_unchecked(&package, &version))
+            .collect::<Vec<_>>();
+        let tables = self.tx().unwrap().tables();
+        let plan = plan::preview_added_memory(EntityKind::Distribution, distributions, |dist| {
+            tables
+                .distribution(dist.package(), dist.version())
+                .is_some()
+        });
+        self.create()?.rollback();
+        Ok(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distributions(names: &[&str]) -> AddDistributions<'static, ()> {
+        names
+            .iter()
+            .fold(AddDistributions::new(()), |builder, name| {
+                builder.distribution(*name)
+            })
+    }
+
+    #[test]
+    fn splits_unique_names_into_package_and_version() {
+        let parts = distributions(&["maya-2018.sp3", "houdini-18.0", "maya-2018.sp3"])
+            .parts()
+            .unwrap();
+        assert_eq!(
+            parts,
+            vec![
+                ("maya".to_string(), "2018.sp3".to_string()),
+                ("houdini".to_string(), "18.0".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_distributions() {
+        match distributions(&["maya-2018.sp3", "maya"]).parts() {
+            Err(AddDistributionsError::InvalidDistributionError { distribution, .. }) => {
+                assert_eq!(distribution, "maya")
+            }
+            _ => panic!("expected an InvalidDistributionError"),
+        }
+    }
+
+    #[test]
+    fn requires_distribution_names() {
+        assert!(matches!(
+            distributions(&[]).prepare(),
+            Err(AddDistributionsError::NoDistributionNamesError)
+        ));
+    }
+
+    #[test]
+    fn prepares_insert() {
+        let prepared = distributions(&["maya-2018.sp3", "houdini-18.0"])
+            .prepare()
+            .unwrap();
+        assert_eq!(
+            prepared.sql,
+            "INSERT INTO distribution (package, version) VALUES  ($1, text2ltree($2)), ($3, text2ltree($4)) ON CONFLICT DO NOTHING"
+        );
+        assert_eq!(
+            format!("{:?}", prepared.params),
+            r#"["maya", "2018.sp3", "houdini", "18.0"]"#
+        );
+    }
+}
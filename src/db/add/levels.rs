@@ -1,9 +1,12 @@
-use crate::traits::TransactionHandler;
+use crate::db::async_packrat::AsyncTransaction;
+use crate::db::memory::MemoryTransaction;
+use crate::db::plan::{self, ChangePlan, EntityKind};
+use crate::db::prepared::Prepared;
+use crate::traits::{AsyncTransactionHandler, MemoryTransactionHandler, TransactionHandler};
 use itertools::Itertools;
-use log;
-use postgres::types::ToSql;
 use postgres::Transaction;
 use snafu::{ResultExt, Snafu};
+use std::marker::PhantomData;
 /// An enum which defines the kinds of InvalidLevelErrors we may encounter. .
 #[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
 pub enum InvalidLevelKind {
@@ -32,10 +35,11 @@ pub enum AddLevelsError {
     },
 }
 /// The AddLevels struct is responsible for creating levels.
-pub struct AddLevels<'a> {
-    tx: Option<Transaction<'a>>,
+pub struct AddLevels<'a, T = Transaction<'a>> {
+    tx: Option<T>,
     names: Vec<String>,
     result_cnt: u64,
+    phantom: PhantomData<&'a ()>,
 }
 
 impl<'a> TransactionHandler<'a> for AddLevels<'a> {
@@ -57,7 +61,44 @@ impl<'a> TransactionHandler<'a> for AddLevels<'a> {
     }
 }
 
-impl<'a> AddLevels<'a> {
+impl<'a> AsyncTransactionHandler<'a> for AddLevels<'a, AsyncTransaction<'a>> {
+    type Error = tokio_postgres::error::Error;
+    fn tx(&mut self) -> Option<&mut AsyncTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> AsyncTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> MemoryTransactionHandler<'a> for AddLevels<'a, MemoryTransaction<'a>> {
+    fn tx(&mut self) -> Option<&mut MemoryTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> MemoryTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a, T> AddLevels<'a, T> {
     /// New up an AddLevels instance
     ///
     /// # Arguments
@@ -66,11 +107,12 @@ impl<'a> AddLevels<'a> {
     ///
     /// # Returns
     /// * An instance of Self
-    pub fn new(tx: Transaction<'a>) -> Self {
+    pub fn new(tx: T) -> Self {
         Self {
             tx: Some(tx),
             names: Vec::new(),
             result_cnt: 0,
+            phantom: PhantomData,
         }
     }
 
@@ -102,12 +144,9 @@ impl<'a> AddLevels<'a> {
         self.names.append(names);
         self
     }
-    /// Create level instances in the database, returning the number of
-    /// new instances created.
-    ///
-    /// # Returns
-    /// * Ok(&mut Self) | Err(AddLevelsError)
-    pub fn create(mut self) -> Result<Self, AddLevelsError> {
+    // validate the levels and expand them into the paths to create, so that
+    // dev01.rd yields facility.dev01 and facility.dev01.rd
+    fn paths(&self) -> Result<Vec<String>, AddLevelsError> {
         let mut expand_levels = Vec::new();
         let levels = self
             .names
@@ -132,30 +171,123 @@ impl<'a> AddLevels<'a> {
                 previous = next;
             }
         }
-        let mut levels_ref: Vec<&(dyn ToSql + Sync)> = Vec::new();
-        for p in &expand_levels {
-            levels_ref.push(p);
-        }
+        Ok(expand_levels)
+    }
+
+    // build the insert statement
+    fn prepare(&self) -> Result<Prepared, AddLevelsError> {
+        let expand_levels = self.paths()?;
         let mut insert_str = "INSERT INTO level (path) VALUES ".to_string();
-        let prepared = (1..=levels_ref.len())
-            .into_iter()
+        let prepared = (1..=expand_levels.len())
             .map(|x| format!(" (text2ltree(${}))", x))
             .collect::<Vec<_>>();
         let prepared = prepared.join(",");
         insert_str.push_str(prepared.as_str());
         insert_str.push_str(" ON CONFLICT (path) DO NOTHING");
-        log::info!("SQL\n{}", insert_str.as_str());
-        log::info!("Prepared\n{:?}", &levels_ref);
-        // here we limit the lifetime of tx, so that we can return &mut self
-
-        let results = self
-            .tx()
-            .unwrap()
-            .execute(insert_str.as_str(), &levels_ref[..])
+        let mut prepared = Prepared::new(insert_str);
+        for param in expand_levels {
+            prepared.push(param);
+        }
+        Ok(prepared)
+    }
+}
+
+impl<'a> AddLevels<'a> {
+    /// Create level instances in the database, returning the number of
+    /// new instances created.
+    ///
+    /// # Returns
+    /// * Ok(&mut Self) | Err(AddLevelsError)
+    pub fn create(mut self) -> Result<Self, AddLevelsError> {
+        let prepared = self.prepare()?;
+        self.result_cnt = prepared
+            .execute(self.tx().unwrap())
             .context(TokioPostgresError {
                 msg: "failed to add levels",
             })?;
-        self.result_cnt = results;
         Ok(self)
     }
+
+    /// Create the levels within the transaction, as `create` does, and
+    /// report the levels added before rolling the transaction back.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddLevelsError)
+    pub fn preview(mut self) -> Result<ChangePlan, AddLevelsError> {
+        let prepared = self.prepare()?;
+        let plan = plan::preview_added(self.tx().unwrap(), prepared, EntityKind::Level).context(
+            TokioPostgresError {
+                msg: "failed to add levels",
+            },
+        )?;
+        self.rollback().context(TokioPostgresError {
+            msg: "failed to roll back transaction",
+        })?;
+        Ok(plan)
+    }
+}
+
+impl<'a> AddLevels<'a, AsyncTransaction<'a>> {
+    /// Create level instances in the database, returning the number of
+    /// new instances created.
+    ///
+    /// # Returns
+    /// * Ok(&mut Self) | Err(AddLevelsError)
+    pub async fn create(mut self) -> Result<Self, AddLevelsError> {
+        let prepared = self.prepare()?;
+        self.result_cnt =
+            prepared
+                .execute_async(self.tx().unwrap())
+                .await
+                .context(TokioPostgresError {
+                    msg: "failed to add levels",
+                })?;
+        Ok(self)
+    }
+
+    /// Create the levels within the transaction, as `create` does, and
+    /// report the levels added before rolling the transaction back.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddLevelsError)
+    pub async fn preview(mut self) -> Result<ChangePlan, AddLevelsError> {
+        let prepared = self.prepare()?;
+        let plan = plan::preview_added_async(self.tx().unwrap(), prepared, EntityKind::Level)
+            .await
+            .context(TokioPostgresError {
+                msg: "failed to add levels",
+            })?;
+        self.rollback().await.context(TokioPostgresError {
+            msg: "failed to roll back transaction",
+        })?;
+        Ok(plan)
+    }
+}
+
+impl<'a> AddLevels<'a, MemoryTransaction<'a>> {
+    /// Create level instances in the database, returning the number of
+    /// new instances created.
+    ///
+    /// # Returns
+    /// * Ok(&mut Self) | Err(AddLevelsError)
+    pub fn create(mut self) -> Result<Self, AddLevelsError> {
+        let paths = self.paths()?;
+        self.result_cnt = self.tx().unwrap().add_levels(&paths);
+        Ok(self)
+    }
+
+    /// Create the levels within the transaction, as `create` does, and
+    /// report the levels added before discarding the transaction.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddLevelsError)
+    pub fn preview(mut self) -> Result<ChangePlan, AddLevelsError> {
+        let names = self.paths()?;
+        let tables = self.tx().unwrap().tables();
+        let plan = plan::preview_added_memory(EntityKind::Level, names, |name| {
+            tables.levels.contains(name)
+        });
+        self.create()?.rollback();
+        Ok(plan)
+    }
 }
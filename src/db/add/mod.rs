@@ -1,6 +1,8 @@
+pub mod distributions;
 pub mod levels;
 pub mod packages;
 pub mod platforms;
 pub mod roles;
+pub mod sites;
 pub mod versionpins;
 pub mod withs;
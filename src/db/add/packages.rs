@@ -1,9 +1,12 @@
-use crate::traits::TransactionHandler;
+use crate::db::async_packrat::AsyncTransaction;
+use crate::db::memory::MemoryTransaction;
+use crate::db::plan::{self, ChangePlan, EntityKind};
+use crate::db::prepared::Prepared;
+use crate::traits::{AsyncTransactionHandler, MemoryTransactionHandler, TransactionHandler};
 use itertools::Itertools;
-use log;
-use postgres::types::ToSql;
 use postgres::Transaction;
 use snafu::{ResultExt, Snafu};
+use std::marker::PhantomData;
 
 /// Error type returned from FindVersionPinsError
 #[derive(Debug, Snafu)]
@@ -19,10 +22,11 @@ pub enum AddPackagesError {
 }
 
 /// Responsible for creating packages
-pub struct AddPackages<'a> {
-    tx: Option<Transaction<'a>>,
+pub struct AddPackages<'a, T = Transaction<'a>> {
+    tx: Option<T>,
     names: Vec<String>,
     result_cnt: u64,
+    phantom: PhantomData<&'a ()>,
 }
 
 impl<'a> TransactionHandler<'a> for AddPackages<'a> {
@@ -44,7 +48,44 @@ impl<'a> TransactionHandler<'a> for AddPackages<'a> {
     }
 }
 
-impl<'a> AddPackages<'a> {
+impl<'a> AsyncTransactionHandler<'a> for AddPackages<'a, AsyncTransaction<'a>> {
+    type Error = tokio_postgres::error::Error;
+    fn tx(&mut self) -> Option<&mut AsyncTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> AsyncTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> MemoryTransactionHandler<'a> for AddPackages<'a, MemoryTransaction<'a>> {
+    fn tx(&mut self) -> Option<&mut MemoryTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> MemoryTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a, T> AddPackages<'a, T> {
     /// new up an AddPackages instance
     ///
     /// # Arguments
@@ -52,11 +93,12 @@ impl<'a> AddPackages<'a> {
     /// * `client` - A reference to a postgres::Client instance, which
     /// stores the connection to the database, and provides crud methods
     /// for us.
-    pub fn new(tx: Transaction<'a>) -> Self {
+    pub fn new(tx: T) -> Self {
         Self {
             tx: Some(tx),
             names: Vec::new(),
             result_cnt: 0,
+            phantom: PhantomData,
         }
     }
 
@@ -88,43 +130,140 @@ impl<'a> AddPackages<'a> {
         self.names.append(names);
         self
     }
-    /// Create previously registered package name(s) in the database. This call is
-    /// fallible, and may return either the number of new packages created, or a
-    /// relevant error.
-    ///
-    /// # Arguments
-    /// None
-    ///
-    /// # Returns Result
-    /// * Ok(u64) | Err(AddPackagesError)
-    pub fn create(mut self) -> Result<Self, AddPackagesError> {
+    // the unique package names, which must not be empty
+    fn unique_names(&self) -> Result<Vec<String>, AddPackagesError> {
         let packages = self.names.iter().unique().cloned().collect::<Vec<String>>();
         if packages.len() == 0 {
             return Err(AddPackagesError::NoPackageNamesError);
         }
-        let mut packages_ref: Vec<&(dyn ToSql + Sync)> = Vec::new();
-        for p in &packages {
-            packages_ref.push(p);
-        }
+        Ok(packages)
+    }
+    // validate the names and build the insert statement
+    fn prepare(&self) -> Result<Prepared, AddPackagesError> {
+        let packages = self.unique_names()?;
         let mut insert_str = "INSERT INTO package (name) VALUES ".to_string();
-        let prepared = (1..=packages_ref.len())
-            .into_iter()
+        let prepared = (1..=packages.len())
             .map(|x| format!(" (${})", x))
             .collect::<Vec<_>>();
         let prepared = prepared.join(",");
         insert_str.push_str(prepared.as_str());
         insert_str.push_str(" ON CONFLICT (name) DO NOTHING");
+        let mut prepared = Prepared::new(insert_str);
+        for param in packages {
+            prepared.push(param);
+        }
+        Ok(prepared)
+    }
+}
 
-        log::info!("SQL\n{}", insert_str.as_str());
-        log::info!("Prepared\n{:?}", &packages_ref);
-        let results = self
-            .tx()
-            .unwrap()
-            .execute(insert_str.as_str(), &packages_ref[..])
+impl<'a> AddPackages<'a> {
+    /// Create previously registered package name(s) in the database. This call is
+    /// fallible, and may return either the number of new packages created, or a
+    /// relevant error.
+    ///
+    /// # Arguments
+    /// None
+    ///
+    /// # Returns Result
+    /// * Ok(u64) | Err(AddPackagesError)
+    pub fn create(mut self) -> Result<Self, AddPackagesError> {
+        let prepared = self.prepare()?;
+        self.result_cnt = prepared
+            .execute(self.tx().unwrap())
+            .context(TokioPostgresError {
+                msg: "failed to add packages",
+            })?;
+        Ok(self)
+    }
+
+    /// Create the packages within the transaction, as `create` does, and
+    /// report the packages added before rolling the transaction back.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddPackagesError)
+    pub fn preview(mut self) -> Result<ChangePlan, AddPackagesError> {
+        let prepared = self.prepare()?;
+        let plan = plan::preview_added(self.tx().unwrap(), prepared, EntityKind::Package).context(
+            TokioPostgresError {
+                msg: "failed to add packages",
+            },
+        )?;
+        self.rollback().context(TokioPostgresError {
+            msg: "failed to roll back transaction",
+        })?;
+        Ok(plan)
+    }
+}
+
+impl<'a> AddPackages<'a, AsyncTransaction<'a>> {
+    /// Create previously registered package name(s) in the database. This call is
+    /// fallible, and may return either the number of new packages created, or a
+    /// relevant error.
+    ///
+    /// # Arguments
+    /// None
+    ///
+    /// # Returns Result
+    /// * Ok(u64) | Err(AddPackagesError)
+    pub async fn create(mut self) -> Result<Self, AddPackagesError> {
+        let prepared = self.prepare()?;
+        self.result_cnt =
+            prepared
+                .execute_async(self.tx().unwrap())
+                .await
+                .context(TokioPostgresError {
+                    msg: "failed to add packages",
+                })?;
+        Ok(self)
+    }
+
+    /// Create the packages within the transaction, as `create` does, and
+    /// report the packages added before rolling the transaction back.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddPackagesError)
+    pub async fn preview(mut self) -> Result<ChangePlan, AddPackagesError> {
+        let prepared = self.prepare()?;
+        let plan = plan::preview_added_async(self.tx().unwrap(), prepared, EntityKind::Package)
+            .await
             .context(TokioPostgresError {
                 msg: "failed to add packages",
             })?;
-        self.result_cnt = results;
+        self.rollback().await.context(TokioPostgresError {
+            msg: "failed to roll back transaction",
+        })?;
+        Ok(plan)
+    }
+}
+
+impl<'a> AddPackages<'a, MemoryTransaction<'a>> {
+    /// Create previously registered package name(s) in the database. This call is
+    /// fallible, and may return either the number of new packages created, or a
+    /// relevant error.
+    ///
+    /// # Arguments
+    /// None
+    ///
+    /// # Returns Result
+    /// * Ok(u64) | Err(AddPackagesError)
+    pub fn create(mut self) -> Result<Self, AddPackagesError> {
+        let packages = self.unique_names()?;
+        self.result_cnt = self.tx().unwrap().add_packages(&packages);
         Ok(self)
     }
+
+    /// Create the packages within the transaction, as `create` does, and
+    /// report the packages added before discarding the transaction.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddPackagesError)
+    pub fn preview(mut self) -> Result<ChangePlan, AddPackagesError> {
+        let names = self.unique_names()?;
+        let tables = self.tx().unwrap().tables();
+        let plan = plan::preview_added_memory(EntityKind::Package, names, |name| {
+            tables.packages.contains(name)
+        });
+        self.create()?.rollback();
+        Ok(plan)
+    }
 }
@@ -1,9 +1,12 @@
 use itertools::Itertools;
-use postgres::types::ToSql;
 use snafu::{ResultExt, Snafu};
+use std::marker::PhantomData;
 //use std::fmt;
-use crate::traits::TransactionHandler;
-use log;
+use crate::db::async_packrat::AsyncTransaction;
+use crate::db::memory::MemoryTransaction;
+use crate::db::plan::{self, ChangePlan, EntityKind};
+use crate::db::prepared::Prepared;
+use crate::traits::{AsyncTransactionHandler, MemoryTransactionHandler, TransactionHandler};
 use postgres::Transaction;
 
 #[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
@@ -30,10 +33,11 @@ pub enum AddPlatformsError {
 }
 
 /// Responsible for creating platforms
-pub struct AddPlatforms<'a> {
-    tx: Option<Transaction<'a>>,
+pub struct AddPlatforms<'a, T = Transaction<'a>> {
+    tx: Option<T>,
     names: Vec<String>,
     result_cnt: u64,
+    phantom: PhantomData<&'a ()>,
 }
 
 impl<'a> TransactionHandler<'a> for AddPlatforms<'a> {
@@ -55,7 +59,44 @@ impl<'a> TransactionHandler<'a> for AddPlatforms<'a> {
     }
 }
 
-impl<'a> AddPlatforms<'a> {
+impl<'a> AsyncTransactionHandler<'a> for AddPlatforms<'a, AsyncTransaction<'a>> {
+    type Error = tokio_postgres::error::Error;
+    fn tx(&mut self) -> Option<&mut AsyncTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> AsyncTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> MemoryTransactionHandler<'a> for AddPlatforms<'a, MemoryTransaction<'a>> {
+    fn tx(&mut self) -> Option<&mut MemoryTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> MemoryTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a, T> AddPlatforms<'a, T> {
     /// New up an AddPlatforms instance. This function takes a mutable
     /// reference to the postgres::Client, which is responsible for holding
     /// a connection to the database, as well as providing a crud interface.
@@ -65,11 +106,12 @@ impl<'a> AddPlatforms<'a> {
     ///
     /// # Returns
     /// * an instance of AddPlatforms
-    pub fn new(tx: Transaction<'a>) -> Self {
+    pub fn new(tx: T) -> Self {
         Self {
             tx: Some(tx),
             names: Vec::new(),
             result_cnt: 0,
+            phantom: PhantomData,
         }
     }
 
@@ -126,13 +168,8 @@ impl<'a> AddPlatforms<'a> {
         insert_str
     }
 
-    /// Create the platforms we have previously identified with the
-    /// `platform` and/or `platforms` methods, returning the number of
-    /// novel platforms created, if successful, or an error if unsuccessful.
-    ///
-    /// # Returns
-    /// * Ok(u64) | Err(AddPlatformsError)
-    pub fn create(mut self) -> Result<Self, AddPlatformsError> {
+    // validate the platforms and prefix them with 'any.'
+    fn paths(&self) -> Result<Vec<String>, AddPlatformsError> {
         // convert the self.names of platforms to lowercase after
         // making sure the list is unique, and prefixing with 'any.'
         let platforms = self
@@ -157,24 +194,119 @@ impl<'a> AddPlatforms<'a> {
                 });
             }
         }
-        let mut platforms_ref: Vec<&(dyn ToSql + Sync)> = Vec::new();
-        for p in &platforms {
-            platforms_ref.push(p);
+        Ok(platforms)
+    }
+
+    // validate the names and build the insert statement
+    fn prepare(&self) -> Result<Prepared, AddPlatformsError> {
+        let platforms = self.paths()?;
+        let insert_str = self.generate_prepared_statement(platforms.len());
+        let mut prepared = Prepared::new(insert_str);
+        for param in platforms {
+            prepared.push(param);
         }
+        Ok(prepared)
+    }
+}
 
-        let insert_str = self.generate_prepared_statement(platforms_ref.len());
+impl<'a> AddPlatforms<'a> {
+    /// Create the platforms we have previously identified with the
+    /// `platform` and/or `platforms` methods, returning the number of
+    /// novel platforms created, if successful, or an error if unsuccessful.
+    ///
+    /// # Returns
+    /// * Ok(u64) | Err(AddPlatformsError)
+    pub fn create(mut self) -> Result<Self, AddPlatformsError> {
+        let prepared = self.prepare()?;
+        self.result_cnt = prepared
+            .execute(self.tx().unwrap())
+            .context(TokioPostgresError {
+                msg: "failed to add platforms",
+            })?;
+        Ok(self)
+    }
+
+    /// Create the platforms within the transaction, as `create` does, and
+    /// report the platforms added before rolling the transaction back.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddPlatformsError)
+    pub fn preview(mut self) -> Result<ChangePlan, AddPlatformsError> {
+        let prepared = self.prepare()?;
+        let plan = plan::preview_added(self.tx().unwrap(), prepared, EntityKind::Platform)
+            .context(TokioPostgresError {
+                msg: "failed to add platforms",
+            })?;
+        self.rollback().context(TokioPostgresError {
+            msg: "failed to roll back transaction",
+        })?;
+        Ok(plan)
+    }
+}
+
+impl<'a> AddPlatforms<'a, AsyncTransaction<'a>> {
+    /// Create the platforms we have previously identified with the
+    /// `platform` and/or `platforms` methods, returning the number of
+    /// novel platforms created, if successful, or an error if unsuccessful.
+    ///
+    /// # Returns
+    /// * Ok(u64) | Err(AddPlatformsError)
+    pub async fn create(mut self) -> Result<Self, AddPlatformsError> {
+        let prepared = self.prepare()?;
+        self.result_cnt =
+            prepared
+                .execute_async(self.tx().unwrap())
+                .await
+                .context(TokioPostgresError {
+                    msg: "failed to add platforms",
+                })?;
+        Ok(self)
+    }
 
-        log::info!("SQL\n{}", insert_str.as_str());
-        log::info!("Prepared\n{:?}", &platforms_ref);
-        // execute the query, capture the results and provide a failure context.
-        let results = self
-            .tx()
-            .unwrap()
-            .execute(insert_str.as_str(), &platforms_ref[..])
+    /// Create the platforms within the transaction, as `create` does, and
+    /// report the platforms added before rolling the transaction back.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddPlatformsError)
+    pub async fn preview(mut self) -> Result<ChangePlan, AddPlatformsError> {
+        let prepared = self.prepare()?;
+        let plan = plan::preview_added_async(self.tx().unwrap(), prepared, EntityKind::Platform)
+            .await
             .context(TokioPostgresError {
                 msg: "failed to add platforms",
             })?;
-        self.result_cnt = results;
+        self.rollback().await.context(TokioPostgresError {
+            msg: "failed to roll back transaction",
+        })?;
+        Ok(plan)
+    }
+}
+
+impl<'a> AddPlatforms<'a, MemoryTransaction<'a>> {
+    /// Create the platforms we have previously identified with the
+    /// `platform` and/or `platforms` methods, returning the number of
+    /// novel platforms created, if successful, or an error if unsuccessful.
+    ///
+    /// # Returns
+    /// * Ok(u64) | Err(AddPlatformsError)
+    pub fn create(mut self) -> Result<Self, AddPlatformsError> {
+        let paths = self.paths()?;
+        self.result_cnt = self.tx().unwrap().add_platforms(&paths);
         Ok(self)
     }
+
+    /// Create the platforms within the transaction, as `create` does, and
+    /// report the platforms added before discarding the transaction.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddPlatformsError)
+    pub fn preview(mut self) -> Result<ChangePlan, AddPlatformsError> {
+        let names = self.paths()?;
+        let tables = self.tx().unwrap().tables();
+        let plan = plan::preview_added_memory(EntityKind::Platform, names, |name| {
+            tables.platforms.contains(name)
+        });
+        self.create()?.rollback();
+        Ok(plan)
+    }
 }
@@ -1,9 +1,12 @@
-use crate::traits::TransactionHandler;
+use crate::db::async_packrat::AsyncTransaction;
+use crate::db::memory::MemoryTransaction;
+use crate::db::plan::{self, ChangePlan, EntityKind};
+use crate::db::prepared::Prepared;
+use crate::traits::{AsyncTransactionHandler, MemoryTransactionHandler, TransactionHandler};
 use itertools::Itertools;
-use log;
-use postgres::types::ToSql;
 use postgres::Transaction;
 use snafu::{ResultExt, Snafu};
+use std::marker::PhantomData;
 
 #[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
 pub enum InvalidRoleKind {
@@ -26,10 +29,11 @@ pub enum AddRolesError {
 }
 
 /// Responsible for creating roles
-pub struct AddRoles<'a> {
-    tx: Option<Transaction<'a>>,
+pub struct AddRoles<'a, T = Transaction<'a>> {
+    tx: Option<T>,
     names: Vec<String>,
     result_cnt: u64,
+    phantom: PhantomData<&'a ()>,
 }
 
 impl<'a> TransactionHandler<'a> for AddRoles<'a> {
@@ -51,7 +55,44 @@ impl<'a> TransactionHandler<'a> for AddRoles<'a> {
     }
 }
 
-impl<'a> AddRoles<'a> {
+impl<'a> AsyncTransactionHandler<'a> for AddRoles<'a, AsyncTransaction<'a>> {
+    type Error = tokio_postgres::error::Error;
+    fn tx(&mut self) -> Option<&mut AsyncTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> AsyncTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> MemoryTransactionHandler<'a> for AddRoles<'a, MemoryTransaction<'a>> {
+    fn tx(&mut self) -> Option<&mut MemoryTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> MemoryTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a, T> AddRoles<'a, T> {
     /// New up an AddRoles instance, given a mutable reference to a
     /// postgres::Client.
     ///
@@ -60,11 +101,12 @@ impl<'a> AddRoles<'a> {
     ///
     /// # Returns
     /// * instance of Self
-    pub fn new(tx: Transaction<'a>) -> Self {
+    pub fn new(tx: T) -> Self {
         Self {
             tx: Some(tx),
             names: Vec::new(),
             result_cnt: 0,
+            phantom: PhantomData,
         }
     }
 
@@ -109,13 +151,9 @@ impl<'a> AddRoles<'a> {
         insert_str.push_str(" ON CONFLICT (path) DO NOTHING");
         insert_str
     }
-    /// Attempt to create roles, based on prior invokations of `role` or `roles`
-    /// methods. If successful, create returnw the number of new roles added. If
-    /// create encounters a problem, it returns an AddRolesError.
-    ///
-    /// # Returns
-    /// * Ok(u64) | Err(AddRolesError)
-    pub fn create(mut self) -> Result<Self, AddRolesError> {
+    // validate the roles and expand them into the paths to create, so that
+    // model_beta yields any.model and any.model.beta
+    fn paths(&self) -> Result<Vec<String>, AddRolesError> {
         let mut expand_roles = Vec::new();
         let roles = self
             .names
@@ -143,24 +181,119 @@ impl<'a> AddRoles<'a> {
                 previous = next;
             }
         }
-        let mut roles_ref: Vec<&(dyn ToSql + Sync)> = Vec::new();
-        for p in &expand_roles {
-            roles_ref.push(p);
+        Ok(expand_roles)
+    }
+
+    // build the insert statement
+    fn prepare(&self) -> Result<Prepared, AddRolesError> {
+        let expand_roles = self.paths()?;
+        let insert_str = self.generate_prepared_statement(expand_roles.len());
+        let mut prepared = Prepared::new(insert_str);
+        for param in expand_roles {
+            prepared.push(param);
         }
+        Ok(prepared)
+    }
+}
 
-        let insert_str = self.generate_prepared_statement(roles_ref.len());
+impl<'a> AddRoles<'a> {
+    /// Attempt to create roles, based on prior invokations of `role` or `roles`
+    /// methods. If successful, create returnw the number of new roles added. If
+    /// create encounters a problem, it returns an AddRolesError.
+    ///
+    /// # Returns
+    /// * Ok(u64) | Err(AddRolesError)
+    pub fn create(mut self) -> Result<Self, AddRolesError> {
+        let prepared = self.prepare()?;
+        self.result_cnt = prepared
+            .execute(self.tx().unwrap())
+            .context(TokioPostgresError {
+                msg: "failed to add roles",
+            })?;
+        Ok(self)
+    }
 
-        log::info!("SQL\n{}", insert_str.as_str());
-        log::info!("Prepared\n{:?}", &roles_ref);
-        let results = self
-            .tx()
-            .unwrap()
-            .execute(insert_str.as_str(), &roles_ref[..])
+    /// Create the roles within the transaction, as `create` does, and
+    /// report the roles added before rolling the transaction back.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddRolesError)
+    pub fn preview(mut self) -> Result<ChangePlan, AddRolesError> {
+        let prepared = self.prepare()?;
+        let plan = plan::preview_added(self.tx().unwrap(), prepared, EntityKind::Role).context(
+            TokioPostgresError {
+                msg: "failed to add roles",
+            },
+        )?;
+        self.rollback().context(TokioPostgresError {
+            msg: "failed to roll back transaction",
+        })?;
+        Ok(plan)
+    }
+}
+
+impl<'a> AddRoles<'a, AsyncTransaction<'a>> {
+    /// Attempt to create roles, based on prior invokations of `role` or `roles`
+    /// methods. If successful, create returnw the number of new roles added. If
+    /// create encounters a problem, it returns an AddRolesError.
+    ///
+    /// # Returns
+    /// * Ok(u64) | Err(AddRolesError)
+    pub async fn create(mut self) -> Result<Self, AddRolesError> {
+        let prepared = self.prepare()?;
+        self.result_cnt =
+            prepared
+                .execute_async(self.tx().unwrap())
+                .await
+                .context(TokioPostgresError {
+                    msg: "failed to add roles",
+                })?;
+        Ok(self)
+    }
+
+    /// Create the roles within the transaction, as `create` does, and
+    /// report the roles added before rolling the transaction back.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddRolesError)
+    pub async fn preview(mut self) -> Result<ChangePlan, AddRolesError> {
+        let prepared = self.prepare()?;
+        let plan = plan::preview_added_async(self.tx().unwrap(), prepared, EntityKind::Role)
+            .await
             .context(TokioPostgresError {
                 msg: "failed to add roles",
             })?;
-        self.result_cnt = results;
+        self.rollback().await.context(TokioPostgresError {
+            msg: "failed to roll back transaction",
+        })?;
+        Ok(plan)
+    }
+}
 
+impl<'a> AddRoles<'a, MemoryTransaction<'a>> {
+    /// Attempt to create roles, based on prior invokations of `role` or `roles`
+    /// methods. If successful, create returnw the number of new roles added. If
+    /// create encounters a problem, it returns an AddRolesError.
+    ///
+    /// # Returns
+    /// * Ok(u64) | Err(AddRolesError)
+    pub fn create(mut self) -> Result<Self, AddRolesError> {
+        let paths = self.paths()?;
+        self.result_cnt = self.tx().unwrap().add_roles(&paths);
         Ok(self)
     }
+
+    /// Create the roles within the transaction, as `create` does, and
+    /// report the roles added before discarding the transaction.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddRolesError)
+    pub fn preview(mut self) -> Result<ChangePlan, AddRolesError> {
+        let names = self.paths()?;
+        let tables = self.tx().unwrap().tables();
+        let plan =
+            plan::preview_added_memory(EntityKind::Role, names, |name| tables.roles.contains(name));
+        self.create()?.rollback();
+        Ok(plan)
+    }
 }
@@ -0,0 +1,360 @@
+use itertools::Itertools;
+use snafu::{ResultExt, Snafu};
+use std::marker::PhantomData;
+//use std::fmt;
+use crate::db::async_packrat::AsyncTransaction;
+use crate::db::memory::MemoryTransaction;
+use crate::db::plan::{self, ChangePlan, EntityKind};
+use crate::db::prepared::Prepared;
+use crate::traits::{AsyncTransactionHandler, MemoryTransactionHandler, TransactionHandler};
+use postgres::Transaction;
+
+#[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
+pub enum InvalidSiteKind {
+    InvalidName,
+    InvalidCharacter,
+}
+/// Error type returned from FindVersionPinsError
+#[derive(Debug, Snafu)]
+pub enum AddSitesError {
+    /// When constructing a query, postgres has thrown an error
+    #[snafu(display("Postgres Error: {} {:#?}", msg, source))]
+    TokioPostgresError {
+        msg: &'static str,
+        source: tokio_postgres::error::Error,
+    },
+    #[snafu(display("No site names supplied"))]
+    NoSiteNamesError,
+    #[snafu(display("Invalid site {:?}: {}", kind, site))]
+    InvalidSite { site: String, kind: InvalidSiteKind },
+}
+
+/// Responsible for creating sites
+pub struct AddSites<'a, T = Transaction<'a>> {
+    tx: Option<T>,
+    names: Vec<String>,
+    result_cnt: u64,
+    phantom: PhantomData<&'a ()>,
+}
+
+impl<'a> TransactionHandler<'a> for AddSites<'a> {
+    type Error = tokio_postgres::error::Error;
+    fn tx(&mut self) -> Option<&mut Transaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> Transaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> AsyncTransactionHandler<'a> for AddSites<'a, AsyncTransaction<'a>> {
+    type Error = tokio_postgres::error::Error;
+    fn tx(&mut self) -> Option<&mut AsyncTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> AsyncTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> MemoryTransactionHandler<'a> for AddSites<'a, MemoryTransaction<'a>> {
+    fn tx(&mut self) -> Option<&mut MemoryTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> MemoryTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a, T> AddSites<'a, T> {
+    /// New up an AddSites instance, which creates the sites within the
+    /// supplied transaction.
+    ///
+    /// # Arguments
+    /// * `tx` - A Transaction instance (blocking, async or MemoryStore)
+    ///
+    /// # Returns
+    /// * an instance of AddSites
+    pub fn new(tx: T) -> Self {
+        Self {
+            tx: Some(tx),
+            names: Vec::new(),
+            result_cnt: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Add a site name to the list of site names that we wish to
+    /// create in the database.
+    ///
+    /// # Arguments
+    /// * `name` - The name of a site we wish to create, provided as any
+    ///   type which implements Into<String> (so &str, String, etc)
+    ///
+    /// # Returns
+    /// * A mutable reference to Self
+    pub fn site<I>(mut self, name: I) -> Self
+    where
+        I: Into<String>,
+    {
+        self.names.push(name.into());
+        self
+    }
+
+    /// Add a vector of site names to the list of sites we intend
+    /// on creating.
+    ///
+    /// # Arguments
+    /// * `names` - a mutable vector of strings, which we will "consume",
+    ///   extending our internal list of names to create.
+    ///
+    /// # Returns
+    /// * a mutable reference to Self
+    pub fn sites(mut self, names: &mut Vec<String>) -> Self {
+        self.names.append(names);
+        self
+    }
+
+    // Validate the site name. Is it Invalid? If it is problematic
+    // return true. Otherwise, return false
+    fn site_is_invalid(&self, site: &str) -> bool {
+        site.matches(".").count() > 1
+            || site.matches(" ").count() > 0
+            || site.matches("__").count() > 0
+    }
+
+    // generate the prepared statement, given the number of elements that
+    // it will have.
+    fn generate_prepared_statement(&self, item_count: usize) -> String {
+        let mut insert_str = "INSERT INTO site (path) VALUES ".to_string();
+        let prepared = (1..=item_count)
+            .map(|x| format!(" (text2ltree(${}))", x))
+            .collect::<Vec<_>>();
+        let prepared = prepared.join(",");
+        insert_str.push_str(prepared.as_str());
+        insert_str.push_str(" ON CONFLICT (path) DO NOTHING");
+        insert_str
+    }
+
+    // validate the sites and prefix them with 'any.'
+    fn paths(&self) -> Result<Vec<String>, AddSitesError> {
+        // convert the self.names of sites to lowercase, prefixing them
+        // with 'any.', and make sure the list is unique
+        let sites = self
+            .names
+            .iter()
+            .map(|x| format!("any.{}", x.to_lowercase()))
+            .unique()
+            .collect::<Vec<_>>();
+        // If the user has not thought to actually add names before calling
+        // create, that is bad. Lets return an Error.
+        if sites.is_empty() {
+            return Err(AddSitesError::NoSiteNamesError);
+        }
+        // Validate that the site names are valid minimally. If
+        //we encounter a problem, lets bail. Note to self, I should consider
+        // moving this validation
+        for site in &sites {
+            if self.site_is_invalid(site) {
+                return Err(AddSitesError::InvalidSite {
+                    site: site.clone(),
+                    kind: InvalidSiteKind::InvalidCharacter,
+                });
+            }
+        }
+        Ok(sites)
+    }
+
+    // validate the names and build the insert statement
+    fn prepare(&self) -> Result<Prepared, AddSitesError> {
+        let sites = self.paths()?;
+        let insert_str = self.generate_prepared_statement(sites.len());
+        let mut prepared = Prepared::new(insert_str);
+        for param in sites {
+            prepared.push(param);
+        }
+        Ok(prepared)
+    }
+}
+
+impl<'a> AddSites<'a> {
+    /// Create the sites we have previously identified with the
+    /// `site` and/or `sites` methods, returning the number of
+    /// novel sites created, if successful, or an error if unsuccessful.
+    ///
+    /// # Returns
+    /// * Ok(u64) | Err(AddSitesError)
+    pub fn create(mut self) -> Result<Self, AddSitesError> {
+        let prepared = self.prepare()?;
+        self.result_cnt = prepared
+            .execute(self.tx().unwrap())
+            .context(TokioPostgresError {
+                msg: "failed to add sites",
+            })?;
+        Ok(self)
+    }
+
+    /// Create the sites within the transaction, as `create` does, and
+    /// report the sites added before rolling the transaction back.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddSitesError)
+    pub fn preview(mut self) -> Result<ChangePlan, AddSitesError> {
+        let prepared = self.prepare()?;
+        let plan = plan::preview_added(self.tx().unwrap(), prepared, EntityKind::Site).context(
+            TokioPostgresError {
+                msg: "failed to add sites",
+            },
+        )?;
+        self.rollback().context(TokioPostgresError {
+            msg: "failed to roll back transaction",
+        })?;
+        Ok(plan)
+    }
+}
+
+impl<'a> AddSites<'a, AsyncTransaction<'a>> {
+    /// Create the sites we have previously identified with the
+    /// `site` and/or `sites` methods, returning the number of
+    /// novel sites created, if successful, or an error if unsuccessful.
+    ///
+    /// # Returns
+    /// * Ok(u64) | Err(AddSitesError)
+    pub async fn create(mut self) -> Result<Self, AddSitesError> {
+        let prepared = self.prepare()?;
+        self.result_cnt =
+            prepared
+                .execute_async(self.tx().unwrap())
+                .await
+                .context(TokioPostgresError {
+                    msg: "failed to add sites",
+                })?;
+        Ok(self)
+    }
+
+    /// Create the sites within the transaction, as `create` does, and
+    /// report the sites added before rolling the transaction back.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddSitesError)
+    pub async fn preview(mut self) -> Result<ChangePlan, AddSitesError> {
+        let prepared = self.prepare()?;
+        let plan = plan::preview_added_async(self.tx().unwrap(), prepared, EntityKind::Site)
+            .await
+            .context(TokioPostgresError {
+                msg: "failed to add sites",
+            })?;
+        self.rollback().await.context(TokioPostgresError {
+            msg: "failed to roll back transaction",
+        })?;
+        Ok(plan)
+    }
+}
+
+impl<'a> AddSites<'a, MemoryTransaction<'a>> {
+    /// Create the sites we have previously identified with the
+    /// `site` and/or `sites` methods, returning the number of
+    /// novel sites created, if successful, or an error if unsuccessful.
+    ///
+    /// # Returns
+    /// * Ok(u64) | Err(AddSitesError)
+    pub fn create(mut self) -> Result<Self, AddSitesError> {
+        let paths = self.paths()?;
+        self.result_cnt = self.tx().unwrap().add_sites(&paths);
+        Ok(self)
+    }
+
+    /// Create the sites within the transaction, as `create` does, and
+    /// report the sites added before discarding the transaction.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddSitesError)
+    pub fn preview(mut self) -> Result<ChangePlan, AddSitesError> {
+        let names = self.paths()?;
+        let tables = self.tx().unwrap().tables();
+        let plan =
+            plan::preview_added_memory(EntityKind::Site, names, |name| tables.sites.contains(name));
+        self.create()?.rollback();
+        Ok(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sites(names: &[&str]) -> AddSites<'static, ()> {
+        names
+            .iter()
+            .fold(AddSites::new(()), |builder, name| builder.site(*name))
+    }
+
+    #[test]
+    fn prefixes_unique_lowercased_paths() {
+        let paths = sites(&["Portland", "playa", "portland", "playa"])
+            .paths()
+            .unwrap();
+        assert_eq!(paths, vec!["any.portland", "any.playa"]);
+    }
+
+    #[test]
+    fn rejects_invalid_site_names() {
+        for name in &["portland.west", "play a", "play__a"] {
+            match sites(&[*name]).paths() {
+                Err(AddSitesError::InvalidSite { site, kind }) => {
+                    assert_eq!(site, format!("any.{}", name));
+                    assert_eq!(kind, InvalidSiteKind::InvalidCharacter);
+                }
+                _ => panic!("expected {} to be rejected", name),
+            }
+        }
+    }
+
+    #[test]
+    fn requires_site_names() {
+        assert!(matches!(
+            sites(&[]).prepare(),
+            Err(AddSitesError::NoSiteNamesError)
+        ));
+    }
+
+    #[test]
+    fn prepares_insert() {
+        let prepared = sites(&["portland", "playa"]).prepare().unwrap();
+        assert_eq!(
+            prepared.sql,
+            "INSERT INTO site (path) VALUES  (text2ltree($1)), (text2ltree($2)) ON CONFLICT (path) DO NOTHING"
+        );
+        assert_eq!(
+            format!("{:?}", prepared.params),
+            r#"["any.portland", "any.playa"]"#
+        );
+    }
+}
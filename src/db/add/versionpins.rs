@@ -1,12 +1,19 @@
 use itertools::Itertools;
-use postgres::types::ToSql;
 use snafu::{ResultExt, Snafu};
 //use std::fmt;
-use crate::traits::TransactionHandler;
-use crate::{Level, Platform, Role, Site};
+use crate::db::async_packrat::AsyncTransaction;
+use crate::db::memory::functions::{any_name_to_path, level_name_to_path, role_name_to_path};
+use crate::db::memory::{MemoryStoreError, MemoryTransaction};
+use crate::db::plan::{self, ChangePlan, ChangePlanError};
+use crate::db::prepared::Prepared;
+use crate::traits::{AsyncTransactionHandler, MemoryTransactionHandler, TransactionHandler};
+use crate::types::IdType;
+use crate::{Coords, Distribution, Level, Platform, Role, Site};
 use log;
-use postgres::Transaction;
+use postgres::{Row, Transaction};
 use std::convert::TryInto;
+use std::marker::PhantomData;
+use strum_macros::{AsRefStr, Display, EnumString, IntoStaticStr};
 
 #[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
 pub enum InvalidPlatformKind {
@@ -35,19 +42,167 @@ pub enum AddVersionPinsError {
         platform: String,
         kind: InvalidPlatformKind,
     },
+    /// The MemoryStore has rejected a change
+    #[snafu(display("MemoryStore Error: {}", source))]
+    MemoryError { source: MemoryStoreError },
+    /// A value supplied to `level` or `levels` is not a valid level
+    #[snafu(display("Invalid level: {}", msg))]
+    InvalidLevelError { msg: String },
+    /// A value supplied to `role` or `roles` is not a valid role
+    #[snafu(display("Invalid role: {}", msg))]
+    InvalidRoleError { msg: String },
+    /// A value supplied to `platform` or `platforms` is not a valid platform
+    #[snafu(display("Invalid platform: {}", msg))]
+    InvalidPlatformError { msg: String },
+    /// A value supplied to `site` or `sites` is not a valid site
+    #[snafu(display("Invalid site: {}", msg))]
+    InvalidSiteError { msg: String },
+    #[snafu(display("Level {} does not exist", level))]
+    UnknownLevelError { level: String },
+    #[snafu(display("Role {} does not exist", role))]
+    UnknownRoleError { role: String },
+    #[snafu(display("Platform {} does not exist", platform))]
+    UnknownPlatformError { platform: String },
+    #[snafu(display("Site {} does not exist", site))]
+    UnknownSiteError { site: String },
+    #[snafu(display("Distribution {} does not exist", distribution))]
+    UnknownDistributionError { distribution: String },
+    /// A versionpin exists at the coords, and the policy is
+    /// `ConflictPolicy::Fail`
+    #[snafu(display("Coords {} are already pinned to {}", coords, distribution))]
+    ConflictError {
+        coords: Coords,
+        distribution: String,
+    },
+    /// No versionpins were created, as the supplied values could not be
+    /// converted, or reference a level, role, platform, site or
+    /// distribution which does not exist. Holds an error for each problem.
+    #[snafu(display("Unable to create versionpins: {}", errors.iter().join("; ")))]
+    ValidationError { errors: Vec<AddVersionPinsError> },
+    /// The changes made by a preview could not be read back
+    #[snafu(display("Problem retrieving change plan: {}", source))]
+    PlanError { source: ChangePlanError },
+}
+
+impl AddVersionPinsError {
+    // the error reported for a reference which does not exist
+    fn unknown(kind: &str, name: String) -> Self {
+        match kind {
+            "level" => Self::UnknownLevelError { level: name },
+            "role" => Self::UnknownRoleError { role: name },
+            "platform" => Self::UnknownPlatformError { platform: name },
+            "site" => Self::UnknownSiteError { site: name },
+            _ => Self::UnknownDistributionError { distribution: name },
+        }
+    }
+}
+
+// Select the kind and name of each of the supplied levels, roles, platforms,
+// sites and distribution which do not exist
+const UNKNOWN_REFERENCES: &str = "\
+SELECT 'level', name FROM unnest($1::TEXT[]) AS name
+    WHERE NOT EXISTS (SELECT 1 FROM level WHERE path = level_name_to_path(name))
+UNION ALL
+SELECT 'role', name FROM unnest($2::TEXT[]) AS name
+    WHERE NOT EXISTS (SELECT 1 FROM role WHERE path = role_name_to_path(name))
+UNION ALL
+SELECT 'platform', name FROM unnest($3::TEXT[]) AS name
+    WHERE NOT EXISTS (SELECT 1 FROM platform WHERE path = any_name_to_path(name))
+UNION ALL
+SELECT 'site', name FROM unnest($4::TEXT[]) AS name
+    WHERE NOT EXISTS (SELECT 1 FROM site WHERE path = any_name_to_path(name))
+UNION ALL
+SELECT 'distribution', $5::TEXT
+    WHERE NOT EXISTS (SELECT 1 FROM distribution_view WHERE name = $5::TEXT)";
+
+// Select the position, id and distribution of each versionpin of the package
+// at the supplied coords, whose levels, roles, platforms and sites are
+// supplied as parallel arrays. Coords without a versionpin are omitted.
+const EXISTING_VERSIONPINS: &str = "\
+SELECT coord.ord, pin.versionpin_id, pin.distribution
+    FROM unnest($2::TEXT[], $3::TEXT[], $4::TEXT[], $5::TEXT[]) WITH ORDINALITY
+        AS coord(level_name, role_name, platform_name, site_name, ord)
+    JOIN versionpin_view AS pin
+        ON pin.package = $1
+            AND pin.level_path = level_name_to_path(coord.level_name)
+            AND pin.role_path = role_name_to_path(coord.role_name)
+            AND pin.platform_path = any_name_to_path(coord.platform_name)
+            AND pin.site_path = any_name_to_path(coord.site_name)";
+
+/// How AddVersionPins treats coords at which the package is already pinned
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr, Clone, Copy,
+)]
+pub enum ConflictPolicy {
+    /// Leave the existing versionpin in place
+    #[strum(serialize = "skip", serialize = "Skip", to_string = "skip")]
+    Skip,
+    /// Pin the new distribution in place of the existing one
+    #[strum(
+        serialize = "overwrite",
+        serialize = "Overwrite",
+        to_string = "overwrite"
+    )]
+    Overwrite,
+    /// Create no versionpins if any coords are already pinned
+    #[strum(serialize = "fail", serialize = "Fail", to_string = "fail")]
+    Fail,
+}
+
+/// What AddVersionPins did at a single set of coords
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr, Clone, Copy,
+)]
+pub enum PinAction {
+    #[strum(serialize = "created", to_string = "created")]
+    Created,
+    #[strum(serialize = "skipped", to_string = "skipped")]
+    Skipped,
+    #[strum(serialize = "overwritten", to_string = "overwritten")]
+    Overwritten,
 }
 
+/// The outcome of pinning the distribution at a single set of coords
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VersionPinOutcome {
+    pub coords: Coords,
+    pub action: PinAction,
+    /// The distribution pinned at the coords beforehand, if any
+    pub previous: Option<Distribution>,
+    /// The id of the versionpin at the coords. A skipped versionpin
+    /// retains its id, as does an overwritten one.
+    pub versionpin_id: IdType,
+}
+
+impl std::fmt::Display for VersionPinOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.action, self.versionpin_id, self.coords)?;
+        if let Some(ref previous) = self.previous {
+            write!(f, " (was {})", previous)?;
+        }
+        Ok(())
+    }
+}
+
+// Coords at which to pin the distribution, along with the id and
+// distribution of the versionpin already there, if any
+type Pending = (Coords, Option<(IdType, Distribution)>);
+
 /// Responsible for creating version pins for the given package, version
 /// level, list of roles, platform, and site
-pub struct AddVersionPins<'a> {
-    tx: Option<Transaction<'a>>,
+pub struct AddVersionPins<'a, T = Transaction<'a>> {
+    tx: Option<T>,
     package: String,
     version: String,
     levels: Vec<Level>,
     roles: Vec<Role>,
     platforms: Vec<Platform>,
     sites: Vec<Site>,
+    errors: Vec<AddVersionPinsError>,
+    policy: ConflictPolicy,
+    outcomes: Vec<VersionPinOutcome>,
     result_cnt: u64,
+    phantom: PhantomData<&'a ()>,
 }
 
 impl<'a> TransactionHandler<'a> for AddVersionPins<'a> {
@@ -69,17 +224,58 @@ impl<'a> TransactionHandler<'a> for AddVersionPins<'a> {
     }
 }
 
-impl<'a> AddVersionPins<'a> {
-    /// New up an AddVersionPins instance. This function takes a mutable
-    /// reference to the postgres::Client, which is responsible for holding
-    /// a connection to the database, as well as providing a crud interface.
+impl<'a> AsyncTransactionHandler<'a> for AddVersionPins<'a, AsyncTransaction<'a>> {
+    type Error = tokio_postgres::error::Error;
+    fn tx(&mut self) -> Option<&mut AsyncTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> AsyncTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> MemoryTransactionHandler<'a> for AddVersionPins<'a, MemoryTransaction<'a>> {
+    fn tx(&mut self) -> Option<&mut MemoryTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> MemoryTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a, T> AddVersionPins<'a, T> {
+    /// New up an AddVersionPins instance. There is no default conflict
+    /// policy, as none of them is safe for every caller: `Fail` refuses to
+    /// pin anything once any coords are pinned, while `Skip` and `Overwrite`
+    /// quietly leave or replace the existing versionpins.
     ///
     /// # Arguments
-    /// * `client` a mutable reference to a postgress:Client
+    /// * `tx` - The transaction within which the versionpins are created
+    /// * `package` - The name of the package
+    /// * `version` - The version of the package's distribution to pin
+    /// * `policy` - How to treat coords at which the package is already pinned
     ///
     /// # Returns
     /// * an instance of AddVersionPins
-    pub fn new(tx: Transaction<'a>, package: String, version: String) -> Self {
+    pub fn new(tx: T, package: String, version: String, policy: ConflictPolicy) -> Self {
         Self {
             tx: Some(tx),
             package,
@@ -88,7 +284,11 @@ impl<'a> AddVersionPins<'a> {
             levels: Vec::new(),
             platforms: Vec::new(),
             sites: Vec::new(),
+            errors: Vec::new(),
+            policy,
+            outcomes: Vec::new(),
             result_cnt: 0,
+            phantom: PhantomData,
         }
     }
 
@@ -101,8 +301,11 @@ impl<'a> AddVersionPins<'a> {
         let irole = match role.try_into() {
             Ok(role) => role,
             Err(e) => {
-                log::error!("Unable to convert into Role: {:?}. Using Role::Any", e);
-                Role::Any
+                log::error!("Unable to convert into Role: {:?}", e);
+                self.errors.push(AddVersionPinsError::InvalidRoleError {
+                    msg: format!("{:?}", e),
+                });
+                return;
             }
         };
         if !self.roles.contains(&irole) {
@@ -119,11 +322,11 @@ impl<'a> AddVersionPins<'a> {
         let level = match lvl.try_into() {
             Ok(level) => level,
             Err(e) => {
-                log::error!(
-                    "Unable to convert into Level: {:?}. Using Level::Facility",
-                    e
-                );
-                Level::Facility
+                log::error!("Unable to convert into Level: {:?}", e);
+                self.errors.push(AddVersionPinsError::InvalidLevelError {
+                    msg: format!("{:?}", e),
+                });
+                return;
             }
         };
         if !self.levels.contains(&level) {
@@ -140,11 +343,11 @@ impl<'a> AddVersionPins<'a> {
         let platform = match pltfrm.try_into() {
             Ok(platform) => platform,
             Err(e) => {
-                log::error!(
-                    "Unable to convert into Platform: {:?}. Using Platform::Any",
-                    e
-                );
-                Platform::Any
+                log::error!("Unable to convert into Platform: {:?}", e);
+                self.errors.push(AddVersionPinsError::InvalidPlatformError {
+                    msg: format!("{:?}", e),
+                });
+                return;
             }
         };
         if !self.platforms.contains(&platform) {
@@ -161,8 +364,11 @@ impl<'a> AddVersionPins<'a> {
         let isite = match site.try_into() {
             Ok(site) => site,
             Err(e) => {
-                log::error!("Unable to convert into site: {:?}.Using Site::Any", e);
-                Site::Any
+                log::error!("Unable to convert into site: {:?}", e);
+                self.errors.push(AddVersionPinsError::InvalidSiteError {
+                    msg: format!("{:?}", e),
+                });
+                return;
             }
         };
         if !self.sites.contains(&isite) {
@@ -322,132 +528,597 @@ impl<'a> AddVersionPins<'a> {
         self
     }
 
-    /// Create the platforms we have previously identified with the
-    /// `platform` and/or `platforms` methods, returning the number of
-    /// novel platforms created, if successful, or an error if unsuccessful.
-    ///
-    /// # Returns
-    /// * Ok(u64) | Err(AddVersionPinsError)
-    pub fn create(mut self) -> Result<Self, AddVersionPinsError> {
-        // make sure the various coords start with any (or facility for level)
-        let platforms = self
-            .platforms
-            .iter()
-            .unique()
-            .map(|x| {
-                x.to_string()
-                // let xs = x.to_string();
-                // if xs.starts_with("any") {
-                //     xs
-                // } else {
-                //     format!("any.{}", x.as_ref())
-                // }
-            })
-            .collect::<Vec<_>>();
-
-        let levels = self
-            .levels
-            .iter()
-            .unique()
-            .map(|x| {
-                x.to_string()
-                //let x = x.to_string()
-                // if xs.starts_with("facility") {
-                //     xs
-                // } else {
-                //     format!("facility.{}", xs)
-                // }
-            })
-            .collect::<Vec<_>>();
-
-        let roles = self
-            .roles
-            .iter()
-            .unique()
-            .map(|x| {
-                x.to_string()
-                // if x.as_ref().starts_with("any") {
-                //     x.as_ref().to_string()
-                // } else {
-                //     format!("any.{}", x.as_ref())
-                // }
-            })
-            .collect::<Vec<_>>();
-
-        let sites = self
-            .sites
-            .iter()
-            .unique()
-            .map(|x| {
-                x.to_string()
-                // if x.as_ref().starts_with("any") {
-                //     x.as_ref().to_string()
-                // } else {
-                //     format!("any.{}", x.as_ref())
-                // }
-            })
-            .collect::<Vec<_>>();
+    /// Retrieve the outcome at each of the coords, in the order they were
+    /// pinned. Populated by `create`.
+    pub fn outcomes(&self) -> &[VersionPinOutcome] {
+        &self.outcomes
+    }
 
+    // validate the coords, returning the coords of each versionpin to create
+    fn coords(&mut self) -> Result<Vec<Coords>, AddVersionPinsError> {
+        // values which failed to convert are reported rather than skipped
+        if !self.errors.is_empty() {
+            return Err(AddVersionPinsError::ValidationError {
+                errors: self.errors.drain(..).collect(),
+            });
+        }
         // If the user has not thought to actually add components before calling
         // create, that is bad. Lets return an Error.
-        if platforms.len() == 0 {
+        if self.platforms.is_empty() {
             return Err(AddVersionPinsError::NoPlatformsError);
         }
-        if roles.len() == 0 {
+        if self.roles.is_empty() {
             return Err(AddVersionPinsError::NoRolesError);
         }
-        if levels.len() == 0 {
+        if self.levels.is_empty() {
             return Err(AddVersionPinsError::NoLevelsError);
         }
-        if sites.len() == 0 {
+        if self.sites.is_empty() {
             return Err(AddVersionPinsError::NoSitesError);
         }
-        let mut result_cnt: u64 = 0;
-        // let package = self.package.clone();
-        // let version = self.version.clone();
-        let dist = format!("{}-{}", self.package, self.version);
-        let tx = self.tx().expect("unable to create a transaction");
-        for role in roles {
-            for level in &levels {
-                for platform in &platforms {
-                    for site in &sites {
-                        //                         let insert_str = "INSERT INTO pkgcoord(package,role,level,site,platform) VALUES($1, text2ltree($2), text2ltree($3), text2ltree($4), text2ltree($5)) ON CONFLICT DO NOTHING";
-                        //                         let args: Vec<&(dyn ToSql + Sync)> =
-                        //                             vec![&package, &role, &level, &site, &platform];
-                        //                         log::info!("Sql: {}", insert_str);
-                        //                         log::info!("Args:{:?}", &args);
-                        //                         tx.execute(insert_str, &args[..])
-                        //                             .context(TokioPostgresError {
-                        //                                 msg: "failed to insert pkgcoord",
-                        //                             })?;
-                        //                         let insert_str = "INSERT INTO versionpin(distribution, coord)
-                        //  WITH
-                        //    t1 AS
-                        //      (SELECT id FROM distribution WHERE package=$1 AND version=$2
-                        //    ),
-                        //    t2 AS
-                        //     (SELECT id FROM pkgcoord WHERE package=$1 AND role=$3 AND level=$4 AND platform=$5 AND site=$6)
-                        //    SELECT t1.id, t2.id
-                        //    FROM t1,t2 ON CONFLICT DO NOTHING";
-                        //                         let args: Vec<&(dyn ToSql + Sync)> =
-                        //                             vec![&package, &version, &role, &level, &platform, &site];
-                        let insert_str = "SELECT * from INSERT_VERSIONPIN($1, level_n => $2, site_n => $3, role_n => $4, platform_n => $5)";
-                        let args: Vec<&(dyn ToSql + Sync)> =
-                            vec![&dist, &level, &site, &role, &platform];
-
-                        log::info!("Sql: {}", insert_str);
-                        log::info!("Args:{:?}", &args);
-                        let results =
-                            tx.execute(insert_str, &args[..])
-                                .context(TokioPostgresError {
-                                    msg: "failed to insert versionpin",
-                                })?;
-                        result_cnt += results;
+        let mut coords = Vec::new();
+        for role in self.roles.iter().unique() {
+            for level in self.levels.iter().unique() {
+                for platform in self.platforms.iter().unique() {
+                    for site in self.sites.iter().unique() {
+                        coords.push(Coords::from_parts(
+                            level.clone(),
+                            role.clone(),
+                            platform.clone(),
+                            site.clone(),
+                        ));
                     }
                 }
             }
         }
-        self.result_cnt = result_cnt;
+        Ok(coords)
+    }
+
+    // the names of the distribution, and the unique levels, roles, platforms
+    // and sites, which must exist before any versionpins are created
+    fn references(&self) -> (String, [Vec<String>; 4]) {
+        fn names<T: ToString + Eq + std::hash::Hash>(values: &[T]) -> Vec<String> {
+            values.iter().unique().map(ToString::to_string).collect()
+        }
+        (
+            format!("{}-{}", self.package, self.version),
+            [
+                names(&self.levels),
+                names(&self.roles),
+                names(&self.platforms),
+                names(&self.sites),
+            ],
+        )
+    }
+
+    // build the query selecting the references which do not exist
+    fn prepare_check(&self) -> Prepared {
+        let (distribution, [levels, roles, platforms, sites]) = self.references();
+        let mut prepared = Prepared::new(UNKNOWN_REFERENCES);
+        prepared.push(levels);
+        prepared.push(roles);
+        prepared.push(platforms);
+        prepared.push(sites);
+        prepared.push(distribution);
+        prepared
+    }
+
+    // report the references which do not exist, if any
+    fn check(unknown: Vec<(String, String)>) -> Result<(), AddVersionPinsError> {
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        Err(AddVersionPinsError::ValidationError {
+            errors: unknown
+                .into_iter()
+                .map(|(kind, name)| AddVersionPinsError::unknown(&kind, name))
+                .collect(),
+        })
+    }
+
+    // build the query selecting the existing versionpins of the package at
+    // each of the coords
+    fn prepare_existing(package: &str, coords: &[Coords]) -> Prepared {
+        let names = |name: fn(&Coords) -> String| coords.iter().map(name).collect::<Vec<_>>();
+        let mut prepared = Prepared::new(EXISTING_VERSIONPINS);
+        prepared.push(package.to_string());
+        prepared.push(names(|coords| coords.level.to_string()));
+        prepared.push(names(|coords| coords.role.to_string()));
+        prepared.push(names(|coords| coords.platform.to_string()));
+        prepared.push(names(|coords| coords.site.to_string()));
+        prepared
+    }
+
+    // the id and distribution of the existing versionpin at each of the
+    // coords, if any, from the rows selected by `prepare_existing`
+    fn existing(count: usize, rows: Vec<Row>) -> Vec<Option<(IdType, String)>> {
+        let mut existing = vec![None; count];
+        for row in rows {
+            let ord: i64 = row.get(0);
+            existing[ord as usize - 1] = Some((row.get(1), row.get(2)));
+        }
+        existing
+    }
+
+    // build the statement pinning the distribution at the coords, which
+    // returns the id of the new versionpin
+    fn prepare_insert(dist: &str, coords: &Coords) -> Prepared {
+        let mut prepared = Prepared::new("SELECT * from INSERT_VERSIONPIN($1, level_n => $2, site_n => $3, role_n => $4, platform_n => $5)");
+        prepared.push(dist.to_string());
+        prepared.push(coords.level.to_string());
+        prepared.push(coords.site.to_string());
+        prepared.push(coords.role.to_string());
+        prepared.push(coords.platform.to_string());
+        prepared
+    }
+
+    // build the statement pinning the distribution in place of the one
+    // pinned by an existing versionpin
+    fn prepare_overwrite(dist: &str, versionpin_id: IdType) -> Prepared {
+        let mut prepared = Prepared::new(
+            "UPDATE versionpin SET distribution = \
+             (SELECT distribution_id FROM distribution_view WHERE name = $1) WHERE id = $2",
+        );
+        prepared.push(dist.to_string());
+        prepared.push(versionpin_id);
+        prepared
+    }
+
+    // pair each of the coords with the id and distribution of the versionpin
+    // already there, if any, reporting them under ConflictPolicy::Fail
+    fn resolve(
+        &self,
+        coords: Vec<Coords>,
+        existing: Vec<Option<(IdType, String)>>,
+    ) -> Result<Vec<Pending>, AddVersionPinsError> {
+        if self.policy == ConflictPolicy::Fail {
+            let errors = coords
+                .iter()
+                .zip(existing.iter())
+                .filter_map(|(coords, existing)| {
+                    existing
+                        .as_ref()
+                        .map(|(_, distribution)| AddVersionPinsError::ConflictError {
+                            coords: coords.clone(),
+                            distribution: distribution.clone(),
+                        })
+                })
+                .collect::<Vec<_>>();
+            if !errors.is_empty() {
+                return Err(AddVersionPinsError::ValidationError { errors });
+            }
+        }
+        Ok(coords
+            .into_iter()
+            .zip(existing)
+            .map(|(coords, existing)| {
+                let existing =
+                    existing.map(|(id, name)| (id, Distribution::new_unchecked(name.as_str())));
+                (coords, existing)
+            })
+            .collect())
+    }
+
+    // record the outcome at the coords
+    fn outcome(
+        &mut self,
+        coords: Coords,
+        action: PinAction,
+        previous: Option<Distribution>,
+        versionpin_id: IdType,
+    ) {
+        if action != PinAction::Skipped {
+            self.result_cnt += 1;
+        }
+        self.outcomes.push(VersionPinOutcome {
+            coords,
+            action,
+            previous,
+            versionpin_id,
+        });
+    }
+}
+
+impl<'a> AddVersionPins<'a> {
+    /// Create a versionpin at each combination of the levels, roles,
+    /// platforms and sites previously identified, applying the conflict
+    /// policy to coords at which the package is already pinned. The outcome
+    /// at each of the coords is available via `outcomes`.
+    /// Nothing is inserted unless every supplied value converted, and every
+    /// referenced level, role, platform, site and distribution exists.
+    ///
+    /// # Returns
+    /// * Ok(Self) | Err(AddVersionPinsError)
+    pub fn create(mut self) -> Result<Self, AddVersionPinsError> {
+        let coords = self.coords()?;
+        let check = self.prepare_check();
+        let (package, dist) = (self.package.clone(), self.references().0);
+        let tx = self.tx().expect("unable to create a transaction");
+        let unknown = check.query_tx(tx).context(TokioPostgresError {
+            msg: "failed to check versionpin references",
+        })?;
+        Self::check(unknown.iter().map(|row| (row.get(0), row.get(1))).collect())?;
+        let rows = Self::prepare_existing(&package, &coords)
+            .query_tx(tx)
+            .context(TokioPostgresError {
+                msg: "failed to select existing versionpins",
+            })?;
+        let existing = Self::existing(coords.len(), rows);
+        let policy = self.policy;
+        for (coords, existing) in self.resolve(coords, existing)? {
+            let tx = self.tx().expect("unable to create a transaction");
+            match (existing, policy) {
+                (None, _) => {
+                    let row = Self::prepare_insert(&dist, &coords)
+                        .query_one_tx(tx)
+                        .context(TokioPostgresError {
+                            msg: "failed to insert versionpin",
+                        })?;
+                    let id = row.get(0);
+                    self.outcome(coords, PinAction::Created, None, id);
+                }
+                (Some((id, previous)), ConflictPolicy::Overwrite) => {
+                    Self::prepare_overwrite(&dist, id)
+                        .execute(tx)
+                        .context(TokioPostgresError {
+                            msg: "failed to overwrite versionpin",
+                        })?;
+                    self.outcome(coords, PinAction::Overwritten, Some(previous), id);
+                }
+                (Some((id, previous)), _) => {
+                    self.outcome(coords, PinAction::Skipped, Some(previous), id);
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    /// Create the versionpins within the transaction, as `create` does, and
+    /// report the versionpins inserted before rolling the transaction back.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddVersionPinsError)
+    pub fn preview(mut self) -> Result<ChangePlan, AddVersionPinsError> {
+        let watermark = plan::watermark(self.tx().unwrap()).context(PlanError)?;
+        let mut pins = self.create()?;
+        let plan = plan::changes_since(pins.tx().unwrap(), watermark).context(PlanError)?;
+        pins.rollback().context(TokioPostgresError {
+            msg: "failed to roll back transaction",
+        })?;
+        Ok(plan)
+    }
+}
+
+impl<'a> AddVersionPins<'a, AsyncTransaction<'a>> {
+    /// Create a versionpin at each combination of the levels, roles,
+    /// platforms and sites previously identified, applying the conflict
+    /// policy to coords at which the package is already pinned. The outcome
+    /// at each of the coords is available via `outcomes`.
+    /// Nothing is inserted unless every supplied value converted, and every
+    /// referenced level, role, platform, site and distribution exists.
+    ///
+    /// # Returns
+    /// * Ok(Self) | Err(AddVersionPinsError)
+    pub async fn create(mut self) -> Result<Self, AddVersionPinsError> {
+        let coords = self.coords()?;
+        let check = self.prepare_check();
+        let (package, dist) = (self.package.clone(), self.references().0);
+        let tx = self.tx().expect("unable to create a transaction");
+        let unknown = check.query_tx_async(tx).await.context(TokioPostgresError {
+            msg: "failed to check versionpin references",
+        })?;
+        Self::check(unknown.iter().map(|row| (row.get(0), row.get(1))).collect())?;
+        let rows = Self::prepare_existing(&package, &coords)
+            .query_tx_async(tx)
+            .await
+            .context(TokioPostgresError {
+                msg: "failed to select existing versionpins",
+            })?;
+        let existing = Self::existing(coords.len(), rows);
+        let policy = self.policy;
+        for (coords, existing) in self.resolve(coords, existing)? {
+            let tx = self.tx().expect("unable to create a transaction");
+            match (existing, policy) {
+                (None, _) => {
+                    let row = Self::prepare_insert(&dist, &coords)
+                        .query_one_tx_async(tx)
+                        .await
+                        .context(TokioPostgresError {
+                            msg: "failed to insert versionpin",
+                        })?;
+                    let id = row.get(0);
+                    self.outcome(coords, PinAction::Created, None, id);
+                }
+                (Some((id, previous)), ConflictPolicy::Overwrite) => {
+                    Self::prepare_overwrite(&dist, id)
+                        .execute_async(tx)
+                        .await
+                        .context(TokioPostgresError {
+                            msg: "failed to overwrite versionpin",
+                        })?;
+                    self.outcome(coords, PinAction::Overwritten, Some(previous), id);
+                }
+                (Some((id, previous)), _) => {
+                    self.outcome(coords, PinAction::Skipped, Some(previous), id);
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    /// Create the versionpins within the transaction, as `create` does, and
+    /// report the versionpins inserted before rolling the transaction back.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddVersionPinsError)
+    pub async fn preview(mut self) -> Result<ChangePlan, AddVersionPinsError> {
+        let watermark = plan::watermark_async(self.tx().unwrap())
+            .await
+            .context(PlanError)?;
+        let mut pins = self.create().await?;
+        let plan = plan::changes_since_async(pins.tx().unwrap(), watermark)
+            .await
+            .context(PlanError)?;
+        pins.rollback().await.context(TokioPostgresError {
+            msg: "failed to roll back transaction",
+        })?;
+        Ok(plan)
+    }
+}
+
+impl<'a> AddVersionPins<'a, MemoryTransaction<'a>> {
+    /// Create a versionpin at each combination of the levels, roles,
+    /// platforms and sites previously identified, applying the conflict
+    /// policy to coords at which the package is already pinned. The outcome
+    /// at each of the coords is available via `outcomes`.
+    /// Nothing is inserted unless every supplied value converted, and every
+    /// referenced level, role, platform, site and distribution exists.
+    ///
+    /// # Returns
+    /// * Ok(Self) | Err(AddVersionPinsError)
+    pub fn create(mut self) -> Result<Self, AddVersionPinsError> {
+        let coords = self.coords()?;
+        let (dist, [levels, roles, platforms, sites]) = self.references();
+        let (package, version) = (self.package.clone(), self.version.clone());
+        let tx = self.tx().expect("unable to create a transaction");
+        let tables = tx.tables();
+        let unknown = levels
+            .into_iter()
+            .filter(|name| !tables.levels.contains(&level_name_to_path(name)))
+            .map(|name| ("level".to_string(), name))
+            .chain(
+                roles
+                    .into_iter()
+                    .filter(|name| !tables.roles.contains(&role_name_to_path(name)))
+                    .map(|name| ("role".to_string(), name)),
+            )
+            .chain(
+                platforms
+                    .into_iter()
+                    .filter(|name| !tables.platforms.contains(&any_name_to_path(name)))
+                    .map(|name| ("platform".to_string(), name)),
+            )
+            .chain(
+                sites
+                    .into_iter()
+                    .filter(|name| !tables.sites.contains(&any_name_to_path(name)))
+                    .map(|name| ("site".to_string(), name)),
+            )
+            .chain(
+                Some(dist.clone())
+                    .filter(|dist| !tables.distribution_view().iter().any(|d| &d.name == dist))
+                    .map(|dist| ("distribution".to_string(), dist)),
+            )
+            .collect();
+        Self::check(unknown)?;
+        let dist_id = tables
+            .distribution(&package, &version)
+            .map(|dist| dist.id)
+            .unwrap_or_default();
+        let pins = tables.versionpin_view();
+        let existing = coords
+            .iter()
+            .map(|coords| {
+                pins.iter()
+                    .find(|pin| {
+                        pin.package == package
+                            && pin.level_path == level_name_to_path(&coords.level.to_string())
+                            && pin.role_path == role_name_to_path(coords.role.as_ref())
+                            && pin.platform_path == any_name_to_path(coords.platform.as_ref())
+                            && pin.site_path == any_name_to_path(coords.site.as_ref())
+                    })
+                    .map(|pin| (pin.versionpin_id, pin.distribution.clone()))
+            })
+            .collect();
+        let policy = self.policy;
+        for (coords, existing) in self.resolve(coords, existing)? {
+            let tx = self.tx().expect("unable to create a transaction");
+            match (existing, policy) {
+                (None, _) => {
+                    let id = tx
+                        .insert_versionpin(
+                            &dist,
+                            &coords.level.to_string(),
+                            coords.site.as_ref(),
+                            coords.role.as_ref(),
+                            coords.platform.as_ref(),
+                        )
+                        .context(MemoryError)?;
+                    self.outcome(coords, PinAction::Created, None, id);
+                }
+                (Some((id, previous)), ConflictPolicy::Overwrite) => {
+                    tx.update_versionpin(id, Some(dist_id), None)
+                        .context(MemoryError)?;
+                    self.outcome(coords, PinAction::Overwritten, Some(previous), id);
+                }
+                (Some((id, previous)), _) => {
+                    self.outcome(coords, PinAction::Skipped, Some(previous), id);
+                }
+            }
+        }
         Ok(self)
     }
+
+    /// Create the versionpins within the transaction, as `create` does, and
+    /// report the versionpins inserted before discarding the transaction.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(AddVersionPinsError)
+    pub fn preview(mut self) -> Result<ChangePlan, AddVersionPinsError> {
+        let watermark = plan::watermark_memory(self.tx().unwrap());
+        let mut pins = self.create()?;
+        let plan = plan::changes_since_memory(pins.tx().unwrap(), watermark).context(PlanError)?;
+        pins.rollback();
+        Ok(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a builder pinning maya-2018.sp3 at dev01 for the model and anim roles
+    fn pins(policy: ConflictPolicy) -> AddVersionPins<'static, ()> {
+        AddVersionPins::new((), "maya".to_string(), "2018.sp3".to_string(), policy)
+            .level("dev01")
+            .role("model")
+            .role("anim")
+            .role("model")
+            .platform("cent7_64")
+            .site("any")
+    }
+
+    fn params(prepared: &Prepared) -> String {
+        format!("{:?}", prepared.params)
+    }
+
+    #[test]
+    fn prepares_unknown_references_check() {
+        let prepared = pins(ConflictPolicy::Fail).prepare_check();
+        assert_eq!(prepared.sql, UNKNOWN_REFERENCES);
+        assert_eq!(
+            params(&prepared),
+            r#"[["dev01"], ["model", "anim"], ["cent7_64"], ["any"], "maya-2018.sp3"]"#
+        );
+    }
+
+    #[test]
+    fn reports_unknown_references() {
+        assert!(AddVersionPins::<()>::check(Vec::new()).is_ok());
+        let unknown = vec![
+            ("role".to_string(), "anim".to_string()),
+            ("distribution".to_string(), "maya-2018.sp3".to_string()),
+        ];
+        match AddVersionPins::<()>::check(unknown) {
+            Err(AddVersionPinsError::ValidationError { errors }) => {
+                let errors = errors.iter().map(ToString::to_string).collect::<Vec<_>>();
+                assert_eq!(
+                    errors,
+                    vec![
+                        "Role anim does not exist",
+                        "Distribution maya-2018.sp3 does not exist",
+                    ]
+                );
+            }
+            _ => panic!("expected a ValidationError"),
+        }
+    }
+
+    #[test]
+    fn prepares_existing_versionpins_select() {
+        let mut builder = pins(ConflictPolicy::Fail);
+        let coords = builder.coords().unwrap();
+        let prepared = AddVersionPins::<()>::prepare_existing("maya", &coords);
+        assert_eq!(prepared.sql, EXISTING_VERSIONPINS);
+        assert_eq!(
+            params(&prepared),
+            r#"["maya", ["dev01", "dev01"], ["model", "anim"], ["cent7_64", "cent7_64"], ["any", "any"]]"#
+        );
+    }
+
+    #[test]
+    fn prepares_insert_and_overwrite() {
+        let mut builder = pins(ConflictPolicy::Overwrite);
+        let coords = builder.coords().unwrap();
+        let insert = AddVersionPins::<()>::prepare_insert("maya-2018.sp3", &coords[0]);
+        assert_eq!(
+            insert.sql,
+            "SELECT * from INSERT_VERSIONPIN($1, level_n => $2, site_n => $3, role_n => $4, platform_n => $5)"
+        );
+        assert_eq!(
+            params(&insert),
+            r#"["maya-2018.sp3", "dev01", "any", "model", "cent7_64"]"#
+        );
+        let overwrite = AddVersionPins::<()>::prepare_overwrite("maya-2018.sp3", 7);
+        assert_eq!(
+            overwrite.sql,
+            "UPDATE versionpin SET distribution = \
+             (SELECT distribution_id FROM distribution_view WHERE name = $1) WHERE id = $2"
+        );
+        assert_eq!(params(&overwrite), r#"["maya-2018.sp3", 7]"#);
+    }
+
+    #[test]
+    fn fail_policy_reports_each_pinned_coords() {
+        let mut builder = pins(ConflictPolicy::Fail);
+        let coords = builder.coords().unwrap();
+        let existing = vec![None, Some((7, "maya-2019".to_string()))];
+        match builder.resolve(coords, existing) {
+            Err(AddVersionPinsError::ValidationError { errors }) => {
+                let errors = errors.iter().map(ToString::to_string).collect::<Vec<_>>();
+                assert_eq!(
+                    errors,
+                    vec![
+                        "Coords level:dev01.role:anim.platform:cent7_64.site:any \
+                         are already pinned to maya-2019"
+                    ]
+                );
+            }
+            _ => panic!("expected a ValidationError"),
+        }
+    }
+
+    #[test]
+    fn reports_outcome_at_each_coords() {
+        for (policy, action) in vec![
+            (ConflictPolicy::Skip, PinAction::Skipped),
+            (ConflictPolicy::Overwrite, PinAction::Overwritten),
+        ] {
+            let mut builder = pins(policy);
+            let coords = builder.coords().unwrap();
+            let existing = vec![None, Some((7, "maya-2019".to_string()))];
+            let pending = builder.resolve(coords.clone(), existing).unwrap();
+            let (created, pinned) = (pending[0].clone(), pending[1].clone());
+            assert_eq!(created, (coords[0].clone(), None));
+            assert_eq!(
+                pinned,
+                (
+                    coords[1].clone(),
+                    Some((7, Distribution::new_unchecked("maya-2019")))
+                )
+            );
+            builder.outcome(created.0, PinAction::Created, None, 8);
+            let (coords, previous) = pinned;
+            let (id, previous) = previous.unwrap();
+            builder.outcome(coords, action, Some(previous), id);
+
+            let outcomes = builder
+                .outcomes()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                outcomes,
+                vec![
+                    "created 8 level:dev01.role:model.platform:cent7_64.site:any".to_string(),
+                    format!(
+                        "{} 7 level:dev01.role:anim.platform:cent7_64.site:any (was maya-2019)",
+                        action
+                    ),
+                ]
+            );
+            let expected = if action == PinAction::Skipped { 1 } else { 2 };
+            assert_eq!(builder.result_cnt, expected);
+        }
+    }
 }
@@ -1,9 +1,12 @@
-use crate::traits::TransactionHandler;
+use crate::db::async_packrat::AsyncTransaction;
+use crate::db::memory::{MemoryStoreError, MemoryTransaction};
+use crate::db::plan::{ChangePlan, PlannedChange};
+use crate::db::prepared::Prepared;
+use crate::traits::{AsyncTransactionHandler, MemoryTransactionHandler, TransactionHandler};
 use crate::types::IdType;
-use log;
-use postgres::types::ToSql;
 use postgres::Transaction;
 use snafu::{ResultExt, Snafu};
+use std::marker::PhantomData;
 
 /// Error type returned from FindVersionPinsError
 #[derive(Debug, Snafu)]
@@ -20,12 +23,16 @@ pub enum AddWithsError {
     NoWithNamesError,
     #[snafu(display("No updates supplied"))]
     NoUpdatesError,
+    /// The MemoryStore has rejected a change
+    #[snafu(display("MemoryStore Error: {}", source))]
+    MemoryError { source: MemoryStoreError },
 }
 
 /// The AddWiths struct is responsible for creating withs.
-pub struct AddWiths<'a> {
-    tx: Option<Transaction<'a>>,
+pub struct AddWiths<'a, T = Transaction<'a>> {
+    tx: Option<T>,
     result_cnt: u64,
+    phantom: PhantomData<&'a ()>,
 }
 
 impl<'a> TransactionHandler<'a> for AddWiths<'a> {
@@ -50,7 +57,50 @@ impl<'a> TransactionHandler<'a> for AddWiths<'a> {
     }
 }
 
-impl<'a> AddWiths<'a> {
+impl<'a> AsyncTransactionHandler<'a> for AddWiths<'a, AsyncTransaction<'a>> {
+    type Error = tokio_postgres::error::Error;
+    /// retrieve an Option wrapped mutable reference to the
+    /// transaction
+    fn tx(&mut self) -> Option<&mut AsyncTransaction<'a>> {
+        self.tx.as_mut()
+    }
+    /// Extract the transaction from Self.
+    fn take_tx(&mut self) -> AsyncTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    /// Return the result count to 0
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+    /// Retrieve th result count
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> MemoryTransactionHandler<'a> for AddWiths<'a, MemoryTransaction<'a>> {
+    /// retrieve an Option wrapped mutable reference to the
+    /// transaction
+    fn tx(&mut self) -> Option<&mut MemoryTransaction<'a>> {
+        self.tx.as_mut()
+    }
+    /// Extract the transaction from Self.
+    fn take_tx(&mut self) -> MemoryTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    /// Return the result count to 0
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+    /// Retrieve th result count
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a, T> AddWiths<'a, T> {
     /// New up an AddWiths instance
     ///
     /// # Arguments
@@ -59,13 +109,56 @@ impl<'a> AddWiths<'a> {
     ///
     /// # Returns
     /// * An instance of Self
-    pub fn new(tx: Transaction<'a>) -> Self {
+    pub fn new(tx: T) -> Self {
         Self {
             tx: Some(tx),
             result_cnt: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    // build the statements replacing the withs of the versionpin, in order
+    fn prepare(vpin_id: IdType, withs: Vec<String>) -> Result<Vec<Prepared>, AddWithsError> {
+        if withs.is_empty() {
+            return Err(AddWithsError::NoUpdatesError);
+        }
+        let mut delete = Prepared::new("DELETE FROM withpackage WHERE versionpin = $1");
+        delete.push(vpin_id);
+        let mut statements = vec![delete];
+        for (cnt, x) in withs.into_iter().enumerate() {
+            let mut prepared = Prepared::new(
+                "INSERT INTO withpackage (versionpin, package, pinorder) values ($1,$2,$3)",
+            );
+            prepared.push(vpin_id);
+            prepared.push(x);
+            prepared.push(cnt as i32);
+            statements.push(prepared);
         }
+        Ok(statements)
     }
 
+    // build the query selecting the current withs of the versionpin, in order
+    fn prepare_current(vpin_id: IdType) -> Prepared {
+        let mut prepared = Prepared::new(
+            "SELECT package FROM withpackage WHERE versionpin = $1 ORDER BY pinorder",
+        );
+        prepared.push(vpin_id);
+        prepared
+    }
+
+    // the plan replacing the old withs of the versionpin with the new
+    fn plan(vpin_id: IdType, old: Vec<String>, new: Vec<String>) -> ChangePlan {
+        let mut plan = ChangePlan::new();
+        plan.push(PlannedChange::Withs {
+            versionpin_id: vpin_id,
+            old,
+            new,
+        });
+        plan
+    }
+}
+
+impl<'a> AddWiths<'a> {
     /// update previously registered with in the database. This call is
     /// fallible, and may return either the number of new packages created, or a
     /// relevant error.
@@ -76,37 +169,175 @@ impl<'a> AddWiths<'a> {
     /// # Returns Result
     /// * Ok(u64) | Err(AddWithsError)
     pub fn create(mut self, vpin_id: IdType, withs: Vec<String>) -> Result<Self, AddWithsError> {
-        if withs.len() == 0 {
-            return Err(AddWithsError::NoUpdatesError);
+        let mut statements = Self::prepare(vpin_id, withs)?.into_iter();
+        if let Some(delete) = statements.next() {
+            delete
+                .execute(self.tx().unwrap())
+                .context(TokioPostgresError {
+                    msg: "failed to delete withs before adding new ones",
+                })?;
         }
-        self.tx()
-            .unwrap()
-            .execute("DELETE FROM withpackage WHERE versionpin = $1", &[&vpin_id])
+        let mut cnt = 0;
+        for prepared in statements {
+            prepared
+                .execute(self.tx().unwrap())
+                .context(TokioPostgresError {
+                    msg: "problem executing prepared statement",
+                })?;
+            cnt += 1;
+        }
+        self.result_cnt = cnt;
+
+        Ok(self)
+    }
+
+    /// Replace the withs of the versionpin within the transaction, as
+    /// `create` does, and report the old and new withs before rolling the
+    /// transaction back.
+    ///
+    /// # Arguments
+    /// * `vpin_id` - The id of the versionpin
+    /// * `withs` - The new withs, in order
+    ///
+    /// # Returns Result
+    /// * Ok(ChangePlan) | Err(AddWithsError)
+    pub fn preview(
+        mut self,
+        vpin_id: IdType,
+        withs: Vec<String>,
+    ) -> Result<ChangePlan, AddWithsError> {
+        let rows = Self::prepare_current(vpin_id)
+            .query_tx(self.tx().unwrap())
             .context(TokioPostgresError {
-                msg: "failed to delete withs before adding new ones",
+                msg: "failed to select withs",
             })?;
-        let mut cnt: i32 = 0;
-        for x in &withs {
-            let prepared_line =
-                "INSERT INTO withpackage (versionpin, package, pinorder) values ($1,$2,$3)"
-                    .to_string();
-
-            let prepared_args: &[&(dyn ToSql + std::marker::Sync)] = &[&vpin_id, &x.as_str(), &cnt];
-            log::info!("SQL\n{}", prepared_line.as_str());
-            log::info!("Prepared\n{:?}", &prepared_args);
-            self.tx()
-                .unwrap()
-                .execute(prepared_line.as_str(), &prepared_args[..])
+        let old = rows.iter().map(|row| row.get(0)).collect();
+        let mut added = self.create(vpin_id, withs.clone())?;
+        added.rollback().context(TokioPostgresError {
+            msg: "failed to roll back transaction",
+        })?;
+        Ok(Self::plan(vpin_id, old, withs))
+    }
+}
+
+impl<'a> AddWiths<'a, AsyncTransaction<'a>> {
+    /// update previously registered with in the database. This call is
+    /// fallible, and may return either the number of new packages created, or a
+    /// relevant error.
+    ///
+    /// # Arguments
+    /// None
+    ///
+    /// # Returns Result
+    /// * Ok(u64) | Err(AddWithsError)
+    pub async fn create(
+        mut self,
+        vpin_id: IdType,
+        withs: Vec<String>,
+    ) -> Result<Self, AddWithsError> {
+        let mut statements = Self::prepare(vpin_id, withs)?.into_iter();
+        if let Some(delete) = statements.next() {
+            delete
+                .execute_async(self.tx().unwrap())
+                .await
+                .context(TokioPostgresError {
+                    msg: "failed to delete withs before adding new ones",
+                })?;
+        }
+        let mut cnt = 0;
+        for prepared in statements {
+            prepared
+                .execute_async(self.tx().unwrap())
+                .await
                 .context(TokioPostgresError {
                     msg: "problem executing prepared statement",
                 })?;
             cnt += 1;
         }
-        // technically this could fail / wrap / behave weird.. but
-        // that would be highly unlikely in the real world.
-        assert!(cnt >= 0);
-        self.result_cnt = cnt as u64;
+        self.result_cnt = cnt;
+
+        Ok(self)
+    }
+
+    /// Replace the withs of the versionpin within the transaction, as
+    /// `create` does, and report the old and new withs before rolling the
+    /// transaction back.
+    ///
+    /// # Arguments
+    /// * `vpin_id` - The id of the versionpin
+    /// * `withs` - The new withs, in order
+    ///
+    /// # Returns Result
+    /// * Ok(ChangePlan) | Err(AddWithsError)
+    pub async fn preview(
+        mut self,
+        vpin_id: IdType,
+        withs: Vec<String>,
+    ) -> Result<ChangePlan, AddWithsError> {
+        let rows = Self::prepare_current(vpin_id)
+            .query_tx_async(self.tx().unwrap())
+            .await
+            .context(TokioPostgresError {
+                msg: "failed to select withs",
+            })?;
+        let old = rows.iter().map(|row| row.get(0)).collect();
+        let mut added = self.create(vpin_id, withs.clone()).await?;
+        added.rollback().await.context(TokioPostgresError {
+            msg: "failed to roll back transaction",
+        })?;
+        Ok(Self::plan(vpin_id, old, withs))
+    }
+}
 
+impl<'a> AddWiths<'a, MemoryTransaction<'a>> {
+    /// update previously registered with in the database. This call is
+    /// fallible, and may return either the number of new packages created, or a
+    /// relevant error.
+    ///
+    /// # Arguments
+    /// None
+    ///
+    /// # Returns Result
+    /// * Ok(u64) | Err(AddWithsError)
+    pub fn create(mut self, vpin_id: IdType, withs: Vec<String>) -> Result<Self, AddWithsError> {
+        if withs.is_empty() {
+            return Err(AddWithsError::NoUpdatesError);
+        }
+        self.result_cnt = self
+            .tx()
+            .unwrap()
+            .set_withs(vpin_id, &withs)
+            .context(MemoryError)?;
         Ok(self)
     }
+
+    /// Replace the withs of the versionpin within the transaction, as
+    /// `create` does, and report the old and new withs before discarding the
+    /// transaction.
+    ///
+    /// # Arguments
+    /// * `vpin_id` - The id of the versionpin
+    /// * `withs` - The new withs, in order
+    ///
+    /// # Returns Result
+    /// * Ok(ChangePlan) | Err(AddWithsError)
+    pub fn preview(
+        mut self,
+        vpin_id: IdType,
+        withs: Vec<String>,
+    ) -> Result<ChangePlan, AddWithsError> {
+        let mut current = self
+            .tx()
+            .unwrap()
+            .tables()
+            .withpackages
+            .iter()
+            .filter(|with| with.versionpin == vpin_id)
+            .map(|with| (with.pinorder, with.package.clone()))
+            .collect::<Vec<_>>();
+        current.sort();
+        let old = current.into_iter().map(|(_, package)| package).collect();
+        self.create(vpin_id, withs.clone())?.rollback();
+        Ok(Self::plan(vpin_id, old, withs))
+    }
 }
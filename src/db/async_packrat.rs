@@ -0,0 +1,319 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! The async counterpart of `PackratDb`, built on tokio_postgres. The builders
+//! returned by `AsyncPackratDb` are the same builders returned by `PackratDb`,
+//! parameterized by the async client or transaction, so they share their sql
+//! generation and row parsing with the blocking api. Their `query`, `create`,
+//! and `update` methods are `async`, as is `commit`, which is provided by the
+//! `AsyncTransactionHandler` trait.
+use crate::db::add::versionpins::ConflictPolicy;
+use crate::db::traits::{PBAdd, PBDelete, PBFind, PBUpdate};
+use crate::db::{add, delete, find, find_all, update};
+use crate::packrat::{LoadPlatformsError, LoadSitesError, PackratDbError, TokioPostgresError};
+use crate::registry;
+use crate::types::IdType;
+use snafu::ResultExt;
+pub use tokio_postgres::{Client as AsyncClient, Transaction as AsyncTransaction};
+
+pub struct AsyncPackratDb<'a> {
+    client: &'a mut AsyncClient,
+}
+
+impl<'a> AsyncPackratDb<'a> {
+    /// New up an AsyncPackratDb instance given an AsyncClient. The caller
+    /// is responsible for spawning the connection returned by
+    /// `tokio_postgres::connect`.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use packybara::db::async_packrat::AsyncPackratDb;
+    /// use packybara::traits::PBFind;
+    /// use packybara::PackratConfig;
+    /// use tokio_postgres::NoTls;
+    /// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = PackratConfig::load()?;
+    /// let (mut client, connection) = config.tokio_postgres_config().connect(NoTls).await?;
+    /// tokio::spawn(connection);
+    /// let mut db = AsyncPackratDb::new(&mut client);
+    /// let dist = db.find_versionpin("maya")
+    ///                     .level("dev01")
+    ///                     .query().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(client: &'a mut AsyncClient) -> Self {
+        AsyncPackratDb { client }
+    }
+
+    /// Insert a revision and commit the transaction, returning `commits`
+    ///
+    /// # Arguments
+    /// * `tx` - The transaction to commit
+    /// * `author` - The author of the revision
+    /// * `comment` - A comment describing the revision
+    /// * `commits` - The number of changes made in the transaction
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - `commits`
+    ///   * `Err` - `PackratDbError`
+    pub async fn commit(
+        tx: AsyncTransaction<'a>,
+        author: &str,
+        comment: &str,
+        commits: u64,
+    ) -> Result<u64, PackratDbError> {
+        tx.execute(
+            "INSERT INTO REVISION (author, comment) VALUES ($1, $2)",
+            &[&author, &comment],
+        )
+        .await
+        .context(TokioPostgresError {
+            msg: "failed to insert Revisions",
+        })?;
+
+        tx.commit().await.context(TokioPostgresError {
+            msg: "failed to commit transaction",
+        })?;
+        Ok(commits)
+    }
+
+    /// Generate a transaction for updates and adds
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - `AsyncTransaction`
+    ///   * `Err` - `PackratDbError`
+    pub async fn transaction(&'a mut self) -> Result<AsyncTransaction<'a>, PackratDbError> {
+        self.client.transaction().await.context(TokioPostgresError {
+            msg: "failed to start transaction",
+        })
+    }
+
+    /// Register the sites and platforms stored in the database, so that
+    /// those which are not built into `Site` and `Platform` may be used in
+    /// Coords, VersionPins, and the various builders.
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - Unit
+    ///   * `Err` - `PackratDbError`
+    pub async fn load_registry(&mut self) -> Result<(), PackratDbError> {
+        let sites = self
+            .find_all_sites()
+            .query()
+            .await
+            .context(LoadSitesError)?;
+        registry::register_sites(sites.iter().map(|site| site.name.as_str()));
+        let platforms = self
+            .find_all_platforms()
+            .query()
+            .await
+            .context(LoadPlatformsError)?;
+        registry::register_platforms(platforms.iter().map(|platform| platform.name.as_str()));
+        Ok(())
+    }
+}
+
+impl<'a> PBFind for AsyncPackratDb<'a> {
+    type Client = AsyncClient;
+
+    /// Find the most appropriate versionpin for a request
+    fn find_versionpin<'b>(
+        &'b mut self,
+        package: &'b str,
+    ) -> find::versionpin::FindVersionPin<'b, Self::Client> {
+        find::versionpin::FindVersionPin::new(self.client, package)
+    }
+
+    fn find_versionpins<'b>(
+        &'b mut self,
+        package: &'b str,
+    ) -> find::versionpins::FindVersionPins<'b, Self::Client> {
+        find::versionpins::FindVersionPins::new(self.client, package)
+    }
+
+    fn find_all_versionpins<'b>(
+        &'b mut self,
+    ) -> find_all::versionpins::FindAllVersionPins<'b, Self::Client> {
+        find_all::versionpins::FindAllVersionPins::new(self.client)
+    }
+
+    fn find_all_roles<'b>(&'b mut self) -> find_all::roles::FindAllRoles<'b, Self::Client> {
+        find_all::roles::FindAllRoles::new(self.client)
+    }
+
+    fn find_all_revisions<'b>(
+        &'b mut self,
+    ) -> find_all::revisions::FindAllRevisions<'b, Self::Client> {
+        find_all::revisions::FindAllRevisions::new(self.client)
+    }
+
+    fn find_all_changes<'b>(&'b mut self) -> find_all::changes::FindAllChanges<'b, Self::Client> {
+        find_all::changes::FindAllChanges::new(self.client)
+    }
+
+    fn find_all_platforms<'b>(
+        &'b mut self,
+    ) -> find_all::platforms::FindAllPlatforms<'b, Self::Client> {
+        find_all::platforms::FindAllPlatforms::new(self.client)
+    }
+
+    fn find_all_sites<'b>(&'b mut self) -> find_all::sites::FindAllSites<'b, Self::Client> {
+        find_all::sites::FindAllSites::new(self.client)
+    }
+
+    fn find_all_levels<'b>(&'b mut self) -> find_all::levels::FindAllLevels<'b, Self::Client> {
+        find_all::levels::FindAllLevels::new(self.client)
+    }
+
+    fn find_all_packages<'b>(
+        &'b mut self,
+    ) -> find_all::packages::FindAllPackages<'b, Self::Client> {
+        find_all::packages::FindAllPackages::new(self.client)
+    }
+    /// find withs for a particular versionpin
+    fn find_all_versionpin_withs<'b>(
+        &'b mut self,
+        versionpin_id: IdType,
+    ) -> find_all::versionpin_withs::FindAllWiths<'b, Self::Client> {
+        find_all::versionpin_withs::FindAllWiths::new(self.client, versionpin_id)
+    }
+    fn find_all_distributions<'b>(
+        &'b mut self,
+    ) -> find_all::distributions::FindAllDistributions<'b, Self::Client> {
+        find_all::distributions::FindAllDistributions::new(self.client)
+    }
+    /// Find pins that meet a specific criteria
+    fn find_pins<'b>(&'b mut self) -> find::pins::FindPins<'b, Self::Client> {
+        find::pins::FindPins::new(self.client)
+    }
+    /// Find pkgcoords that meet a specific criteria
+    fn find_pkgcoords<'b>(&'b mut self) -> find_all::pkgcoords::FindAllPkgCoords<'b, Self::Client> {
+        find_all::pkgcoords::FindAllPkgCoords::with_client(Some(self.client))
+    }
+    /// find withs of a
+    fn find_withs<'b>(&'b mut self, package: &'b str) -> find::withs::FindWiths<'b, Self::Client> {
+        find::withs::FindWiths::new(self.client, package)
+    }
+}
+
+impl<'b> PBAdd<'b> for AsyncPackratDb<'b> {
+    type TransactionType = AsyncTransaction<'b>;
+
+    /// add packages
+    fn add_packages(
+        tx: Self::TransactionType,
+    ) -> add::packages::AddPackages<'b, Self::TransactionType> {
+        add::packages::AddPackages::new(tx)
+    }
+
+    /// add levels
+    fn add_levels(tx: Self::TransactionType) -> add::levels::AddLevels<'b, Self::TransactionType> {
+        add::levels::AddLevels::new(tx)
+    }
+
+    /// add roles
+    fn add_roles(tx: Self::TransactionType) -> add::roles::AddRoles<'b, Self::TransactionType> {
+        add::roles::AddRoles::new(tx)
+    }
+
+    /// add platforms
+    fn add_platforms(
+        tx: Self::TransactionType,
+    ) -> add::platforms::AddPlatforms<'b, Self::TransactionType> {
+        add::platforms::AddPlatforms::new(tx)
+    }
+
+    /// add sites
+    fn add_sites(tx: Self::TransactionType) -> add::sites::AddSites<'b, Self::TransactionType> {
+        add::sites::AddSites::new(tx)
+    }
+
+    /// add distributions. The packages must already exist.
+    fn add_distributions(
+        tx: Self::TransactionType,
+    ) -> add::distributions::AddDistributions<'b, Self::TransactionType> {
+        add::distributions::AddDistributions::new(tx)
+    }
+
+    /// Add withs to the transaction
+    fn add_withs(tx: Self::TransactionType) -> add::withs::AddWiths<'b, Self::TransactionType> {
+        add::withs::AddWiths::new(tx)
+    }
+
+    fn add_versionpins<I>(
+        tx: Self::TransactionType,
+        package: I,
+        version: I,
+        policy: ConflictPolicy,
+    ) -> add::versionpins::AddVersionPins<'b, Self::TransactionType>
+    where
+        I: Into<String>,
+    {
+        add::versionpins::AddVersionPins::new(tx, package.into(), version.into(), policy)
+    }
+}
+
+impl<'a> PBUpdate<'a> for AsyncPackratDb<'a> {
+    type TransactionType = AsyncTransaction<'a>;
+
+    /// update versionpins
+    fn update_versionpins(
+        tx: Self::TransactionType,
+    ) -> update::versionpins::UpdateVersionPins<'a, Self::TransactionType> {
+        update::versionpins::UpdateVersionPins::new(tx)
+    }
+}
+
+impl<'a> PBDelete<'a> for AsyncPackratDb<'a> {
+    type TransactionType = AsyncTransaction<'a>;
+
+    /// delete versionpins, along with their withs
+    fn delete_versionpins(
+        tx: Self::TransactionType,
+    ) -> delete::versionpins::DeleteVersionPins<'a, Self::TransactionType> {
+        delete::versionpins::DeleteVersionPins::new(tx)
+    }
+
+    /// delete withs from a versionpin
+    fn delete_withs(
+        tx: Self::TransactionType,
+    ) -> delete::withs::DeleteWiths<'a, Self::TransactionType> {
+        delete::withs::DeleteWiths::new(tx)
+    }
+
+    /// delete distributions
+    fn delete_distributions(
+        tx: Self::TransactionType,
+    ) -> delete::distributions::DeleteDistributions<'a, Self::TransactionType> {
+        delete::distributions::DeleteDistributions::new(tx)
+    }
+
+    /// delete packages
+    fn delete_packages(
+        tx: Self::TransactionType,
+    ) -> delete::packages::DeletePackages<'a, Self::TransactionType> {
+        delete::packages::DeletePackages::new(tx)
+    }
+
+    /// delete levels
+    fn delete_levels(
+        tx: Self::TransactionType,
+    ) -> delete::levels::DeleteLevels<'a, Self::TransactionType> {
+        delete::levels::DeleteLevels::new(tx)
+    }
+
+    /// delete roles
+    fn delete_roles(
+        tx: Self::TransactionType,
+    ) -> delete::roles::DeleteRoles<'a, Self::TransactionType> {
+        delete::roles::DeleteRoles::new(tx)
+    }
+}
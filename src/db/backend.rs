@@ -0,0 +1,190 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! The storage backend queried by the find builders.
+//!
+//! A `PackratDb` is generic over its backend, which defaults to a postgres
+//! `Client`. The `MemoryStore` is an alternative, in-memory, backend which
+//! implements the same semantics, so that code built on packybara may be
+//! tested without a database. Calling `query` on a find builder dispatches
+//! to the backend's implementation of that find.
+use crate::db::find::pins::{FindPins, FindPinsError, FindPinsRow};
+use crate::db::find::versionpin::{FindVersionPin, FindVersionPinError};
+use crate::db::find::versionpins::{FindVersionPins, FindVersionPinsRow};
+use crate::db::find::withs::{FindWiths, FindWithsError, FindWithsRow};
+use crate::db::find_all::changes::{FindAllChanges, FindAllChangesError, FindAllChangesRow};
+use crate::db::find_all::distributions::{
+    FindAllDistributions, FindAllDistributionsError, FindAllDistributionsRow,
+};
+use crate::db::find_all::levels::{FindAllLevels, FindAllLevelsError, FindAllLevelsRow};
+use crate::db::find_all::packages::{FindAllPackages, FindAllPackagesError, FindAllPackagesRow};
+use crate::db::find_all::pkgcoords::{
+    FindAllPkgCoords, FindAllPkgCoordsError, FindAllPkgCoordsRow,
+};
+use crate::db::find_all::platforms::{
+    FindAllPlatforms, FindAllPlatformsError, FindAllPlatformsRow,
+};
+use crate::db::find_all::revisions::{
+    FindAllRevisions, FindAllRevisionsError, FindAllRevisionsRow,
+};
+use crate::db::find_all::roles::{FindAllRoles, FindAllRolesError, FindAllRolesRow};
+use crate::db::find_all::sites::{FindAllSites, FindAllSitesError, FindAllSitesRow};
+use crate::db::find_all::versionpin_withs::{FindAllWiths, FindAllWithsError, FindAllWithsRow};
+use crate::db::find_all::versionpins::{
+    FindAllVersionPins, FindAllVersionPinsError, FindAllVersionPinsRow,
+};
+use postgres::Client;
+
+/// A store which the find builders may query. There is one method per
+/// `PBFind` method, each of which executes the supplied find builder
+/// against the store.
+pub trait Backend: Sized {
+    fn find_versionpin(
+        find: &mut FindVersionPin<'_, Self>,
+    ) -> Result<FindVersionPinsRow, FindVersionPinError>;
+
+    fn find_versionpins(
+        find: &mut FindVersionPins<'_, Self>,
+    ) -> Result<Vec<FindVersionPinsRow>, Box<dyn std::error::Error>>;
+
+    fn find_all_versionpins(
+        find: &mut FindAllVersionPins<'_, Self>,
+    ) -> Result<Vec<FindAllVersionPinsRow>, FindAllVersionPinsError>;
+
+    fn find_all_roles(
+        find: &mut FindAllRoles<'_, Self>,
+    ) -> Result<Vec<FindAllRolesRow>, FindAllRolesError>;
+
+    fn find_all_revisions(
+        find: &mut FindAllRevisions<'_, Self>,
+    ) -> Result<Vec<FindAllRevisionsRow>, FindAllRevisionsError>;
+
+    fn find_all_changes(
+        find: &mut FindAllChanges<'_, Self>,
+    ) -> Result<Vec<FindAllChangesRow>, FindAllChangesError>;
+
+    fn find_all_platforms(
+        find: &mut FindAllPlatforms<'_, Self>,
+    ) -> Result<Vec<FindAllPlatformsRow>, FindAllPlatformsError>;
+
+    fn find_all_sites(
+        find: &mut FindAllSites<'_, Self>,
+    ) -> Result<Vec<FindAllSitesRow>, FindAllSitesError>;
+
+    fn find_all_levels(
+        find: &mut FindAllLevels<'_, Self>,
+    ) -> Result<Vec<FindAllLevelsRow>, FindAllLevelsError>;
+
+    fn find_all_packages(
+        find: &mut FindAllPackages<'_, Self>,
+    ) -> Result<Vec<FindAllPackagesRow>, FindAllPackagesError>;
+
+    fn find_all_versionpin_withs(
+        find: &mut FindAllWiths<'_, Self>,
+    ) -> Result<Vec<FindAllWithsRow>, FindAllWithsError>;
+
+    fn find_all_distributions(
+        find: &mut FindAllDistributions<'_, Self>,
+    ) -> Result<Vec<FindAllDistributionsRow>, FindAllDistributionsError>;
+
+    fn find_pins(find: &mut FindPins<'_, Self>) -> Result<Vec<FindPinsRow>, FindPinsError>;
+
+    fn find_pkgcoords(
+        find: &mut FindAllPkgCoords<'_, Self>,
+    ) -> Result<Vec<FindAllPkgCoordsRow>, FindAllPkgCoordsError>;
+
+    fn find_withs(find: &mut FindWiths<'_, Self>) -> Result<Vec<FindWithsRow>, FindWithsError>;
+}
+
+impl Backend for Client {
+    fn find_versionpin(
+        find: &mut FindVersionPin<'_, Self>,
+    ) -> Result<FindVersionPinsRow, FindVersionPinError> {
+        find.query_postgres()
+    }
+
+    fn find_versionpins(
+        find: &mut FindVersionPins<'_, Self>,
+    ) -> Result<Vec<FindVersionPinsRow>, Box<dyn std::error::Error>> {
+        find.query_postgres()
+    }
+
+    fn find_all_versionpins(
+        find: &mut FindAllVersionPins<'_, Self>,
+    ) -> Result<Vec<FindAllVersionPinsRow>, FindAllVersionPinsError> {
+        find.query_postgres()
+    }
+
+    fn find_all_roles(
+        find: &mut FindAllRoles<'_, Self>,
+    ) -> Result<Vec<FindAllRolesRow>, FindAllRolesError> {
+        find.query_postgres()
+    }
+
+    fn find_all_revisions(
+        find: &mut FindAllRevisions<'_, Self>,
+    ) -> Result<Vec<FindAllRevisionsRow>, FindAllRevisionsError> {
+        find.query_postgres()
+    }
+
+    fn find_all_changes(
+        find: &mut FindAllChanges<'_, Self>,
+    ) -> Result<Vec<FindAllChangesRow>, FindAllChangesError> {
+        find.query_postgres()
+    }
+
+    fn find_all_platforms(
+        find: &mut FindAllPlatforms<'_, Self>,
+    ) -> Result<Vec<FindAllPlatformsRow>, FindAllPlatformsError> {
+        find.query_postgres()
+    }
+
+    fn find_all_sites(
+        find: &mut FindAllSites<'_, Self>,
+    ) -> Result<Vec<FindAllSitesRow>, FindAllSitesError> {
+        find.query_postgres()
+    }
+
+    fn find_all_levels(
+        find: &mut FindAllLevels<'_, Self>,
+    ) -> Result<Vec<FindAllLevelsRow>, FindAllLevelsError> {
+        find.query_postgres()
+    }
+
+    fn find_all_packages(
+        find: &mut FindAllPackages<'_, Self>,
+    ) -> Result<Vec<FindAllPackagesRow>, FindAllPackagesError> {
+        find.query_postgres()
+    }
+
+    fn find_all_versionpin_withs(
+        find: &mut FindAllWiths<'_, Self>,
+    ) -> Result<Vec<FindAllWithsRow>, FindAllWithsError> {
+        find.query_postgres()
+    }
+
+    fn find_all_distributions(
+        find: &mut FindAllDistributions<'_, Self>,
+    ) -> Result<Vec<FindAllDistributionsRow>, FindAllDistributionsError> {
+        find.query_postgres()
+    }
+
+    fn find_pins(find: &mut FindPins<'_, Self>) -> Result<Vec<FindPinsRow>, FindPinsError> {
+        find.query_postgres()
+    }
+
+    fn find_pkgcoords(
+        find: &mut FindAllPkgCoords<'_, Self>,
+    ) -> Result<Vec<FindAllPkgCoordsRow>, FindAllPkgCoordsError> {
+        find.query_postgres()
+    }
+
+    fn find_withs(find: &mut FindWiths<'_, Self>) -> Result<Vec<FindWithsRow>, FindWithsError> {
+        find.query_postgres()
+    }
+}
@@ -0,0 +1,452 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! Connection configuration for the packrat database.
+//!
+//! `PackratConfig::load` starts from the defaults, overlays the toml config
+//! file named by `PACKRAT_CONFIG` (or `~/.packrat.toml`, if it exists), then
+//! the `PACKRAT_*` environment variables. If no password has been supplied by
+//! then, it is looked up in the `.pgpass` file (`PGPASSFILE` or `~/.pgpass`).
+//!
+//! The config file takes the form
+//!
+//! ```toml
+//! host = "packrat.example.com"
+//! port = 5432
+//! user = "packrat"
+//! dbname = "packrat"
+//! pool_size = 8
+//! check_schema = true
+//! ```
+//!
+//! and the environment variables are `PACKRAT_HOST`, `PACKRAT_PORT`,
+//! `PACKRAT_USER`, `PACKRAT_PASSWORD`, `PACKRAT_DBNAME`, `PACKRAT_POOL_SIZE`
+//! and `PACKRAT_CHECK_SCHEMA`.
+//!
+//! `PackratConfig::connect` compares the database schema version with the one
+//! expected by this version of packybara (see `db::migrate`). A mismatch is
+//! logged as a warning, unless `check_schema` is set, in which case it is an error.
+use crate::db::migrate::{self, MigrateError};
+use postgres::{Client, NoTls};
+use serde::Deserialize;
+use snafu::{ResultExt, Snafu};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Error type returned when loading a PackratConfig
+#[derive(Debug, Snafu)]
+pub enum PackratConfigError {
+    /// Unable to read the config file
+    #[snafu(display("Unable to read packrat config {}: {}", path, source))]
+    ReadConfigError {
+        path: String,
+        source: std::io::Error,
+    },
+    /// Unable to parse the config file
+    #[snafu(display("Unable to parse packrat config {}: {}", path, source))]
+    ParseConfigError {
+        path: String,
+        source: toml::de::Error,
+    },
+    /// An environment variable holds a value which may not be parsed
+    #[snafu(display("Invalid value for {}: '{}'", name, value))]
+    InvalidEnvVarError { name: &'static str, value: String },
+    /// Unable to connect to the database
+    #[snafu(display("Unable to connect to {}: {}", config, source))]
+    ConnectError {
+        config: String,
+        source: tokio_postgres::error::Error,
+    },
+    /// The database schema is not the one expected by this version of packybara
+    #[snafu(display("Unable to use {}: {}", config, source))]
+    SchemaVersionError {
+        config: String,
+        source: MigrateError,
+    },
+}
+
+pub type PackratConfigResult<T, E = PackratConfigError> = std::result::Result<T, E>;
+
+/// The parameters used to connect to the packrat database
+#[derive(PartialEq, Eq, Clone, Deserialize)]
+#[serde(default)]
+pub struct PackratConfig {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub password: Option<String>,
+    pub dbname: String,
+    /// The maximum number of connections held by a PackratPool
+    pub pool_size: u32,
+    /// Refuse to connect to a database whose schema version is not the
+    /// one expected, rather than warning
+    pub check_schema: bool,
+}
+
+impl Default for PackratConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 5432,
+            user: "postgres".to_string(),
+            password: None,
+            dbname: "packrat".to_string(),
+            pool_size: 4,
+            check_schema: false,
+        }
+    }
+}
+
+// the password is deliberately left out
+impl fmt::Debug for PackratConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "PackratConfig(host:{} port:{} user:{} dbname:{} pool_size:{} check_schema:{})",
+            self.host, self.port, self.user, self.dbname, self.pool_size, self.check_schema
+        )
+    }
+}
+
+impl fmt::Display for PackratConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}@{}:{}/{}",
+            self.user, self.host, self.port, self.dbname
+        )
+    }
+}
+
+impl PackratConfig {
+    /// Load the config, starting from the defaults, overlaid by the config
+    /// file, which is in turn overridden by the `PACKRAT_*` environment
+    /// variables. The password is only looked up in `.pgpass` if neither the
+    /// file nor the environment supplies one.
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - PackratConfig
+    ///   * `Err` - PackratConfigError
+    pub fn load() -> PackratConfigResult<Self> {
+        let config = match std::env::var_os("PACKRAT_CONFIG") {
+            Some(path) => Self::from_file(path)?,
+            None => match home_file(".packrat.toml") {
+                Some(path) if path.is_file() => Self::from_file(path)?,
+                _ => Self::default(),
+            },
+        };
+        let mut config = config.with_env()?;
+        if config.password.is_none() {
+            config.password = pgpass_file()
+                .and_then(|path| std::fs::read_to_string(path).ok())
+                .and_then(|contents| {
+                    pgpass_password(
+                        &contents,
+                        &config.host,
+                        config.port,
+                        &config.dbname,
+                        &config.user,
+                    )
+                });
+        }
+        Ok(config)
+    }
+
+    /// Load the config from a toml file. Values missing from the file
+    /// take their defaults.
+    ///
+    /// # Arguments
+    /// * `path` - The path to the config file
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - PackratConfig
+    ///   * `Err` - PackratConfigError
+    pub fn from_file<P: AsRef<Path>>(path: P) -> PackratConfigResult<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).context(ReadConfigError {
+            path: path.display().to_string(),
+        })?;
+        toml::from_str(&contents).context(ParseConfigError {
+            path: path.display().to_string(),
+        })
+    }
+
+    /// Overlay the `PACKRAT_*` environment variables on the config
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - PackratConfig
+    ///   * `Err` - PackratConfigError
+    pub fn with_env(self) -> PackratConfigResult<Self> {
+        self.with_vars(|name| std::env::var(name).ok())
+    }
+
+    // overlay the variables retrieved by `var` on the config
+    fn with_vars<F>(mut self, var: F) -> PackratConfigResult<Self>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        if let Some(host) = var("PACKRAT_HOST") {
+            self.host = host;
+        }
+        if let Some(port) = var("PACKRAT_PORT") {
+            self.port = match port.parse() {
+                Ok(port) => port,
+                Err(_) => {
+                    return InvalidEnvVarError {
+                        name: "PACKRAT_PORT",
+                        value: port,
+                    }
+                    .fail()
+                }
+            };
+        }
+        if let Some(user) = var("PACKRAT_USER") {
+            self.user = user;
+        }
+        if let Some(password) = var("PACKRAT_PASSWORD") {
+            self.password = Some(password);
+        }
+        if let Some(dbname) = var("PACKRAT_DBNAME") {
+            self.dbname = dbname;
+        }
+        if let Some(pool_size) = var("PACKRAT_POOL_SIZE") {
+            self.pool_size = match pool_size.parse() {
+                Ok(pool_size) => pool_size,
+                Err(_) => {
+                    return InvalidEnvVarError {
+                        name: "PACKRAT_POOL_SIZE",
+                        value: pool_size,
+                    }
+                    .fail()
+                }
+            };
+        }
+        if let Some(check_schema) = var("PACKRAT_CHECK_SCHEMA") {
+            self.check_schema = match check_schema.parse() {
+                Ok(check_schema) => check_schema,
+                Err(_) => {
+                    return InvalidEnvVarError {
+                        name: "PACKRAT_CHECK_SCHEMA",
+                        value: check_schema,
+                    }
+                    .fail()
+                }
+            };
+        }
+        Ok(self)
+    }
+
+    /// Retrieve the postgres::Config described by self
+    pub fn postgres_config(&self) -> postgres::Config {
+        let mut config = postgres::Config::new();
+        config
+            .host(&self.host)
+            .port(self.port)
+            .user(&self.user)
+            .dbname(&self.dbname);
+        if let Some(ref password) = self.password {
+            config.password(password);
+        }
+        config
+    }
+
+    /// Retrieve the tokio_postgres::Config described by self, for use
+    /// with the AsyncPackratDb
+    pub fn tokio_postgres_config(&self) -> tokio_postgres::Config {
+        let mut config = tokio_postgres::Config::new();
+        config
+            .host(&self.host)
+            .port(self.port)
+            .user(&self.user)
+            .dbname(&self.dbname);
+        if let Some(ref password) = self.password {
+            config.password(password);
+        }
+        config
+    }
+
+    /// Connect to the database, comparing its schema version with the one
+    /// expected by this version of packybara. A mismatch is an error if
+    /// `check_schema` is set, and is logged as a warning otherwise.
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - postgres::Client
+    ///   * `Err` - PackratConfigError
+    pub fn connect(&self) -> PackratConfigResult<Client> {
+        let mut client = self.connect_unchecked()?;
+        self.check_schema_version(&mut client)
+            .context(SchemaVersionError {
+                config: self.to_string(),
+            })?;
+        Ok(client)
+    }
+
+    /// Compare the schema version of the database with the one expected by
+    /// this version of packybara, failing on a mismatch if `check_schema` is
+    /// set, and logging it as a warning otherwise.
+    ///
+    /// # Arguments
+    /// * `client` - The client connected to the database
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - The schema version, or None if the database has not been migrated
+    ///   * `Err` - MigrateError
+    pub fn check_schema_version(&self, client: &mut Client) -> Result<Option<i32>, MigrateError> {
+        if self.check_schema {
+            migrate::check_schema_version(client).map(Some)
+        } else {
+            migrate::warn_schema_version(client)
+        }
+    }
+
+    /// Connect to the database without checking the version of its schema.
+    /// Used to migrate the database.
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - postgres::Client
+    ///   * `Err` - PackratConfigError
+    pub fn connect_unchecked(&self) -> PackratConfigResult<Client> {
+        self.postgres_config().connect(NoTls).context(ConnectError {
+            config: self.to_string(),
+        })
+    }
+}
+
+// the path of a file in the home directory
+fn home_file(name: &str) -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(name))
+}
+
+// the path of the pgpass file, honoring PGPASSFILE
+fn pgpass_file() -> Option<PathBuf> {
+    match std::env::var_os("PGPASSFILE") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => home_file(".pgpass"),
+    }
+}
+
+// split a line of a pgpass file into its fields, honoring `\:` and `\\`
+fn pgpass_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    fields.last_mut().unwrap().push(escaped);
+                }
+            }
+            ':' => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// Look up a password in the contents of a pgpass file. Each line takes the
+/// form `hostname:port:database:username:password`, where any of the first four
+/// fields may be `*`. The first matching line wins.
+///
+/// # Arguments
+/// * `contents` - The contents of the pgpass file
+/// * `host` - The host to match
+/// * `port` - The port to match
+/// * `dbname` - The database name to match
+/// * `user` - The user name to match
+///
+/// # Returns
+/// * The password, if a line matches
+pub fn pgpass_password(
+    contents: &str,
+    host: &str,
+    port: u16,
+    dbname: &str,
+    user: &str,
+) -> Option<String> {
+    let port = port.to_string();
+    let wanted = [host, port.as_str(), dbname, user];
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(pgpass_fields)
+        .filter(|fields| fields.len() == 5)
+        .find(|fields| {
+            fields
+                .iter()
+                .zip(wanted.iter())
+                .all(|(field, wanted)| field == "*" || field == wanted)
+        })
+        .map(|mut fields| fields.remove(4))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_config_with_defaults() {
+        let config: PackratConfig =
+            toml::from_str("host = \"packrat.example.com\"\npool_size = 8\n").unwrap();
+        assert_eq!(
+            config,
+            PackratConfig {
+                host: "packrat.example.com".to_string(),
+                pool_size: 8,
+                ..PackratConfig::default()
+            }
+        );
+    }
+
+    #[test]
+    fn vars_override_config() {
+        let config = PackratConfig::default()
+            .with_vars(|name| match name {
+                "PACKRAT_PORT" => Some("5433".to_string()),
+                "PACKRAT_PASSWORD" => Some("secret".to_string()),
+                "PACKRAT_CHECK_SCHEMA" => Some("true".to_string()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(config.port, 5433);
+        assert_eq!(config.password, Some("secret".to_string()));
+        assert_eq!(config.user, "postgres");
+        assert!(config.check_schema);
+
+        let result = PackratConfig::default().with_vars(|name| match name {
+            "PACKRAT_PORT" => Some("nope".to_string()),
+            _ => None,
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn can_lookup_pgpass_password() {
+        let contents = "# comment\n\
+                        otherhost:5432:packrat:postgres:wrong\n\
+                        127.0.0.1:*:packrat:postgres:pa\\:ss\n\
+                        *:*:*:*:fallback\n";
+        assert_eq!(
+            pgpass_password(contents, "127.0.0.1", 5432, "packrat", "postgres"),
+            Some("pa:ss".to_string())
+        );
+        assert_eq!(
+            pgpass_password(contents, "elsewhere", 5432, "packrat", "postgres"),
+            Some("fallback".to_string())
+        );
+        assert_eq!(
+            pgpass_password("", "127.0.0.1", 5432, "packrat", "postgres"),
+            None
+        );
+    }
+}
@@ -0,0 +1,370 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+use super::{
+    prepare_count_versionpins, remove_versionpins, remove_versionpins_async, statement, Cascaded,
+};
+use crate::coords_error::CoordsError;
+use crate::db::async_packrat::AsyncTransaction;
+use crate::db::memory::MemoryTransaction;
+use crate::db::prepared::Prepared;
+use crate::traits::{AsyncTransactionHandler, MemoryTransactionHandler, TransactionHandler};
+use crate::types::IdType;
+use crate::Distribution;
+use itertools::Itertools;
+use postgres::Transaction;
+use snafu::{ResultExt, Snafu};
+use std::marker::PhantomData;
+
+/// Error type returned from DeleteDistributions
+#[derive(Debug, Snafu)]
+pub enum DeleteDistributionsError {
+    /// When constructing a query, postgres has thrown an error
+    #[snafu(display("Postgres Error: {} {:#?}", msg, source))]
+    TokioPostgresError {
+        msg: &'static str,
+        source: tokio_postgres::error::Error,
+    },
+    /// No distributions were registered for deletion
+    #[snafu(display("No distribution names supplied"))]
+    NoDistributionNamesError,
+    /// The distribution name is malformed
+    #[snafu(display("Invalid distribution {}: {}", distribution, source))]
+    InvalidDistributionError {
+        distribution: String,
+        source: CoordsError,
+    },
+    /// The distribution does not exist in the database
+    #[snafu(display("Distribution {} does not exist", distribution))]
+    DistributionNotFoundError { distribution: String },
+    /// The distribution is pinned, and cascade has not been requested
+    #[snafu(display(
+        "Unable to delete {}. It is referenced by {} versionpin(s). Set cascade to remove them as well",
+        distribution,
+        versionpins
+    ))]
+    DistributionHasDependentsError {
+        distribution: String,
+        versionpins: i64,
+    },
+}
+
+/// Responsible for deleting distributions. Distributions which are referenced
+/// by versionpins are only deleted, along with those versionpins and their
+/// withs, when `cascade` is set.
+pub struct DeleteDistributions<'a, T = Transaction<'a>> {
+    tx: Option<T>,
+    names: Vec<String>,
+    cascade: bool,
+    cascaded: Cascaded,
+    result_cnt: u64,
+    phantom: PhantomData<&'a ()>,
+}
+
+impl<'a> TransactionHandler<'a> for DeleteDistributions<'a> {
+    type Error = tokio_postgres::error::Error;
+    /// retrieve an Option wrapped mutable reference to the
+    /// transaction
+    fn tx(&mut self) -> Option<&mut Transaction<'a>> {
+        self.tx.as_mut()
+    }
+    /// Extract the transaction from Self.
+    fn take_tx(&mut self) -> Transaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    /// Return the result count to 0
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+    /// Retrieve th result count
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> AsyncTransactionHandler<'a> for DeleteDistributions<'a, AsyncTransaction<'a>> {
+    type Error = tokio_postgres::error::Error;
+    fn tx(&mut self) -> Option<&mut AsyncTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> AsyncTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> MemoryTransactionHandler<'a> for DeleteDistributions<'a, MemoryTransaction<'a>> {
+    fn tx(&mut self) -> Option<&mut MemoryTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> MemoryTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a, T> DeleteDistributions<'a, T> {
+    /// new up a DeleteDistributions instance
+    ///
+    /// # Arguments
+    ///
+    /// * `tx` - A Transaction instance
+    pub fn new(tx: T) -> Self {
+        Self {
+            tx: Some(tx),
+            names: Vec::new(),
+            cascade: false,
+            cascaded: Cascaded::default(),
+            result_cnt: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Register a distribution (eg `maya-2018.sp3`) for deletion
+    ///
+    /// # Arguments
+    /// * `name` - The name of the distribution
+    ///
+    /// # Returns
+    /// * Self
+    pub fn distribution<I: Into<String>>(mut self, name: I) -> Self {
+        self.names.push(name.into());
+        self
+    }
+
+    /// Register a vector of distributions for deletion
+    ///
+    /// # Arguments
+    /// * `names` - The names of the distributions
+    ///
+    /// # Returns
+    /// * Self
+    pub fn distributions(mut self, names: &mut Vec<String>) -> Self {
+        self.names.append(names);
+        self
+    }
+
+    /// Determine whether versionpins referencing the distributions are
+    /// deleted as well, along with their withs. Defaults to false. The
+    /// versionpins and withs deleted are reported by `cascaded`.
+    ///
+    /// # Arguments
+    /// * `cascade` - Whether to delete dependent versionpins
+    ///
+    /// # Returns
+    /// * Self
+    pub fn cascade(mut self, cascade: bool) -> Self {
+        self.cascade = cascade;
+        self
+    }
+
+    /// Retrieve the versionpins and withs deleted along with the
+    /// distributions by `delete`
+    pub fn cascaded(&self) -> Cascaded {
+        self.cascaded
+    }
+
+    // validate the registered distributions
+    fn validate(&self) -> Result<Vec<Distribution>, DeleteDistributionsError> {
+        let names = self.names.iter().unique().cloned().collect::<Vec<String>>();
+        if names.is_empty() {
+            return Err(DeleteDistributionsError::NoDistributionNamesError);
+        }
+        names
+            .iter()
+            .map(|name| {
+                Distribution::new(name.as_str()).context(InvalidDistributionError {
+                    distribution: name.as_str(),
+                })
+            })
+            .collect()
+    }
+
+    // build the query looking up the id of the distribution
+    fn prepare_lookup(distribution: &Distribution) -> Prepared {
+        let mut prepared = Prepared::new(
+            "SELECT distribution_id FROM distribution_view WHERE package = $1 AND version_name = $2",
+        );
+        prepared.push(distribution.package().to_string());
+        prepared.push(distribution.version().to_string());
+        prepared
+    }
+
+    // the id of the distribution, if found, or the error reporting its absence
+    fn found(
+        id: Option<IdType>,
+        distribution: &Distribution,
+    ) -> Result<IdType, DeleteDistributionsError> {
+        match id {
+            Some(id) => Ok(id),
+            None => DistributionNotFoundError {
+                distribution: distribution.distribution(),
+            }
+            .fail(),
+        }
+    }
+
+    // refuse to delete a distribution which is pinned, unless cascading
+    fn check(
+        &self,
+        distribution: &Distribution,
+        versionpins: i64,
+    ) -> Result<bool, DeleteDistributionsError> {
+        if versionpins == 0 {
+            return Ok(false);
+        }
+        if !self.cascade {
+            return DistributionHasDependentsError {
+                distribution: distribution.distribution(),
+                versionpins,
+            }
+            .fail();
+        }
+        Ok(true)
+    }
+
+    // accumulate the versionpins and withs deleted along with a distribution
+    fn cascade_deleted(&mut self, cascaded: Cascaded) {
+        self.cascaded.versionpins += cascaded.versionpins;
+        self.cascaded.withs += cascaded.withs;
+    }
+}
+
+impl<'a> DeleteDistributions<'a> {
+    /// Delete the previously registered distributions within the transaction.
+    /// The database is not updated until one calls `commit(...)`.
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeleteDistributionsError)
+    pub fn delete(mut self) -> Result<Self, DeleteDistributionsError> {
+        let mut cnt = 0;
+        for distribution in self.validate()? {
+            let rows = Self::prepare_lookup(&distribution)
+                .query_tx(self.tx().unwrap())
+                .context(TokioPostgresError {
+                    msg: "failed to look up distribution",
+                })?;
+            let id = Self::found(rows.first().map(|row| row.get(0)), &distribution)?;
+            let versionpins = prepare_count_versionpins("distribution = $1", id)
+                .query_one_tx(self.tx().unwrap())
+                .context(TokioPostgresError {
+                    msg: "failed to count versionpins",
+                })?
+                .get(0);
+            if self.check(&distribution, versionpins)? {
+                let cascaded = remove_versionpins(self.tx().unwrap(), "distribution = $1", id)
+                    .context(TokioPostgresError {
+                        msg: "failed to delete versionpins",
+                    })?;
+                self.cascade_deleted(cascaded);
+            }
+            cnt += statement("DELETE FROM distribution WHERE id = $1", id)
+                .execute(self.tx().unwrap())
+                .context(TokioPostgresError {
+                    msg: "failed to delete distribution",
+                })?;
+        }
+        self.result_cnt = cnt;
+        Ok(self)
+    }
+}
+
+impl<'a> DeleteDistributions<'a, AsyncTransaction<'a>> {
+    /// Delete the previously registered distributions within the transaction.
+    /// The database is not updated until one calls `commit(...)`.
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeleteDistributionsError)
+    pub async fn delete(mut self) -> Result<Self, DeleteDistributionsError> {
+        let mut cnt = 0;
+        for distribution in self.validate()? {
+            let rows = Self::prepare_lookup(&distribution)
+                .query_tx_async(self.tx().unwrap())
+                .await
+                .context(TokioPostgresError {
+                    msg: "failed to look up distribution",
+                })?;
+            let id = Self::found(rows.first().map(|row| row.get(0)), &distribution)?;
+            let versionpins = prepare_count_versionpins("distribution = $1", id)
+                .query_one_tx_async(self.tx().unwrap())
+                .await
+                .context(TokioPostgresError {
+                    msg: "failed to count versionpins",
+                })?
+                .get(0);
+            if self.check(&distribution, versionpins)? {
+                let cascaded =
+                    remove_versionpins_async(self.tx().unwrap(), "distribution = $1", id)
+                        .await
+                        .context(TokioPostgresError {
+                            msg: "failed to delete versionpins",
+                        })?;
+                self.cascade_deleted(cascaded);
+            }
+            cnt += statement("DELETE FROM distribution WHERE id = $1", id)
+                .execute_async(self.tx().unwrap())
+                .await
+                .context(TokioPostgresError {
+                    msg: "failed to delete distribution",
+                })?;
+        }
+        self.result_cnt = cnt;
+        Ok(self)
+    }
+}
+
+impl<'a> DeleteDistributions<'a, MemoryTransaction<'a>> {
+    /// Delete the previously registered distributions within the transaction.
+    /// The store is not updated until one calls `commit(...)`.
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeleteDistributionsError)
+    pub fn delete(mut self) -> Result<Self, DeleteDistributionsError> {
+        let mut cnt = 0;
+        for distribution in self.validate()? {
+            let tables = self.tx().unwrap().tables();
+            let id = tables
+                .distribution(distribution.package(), distribution.version())
+                .map(|dist| dist.id);
+            let id = Self::found(id, &distribution)?;
+            let pins = tables
+                .versionpins
+                .iter()
+                .filter(|pin| pin.distribution == id)
+                .map(|pin| pin.id)
+                .collect::<Vec<_>>();
+            if self.check(&distribution, pins.len() as i64)? {
+                let cascaded = self.tx().unwrap().remove_versionpins(&pins);
+                self.cascade_deleted(cascaded);
+            }
+            cnt += self
+                .tx()
+                .unwrap()
+                .remove_distributions(|dist| dist.id == id);
+        }
+        self.result_cnt = cnt;
+        Ok(self)
+    }
+}
@@ -0,0 +1,386 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+use super::{
+    prepare_count_versionpins, remove_versionpins, remove_versionpins_async, statement, Cascaded,
+};
+use crate::db::async_packrat::AsyncTransaction;
+use crate::db::memory::functions::{is_ancestor, level_name_to_path};
+use crate::db::memory::{MemoryTransaction, PkgCoordRecord};
+use crate::db::prepared::Prepared;
+use crate::db::utils::prep_query_str;
+use crate::traits::{AsyncTransactionHandler, MemoryTransactionHandler, TransactionHandler};
+use itertools::Itertools;
+use postgres::Transaction;
+use snafu::{ResultExt, Snafu};
+use std::marker::PhantomData;
+
+/// Error type returned from DeleteLevels
+#[derive(Debug, Snafu)]
+pub enum DeleteLevelsError {
+    /// When constructing a query, postgres has thrown an error
+    #[snafu(display("Postgres Error: {} {:#?}", msg, source))]
+    TokioPostgresError {
+        msg: &'static str,
+        source: tokio_postgres::error::Error,
+    },
+    /// No levels were registered for deletion
+    #[snafu(display("No level names supplied"))]
+    NoLevelNamesError,
+    /// The facility is the root of the level hierarchy, and may not be deleted
+    #[snafu(display("The facility level may not be deleted"))]
+    FacilityLevelError,
+    /// The level does not exist in the database
+    #[snafu(display("Level {} does not exist", level))]
+    LevelNotFoundError { level: String },
+    /// The level has dependents, and cascade has not been requested
+    #[snafu(display(
+        "Unable to delete {}. It has {} child level(s) and {} versionpin(s). Set cascade to remove them as well",
+        level,
+        children,
+        versionpins
+    ))]
+    LevelHasDependentsError {
+        level: String,
+        children: i64,
+        versionpins: i64,
+    },
+}
+
+// versionpins at, or below, the level
+const LEVEL_VERSIONPINS: &str = "coord IN (SELECT id FROM pkgcoord WHERE level <@ text2ltree($1))";
+
+// the statements taking the level path as their sole parameter
+const COUNT_LEVEL: &str = "SELECT count(*) FROM level WHERE path = text2ltree($1)";
+const COUNT_CHILDREN: &str =
+    "SELECT count(*) FROM level WHERE path <@ text2ltree($1) AND path <> text2ltree($1)";
+const DELETE_PKGCOORDS: &str = "DELETE FROM pkgcoord WHERE level <@ text2ltree($1)";
+const DELETE_LEVEL: &str = "DELETE FROM level WHERE path <@ text2ltree($1)";
+
+/// Responsible for deleting levels. Levels which have child levels or
+/// versionpins are only deleted, along with their dependents, when
+/// `cascade` is set.
+pub struct DeleteLevels<'a, T = Transaction<'a>> {
+    tx: Option<T>,
+    names: Vec<String>,
+    cascade: bool,
+    cascaded: Cascaded,
+    result_cnt: u64,
+    phantom: PhantomData<&'a ()>,
+}
+
+impl<'a> TransactionHandler<'a> for DeleteLevels<'a> {
+    type Error = tokio_postgres::error::Error;
+    /// retrieve an Option wrapped mutable reference to the
+    /// transaction
+    fn tx(&mut self) -> Option<&mut Transaction<'a>> {
+        self.tx.as_mut()
+    }
+    /// Extract the transaction from Self.
+    fn take_tx(&mut self) -> Transaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    /// Return the result count to 0
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+    /// Retrieve th result count
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> AsyncTransactionHandler<'a> for DeleteLevels<'a, AsyncTransaction<'a>> {
+    type Error = tokio_postgres::error::Error;
+    fn tx(&mut self) -> Option<&mut AsyncTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> AsyncTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> MemoryTransactionHandler<'a> for DeleteLevels<'a, MemoryTransaction<'a>> {
+    fn tx(&mut self) -> Option<&mut MemoryTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> MemoryTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a, T> DeleteLevels<'a, T> {
+    /// new up a DeleteLevels instance
+    ///
+    /// # Arguments
+    ///
+    /// * `tx` - A Transaction instance
+    pub fn new(tx: T) -> Self {
+        Self {
+            tx: Some(tx),
+            names: Vec::new(),
+            cascade: false,
+            cascaded: Cascaded::default(),
+            result_cnt: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Register a level (eg `dev01.rd`) for deletion
+    ///
+    /// # Arguments
+    /// * `name` - The name of the level
+    ///
+    /// # Returns
+    /// * Self
+    pub fn level<I: Into<String>>(mut self, name: I) -> Self {
+        self.names.push(name.into());
+        self
+    }
+
+    /// Register a vector of levels for deletion
+    ///
+    /// # Arguments
+    /// * `names` - The names of the levels
+    ///
+    /// # Returns
+    /// * Self
+    pub fn levels(mut self, names: &mut Vec<String>) -> Self {
+        self.names.append(names);
+        self
+    }
+
+    /// Determine whether the child levels and versionpins of the
+    /// levels are deleted as well, along with the withs of those
+    /// versionpins. Defaults to false. The versionpins and withs deleted
+    /// are reported by `cascaded`.
+    ///
+    /// # Arguments
+    /// * `cascade` - Whether to delete dependents
+    ///
+    /// # Returns
+    /// * Self
+    pub fn cascade(mut self, cascade: bool) -> Self {
+        self.cascade = cascade;
+        self
+    }
+
+    /// Retrieve the versionpins and withs deleted along with the levels
+    /// by `delete`
+    pub fn cascaded(&self) -> Cascaded {
+        self.cascaded
+    }
+
+    // the unique, lowercased, levels to delete
+    fn validate(&self) -> Result<Vec<String>, DeleteLevelsError> {
+        let levels = self
+            .names
+            .iter()
+            .unique()
+            .map(|x| x.to_lowercase())
+            .collect::<Vec<_>>();
+        if levels.is_empty() {
+            return Err(DeleteLevelsError::NoLevelNamesError);
+        }
+        if levels.iter().any(|level| level == "facility") {
+            return Err(DeleteLevelsError::FacilityLevelError);
+        }
+        Ok(levels)
+    }
+
+    // refuse to delete a level which has dependents, unless cascading. Returns
+    // whether there are dependents to delete.
+    fn check(
+        &self,
+        level: &str,
+        exists: bool,
+        children: i64,
+        versionpins: i64,
+    ) -> Result<bool, DeleteLevelsError> {
+        if !exists {
+            return LevelNotFoundError { level }.fail();
+        }
+        if children + versionpins == 0 {
+            return Ok(false);
+        }
+        if !self.cascade {
+            return LevelHasDependentsError {
+                level,
+                children,
+                versionpins,
+            }
+            .fail();
+        }
+        Ok(true)
+    }
+
+    // accumulate the versionpins and withs deleted along with a level
+    fn cascade_deleted(&mut self, cascaded: Cascaded) {
+        self.cascaded.versionpins += cascaded.versionpins;
+        self.cascaded.withs += cascaded.withs;
+    }
+}
+
+impl<'a> DeleteLevels<'a> {
+    // count the rows matching the query
+    fn count(&mut self, prepared: Prepared) -> Result<i64, DeleteLevelsError> {
+        let row = prepared
+            .query_one_tx(self.tx().unwrap())
+            .context(TokioPostgresError {
+                msg: "failed to count level dependents",
+            })?;
+        Ok(row.get(0))
+    }
+
+    // execute the statement
+    fn execute(&mut self, prepared: Prepared) -> Result<u64, DeleteLevelsError> {
+        prepared
+            .execute(self.tx().unwrap())
+            .context(TokioPostgresError {
+                msg: "failed to delete level",
+            })
+    }
+
+    /// Delete the previously registered levels within the transaction.
+    /// The database is not updated until one calls `commit(...)`.
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeleteLevelsError)
+    pub fn delete(mut self) -> Result<Self, DeleteLevelsError> {
+        let mut cnt = 0;
+        for level in self.validate()? {
+            let path = prep_query_str("facility", &level, false);
+            let exists = self.count(statement(COUNT_LEVEL, path.clone()))? > 0;
+            let children = self.count(statement(COUNT_CHILDREN, path.clone()))?;
+            let versionpins =
+                self.count(prepare_count_versionpins(LEVEL_VERSIONPINS, path.clone()))?;
+            if self.check(&level, exists, children, versionpins)? {
+                let cascaded =
+                    remove_versionpins(self.tx().unwrap(), LEVEL_VERSIONPINS, path.clone())
+                        .context(TokioPostgresError {
+                            msg: "failed to delete versionpins",
+                        })?;
+                self.cascade_deleted(cascaded);
+            }
+            self.execute(statement(DELETE_PKGCOORDS, path.clone()))?;
+            cnt += self.execute(statement(DELETE_LEVEL, path.clone()))?;
+        }
+        self.result_cnt = cnt;
+        Ok(self)
+    }
+}
+
+impl<'a> DeleteLevels<'a, AsyncTransaction<'a>> {
+    // count the rows matching the query
+    async fn count(&mut self, prepared: Prepared) -> Result<i64, DeleteLevelsError> {
+        let row = prepared
+            .query_one_tx_async(self.tx().unwrap())
+            .await
+            .context(TokioPostgresError {
+                msg: "failed to count level dependents",
+            })?;
+        Ok(row.get(0))
+    }
+
+    // execute the statement
+    async fn execute(&mut self, prepared: Prepared) -> Result<u64, DeleteLevelsError> {
+        prepared
+            .execute_async(self.tx().unwrap())
+            .await
+            .context(TokioPostgresError {
+                msg: "failed to delete level",
+            })
+    }
+
+    /// Delete the previously registered levels within the transaction.
+    /// The database is not updated until one calls `commit(...)`.
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeleteLevelsError)
+    pub async fn delete(mut self) -> Result<Self, DeleteLevelsError> {
+        let mut cnt = 0;
+        for level in self.validate()? {
+            let path = prep_query_str("facility", &level, false);
+            let exists = self.count(statement(COUNT_LEVEL, path.clone())).await? > 0;
+            let children = self.count(statement(COUNT_CHILDREN, path.clone())).await?;
+            let versionpins = self
+                .count(prepare_count_versionpins(LEVEL_VERSIONPINS, path.clone()))
+                .await?;
+            if self.check(&level, exists, children, versionpins)? {
+                let cascaded =
+                    remove_versionpins_async(self.tx().unwrap(), LEVEL_VERSIONPINS, path.clone())
+                        .await
+                        .context(TokioPostgresError {
+                            msg: "failed to delete versionpins",
+                        })?;
+                self.cascade_deleted(cascaded);
+            }
+            self.execute(statement(DELETE_PKGCOORDS, path.clone()))
+                .await?;
+            cnt += self.execute(statement(DELETE_LEVEL, path.clone())).await?;
+        }
+        self.result_cnt = cnt;
+        Ok(self)
+    }
+}
+
+impl<'a> DeleteLevels<'a, MemoryTransaction<'a>> {
+    /// Delete the previously registered levels within the transaction.
+    /// The store is not updated until one calls `commit(...)`.
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeleteLevelsError)
+    pub fn delete(mut self) -> Result<Self, DeleteLevelsError> {
+        let mut cnt = 0;
+        for level in self.validate()? {
+            let path = level_name_to_path(&level);
+            let tables = self.tx().unwrap().tables();
+            let exists = tables.levels.contains(&path);
+            let children = tables
+                .levels
+                .iter()
+                .filter(|stored| is_ancestor(&path, stored) && **stored != path)
+                .count() as i64;
+            let below = |coord: &PkgCoordRecord| is_ancestor(&path, &coord.level);
+            let pins = tables
+                .versionpins
+                .iter()
+                .filter(|pin| tables.pkgcoord(pin.coord).map_or(false, &below))
+                .map(|pin| pin.id)
+                .collect::<Vec<_>>();
+            if self.check(&level, exists, children, pins.len() as i64)? {
+                let cascaded = self.tx().unwrap().remove_versionpins(&pins);
+                self.cascade_deleted(cascaded);
+            }
+            let tx = self.tx().unwrap();
+            tx.remove_pkgcoords(&below);
+            cnt += tx.remove_levels(&path);
+        }
+        self.result_cnt = cnt;
+        Ok(self)
+    }
+}
@@ -0,0 +1,185 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! Builders responsible for removing entities from the database. Like
+//! their add and update counterparts, they operate within a transaction
+//! (blocking, async or MemoryStore), which is only committed when calling
+//! `commit(author, comment)`.
+//!
+//! Entities which others depend upon (eg a distribution referenced by
+//! versionpins) are protected. Unless `cascade(true)` is set on the builder,
+//! attempting to delete them results in an error. Versionpins deleted along
+//! with them take their withs with them, and each builder reports the
+//! versionpins and withs it removed in this way via `cascaded`.
+pub mod distributions;
+pub mod levels;
+pub mod packages;
+pub mod roles;
+pub mod versionpins;
+pub mod withs;
+
+use crate::db::async_packrat::AsyncTransaction;
+use crate::db::prepared::Prepared;
+use crate::db::utils::prep_query_str;
+use crate::types::IdType;
+use crate::Coords;
+use postgres::types::ToSql;
+use postgres::Transaction;
+
+/// The dependents deleted along with the entities a builder was asked to
+/// delete. Versionpins are only deleted when `cascade(true)` is set (or when
+/// they are the entities being deleted), and their withs go along with them.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Cascaded {
+    /// The number of versionpins deleted
+    pub versionpins: u64,
+    /// The number of withs deleted, both those of the deleted versionpins,
+    /// and those naming a deleted package
+    pub withs: u64,
+}
+
+// A statement taking `param` as its sole parameter
+pub(crate) fn statement<P>(sql: &str, param: P) -> Prepared
+where
+    P: ToSql + Sync + Send + 'static,
+{
+    let mut prepared = Prepared::new(sql);
+    prepared.push(param);
+    prepared
+}
+
+// Select the id of the versionpin for `package` located exactly at `coords`
+pub(crate) fn prepare_find_versionpin_id(package: &str, coords: &Coords) -> Prepared {
+    let mut prepared = Prepared::new(
+        "SELECT versionpin.id FROM versionpin
+        JOIN pkgcoord ON versionpin.coord = pkgcoord.id
+        WHERE pkgcoord.package = $1
+            AND pkgcoord.level = text2ltree($2)
+            AND pkgcoord.role = text2ltree($3)
+            AND pkgcoord.platform = text2ltree($4)
+            AND pkgcoord.site = text2ltree($5)",
+    );
+    prepared.push(package.to_string());
+    prepared.push(prep_query_str(
+        "facility",
+        coords.level.to_string().as_str(),
+        false,
+    ));
+    prepared.push(prep_query_str("any", coords.role.as_ref(), true));
+    prepared.push(prep_query_str("any", coords.platform.as_ref(), false));
+    prepared.push(prep_query_str("any", coords.site.as_ref(), false));
+    prepared
+}
+
+// Look up the id of the versionpin for `package` located exactly at `coords`
+pub(crate) fn find_versionpin_id(
+    tx: &mut Transaction<'_>,
+    package: &str,
+    coords: &Coords,
+) -> Result<Option<IdType>, tokio_postgres::error::Error> {
+    let rows = prepare_find_versionpin_id(package, coords).query_tx(tx)?;
+    Ok(rows.first().map(|row| row.get(0)))
+}
+
+// Count the versionpins matching the supplied where clause, which takes
+// `param` as $1
+pub(crate) fn prepare_count_versionpins<P>(versionpin_where: &str, param: P) -> Prepared
+where
+    P: ToSql + Sync + Send + 'static,
+{
+    statement(
+        format!("SELECT count(*) FROM versionpin WHERE {}", versionpin_where).as_str(),
+        param,
+    )
+}
+
+// Delete the versionpins matching the supplied where clause, preceded by
+// the statement deleting their withs
+pub(crate) fn prepare_remove_versionpins<P>(
+    versionpin_where: &str,
+    param: P,
+) -> (Prepared, Prepared)
+where
+    P: ToSql + Sync + Send + Clone + 'static,
+{
+    (
+        statement(
+            format!(
+                "DELETE FROM withpackage WHERE versionpin IN (SELECT id FROM versionpin WHERE {})",
+                versionpin_where
+            )
+            .as_str(),
+            param.clone(),
+        ),
+        statement(
+            format!("DELETE FROM versionpin WHERE {}", versionpin_where).as_str(),
+            param,
+        ),
+    )
+}
+
+// Remove the versionpins matching the supplied where clause, along with
+// their withs.
+pub(crate) fn remove_versionpins<P>(
+    tx: &mut Transaction<'_>,
+    versionpin_where: &str,
+    param: P,
+) -> Result<Cascaded, tokio_postgres::error::Error>
+where
+    P: ToSql + Sync + Send + Clone + 'static,
+{
+    let (withs, versionpins) = prepare_remove_versionpins(versionpin_where, param);
+    let withs = withs.execute(tx)?;
+    Ok(Cascaded {
+        versionpins: versionpins.execute(tx)?,
+        withs,
+    })
+}
+
+// Remove the versionpins matching the supplied where clause, along with
+// their withs, within an async transaction.
+pub(crate) async fn remove_versionpins_async<P>(
+    tx: &AsyncTransaction<'_>,
+    versionpin_where: &str,
+    param: P,
+) -> Result<Cascaded, tokio_postgres::error::Error>
+where
+    P: ToSql + Sync + Send + Clone + 'static,
+{
+    let (withs, versionpins) = prepare_remove_versionpins(versionpin_where, param);
+    let withs = withs.execute_async(tx).await?;
+    Ok(Cascaded {
+        versionpins: versionpins.execute_async(tx).await?,
+        withs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prepares_versionpin_removal_with_their_withs() {
+        let (withs, versionpins) = prepare_remove_versionpins("distribution = $1", 7);
+        assert_eq!(
+            withs.sql,
+            "DELETE FROM withpackage WHERE versionpin IN (SELECT id FROM versionpin WHERE distribution = $1)"
+        );
+        assert_eq!(
+            versionpins.sql,
+            "DELETE FROM versionpin WHERE distribution = $1"
+        );
+        assert_eq!(format!("{:?}", withs.params), "[7]");
+        assert_eq!(format!("{:?}", versionpins.params), "[7]");
+        let count = prepare_count_versionpins("distribution = $1", 7);
+        assert_eq!(
+            count.sql,
+            "SELECT count(*) FROM versionpin WHERE distribution = $1"
+        );
+    }
+}
@@ -0,0 +1,445 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+use super::{
+    prepare_count_versionpins, remove_versionpins, remove_versionpins_async, statement, Cascaded,
+};
+use crate::db::async_packrat::AsyncTransaction;
+use crate::db::memory::MemoryTransaction;
+use crate::db::prepared::Prepared;
+use crate::traits::{AsyncTransactionHandler, MemoryTransactionHandler, TransactionHandler};
+use itertools::Itertools;
+use postgres::Transaction;
+use snafu::{ResultExt, Snafu};
+use std::marker::PhantomData;
+
+/// Error type returned from DeletePackages
+#[derive(Debug, Snafu)]
+pub enum DeletePackagesError {
+    /// When constructing a query, postgres has thrown an error
+    #[snafu(display("Postgres Error: {} {:#?}", msg, source))]
+    TokioPostgresError {
+        msg: &'static str,
+        source: tokio_postgres::error::Error,
+    },
+    /// No packages were registered for deletion
+    #[snafu(display("No package names supplied"))]
+    NoPackageNamesError,
+    /// The package does not exist in the database
+    #[snafu(display("Package {} does not exist", package))]
+    PackageNotFoundError { package: String },
+    /// The package has dependents, and cascade has not been requested
+    #[snafu(display(
+        "Unable to delete {}. It has {} distribution(s), {} versionpin(s) and is a with of {} versionpin(s). Set cascade to remove them as well",
+        package,
+        distributions,
+        versionpins,
+        withs
+    ))]
+    PackageHasDependentsError {
+        package: String,
+        distributions: i64,
+        versionpins: i64,
+        withs: i64,
+    },
+}
+
+// versionpins belonging to the package, either via their distribution or their pkgcoord
+const PACKAGE_VERSIONPINS: &str = "coord IN (SELECT id FROM pkgcoord WHERE package = $1) \
+                                   OR distribution IN (SELECT id FROM distribution WHERE package = $1)";
+
+// the statements taking the package as their sole parameter
+const COUNT_PACKAGE: &str = "SELECT count(*) FROM package WHERE name = $1";
+const COUNT_DISTRIBUTIONS: &str = "SELECT count(*) FROM distribution WHERE package = $1";
+const COUNT_WITHS: &str = "SELECT count(*) FROM withpackage WHERE package = $1";
+const DELETE_WITHS: &str = "DELETE FROM withpackage WHERE package = $1";
+const DELETE_DISTRIBUTIONS: &str = "DELETE FROM distribution WHERE package = $1";
+const DELETE_PKGCOORDS: &str = "DELETE FROM pkgcoord WHERE package = $1";
+const DELETE_PACKAGE: &str = "DELETE FROM package WHERE name = $1";
+
+/// The number of each of the dependents of a package
+struct Dependents {
+    distributions: i64,
+    versionpins: i64,
+    withs: i64,
+}
+
+/// Responsible for deleting packages. Packages which have distributions,
+/// versionpins, or which are used as withs are only deleted, along with
+/// their dependents, when `cascade` is set.
+pub struct DeletePackages<'a, T = Transaction<'a>> {
+    tx: Option<T>,
+    names: Vec<String>,
+    cascade: bool,
+    cascaded: Cascaded,
+    result_cnt: u64,
+    phantom: PhantomData<&'a ()>,
+}
+
+impl<'a> TransactionHandler<'a> for DeletePackages<'a> {
+    type Error = tokio_postgres::error::Error;
+    /// retrieve an Option wrapped mutable reference to the
+    /// transaction
+    fn tx(&mut self) -> Option<&mut Transaction<'a>> {
+        self.tx.as_mut()
+    }
+    /// Extract the transaction from Self.
+    fn take_tx(&mut self) -> Transaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    /// Return the result count to 0
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+    /// Retrieve th result count
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> AsyncTransactionHandler<'a> for DeletePackages<'a, AsyncTransaction<'a>> {
+    type Error = tokio_postgres::error::Error;
+    fn tx(&mut self) -> Option<&mut AsyncTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> AsyncTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> MemoryTransactionHandler<'a> for DeletePackages<'a, MemoryTransaction<'a>> {
+    fn tx(&mut self) -> Option<&mut MemoryTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> MemoryTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a, T> DeletePackages<'a, T> {
+    /// new up a DeletePackages instance
+    ///
+    /// # Arguments
+    ///
+    /// * `tx` - A Transaction instance
+    pub fn new(tx: T) -> Self {
+        Self {
+            tx: Some(tx),
+            names: Vec::new(),
+            cascade: false,
+            cascaded: Cascaded::default(),
+            result_cnt: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Register a package for deletion
+    ///
+    /// # Arguments
+    /// * `name` - The name of the package
+    ///
+    /// # Returns
+    /// * Self
+    pub fn package<I: Into<String>>(mut self, name: I) -> Self {
+        self.names.push(name.into());
+        self
+    }
+
+    /// Register a vector of packages for deletion
+    ///
+    /// # Arguments
+    /// * `names` - The names of the packages
+    ///
+    /// # Returns
+    /// * Self
+    pub fn packages(mut self, names: &mut Vec<String>) -> Self {
+        self.names.append(names);
+        self
+    }
+
+    /// Determine whether the distributions, versionpins and withs
+    /// depending upon the packages are deleted as well, along with the
+    /// withs of those versionpins. Defaults to false. The versionpins and
+    /// withs deleted are reported by `cascaded`.
+    ///
+    /// # Arguments
+    /// * `cascade` - Whether to delete dependents
+    ///
+    /// # Returns
+    /// * Self
+    pub fn cascade(mut self, cascade: bool) -> Self {
+        self.cascade = cascade;
+        self
+    }
+
+    /// Retrieve the versionpins and withs deleted along with the packages
+    /// by `delete`
+    pub fn cascaded(&self) -> Cascaded {
+        self.cascaded
+    }
+
+    // the unique packages to delete
+    fn validate(&self) -> Result<Vec<String>, DeletePackagesError> {
+        let packages = self.names.iter().unique().cloned().collect::<Vec<String>>();
+        if packages.is_empty() {
+            return Err(DeletePackagesError::NoPackageNamesError);
+        }
+        Ok(packages)
+    }
+
+    // refuse to delete a package which has dependents, unless cascading.
+    // Returns whether there are dependents to delete.
+    fn check(
+        &self,
+        package: &str,
+        exists: bool,
+        dependents: Dependents,
+    ) -> Result<bool, DeletePackagesError> {
+        if !exists {
+            return PackageNotFoundError { package }.fail();
+        }
+        let Dependents {
+            distributions,
+            versionpins,
+            withs,
+        } = dependents;
+        if distributions + versionpins + withs == 0 {
+            return Ok(false);
+        }
+        if !self.cascade {
+            return PackageHasDependentsError {
+                package,
+                distributions,
+                versionpins,
+                withs,
+            }
+            .fail();
+        }
+        Ok(true)
+    }
+
+    // accumulate the versionpins and withs deleted along with a package
+    fn cascade_deleted(&mut self, cascaded: Cascaded) {
+        self.cascaded.versionpins += cascaded.versionpins;
+        self.cascaded.withs += cascaded.withs;
+    }
+}
+
+impl<'a> DeletePackages<'a> {
+    // count the rows matching the query
+    fn count(&mut self, prepared: Prepared) -> Result<i64, DeletePackagesError> {
+        let row = prepared
+            .query_one_tx(self.tx().unwrap())
+            .context(TokioPostgresError {
+                msg: "failed to count package dependents",
+            })?;
+        Ok(row.get(0))
+    }
+
+    // execute the statement
+    fn execute(&mut self, prepared: Prepared) -> Result<u64, DeletePackagesError> {
+        prepared
+            .execute(self.tx().unwrap())
+            .context(TokioPostgresError {
+                msg: "failed to delete package dependents",
+            })
+    }
+
+    /// Delete the previously registered packages within the transaction.
+    /// The database is not updated until one calls `commit(...)`.
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeletePackagesError)
+    pub fn delete(mut self) -> Result<Self, DeletePackagesError> {
+        let mut cnt = 0;
+        for package in self.validate()? {
+            let exists = self.count(statement(COUNT_PACKAGE, package.clone()))? > 0;
+            let dependents = Dependents {
+                distributions: self.count(statement(COUNT_DISTRIBUTIONS, package.clone()))?,
+                versionpins: self.count(prepare_count_versionpins(
+                    PACKAGE_VERSIONPINS,
+                    package.clone(),
+                ))?,
+                withs: self.count(statement(COUNT_WITHS, package.clone()))?,
+            };
+            if self.check(&package, exists, dependents)? {
+                let withs = self.execute(statement(DELETE_WITHS, package.clone()))?;
+                let cascaded =
+                    remove_versionpins(self.tx().unwrap(), PACKAGE_VERSIONPINS, package.clone())
+                        .context(TokioPostgresError {
+                            msg: "failed to delete versionpins",
+                        })?;
+                self.cascade_deleted(Cascaded {
+                    withs: withs + cascaded.withs,
+                    ..cascaded
+                });
+                self.execute(statement(DELETE_DISTRIBUTIONS, package.clone()))?;
+            }
+            // pkgcoords without versionpins are an implementation detail
+            // and are removed regardless
+            self.execute(statement(DELETE_PKGCOORDS, package.clone()))?;
+            cnt += self.execute(statement(DELETE_PACKAGE, package.clone()))?;
+        }
+        self.result_cnt = cnt;
+        Ok(self)
+    }
+}
+
+impl<'a> DeletePackages<'a, AsyncTransaction<'a>> {
+    // count the rows matching the query
+    async fn count(&mut self, prepared: Prepared) -> Result<i64, DeletePackagesError> {
+        let row = prepared
+            .query_one_tx_async(self.tx().unwrap())
+            .await
+            .context(TokioPostgresError {
+                msg: "failed to count package dependents",
+            })?;
+        Ok(row.get(0))
+    }
+
+    // execute the statement
+    async fn execute(&mut self, prepared: Prepared) -> Result<u64, DeletePackagesError> {
+        prepared
+            .execute_async(self.tx().unwrap())
+            .await
+            .context(TokioPostgresError {
+                msg: "failed to delete package dependents",
+            })
+    }
+
+    /// Delete the previously registered packages within the transaction.
+    /// The database is not updated until one calls `commit(...)`.
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeletePackagesError)
+    pub async fn delete(mut self) -> Result<Self, DeletePackagesError> {
+        let mut cnt = 0;
+        for package in self.validate()? {
+            let exists = self
+                .count(statement(COUNT_PACKAGE, package.clone()))
+                .await?
+                > 0;
+            let dependents = Dependents {
+                distributions: self
+                    .count(statement(COUNT_DISTRIBUTIONS, package.clone()))
+                    .await?,
+                versionpins: self
+                    .count(prepare_count_versionpins(
+                        PACKAGE_VERSIONPINS,
+                        package.clone(),
+                    ))
+                    .await?,
+                withs: self.count(statement(COUNT_WITHS, package.clone())).await?,
+            };
+            if self.check(&package, exists, dependents)? {
+                let withs = self
+                    .execute(statement(DELETE_WITHS, package.clone()))
+                    .await?;
+                let cascaded = remove_versionpins_async(
+                    self.tx().unwrap(),
+                    PACKAGE_VERSIONPINS,
+                    package.clone(),
+                )
+                .await
+                .context(TokioPostgresError {
+                    msg: "failed to delete versionpins",
+                })?;
+                self.cascade_deleted(Cascaded {
+                    withs: withs + cascaded.withs,
+                    ..cascaded
+                });
+                self.execute(statement(DELETE_DISTRIBUTIONS, package.clone()))
+                    .await?;
+            }
+            // pkgcoords without versionpins are an implementation detail
+            // and are removed regardless
+            self.execute(statement(DELETE_PKGCOORDS, package.clone()))
+                .await?;
+            cnt += self
+                .execute(statement(DELETE_PACKAGE, package.clone()))
+                .await?;
+        }
+        self.result_cnt = cnt;
+        Ok(self)
+    }
+}
+
+impl<'a> DeletePackages<'a, MemoryTransaction<'a>> {
+    /// Delete the previously registered packages within the transaction.
+    /// The store is not updated until one calls `commit(...)`.
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeletePackagesError)
+    pub fn delete(mut self) -> Result<Self, DeletePackagesError> {
+        let mut cnt = 0;
+        for package in self.validate()? {
+            let tables = self.tx().unwrap().tables();
+            let exists = tables.packages.contains(&package);
+            let distributions = tables
+                .distributions
+                .iter()
+                .filter(|dist| dist.package == package)
+                .map(|dist| dist.id)
+                .collect::<Vec<_>>();
+            let pins = tables
+                .versionpins
+                .iter()
+                .filter(|pin| {
+                    distributions.contains(&pin.distribution)
+                        || tables
+                            .pkgcoord(pin.coord)
+                            .map_or(false, |coord| coord.package == package)
+                })
+                .map(|pin| pin.id)
+                .collect::<Vec<_>>();
+            let dependents = Dependents {
+                distributions: distributions.len() as i64,
+                versionpins: pins.len() as i64,
+                withs: tables
+                    .withpackages
+                    .iter()
+                    .filter(|with| with.package == package)
+                    .count() as i64,
+            };
+            if self.check(&package, exists, dependents)? {
+                let tx = self.tx().unwrap();
+                let withs = tx.remove_withs(|with| with.package == package);
+                let cascaded = tx.remove_versionpins(&pins);
+                tx.remove_distributions(|dist| dist.package == package);
+                self.cascade_deleted(Cascaded {
+                    withs: withs + cascaded.withs,
+                    ..cascaded
+                });
+            }
+            let tx = self.tx().unwrap();
+            tx.remove_pkgcoords(|coord| coord.package == package);
+            cnt += tx.remove_package(&package);
+        }
+        self.result_cnt = cnt;
+        Ok(self)
+    }
+}
@@ -0,0 +1,386 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+use super::{
+    prepare_count_versionpins, remove_versionpins, remove_versionpins_async, statement, Cascaded,
+};
+use crate::db::async_packrat::AsyncTransaction;
+use crate::db::memory::functions::{is_ancestor, role_name_to_path};
+use crate::db::memory::{MemoryTransaction, PkgCoordRecord};
+use crate::db::prepared::Prepared;
+use crate::db::utils::prep_query_str;
+use crate::traits::{AsyncTransactionHandler, MemoryTransactionHandler, TransactionHandler};
+use itertools::Itertools;
+use postgres::Transaction;
+use snafu::{ResultExt, Snafu};
+use std::marker::PhantomData;
+
+/// Error type returned from DeleteRoles
+#[derive(Debug, Snafu)]
+pub enum DeleteRolesError {
+    /// When constructing a query, postgres has thrown an error
+    #[snafu(display("Postgres Error: {} {:#?}", msg, source))]
+    TokioPostgresError {
+        msg: &'static str,
+        source: tokio_postgres::error::Error,
+    },
+    /// No roles were registered for deletion
+    #[snafu(display("No role names supplied"))]
+    NoRoleNamesError,
+    /// Any is the root of the role hierarchy, and may not be deleted
+    #[snafu(display("The any role may not be deleted"))]
+    AnyRoleError,
+    /// The role does not exist in the database
+    #[snafu(display("Role {} does not exist", role))]
+    RoleNotFoundError { role: String },
+    /// The role has dependents, and cascade has not been requested
+    #[snafu(display(
+        "Unable to delete {}. It has {} subrole(s) and {} versionpin(s). Set cascade to remove them as well",
+        role,
+        subroles,
+        versionpins
+    ))]
+    RoleHasDependentsError {
+        role: String,
+        subroles: i64,
+        versionpins: i64,
+    },
+}
+
+// versionpins at, or below, the role
+const ROLE_VERSIONPINS: &str = "coord IN (SELECT id FROM pkgcoord WHERE role <@ text2ltree($1))";
+
+// the statements taking the role path as their sole parameter
+const COUNT_ROLE: &str = "SELECT count(*) FROM role WHERE path = text2ltree($1)";
+const COUNT_SUBROLES: &str =
+    "SELECT count(*) FROM role WHERE path <@ text2ltree($1) AND path <> text2ltree($1)";
+const DELETE_PKGCOORDS: &str = "DELETE FROM pkgcoord WHERE role <@ text2ltree($1)";
+const DELETE_ROLE: &str = "DELETE FROM role WHERE path <@ text2ltree($1)";
+
+/// Responsible for deleting roles. Roles which have subroles or
+/// versionpins are only deleted, along with their dependents, when
+/// `cascade` is set.
+pub struct DeleteRoles<'a, T = Transaction<'a>> {
+    tx: Option<T>,
+    names: Vec<String>,
+    cascade: bool,
+    cascaded: Cascaded,
+    result_cnt: u64,
+    phantom: PhantomData<&'a ()>,
+}
+
+impl<'a> TransactionHandler<'a> for DeleteRoles<'a> {
+    type Error = tokio_postgres::error::Error;
+    /// retrieve an Option wrapped mutable reference to the
+    /// transaction
+    fn tx(&mut self) -> Option<&mut Transaction<'a>> {
+        self.tx.as_mut()
+    }
+    /// Extract the transaction from Self.
+    fn take_tx(&mut self) -> Transaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    /// Return the result count to 0
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+    /// Retrieve th result count
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> AsyncTransactionHandler<'a> for DeleteRoles<'a, AsyncTransaction<'a>> {
+    type Error = tokio_postgres::error::Error;
+    fn tx(&mut self) -> Option<&mut AsyncTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> AsyncTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> MemoryTransactionHandler<'a> for DeleteRoles<'a, MemoryTransaction<'a>> {
+    fn tx(&mut self) -> Option<&mut MemoryTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> MemoryTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a, T> DeleteRoles<'a, T> {
+    /// new up a DeleteRoles instance
+    ///
+    /// # Arguments
+    ///
+    /// * `tx` - A Transaction instance
+    pub fn new(tx: T) -> Self {
+        Self {
+            tx: Some(tx),
+            names: Vec::new(),
+            cascade: false,
+            cascaded: Cascaded::default(),
+            result_cnt: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Register a role (eg `model_beta`) for deletion
+    ///
+    /// # Arguments
+    /// * `name` - The name of the role
+    ///
+    /// # Returns
+    /// * Self
+    pub fn role<I: Into<String>>(mut self, name: I) -> Self {
+        self.names.push(name.into());
+        self
+    }
+
+    /// Register a vector of roles for deletion
+    ///
+    /// # Arguments
+    /// * `names` - The names of the roles
+    ///
+    /// # Returns
+    /// * Self
+    pub fn roles(mut self, names: &mut Vec<String>) -> Self {
+        self.names.append(names);
+        self
+    }
+
+    /// Determine whether the subroles and versionpins of the
+    /// roles are deleted as well, along with the withs of those
+    /// versionpins. Defaults to false. The versionpins and withs deleted
+    /// are reported by `cascaded`.
+    ///
+    /// # Arguments
+    /// * `cascade` - Whether to delete dependents
+    ///
+    /// # Returns
+    /// * Self
+    pub fn cascade(mut self, cascade: bool) -> Self {
+        self.cascade = cascade;
+        self
+    }
+
+    /// Retrieve the versionpins and withs deleted along with the roles
+    /// by `delete`
+    pub fn cascaded(&self) -> Cascaded {
+        self.cascaded
+    }
+
+    // the unique, lowercased, roles to delete
+    fn validate(&self) -> Result<Vec<String>, DeleteRolesError> {
+        let roles = self
+            .names
+            .iter()
+            .unique()
+            .map(|x| x.to_lowercase())
+            .collect::<Vec<_>>();
+        if roles.is_empty() {
+            return Err(DeleteRolesError::NoRoleNamesError);
+        }
+        if roles.iter().any(|role| role == "any") {
+            return Err(DeleteRolesError::AnyRoleError);
+        }
+        Ok(roles)
+    }
+
+    // refuse to delete a role which has dependents, unless cascading. Returns
+    // whether there are dependents to delete.
+    fn check(
+        &self,
+        role: &str,
+        exists: bool,
+        subroles: i64,
+        versionpins: i64,
+    ) -> Result<bool, DeleteRolesError> {
+        if !exists {
+            return RoleNotFoundError { role }.fail();
+        }
+        if subroles + versionpins == 0 {
+            return Ok(false);
+        }
+        if !self.cascade {
+            return RoleHasDependentsError {
+                role,
+                subroles,
+                versionpins,
+            }
+            .fail();
+        }
+        Ok(true)
+    }
+
+    // accumulate the versionpins and withs deleted along with a role
+    fn cascade_deleted(&mut self, cascaded: Cascaded) {
+        self.cascaded.versionpins += cascaded.versionpins;
+        self.cascaded.withs += cascaded.withs;
+    }
+}
+
+impl<'a> DeleteRoles<'a> {
+    // count the rows matching the query
+    fn count(&mut self, prepared: Prepared) -> Result<i64, DeleteRolesError> {
+        let row = prepared
+            .query_one_tx(self.tx().unwrap())
+            .context(TokioPostgresError {
+                msg: "failed to count role dependents",
+            })?;
+        Ok(row.get(0))
+    }
+
+    // execute the statement
+    fn execute(&mut self, prepared: Prepared) -> Result<u64, DeleteRolesError> {
+        prepared
+            .execute(self.tx().unwrap())
+            .context(TokioPostgresError {
+                msg: "failed to delete role",
+            })
+    }
+
+    /// Delete the previously registered roles within the transaction.
+    /// The database is not updated until one calls `commit(...)`.
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeleteRolesError)
+    pub fn delete(mut self) -> Result<Self, DeleteRolesError> {
+        let mut cnt = 0;
+        for role in self.validate()? {
+            let path = prep_query_str("any", &role, true);
+            let exists = self.count(statement(COUNT_ROLE, path.clone()))? > 0;
+            let subroles = self.count(statement(COUNT_SUBROLES, path.clone()))?;
+            let versionpins =
+                self.count(prepare_count_versionpins(ROLE_VERSIONPINS, path.clone()))?;
+            if self.check(&role, exists, subroles, versionpins)? {
+                let cascaded =
+                    remove_versionpins(self.tx().unwrap(), ROLE_VERSIONPINS, path.clone())
+                        .context(TokioPostgresError {
+                            msg: "failed to delete versionpins",
+                        })?;
+                self.cascade_deleted(cascaded);
+            }
+            self.execute(statement(DELETE_PKGCOORDS, path.clone()))?;
+            cnt += self.execute(statement(DELETE_ROLE, path.clone()))?;
+        }
+        self.result_cnt = cnt;
+        Ok(self)
+    }
+}
+
+impl<'a> DeleteRoles<'a, AsyncTransaction<'a>> {
+    // count the rows matching the query
+    async fn count(&mut self, prepared: Prepared) -> Result<i64, DeleteRolesError> {
+        let row = prepared
+            .query_one_tx_async(self.tx().unwrap())
+            .await
+            .context(TokioPostgresError {
+                msg: "failed to count role dependents",
+            })?;
+        Ok(row.get(0))
+    }
+
+    // execute the statement
+    async fn execute(&mut self, prepared: Prepared) -> Result<u64, DeleteRolesError> {
+        prepared
+            .execute_async(self.tx().unwrap())
+            .await
+            .context(TokioPostgresError {
+                msg: "failed to delete role",
+            })
+    }
+
+    /// Delete the previously registered roles within the transaction.
+    /// The database is not updated until one calls `commit(...)`.
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeleteRolesError)
+    pub async fn delete(mut self) -> Result<Self, DeleteRolesError> {
+        let mut cnt = 0;
+        for role in self.validate()? {
+            let path = prep_query_str("any", &role, true);
+            let exists = self.count(statement(COUNT_ROLE, path.clone())).await? > 0;
+            let subroles = self.count(statement(COUNT_SUBROLES, path.clone())).await?;
+            let versionpins = self
+                .count(prepare_count_versionpins(ROLE_VERSIONPINS, path.clone()))
+                .await?;
+            if self.check(&role, exists, subroles, versionpins)? {
+                let cascaded =
+                    remove_versionpins_async(self.tx().unwrap(), ROLE_VERSIONPINS, path.clone())
+                        .await
+                        .context(TokioPostgresError {
+                            msg: "failed to delete versionpins",
+                        })?;
+                self.cascade_deleted(cascaded);
+            }
+            self.execute(statement(DELETE_PKGCOORDS, path.clone()))
+                .await?;
+            cnt += self.execute(statement(DELETE_ROLE, path.clone())).await?;
+        }
+        self.result_cnt = cnt;
+        Ok(self)
+    }
+}
+
+impl<'a> DeleteRoles<'a, MemoryTransaction<'a>> {
+    /// Delete the previously registered roles within the transaction.
+    /// The store is not updated until one calls `commit(...)`.
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeleteRolesError)
+    pub fn delete(mut self) -> Result<Self, DeleteRolesError> {
+        let mut cnt = 0;
+        for role in self.validate()? {
+            let path = role_name_to_path(&role);
+            let tables = self.tx().unwrap().tables();
+            let exists = tables.roles.contains(&path);
+            let subroles = tables
+                .roles
+                .iter()
+                .filter(|stored| is_ancestor(&path, stored) && **stored != path)
+                .count() as i64;
+            let below = |coord: &PkgCoordRecord| is_ancestor(&path, &coord.role);
+            let pins = tables
+                .versionpins
+                .iter()
+                .filter(|pin| tables.pkgcoord(pin.coord).map_or(false, &below))
+                .map(|pin| pin.id)
+                .collect::<Vec<_>>();
+            if self.check(&role, exists, subroles, pins.len() as i64)? {
+                let cascaded = self.tx().unwrap().remove_versionpins(&pins);
+                self.cascade_deleted(cascaded);
+            }
+            let tx = self.tx().unwrap();
+            tx.remove_pkgcoords(&below);
+            cnt += tx.remove_roles(&path);
+        }
+        self.result_cnt = cnt;
+        Ok(self)
+    }
+}
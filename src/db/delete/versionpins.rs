@@ -0,0 +1,305 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+use super::{prepare_find_versionpin_id, remove_versionpins, remove_versionpins_async, Cascaded};
+use crate::db::async_packrat::AsyncTransaction;
+use crate::db::memory::functions::{any_name_to_path, level_name_to_path, role_name_to_path};
+use crate::db::memory::MemoryTransaction;
+use crate::traits::{AsyncTransactionHandler, MemoryTransactionHandler, TransactionHandler};
+use crate::types::IdType;
+use crate::Coords;
+use itertools::Itertools;
+use postgres::Transaction;
+use snafu::{ResultExt, Snafu};
+use std::marker::PhantomData;
+
+/// Error type returned from DeleteVersionPins
+#[derive(Debug, Snafu)]
+pub enum DeleteVersionPinsError {
+    /// When constructing a query, postgres has thrown an error
+    #[snafu(display("Postgres Error: {} {:#?}", msg, source))]
+    TokioPostgresError {
+        msg: &'static str,
+        source: tokio_postgres::error::Error,
+    },
+    /// No versionpins were registered for deletion
+    #[snafu(display("No versionpins supplied"))]
+    NoVersionPinsError,
+    /// There is no versionpin for the package at the supplied coords
+    #[snafu(display("No versionpin found for {} at {}", package, coords))]
+    VersionPinNotFoundError { package: String, coords: String },
+}
+
+/// Responsible for deleting versionpins, along with their withs. Versionpins
+/// may be identified either by id, or by package and coords.
+pub struct DeleteVersionPins<'a, T = Transaction<'a>> {
+    tx: Option<T>,
+    ids: Vec<IdType>,
+    pins: Vec<(String, Coords)>,
+    cascaded: Cascaded,
+    result_cnt: u64,
+    phantom: PhantomData<&'a ()>,
+}
+
+impl<'a> TransactionHandler<'a> for DeleteVersionPins<'a> {
+    type Error = tokio_postgres::error::Error;
+    /// retrieve an Option wrapped mutable reference to the
+    /// transaction
+    fn tx(&mut self) -> Option<&mut Transaction<'a>> {
+        self.tx.as_mut()
+    }
+    /// Extract the transaction from Self.
+    fn take_tx(&mut self) -> Transaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    /// Return the result count to 0
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+    /// Retrieve th result count
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> AsyncTransactionHandler<'a> for DeleteVersionPins<'a, AsyncTransaction<'a>> {
+    type Error = tokio_postgres::error::Error;
+    fn tx(&mut self) -> Option<&mut AsyncTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> AsyncTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> MemoryTransactionHandler<'a> for DeleteVersionPins<'a, MemoryTransaction<'a>> {
+    fn tx(&mut self) -> Option<&mut MemoryTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> MemoryTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a, T> DeleteVersionPins<'a, T> {
+    /// new up a DeleteVersionPins instance
+    ///
+    /// # Arguments
+    ///
+    /// * `tx` - A Transaction instance
+    pub fn new(tx: T) -> Self {
+        Self {
+            tx: Some(tx),
+            ids: Vec::new(),
+            pins: Vec::new(),
+            cascaded: Cascaded::default(),
+            result_cnt: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Register the id of a versionpin we wish to delete
+    ///
+    /// # Arguments
+    /// * `id` - The database id of the versionpin
+    ///
+    /// # Returns
+    /// * Self
+    pub fn versionpin_id(mut self, id: IdType) -> Self {
+        self.ids.push(id);
+        self
+    }
+
+    /// Register a vector of versionpin ids we wish to delete
+    ///
+    /// # Arguments
+    /// * `ids` - The database ids of the versionpins
+    ///
+    /// # Returns
+    /// * Self
+    pub fn versionpin_ids(mut self, ids: &mut Vec<IdType>) -> Self {
+        self.ids.append(ids);
+        self
+    }
+
+    /// Register the versionpin for a package at the supplied coords
+    /// for deletion. Note that the coords must match exactly.
+    ///
+    /// # Arguments
+    /// * `package` - The name of the package
+    /// * `coords` - The exact location of the versionpin in package space
+    ///
+    /// # Returns
+    /// * Self
+    pub fn versionpin<I: Into<String>>(mut self, package: I, coords: Coords) -> Self {
+        self.pins.push((package.into(), coords));
+        self
+    }
+
+    /// Retrieve the versionpins and withs deleted by `delete`. The result
+    /// count is that of the versionpins.
+    pub fn cascaded(&self) -> Cascaded {
+        self.cascaded
+    }
+
+    // the id of the versionpin, if found, or the error reporting its absence
+    fn found(
+        id: Option<IdType>,
+        package: &str,
+        coords: &Coords,
+    ) -> Result<IdType, DeleteVersionPinsError> {
+        match id {
+            Some(id) => Ok(id),
+            None => VersionPinNotFoundError {
+                package,
+                coords: coords.to_string(),
+            }
+            .fail(),
+        }
+    }
+
+    // the unique ids to delete
+    fn unique(ids: Vec<IdType>) -> Result<Vec<IdType>, DeleteVersionPinsError> {
+        let ids = ids.into_iter().unique().collect::<Vec<_>>();
+        if ids.is_empty() {
+            return Err(DeleteVersionPinsError::NoVersionPinsError);
+        }
+        Ok(ids)
+    }
+
+    // record the versionpins and withs deleted
+    fn deleted(&mut self, cascaded: Cascaded) {
+        self.result_cnt = cascaded.versionpins;
+        self.cascaded = cascaded;
+    }
+}
+
+impl<'a> DeleteVersionPins<'a> {
+    /// Delete the previously registered versionpins, and their withs,
+    /// within the transaction. The database is not updated until one
+    /// calls `commit(...)`.
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeleteVersionPinsError)
+    pub fn delete(mut self) -> Result<Self, DeleteVersionPinsError> {
+        let pins = std::mem::take(&mut self.pins);
+        let mut ids = std::mem::take(&mut self.ids);
+        for (package, coords) in &pins {
+            let rows = prepare_find_versionpin_id(package, coords)
+                .query_tx(self.tx().unwrap())
+                .context(TokioPostgresError {
+                    msg: "failed to look up versionpin",
+                })?;
+            ids.push(Self::found(
+                rows.first().map(|row| row.get(0)),
+                package,
+                coords,
+            )?);
+        }
+        let ids = Self::unique(ids)?;
+        let cascaded = remove_versionpins(self.tx().unwrap(), "id = ANY($1)", ids).context(
+            TokioPostgresError {
+                msg: "failed to delete versionpins",
+            },
+        )?;
+        self.deleted(cascaded);
+        Ok(self)
+    }
+}
+
+impl<'a> DeleteVersionPins<'a, AsyncTransaction<'a>> {
+    /// Delete the previously registered versionpins, and their withs,
+    /// within the transaction. The database is not updated until one
+    /// calls `commit(...)`.
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeleteVersionPinsError)
+    pub async fn delete(mut self) -> Result<Self, DeleteVersionPinsError> {
+        let pins = std::mem::take(&mut self.pins);
+        let mut ids = std::mem::take(&mut self.ids);
+        for (package, coords) in &pins {
+            let rows = prepare_find_versionpin_id(package, coords)
+                .query_tx_async(self.tx().unwrap())
+                .await
+                .context(TokioPostgresError {
+                    msg: "failed to look up versionpin",
+                })?;
+            ids.push(Self::found(
+                rows.first().map(|row| row.get(0)),
+                package,
+                coords,
+            )?);
+        }
+        let ids = Self::unique(ids)?;
+        let cascaded = remove_versionpins_async(self.tx().unwrap(), "id = ANY($1)", ids)
+            .await
+            .context(TokioPostgresError {
+                msg: "failed to delete versionpins",
+            })?;
+        self.deleted(cascaded);
+        Ok(self)
+    }
+}
+
+impl<'a> DeleteVersionPins<'a, MemoryTransaction<'a>> {
+    /// Delete the previously registered versionpins, and their withs,
+    /// within the transaction. The store is not updated until one
+    /// calls `commit(...)`.
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeleteVersionPinsError)
+    pub fn delete(mut self) -> Result<Self, DeleteVersionPinsError> {
+        let pins = std::mem::take(&mut self.pins);
+        let mut ids = std::mem::take(&mut self.ids);
+        for (package, coords) in &pins {
+            let tables = self.tx().unwrap().tables();
+            let (level, role, platform, site) = (
+                level_name_to_path(&coords.level.to_string()),
+                role_name_to_path(coords.role.as_ref()),
+                any_name_to_path(coords.platform.as_ref()),
+                any_name_to_path(coords.site.as_ref()),
+            );
+            let id = tables
+                .pkgcoords
+                .iter()
+                .find(|coord| {
+                    &coord.package == package
+                        && coord.level == level
+                        && coord.role == role
+                        && coord.platform == platform
+                        && coord.site == site
+                })
+                .and_then(|coord| tables.versionpins.iter().find(|pin| pin.coord == coord.id))
+                .map(|pin| pin.id);
+            ids.push(Self::found(id, package, coords)?);
+        }
+        let ids = Self::unique(ids)?;
+        let cascaded = self.tx().unwrap().remove_versionpins(&ids);
+        self.deleted(cascaded);
+        Ok(self)
+    }
+}
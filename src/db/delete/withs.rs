@@ -0,0 +1,311 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+use crate::db::async_packrat::AsyncTransaction;
+use crate::db::memory::MemoryTransaction;
+use crate::db::prepared::Prepared;
+use crate::traits::{AsyncTransactionHandler, MemoryTransactionHandler, TransactionHandler};
+use crate::types::IdType;
+use postgres::Transaction;
+use snafu::{ResultExt, Snafu};
+use std::marker::PhantomData;
+
+/// Error type returned from DeleteWiths
+#[derive(Debug, Snafu)]
+pub enum DeleteWithsError {
+    /// When constructing a query, postgres has thrown an error
+    #[snafu(display("Postgres Error: {} {:#?}", msg, source))]
+    TokioPostgresError {
+        msg: &'static str,
+        source: tokio_postgres::error::Error,
+    },
+    /// If we attempt to call delete without any withs
+    #[snafu(display("No withs supplied"))]
+    NoWithNamesError,
+}
+
+// Renumber the remaining withs of the versionpin from 0, retaining their order
+const RENUMBER_WITHS: &str = "UPDATE withpackage SET pinorder = renumbered.pinorder
+            FROM (
+                SELECT id, (row_number() OVER (ORDER BY pinorder) - 1)::INTEGER AS pinorder
+                FROM withpackage WHERE versionpin = $1
+            ) AS renumbered
+            WHERE withpackage.id = renumbered.id
+                AND withpackage.pinorder <> renumbered.pinorder";
+
+/// The DeleteWiths struct is responsible for removing withs from a versionpin.
+pub struct DeleteWiths<'a, T = Transaction<'a>> {
+    tx: Option<T>,
+    result_cnt: u64,
+    phantom: PhantomData<&'a ()>,
+}
+
+impl<'a> TransactionHandler<'a> for DeleteWiths<'a> {
+    type Error = tokio_postgres::error::Error;
+    /// retrieve an Option wrapped mutable reference to the
+    /// transaction
+    fn tx(&mut self) -> Option<&mut Transaction<'a>> {
+        self.tx.as_mut()
+    }
+    /// Extract the transaction from Self.
+    fn take_tx(&mut self) -> Transaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    /// Return the result count to 0
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+    /// Retrieve th result count
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> AsyncTransactionHandler<'a> for DeleteWiths<'a, AsyncTransaction<'a>> {
+    type Error = tokio_postgres::error::Error;
+    fn tx(&mut self) -> Option<&mut AsyncTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> AsyncTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> MemoryTransactionHandler<'a> for DeleteWiths<'a, MemoryTransaction<'a>> {
+    fn tx(&mut self) -> Option<&mut MemoryTransaction<'a>> {
+        self.tx.as_mut()
+    }
+
+    fn take_tx(&mut self) -> MemoryTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a, T> DeleteWiths<'a, T> {
+    /// New up a DeleteWiths instance
+    ///
+    /// # Arguments
+    /// * `tx` - A Transaction instance
+    ///
+    /// # Returns
+    /// * An instance of Self
+    pub fn new(tx: T) -> Self {
+        Self {
+            tx: Some(tx),
+            result_cnt: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    // build the statements deleting the named withs from the versionpin,
+    // and renumbering those which remain
+    fn prepare(
+        vpin_id: IdType,
+        withs: Vec<String>,
+    ) -> Result<(Prepared, Prepared), DeleteWithsError> {
+        if withs.is_empty() {
+            return Err(DeleteWithsError::NoWithNamesError);
+        }
+        let mut delete =
+            Prepared::new("DELETE FROM withpackage WHERE versionpin = $1 AND package = ANY($2)");
+        delete.push(vpin_id);
+        delete.push(withs);
+        let mut renumber = Prepared::new(RENUMBER_WITHS);
+        renumber.push(vpin_id);
+        Ok((delete, renumber))
+    }
+
+    // build the statement deleting all of the withs of the versionpin
+    fn prepare_all(vpin_id: IdType) -> Prepared {
+        let mut prepared = Prepared::new("DELETE FROM withpackage WHERE versionpin = $1");
+        prepared.push(vpin_id);
+        prepared
+    }
+}
+
+impl<'a> DeleteWiths<'a> {
+    /// Remove the named withs from a versionpin. The remaining withs
+    /// retain their relative order, and are renumbered from 0 within the
+    /// same transaction.
+    ///
+    /// # Arguments
+    /// * `vpin_id` - The id of the versionpin
+    /// * `withs` - The names of the with packages to remove
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeleteWithsError)
+    pub fn delete(mut self, vpin_id: IdType, withs: Vec<String>) -> Result<Self, DeleteWithsError> {
+        let (delete, renumber) = Self::prepare(vpin_id, withs)?;
+        let results = delete
+            .execute(self.tx().unwrap())
+            .context(TokioPostgresError {
+                msg: "failed to delete withs",
+            })?;
+        renumber
+            .execute(self.tx().unwrap())
+            .context(TokioPostgresError {
+                msg: "failed to renumber withs",
+            })?;
+        self.result_cnt = results;
+        Ok(self)
+    }
+
+    /// Remove all of the withs from a versionpin
+    ///
+    /// # Arguments
+    /// * `vpin_id` - The id of the versionpin
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeleteWithsError)
+    pub fn delete_all(mut self, vpin_id: IdType) -> Result<Self, DeleteWithsError> {
+        self.result_cnt = Self::prepare_all(vpin_id)
+            .execute(self.tx().unwrap())
+            .context(TokioPostgresError {
+                msg: "failed to delete withs",
+            })?;
+        Ok(self)
+    }
+}
+
+impl<'a> DeleteWiths<'a, AsyncTransaction<'a>> {
+    /// Remove the named withs from a versionpin. The remaining withs
+    /// retain their relative order, and are renumbered from 0 within the
+    /// same transaction.
+    ///
+    /// # Arguments
+    /// * `vpin_id` - The id of the versionpin
+    /// * `withs` - The names of the with packages to remove
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeleteWithsError)
+    pub async fn delete(
+        mut self,
+        vpin_id: IdType,
+        withs: Vec<String>,
+    ) -> Result<Self, DeleteWithsError> {
+        let (delete, renumber) = Self::prepare(vpin_id, withs)?;
+        let results =
+            delete
+                .execute_async(self.tx().unwrap())
+                .await
+                .context(TokioPostgresError {
+                    msg: "failed to delete withs",
+                })?;
+        renumber
+            .execute_async(self.tx().unwrap())
+            .await
+            .context(TokioPostgresError {
+                msg: "failed to renumber withs",
+            })?;
+        self.result_cnt = results;
+        Ok(self)
+    }
+
+    /// Remove all of the withs from a versionpin
+    ///
+    /// # Arguments
+    /// * `vpin_id` - The id of the versionpin
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeleteWithsError)
+    pub async fn delete_all(mut self, vpin_id: IdType) -> Result<Self, DeleteWithsError> {
+        self.result_cnt = Self::prepare_all(vpin_id)
+            .execute_async(self.tx().unwrap())
+            .await
+            .context(TokioPostgresError {
+                msg: "failed to delete withs",
+            })?;
+        Ok(self)
+    }
+}
+
+impl<'a> DeleteWiths<'a, MemoryTransaction<'a>> {
+    /// Remove the named withs from a versionpin. The remaining withs
+    /// retain their relative order, and are renumbered from 0 within the
+    /// same transaction.
+    ///
+    /// # Arguments
+    /// * `vpin_id` - The id of the versionpin
+    /// * `withs` - The names of the with packages to remove
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeleteWithsError)
+    pub fn delete(mut self, vpin_id: IdType, withs: Vec<String>) -> Result<Self, DeleteWithsError> {
+        if withs.is_empty() {
+            return Err(DeleteWithsError::NoWithNamesError);
+        }
+        let tx = self.tx().unwrap();
+        let results =
+            tx.remove_withs(|with| with.versionpin == vpin_id && withs.contains(&with.package));
+        tx.renumber_withs(vpin_id);
+        self.result_cnt = results;
+        Ok(self)
+    }
+
+    /// Remove all of the withs from a versionpin
+    ///
+    /// # Arguments
+    /// * `vpin_id` - The id of the versionpin
+    ///
+    /// # Returns Result
+    /// * Ok(Self) | Err(DeleteWithsError)
+    pub fn delete_all(mut self, vpin_id: IdType) -> Result<Self, DeleteWithsError> {
+        self.result_cnt = self
+            .tx()
+            .unwrap()
+            .remove_withs(|with| with.versionpin == vpin_id);
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prepares_delete_and_renumber() {
+        let (delete, renumber) =
+            DeleteWiths::<()>::prepare(3, vec!["houdini".to_string(), "nuke".to_string()]).unwrap();
+        assert_eq!(
+            delete.sql,
+            "DELETE FROM withpackage WHERE versionpin = $1 AND package = ANY($2)"
+        );
+        assert_eq!(
+            format!("{:?}", delete.params),
+            r#"[3, ["houdini", "nuke"]]"#
+        );
+        assert_eq!(renumber.sql, RENUMBER_WITHS);
+        assert_eq!(format!("{:?}", renumber.params), "[3]");
+    }
+
+    #[test]
+    fn requires_with_names() {
+        assert!(matches!(
+            DeleteWiths::<()>::prepare(3, Vec::new()),
+            Err(DeleteWithsError::NoWithNamesError)
+        ));
+    }
+}
@@ -0,0 +1,421 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! Compare package space at two points in time (revisions or dates),
+//! reporting the versionpins which were added, removed or changed between them.
+//!
+//! Both sides are reconstructed from the audit log via `history`, which
+//! records the distribution and the withs of each versionpin, so a change
+//! to the withs alone is reported as a change.
+use crate::db::find_all::versionpins::FindAllVersionPinsRow;
+use crate::db::history::{self, AsOf, HistoryError};
+use crate::resolver::coords_contain;
+use crate::{Coords, Distribution};
+use postgres::Client;
+use snafu::{ResultExt, Snafu};
+use std::collections::BTreeMap;
+use std::fmt;
+use strum_macros::{AsRefStr, Display, EnumString, IntoStaticStr};
+
+/// Error type returned from DiffRevisions
+#[derive(Debug, Snafu)]
+pub enum DiffRevisionsError {
+    /// An error has occured while reconstructing the historical versionpins
+    #[snafu(display("Error reconstructing versionpins as of {:?}: {}", as_of, source))]
+    AsOfError { as_of: AsOf, source: HistoryError },
+    /// The level, role, platform and site filter is invalid
+    #[snafu(display("Invalid filter: {}", source))]
+    InvalidFilterError { source: HistoryError },
+}
+
+pub type DiffRevisionsResult<T, E = DiffRevisionsError> = std::result::Result<T, E>;
+
+/// The manner in which a versionpin differs between the two points in time
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr, Clone,
+)]
+pub enum PinDiffKind {
+    #[strum(serialize = "added", serialize = "Added", to_string = "ADDED")]
+    Added,
+    #[strum(serialize = "removed", serialize = "Removed", to_string = "REMOVED")]
+    Removed,
+    #[strum(serialize = "changed", serialize = "Changed", to_string = "CHANGED")]
+    Changed,
+}
+
+/// A versionpin which differs between the two points in time
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PinDiff {
+    pub package: String,
+    pub coords: Coords,
+    /// The distribution before. None if the versionpin was added
+    pub old: Option<Distribution>,
+    /// The distribution after. None if the versionpin was removed
+    pub new: Option<Distribution>,
+    pub old_withs: Option<Vec<String>>,
+    pub new_withs: Option<Vec<String>>,
+}
+
+impl PinDiff {
+    /// Retrieve the manner in which the versionpin differs
+    pub fn kind(&self) -> PinDiffKind {
+        match (&self.old, &self.new) {
+            (None, _) => PinDiffKind::Added,
+            (_, None) => PinDiffKind::Removed,
+            _ => PinDiffKind::Changed,
+        }
+    }
+}
+
+impl fmt::Display for PinDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn dist(distribution: &Option<Distribution>) -> &str {
+            distribution
+                .as_ref()
+                .map(|d| d.distribution())
+                .unwrap_or("-")
+        }
+        fn withs(withs: &Option<Vec<String>>) -> String {
+            withs.as_ref().map(|w| w.join(", ")).unwrap_or_default()
+        }
+        write!(
+            f,
+            "{} {} {} {} -> {} [{}] -> [{}]",
+            self.kind(),
+            self.package,
+            self.coords,
+            dist(&self.old),
+            dist(&self.new),
+            withs(&self.old_withs),
+            withs(&self.new_withs)
+        )
+    }
+}
+
+/// Compare two sets of versionpins, returning the differences ordered by
+/// package and coords.
+///
+/// # Arguments
+/// * `before` - The versionpins at the earlier point in time
+/// * `after` - The versionpins at the later point in time
+///
+/// # Returns
+/// * Vector of PinDiff
+pub fn diff_versionpins(
+    before: Vec<FindAllVersionPinsRow>,
+    after: Vec<FindAllVersionPinsRow>,
+) -> Vec<PinDiff> {
+    type Side = (Option<Distribution>, Option<Vec<String>>);
+    let mut pins: BTreeMap<(String, Coords), (Side, Side)> = BTreeMap::new();
+    for row in before {
+        let key = (row.distribution.package().to_string(), row.coords);
+        pins.entry(key).or_default().0 = (Some(row.distribution), row.withs);
+    }
+    for row in after {
+        let key = (row.distribution.package().to_string(), row.coords);
+        pins.entry(key).or_default().1 = (Some(row.distribution), row.withs);
+    }
+    pins.into_iter()
+        .filter(|(_, (before, after))| before != after)
+        .map(
+            |((package, coords), ((old, old_withs), (new, new_withs)))| PinDiff {
+                package,
+                coords,
+                old,
+                new,
+                old_withs,
+                new_withs,
+            },
+        )
+        .collect()
+}
+
+/// Responsible for comparing package space at two points in time.
+/// Typically constructed via `PackratDb::diff`.
+pub struct DiffRevisions<'a> {
+    client: &'a mut Client,
+    from: AsOf,
+    to: AsOf,
+    level: Option<&'a str>,
+    role: Option<&'a str>,
+    platform: Option<&'a str>,
+    site: Option<&'a str>,
+}
+
+impl fmt::Debug for DiffRevisions<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DiffRevisions(from:{:?} to:{:?})", self.from, self.to)
+    }
+}
+
+impl<'a> DiffRevisions<'a> {
+    /// new up a DiffRevisions instance
+    ///
+    /// # Arguments
+    /// * `client` - A mutable reference to a postgres::Client
+    /// * `from` - The earlier revision id or `DateTime<Local>`
+    /// * `to` - The later revision id or `DateTime<Local>`
+    pub fn new<F: Into<AsOf>, T: Into<AsOf>>(client: &'a mut Client, from: F, to: T) -> Self {
+        Self {
+            client,
+            from: from.into(),
+            to: to.into(),
+            level: None,
+            role: None,
+            platform: None,
+            site: None,
+        }
+    }
+
+    /// Restrict the diff to versionpins at, or below, the level
+    ///
+    /// # Arguments
+    /// * `level_n` - The level name
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn level(&mut self, level_n: &'a str) -> &mut Self {
+        self.level = Some(level_n);
+        self
+    }
+
+    /// Optionally restrict the diff to versionpins at, or below, the level
+    ///
+    /// # Arguments
+    /// * `level_n` - An Option wrapping a level name
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn level_opt(&mut self, level_n: Option<&'a str>) -> &mut Self {
+        self.level = level_n;
+        self
+    }
+
+    /// Restrict the diff to versionpins at, or below, the role
+    ///
+    /// # Arguments
+    /// * `role_n` - The role name
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn role(&mut self, role_n: &'a str) -> &mut Self {
+        self.role = Some(role_n);
+        self
+    }
+
+    /// Optionally restrict the diff to versionpins at, or below, the role
+    ///
+    /// # Arguments
+    /// * `role_n` - An Option wrapping a role name
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn role_opt(&mut self, role_n: Option<&'a str>) -> &mut Self {
+        self.role = role_n;
+        self
+    }
+
+    /// Restrict the diff to versionpins for the platform
+    ///
+    /// # Arguments
+    /// * `platform_n` - The platform name
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn platform(&mut self, platform_n: &'a str) -> &mut Self {
+        self.platform = Some(platform_n);
+        self
+    }
+
+    /// Optionally restrict the diff to versionpins for the platform
+    ///
+    /// # Arguments
+    /// * `platform_n` - An Option wrapping a platform name
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn platform_opt(&mut self, platform_n: Option<&'a str>) -> &mut Self {
+        self.platform = platform_n;
+        self
+    }
+
+    /// Restrict the diff to versionpins for the site
+    ///
+    /// # Arguments
+    /// * `site_n` - The site name
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn site(&mut self, site_n: &'a str) -> &mut Self {
+        self.site = Some(site_n);
+        self
+    }
+
+    /// Optionally restrict the diff to versionpins for the site
+    ///
+    /// # Arguments
+    /// * `site_n` - An Option wrapping a site name
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn site_opt(&mut self, site_n: Option<&'a str>) -> &mut Self {
+        self.site = site_n;
+        self
+    }
+
+    // retrieve the versionpins as of the supplied point in time, which fall
+    // within the filter
+    fn versionpins(
+        &mut self,
+        as_of: &AsOf,
+        filter: &Coords,
+    ) -> DiffRevisionsResult<Vec<FindAllVersionPinsRow>> {
+        let rows = history::versionpins_as_of(self.client, as_of).context(AsOfError {
+            as_of: as_of.clone(),
+        })?;
+        Ok(rows
+            .into_iter()
+            .filter(|row| coords_contain(filter, &row.coords))
+            .collect())
+    }
+
+    /// Compare the versionpins at the two points in time
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - Vector of PinDiff, ordered by package and coords
+    ///   * `Err` - DiffRevisionsError
+    pub fn query(&mut self) -> DiffRevisionsResult<Vec<PinDiff>> {
+        let filter = history::coords_from_opts(self.level, self.role, self.platform, self.site)
+            .context(InvalidFilterError)?;
+        let from = self.from.clone();
+        let to = self.to.clone();
+        let before = self.versionpins(&from, &filter)?;
+        let after = self.versionpins(&to, &filter)?;
+        Ok(diff_versionpins(before, after))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::find_all::changes::{ChangeAction, ChangeType, FindAllChangesRow};
+    use crate::types::LongIdType;
+    use std::collections::HashMap;
+
+    fn row(dist: &str, level: &str, withs: Option<Vec<&str>>) -> FindAllVersionPinsRow {
+        FindAllVersionPinsRow::new(
+            1,
+            1,
+            1,
+            Distribution::new(dist).unwrap(),
+            Coords::try_from_parts(level, "any", "any", "any").unwrap(),
+            withs.map(|w| w.into_iter().map(String::from).collect()),
+        )
+    }
+
+    #[test]
+    fn identical_pins_have_no_diff() {
+        let diff = diff_versionpins(
+            vec![row("maya-2018.sp3", "facility", None)],
+            vec![row("maya-2018.sp3", "facility", None)],
+        );
+        assert!(diff.is_empty());
+    }
+
+    #[test]
+    fn can_diff_added_removed_and_changed() {
+        let diff = diff_versionpins(
+            vec![
+                row("maya-2018.sp3", "facility", None),
+                row("maya-2019.1", "dev01", None),
+            ],
+            vec![
+                row("maya-2020.1", "facility", None),
+                row("houdini-17.5", "facility", None),
+            ],
+        );
+        let kinds = diff
+            .iter()
+            .map(|d| (d.package.as_str(), d.kind()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                ("houdini", PinDiffKind::Added),
+                ("maya", PinDiffKind::Changed),
+                ("maya", PinDiffKind::Removed),
+            ]
+        );
+        let changed = &diff[1];
+        assert_eq!(
+            changed.old,
+            Some(Distribution::new("maya-2018.sp3").unwrap())
+        );
+        assert_eq!(changed.new, Some(Distribution::new("maya-2020.1").unwrap()));
+    }
+
+    #[test]
+    fn changed_withs_are_a_diff() {
+        let diff = diff_versionpins(
+            vec![row("maya-2018.sp3", "facility", None)],
+            vec![row("maya-2018.sp3", "facility", Some(vec!["mayapipeline"]))],
+        );
+        assert_eq!(diff.len(), 1);
+        assert_eq!(diff[0].kind(), PinDiffKind::Changed);
+        assert_eq!(diff[0].new_withs, Some(vec!["mayapipeline".to_string()]));
+    }
+
+    // the versionpins as of the transaction, replayed from a pin inserted by
+    // the first transaction, whose withs are set by the second
+    fn replayed(transaction_id: LongIdType) -> Vec<FindAllVersionPinsRow> {
+        let coords = Coords::try_from_parts("facility", "any", "any", "any").unwrap();
+        let changes = vec![
+            FindAllChangesRow::new(
+                1,
+                7,
+                1,
+                ChangeAction::Insert,
+                coords.level.clone(),
+                coords.role.clone(),
+                coords.platform.clone(),
+                coords.site.clone(),
+                "maya",
+                ChangeType::Distribution {
+                    old: None,
+                    new: Distribution::new("maya-2018.sp3").unwrap(),
+                },
+            ),
+            FindAllChangesRow::from_withs(
+                2,
+                7,
+                2,
+                coords,
+                "maya",
+                Vec::new(),
+                vec!["mayapipeline".to_string()],
+            ),
+        ];
+        let state = history::replay_changes(
+            changes
+                .into_iter()
+                .filter(|change| change.transaction_id <= transaction_id),
+        );
+        history::assemble(state, &BTreeMap::new(), &HashMap::new())
+    }
+
+    #[test]
+    fn replayed_withs_only_change_is_a_diff() {
+        let diff = diff_versionpins(replayed(1), replayed(2));
+        assert_eq!(diff.len(), 1);
+        assert_eq!(diff[0].kind(), PinDiffKind::Changed);
+        assert_eq!(diff[0].old, diff[0].new);
+        assert_eq!(diff[0].old_withs, None);
+        assert_eq!(diff[0].new_withs, Some(vec!["mayapipeline".to_string()]));
+    }
+}
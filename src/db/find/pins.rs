@@ -1,13 +1,21 @@
 //! NOTICE
 //! Deprecated in favor of pkgcoords
 pub use crate::coords_error::{CoordsError, CoordsResult};
+use crate::db::async_packrat::AsyncClient;
+use crate::db::backend::Backend;
+use crate::db::memory::functions::{like, ltree_matches};
+use crate::db::memory::MemoryStore;
+use crate::db::pool::PackratPool;
+use crate::db::prepared::Prepared;
+use crate::db::query::SelectQuery;
+use crate::db::search_attribute::SearchMode;
 pub use crate::db::search_attribute::{LtreeSearchMode, OrderDirection, SearchAttribute};
+use crate::db::sqlite::{ltree_clause, SqlitePrepared, SqliteStore};
+use crate::io::render::{FieldValue, Record};
 use crate::types::IdType;
 pub use crate::Coords;
 pub use crate::Distribution;
-use log;
-use postgres::types::ToSql;
-use postgres::Client;
+use postgres::{Client, Row};
 use serde::Serialize;
 use snafu::{ResultExt, Snafu};
 use std::fmt;
@@ -41,6 +49,18 @@ pub enum FindPinsError {
         msg: &'static str,
         source: tokio_postgres::error::Error,
     },
+    /// Error from sqlite
+    #[snafu(display("Sqlite Error: {} {}", msg, source))]
+    SqliteError {
+        msg: &'static str,
+        source: rusqlite::Error,
+    },
+    /// Unable to check a connection out of a PackratPool
+    #[snafu(display("Pool Error: {} {}", msg, source))]
+    PoolError {
+        msg: &'static str,
+        source: r2d2::Error,
+    },
 }
 
 /// A row returned from the  FindPins.query
@@ -63,6 +83,21 @@ impl fmt::Display for FindPinsRow {
     }
 }
 
+impl Record for FindPinsRow {
+    fn field_names() -> &'static [&'static str] {
+        &["level", "role", "platform", "site"]
+    }
+
+    fn field_values(&self) -> Vec<FieldValue> {
+        vec![
+            self.level.as_str().into(),
+            self.role.as_str().into(),
+            self.platform.as_str().into(),
+            self.site.as_str().into(),
+        ]
+    }
+}
+
 impl FindPinsRow {
     /// New up a  FindPinsRow instance
     ///
@@ -104,10 +139,7 @@ impl FindPinsRow {
     ) -> FindPinsResult<FindPinsRow> {
         let coords = Coords::try_from_parts(level, role, platform, site).context(
             CoordsTryFromPartsError {
-                coords: format!(
-                    "(level:'{}' role:'{}' platform:'{}' site:'{}')",
-                    level, role, platform, site
-                ),
+                coords: Coords::display_parts(level, role, platform, site),
             },
         )?;
         let Coords {
@@ -139,8 +171,8 @@ impl FindPinsRow {
     }
 }
 /// Responsible for finding a distribution
-pub struct FindPins<'a> {
-    client: &'a mut Client,
+pub struct FindPins<'a, C = Client> {
+    client: &'a mut C,
     role: Option<&'a str>,
     level: Option<&'a str>,
     platform: Option<&'a str>,
@@ -152,7 +184,7 @@ pub struct FindPins<'a> {
     simple: bool,
 }
 
-impl fmt::Debug for FindPins<'_> {
+impl<C> fmt::Debug for FindPins<'_, C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
@@ -161,18 +193,23 @@ impl fmt::Debug for FindPins<'_> {
         )
     }
 }
-// helper function to simplify handing different types of queries
-fn prep_query(extension: &str, op: &str, params_cnt: IdType, is_like: bool) -> String {
-    if is_like {
-        format!(" LIKE ${}", params_cnt)
+
+// helper function to root the supplied coordinate, unless it is a LIKE pattern
+fn process_map(root: &str, value: &str) -> String {
+    if value != root {
+        if !value.contains("%") {
+            format!("{}.{}", root, value)
+        } else {
+            value.to_string()
+        }
     } else {
-        format!("{} {} text2ltree(${})", extension, op, params_cnt)
+        root.to_string()
     }
 }
 
-impl<'a> FindPins<'a> {
+impl<'a, C> FindPins<'a, C> {
     /// new up a FIndAllPins instance.
-    pub fn new(client: &'a mut Client) -> Self {
+    pub fn new(client: &'a mut C) -> Self {
         FindPins {
             client,
             level: None,
@@ -260,175 +297,101 @@ impl<'a> FindPins<'a> {
         self
     }
 
-    fn simple_query(&mut self) -> Result<Vec<FindPinsRow>, FindPinsError> {
-        let query_str = "SELECT DISTINCT 
-                name
-            FROM 
-                role_view ORDER BY name";
-        let mut result = Vec::new();
-        log::info!("SQL\n{}", query_str);
-        for row in self
-            .client
-            .query(query_str, &[])
-            .context(TokioPostgresError {
-                msg: "problem with select from platform_view",
-            })?
-        {
-            let role_name = row.get(0);
-            result.push(FindPinsRow::try_from_parts(
-                role_name, "facility", "any", "any",
-            )?);
+    // the versionpin's path is the left operand, so an ancestor search
+    // matches the versionpins at, or below, the supplied coordinates
+    fn path_search_mode(&self) -> LtreeSearchMode {
+        match self.search_mode {
+            LtreeSearchMode::Ancestor => LtreeSearchMode::Descendant,
+            LtreeSearchMode::Descendant => LtreeSearchMode::Ancestor,
+            LtreeSearchMode::Exact => LtreeSearchMode::Exact,
         }
-        Ok(result)
     }
 
-    /// Initiate the query based on the current state of self and return a
-    /// vector of results
-    pub fn query(&mut self) -> Result<Vec<FindPinsRow>, FindPinsError> {
-        fn process_map(root: &str, value: &str) -> String {
-            if value != root {
-                if !value.contains("%") {
-                    format!("{}.{}", root, value)
-                } else {
-                    value.to_string()
-                }
-            } else {
-                root.to_string()
-            }
+    // order and limit the distinct (role, level, platform, site) pins
+    fn arrange(
+        &self,
+        mut pins: Vec<(String, String, String, String)>,
+    ) -> Result<Vec<FindPinsRow>, FindPinsError> {
+        if let Some(ref orderby) = self.order_by {
+            pins.sort_by_cached_key(|(role, level, platform, site)| {
+                orderby
+                    .iter()
+                    .map(|attr| match attr {
+                        SearchAttribute::Level => level.clone(),
+                        SearchAttribute::Role => role.clone(),
+                        SearchAttribute::Platform => platform.clone(),
+                        SearchAttribute::Site => site.clone(),
+                        _ => String::new(),
+                    })
+                    .collect::<Vec<_>>()
+            });
         }
-        if self.simple {
-            return self.simple_query();
+        if let Some(OrderDirection::Desc) = self.order_direction {
+            pins.reverse();
         }
-        let level = self
-            .level
-            .map_or("facility".to_string(), |x| process_map("facility", x));
-        let role = self
-            .role
-            .map_or("any".to_string(), |x| process_map("any", x));
-        let platform = self
-            .platform
-            .map_or("any".to_string(), |x| process_map("any", x));
-        let site = self
-            .site
-            .map_or("any".to_string(), |x| process_map("any", x));
-        let mut result = Vec::new();
-        // build up a vector of parameters for the prepared search
-        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
-
-        // Parameter Count used to supply prepared statement
-        // with correct index. It is 1's based.
-        let mut params_cnt = 1;
+        if let Some(limit) = self.limit {
+            pins.truncate(limit as usize);
+        }
+        pins.iter()
+            .map(|(role, level, platform, site)| {
+                FindPinsRow::try_from_parts(role, level, platform, site)
+            })
+            .collect()
+    }
 
-        // used to join optional queries. will be set to " AND "
-        // after first optional parameter is used
-        let mut join_b = false;
+    /// The select statement issued by `query` against postgres, for
+    /// inspection via `SelectQuery::to_sql`
+    pub fn select(&self) -> SelectQuery {
+        if self.simple {
+            let mut query = SelectQuery::new(["name"], "role_view");
+            query.distinct().order_by(["name"]);
+            return query;
+        }
+        let mut query = SelectQuery::new(["role", "level", "platform", "site"], "versionpin_view");
+        query.distinct();
         // The op is the operator symbol used in the search
         let op = self.search_mode.to_symbol();
-        let mut query_str = "SELECT DISTINCT 
-                role,
-                level,
-                platform,
-                site 
-            FROM 
-                versionpin_view"
-            .to_string();
-
-        // build up query to handle optional parameters
-        if self.level.is_some()
-            || self.role.is_some()
-            || self.platform.is_some()
-            || self.site.is_some()
-        {
-            fn prep_coord(
-                query_str: &mut String,
-                coord: &str,
-                coord_name: &str,
-                join_b: &mut bool,
-                op: &str,
-                params_cnt: &mut IdType,
-            ) {
-                let is_like = coord.contains("%");
-                let join = if *join_b { " AND " } else { "" };
-                *query_str = format!(
-                    "{}{} {}{}",
-                    query_str,
-                    join,
-                    coord_name,
-                    prep_query("_path", op, *params_cnt, is_like)
-                );
-                *join_b = true;
-                *params_cnt = *params_cnt + 1;
-            }
-            query_str.push_str(" WHERE ");
-            if self.level.is_some() {
-                // just including join here in case i reorder or add an additional
-                // item above
-                //
-                prep_coord(
-                    &mut query_str,
-                    &level,
-                    "level",
-                    &mut join_b,
-                    &op,
-                    &mut params_cnt,
-                );
-                params.push(&level);
-            }
-            if self.role.is_some() {
-                prep_coord(
-                    &mut query_str,
-                    &role,
-                    "role",
-                    &mut join_b,
-                    &op,
-                    &mut params_cnt,
-                );
-                params.push(&role);
-            }
-            if self.platform.is_some() {
-                prep_coord(
-                    &mut query_str,
-                    &platform,
-                    "platform",
-                    &mut join_b,
-                    &op,
-                    &mut params_cnt,
-                );
-                params.push(&platform);
-            }
-            if self.site.is_some() {
-                prep_coord(
-                    &mut query_str,
-                    &site,
-                    "site",
-                    &mut join_b,
-                    &op,
-                    &mut params_cnt,
-                );
-                params.push(&site);
+        for (coord, root, value) in &[
+            ("level", "facility", self.level),
+            ("role", "any", self.role),
+            ("platform", "any", self.platform),
+            ("site", "any", self.site),
+        ] {
+            if let Some(value) = value {
+                let value = process_map(root, value);
+                if value.contains('%') {
+                    query.filter(coord, &SearchMode::Like, value);
+                } else {
+                    let cnt = query.bind(value);
+                    query.filter_sql(format!("{}_path {} text2ltree(${})", coord, op, cnt));
+                }
             }
         }
         if let Some(ref orderby) = self.order_by {
-            let orderby = orderby.iter().map(|x| match_attrib(x)).collect::<Vec<_>>();
-            query_str = format!("{} ORDER BY {}", query_str, orderby.join(","));
-        }
-        if let Some(ref orderdir) = self.order_direction {
-            query_str.push_str(&[" ", orderdir.as_ref(), " "].concat());
+            query
+                .order_by(orderby.iter().map(match_attrib))
+                .order_direction(self.order_direction);
         }
+        query.limit(self.limit);
+        query
+    }
 
-        if let Some(limit) = self.limit {
-            query_str.push_str(format!(" LIMIT {}", limit).as_str());
-        }
-        let qstr = query_str.as_str();
-        log::info!("SQL {}", qstr);
-        log::info!("Prepared Arguments: {:?}", &params);
-        for row in self
-            .client
-            .query(qstr, &params[..])
-            .context(TokioPostgresError {
-                msg: "problem with select from platform_view",
-            })?
-        {
+    // build the query shared by the blocking and async clients
+    fn prepare(&self) -> Prepared {
+        self.select().into()
+    }
+
+    // convert the rows returned by the query
+    fn rows(&self, rows: Vec<Row>) -> Result<Vec<FindPinsRow>, FindPinsError> {
+        let mut result = Vec::new();
+        for row in rows {
+            if self.simple {
+                let role_name = row.get(0);
+                result.push(FindPinsRow::try_from_parts(
+                    role_name, "facility", "any", "any",
+                )?);
+                continue;
+            }
             let role_name: &str = row.get(0);
             let level_name: &str = row.get(1);
             let platform_name: &str = row.get(2);
@@ -443,3 +406,227 @@ impl<'a> FindPins<'a> {
         Ok(result)
     }
 }
+
+impl<'a, C: Backend> FindPins<'a, C> {
+    /// Initiate the query based on the current state of self and return a
+    /// vector of results
+    pub fn query(&mut self) -> Result<Vec<FindPinsRow>, FindPinsError> {
+        C::find_pins(self)
+    }
+}
+
+impl<'a> FindPins<'a, Client> {
+    // execute the query against postgres
+    pub(crate) fn query_postgres(&mut self) -> Result<Vec<FindPinsRow>, FindPinsError> {
+        let rows = self
+            .prepare()
+            .query(self.client)
+            .context(TokioPostgresError {
+                msg: "problem with select from platform_view",
+            })?;
+        self.rows(rows)
+    }
+}
+
+impl<'a> FindPins<'a, PackratPool> {
+    // execute the query against a connection checked out of the pool for
+    // the duration of the query
+    pub(crate) fn query_pool(&mut self) -> Result<Vec<FindPinsRow>, FindPinsError> {
+        let mut conn = self.client.connection().context(PoolError {
+            msg: "unable to check out connection",
+        })?;
+        FindPins {
+            client: &mut *conn,
+            role: self.role,
+            level: self.level,
+            platform: self.platform,
+            site: self.site,
+            order_by: self.order_by.clone(),
+            order_direction: self.order_direction,
+            limit: self.limit,
+            search_mode: self.search_mode,
+            simple: self.simple,
+        }
+        .query_postgres()
+    }
+}
+
+impl<'a> FindPins<'a, MemoryStore> {
+    // execute the query against the tables of a MemoryStore
+    pub(crate) fn query_memory(&mut self) -> Result<Vec<FindPinsRow>, FindPinsError> {
+        let tables = self.client.tables();
+        if self.simple {
+            let mut names = tables
+                .role_view()
+                .into_iter()
+                .map(|role| role.name)
+                .collect::<Vec<_>>();
+            names.sort();
+            names.dedup();
+            return names
+                .iter()
+                .map(|name| FindPinsRow::try_from_parts(name, "facility", "any", "any"))
+                .collect();
+        }
+        let mode = self.path_search_mode();
+        let coord_matches = |name: &str, path: &str, root: &str, value: Option<&str>| match value {
+            Some(value) => {
+                let value = process_map(root, value);
+                if value.contains("%") {
+                    like(name, &value)
+                } else {
+                    ltree_matches(path, &value, &mode)
+                }
+            }
+            None => true,
+        };
+        let mut pins = Vec::new();
+        for pin in tables.versionpin_view() {
+            if coord_matches(&pin.level, &pin.level_path, "facility", self.level)
+                && coord_matches(&pin.role, &pin.role_path, "any", self.role)
+                && coord_matches(&pin.platform, &pin.platform_path, "any", self.platform)
+                && coord_matches(&pin.site, &pin.site_path, "any", self.site)
+            {
+                let pin = (pin.role, pin.level, pin.platform, pin.site);
+                if !pins.contains(&pin) {
+                    pins.push(pin);
+                }
+            }
+        }
+        self.arrange(pins)
+    }
+}
+
+impl<'a> FindPins<'a, SqliteStore> {
+    // execute the query against a SqliteStore
+    pub(crate) fn query_sqlite(&mut self) -> Result<Vec<FindPinsRow>, FindPinsError> {
+        if self.simple {
+            let names = SqlitePrepared::new("SELECT DISTINCT name FROM role_view ORDER BY name")
+                .query(self.client, |row| row.get::<_, String>(0))
+                .context(SqliteError {
+                    msg: "problem with select from role_view",
+                })?;
+            return names
+                .iter()
+                .map(|name| FindPinsRow::try_from_parts(name, "facility", "any", "any"))
+                .collect();
+        }
+        let mode = self.path_search_mode();
+        let mut prepared = SqlitePrepared::new("");
+        let mut clauses = Vec::new();
+        for (name, path, root, value) in &[
+            ("level", "level_path", "facility", self.level),
+            ("role", "role_path", "any", self.role),
+            ("platform", "platform_path", "any", self.platform),
+            ("site", "site_path", "any", self.site),
+        ] {
+            if let Some(value) = value {
+                let value = process_map(root, value);
+                let like = value.contains('%');
+                let idx = prepared.push(value);
+                if like {
+                    clauses.push(format!("{} LIKE ?{} ESCAPE '\\'", name, idx));
+                } else {
+                    clauses.push(ltree_clause(path, idx, &mode));
+                }
+            }
+        }
+        prepared.sql = "SELECT role, level, platform, site FROM versionpin_view".to_string();
+        if !clauses.is_empty() {
+            prepared.sql = format!("{} WHERE {}", prepared.sql, clauses.join(" AND "));
+        }
+        prepared.sql = format!("{} ORDER BY versionpin_id", prepared.sql);
+        let rows = prepared
+            .query(self.client, |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .context(SqliteError {
+                msg: "problem with select from versionpin_view",
+            })?;
+        let mut pins = Vec::new();
+        for pin in rows {
+            if !pins.contains(&pin) {
+                pins.push(pin);
+            }
+        }
+        self.arrange(pins)
+    }
+}
+
+impl<'a> FindPins<'a, AsyncClient> {
+    /// Initiate the query based on the current state of self and return a
+    /// vector of results
+    pub async fn query(&mut self) -> Result<Vec<FindPinsRow>, FindPinsError> {
+        let rows = self
+            .prepare()
+            .query_async(self.client)
+            .await
+            .context(TokioPostgresError {
+                msg: "problem with select from platform_view",
+            })?;
+        self.rows(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_query_string_with_coords() {
+        let mut store = MemoryStore::new();
+        let mut fp = FindPins::new(&mut store);
+        fp.level("dev01").role("fx%");
+        let query = fp.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT DISTINCT role, level, platform, site FROM versionpin_view \
+             WHERE level_path <@ text2ltree($1) AND role LIKE $2"
+        );
+        assert_eq!(ps, &["facility.dev01", "fx%"]);
+    }
+    #[test]
+    fn get_query_string_with_search_mode() {
+        let mut store = MemoryStore::new();
+        let mut fp = FindPins::new(&mut store);
+        fp.site("portland").search_mode(LtreeSearchMode::Descendant);
+        let query = fp.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT DISTINCT role, level, platform, site FROM versionpin_view \
+             WHERE site_path @> text2ltree($1)"
+        );
+        assert_eq!(ps, &["any.portland"]);
+    }
+    #[test]
+    fn get_query_string_with_order_and_limit() {
+        let mut store = MemoryStore::new();
+        let mut fp = FindPins::new(&mut store);
+        fp.order_by(vec![SearchAttribute::Package, SearchAttribute::Level])
+            .order_direction(OrderDirection::Desc)
+            .limit(3);
+        let query = fp.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT DISTINCT role, level, platform, site FROM versionpin_view \
+             ORDER BY distribution DESC, level DESC LIMIT 3"
+        );
+        assert!(ps.is_empty());
+    }
+    #[test]
+    fn get_query_string_simple() {
+        let mut store = MemoryStore::new();
+        let mut fp = FindPins::new(&mut store);
+        fp.level("dev01").simple(true);
+        let query = fp.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT DISTINCT name FROM role_view ORDER BY name"
+        );
+        assert!(ps.is_empty());
+    }
+}
@@ -1,10 +1,18 @@
 use super::versionpins::{FindVersionPinsError, FindVersionPinsRow};
 use crate::coords_error::CoordsError;
+use crate::db::async_packrat::AsyncClient;
+use crate::db::backend::Backend;
+use crate::db::find_all::versionpins::FindAllVersionPinsRow;
+use crate::db::history::{self, AsOf, HistoryError};
+use crate::db::memory::views::CoordPaths;
+use crate::db::memory::MemoryStore;
+use crate::db::pool::PackratPool;
+use crate::db::prepared::Prepared;
+use crate::db::query::SelectQuery;
+use crate::db::sqlite::SqliteStore;
 use crate::types::IdType;
 pub use crate::Distribution;
-use log;
-use postgres::types::ToSql;
-use postgres::Client;
+use postgres::{Client, Row};
 use snafu::{ResultExt, Snafu};
 
 /// Error type returned from FindVersionPinsError
@@ -22,16 +30,31 @@ pub enum FindVersionPinError {
         msg: &'static str,
         source: tokio_postgres::error::Error,
     },
+    /// Error from sqlite
+    #[snafu(display("Sqlite Error: {} {}", msg, source))]
+    SqliteError {
+        msg: &'static str,
+        source: rusqlite::Error,
+    },
+    /// Unable to check a connection out of a PackratPool
+    #[snafu(display("Pool Error: {} {}", msg, source))]
+    PoolError {
+        msg: &'static str,
+        source: r2d2::Error,
+    },
     /// An error has occured while trying to instantiate a FindVersionPinsRow
     #[snafu(display("Error Constructing FindVersionPinsRow {}", source))]
     FindVersionPinsRowError { source: FindVersionPinsError },
+    /// An error has occured while reconstructing the historical versionpins
+    #[snafu(display("Error reconstructing historical versionpins: {}", source))]
+    AsOfError { source: HistoryError },
 }
 
 /// Contains the parameters used to search for the distribution and its
 /// with distributions.
-pub struct FindVersionPin<'a> {
+pub struct FindVersionPin<'a, C = Client> {
     /// The database client which is responsible for performing the query
-    client: &'a mut Client,
+    client: &'a mut C,
     /// The name of the package we are interested in searching for
     package: &'a str,
     /// The optional level we wish to start our search at
@@ -42,20 +65,22 @@ pub struct FindVersionPin<'a> {
     platform: Option<&'a str>,
     /// The optional site (eg portland) we wish to start our search at
     site: Option<&'a str>,
+    /// The optional point in time we wish to resolve at
+    as_of: Option<AsOf>,
 }
 
-impl<'a> FindVersionPin<'a> {
+impl<'a, C> FindVersionPin<'a, C> {
     /// New up a FindVersionPin instance given a client and package name
     ///
     /// # Arguments
     ///
-    /// * `client` - A mutable reference to a Client instance
+    /// * `client` - A mutable reference to a Client or AsyncClient instance
     /// * `package` - The name of the package whose distribution we are interested in
     ///
     /// # Returns
     ///
     /// * FindVersionPin instance
-    pub fn new(client: &'a mut Client, package: &'a str) -> Self {
+    pub fn new(client: &'a mut C, package: &'a str) -> Self {
         FindVersionPin {
             client,
             package,
@@ -63,6 +88,7 @@ impl<'a> FindVersionPin<'a> {
             role: None,
             platform: None,
             site: None,
+            as_of: None,
         }
     }
 
@@ -176,53 +202,68 @@ impl<'a> FindVersionPin<'a> {
         self
     }
 
-    /// Execute the db query searching for the closest distribution to the
-    /// provided (or default) package coordinates (package name, level, role, platform, site)
-    /// and returning a `FindVersionPinsRow` instance if successful, which also provides
-    /// the closest matching distributions for all of the with packages, making the
-    /// return value suitable for evaluating contexts
+    /// Resolve against the versionpins as they stood at an earlier revision
+    /// or date, reconstructed from the audit log, rather than the live tables.
     ///
     /// # Arguments
     ///
-    /// * None
+    /// * `as_of` - A revision id or `DateTime<Local>`
     ///
     /// # Returns
     ///
-    /// * Result
-    ///   * `Ok`  - `FindVersionPinsRow` instance
-    ///   * `Err` - `FindVersionPinError` instance
-    pub fn query(&mut self) -> Result<FindVersionPinsRow, FindVersionPinError> {
-        let query_str = "SELECT 
-            versionpin_id, 
-            distribution, 
-            level_name, 
-            role_name, 
-            site_name, 
-            platform_name,
-            withs
-        FROM find_distribution_and_withs(
-            $1, 
-            role => $2, 
-            platform => $3, 
-            level=>$4, 
-            site => $5)";
-        let level = self.level.unwrap_or("facility");
-        let role = self.role.unwrap_or("any");
-        let platform = self.platform.unwrap_or("any");
-        let site = self.site.unwrap_or("any");
-
-        let prepared_args: &[&(dyn ToSql + std::marker::Sync)] =
-            &[&self.package, &role, &platform, &level, &site];
-        log::info!("SQL\n{}", query_str);
-        log::info!("Arguments\n{:?}", prepared_args);
-        let row = self
-            .client
-            .query(query_str, prepared_args)
-            .context(TokioPostgresError {
-                msg: "problem with select from find_distribution_and_withs",
-            })?
-            .pop()
-            .ok_or(FindVersionPinError::NoQueryResults)?;
+    /// * A mutable reference to Self, per the builder pattern
+    pub fn as_of<T: Into<AsOf>>(&mut self, as_of: T) -> &mut Self {
+        self.as_of = Some(as_of.into());
+        self
+    }
+
+    /// Optionally resolve against the versionpins as they stood at an earlier
+    /// revision or date.
+    ///
+    /// # Arguments
+    ///
+    /// * `as_of` - An Option wrapping an AsOf instance
+    ///
+    /// # Returns
+    ///
+    /// * A mutable reference to Self, per the builder pattern
+    pub fn as_of_opt(&mut self, as_of: Option<AsOf>) -> &mut Self {
+        self.as_of = as_of;
+        self
+    }
+
+    /// The select statement issued by `query` against postgres, for
+    /// inspection via `SelectQuery::to_sql`
+    pub fn select(&self) -> SelectQuery {
+        let mut query = SelectQuery::function(
+            [
+                "versionpin_id",
+                "distribution",
+                "level_name",
+                "role_name",
+                "site_name",
+                "platform_name",
+                "withs",
+            ],
+            "find_distribution_and_withs",
+        );
+        query
+            .arg(self.package)
+            .named_arg("role", self.role.unwrap_or("any"))
+            .named_arg("platform", self.platform.unwrap_or("any"))
+            .named_arg("level", self.level.unwrap_or("facility"))
+            .named_arg("site", self.site.unwrap_or("any"));
+        query
+    }
+
+    // build the query shared by the blocking and async clients
+    fn prepare(&self) -> Prepared {
+        self.select().into()
+    }
+
+    // convert the last row returned by the query
+    fn row(mut rows: Vec<Row>) -> Result<FindVersionPinsRow, FindVersionPinError> {
+        let row = rows.pop().ok_or(FindVersionPinError::NoQueryResults)?;
         let id: IdType = row.get(0);
         let distribution: &str = row.get(1);
         let level_name: &str = row.get(2);
@@ -241,4 +282,198 @@ impl<'a> FindVersionPin<'a> {
         )
         .context(FindVersionPinsRowError)
     }
+
+    // resolve against the versionpins reconstructed from the audit log
+    fn resolve_as_of(
+        &self,
+        rows: &[FindAllVersionPinsRow],
+    ) -> Result<FindVersionPinsRow, FindVersionPinError> {
+        let coords = history::coords_from_opts(self.level, self.role, self.platform, self.site)
+            .context(AsOfError)?;
+        let row = history::resolve(rows, self.package, &coords)
+            .ok_or(FindVersionPinError::NoQueryResults)?;
+        Ok(FindVersionPinsRow::new(
+            row.versionpin_id,
+            row.distribution.clone(),
+            row.coords.clone(),
+            row.withs.clone(),
+        ))
+    }
+}
+
+impl<'a, C: Backend> FindVersionPin<'a, C> {
+    /// Execute the db query searching for the closest distribution to the
+    /// provided (or default) package coordinates (package name, level, role, platform, site)
+    /// and returning a `FindVersionPinsRow` instance if successful, which also provides
+    /// the closest matching distributions for all of the with packages, making the
+    /// return value suitable for evaluating contexts
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    /// # Returns
+    ///
+    /// * Result
+    ///   * `Ok`  - `FindVersionPinsRow` instance
+    ///   * `Err` - `FindVersionPinError` instance
+    pub fn query(&mut self) -> Result<FindVersionPinsRow, FindVersionPinError> {
+        C::find_versionpin(self)
+    }
+}
+
+impl<'a> FindVersionPin<'a, Client> {
+    // execute the query against postgres
+    pub(crate) fn query_postgres(&mut self) -> Result<FindVersionPinsRow, FindVersionPinError> {
+        if let Some(as_of) = self.as_of.as_ref() {
+            let rows = history::versionpins_as_of(self.client, as_of).context(AsOfError)?;
+            return self.resolve_as_of(&rows);
+        }
+        let rows = self
+            .prepare()
+            .query(self.client)
+            .context(TokioPostgresError {
+                msg: "problem with select from find_distribution_and_withs",
+            })?;
+        Self::row(rows)
+    }
+}
+
+impl<'a> FindVersionPin<'a, PackratPool> {
+    // execute the query against a connection checked out of the pool for
+    // the duration of the query
+    pub(crate) fn query_pool(&mut self) -> Result<FindVersionPinsRow, FindVersionPinError> {
+        let mut conn = self.client.connection().context(PoolError {
+            msg: "unable to check out connection",
+        })?;
+        FindVersionPin {
+            client: &mut *conn,
+            package: self.package,
+            level: self.level,
+            role: self.role,
+            platform: self.platform,
+            site: self.site,
+            as_of: self.as_of.clone(),
+        }
+        .query_postgres()
+    }
+}
+
+impl<'a> FindVersionPin<'a, MemoryStore> {
+    // execute the query against the tables of a MemoryStore
+    pub(crate) fn query_memory(&mut self) -> Result<FindVersionPinsRow, FindVersionPinError> {
+        if let Some(as_of) = self.as_of.as_ref() {
+            let rows = history::versionpins_as_of_memory(self.client, as_of).context(AsOfError)?;
+            return self.resolve_as_of(&rows);
+        }
+        let paths = CoordPaths::from_names(self.level, self.role, self.platform, self.site);
+        let pin = self
+            .client
+            .tables()
+            .find_distribution_and_withs(self.package, &paths)
+            .ok_or(FindVersionPinError::NoQueryResults)?;
+        FindVersionPinsRow::try_from_parts(
+            pin.versionpin_id,
+            &pin.distribution,
+            &pin.level,
+            &pin.role,
+            &pin.platform,
+            &pin.site,
+            Some(pin.withs),
+        )
+        .context(FindVersionPinsRowError)
+    }
+}
+
+impl<'a> FindVersionPin<'a, SqliteStore> {
+    // execute the query against a SqliteStore
+    pub(crate) fn query_sqlite(&mut self) -> Result<FindVersionPinsRow, FindVersionPinError> {
+        if let Some(as_of) = self.as_of.as_ref() {
+            let rows = history::versionpins_as_of_sqlite(self.client, as_of).context(AsOfError)?;
+            return self.resolve_as_of(&rows);
+        }
+        let paths = CoordPaths::from_names(self.level, self.role, self.platform, self.site);
+        let pin = self
+            .client
+            .find_distribution_and_withs(self.package, &paths)
+            .context(SqliteError {
+                msg: "problem with select from find_distribution_and_withs",
+            })?
+            .ok_or(FindVersionPinError::NoQueryResults)?;
+        FindVersionPinsRow::try_from_parts(
+            pin.versionpin_id,
+            &pin.distribution,
+            &pin.level,
+            &pin.role,
+            &pin.platform,
+            &pin.site,
+            Some(pin.withs),
+        )
+        .context(FindVersionPinsRowError)
+    }
+}
+
+impl<'a> FindVersionPin<'a, AsyncClient> {
+    /// Execute the db query searching for the closest distribution to the
+    /// provided (or default) package coordinates (package name, level, role, platform, site)
+    /// and returning a `FindVersionPinsRow` instance if successful, which also provides
+    /// the closest matching distributions for all of the with packages, making the
+    /// return value suitable for evaluating contexts
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    /// # Returns
+    ///
+    /// * Result
+    ///   * `Ok`  - `FindVersionPinsRow` instance
+    ///   * `Err` - `FindVersionPinError` instance
+    pub async fn query(&mut self) -> Result<FindVersionPinsRow, FindVersionPinError> {
+        if let Some(as_of) = self.as_of.as_ref() {
+            let rows = history::versionpins_as_of_async(self.client, as_of)
+                .await
+                .context(AsOfError)?;
+            return self.resolve_as_of(&rows);
+        }
+        let rows = self
+            .prepare()
+            .query_async(self.client)
+            .await
+            .context(TokioPostgresError {
+                msg: "problem with select from find_distribution_and_withs",
+            })?;
+        Self::row(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_query_string_default_works() {
+        let mut store = MemoryStore::new();
+        let fv = FindVersionPin::new(&mut store, "maya");
+        let query = fv.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT versionpin_id, distribution, level_name, role_name, site_name, platform_name, withs \
+             FROM find_distribution_and_withs($1, role => $2, platform => $3, level => $4, site => $5)"
+        );
+        assert_eq!(ps, &["maya", "any", "any", "facility", "any"]);
+    }
+    #[test]
+    fn get_query_string_with_coords() {
+        let mut store = MemoryStore::new();
+        let mut fv = FindVersionPin::new(&mut store, "maya");
+        fv.level("dev01")
+            .role("model")
+            .platform("cent7_64")
+            .site("portland");
+        let query = fv.select();
+        let (_, ps) = query.to_sql();
+        assert_eq!(ps, &["maya", "model", "cent7_64", "dev01", "portland"]);
+    }
 }
@@ -1,11 +1,21 @@
 pub use crate::coords_error::{CoordsError, CoordsResult};
+use crate::db::async_packrat::AsyncClient;
+use crate::db::backend::Backend;
+use crate::db::find_all::versionpins::FindAllVersionPinsRow;
+use crate::db::history;
+pub use crate::db::history::AsOf;
+use crate::db::memory::views::CoordPaths;
+use crate::db::memory::MemoryStore;
+use crate::db::pool::PackratPool;
+use crate::db::prepared::Prepared;
+use crate::db::query::SelectQuery;
 pub use crate::db::search_attribute::{LtreeSearchMode, OrderDirection, SearchAttribute};
+use crate::db::sqlite::SqliteStore;
+use crate::io::render::{FieldValue, Record};
 use crate::types::IdType;
 pub use crate::Coords;
 pub use crate::Distribution;
-use log;
-use postgres::types::ToSql;
-use postgres::Client;
+use postgres::{Client, Row};
 use serde::Serialize;
 use snafu::{ResultExt, Snafu};
 use std::fmt;
@@ -48,6 +58,36 @@ impl fmt::Display for FindVersionPinsRow {
     }
 }
 
+impl Record for FindVersionPinsRow {
+    fn field_names() -> &'static [&'static str] {
+        &[
+            "versionpin_id",
+            "distribution",
+            "package",
+            "version",
+            "level",
+            "role",
+            "platform",
+            "site",
+            "withs",
+        ]
+    }
+
+    fn field_values(&self) -> Vec<FieldValue> {
+        vec![
+            self.versionpin_id.into(),
+            self.distribution.distribution().into(),
+            self.distribution.package().into(),
+            self.distribution.version().into(),
+            self.coords.level().to_string().into(),
+            self.coords.role().to_string().into(),
+            self.coords.platform().to_string().into(),
+            self.coords.site().to_string().into(),
+            self.withs.clone().into(),
+        ]
+    }
+}
+
 impl FindVersionPinsRow {
     /// New up a FindVersionPinsRow instance
     ///
@@ -87,10 +127,7 @@ impl FindVersionPinsRow {
 
         let coords = Coords::try_from_parts(level, role, platform, site).context(
             CoordsTryFromPartsError {
-                coords: format!(
-                    "(level:'{}' role:'{}' platform:'{}' site:'{}')",
-                    level, role, platform, site
-                ),
+                coords: Coords::display_parts(level, role, platform, site),
             },
         )?;
 
@@ -113,8 +150,8 @@ impl FindVersionPinsRow {
     }
 }
 /// Responsible for finding a distribution
-pub struct FindVersionPins<'a> {
-    client: &'a mut Client,
+pub struct FindVersionPins<'a, C = Client> {
+    client: &'a mut C,
     package: &'a str,
     level: Option<&'a str>,
     role: Option<&'a str>,
@@ -122,10 +159,11 @@ pub struct FindVersionPins<'a> {
     site: Option<&'a str>,
     order_by: Option<Vec<SearchAttribute>>,
     order_direction: Option<OrderDirection>,
+    as_of: Option<AsOf>,
 }
 
-impl<'a> FindVersionPins<'a> {
-    pub fn new(client: &'a mut Client, package: &'a str) -> Self {
+impl<'a, C> FindVersionPins<'a, C> {
+    pub fn new(client: &'a mut C, package: &'a str) -> Self {
         FindVersionPins {
             client,
             package,
@@ -135,6 +173,7 @@ impl<'a> FindVersionPins<'a> {
             site: None,
             order_by: None,
             order_direction: None,
+            as_of: None,
         }
     }
 
@@ -187,46 +226,56 @@ impl<'a> FindVersionPins<'a> {
         self.order_direction = Some(direction);
         self
     }
-    pub fn query(&mut self) -> Result<Vec<FindVersionPinsRow>, Box<dyn std::error::Error>> {
-        let level = self.level.unwrap_or("facility");
-        let role = self.role.unwrap_or("any");
-        let platform = self.platform.unwrap_or("any");
-        let site = self.site.unwrap_or("any");
-        let mut result = Vec::new();
-        let prepared_args: &[&(dyn ToSql + std::marker::Sync)] =
-            &[&self.package, &role, &platform, &level, &site];
-        let mut query_str = "SELECT versionpin_id, 
-                        distribution, 
-                        level_name, 
-                        role_name, 
-                        site_name, 
-                        platform_name,
-                        withs
-                    FROM search_distributions(
-                        $1, 
-                        role => $2, 
-                        platform => $3, 
-                        level=>$4, 
-                        site => $5)"
-            .to_string();
-
-        let direction = match self.order_direction {
-            Some(ref dir) => dir.as_ref(),
-            None => "ASC",
-        };
 
+    /// Search the versionpins as they stood at a revision id or `DateTime<Local>`
+    pub fn as_of<T: Into<AsOf>>(&mut self, as_of: T) -> &mut Self {
+        self.as_of = Some(as_of.into());
+        self
+    }
+
+    pub fn as_of_opt(&mut self, as_of: Option<AsOf>) -> &mut Self {
+        self.as_of = as_of;
+        self
+    }
+
+    /// The select statement issued by `query` against postgres, for
+    /// inspection via `SelectQuery::to_sql`
+    pub fn select(&self) -> SelectQuery {
+        let mut query = SelectQuery::function(
+            [
+                "versionpin_id",
+                "distribution",
+                "level_name",
+                "role_name",
+                "site_name",
+                "platform_name",
+                "withs",
+            ],
+            "search_distributions",
+        );
+        query
+            .arg(self.package)
+            .named_arg("role", self.role.unwrap_or("any"))
+            .named_arg("platform", self.platform.unwrap_or("any"))
+            .named_arg("level", self.level.unwrap_or("facility"))
+            .named_arg("site", self.site.unwrap_or("any"));
         if let Some(ref orderby) = self.order_by {
-            if orderby.len() > 0 {
-                let orderby = orderby
-                    .iter()
-                    .map(|x| format!("{} {}", x.as_ref(), direction))
-                    .collect::<Vec<_>>();
-                query_str = format!("{} ORDER BY {}", query_str, orderby.join(","));
-            }
+            query
+                .order_by(orderby)
+                .order_direction(Some(self.order_direction.unwrap_or(OrderDirection::Asc)));
         }
-        log::info!("SQL\n{}", query_str);
-        log::info!("Arguments\n{:?}", prepared_args);
-        for row in self.client.query(query_str.as_str(), prepared_args)? {
+        query
+    }
+
+    // build the query shared by the blocking and async clients
+    fn prepare(&self) -> Prepared {
+        self.select().into()
+    }
+
+    // convert the rows returned by the query
+    fn rows(rows: Vec<Row>) -> Result<Vec<FindVersionPinsRow>, Box<dyn std::error::Error>> {
+        let mut result = Vec::new();
+        for row in rows {
             let id: IdType = row.get(0);
             let distribution: &str = row.get(1);
             let level_name: &str = row.get(2);
@@ -246,4 +295,183 @@ impl<'a> FindVersionPins<'a> {
         }
         Ok(result)
     }
+
+    // search the versionpins reconstructed from the audit log
+    fn search_as_of(
+        &self,
+        rows: &[FindAllVersionPinsRow],
+    ) -> Result<Vec<FindVersionPinsRow>, Box<dyn std::error::Error>> {
+        let coords = history::coords_from_opts(self.level, self.role, self.platform, self.site)?;
+        let mut result = history::candidates(rows, self.package, &coords)
+            .into_iter()
+            .map(|row| {
+                FindVersionPinsRow::new(
+                    row.versionpin_id,
+                    row.distribution.clone(),
+                    row.coords.clone(),
+                    row.withs.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        self.order(&mut result);
+        Ok(result)
+    }
+
+    // order rows which were not ordered by the database
+    fn order(&self, rows: &mut [FindVersionPinsRow]) {
+        if let Some(ref orderby) = self.order_by {
+            history::sort_by_attributes(rows, orderby, |row| (&row.distribution, &row.coords));
+            if let Some(OrderDirection::Desc) = self.order_direction {
+                rows.reverse();
+            }
+        }
+    }
+}
+
+impl<'a, C: Backend> FindVersionPins<'a, C> {
+    pub fn query(&mut self) -> Result<Vec<FindVersionPinsRow>, Box<dyn std::error::Error>> {
+        C::find_versionpins(self)
+    }
+}
+
+impl<'a> FindVersionPins<'a, Client> {
+    // execute the query against postgres
+    pub(crate) fn query_postgres(
+        &mut self,
+    ) -> Result<Vec<FindVersionPinsRow>, Box<dyn std::error::Error>> {
+        if let Some(as_of) = self.as_of.as_ref() {
+            let rows = history::versionpins_as_of(self.client, as_of)?;
+            return self.search_as_of(&rows);
+        }
+        let rows = self.prepare().query(self.client)?;
+        Self::rows(rows)
+    }
+}
+
+impl<'a> FindVersionPins<'a, PackratPool> {
+    // execute the query against a connection checked out of the pool for
+    // the duration of the query
+    pub(crate) fn query_pool(
+        &mut self,
+    ) -> Result<Vec<FindVersionPinsRow>, Box<dyn std::error::Error>> {
+        let mut conn = self.client.connection()?;
+        FindVersionPins {
+            client: &mut *conn,
+            package: self.package,
+            level: self.level,
+            role: self.role,
+            platform: self.platform,
+            site: self.site,
+            order_by: self.order_by.clone(),
+            order_direction: self.order_direction,
+            as_of: self.as_of.clone(),
+        }
+        .query_postgres()
+    }
+}
+
+impl<'a> FindVersionPins<'a, MemoryStore> {
+    // execute the query against the tables of a MemoryStore
+    pub(crate) fn query_memory(
+        &mut self,
+    ) -> Result<Vec<FindVersionPinsRow>, Box<dyn std::error::Error>> {
+        if let Some(as_of) = self.as_of.as_ref() {
+            let rows = history::versionpins_as_of_memory(self.client, as_of)?;
+            return self.search_as_of(&rows);
+        }
+        let paths = CoordPaths::from_names(self.level, self.role, self.platform, self.site);
+        let mut result = Vec::new();
+        for pin in self
+            .client
+            .tables()
+            .search_distributions(self.package, &paths)
+        {
+            result.push(FindVersionPinsRow::try_from_parts(
+                pin.versionpin_id,
+                &pin.distribution,
+                &pin.level,
+                &pin.role,
+                &pin.platform,
+                &pin.site,
+                Some(pin.withs),
+            )?);
+        }
+        self.order(&mut result);
+        Ok(result)
+    }
+}
+
+impl<'a> FindVersionPins<'a, SqliteStore> {
+    // execute the query against a SqliteStore
+    pub(crate) fn query_sqlite(
+        &mut self,
+    ) -> Result<Vec<FindVersionPinsRow>, Box<dyn std::error::Error>> {
+        if let Some(as_of) = self.as_of.as_ref() {
+            let rows = history::versionpins_as_of_sqlite(self.client, as_of)?;
+            return self.search_as_of(&rows);
+        }
+        let paths = CoordPaths::from_names(self.level, self.role, self.platform, self.site);
+        let mut result = Vec::new();
+        for pin in self.client.search_distributions(self.package, &paths)? {
+            result.push(FindVersionPinsRow::try_from_parts(
+                pin.versionpin_id,
+                &pin.distribution,
+                &pin.level,
+                &pin.role,
+                &pin.platform,
+                &pin.site,
+                Some(pin.withs),
+            )?);
+        }
+        self.order(&mut result);
+        Ok(result)
+    }
+}
+
+impl<'a> FindVersionPins<'a, AsyncClient> {
+    pub async fn query(&mut self) -> Result<Vec<FindVersionPinsRow>, Box<dyn std::error::Error>> {
+        if let Some(as_of) = self.as_of.as_ref() {
+            let rows = history::versionpins_as_of_async(self.client, as_of).await?;
+            return self.search_as_of(&rows);
+        }
+        let rows = self.prepare().query_async(self.client).await?;
+        Self::rows(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_query_string_default_works() {
+        let mut store = MemoryStore::new();
+        let fv = FindVersionPins::new(&mut store, "maya");
+        let query = fv.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT versionpin_id, distribution, level_name, role_name, site_name, platform_name, withs \
+             FROM search_distributions($1, role => $2, platform => $3, level => $4, site => $5)"
+        );
+        assert_eq!(ps, &["maya", "any", "any", "facility", "any"]);
+    }
+    #[test]
+    fn get_query_string_with_coords_and_order() {
+        let mut store = MemoryStore::new();
+        let mut fv = FindVersionPins::new(&mut store, "maya");
+        fv.level("dev01")
+            .role("model")
+            .order_by(vec![SearchAttribute::Level, SearchAttribute::Role])
+            .order_direction(OrderDirection::Desc);
+        let query = fv.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT versionpin_id, distribution, level_name, role_name, site_name, platform_name, withs \
+             FROM search_distributions($1, role => $2, platform => $3, level => $4, site => $5) \
+             ORDER BY level DESC, role DESC"
+        );
+        assert_eq!(ps, &["maya", "model", "any", "dev01", "any"]);
+    }
 }
@@ -1,11 +1,18 @@
 pub use crate::coords_error::{CoordsError, CoordsResult};
+use crate::db::async_packrat::AsyncClient;
+use crate::db::backend::Backend;
+use crate::db::memory::views::{CoordPaths, WithView};
+use crate::db::memory::MemoryStore;
+use crate::db::pool::PackratPool;
+use crate::db::prepared::Prepared;
+use crate::db::query::SelectQuery;
 pub use crate::db::search_attribute::{LtreeSearchMode, OrderDirection, SearchAttribute};
+use crate::db::sqlite::SqliteStore;
+use crate::io::render::{FieldValue, Record};
 use crate::types::IdType;
 pub use crate::Coords;
 pub use crate::Distribution;
-use log;
-use postgres::types::ToSql;
-use postgres::Client;
+use postgres::{Client, Row};
 use serde::Serialize;
 use snafu::{ResultExt, Snafu};
 use std::fmt;
@@ -27,6 +34,18 @@ pub enum FindWithsError {
         msg: &'static str,
         source: tokio_postgres::error::Error,
     },
+    /// Error from sqlite
+    #[snafu(display("Sqlite Error: {} {}", msg, source))]
+    SqliteError {
+        msg: &'static str,
+        source: rusqlite::Error,
+    },
+    /// Unable to check a connection out of a PackratPool
+    #[snafu(display("Pool Error: {} {}", msg, source))]
+    PoolError {
+        msg: &'static str,
+        source: r2d2::Error,
+    },
 }
 
 /// A row returned from the FindDistributions.query
@@ -48,6 +67,34 @@ impl fmt::Display for FindWithsRow {
     }
 }
 
+impl Record for FindWithsRow {
+    fn field_names() -> &'static [&'static str] {
+        &[
+            "versionpin_id",
+            "distribution",
+            "package",
+            "version",
+            "level",
+            "role",
+            "platform",
+            "site",
+        ]
+    }
+
+    fn field_values(&self) -> Vec<FieldValue> {
+        vec![
+            self.versionpin_id.into(),
+            self.distribution.distribution().into(),
+            self.distribution.package().into(),
+            self.distribution.version().into(),
+            self.coords.level().to_string().into(),
+            self.coords.role().to_string().into(),
+            self.coords.platform().to_string().into(),
+            self.coords.site().to_string().into(),
+        ]
+    }
+}
+
 impl FindWithsRow {
     /// New up a FindDistributionsRow instance
     ///
@@ -80,10 +127,7 @@ impl FindWithsRow {
 
         let coords = Coords::try_from_parts(level, role, platform, site).context(
             CoordsTryFromPartsError {
-                coords: format!(
-                    "(level:'{}' role:'{}' platform:'{}' site:'{}')",
-                    level, role, platform, site
-                ),
+                coords: Coords::display_parts(level, role, platform, site),
             },
         )?;
 
@@ -105,8 +149,8 @@ impl FindWithsRow {
     }
 }
 /// Responsible for finding a distribution
-pub struct FindWiths<'a> {
-    client: &'a mut Client,
+pub struct FindWiths<'a, C = Client> {
+    client: &'a mut C,
     package: &'a str,
     level: Option<&'a str>,
     role: Option<&'a str>,
@@ -116,8 +160,8 @@ pub struct FindWiths<'a> {
     order_direction: Option<OrderDirection>,
 }
 
-impl<'a> FindWiths<'a> {
-    pub fn new(client: &'a mut Client, package: &'a str) -> Self {
+impl<'a, C> FindWiths<'a, C> {
+    pub fn new(client: &'a mut C, package: &'a str) -> Self {
         FindWiths {
             client,
             package,
@@ -183,26 +227,57 @@ impl<'a> FindWiths<'a> {
         self.order_direction = direction;
         self
     }
-    pub fn query(&mut self) -> Result<Vec<FindWithsRow>, FindWithsError> {
-        let level = self.level.unwrap_or("facility");
-        let role = self.role.unwrap_or("any");
-        let platform = self.platform.unwrap_or("any");
-        let site = self.site.unwrap_or("any");
+    // order the withs, and convert them into rows
+    fn arrange(&self, mut withs: Vec<WithView>) -> Result<Vec<FindWithsRow>, FindWithsError> {
+        if let Some(ref orderby) = self.order_by {
+            withs.sort_by_cached_key(|with| {
+                orderby
+                    .iter()
+                    .map(|attr| match attr {
+                        SearchAttribute::Level => with.pin.level.clone(),
+                        SearchAttribute::Role => with.pin.role.clone(),
+                        SearchAttribute::Platform => with.pin.platform.clone(),
+                        SearchAttribute::Site => with.pin.site.clone(),
+                        SearchAttribute::Package => with.package.clone(),
+                        SearchAttribute::Unknown => String::new(),
+                    })
+                    .collect::<Vec<_>>()
+            });
+        }
         let mut result = Vec::new();
-        let mut query_str = "SELECT 
-                versionpin_id,
-                distribution, 
-                level_name, 
-                role_name, 
-                site_name, 
-                platform_name
-            FROM find_distribution_withs(
-                $1,
-                role => $2, 
-                platform => $3, 
-                level=> $4, 
-                site => $5)"
-            .to_string();
+        for with in withs {
+            result.push(FindWithsRow::try_from_parts(
+                with.pin.versionpin_id,
+                &with.pin.distribution,
+                &with.pin.level,
+                &with.pin.role,
+                &with.pin.platform,
+                &with.pin.site,
+            )?);
+        }
+        Ok(result)
+    }
+
+    /// The select statement issued by `query` against postgres, for
+    /// inspection via `SelectQuery::to_sql`
+    pub fn select(&self) -> SelectQuery {
+        let mut query = SelectQuery::function(
+            [
+                "versionpin_id",
+                "distribution",
+                "level_name",
+                "role_name",
+                "site_name",
+                "platform_name",
+            ],
+            "find_distribution_withs",
+        );
+        query
+            .arg(self.package)
+            .named_arg("role", self.role.unwrap_or("any"))
+            .named_arg("platform", self.platform.unwrap_or("any"))
+            .named_arg("level", self.level.unwrap_or("facility"))
+            .named_arg("site", self.site.unwrap_or("any"));
         fn from_attr_to_str(attr: &SearchAttribute) -> &'static str {
             match attr {
                 SearchAttribute::Level => "level_name",
@@ -214,25 +289,20 @@ impl<'a> FindWiths<'a> {
             }
         }
         if let Some(ref orderby) = self.order_by {
-            let orderby = orderby
-                .iter()
-                .map(|x| from_attr_to_str(&x))
-                .collect::<Vec<_>>();
-            query_str = format!("{} ORDER BY {}", query_str, orderby.join(","));
+            query.order_by(orderby.iter().map(from_attr_to_str));
         }
+        query
+    }
 
-        let prep_vals: &[&(dyn ToSql + std::marker::Sync)] =
-            &[&self.package, &role, &platform, &level, &site];
-        log::info!("SQL\n{}", query_str.as_str());
-        log::info!("Arguments\n{:?}", prep_vals);
+    // build the query shared by the blocking and async clients
+    fn prepare(&self) -> Prepared {
+        self.select().into()
+    }
 
-        for row in self
-            .client
-            .query(query_str.as_str(), prep_vals)
-            .context(TokioPostgresError {
-                msg: "problem querying withs row",
-            })?
-        {
+    // convert the rows returned by the query
+    fn rows(rows: Vec<Row>) -> Result<Vec<FindWithsRow>, FindWithsError> {
+        let mut result = Vec::new();
+        for row in rows {
             let id: IdType = row.get(0);
             let distribution: &str = row.get(1);
             let level_name: &str = row.get(2);
@@ -251,3 +321,118 @@ impl<'a> FindWiths<'a> {
         Ok(result)
     }
 }
+
+impl<'a, C: Backend> FindWiths<'a, C> {
+    pub fn query(&mut self) -> Result<Vec<FindWithsRow>, FindWithsError> {
+        C::find_withs(self)
+    }
+}
+
+impl<'a> FindWiths<'a, Client> {
+    // execute the query against postgres
+    pub(crate) fn query_postgres(&mut self) -> Result<Vec<FindWithsRow>, FindWithsError> {
+        let rows = self
+            .prepare()
+            .query(self.client)
+            .context(TokioPostgresError {
+                msg: "problem querying withs row",
+            })?;
+        Self::rows(rows)
+    }
+}
+
+impl<'a> FindWiths<'a, PackratPool> {
+    // execute the query against a connection checked out of the pool for
+    // the duration of the query
+    pub(crate) fn query_pool(&mut self) -> Result<Vec<FindWithsRow>, FindWithsError> {
+        let mut conn = self.client.connection().context(PoolError {
+            msg: "unable to check out connection",
+        })?;
+        FindWiths {
+            client: &mut *conn,
+            package: self.package,
+            level: self.level,
+            role: self.role,
+            platform: self.platform,
+            site: self.site,
+            order_by: self.order_by.clone(),
+            order_direction: self.order_direction,
+        }
+        .query_postgres()
+    }
+}
+
+impl<'a> FindWiths<'a, MemoryStore> {
+    // execute the query against the tables of a MemoryStore
+    pub(crate) fn query_memory(&mut self) -> Result<Vec<FindWithsRow>, FindWithsError> {
+        let paths = CoordPaths::from_names(self.level, self.role, self.platform, self.site);
+        let withs = self
+            .client
+            .tables()
+            .find_distribution_withs(self.package, &paths);
+        self.arrange(withs)
+    }
+}
+
+impl<'a> FindWiths<'a, SqliteStore> {
+    // execute the query against a SqliteStore
+    pub(crate) fn query_sqlite(&mut self) -> Result<Vec<FindWithsRow>, FindWithsError> {
+        let paths = CoordPaths::from_names(self.level, self.role, self.platform, self.site);
+        let withs = self
+            .client
+            .find_distribution_withs(self.package, &paths)
+            .context(SqliteError {
+                msg: "problem with select from find_distribution_withs",
+            })?;
+        self.arrange(withs)
+    }
+}
+
+impl<'a> FindWiths<'a, AsyncClient> {
+    pub async fn query(&mut self) -> Result<Vec<FindWithsRow>, FindWithsError> {
+        let rows = self
+            .prepare()
+            .query_async(self.client)
+            .await
+            .context(TokioPostgresError {
+                msg: "problem querying withs row",
+            })?;
+        Self::rows(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_query_string_default_works() {
+        let mut store = MemoryStore::new();
+        let fw = FindWiths::new(&mut store, "maya");
+        let query = fw.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT versionpin_id, distribution, level_name, role_name, site_name, platform_name \
+             FROM find_distribution_withs($1, role => $2, platform => $3, level => $4, site => $5)"
+        );
+        assert_eq!(ps, &["maya", "any", "any", "facility", "any"]);
+    }
+    #[test]
+    fn get_query_string_with_coords_and_order() {
+        let mut store = MemoryStore::new();
+        let mut fw = FindWiths::new(&mut store, "maya");
+        fw.role("model")
+            .site("portland")
+            .order_by(vec![SearchAttribute::Level, SearchAttribute::Site]);
+        let query = fw.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT versionpin_id, distribution, level_name, role_name, site_name, platform_name \
+             FROM find_distribution_withs($1, role => $2, platform => $3, level => $4, site => $5) \
+             ORDER BY level_name, site_name"
+        );
+        assert_eq!(ps, &["maya", "model", "any", "facility", "portland"]);
+    }
+}
@@ -1,14 +1,29 @@
 pub use crate::coords_error::{CoordsError, CoordsResult};
+use crate::db::async_packrat::AsyncClient;
+use crate::db::backend::Backend;
+use crate::db::memory::functions::{
+    any_name_to_path, level_name_to_path, ltree_matches, role_name_to_path,
+};
+use crate::db::memory::views::AuditView;
+use crate::db::memory::MemoryStore;
+use crate::db::pool::PackratPool;
+use crate::db::prepared::Prepared;
+use crate::db::query::SelectQuery;
+use crate::db::search_attribute::SearchMode;
 pub use crate::db::search_attribute::{LtreeSearchMode, OrderDirection, SearchAttribute};
+use crate::db::sqlite::views::{format_datetime, parse_datetime};
+use crate::db::sqlite::{ltree_clause, SqlitePrepared, SqliteStore};
+use crate::io::render::{FieldValue, Record};
 use crate::types::{IdType, LongIdType};
 pub use crate::Coords;
 pub use crate::Distribution;
 use crate::{Level, Platform, Role, Site};
-use log;
-use postgres::types::ToSql;
-use postgres::Client;
+use chrono::{DateTime, Local};
+use postgres::fallible_iterator::FallibleIterator;
+use postgres::{Client, Row};
 use snafu::ResultExt;
 use snafu::Snafu;
+use std::cmp::Ordering;
 use std::convert::TryFrom;
 use std::fmt;
 use std::str::FromStr;
@@ -41,9 +56,71 @@ pub enum ChangeAction {
         to_string = "TRUNCATE"
     )]
     Truncate,
+    #[strum(
+        serialize = "delete",
+        serialize = "Delete",
+        serialize = "DELETE",
+        to_string = "DELETE"
+    )]
+    Delete,
+}
+
+/// What a change did to a versionpin
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ChangeType {
+    /// The distribution of the versionpin changed. `old` is None when the
+    /// versionpin is inserted, and `new` is empty when it is deleted.
+    Distribution {
+        old: Option<Distribution>,
+        new: Distribution,
+    },
+    /// The withs of the versionpin were added, removed or reordered
+    Withs { old: Vec<String>, new: Vec<String> },
+}
+
+impl ChangeType {
+    /// The distribution after the change, if the change is to a distribution
+    pub fn distribution(&self) -> Option<&Distribution> {
+        match self {
+            Self::Distribution { new, .. } => Some(new),
+            Self::Withs { .. } => None,
+        }
+    }
+
+    /// The withs after the change, if the change is to the withs
+    pub fn withs(&self) -> Option<&[String]> {
+        match self {
+            Self::Distribution { .. } => None,
+            Self::Withs { new, .. } => Some(new),
+        }
+    }
 }
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr)]
+
+impl fmt::Display for ChangeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Distribution { old, new } => write!(
+                f,
+                "{} {}",
+                old.as_ref().map(|x| format!("{}", x)).unwrap_or_default(),
+                new
+            ),
+            Self::Withs { old, new } => write!(f, "[{}] [{}]", old.join(", "), new.join(", ")),
+        }
+    }
+}
+
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr, Clone, Copy,
+)]
 pub enum OrderChangeBy {
+    #[strum(
+        serialize = "event_id",
+        serialize = "EventId",
+        serialize = "EVENT_ID",
+        to_string = "event_id"
+    )]
+    EventId,
     #[strum(
         serialize = "transaction_id",
         serialize = "TransactionId",
@@ -51,6 +128,27 @@ pub enum OrderChangeBy {
         to_string = "transaction_id"
     )]
     TransactionId,
+    #[strum(
+        serialize = "package",
+        serialize = "Package",
+        serialize = "PACKAGE",
+        to_string = "package"
+    )]
+    Package,
+    #[strum(
+        serialize = "author",
+        serialize = "Author",
+        serialize = "AUTHOR",
+        to_string = "author"
+    )]
+    Author,
+    #[strum(
+        serialize = "datetime",
+        serialize = "DateTime",
+        serialize = "DATETIME",
+        to_string = "datetime"
+    )]
+    DateTime,
 }
 
 pub type FindAllChangesResult<T, E = FindAllChangesError> = std::result::Result<T, E>;
@@ -75,11 +173,25 @@ pub enum FindAllChangesError {
         msg: &'static str,
         source: tokio_postgres::error::Error,
     },
+    /// Error from sqlite
+    #[snafu(display("Sqlite Error: {} {}", msg, source))]
+    SqliteError {
+        msg: &'static str,
+        source: rusqlite::Error,
+    },
+    /// Unable to check a connection out of a PackratPool
+    #[snafu(display("Pool Error: {} {}", msg, source))]
+    PoolError {
+        msg: &'static str,
+        source: r2d2::Error,
+    },
 }
 
 /// A row returned from the  FindAllChanges.query
 #[derive(Debug, PartialEq, Eq)]
 pub struct FindAllChangesRow {
+    /// The id of the audit event, which increases with each change
+    pub event_id: LongIdType,
     pub id: LongIdType,
     pub transaction_id: LongIdType,
     pub action: ChangeAction,
@@ -88,17 +200,22 @@ pub struct FindAllChangesRow {
     pub platform: Platform,
     pub site: Site,
     pub package: String,
-    // when I start to track updates to with's this will have to
-    // be an enum ChangeType {Distribution(Distribution), Withs(Vec<String>)}
-    pub old: Option<Distribution>, // should this be a distribution?
-    pub new: Distribution,
+    /// The change made to the distribution or the withs of the versionpin
+    pub change: ChangeType,
+    /// The author of the revision made by the transaction. None if the
+    /// transaction recorded no revision
+    pub author: Option<String>,
+    /// The comment of the revision made by the transaction
+    pub comment: Option<String>,
+    /// The datetime of the revision made by the transaction
+    pub datetime: Option<DateTime<Local>>,
 }
 
 impl fmt::Display for FindAllChangesRow {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "{} {} {} ({} {} {} {}) {} {}",
+            "{} {} {} ({} {} {} {}) {}",
             self.id,
             self.transaction_id,
             self.action,
@@ -106,27 +223,71 @@ impl fmt::Display for FindAllChangesRow {
             self.role,
             self.platform,
             self.site,
-            self.old
-                .as_ref()
-                .map(|x| format!("{}", x))
-                .unwrap_or(String::new()),
-            self.new
+            self.change
         )
     }
 }
 
+impl Record for FindAllChangesRow {
+    fn field_names() -> &'static [&'static str] {
+        &[
+            "id",
+            "transaction_id",
+            "action",
+            "package",
+            "level",
+            "role",
+            "platform",
+            "site",
+            "old",
+            "new",
+            "author",
+            "comment",
+            "datetime",
+        ]
+    }
+
+    fn field_values(&self) -> Vec<FieldValue> {
+        let (old, new) = match &self.change {
+            ChangeType::Distribution { old, new } => (
+                old.as_ref().map(Distribution::distribution).into(),
+                new.distribution().into(),
+            ),
+            ChangeType::Withs { old, new } => {
+                (FieldValue::List(old.clone()), FieldValue::List(new.clone()))
+            }
+        };
+        vec![
+            self.id.into(),
+            self.transaction_id.into(),
+            self.action.as_ref().into(),
+            self.package.as_str().into(),
+            self.level.to_string().into(),
+            self.role.to_string().into(),
+            self.platform.to_string().into(),
+            self.site.to_string().into(),
+            old,
+            new,
+            self.author.as_deref().into(),
+            self.comment.as_deref().into(),
+            self.datetime.map(|datetime| datetime.to_rfc3339()).into(),
+        ]
+    }
+}
+
 impl FindAllChangesRow {
     /// New up a  FindAllChangesRow instance
     ///
     /// # Arguments
+    /// * `event_id` - the id of the audit event
     /// * `id`  - the revision id
     /// * `transaction_id` - the transaction id
-    /// * `author` - The author of the revision
-    /// * `comment` - The comment associated with the revision
+    /// * `change` - The change made to the distribution or withs
     ///
     /// # Returns
     /// - FindAllChangesRow instance
     pub fn new<S: Into<String>>(
+        event_id: LongIdType,
         id: LongIdType,
         transaction_id: LongIdType,
         action: ChangeAction,
@@ -135,10 +296,10 @@ impl FindAllChangesRow {
         platform: Platform,
         site: Site,
         package: S,
-        old: Option<Distribution>,
-        new: Distribution,
+        change: ChangeType,
     ) -> Self {
         FindAllChangesRow {
+            event_id,
             id,
             transaction_id,
             action,
@@ -147,15 +308,40 @@ impl FindAllChangesRow {
             platform,
             site,
             package: package.into(),
-            old,
-            new,
+            change,
+            author: None,
+            comment: None,
+            datetime: None,
         }
     }
+
+    /// Set the author, comment and datetime of the revision made by the
+    /// transaction which made the change
+    ///
+    /// # Arguments
+    /// * `author` - The author of the revision, if any
+    /// * `comment` - The comment of the revision, if any
+    /// * `datetime` - The datetime of the revision, if any
+    ///
+    /// # Returns
+    /// - FindAllChangesRow instance
+    pub fn with_revision(
+        mut self,
+        author: Option<String>,
+        comment: Option<String>,
+        datetime: Option<DateTime<Local>>,
+    ) -> Self {
+        self.author = author;
+        self.comment = comment;
+        self.datetime = datetime;
+        self
+    }
     /// Attempt to construct a revision from &strs. This is a fallible operation
     /// returning a result.
     ///
     /// # Arguments
     ///
+    /// * `event_id`
     /// * `id`
     /// * `transaction_id`
     /// * `author`
@@ -167,6 +353,7 @@ impl FindAllChangesRow {
     /// - Ok - FindAllChangesRow instance
     /// - Err - FindAllChangesError
     pub fn try_from_parts<'b>(
+        event_id: LongIdType,
         id: LongIdType,
         transaction_id: LongIdType,
         action: &'b str,
@@ -178,22 +365,10 @@ impl FindAllChangesRow {
         old: &'b str,
         new: &'b str,
     ) -> FindAllChangesResult<FindAllChangesRow> {
-        // TODO: police category
         let action = ChangeAction::from_str(action).context(ChangeActionError {
             input: action.to_string(),
         })?;
-        let level = Level::try_from(level).context(CoordsTryFromPartsError {
-            coords: "unable to create from supplied str",
-        })?;
-        let role = Role::try_from(role).context(CoordsTryFromPartsError {
-            coords: "unable to create from supplied str",
-        })?;
-        let platform = Platform::try_from(platform).context(CoordsTryFromPartsError {
-            coords: "unable to create from supplied str",
-        })?;
-        let site = Site::try_from(site).context(CoordsTryFromPartsError {
-            coords: "unable to create from supplied str",
-        })?;
+        let coords = Self::coords_from_parts(level, role, platform, site)?;
         let old = if old == "" {
             None
         } else {
@@ -204,26 +379,91 @@ impl FindAllChangesRow {
             )
         };
 
-        let new = Distribution::try_from(new).context(CoordsTryFromPartsError {
-            coords: "unable to create from supplied str",
-        })?;
+        // a deleted versionpin has no new distribution
+        let new = if new == "" && action == ChangeAction::Delete {
+            Distribution::empty()
+        } else {
+            Distribution::try_from(new).context(CoordsTryFromPartsError {
+                coords: "unable to create from supplied str",
+            })?
+        };
         Ok(Self::new(
+            event_id,
             id,
             transaction_id,
             action,
-            level,
-            role,
-            platform,
-            site,
+            coords.level,
+            coords.role,
+            coords.platform,
+            coords.site,
             package,
-            old,
-            new,
+            ChangeType::Distribution { old, new },
         ))
     }
 
+    /// New up a FindAllChangesRow recording a change to the withs of a
+    /// versionpin
+    ///
+    /// # Arguments
+    /// * `event_id` - the id of the audit event
+    /// * `id` - The id of the versionpin
+    /// * `transaction_id` - the transaction id
+    /// * `coords` - The coordinates of the versionpin
+    /// * `package` - The package of the versionpin
+    /// * `old` - The withs before the change, in order
+    /// * `new` - The withs after the change, in order
+    ///
+    /// # Returns
+    /// - FindAllChangesRow instance
+    pub fn from_withs<S: Into<String>>(
+        event_id: LongIdType,
+        id: LongIdType,
+        transaction_id: LongIdType,
+        coords: Coords,
+        package: S,
+        old: Vec<String>,
+        new: Vec<String>,
+    ) -> Self {
+        Self::new(
+            event_id,
+            id,
+            transaction_id,
+            ChangeAction::Update,
+            coords.level,
+            coords.role,
+            coords.platform,
+            coords.site,
+            package,
+            ChangeType::Withs { old, new },
+        )
+    }
+
+    // parse the coordinates of a change. TODO: police category
+    fn coords_from_parts(
+        level: &str,
+        role: &str,
+        platform: &str,
+        site: &str,
+    ) -> FindAllChangesResult<Coords> {
+        let level = Level::try_from(level).context(CoordsTryFromPartsError {
+            coords: "unable to create from supplied str",
+        })?;
+        let role = Role::try_from(role).context(CoordsTryFromPartsError {
+            coords: "unable to create from supplied str",
+        })?;
+        let platform = Platform::try_from(platform).context(CoordsTryFromPartsError {
+            coords: "unable to create from supplied str",
+        })?;
+        let site = Site::try_from(site).context(CoordsTryFromPartsError {
+            coords: "unable to create from supplied str",
+        })?;
+        Ok(Coords::from_parts(level, role, platform, site))
+    }
+
     /// Infallible counterpart to try_from_parts. Will panic if there is a problem
     ///
     /// # Arguments
+    /// * `event_id`
     /// * `id`
     /// * `transaction_id`
     /// * `author`
@@ -233,6 +473,7 @@ impl FindAllChangesRow {
     /// # Returns
     /// - FindAllChangesRow instance
     pub fn from_parts<'b>(
+        event_id: LongIdType,
         id: LongIdType,
         transaction_id: LongIdType,
         action: &'b str,
@@ -245,6 +486,7 @@ impl FindAllChangesRow {
         new: &'b str,
     ) -> FindAllChangesRow {
         Self::try_from_parts(
+            event_id,
             id,
             transaction_id,
             action,
@@ -258,25 +500,186 @@ impl FindAllChangesRow {
         )
         .expect("unable to create changes row")
     }
+
+    /// The columns of `find_vpin_audit` read by `from_row`
+    pub(crate) const COLUMNS: [&'static str; 11] = [
+        "event_id",
+        "id",
+        "transaction_id",
+        "action",
+        "level_name",
+        "role_name",
+        "platform_name",
+        "site_name",
+        "package",
+        "old",
+        "new",
+    ];
+
+    /// The columns of `vpin_change_view` read by `from_change_row`, which
+    /// follow the columns of `find_vpin_audit`
+    pub(crate) const REVISION_COLUMNS: [&'static str; 3] = ["author", "comment", "datetime"];
+
+    /// The columns of `vpin_change_view` holding the withs before and after
+    /// a change to the withs, which follow the revision columns. Both are
+    /// NULL for changes to the distribution.
+    pub(crate) const WITHS_COLUMNS: [&'static str; 2] = ["old_withs", "new_withs"];
+
+    // convert a row of `find_vpin_audit`
+    pub(crate) fn from_row(row: &Row) -> FindAllChangesResult<FindAllChangesRow> {
+        let event_id: LongIdType = row.get(0);
+        let id: IdType = row.get(1);
+        let txid: LongIdType = row.get(2);
+        let action: &str = row.get(3);
+        let level: &str = row.get(4);
+        let role: &str = row.get(5);
+        let platform: &str = row.get(6);
+        let site: &str = row.get(7);
+        let package: &str = row.get(8);
+        let old: &str = row.get(9);
+        let new: Option<&str> = row.get(10);
+
+        Self::try_from_parts(
+            event_id,
+            id as LongIdType,
+            txid,
+            action,
+            level,
+            role,
+            platform,
+            site,
+            package,
+            old,
+            new.unwrap_or(""),
+        )
+    }
+
+    // convert a row of `vpin_change_view`
+    pub(crate) fn from_change_row(row: &Row) -> FindAllChangesResult<FindAllChangesRow> {
+        let old_withs: Option<Vec<String>> = row.get(14);
+        let new_withs: Option<Vec<String>> = row.get(15);
+        let change = match (old_withs, new_withs) {
+            (Some(old), Some(new)) => {
+                let id: IdType = row.get(1);
+                let coords =
+                    Self::coords_from_parts(row.get(4), row.get(5), row.get(6), row.get(7))?;
+                let package: &str = row.get(8);
+                Self::from_withs(
+                    row.get(0),
+                    id as LongIdType,
+                    row.get(2),
+                    coords,
+                    package,
+                    old,
+                    new,
+                )
+            }
+            _ => Self::from_row(row)?,
+        };
+        Ok(change.with_revision(row.get(11), row.get(12), row.get(13)))
+    }
+
+    // convert a change recorded by a MemoryStore
+    pub(crate) fn from_view(change: &AuditView) -> FindAllChangesResult<FindAllChangesRow> {
+        if let (Some(old), Some(new)) = (&change.old_withs, &change.new_withs) {
+            let coords = Self::coords_from_parts(
+                &change.level_name,
+                &change.role_name,
+                &change.platform_name,
+                &change.site_name,
+            )?;
+            return Ok(Self::from_withs(
+                change.event_id,
+                change.id as LongIdType,
+                change.transaction_id,
+                coords,
+                change.package.as_str(),
+                old.clone(),
+                new.clone(),
+            ));
+        }
+        Self::try_from_parts(
+            change.event_id,
+            change.id as LongIdType,
+            change.transaction_id,
+            change.action.as_ref(),
+            &change.level_name,
+            &change.role_name,
+            &change.platform_name,
+            &change.site_name,
+            &change.package,
+            &change.old,
+            change.new.as_deref().unwrap_or(""),
+        )
+    }
 }
 /// Responsible for finding a distribution
-pub struct FindAllChanges<'a> {
-    client: &'a mut Client,
+pub struct FindAllChanges<'a, C = Client> {
+    client: &'a mut C,
     transaction_id: Option<LongIdType>,
+    package: Option<&'a str>,
+    level: Option<&'a str>,
+    role: Option<&'a str>,
+    platform: Option<&'a str>,
+    site: Option<&'a str>,
+    search_mode: LtreeSearchMode,
+    action: Option<ChangeAction>,
+    author: Option<&'a str>,
+    since: Option<DateTime<Local>>,
+    until: Option<DateTime<Local>>,
+    order_by: Option<Vec<OrderChangeBy>>,
+    order_direction: Option<OrderDirection>,
+    limit: Option<IdType>,
+    after: Option<LongIdType>,
+    page_size: Option<IdType>,
 }
 
-impl fmt::Debug for FindAllChanges<'_> {
+impl<C> fmt::Debug for FindAllChanges<'_, C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "FindAllChanges( txid:{:?})", self.transaction_id)
+        write!(
+            f,
+            "FindAllChanges(txid:{:?} package:{:?} level:{:?} role:{:?} platform:{:?} site:{:?} \
+             search mode:{:?} action:{:?} author:{:?} since:{:?} until:{:?} order by:{:?} \
+             direction:{:?} limit:{:?})",
+            self.transaction_id,
+            self.package,
+            self.level,
+            self.role,
+            self.platform,
+            self.site,
+            self.search_mode,
+            self.action,
+            self.author,
+            self.since,
+            self.until,
+            self.order_by,
+            self.order_direction,
+            self.limit
+        )
     }
 }
 
-impl<'a> FindAllChanges<'a> {
+impl<'a, C> FindAllChanges<'a, C> {
     /// new up a FIndAllChanges instance.
-    pub fn new(client: &'a mut Client) -> Self {
+    pub fn new(client: &'a mut C) -> Self {
         FindAllChanges {
             client,
             transaction_id: None,
+            package: None,
+            level: None,
+            role: None,
+            platform: None,
+            site: None,
+            search_mode: LtreeSearchMode::Exact,
+            action: None,
+            author: None,
+            since: None,
+            until: None,
+            order_by: None,
+            order_direction: None,
+            limit: None,
+            after: None,
+            page_size: None,
         }
     }
 
@@ -296,63 +699,931 @@ impl<'a> FindAllChanges<'a> {
         self
     }
 
+    /// Set the package whose changes are returned
+    ///
+    /// # Arguments
+    /// * `package` - The name of the package
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn package(&mut self, package: &'a str) -> &mut Self {
+        self.package = Some(package);
+        self
+    }
+
+    /// Set an optional package.
+    ///
+    /// # Arguments
+    /// * `package` - optional package name
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn package_opt(&mut self, package: Option<&'a str>) -> &mut Self {
+        self.package = package;
+        self
+    }
+
+    /// Set the level of the changes returned, which is compared against
+    /// the level of each change according to the `search_mode`
+    ///
+    /// # Arguments
+    /// * `level` - The name, or path, of the level
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn level(&mut self, level: &'a str) -> &mut Self {
+        self.level = Some(level);
+        self
+    }
+
+    /// Set an optional level.
+    ///
+    /// # Arguments
+    /// * `level` - optional level name
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn level_opt(&mut self, level: Option<&'a str>) -> &mut Self {
+        self.level = level;
+        self
+    }
+
+    /// Set the role of the changes returned. See `level`.
+    ///
+    /// # Arguments
+    /// * `role` - The name, or path, of the role
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn role(&mut self, role: &'a str) -> &mut Self {
+        self.role = Some(role);
+        self
+    }
+
+    /// Set an optional role.
+    ///
+    /// # Arguments
+    /// * `role` - optional role name
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn role_opt(&mut self, role: Option<&'a str>) -> &mut Self {
+        self.role = role;
+        self
+    }
+
+    /// Set the platform of the changes returned. See `level`.
+    ///
+    /// # Arguments
+    /// * `platform` - The name, or path, of the platform
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn platform(&mut self, platform: &'a str) -> &mut Self {
+        self.platform = Some(platform);
+        self
+    }
+
+    /// Set an optional platform.
+    ///
+    /// # Arguments
+    /// * `platform` - optional platform name
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn platform_opt(&mut self, platform: Option<&'a str>) -> &mut Self {
+        self.platform = platform;
+        self
+    }
+
+    /// Set the site of the changes returned. See `level`.
+    ///
+    /// # Arguments
+    /// * `site` - The name, or path, of the site
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn site(&mut self, site: &'a str) -> &mut Self {
+        self.site = Some(site);
+        self
+    }
+
+    /// Set an optional site.
+    ///
+    /// # Arguments
+    /// * `site` - optional site name
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn site_opt(&mut self, site: Option<&'a str>) -> &mut Self {
+        self.site = site;
+        self
+    }
+
+    /// Set the manner in which the level, role, platform and site are
+    /// compared against those of each change. `Ancestor` returns changes at,
+    /// or above, the coordinates; `Descendant` those at, or below, them.
+    /// Defaults to `Exact`.
+    ///
+    /// # Arguments
+    /// * `mode` - The search mode
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn search_mode(&mut self, mode: LtreeSearchMode) -> &mut Self {
+        self.search_mode = mode;
+        self
+    }
+
+    /// Set the action of the changes returned
+    ///
+    /// # Arguments
+    /// * `action` - The action
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn action(&mut self, action: ChangeAction) -> &mut Self {
+        self.action = Some(action);
+        self
+    }
+
+    /// Set an optional action.
+    ///
+    /// # Arguments
+    /// * `action` - optional action
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn action_opt(&mut self, action: Option<ChangeAction>) -> &mut Self {
+        self.action = action;
+        self
+    }
+
+    /// Set the author of the revisions whose changes are returned
+    ///
+    /// # Arguments
+    /// * `author`
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn author(&mut self, author: &'a str) -> &mut Self {
+        self.author = Some(author);
+        self
+    }
+
+    /// Set an optional author.
+    ///
+    /// # Arguments
+    /// * `author` - optional author str
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn author_opt(&mut self, author: Option<&'a str>) -> &mut Self {
+        self.author = author;
+        self
+    }
+
+    /// Return the changes whose revisions were made at, or after, the
+    /// supplied datetime
+    ///
+    /// # Arguments
+    /// * `datetime` - The start of the date range
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn since(&mut self, datetime: DateTime<Local>) -> &mut Self {
+        self.since = Some(datetime);
+        self
+    }
+
+    /// Set an optional start of the date range. See `since`.
+    ///
+    /// # Arguments
+    /// * `datetime` - optional datetime
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn since_opt(&mut self, datetime: Option<DateTime<Local>>) -> &mut Self {
+        self.since = datetime;
+        self
+    }
+
+    /// Return the changes whose revisions were made before the supplied
+    /// datetime
+    ///
+    /// # Arguments
+    /// * `datetime` - The end of the date range
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn until(&mut self, datetime: DateTime<Local>) -> &mut Self {
+        self.until = Some(datetime);
+        self
+    }
+
+    /// Set an optional end of the date range. See `until`.
+    ///
+    /// # Arguments
+    /// * `datetime` - optional datetime
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn until_opt(&mut self, datetime: Option<DateTime<Local>>) -> &mut Self {
+        self.until = datetime;
+        self
+    }
+
+    /// Set ordering. Changes which compare equal are ordered by event id.
+    ///
+    /// # Arguments
+    /// * `order_by` - vector of OrderChangeBy attributes
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn order_by(&mut self, attributes: Vec<OrderChangeBy>) -> &mut Self {
+        self.order_by = Some(attributes);
+        self
+    }
+
+    pub fn order_direction(&mut self, direction: OrderDirection) -> &mut Self {
+        self.order_direction = Some(direction);
+        self
+    }
+
+    pub fn order_direction_opt(&mut self, direction: Option<OrderDirection>) -> &mut Self {
+        self.order_direction = direction;
+        self
+    }
+
+    pub fn limit(&mut self, limit: IdType) -> &mut Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn limit_opt(&mut self, limit: Option<IdType>) -> &mut Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Page through the changes, returning those made after the change
+    /// with the supplied event id. Use the `event_id` of the last change of
+    /// the previous page. Paged queries are ordered by event id, ignoring
+    /// `order_by` and `limit`.
+    ///
+    /// # Arguments
+    /// * `event_id` - The event id of the last change already retrieved
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn after(&mut self, event_id: LongIdType) -> &mut Self {
+        self.after = Some(event_id);
+        self
+    }
+
+    /// Set the maximum number of changes to return per page
+    ///
+    /// # Arguments
+    /// * `page_size` - The number of changes per page
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn page_size(&mut self, page_size: IdType) -> &mut Self {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    fn is_paged(&self) -> bool {
+        self.after.is_some() || self.page_size.is_some()
+    }
+
+    // the paths searched for in the level, role, platform and site columns
+    fn coord_paths(&self) -> [(&'static str, Option<String>); 4] {
+        [
+            ("level", self.level.map(level_name_to_path)),
+            ("role", self.role.map(role_name_to_path)),
+            ("platform", self.platform.map(any_name_to_path)),
+            ("site", self.site.map(any_name_to_path)),
+        ]
+    }
+
+    // does the change pass the filters. changes without a revision fail
+    // the author and date range filters
+    fn matches(&self, change: &FindAllChangesRow) -> bool {
+        let paths = [
+            level_name_to_path(&change.level.to_string()),
+            role_name_to_path(change.role.as_ref()),
+            any_name_to_path(change.platform.as_ref()),
+            any_name_to_path(change.site.as_ref()),
+        ];
+        let datetime = change.datetime;
+        self.transaction_id
+            .iter()
+            .all(|txid| change.transaction_id == *txid)
+            && self
+                .package
+                .iter()
+                .all(|package| change.package == *package)
+            && self.action.iter().all(|action| change.action == *action)
+            && self
+                .author
+                .iter()
+                .all(|author| change.author.as_deref() == Some(*author))
+            && self
+                .since
+                .iter()
+                .all(|since| datetime.filter(|datetime| datetime >= since).is_some())
+            && self
+                .until
+                .iter()
+                .all(|until| datetime.filter(|datetime| datetime < until).is_some())
+            && self
+                .coord_paths()
+                .iter()
+                .zip(&paths)
+                .all(|((_, search), path)| match search {
+                    Some(search) => ltree_matches(path, search, &self.search_mode),
+                    None => true,
+                })
+    }
+
+    // order the changes and restrict them to the requested page or limit
+    fn arrange(&self, mut changes: Vec<FindAllChangesRow>) -> Vec<FindAllChangesRow> {
+        if self.is_paged() {
+            if let Some(after) = self.after {
+                changes.retain(|change| change.event_id > after);
+            }
+            changes.sort_by_key(|change| change.event_id);
+            if let Some(size) = self.page_size.filter(|size| *size > 0) {
+                changes.truncate(size as usize);
+            }
+            return changes;
+        }
+        let descending = self.order_direction == Some(OrderDirection::Desc);
+        let orderby = self.order_by.as_deref().unwrap_or(&[]);
+        changes.sort_by(|a, b| {
+            let ordering = orderby
+                .iter()
+                .fold(Ordering::Equal, |ordering, attr| {
+                    ordering.then_with(|| match attr {
+                        OrderChangeBy::EventId => a.event_id.cmp(&b.event_id),
+                        OrderChangeBy::TransactionId => a.transaction_id.cmp(&b.transaction_id),
+                        OrderChangeBy::Package => a.package.cmp(&b.package),
+                        OrderChangeBy::Author => a.author.cmp(&b.author),
+                        OrderChangeBy::DateTime => a.datetime.cmp(&b.datetime),
+                    })
+                })
+                .then_with(|| a.event_id.cmp(&b.event_id));
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        if let Some(limit) = self.limit.filter(|limit| *limit > 0) {
+            changes.truncate(limit as usize);
+        }
+        changes
+    }
+
+    /// The select statement issued by `query` against postgres, for
+    /// inspection via `SelectQuery::to_sql`
+    pub fn select(&self) -> SelectQuery {
+        let mut query = SelectQuery::new(
+            FindAllChangesRow::COLUMNS
+                .iter()
+                .chain(FindAllChangesRow::REVISION_COLUMNS.iter())
+                .chain(FindAllChangesRow::WITHS_COLUMNS.iter()),
+            "vpin_change_view",
+        );
+        if let Some(transaction_id) = self.transaction_id {
+            query.filter("transaction_id", &SearchMode::Equal, transaction_id);
+        }
+        if let Some(package) = self.package {
+            query.filter("package", &SearchMode::Equal, package);
+        }
+        for (column, path) in self.coord_paths().iter() {
+            if let Some(path) = path {
+                query.filter(column, &SearchMode::Ltree(self.search_mode), path.as_str());
+            }
+        }
+        if let Some(ref action) = self.action {
+            query.filter("action", &SearchMode::Equal, action.to_string());
+        }
+        if let Some(author) = self.author {
+            query.filter("author", &SearchMode::Equal, author);
+        }
+        if let Some(since) = self.since {
+            let cnt = query.bind(since);
+            query.filter_sql(format!("datetime >= ${}", cnt));
+        }
+        if let Some(until) = self.until {
+            let cnt = query.bind(until);
+            query.filter_sql(format!("datetime < ${}", cnt));
+        }
+        if self.is_paged() {
+            if let Some(after) = self.after {
+                let cnt = query.bind(after);
+                query.filter_sql(format!("event_id > ${}", cnt));
+            }
+            query.order_by(["event_id"]).limit(self.page_size);
+        } else {
+            if let Some(ref orderby) = self.order_by {
+                query.order_by(orderby);
+            }
+            query
+                .order_by(["event_id"])
+                .order_direction(Some(self.order_direction.unwrap_or(OrderDirection::Asc)))
+                .limit(self.limit);
+        }
+        query
+    }
+
+    fn prepare(&self) -> FindAllChangesResult<Prepared> {
+        Ok(self.select().into())
+    }
+
+    // convert the rows returned by the query
+    fn rows(rows: Vec<Row>) -> FindAllChangesResult<Vec<FindAllChangesRow>> {
+        rows.iter()
+            .map(FindAllChangesRow::from_change_row)
+            .collect()
+    }
+}
+
+impl<'a, C: Backend> FindAllChanges<'a, C> {
     pub fn query(&mut self) -> FindAllChangesResult<Vec<FindAllChangesRow>> {
-        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
-        let query_str = "SELECT
-                id,
-                transaction_id,
-                action,
-                level_name,
-                role_name,
-                platform_name,
-                site_name,
-                package,
-                old,
-                new
-            FROM 
-                find_vpin_audit($1)"
-            .to_string();
-        if self.transaction_id.is_none() {
-            return Err(FindAllChangesError::TransactionIdMissingError)?;
+        C::find_all_changes(self)
+    }
+}
+
+impl<'a> FindAllChanges<'a, Client> {
+    // execute the query against postgres
+    pub(crate) fn query_postgres(&mut self) -> FindAllChangesResult<Vec<FindAllChangesRow>> {
+        let rows = self
+            .prepare()?
+            .query(self.client)
+            .context(TokioPostgresError {
+                msg: "problem with select from vpin_change_view",
+            })?;
+        Self::rows(rows)
+    }
+}
+
+impl<'a> FindAllChanges<'a, PackratPool> {
+    // execute the query against a connection checked out of the pool for
+    // the duration of the query
+    pub(crate) fn query_pool(&mut self) -> FindAllChangesResult<Vec<FindAllChangesRow>> {
+        let mut conn = self.client.connection().context(PoolError {
+            msg: "unable to check out connection",
+        })?;
+        FindAllChanges {
+            client: &mut *conn,
+            transaction_id: self.transaction_id,
+            package: self.package,
+            level: self.level,
+            role: self.role,
+            platform: self.platform,
+            site: self.site,
+            search_mode: self.search_mode,
+            action: self.action.clone(),
+            author: self.author,
+            since: self.since,
+            until: self.until,
+            order_by: self.order_by.clone(),
+            order_direction: self.order_direction,
+            limit: self.limit,
+            after: self.after,
+            page_size: self.page_size,
+        }
+        .query_postgres()
+    }
+}
+
+impl<'a> FindAllChanges<'a, Client> {
+    /// Execute the query, streaming the changes from the database rather than
+    /// collecting them into a Vec
+    ///
+    /// # Returns
+    /// * Result
+    ///   * Ok - Iterator over the changes, each of which may fail to convert
+    ///   * Err - FindAllChangesError
+    pub fn query_iter(
+        &mut self,
+    ) -> FindAllChangesResult<impl Iterator<Item = FindAllChangesResult<FindAllChangesRow>> + '_>
+    {
+        let rows = self
+            .prepare()?
+            .query_raw(self.client)
+            .context(TokioPostgresError {
+                msg: "problem with select from vpin_change_view",
+            })?;
+        Ok(rows.iterator().map(|row| {
+            row.context(TokioPostgresError {
+                msg: "problem retrieving row from vpin_change_view",
+            })
+            .and_then(|row| FindAllChangesRow::from_change_row(&row))
+        }))
+    }
+}
+
+impl<'a> FindAllChanges<'a, MemoryStore> {
+    // execute the query against the tables of a MemoryStore
+    pub(crate) fn query_memory(&mut self) -> FindAllChangesResult<Vec<FindAllChangesRow>> {
+        let tables = self.client.tables();
+        let mut changes = Vec::new();
+        for change in tables.vpin_change_view() {
+            let revision = tables
+                .revisions
+                .iter()
+                .find(|revision| revision.transaction_id == change.transaction_id);
+            let change = FindAllChangesRow::from_view(&change)?.with_revision(
+                revision.map(|revision| revision.author.clone()),
+                revision.map(|revision| revision.comment.clone()),
+                revision.map(|revision| revision.datetime),
+            );
+            if self.matches(&change) {
+                changes.push(change);
+            }
         }
+        Ok(self.arrange(changes))
+    }
+}
 
-        let transaction_id = self.transaction_id.unwrap();
-        params.push(&transaction_id);
+impl<'a> FindAllChanges<'a, SqliteStore> {
+    // execute the query against a SqliteStore
+    pub(crate) fn query_sqlite(&mut self) -> FindAllChangesResult<Vec<FindAllChangesRow>> {
+        let mut prepared = SqlitePrepared::new(
+            "SELECT a.event_id, a.id, a.transaction_id, a.action, a.level_name, a.role_name,
+                    a.platform_name, a.site_name, a.package, a.old, a.new,
+                    r.author, r.comment, r.datetime, a.old_withs, a.new_withs
+                FROM vpin_audit AS a
+                LEFT JOIN revision AS r ON r.transaction_id = a.transaction_id",
+        );
+        let mut clauses = Vec::new();
+        if let Some(transaction_id) = self.transaction_id {
+            let idx = prepared.push(transaction_id);
+            clauses.push(format!("a.transaction_id = ?{}", idx));
+        }
+        if let Some(package) = self.package {
+            let idx = prepared.push(package.to_string());
+            clauses.push(format!("a.package = ?{}", idx));
+        }
+        // the audit records the names of the coordinates, not their paths
+        let columns = [
+            "level_name_to_path(a.level_name)",
+            "role_name_to_path(a.role_name)",
+            "any_name_to_path(a.platform_name)",
+            "any_name_to_path(a.site_name)",
+        ];
+        for (column, (_, path)) in columns.iter().zip(&self.coord_paths()) {
+            if let Some(path) = path {
+                let idx = prepared.push(path.to_string());
+                clauses.push(ltree_clause(column, idx, &self.search_mode));
+            }
+        }
+        if let Some(ref action) = self.action {
+            let idx = prepared.push(action.to_string());
+            clauses.push(format!("a.action = ?{}", idx));
+        }
+        if let Some(author) = self.author {
+            let idx = prepared.push(author.to_string());
+            clauses.push(format!("r.author = ?{}", idx));
+        }
+        if let Some(since) = self.since {
+            let idx = prepared.push(format_datetime(&since));
+            clauses.push(format!("r.datetime >= ?{}", idx));
+        }
+        if let Some(until) = self.until {
+            let idx = prepared.push(format_datetime(&until));
+            clauses.push(format!("r.datetime < ?{}", idx));
+        }
+        if let Some(after) = self.after {
+            let idx = prepared.push(after);
+            clauses.push(format!("a.event_id > ?{}", idx));
+        }
+        if !clauses.is_empty() {
+            prepared.sql = format!("{} WHERE {}", prepared.sql, clauses.join(" AND "));
+        }
+        // paged queries are ordered by event id alone
+        let direction = match self.order_direction {
+            Some(OrderDirection::Desc) if !self.is_paged() => "DESC",
+            _ => "ASC",
+        };
+        let order_by = if self.is_paged() {
+            None
+        } else {
+            self.order_by.as_ref()
+        };
+        let mut orderby = order_by
+            .into_iter()
+            .flatten()
+            .map(|attr| {
+                let column = match attr {
+                    OrderChangeBy::EventId => "a.event_id",
+                    OrderChangeBy::TransactionId => "a.transaction_id",
+                    OrderChangeBy::Package => "a.package",
+                    OrderChangeBy::Author => "r.author",
+                    OrderChangeBy::DateTime => "r.datetime",
+                };
+                format!("{} {}", column, direction)
+            })
+            .collect::<Vec<_>>();
+        orderby.push(format!("a.event_id {}", direction));
+        prepared.sql = format!("{} ORDER BY {}", prepared.sql, orderby.join(", "));
+        let limit = if self.is_paged() {
+            self.page_size
+        } else {
+            self.limit
+        };
+        if let Some(limit) = limit.filter(|limit| *limit > 0) {
+            let idx = prepared.push(limit);
+            prepared.sql = format!("{} LIMIT ?{}", prepared.sql, idx);
+        }
+        let changes = prepared
+            .query(self.client, |row| {
+                let datetime = match row.get::<_, Option<String>>(13)? {
+                    Some(_) => Some(parse_datetime(row, 13)?),
+                    None => None,
+                };
+                Ok((
+                    (
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, String>(4)?,
+                        row.get::<_, String>(5)?,
+                        row.get::<_, String>(6)?,
+                        row.get::<_, String>(7)?,
+                        row.get::<_, String>(8)?,
+                        row.get::<_, String>(9)?,
+                        row.get::<_, Option<String>>(10)?,
+                    ),
+                    (
+                        row.get::<_, Option<String>>(11)?,
+                        row.get::<_, Option<String>>(12)?,
+                        datetime,
+                    ),
+                    (
+                        row.get::<_, Option<String>>(14)?,
+                        row.get::<_, Option<String>>(15)?,
+                    ),
+                ))
+            })
+            .context(SqliteError {
+                msg: "problem with select from vpin_audit",
+            })?;
         let mut result = Vec::new();
-
-        log::info!("SQL\n{}", query_str.as_str());
-        log::info!("Prepared: {:?}", &params);
-        for row in
-            self.client
-                .query(query_str.as_str(), &params[..])
-                .context(TokioPostgresError {
-                    msg: "problem with select from find_vpin_audit  function",
-                })?
+        for (
+            (event_id, id, transaction_id, action, level, role, platform, site, package, old, new),
+            (author, comment, datetime),
+            withs,
+        ) in changes
         {
-            let id: IdType = row.get(0);
-            let txid: LongIdType = row.get(1);
-            let action: &str = row.get(2);
-            let level: &str = row.get(3);
-            let role: &str = row.get(4);
-            let platform: &str = row.get(5);
-            let site: &str = row.get(6);
-            let package: &str = row.get(7);
-            let old: &str = row.get(8);
-            let new: &str = row.get(9);
-
-            result.push(FindAllChangesRow::try_from_parts(
-                id as LongIdType,
-                txid,
-                action,
-                level,
-                role,
-                platform,
-                site,
-                package,
-                old,
-                new,
-            )?);
+            let change = match withs {
+                (Some(old_withs), Some(new_withs)) => FindAllChangesRow::from_withs(
+                    event_id,
+                    id,
+                    transaction_id,
+                    FindAllChangesRow::coords_from_parts(&level, &role, &platform, &site)?,
+                    package,
+                    split_withs(&old_withs),
+                    split_withs(&new_withs),
+                ),
+                _ => FindAllChangesRow::try_from_parts(
+                    event_id,
+                    id,
+                    transaction_id,
+                    &action,
+                    &level,
+                    &role,
+                    &platform,
+                    &site,
+                    &package,
+                    &old,
+                    new.as_deref().unwrap_or(""),
+                )?,
+            };
+            result.push(change.with_revision(author, comment, datetime));
         }
         Ok(result)
     }
 }
+
+impl<'a> FindAllChanges<'a, AsyncClient> {
+    pub async fn query(&mut self) -> FindAllChangesResult<Vec<FindAllChangesRow>> {
+        let rows = self
+            .prepare()?
+            .query_async(self.client)
+            .await
+            .context(TokioPostgresError {
+                msg: "problem with select from vpin_change_view",
+            })?;
+        Self::rows(rows)
+    }
+}
+
+// the withs of a change are stored in a SqliteStore as comma separated text
+fn split_withs(withs: &str) -> Vec<String> {
+    withs
+        .split(',')
+        .filter(|with| !with.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::query::QueryParam;
+    use chrono::TimeZone;
+
+    const SELECT: &str = "SELECT event_id, id, transaction_id, action, level_name, role_name, \
+                          platform_name, site_name, package, old, new, author, comment, datetime, \
+                          old_withs, new_withs FROM vpin_change_view";
+
+    #[test]
+    fn get_query_string_default_works() {
+        let mut store = MemoryStore::new();
+        let fac = FindAllChanges::new(&mut store);
+        let query = fac.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(qs, format!("{} ORDER BY event_id ASC", SELECT));
+        assert!(ps.is_empty());
+    }
+    #[test]
+    fn get_query_string_with_transaction_id() {
+        let mut store = MemoryStore::new();
+        let mut fac = FindAllChanges::new(&mut store);
+        fac.transaction_id(12);
+        let query = fac.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs,
+            format!("{} WHERE transaction_id = $1 ORDER BY event_id ASC", SELECT)
+        );
+        assert_eq!(ps, &[QueryParam::BigInt(12)]);
+    }
+    #[test]
+    fn get_query_string_with_package() {
+        let mut store = MemoryStore::new();
+        let mut fac = FindAllChanges::new(&mut store);
+        fac.package("maya");
+        let query = fac.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs,
+            format!("{} WHERE package = $1 ORDER BY event_id ASC", SELECT)
+        );
+        assert_eq!(ps, &["maya"]);
+    }
+    #[test]
+    fn get_query_string_with_coords() {
+        let mut store = MemoryStore::new();
+        let mut fac = FindAllChanges::new(&mut store);
+        fac.level("dev01")
+            .role("fx_beta")
+            .platform("cent7_64")
+            .site("portland");
+        let query = fac.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs,
+            format!(
+                "{} WHERE text2ltree($1) = level AND text2ltree($2) = role \
+                 AND text2ltree($3) = platform AND text2ltree($4) = site ORDER BY event_id ASC",
+                SELECT
+            )
+        );
+        assert_eq!(
+            ps,
+            &[
+                "facility.dev01",
+                "any.fx.beta",
+                "any.cent7_64",
+                "any.portland"
+            ]
+        );
+    }
+    #[test]
+    fn get_query_string_with_level_and_search_mode() {
+        let mut store = MemoryStore::new();
+        let mut fac = FindAllChanges::new(&mut store);
+        fac.level("dev01.rd")
+            .search_mode(LtreeSearchMode::Descendant);
+        let query = fac.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs,
+            format!(
+                "{} WHERE text2ltree($1) @> level ORDER BY event_id ASC",
+                SELECT
+            )
+        );
+        assert_eq!(ps, &["facility.dev01.rd"]);
+    }
+    #[test]
+    fn get_query_string_with_action_and_author() {
+        let mut store = MemoryStore::new();
+        let mut fac = FindAllChanges::new(&mut store);
+        fac.action(ChangeAction::Update).author("jgerber");
+        let query = fac.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs,
+            format!(
+                "{} WHERE action = $1 AND author = $2 ORDER BY event_id ASC",
+                SELECT
+            )
+        );
+        assert_eq!(ps, &["UPDATE", "jgerber"]);
+    }
+    #[test]
+    fn get_query_string_with_date_range() {
+        let since = Local.ymd(2020, 3, 1).and_hms(0, 0, 0);
+        let until = Local.ymd(2020, 3, 8).and_hms(0, 0, 0);
+        let mut store = MemoryStore::new();
+        let mut fac = FindAllChanges::new(&mut store);
+        fac.since(since).until(until);
+        let query = fac.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs,
+            format!(
+                "{} WHERE datetime >= $1 AND datetime < $2 ORDER BY event_id ASC",
+                SELECT
+            )
+        );
+        assert_eq!(
+            ps,
+            &[QueryParam::DateTime(since), QueryParam::DateTime(until)]
+        );
+    }
+    #[test]
+    fn get_query_string_with_filters_numbers_params_in_order() {
+        let since = Local.ymd(2020, 3, 1).and_hms(0, 0, 0);
+        let mut store = MemoryStore::new();
+        let mut fac = FindAllChanges::new(&mut store);
+        fac.package("maya")
+            .level("dev01")
+            .author("jgerber")
+            .since(since);
+        let query = fac.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs,
+            format!(
+                "{} WHERE package = $1 AND text2ltree($2) = level AND author = $3 \
+                 AND datetime >= $4 ORDER BY event_id ASC",
+                SELECT
+            )
+        );
+        assert_eq!(
+            ps,
+            &[
+                QueryParam::from("maya"),
+                QueryParam::from("facility.dev01"),
+                QueryParam::from("jgerber"),
+                QueryParam::DateTime(since)
+            ]
+        );
+    }
+    #[test]
+    fn get_query_string_with_order_by_and_limit() {
+        let mut store = MemoryStore::new();
+        let mut fac = FindAllChanges::new(&mut store);
+        fac.order_by(vec![OrderChangeBy::Author, OrderChangeBy::DateTime])
+            .order_direction(OrderDirection::Desc)
+            .limit(5);
+        let query = fac.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs,
+            format!(
+                "{} ORDER BY author DESC, datetime DESC, event_id DESC LIMIT 5",
+                SELECT
+            )
+        );
+        assert!(ps.is_empty());
+    }
+    #[test]
+    fn get_query_string_with_page() {
+        let mut store = MemoryStore::new();
+        let mut fac = FindAllChanges::new(&mut store);
+        fac.package("maya")
+            .order_by(vec![OrderChangeBy::Package])
+            .limit(5)
+            .after(20)
+            .page_size(10);
+        let query = fac.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs,
+            format!(
+                "{} WHERE package = $1 AND event_id > $2 ORDER BY event_id LIMIT 10",
+                SELECT
+            )
+        );
+        assert_eq!(ps, &[QueryParam::from("maya"), QueryParam::BigInt(20)]);
+    }
+}
@@ -1,11 +1,21 @@
 pub use crate::coords_error::{CoordsError, CoordsResult};
+use crate::db::async_packrat::AsyncClient;
+use crate::db::backend::Backend;
+use crate::db::memory::functions::like;
+use crate::db::memory::views::DistributionView;
+use crate::db::memory::MemoryStore;
+use crate::db::pool::PackratPool;
+use crate::db::prepared::Prepared;
+use crate::db::query::SelectQuery;
+use crate::db::search_attribute::SearchMode;
 pub use crate::db::search_attribute::{LtreeSearchMode, OrderDirection, SearchAttribute};
+use crate::db::sqlite::views::{distribution_row, DISTRIBUTION_COLUMNS};
+use crate::db::sqlite::{like_clause, SqlitePrepared, SqliteStore};
+use crate::io::render::{FieldValue, Record};
 use crate::types::IdType;
 pub use crate::Coords;
 pub use crate::Distribution;
-use log;
-use postgres::types::ToSql;
-use postgres::Client;
+use postgres::{Client, Row};
 use serde::Serialize;
 use snafu::{ResultExt, Snafu};
 use std::fmt;
@@ -46,6 +56,18 @@ pub enum FindAllDistributionsError {
         msg: &'static str,
         source: tokio_postgres::error::Error,
     },
+    /// Error from sqlite
+    #[snafu(display("Sqlite Error: {} {}", msg, source))]
+    SqliteError {
+        msg: &'static str,
+        source: rusqlite::Error,
+    },
+    /// Unable to check a connection out of a PackratPool
+    #[snafu(display("Pool Error: {} {}", msg, source))]
+    PoolError {
+        msg: &'static str,
+        source: r2d2::Error,
+    },
 }
 
 /// A row returned from the  FindAllDistributions.query
@@ -62,6 +84,20 @@ impl fmt::Display for FindAllDistributionsRow {
     }
 }
 
+impl Record for FindAllDistributionsRow {
+    fn field_names() -> &'static [&'static str] {
+        &["id", "package", "version"]
+    }
+
+    fn field_values(&self) -> Vec<FieldValue> {
+        vec![
+            self.id.into(),
+            self.package.as_str().into(),
+            self.version.as_str().into(),
+        ]
+    }
+}
+
 impl FindAllDistributionsRow {
     /// New up a FindAllDistributionsRow instance
     ///
@@ -122,8 +158,8 @@ impl FindAllDistributionsRow {
     }
 }
 /// Responsible for finding a distribution
-pub struct FindAllDistributions<'a> {
-    client: &'a mut Client,
+pub struct FindAllDistributions<'a, C = Client> {
+    client: &'a mut C,
     package: Option<&'a str>,
     version: Option<&'a str>,
     //order_by: Vec<OrderDistributionBy>,
@@ -131,7 +167,7 @@ pub struct FindAllDistributions<'a> {
     // limit: Option<IdType>,
 }
 
-impl fmt::Debug for FindAllDistributions<'_> {
+impl<C> fmt::Debug for FindAllDistributions<'_, C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
@@ -141,9 +177,9 @@ impl fmt::Debug for FindAllDistributions<'_> {
     }
 }
 
-impl<'a> FindAllDistributions<'a> {
+impl<'a, C> FindAllDistributions<'a, C> {
     /// new up a FIndAllDistributions instance.
-    pub fn new(client: &'a mut Client) -> Self {
+    pub fn new(client: &'a mut C) -> Self {
         FindAllDistributions {
             client,
             package: None,
@@ -191,53 +227,65 @@ impl<'a> FindAllDistributions<'a> {
     //     self
     // }
 
-    pub fn query(&mut self) -> FindAllDistributionsResult<Vec<FindAllDistributionsRow>> {
-        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
-        let mut query_str = "SELECT 
-                distribution_id,
-                package,
-                version_name
-            FROM 
-                distribution_view"
-            .to_string();
-        let mut cnt = 1;
-        let package = self.package.unwrap_or("none");
-        let version = self.version.unwrap_or("0");
-        let direction = self
-            .order_direction
-            .as_ref()
-            .unwrap_or(&OrderDirection::Desc);
-        if self.package.is_some() {
-            let q = if package.contains("%") { "like" } else { "=" };
-            query_str = format!("{} WHERE package {} ${}", query_str, q, cnt);
-            cnt += 1;
-            params.push(&package);
+    // order the distributions by package and version, as postgres does
+    fn arrange(
+        &self,
+        mut dists: Vec<DistributionView>,
+    ) -> FindAllDistributionsResult<Vec<FindAllDistributionsRow>> {
+        dists.sort_by(|a, b| {
+            a.package
+                .cmp(&b.package)
+                .then_with(|| a.version.cmp(&b.version))
+        });
+        if self.order_direction != Some(OrderDirection::Asc) {
+            dists.reverse();
+        }
+        let mut result = Vec::new();
+        for dist in dists {
+            result.push(FindAllDistributionsRow::try_from_parts(
+                dist.distribution_id,
+                &dist.package,
+                &dist.version_name,
+            )?);
         }
-        if self.version.is_some() {
-            let q = if version.contains("%") { "like" } else { "=" };
+        Ok(result)
+    }
 
-            if cnt == 1 {
-                query_str = format!("{} WHERE version_name {} $1", query_str, q);
+    /// The select statement issued by `query` against postgres, for
+    /// inspection via `SelectQuery::to_sql`
+    pub fn select(&self) -> SelectQuery {
+        let mut query = SelectQuery::new(
+            ["distribution_id", "package", "version_name"],
+            "distribution_view",
+        );
+        let search_mode = |value: &str| {
+            if value.contains('%') {
+                SearchMode::Like
             } else {
-                query_str = format!("{} AND version_name {} $2", query_str, q);
+                SearchMode::Equal
             }
-            params.push(&version);
+        };
+        if let Some(package) = self.package {
+            query.filter("package", &search_mode(package), package);
+        }
+        if let Some(version) = self.version {
+            query.filter("version_name", &search_mode(version), version);
         }
+        query
+            .order_by(["package", "version"])
+            .order_direction(Some(self.order_direction.unwrap_or(OrderDirection::Desc)));
+        query
+    }
 
-        query_str = format!(
-            "{}  ORDER BY package {}, version {}",
-            query_str, direction, direction
-        );
+    // build the query shared by the blocking and async clients
+    fn prepare(&self) -> Prepared {
+        self.select().into()
+    }
+
+    // convert the rows returned by the query
+    fn rows(rows: Vec<Row>) -> FindAllDistributionsResult<Vec<FindAllDistributionsRow>> {
         let mut result = Vec::new();
-        log::info!("SQL\n{}", query_str.as_str());
-        log::info!("Arguments\n{:?}", &params);
-        for row in
-            self.client
-                .query(query_str.as_str(), &params[..])
-                .context(TokioPostgresError {
-                    msg: "problem with select from distribution_view",
-                })?
-        {
+        for row in rows {
             let id = row.get(0);
             let package = row.get(1);
             let version = row.get(2);
@@ -248,3 +296,144 @@ impl<'a> FindAllDistributions<'a> {
         Ok(result)
     }
 }
+
+impl<'a, C: Backend> FindAllDistributions<'a, C> {
+    pub fn query(&mut self) -> FindAllDistributionsResult<Vec<FindAllDistributionsRow>> {
+        C::find_all_distributions(self)
+    }
+}
+
+impl<'a> FindAllDistributions<'a, Client> {
+    // execute the query against postgres
+    pub(crate) fn query_postgres(
+        &mut self,
+    ) -> FindAllDistributionsResult<Vec<FindAllDistributionsRow>> {
+        let rows = self
+            .prepare()
+            .query(self.client)
+            .context(TokioPostgresError {
+                msg: "problem with select from distribution_view",
+            })?;
+        Self::rows(rows)
+    }
+}
+
+impl<'a> FindAllDistributions<'a, PackratPool> {
+    // execute the query against a connection checked out of the pool for
+    // the duration of the query
+    pub(crate) fn query_pool(
+        &mut self,
+    ) -> FindAllDistributionsResult<Vec<FindAllDistributionsRow>> {
+        let mut conn = self.client.connection().context(PoolError {
+            msg: "unable to check out connection",
+        })?;
+        FindAllDistributions {
+            client: &mut *conn,
+            package: self.package,
+            version: self.version,
+            order_direction: self.order_direction,
+        }
+        .query_postgres()
+    }
+}
+
+impl<'a> FindAllDistributions<'a, MemoryStore> {
+    // execute the query against the tables of a MemoryStore
+    pub(crate) fn query_memory(
+        &mut self,
+    ) -> FindAllDistributionsResult<Vec<FindAllDistributionsRow>> {
+        let matches = |value: &str, search: Option<&str>| match search {
+            Some(search) if search.contains("%") => like(value, search),
+            Some(search) => value == search,
+            None => true,
+        };
+        let dists = self
+            .client
+            .tables()
+            .distribution_view()
+            .into_iter()
+            .filter(|dist| matches(&dist.package, self.package))
+            .filter(|dist| matches(&dist.version_name, self.version))
+            .collect::<Vec<_>>();
+        self.arrange(dists)
+    }
+}
+
+impl<'a> FindAllDistributions<'a, SqliteStore> {
+    // execute the query against a SqliteStore
+    pub(crate) fn query_sqlite(
+        &mut self,
+    ) -> FindAllDistributionsResult<Vec<FindAllDistributionsRow>> {
+        let mut prepared = SqlitePrepared::new(format!(
+            "SELECT {} FROM distribution_view",
+            DISTRIBUTION_COLUMNS
+        ));
+        let mut clauses = Vec::new();
+        if let Some(package) = self.package {
+            let idx = prepared.push(package.to_string());
+            clauses.push(like_clause("package", idx, package));
+        }
+        if let Some(version) = self.version {
+            let idx = prepared.push(version.to_string());
+            clauses.push(like_clause("version_name", idx, version));
+        }
+        if !clauses.is_empty() {
+            prepared.sql = format!("{} WHERE {}", prepared.sql, clauses.join(" AND "));
+        }
+        prepared.sql = format!("{} ORDER BY distribution_id", prepared.sql);
+        let dists = prepared
+            .query(self.client, distribution_row)
+            .context(SqliteError {
+                msg: "problem with select from distribution_view",
+            })?;
+        self.arrange(dists)
+    }
+}
+
+impl<'a> FindAllDistributions<'a, AsyncClient> {
+    pub async fn query(&mut self) -> FindAllDistributionsResult<Vec<FindAllDistributionsRow>> {
+        let rows = self
+            .prepare()
+            .query_async(self.client)
+            .await
+            .context(TokioPostgresError {
+                msg: "problem with select from distribution_view",
+            })?;
+        Self::rows(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_query_string_default_works() {
+        let mut store = MemoryStore::new();
+        let fd = FindAllDistributions::new(&mut store);
+        let query = fd.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT distribution_id, package, version_name FROM distribution_view \
+             ORDER BY package DESC, version DESC"
+        );
+        assert!(ps.is_empty());
+    }
+    #[test]
+    fn get_query_string_with_package_and_like_version() {
+        let mut store = MemoryStore::new();
+        let mut fd = FindAllDistributions::new(&mut store);
+        fd.package("maya")
+            .version("2019.%")
+            .order_direction(OrderDirection::Asc);
+        let query = fd.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT distribution_id, package, version_name FROM distribution_view \
+             WHERE package = $1 AND version_name LIKE $2 ORDER BY package ASC, version ASC"
+        );
+        assert_eq!(ps, &["maya", "2019.%"]);
+    }
+}
@@ -1,19 +1,29 @@
 pub use crate::coords_error::{CoordsError, CoordsResult};
+use crate::db::async_packrat::AsyncClient;
+use crate::db::backend::Backend;
+use crate::db::memory::functions::nlevel;
+use crate::db::memory::MemoryStore;
+use crate::db::pool::PackratPool;
+use crate::db::prepared::Prepared;
+use crate::db::query::SelectQuery;
+use crate::db::search_attribute::SearchMode;
 pub use crate::db::search_attribute::{LtreeSearchMode, OrderDirection, SearchAttribute};
+use crate::db::sqlite::{SqlitePrepared, SqliteStore};
 use crate::types::IdType;
 pub use crate::Coords;
 pub use crate::Distribution;
-use log;
-use postgres::types::ToSql;
-use postgres::Client;
+use postgres::{Client, Row};
 use serde::Serialize;
 use snafu::{ResultExt, Snafu};
 use std::fmt;
 
 //use std::str::FromStr;
+use crate::io::render::{FieldValue, Record};
 use strum_macros::{AsRefStr, Display, EnumString, IntoStaticStr};
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr)]
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr, Clone, Copy,
+)]
 pub enum OrderLevelBy {
     #[strum(
         serialize = "name",
@@ -48,6 +58,18 @@ pub enum FindAllLevelsError {
         msg: &'static str,
         source: tokio_postgres::error::Error,
     },
+    /// Error from sqlite
+    #[snafu(display("Sqlite Error: {} {}", msg, source))]
+    SqliteError {
+        msg: &'static str,
+        source: rusqlite::Error,
+    },
+    /// Unable to check a connection out of a PackratPool
+    #[snafu(display("Pool Error: {} {}", msg, source))]
+    PoolError {
+        msg: &'static str,
+        source: r2d2::Error,
+    },
 }
 
 /// A row returned from the  FindAllLevels.query
@@ -63,6 +85,16 @@ impl fmt::Display for FindAllLevelsRow {
     }
 }
 
+impl Record for FindAllLevelsRow {
+    fn field_names() -> &'static [&'static str] {
+        &["level", "show"]
+    }
+
+    fn field_values(&self) -> Vec<FieldValue> {
+        vec![self.level.as_str().into(), self.show.as_str().into()]
+    }
+}
+
 impl FindAllLevelsRow {
     /// New up a FindAllLevelsRow instance
     ///
@@ -105,8 +137,8 @@ impl FindAllLevelsRow {
     }
 }
 /// Responsible for finding a distribution
-pub struct FindAllLevels<'a> {
-    client: &'a mut Client,
+pub struct FindAllLevels<'a, C = Client> {
+    client: &'a mut C,
     level: Option<&'a str>,
     show: Option<&'a str>,
     depth: Option<u8>,
@@ -115,13 +147,13 @@ pub struct FindAllLevels<'a> {
     limit: Option<IdType>,
 }
 
-impl fmt::Debug for FindAllLevels<'_> {
+impl<C> fmt::Debug for FindAllLevels<'_, C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "FindAllLevels({:?} {:?})", self.level, self.show)
     }
 }
 
-impl<'a> FindAllLevels<'a> {
+impl<'a, C> FindAllLevels<'a, C> {
     /// new up a FindAllLevels instance.
     ///
     /// # Arguments
@@ -129,7 +161,7 @@ impl<'a> FindAllLevels<'a> {
     ///
     /// # Returns
     /// * a FindAllLevels instance
-    pub fn new(client: &'a mut Client) -> Self {
+    pub fn new(client: &'a mut C) -> Self {
         FindAllLevels {
             client,
             show: None,
@@ -247,6 +279,43 @@ impl<'a> FindAllLevels<'a> {
         self
     }
 
+    /// The select statement issued by `query` against postgres, for
+    /// inspection via `SelectQuery::to_sql`
+    pub fn select(&self) -> SelectQuery {
+        let mut query = SelectQuery::new(["name", "show"], "level_view");
+        query.distinct().filter_sql("name <> 'any'");
+        let show = self.show.unwrap_or("any");
+        if self.show.is_some() && show != "any" {
+            query.filter("show", &SearchMode::Equal, show);
+        }
+        if let Some(depth) = self.depth {
+            query.filter("nlevel(path)", &SearchMode::Equal, (depth + 1) as IdType);
+        }
+        match self.order_by {
+            Some(ref orderby) => query.order_by(orderby),
+            None => query.order_by(["name"]),
+        };
+        query
+    }
+
+    // build the query shared by the blocking and async clients
+    fn prepare(&self) -> Prepared {
+        self.select().into()
+    }
+
+    // convert the rows returned by the query
+    fn rows(rows: Vec<Row>) -> FindAllLevelsResult<Vec<FindAllLevelsRow>> {
+        let mut result = Vec::new();
+        for row in rows {
+            let level_name = row.get(0);
+            let show = row.get(1);
+            result.push(FindAllLevelsRow::try_from_parts(level_name, show)?);
+        }
+        Ok(result)
+    }
+}
+
+impl<'a, C: Backend> FindAllLevels<'a, C> {
     /// Execute the query from the built up parameters
     ///
     /// # Arguments
@@ -255,50 +324,182 @@ impl<'a> FindAllLevels<'a> {
     /// # Returns
     /// * Ok wrapped Vector of FindAllLevelsRow or an Error wrapped Box dyn Error
     pub fn query(&mut self) -> FindAllLevelsResult<Vec<FindAllLevelsRow>> {
-        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
-        let mut query_str = "SELECT DISTINCT 
-                name,
-                show
-            FROM 
-                level_view WHERE name <> 'any'"
-            .to_string();
-        let show = self.show.unwrap_or("any");
-        let mut cnt = 1;
-        if self.show.is_some() {
-            if show != "any" {
-                query_str = format!("{} AND show = ${}", query_str, cnt);
-                params.push(&show);
-                cnt += 1;
-            }
-        }
-        let depth = (self.depth.unwrap_or(0) + 1) as i32;
-        if self.depth.is_some() {
-            query_str = format!("{} AND nlevel(path) = ${}", query_str, cnt);
-            params.push(&depth);
-            // not using currently because we are the last query_str extension
-            //cnt += 1;
-        }
+        C::find_all_levels(self)
+    }
+}
 
-        if let Some(ref orderby) = self.order_by {
-            let orderby = orderby.iter().map(|x| x.as_ref()).collect::<Vec<_>>();
-            query_str = format!("{} ORDER BY {}", query_str, orderby.join(","));
-        } else {
-            query_str = format!("{}  ORDER BY name", query_str);
+impl<'a> FindAllLevels<'a, Client> {
+    // execute the query against postgres
+    pub(crate) fn query_postgres(&mut self) -> FindAllLevelsResult<Vec<FindAllLevelsRow>> {
+        let rows = self
+            .prepare()
+            .query(self.client)
+            .context(TokioPostgresError {
+                msg: "problem with select from level_view",
+            })?;
+        Self::rows(rows)
+    }
+}
+
+impl<'a> FindAllLevels<'a, PackratPool> {
+    // execute the query against a connection checked out of the pool for
+    // the duration of the query
+    pub(crate) fn query_pool(&mut self) -> FindAllLevelsResult<Vec<FindAllLevelsRow>> {
+        let mut conn = self.client.connection().context(PoolError {
+            msg: "unable to check out connection",
+        })?;
+        FindAllLevels {
+            client: &mut *conn,
+            level: self.level,
+            show: self.show,
+            depth: self.depth,
+            order_by: self.order_by.clone(),
+            order_direction: self.order_direction,
+            limit: self.limit,
         }
+        .query_postgres()
+    }
+}
+
+impl<'a> FindAllLevels<'a, MemoryStore> {
+    // execute the query against the tables of a MemoryStore
+    pub(crate) fn query_memory(&mut self) -> FindAllLevelsResult<Vec<FindAllLevelsRow>> {
+        let show = self.show.filter(|show| *show != "any");
+        let mut levels = self
+            .client
+            .tables()
+            .level_view()
+            .into_iter()
+            .filter(|level| level.name != "any")
+            .filter(|level| match show {
+                Some(show) => level.show == show,
+                None => true,
+            })
+            .filter(|level| match self.depth {
+                Some(depth) => nlevel(&level.path) == depth as usize + 1,
+                None => true,
+            })
+            .collect::<Vec<_>>();
+        let orderby = self.order_by.as_deref().unwrap_or(&[OrderLevelBy::Name]);
+        levels.sort_by_cached_key(|level| {
+            orderby
+                .iter()
+                .map(|attr| match attr {
+                    OrderLevelBy::Name => level.name.clone(),
+                    OrderLevelBy::Category => level.show.clone(),
+                })
+                .collect::<Vec<_>>()
+        });
         let mut result = Vec::new();
-        log::info!("SQL\n{}", query_str.as_str());
-        log::info!("Arguments\n{:?}", &params);
-        for row in
-            self.client
-                .query(query_str.as_str(), &params[..])
-                .context(TokioPostgresError {
-                    msg: "problem with select from level_view",
-                })?
-        {
-            let level_name = row.get(0);
-            let show = row.get(1);
-            result.push(FindAllLevelsRow::try_from_parts(level_name, show)?);
+        for level in levels {
+            result.push(FindAllLevelsRow::try_from_parts(&level.name, &level.show)?);
         }
         Ok(result)
     }
 }
+
+impl<'a> FindAllLevels<'a, SqliteStore> {
+    // execute the query against a SqliteStore
+    pub(crate) fn query_sqlite(&mut self) -> FindAllLevelsResult<Vec<FindAllLevelsRow>> {
+        let mut prepared =
+            SqlitePrepared::new("SELECT name, show FROM level_view WHERE name <> 'any'");
+        if let Some(show) = self.show.filter(|show| *show != "any") {
+            let idx = prepared.push(show.to_string());
+            prepared.sql = format!("{} AND show = ?{}", prepared.sql, idx);
+        }
+        if let Some(depth) = self.depth {
+            let idx = prepared.push(depth as i64 + 1);
+            prepared.sql = format!("{} AND nlevel(path) = ?{}", prepared.sql, idx);
+        }
+        let orderby = self
+            .order_by
+            .as_deref()
+            .unwrap_or(&[OrderLevelBy::Name])
+            .iter()
+            .map(|attr| match attr {
+                OrderLevelBy::Name => "name",
+                OrderLevelBy::Category => "show",
+            })
+            .collect::<Vec<_>>();
+        prepared.sql = format!("{} ORDER BY {}, path", prepared.sql, orderby.join(", "));
+        let levels = prepared
+            .query(self.client, |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .context(SqliteError {
+                msg: "problem with select from level_view",
+            })?;
+        levels
+            .iter()
+            .map(|(name, show)| FindAllLevelsRow::try_from_parts(name, show))
+            .collect()
+    }
+}
+
+impl<'a> FindAllLevels<'a, AsyncClient> {
+    /// Execute the query from the built up parameters
+    ///
+    /// # Arguments
+    /// * None
+    ///
+    /// # Returns
+    /// * Ok wrapped Vector of FindAllLevelsRow or an Error wrapped Box dyn Error
+    pub async fn query(&mut self) -> FindAllLevelsResult<Vec<FindAllLevelsRow>> {
+        let rows = self
+            .prepare()
+            .query_async(self.client)
+            .await
+            .context(TokioPostgresError {
+                msg: "problem with select from level_view",
+            })?;
+        Self::rows(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::query::QueryParam;
+
+    #[test]
+    fn get_query_string_default_works() {
+        let mut store = MemoryStore::new();
+        let fl = FindAllLevels::new(&mut store);
+        let query = fl.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT DISTINCT name, show FROM level_view WHERE name <> 'any' ORDER BY name"
+        );
+        assert!(ps.is_empty());
+    }
+    #[test]
+    fn get_query_string_with_show_depth_and_order() {
+        let mut store = MemoryStore::new();
+        let mut fl = FindAllLevels::new(&mut store);
+        fl.show("dev01")
+            .depth(1)
+            .order_by(vec![OrderLevelBy::Show, OrderLevelBy::Name]);
+        let query = fl.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT DISTINCT name, show FROM level_view WHERE name <> 'any' \
+             AND show = $1 AND nlevel(path) = $2 ORDER BY show, name"
+        );
+        assert_eq!(ps, &[QueryParam::from("dev01"), QueryParam::Int(2)]);
+    }
+    #[test]
+    fn get_query_string_with_any_show() {
+        let mut store = MemoryStore::new();
+        let mut fl = FindAllLevels::new(&mut store);
+        fl.show("any");
+        let query = fl.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT DISTINCT name, show FROM level_view WHERE name <> 'any' ORDER BY name"
+        );
+        assert!(ps.is_empty());
+    }
+}
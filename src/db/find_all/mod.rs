@@ -13,4 +13,5 @@ pub mod pkgcoords;
 pub mod revisions;
 pub use revisions::OrderRevisionBy;
 pub mod changes;
+pub use changes::OrderChangeBy;
 pub mod versionpin_withs;
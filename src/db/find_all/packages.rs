@@ -1,14 +1,20 @@
 pub use crate::coords_error::{CoordsError, CoordsResult};
+use crate::db::async_packrat::AsyncClient;
+use crate::db::backend::Backend;
+use crate::db::memory::MemoryStore;
+use crate::db::pool::PackratPool;
+use crate::db::prepared::Prepared;
+use crate::db::query::SelectQuery;
 pub use crate::db::search_attribute::{LtreeSearchMode, OrderDirection, SearchAttribute};
+use crate::db::sqlite::{SqlitePrepared, SqliteStore};
 pub use crate::Coords;
 pub use crate::Distribution;
-use log;
-//use postgres::types::ToSql;
-use postgres::Client;
+use postgres::{Client, Row};
 use snafu::{ResultExt, Snafu};
 use std::fmt;
 //use std::str::FromStr;
 //use crate::types::IdType;
+use crate::io::render::{FieldValue, Record};
 use serde::Serialize;
 use strum_macros::{AsRefStr, Display, EnumString, IntoStaticStr};
 
@@ -40,6 +46,18 @@ pub enum FindAllPackagesError {
         msg: &'static str,
         source: tokio_postgres::error::Error,
     },
+    /// Error from sqlite
+    #[snafu(display("Sqlite Error: {} {}", msg, source))]
+    SqliteError {
+        msg: &'static str,
+        source: rusqlite::Error,
+    },
+    /// Unable to check a connection out of a PackratPool
+    #[snafu(display("Pool Error: {} {}", msg, source))]
+    PoolError {
+        msg: &'static str,
+        source: r2d2::Error,
+    },
 }
 
 /// A row returned from the  FindAllPackages.query
@@ -54,6 +72,16 @@ impl fmt::Display for FindAllPackagesRow {
     }
 }
 
+impl Record for FindAllPackagesRow {
+    fn field_names() -> &'static [&'static str] {
+        &["name"]
+    }
+
+    fn field_values(&self) -> Vec<FieldValue> {
+        vec![self.name.as_str().into()]
+    }
+}
+
 impl FindAllPackagesRow {
     /// New up a  FindAllPackagesRow instance
     ///
@@ -93,20 +121,20 @@ impl FindAllPackagesRow {
     }
 }
 /// Responsible for finding a distribution
-pub struct FindAllPackages<'a> {
-    client: &'a mut Client,
+pub struct FindAllPackages<'a, C = Client> {
+    client: &'a mut C,
     //order_by: Vec<OrderPackageBy>,
     // order_direction: Option<OrderDirection>,
     // limit: Option<IdType>,
 }
 
-impl fmt::Debug for FindAllPackages<'_> {
+impl<C> fmt::Debug for FindAllPackages<'_, C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "FindAllPackages()")
     }
 }
 
-impl<'a> FindAllPackages<'a> {
+impl<'a, C> FindAllPackages<'a, C> {
     /// new up a FIndAllPackages instance.
     ///
     /// # Arguments
@@ -114,7 +142,7 @@ impl<'a> FindAllPackages<'a> {
     ///
     /// # Returns
     /// * An instance of FndAllPackages
-    pub fn new(client: &'a mut Client) -> Self {
+    pub fn new(client: &'a mut C) -> Self {
         FindAllPackages {
             client,
             //order_by: Vec::new(),
@@ -137,20 +165,10 @@ impl<'a> FindAllPackages<'a> {
     //     self
     // }
 
-    /// Execute the query using previously set parameters
-    ///
-    /// # Arguments
-    /// * None
-    ///
-    /// # Returns
-    /// * an Ok wrapped Vector of FindAllPackagesRow or an Error wrapped Box dyn Error
-    pub fn query(&mut self) -> FindAllPackagesResult<Vec<FindAllPackagesRow>> {
-        //let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
-        let mut query_str = "SELECT 
-                name
-            FROM 
-                package"
-            .to_string();
+    /// The select statement issued by `query` against postgres, for
+    /// inspection via `SelectQuery::to_sql`
+    pub fn select(&self) -> SelectQuery {
+        let mut query = SelectQuery::new(["name"], "package");
 
         // if self.order_by.len() > 0 {
         //     query_str = format!(
@@ -163,21 +181,122 @@ impl<'a> FindAllPackages<'a> {
         //             .join(",")
         //     );
         // } else {
-        query_str = format!("{}  ORDER BY name", query_str);
+        query.order_by(["name"]);
         //}
+        query
+    }
+
+    // build the query shared by the blocking and async clients
+    fn prepare(&self) -> Prepared {
+        self.select().into()
+    }
+
+    // convert the rows returned by the query
+    fn rows(rows: Vec<Row>) -> FindAllPackagesResult<Vec<FindAllPackagesRow>> {
         let mut result = Vec::new();
-        log::info!("SQL\n{}", query_str.as_str());
-        //log::info!("Arguments\n{:?}", &params);
-        for row in self
-            .client
-            .query(query_str.as_str(), &[])
+        for row in rows {
+            let name = row.get(0);
+            result.push(FindAllPackagesRow::try_from_parts(name)?);
+        }
+        Ok(result)
+    }
+}
+
+impl<'a, C: Backend> FindAllPackages<'a, C> {
+    /// Execute the query using previously set parameters
+    ///
+    /// # Arguments
+    /// * None
+    ///
+    /// # Returns
+    /// * an Ok wrapped Vector of FindAllPackagesRow or an Error wrapped Box dyn Error
+    pub fn query(&mut self) -> FindAllPackagesResult<Vec<FindAllPackagesRow>> {
+        C::find_all_packages(self)
+    }
+}
+
+impl<'a> FindAllPackages<'a, Client> {
+    // execute the query against postgres
+    pub(crate) fn query_postgres(&mut self) -> FindAllPackagesResult<Vec<FindAllPackagesRow>> {
+        let rows = self
+            .prepare()
+            .query(self.client)
             .context(TokioPostgresError {
                 msg: "problem with select from package table",
-            })?
-        {
-            let name = row.get(0);
+            })?;
+        Self::rows(rows)
+    }
+}
+
+impl<'a> FindAllPackages<'a, PackratPool> {
+    // execute the query against a connection checked out of the pool for
+    // the duration of the query
+    pub(crate) fn query_pool(&mut self) -> FindAllPackagesResult<Vec<FindAllPackagesRow>> {
+        let mut conn = self.client.connection().context(PoolError {
+            msg: "unable to check out connection",
+        })?;
+        FindAllPackages { client: &mut *conn }.query_postgres()
+    }
+}
+
+impl<'a> FindAllPackages<'a, MemoryStore> {
+    // execute the query against the tables of a MemoryStore
+    pub(crate) fn query_memory(&mut self) -> FindAllPackagesResult<Vec<FindAllPackagesRow>> {
+        let mut result = Vec::new();
+        // the package table is kept in name order
+        for name in &self.client.tables().packages {
             result.push(FindAllPackagesRow::try_from_parts(name)?);
         }
         Ok(result)
     }
 }
+
+impl<'a> FindAllPackages<'a, SqliteStore> {
+    // execute the query against a SqliteStore
+    pub(crate) fn query_sqlite(&mut self) -> FindAllPackagesResult<Vec<FindAllPackagesRow>> {
+        let names = SqlitePrepared::new("SELECT name FROM package ORDER BY name")
+            .query(self.client, |row| row.get::<_, String>(0))
+            .context(SqliteError {
+                msg: "problem with select from package",
+            })?;
+        names
+            .iter()
+            .map(|name| FindAllPackagesRow::try_from_parts(name))
+            .collect()
+    }
+}
+
+impl<'a> FindAllPackages<'a, AsyncClient> {
+    /// Execute the query using previously set parameters
+    ///
+    /// # Arguments
+    /// * None
+    ///
+    /// # Returns
+    /// * an Ok wrapped Vector of FindAllPackagesRow or an Error wrapped Box dyn Error
+    pub async fn query(&mut self) -> FindAllPackagesResult<Vec<FindAllPackagesRow>> {
+        let rows = self
+            .prepare()
+            .query_async(self.client)
+            .await
+            .context(TokioPostgresError {
+                msg: "problem with select from package table",
+            })?;
+        Self::rows(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_query_string_default_works() {
+        let mut store = MemoryStore::new();
+        let fp = FindAllPackages::new(&mut store);
+        let query = fp.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(qs.as_str(), "SELECT name FROM package ORDER BY name");
+        assert!(ps.is_empty());
+    }
+}
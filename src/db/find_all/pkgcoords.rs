@@ -7,16 +7,28 @@ pub use crate::Coords;
 pub use crate::Distribution;
 use serde::Serialize;
 
+use crate::db::async_packrat::AsyncClient;
+use crate::db::backend::Backend;
+use crate::db::memory::functions::{like, ltree_matches};
+use crate::db::memory::views::{ltree_key, PkgCoordView};
+use crate::db::memory::MemoryStore;
+use crate::db::pool::PackratPool;
+use crate::db::prepared::Prepared;
+use crate::db::query::SelectQuery;
+use crate::db::sqlite::views::{pkgcoord_row, PKGCOORD_COLUMNS};
+use crate::db::sqlite::{ltree_clause, SqlitePrepared, SqliteStore};
+use crate::io::render::{FieldValue, Record};
 use crate::types::IdType;
-use log;
-use postgres::types::ToSql;
-use postgres::Client;
+use postgres::{Client, Row};
 use snafu::{ResultExt, Snafu};
+use std::cmp::Ordering;
 use std::fmt;
 use std::str::FromStr;
 use strum_macros::{AsRefStr, Display, EnumString, IntoStaticStr};
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr)]
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr, Clone, Copy,
+)]
 pub enum OrderPkgCoordsBy {
     #[strum(
         serialize = "package",
@@ -74,6 +86,18 @@ pub enum FindAllPkgCoordsError {
         msg: &'static str,
         source: tokio_postgres::error::Error,
     },
+    /// Error from sqlite
+    #[snafu(display("Sqlite Error: {} {}", msg, source))]
+    SqliteError {
+        msg: &'static str,
+        source: rusqlite::Error,
+    },
+    /// Unable to check a connection out of a PackratPool
+    #[snafu(display("Pool Error: {} {}", msg, source))]
+    PoolError {
+        msg: &'static str,
+        source: r2d2::Error,
+    },
 }
 
 /// A row returned from the FindAllPkgCoords.query
@@ -98,6 +122,23 @@ impl fmt::Display for FindAllPkgCoordsRow {
     }
 }
 
+impl Record for FindAllPkgCoordsRow {
+    fn field_names() -> &'static [&'static str] {
+        &["id", "package", "level", "role", "platform", "site"]
+    }
+
+    fn field_values(&self) -> Vec<FieldValue> {
+        vec![
+            self.id.into(),
+            self.package.as_str().into(),
+            self.level.as_str().into(),
+            self.role.as_str().into(),
+            self.platform.as_str().into(),
+            self.site.as_str().into(),
+        ]
+    }
+}
+
 impl FindAllPkgCoordsRow {
     /// New up a FindAllPkgCoordsRow instance
     ///
@@ -149,8 +190,8 @@ impl FindAllPkgCoordsRow {
     }
 }
 /// Responsible for finding a distribution
-pub struct FindAllPkgCoords<'a> {
-    client: Option<&'a mut Client>,
+pub struct FindAllPkgCoords<'a, C = Client> {
+    client: Option<&'a mut C>,
     pub package: Option<&'a str>,
     pub level: Option<&'a str>,
     pub role: Option<&'a str>,
@@ -158,9 +199,11 @@ pub struct FindAllPkgCoords<'a> {
     pub site: Option<&'a str>,
     pub search_mode: SearchMode,
     pub order_by: Option<Vec<OrderPkgCoordsBy>>,
+    pub after: Option<IdType>,
+    pub page_size: Option<IdType>,
 }
 
-impl<'a> FindAllPkgCoords<'a> {
+impl<'a> FindAllPkgCoords<'a, Client> {
     /// New up an instance of FindAllPkgCoords
     ///
     /// # Arguments
@@ -169,6 +212,20 @@ impl<'a> FindAllPkgCoords<'a> {
     /// # Returns
     /// * An instance of FindAllPkgCoords
     pub fn new(client: Option<&'a mut Client>) -> Self {
+        Self::with_client(client)
+    }
+}
+
+impl<'a, C> FindAllPkgCoords<'a, C> {
+    /// New up an instance of FindAllPkgCoords from either a Client or
+    /// an AsyncClient
+    ///
+    /// # Arguments
+    /// * `client` - An Option wrapped mutable reference to a Client or AsyncClient instance
+    ///
+    /// # Returns
+    /// * An instance of FindAllPkgCoords
+    pub fn with_client(client: Option<&'a mut C>) -> Self {
         FindAllPkgCoords {
             client,
             package: None,
@@ -178,6 +235,8 @@ impl<'a> FindAllPkgCoords<'a> {
             site: None,
             search_mode: SearchMode::Ltree(LtreeSearchMode::Ancestor),
             order_by: None,
+            after: None,
+            page_size: None,
         }
     }
 
@@ -270,6 +329,33 @@ impl<'a> FindAllPkgCoords<'a> {
         self.order_by = Some(orders);
         self
     }
+
+    /// Return the pkgcoords whose id is greater than the supplied id. Paged
+    /// queries are ordered by pkgcoord id, ignoring `order_by`, so that the
+    /// id of the last row of one page fetches the next.
+    ///
+    /// # Arguments
+    /// * `pkgcoord_id` - The id of the last pkgcoord of the previous page
+    ///
+    /// # Returns
+    /// * A mutable reference to Self
+    pub fn after(&mut self, pkgcoord_id: IdType) -> &mut Self {
+        self.after = Some(pkgcoord_id);
+        self
+    }
+
+    /// Return at most `size` pkgcoords, ordered by pkgcoord id. See `after`.
+    ///
+    /// # Arguments
+    /// * `size` - The number of pkgcoords in a page
+    ///
+    /// # Returns
+    /// * A mutable reference to Self
+    pub fn page_size(&mut self, size: IdType) -> &mut Self {
+        self.page_size = Some(size);
+        self
+    }
+
     /// Set an option wrapped package name
     ///
     /// # Arguments
@@ -361,8 +447,8 @@ impl<'a> FindAllPkgCoords<'a> {
         }
         result
     }
-    fn get_query_str(&mut self) -> (String, Vec<String>) {
-        let package = self.package.unwrap_or("");
+    // the level, role, platform and site values searched for
+    fn coord_values(&self) -> (String, String, String, String) {
         let level = self.level.map_or("facility".to_string(), |x| {
             Self::prep_query_str("facility", x, false)
         });
@@ -375,80 +461,113 @@ impl<'a> FindAllPkgCoords<'a> {
         let site = self
             .site
             .map_or("any".to_string(), |x| Self::prep_query_str("any", x, false));
-
-        let mut prepared = Vec::new();
-        let mut query_str = "SELECT \n\
-                             pkgcoord_id, \n\
-                             package, \n\
-                             level_name, \n\
-                             role_name, \n\
-                             platform_name, \n\
-                             site_name \n\
-                             FROM pkgcoord_view"
-            .to_string();
-        let mut cnt = 1;
-        // Package
-        if self.package.is_some() {
-            let sm = pred_true_false(package.contains("%s"), SearchMode::Like, SearchMode::Equal);
-            let search_var = pred_true_false(sm == SearchMode::Like, "package_name", "package");
-            query_str.push_str(SearchMode::search_string(search_var, &sm, cnt).as_str());
-            cnt += 1;
-            prepared.push(package.to_string());
+        (level, role, platform, site)
+    }
+    /// The select statement issued by `query` against postgres, for
+    /// inspection via `SelectQuery::to_sql`. Values containing a % are
+    /// matched against the name, others against the path.
+    pub fn select(&self) -> SelectQuery {
+        let (level, role, platform, site) = self.coord_values();
+        let mut query = SelectQuery::new(
+            [
+                "pkgcoord_id",
+                "package",
+                "level_name",
+                "role_name",
+                "platform_name",
+                "site_name",
+            ],
+            "pkgcoord_view",
+        );
+        if let Some(package) = self.package {
+            if package.contains("%s") {
+                query.filter("package_name", &SearchMode::Like, package);
+            } else {
+                query.filter("package", &SearchMode::Equal, package);
+            }
         }
-        //Level
-        let sm = pred_true_false(level.contains("%"), &SearchMode::Like, &self.search_mode);
-        let search_var = pred_true_false(sm == &SearchMode::Like, "level_name", "level");
-        query_str.push_str(SearchMode::search_string(search_var, &sm, cnt).as_str());
-        cnt += 1;
-        prepared.push(level);
-        // Role
-        let sm = pred_true_false(role.contains("%"), &SearchMode::Like, &self.search_mode);
-        let search_var = pred_true_false(sm == &SearchMode::Like, "role_name", "role");
-        query_str.push_str(SearchMode::search_string(search_var, &sm, cnt).as_str());
-        cnt += 1;
-        prepared.push(role);
-        // Platform
-        let sm = pred_true_false(platform.contains("%"), &SearchMode::Like, &self.search_mode);
-        let search_var = pred_true_false(sm == &SearchMode::Like, "platform_name", "platform");
-        query_str.push_str(SearchMode::search_string(search_var, &sm, cnt).as_str());
-        cnt += 1;
-        prepared.push(platform);
-        // Site
-        let sm = pred_true_false(site.contains("%"), &SearchMode::Like, &self.search_mode);
-        let search_var = pred_true_false(sm == &SearchMode::Like, "site_name", "site");
-        query_str.push_str(SearchMode::search_string(search_var, &sm, cnt).as_str());
-        prepared.push(site);
-        if let Some(ref order) = self.order_by {
-            let joined = order
-                .iter()
-                .map(|x| x.as_ref())
-                .collect::<Vec<_>>()
-                .join(",");
-            query_str.push_str(format!(" ORDER BY {}", joined).as_str());
+        for (name, path, value) in &[
+            ("level_name", "level", level),
+            ("role_name", "role", role),
+            ("platform_name", "platform", platform),
+            ("site_name", "site", site),
+        ] {
+            if value.contains('%') {
+                query.filter(name, &SearchMode::Like, value.as_str());
+            } else if self.search_mode.is_like() {
+                query.filter(name, &self.search_mode, value.as_str());
+            } else {
+                query.filter(path, &self.search_mode, value.as_str());
+            }
+        }
+        if self.is_paged() {
+            if let Some(after) = self.after {
+                let cnt = query.bind(after);
+                query.filter_sql(format!("pkgcoord_id > ${}", cnt));
+            }
+            query.order_by(["pkgcoord_id"]).limit(self.page_size);
+        } else if let Some(ref order) = self.order_by {
+            query.order_by(order);
         }
-        (query_str, prepared)
+        query
     }
-    /// execute the query
-    pub fn query(&mut self) -> FindAllPkgCoordsResult<Vec<FindAllPkgCoordsRow>> {
-        let (query_str, prep) = self.get_query_str();
-        let mut result = Vec::new();
-        let mut prepared_args: Vec<&(dyn ToSql + Sync)> = Vec::new();
-        for argval in &prep {
-            prepared_args.push(argval);
+
+    fn is_paged(&self) -> bool {
+        self.after.is_some() || self.page_size.is_some()
+    }
+    // order the pkgcoords, comparing paths label by label as postgres does
+    fn arrange(
+        &self,
+        mut coords: Vec<PkgCoordView>,
+    ) -> FindAllPkgCoordsResult<Vec<FindAllPkgCoordsRow>> {
+        if self.is_paged() {
+            if let Some(after) = self.after {
+                coords.retain(|coord| coord.pkgcoord_id > after);
+            }
+            coords.sort_by_key(|coord| coord.pkgcoord_id);
+            if let Some(size) = self.page_size {
+                if size > 0 {
+                    coords.truncate(size as usize);
+                }
+            }
+        } else if let Some(ref orderby) = self.order_by {
+            coords.sort_by(|a, b| {
+                orderby.iter().fold(Ordering::Equal, |ordering, attr| {
+                    ordering.then_with(|| match attr {
+                        OrderPkgCoordsBy::Package => a.package.cmp(&b.package),
+                        OrderPkgCoordsBy::Level => ltree_key(&a.level).cmp(&ltree_key(&b.level)),
+                        OrderPkgCoordsBy::Role => ltree_key(&a.role).cmp(&ltree_key(&b.role)),
+                        OrderPkgCoordsBy::Platform => {
+                            ltree_key(&a.platform).cmp(&ltree_key(&b.platform))
+                        }
+                        OrderPkgCoordsBy::Site => ltree_key(&a.site).cmp(&ltree_key(&b.site)),
+                    })
+                })
+            });
         }
-        log::info!("SQL\n{}", query_str);
-        log::info!("Arguments\n{:?}", prepared_args);
-        let client = self.client.as_mut();
-        if client.is_none() {
-            return Err(FindAllPkgCoordsError::NoClientError)?;
+        let mut result = Vec::new();
+        for coord in coords {
+            result.push(FindAllPkgCoordsRow::try_from_parts(
+                coord.pkgcoord_id,
+                &coord.package,
+                &coord.level_name,
+                &coord.role_name,
+                &coord.platform_name,
+                &coord.site_name,
+            )?);
         }
-        let client = client.unwrap();
-        for row in client
-            .query(query_str.as_str(), &prepared_args[..])
-            .context(TokioPostgresError {
-                msg: "problem with select from pkgcoord_view",
-            })?
-        {
+        Ok(result)
+    }
+
+    // build the query shared by the blocking and async clients
+    fn prepare(&self) -> Prepared {
+        self.select().into()
+    }
+
+    // convert the rows returned by the query
+    fn rows(rows: Vec<Row>) -> FindAllPkgCoordsResult<Vec<FindAllPkgCoordsRow>> {
+        let mut result = Vec::new();
+        for row in rows {
             let id: IdType = row.get(0);
             let package: &str = row.get(1);
             let level_name: &str = row.get(2);
@@ -468,41 +587,201 @@ impl<'a> FindAllPkgCoords<'a> {
     }
 }
 
+impl<'a, C: Backend> FindAllPkgCoords<'a, C> {
+    /// execute the query
+    pub fn query(&mut self) -> FindAllPkgCoordsResult<Vec<FindAllPkgCoordsRow>> {
+        C::find_pkgcoords(self)
+    }
+}
+
+impl<'a> FindAllPkgCoords<'a, Client> {
+    // execute the query against postgres
+    pub(crate) fn query_postgres(&mut self) -> FindAllPkgCoordsResult<Vec<FindAllPkgCoordsRow>> {
+        let prepared = self.prepare();
+        let client = self
+            .client
+            .as_mut()
+            .ok_or(FindAllPkgCoordsError::NoClientError)?;
+        let rows = prepared.query(client).context(TokioPostgresError {
+            msg: "problem with select from pkgcoord_view",
+        })?;
+        Self::rows(rows)
+    }
+}
+
+impl<'a> FindAllPkgCoords<'a, PackratPool> {
+    // execute the query against a connection checked out of the pool for
+    // the duration of the query
+    pub(crate) fn query_pool(&mut self) -> FindAllPkgCoordsResult<Vec<FindAllPkgCoordsRow>> {
+        let mut conn = self
+            .client
+            .as_mut()
+            .ok_or(FindAllPkgCoordsError::NoClientError)?
+            .connection()
+            .context(PoolError {
+                msg: "unable to check out connection",
+            })?;
+        FindAllPkgCoords {
+            client: Some(&mut *conn),
+            package: self.package,
+            level: self.level,
+            role: self.role,
+            platform: self.platform,
+            site: self.site,
+            search_mode: self.search_mode,
+            order_by: self.order_by.clone(),
+            after: self.after,
+            page_size: self.page_size,
+        }
+        .query_postgres()
+    }
+}
+
+impl<'a> FindAllPkgCoords<'a, MemoryStore> {
+    // execute the query against the tables of a MemoryStore
+    pub(crate) fn query_memory(&mut self) -> FindAllPkgCoordsResult<Vec<FindAllPkgCoordsRow>> {
+        let (level, role, platform, site) = self.coord_values();
+        let search_mode = &self.search_mode;
+        // values containing a % are matched against the name, others against the path
+        let coord_matches = |name: &str, path: &str, value: &str| {
+            if value.contains("%") {
+                return like(name, value);
+            }
+            match search_mode {
+                SearchMode::Like => like(name, value),
+                SearchMode::Equal => path == value,
+                SearchMode::Ltree(mode) => ltree_matches(path, value, mode),
+            }
+        };
+        let tables = self
+            .client
+            .as_ref()
+            .ok_or(FindAllPkgCoordsError::NoClientError)?
+            .tables();
+        let coords = tables
+            .pkgcoord_view()
+            .into_iter()
+            .filter(|coord| match self.package {
+                Some(package) if package.contains("%s") => like(&coord.package, package),
+                Some(package) => coord.package == package,
+                None => true,
+            })
+            .filter(|coord| coord_matches(&coord.level_name, &coord.level, &level))
+            .filter(|coord| coord_matches(&coord.role_name, &coord.role, &role))
+            .filter(|coord| coord_matches(&coord.platform_name, &coord.platform, &platform))
+            .filter(|coord| coord_matches(&coord.site_name, &coord.site, &site))
+            .collect::<Vec<_>>();
+        self.arrange(coords)
+    }
+}
+
+impl<'a> FindAllPkgCoords<'a, SqliteStore> {
+    // execute the query against a SqliteStore
+    pub(crate) fn query_sqlite(&mut self) -> FindAllPkgCoordsResult<Vec<FindAllPkgCoordsRow>> {
+        let (level, role, platform, site) = self.coord_values();
+        let mut prepared = SqlitePrepared::new("");
+        let mut clauses = Vec::new();
+        if let Some(package) = self.package {
+            let idx = prepared.push(package.to_string());
+            if package.contains("%s") {
+                clauses.push(format!("package_name LIKE ?{} ESCAPE '\\'", idx));
+            } else {
+                clauses.push(format!("package = ?{}", idx));
+            }
+        }
+        // values containing a % are matched against the name, others against the path
+        for (name, path, value) in &[
+            ("level_name", "level", level),
+            ("role_name", "role", role),
+            ("platform_name", "platform", platform),
+            ("site_name", "site", site),
+        ] {
+            let like = value.contains('%') || self.search_mode == SearchMode::Like;
+            let idx = prepared.push(value.to_string());
+            clauses.push(match &self.search_mode {
+                _ if like => format!("{} LIKE ?{} ESCAPE '\\'", name, idx),
+                SearchMode::Ltree(mode) => ltree_clause(path, idx, mode),
+                _ => format!("{} = ?{}", path, idx),
+            });
+        }
+        if let Some(after) = self.after {
+            let idx = prepared.push(after);
+            clauses.push(format!("pkgcoord_id > ?{}", idx));
+        }
+        prepared.sql = format!(
+            "SELECT {} FROM pkgcoord_view WHERE {} ORDER BY pkgcoord_id",
+            PKGCOORD_COLUMNS,
+            clauses.join(" AND ")
+        );
+        let store = self
+            .client
+            .as_mut()
+            .ok_or(FindAllPkgCoordsError::NoClientError)?;
+        let coords = prepared.query(store, pkgcoord_row).context(SqliteError {
+            msg: "problem with select from pkgcoord_view",
+        })?;
+        self.arrange(coords)
+    }
+}
+
+impl<'a> FindAllPkgCoords<'a, AsyncClient> {
+    /// execute the query
+    pub async fn query(&mut self) -> FindAllPkgCoordsResult<Vec<FindAllPkgCoordsRow>> {
+        let prepared = self.prepare();
+        let client = self
+            .client
+            .as_mut()
+            .ok_or(FindAllPkgCoordsError::NoClientError)?;
+        let rows = prepared
+            .query_async(client)
+            .await
+            .context(TokioPostgresError {
+                msg: "problem with select from pkgcoord_view",
+            })?;
+        Self::rows(rows)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::db::query::QueryParam;
     #[test]
     fn get_query_string_default_works() {
-        let mut fpc = FindAllPkgCoords::new(None);
-        let (qs, ps) = fpc.get_query_str();
+        let fpc = FindAllPkgCoords::new(None);
+        let query = fpc.select();
+        let (qs, ps) = query.to_sql();
         assert_eq!(
             qs.as_str(),
-            "SELECT \n\
-        pkgcoord_id, \n\
-        package, \n\
-        level_name, \n\
-        role_name, \n\
-        platform_name, \n\
-        site_name \n\
-    FROM pkgcoord_view WHERE text2ltree($1) <@ level AND text2ltree($2) <@ role AND text2ltree($3) <@ platform AND text2ltree($4) <@ site"
+            "SELECT pkgcoord_id, package, level_name, role_name, platform_name, site_name \
+             FROM pkgcoord_view WHERE text2ltree($1) <@ level AND text2ltree($2) <@ role AND text2ltree($3) <@ platform AND text2ltree($4) <@ site"
         );
         assert_eq!(ps, &["facility", "any", "any", "any"]);
     }
     #[test]
+    fn get_query_string_with_page() {
+        let mut fpc = FindAllPkgCoords::new(None);
+        fpc.order_by("package").after(20).page_size(10);
+        let query = fpc.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT pkgcoord_id, package, level_name, role_name, platform_name, site_name \
+             FROM pkgcoord_view WHERE text2ltree($1) <@ level AND text2ltree($2) <@ role AND text2ltree($3) <@ platform AND text2ltree($4) <@ site \
+             AND pkgcoord_id > $5 ORDER BY pkgcoord_id LIMIT 10"
+        );
+        assert_eq!(&ps[4], &QueryParam::Int(20));
+    }
+    #[test]
     fn get_query_string_with_level() {
         let mut fpc = FindAllPkgCoords::new(None);
         fpc.level("bayou");
-        let (qs, ps) = fpc.get_query_str();
+        let query = fpc.select();
+        let (qs, ps) = query.to_sql();
         assert_eq!(
             qs.as_str(),
-            "SELECT \n\
-        pkgcoord_id, \n\
-        package, \n\
-        level_name, \n\
-        role_name, \n\
-        platform_name, \n\
-        site_name \n\
-    FROM pkgcoord_view WHERE text2ltree($1) <@ level AND text2ltree($2) <@ role AND text2ltree($3) <@ platform AND text2ltree($4) <@ site"
+            "SELECT pkgcoord_id, package, level_name, role_name, platform_name, site_name \
+             FROM pkgcoord_view WHERE text2ltree($1) <@ level AND text2ltree($2) <@ role AND text2ltree($3) <@ platform AND text2ltree($4) <@ site"
         );
         assert_eq!(ps, &["facility.bayou", "any", "any", "any"]);
     }
@@ -510,17 +789,12 @@ mod tests {
     fn get_query_string_with_like_level() {
         let mut fpc = FindAllPkgCoords::new(None);
         fpc.level("bayou%");
-        let (qs, ps) = fpc.get_query_str();
+        let query = fpc.select();
+        let (qs, ps) = query.to_sql();
         assert_eq!(
             qs.as_str(),
-            "SELECT \n\
-        pkgcoord_id, \n\
-        package, \n\
-        level_name, \n\
-        role_name, \n\
-        platform_name, \n\
-        site_name \n\
-    FROM pkgcoord_view WHERE level_name LIKE $1 AND text2ltree($2) <@ role AND text2ltree($3) <@ platform AND text2ltree($4) <@ site"
+            "SELECT pkgcoord_id, package, level_name, role_name, platform_name, site_name \
+             FROM pkgcoord_view WHERE level_name LIKE $1 AND text2ltree($2) <@ role AND text2ltree($3) <@ platform AND text2ltree($4) <@ site"
         );
         assert_eq!(ps, &["bayou%", "any", "any", "any"]);
     }
@@ -528,17 +802,12 @@ mod tests {
     fn get_query_string_with_subrole() {
         let mut fpc = FindAllPkgCoords::new(None);
         fpc.role("fx_beta");
-        let (qs, ps) = fpc.get_query_str();
+        let query = fpc.select();
+        let (qs, ps) = query.to_sql();
         assert_eq!(
             qs.as_str(),
-            "SELECT \n\
-        pkgcoord_id, \n\
-        package, \n\
-        level_name, \n\
-        role_name, \n\
-        platform_name, \n\
-        site_name \n\
-    FROM pkgcoord_view WHERE text2ltree($1) <@ level AND text2ltree($2) <@ role AND text2ltree($3) <@ platform AND text2ltree($4) <@ site"
+            "SELECT pkgcoord_id, package, level_name, role_name, platform_name, site_name \
+             FROM pkgcoord_view WHERE text2ltree($1) <@ level AND text2ltree($2) <@ role AND text2ltree($3) <@ platform AND text2ltree($4) <@ site"
         );
         assert_eq!(ps, &["facility", "any.fx.beta", "any", "any"]);
     }
@@ -546,17 +815,12 @@ mod tests {
     fn get_query_string_with_like_role() {
         let mut fpc = FindAllPkgCoords::new(None);
         fpc.role("fx%");
-        let (qs, ps) = fpc.get_query_str();
+        let query = fpc.select();
+        let (qs, ps) = query.to_sql();
         assert_eq!(
             qs.as_str(),
-            "SELECT \n\
-        pkgcoord_id, \n\
-        package, \n\
-        level_name, \n\
-        role_name, \n\
-        platform_name, \n\
-        site_name \n\
-    FROM pkgcoord_view WHERE text2ltree($1) <@ level AND role_name LIKE $2 AND text2ltree($3) <@ platform AND text2ltree($4) <@ site"
+            "SELECT pkgcoord_id, package, level_name, role_name, platform_name, site_name \
+             FROM pkgcoord_view WHERE text2ltree($1) <@ level AND role_name LIKE $2 AND text2ltree($3) <@ platform AND text2ltree($4) <@ site"
         );
         assert_eq!(ps, &["facility", "fx%", "any", "any"]);
     }
@@ -564,17 +828,12 @@ mod tests {
     fn get_query_string_with_platform() {
         let mut fpc = FindAllPkgCoords::new(None);
         fpc.platform("cent7_64");
-        let (qs, ps) = fpc.get_query_str();
+        let query = fpc.select();
+        let (qs, ps) = query.to_sql();
         assert_eq!(
             qs.as_str(),
-            "SELECT \n\
-        pkgcoord_id, \n\
-        package, \n\
-        level_name, \n\
-        role_name, \n\
-        platform_name, \n\
-        site_name \n\
-    FROM pkgcoord_view WHERE text2ltree($1) <@ level AND text2ltree($2) <@ role AND text2ltree($3) <@ platform AND text2ltree($4) <@ site"
+            "SELECT pkgcoord_id, package, level_name, role_name, platform_name, site_name \
+             FROM pkgcoord_view WHERE text2ltree($1) <@ level AND text2ltree($2) <@ role AND text2ltree($3) <@ platform AND text2ltree($4) <@ site"
         );
         assert_eq!(ps, &["facility", "any", "any.cent7_64", "any"]);
     }
@@ -582,17 +841,12 @@ mod tests {
     fn get_query_string_with_like_platform() {
         let mut fpc = FindAllPkgCoords::new(None);
         fpc.platform("cent7%");
-        let (qs, ps) = fpc.get_query_str();
+        let query = fpc.select();
+        let (qs, ps) = query.to_sql();
         assert_eq!(
             qs.as_str(),
-            "SELECT \n\
-        pkgcoord_id, \n\
-        package, \n\
-        level_name, \n\
-        role_name, \n\
-        platform_name, \n\
-        site_name \n\
-    FROM pkgcoord_view WHERE text2ltree($1) <@ level AND text2ltree($2) <@ role AND platform_name LIKE $3 AND text2ltree($4) <@ site"
+            "SELECT pkgcoord_id, package, level_name, role_name, platform_name, site_name \
+             FROM pkgcoord_view WHERE text2ltree($1) <@ level AND text2ltree($2) <@ role AND platform_name LIKE $3 AND text2ltree($4) <@ site"
         );
         assert_eq!(ps, &["facility", "any", "cent7%", "any"]);
     }
@@ -600,17 +854,12 @@ mod tests {
     fn get_query_string_with_site() {
         let mut fpc = FindAllPkgCoords::new(None);
         fpc.site("montreal");
-        let (qs, ps) = fpc.get_query_str();
+        let query = fpc.select();
+        let (qs, ps) = query.to_sql();
         assert_eq!(
             qs.as_str(),
-            "SELECT \n\
-        pkgcoord_id, \n\
-        package, \n\
-        level_name, \n\
-        role_name, \n\
-        platform_name, \n\
-        site_name \n\
-    FROM pkgcoord_view WHERE text2ltree($1) <@ level AND text2ltree($2) <@ role AND text2ltree($3) <@ platform AND text2ltree($4) <@ site"
+            "SELECT pkgcoord_id, package, level_name, role_name, platform_name, site_name \
+             FROM pkgcoord_view WHERE text2ltree($1) <@ level AND text2ltree($2) <@ role AND text2ltree($3) <@ platform AND text2ltree($4) <@ site"
         );
         assert_eq!(ps, &["facility", "any", "any", "any.montreal"]);
     }
@@ -618,17 +867,12 @@ mod tests {
     fn get_query_string_with_like_site() {
         let mut fpc = FindAllPkgCoords::new(None);
         fpc.site("montreal%");
-        let (qs, ps) = fpc.get_query_str();
+        let query = fpc.select();
+        let (qs, ps) = query.to_sql();
         assert_eq!(
             qs.as_str(),
-            "SELECT \n\
-        pkgcoord_id, \n\
-        package, \n\
-        level_name, \n\
-        role_name, \n\
-        platform_name, \n\
-        site_name \n\
-    FROM pkgcoord_view WHERE text2ltree($1) <@ level AND text2ltree($2) <@ role AND text2ltree($3) <@ platform AND site_name LIKE $4"
+            "SELECT pkgcoord_id, package, level_name, role_name, platform_name, site_name \
+             FROM pkgcoord_view WHERE text2ltree($1) <@ level AND text2ltree($2) <@ role AND text2ltree($3) <@ platform AND site_name LIKE $4"
         );
         assert_eq!(ps, &["facility", "any", "any", "montreal%"]);
     }
@@ -636,17 +880,12 @@ mod tests {
     fn get_query_string_with_level_like_and_package() {
         let mut fpc = FindAllPkgCoords::new(None);
         fpc.level("bayou%").package("maya");
-        let (qs, ps) = fpc.get_query_str();
+        let query = fpc.select();
+        let (qs, ps) = query.to_sql();
         assert_eq!(
             qs.as_str(),
-            "SELECT \n\
-        pkgcoord_id, \n\
-        package, \n\
-        level_name, \n\
-        role_name, \n\
-        platform_name, \n\
-        site_name \n\
-    FROM pkgcoord_view WHERE package = $1 AND level_name LIKE $2 AND text2ltree($3) <@ role AND text2ltree($4) <@ platform AND text2ltree($5) <@ site"
+            "SELECT pkgcoord_id, package, level_name, role_name, platform_name, site_name \
+             FROM pkgcoord_view WHERE package = $1 AND level_name LIKE $2 AND text2ltree($3) <@ role AND text2ltree($4) <@ platform AND text2ltree($5) <@ site"
         );
         assert_eq!(ps, &["maya", "bayou%", "any", "any", "any"]);
     }
@@ -657,17 +896,12 @@ mod tests {
     fn get_query_string_with_level_like_and_package_and_role() {
         let mut fpc = FindAllPkgCoords::new(None);
         fpc.level("bayou%").package("maya").role("fx_beta");
-        let (qs, ps) = fpc.get_query_str();
+        let query = fpc.select();
+        let (qs, ps) = query.to_sql();
         assert_eq!(
             qs.as_str(),
-            "SELECT \n\
-        pkgcoord_id, \n\
-        package, \n\
-        level_name, \n\
-        role_name, \n\
-        platform_name, \n\
-        site_name \n\
-    FROM pkgcoord_view WHERE package = $1 AND level_name LIKE $2 AND text2ltree($3) <@ role AND text2ltree($4) <@ platform AND text2ltree($5) <@ site"
+            "SELECT pkgcoord_id, package, level_name, role_name, platform_name, site_name \
+             FROM pkgcoord_view WHERE package = $1 AND level_name LIKE $2 AND text2ltree($3) <@ role AND text2ltree($4) <@ platform AND text2ltree($5) <@ site"
         );
         assert_eq!(ps, &["maya", "bayou%", "any.fx.beta", "any", "any"]);
     }
@@ -678,17 +912,12 @@ mod tests {
             .package("maya")
             .role("fx_beta")
             .platform("cent7_64");
-        let (qs, ps) = fpc.get_query_str();
+        let query = fpc.select();
+        let (qs, ps) = query.to_sql();
         assert_eq!(
             qs.as_str(),
-            "SELECT \n\
-        pkgcoord_id, \n\
-        package, \n\
-        level_name, \n\
-        role_name, \n\
-        platform_name, \n\
-        site_name \n\
-    FROM pkgcoord_view WHERE package = $1 AND level_name LIKE $2 AND text2ltree($3) <@ role AND text2ltree($4) <@ platform AND text2ltree($5) <@ site"
+            "SELECT pkgcoord_id, package, level_name, role_name, platform_name, site_name \
+             FROM pkgcoord_view WHERE package = $1 AND level_name LIKE $2 AND text2ltree($3) <@ role AND text2ltree($4) <@ platform AND text2ltree($5) <@ site"
         );
         assert_eq!(
             ps,
@@ -703,17 +932,12 @@ mod tests {
             .role("fx_beta")
             .platform("cent7_64")
             .site("montreal");
-        let (qs, ps) = fpc.get_query_str();
+        let query = fpc.select();
+        let (qs, ps) = query.to_sql();
         assert_eq!(
             qs.as_str(),
-            "SELECT \n\
-        pkgcoord_id, \n\
-        package, \n\
-        level_name, \n\
-        role_name, \n\
-        platform_name, \n\
-        site_name \n\
-    FROM pkgcoord_view WHERE package = $1 AND level_name LIKE $2 AND text2ltree($3) <@ role AND text2ltree($4) <@ platform AND text2ltree($5) <@ site"
+            "SELECT pkgcoord_id, package, level_name, role_name, platform_name, site_name \
+             FROM pkgcoord_view WHERE package = $1 AND level_name LIKE $2 AND text2ltree($3) <@ role AND text2ltree($4) <@ platform AND text2ltree($5) <@ site"
         );
         assert_eq!(
             ps,
@@ -1,19 +1,27 @@
 pub use crate::coords_error::{CoordsError, CoordsResult};
+use crate::db::async_packrat::AsyncClient;
+use crate::db::backend::Backend;
+use crate::db::memory::MemoryStore;
+use crate::db::pool::PackratPool;
+use crate::db::prepared::Prepared;
+use crate::db::query::SelectQuery;
 pub use crate::db::search_attribute::{LtreeSearchMode, OrderDirection, SearchAttribute};
+use crate::db::sqlite::{SqlitePrepared, SqliteStore};
 pub use crate::Coords;
 pub use crate::Distribution;
-use log;
-//use postgres::types::ToSql;
-use postgres::Client;
+use postgres::{Client, Row};
 use snafu::{ResultExt, Snafu};
 use std::fmt;
 //use std::str::FromStr;
+use crate::io::render::{FieldValue, Record};
 use crate::types::IdType;
 use serde::Serialize;
 use strum_macros::{AsRefStr, Display, EnumString, IntoStaticStr};
 
 /// A simple enum representing the possible columns to order the return by.
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr)]
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr, Clone, Copy,
+)]
 pub enum OrderPlatformBy {
     #[strum(
         serialize = "name",
@@ -41,6 +49,18 @@ pub enum FindAllPlatformsError {
         msg: &'static str,
         source: tokio_postgres::error::Error,
     },
+    /// Error from sqlite
+    #[snafu(display("Sqlite Error: {} {}", msg, source))]
+    SqliteError {
+        msg: &'static str,
+        source: rusqlite::Error,
+    },
+    /// Unable to check a connection out of a PackratPool
+    #[snafu(display("Pool Error: {} {}", msg, source))]
+    PoolError {
+        msg: &'static str,
+        source: r2d2::Error,
+    },
 }
 
 /// A row returned from the  FindAllPlatforms.query
@@ -55,6 +75,16 @@ impl fmt::Display for FindAllPlatformsRow {
     }
 }
 
+impl Record for FindAllPlatformsRow {
+    fn field_names() -> &'static [&'static str] {
+        &["name"]
+    }
+
+    fn field_values(&self) -> Vec<FieldValue> {
+        vec![self.name.as_str().into()]
+    }
+}
+
 impl FindAllPlatformsRow {
     /// New up a  FindAllPlatformsRow instance
     ///
@@ -95,23 +125,23 @@ impl FindAllPlatformsRow {
     }
 }
 /// Responsible for finding a distribution
-pub struct FindAllPlatforms<'a> {
-    client: &'a mut Client,
+pub struct FindAllPlatforms<'a, C = Client> {
+    client: &'a mut C,
     name: Option<&'a str>,
     order_by: Option<Vec<OrderPlatformBy>>,
     order_direction: Option<OrderDirection>,
     limit: Option<IdType>,
 }
 
-impl fmt::Debug for FindAllPlatforms<'_> {
+impl<C> fmt::Debug for FindAllPlatforms<'_, C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "FindAllPlatforms({:?})", self.name)
     }
 }
 
-impl<'a> FindAllPlatforms<'a> {
+impl<'a, C> FindAllPlatforms<'a, C> {
     /// new up a FIndAllPlatforms instance.
-    pub fn new(client: &'a mut Client) -> Self {
+    pub fn new(client: &'a mut C) -> Self {
         FindAllPlatforms {
             client,
             name: None,
@@ -178,33 +208,126 @@ impl<'a> FindAllPlatforms<'a> {
         self
     }
 
-    pub fn query(&mut self) -> FindAllPlatformsResult<Vec<FindAllPlatformsRow>> {
-        //let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
-        let mut query_str = "SELECT DISTINCT 
-                name
-            FROM 
-                platform_view"
-            .to_string();
-        if let Some(ref orderby) = self.order_by {
-            let orderby = orderby.iter().map(|x| x.as_ref()).collect::<Vec<_>>();
-            query_str = format!("{} ORDER BY {}", query_str, orderby.join(","));
-        } else {
-            query_str = format!("{}  ORDER BY name", query_str);
-        }
+    /// The select statement issued by `query` against postgres, for
+    /// inspection via `SelectQuery::to_sql`
+    pub fn select(&self) -> SelectQuery {
+        let mut query = SelectQuery::new(["name"], "platform_view");
+        query.distinct();
+        match self.order_by {
+            Some(ref orderby) => query.order_by(orderby),
+            None => query.order_by(["name"]),
+        };
+        query
+    }
+
+    // build the query shared by the blocking and async clients
+    fn prepare(&self) -> Prepared {
+        self.select().into()
+    }
+
+    // convert the rows returned by the query
+    fn rows(rows: Vec<Row>) -> FindAllPlatformsResult<Vec<FindAllPlatformsRow>> {
         let mut result = Vec::new();
-        log::info!("SQL\n{}", query_str.as_str());
-        //log::info!("Prepared: {:?}", &params);
-        for row in self
-            .client
-            .query(query_str.as_str(), &[])
-            .context(TokioPostgresError {
-                msg: "problem with select from platform_view",
-            })?
-        {
-            //&params[..])? {
+        for row in rows {
             let name = row.get(0);
             result.push(FindAllPlatformsRow::try_from_parts(name)?);
         }
         Ok(result)
     }
 }
+
+impl<'a, C: Backend> FindAllPlatforms<'a, C> {
+    pub fn query(&mut self) -> FindAllPlatformsResult<Vec<FindAllPlatformsRow>> {
+        C::find_all_platforms(self)
+    }
+}
+
+impl<'a> FindAllPlatforms<'a, Client> {
+    // execute the query against postgres
+    pub(crate) fn query_postgres(&mut self) -> FindAllPlatformsResult<Vec<FindAllPlatformsRow>> {
+        let rows = self
+            .prepare()
+            .query(self.client)
+            .context(TokioPostgresError {
+                msg: "problem with select from platform_view",
+            })?;
+        Self::rows(rows)
+    }
+}
+
+impl<'a> FindAllPlatforms<'a, PackratPool> {
+    // execute the query against a connection checked out of the pool for
+    // the duration of the query
+    pub(crate) fn query_pool(&mut self) -> FindAllPlatformsResult<Vec<FindAllPlatformsRow>> {
+        let mut conn = self.client.connection().context(PoolError {
+            msg: "unable to check out connection",
+        })?;
+        FindAllPlatforms {
+            client: &mut *conn,
+            name: self.name,
+            order_by: self.order_by.clone(),
+            order_direction: self.order_direction,
+            limit: self.limit,
+        }
+        .query_postgres()
+    }
+}
+
+impl<'a> FindAllPlatforms<'a, MemoryStore> {
+    // execute the query against the tables of a MemoryStore
+    pub(crate) fn query_memory(&mut self) -> FindAllPlatformsResult<Vec<FindAllPlatformsRow>> {
+        let mut platforms = self.client.tables().platform_view();
+        platforms.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut result = Vec::new();
+        for platform in platforms {
+            result.push(FindAllPlatformsRow::try_from_parts(&platform.name)?);
+        }
+        Ok(result)
+    }
+}
+
+impl<'a> FindAllPlatforms<'a, SqliteStore> {
+    // execute the query against a SqliteStore
+    pub(crate) fn query_sqlite(&mut self) -> FindAllPlatformsResult<Vec<FindAllPlatformsRow>> {
+        let names = SqlitePrepared::new("SELECT name FROM platform_view ORDER BY name")
+            .query(self.client, |row| row.get::<_, String>(0))
+            .context(SqliteError {
+                msg: "problem with select from platform_view",
+            })?;
+        names
+            .iter()
+            .map(|name| FindAllPlatformsRow::try_from_parts(name))
+            .collect()
+    }
+}
+
+impl<'a> FindAllPlatforms<'a, AsyncClient> {
+    pub async fn query(&mut self) -> FindAllPlatformsResult<Vec<FindAllPlatformsRow>> {
+        let rows = self
+            .prepare()
+            .query_async(self.client)
+            .await
+            .context(TokioPostgresError {
+                msg: "problem with select from platform_view",
+            })?;
+        Self::rows(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_query_string_default_works() {
+        let mut store = MemoryStore::new();
+        let fp = FindAllPlatforms::new(&mut store);
+        let query = fp.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT DISTINCT name FROM platform_view ORDER BY name"
+        );
+        assert!(ps.is_empty());
+    }
+}
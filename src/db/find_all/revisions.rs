@@ -1,18 +1,29 @@
 pub use crate::coords_error::{CoordsError, CoordsResult};
+use crate::db::async_packrat::AsyncClient;
+use crate::db::backend::Backend;
+use crate::db::memory::MemoryStore;
+use crate::db::pool::PackratPool;
+use crate::db::prepared::Prepared;
+use crate::db::query::SelectQuery;
+use crate::db::search_attribute::SearchMode;
 pub use crate::db::search_attribute::{LtreeSearchMode, OrderDirection, SearchAttribute};
+use crate::db::sqlite::views::parse_datetime;
+use crate::db::sqlite::{SqlitePrepared, SqliteStore};
+use crate::io::render::{FieldValue, Record};
 use crate::types::{IdType, LongIdType};
 pub use crate::Coords;
 pub use crate::Distribution;
 use chrono::{DateTime, Local /*TimeZone*/};
-use log;
-use postgres::types::ToSql;
-use postgres::Client;
+use postgres::{Client, Row};
 use serde::Serialize;
 use snafu::{ResultExt, Snafu};
+use std::cmp::Ordering;
 use std::fmt;
 use strum_macros::{AsRefStr, Display, EnumString, IntoStaticStr};
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr)]
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr, Clone, Copy,
+)]
 pub enum OrderRevisionBy {
     #[strum(serialize = "id", serialize = "Id", serialize = "ID", to_string = "id")]
     Id,
@@ -49,6 +60,18 @@ pub enum FindAllRevisionsError {
         msg: &'static str,
         source: tokio_postgres::error::Error,
     },
+    /// Error from sqlite
+    #[snafu(display("Sqlite Error: {} {}", msg, source))]
+    SqliteError {
+        msg: &'static str,
+        source: rusqlite::Error,
+    },
+    /// Unable to check a connection out of a PackratPool
+    #[snafu(display("Pool Error: {} {}", msg, source))]
+    PoolError {
+        msg: &'static str,
+        source: r2d2::Error,
+    },
 }
 
 /// A row returned from the  FindAllRevisions.query
@@ -71,6 +94,22 @@ impl fmt::Display for FindAllRevisionsRow {
     }
 }
 
+impl Record for FindAllRevisionsRow {
+    fn field_names() -> &'static [&'static str] {
+        &["id", "transaction_id", "author", "comment", "datetime"]
+    }
+
+    fn field_values(&self) -> Vec<FieldValue> {
+        vec![
+            self.id.into(),
+            self.transaction_id.into(),
+            self.author.as_str().into(),
+            self.comment.as_str().into(),
+            self.datetime.to_rfc3339().into(),
+        ]
+    }
+}
+
 impl FindAllRevisionsRow {
     /// New up a  FindAllRevisionsRow instance
     ///
@@ -151,26 +190,28 @@ impl FindAllRevisionsRow {
     }
 }
 /// Responsible for finding a distribution
-pub struct FindAllRevisions<'a> {
-    client: &'a mut Client,
+pub struct FindAllRevisions<'a, C = Client> {
+    client: &'a mut C,
     id: Option<IdType>,
     transaction_id: Option<LongIdType>,
     author: Option<&'a str>,
     order_by: Option<Vec<OrderRevisionBy>>,
     order_direction: Option<OrderDirection>,
     limit: Option<IdType>,
+    after: Option<IdType>,
+    page_size: Option<IdType>,
 }
 
-impl fmt::Debug for FindAllRevisions<'_> {
+impl<C> fmt::Debug for FindAllRevisions<'_, C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "FindAllRevisions(id:{:?} txid:{:?} author:{:?} order by:{:?} direction:{:?} limit:{:?})", 
         self.id, self.transaction_id, self.author, self.order_by, self.order_direction, self.limit)
     }
 }
 
-impl<'a> FindAllRevisions<'a> {
+impl<'a, C> FindAllRevisions<'a, C> {
     /// new up a FIndAllRevisions instance.
-    pub fn new(client: &'a mut Client) -> Self {
+    pub fn new(client: &'a mut C) -> Self {
         FindAllRevisions {
             client,
             id: None,
@@ -179,6 +220,8 @@ impl<'a> FindAllRevisions<'a> {
             order_by: None,
             order_direction: None,
             limit: None,
+            after: None,
+            page_size: None,
         }
     }
 
@@ -265,61 +308,76 @@ impl<'a> FindAllRevisions<'a> {
         self.limit = limit;
         self
     }
-    pub fn query(&mut self) -> FindAllRevisionsResult<Vec<FindAllRevisionsRow>> {
-        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
-        let mut query_str = "SELECT 
-                id, transaction_id, author, datetime, comment
-            FROM 
-                revision_view"
-            .to_string();
-        let mut where_and = "WHERE";
-        let mut prep_id = 1;
-        if let Some(ref id) = self.id {
-            query_str = format!(" {} id = ${}", where_and, prep_id);
-            where_and = "AND";
-            prep_id += 1;
-            params.push(id);
-        }
 
-        if let Some(ref transaction_id) = self.transaction_id {
-            query_str = format!(" {} transaction_id = ${}", where_and, prep_id);
-            where_and = "AND";
-            prep_id += 1;
-            params.push(transaction_id);
-        }
+    /// Return the revisions whose id is greater than the supplied id. Paged
+    /// queries are ordered by id, ignoring `order_by`, so that the id of the
+    /// last row of one page fetches the next.
+    ///
+    /// # Arguments
+    /// * `id` - The id of the last revision of the previous page
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn after(&mut self, id: IdType) -> &mut Self {
+        self.after = Some(id);
+        self
+    }
 
-        if let Some(ref author) = self.author {
-            query_str = format!(" {} author = ${}", where_and, prep_id);
-            //where_and = "AND";
-            //prep_id += 1;
-            params.push(author);
-        }
+    /// Return at most `size` revisions, ordered by id. See `after`.
+    ///
+    /// # Arguments
+    /// * `size` - The number of revisions in a page
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn page_size(&mut self, size: IdType) -> &mut Self {
+        self.page_size = Some(size);
+        self
+    }
 
-        let direction = match self.order_direction {
-            Some(ref dir) => dir.as_ref(),
-            None => "ASC",
-        };
+    fn is_paged(&self) -> bool {
+        self.after.is_some() || self.page_size.is_some()
+    }
 
-        if let Some(ref orderby) = self.order_by {
-            if orderby.len() > 0 {
-                let orderby = orderby
-                    .iter()
-                    .map(|x| format!("{} {}", x.as_ref(), direction))
-                    .collect::<Vec<_>>();
-                query_str = format!("{} ORDER BY {}", query_str, orderby.join(","));
+    /// The select statement issued by `query` against postgres, for
+    /// inspection via `SelectQuery::to_sql`
+    pub fn select(&self) -> SelectQuery {
+        let mut query = SelectQuery::new(
+            ["id", "transaction_id", "author", "datetime", "comment"],
+            "revision_view",
+        );
+        if let Some(id) = self.id {
+            query.filter("id", &SearchMode::Equal, id);
+        }
+        if let Some(transaction_id) = self.transaction_id {
+            query.filter("transaction_id", &SearchMode::Equal, transaction_id);
+        }
+        if let Some(author) = self.author {
+            query.filter("author", &SearchMode::Equal, author);
+        }
+        if self.is_paged() {
+            if let Some(after) = self.after {
+                let cnt = query.bind(after);
+                query.filter_sql(format!("id > ${}", cnt));
             }
+            query.order_by(["id"]).limit(self.page_size);
+        } else if let Some(ref orderby) = self.order_by {
+            query
+                .order_by(orderby)
+                .order_direction(Some(self.order_direction.unwrap_or(OrderDirection::Asc)));
         }
+        query
+    }
+
+    // build the query shared by the blocking and async clients
+    fn prepare(&self) -> Prepared {
+        self.select().into()
+    }
 
+    // convert the rows returned by the query
+    fn rows(rows: Vec<Row>) -> FindAllRevisionsResult<Vec<FindAllRevisionsRow>> {
         let mut result = Vec::new();
-        log::info!("SQL\n{}", query_str.as_str());
-        //log::info!("Prepared: {:?}", &params);
-        for row in
-            self.client
-                .query(query_str.as_str(), &params[..])
-                .context(TokioPostgresError {
-                    msg: "problem with select from revision_view",
-                })?
-        {
+        for row in rows {
             let id: IdType = row.get(0);
             let txid: LongIdType = row.get(1);
             let author: &str = row.get(2);
@@ -332,3 +390,240 @@ impl<'a> FindAllRevisions<'a> {
         Ok(result)
     }
 }
+
+impl<'a, C: Backend> FindAllRevisions<'a, C> {
+    pub fn query(&mut self) -> FindAllRevisionsResult<Vec<FindAllRevisionsRow>> {
+        C::find_all_revisions(self)
+    }
+}
+
+impl<'a> FindAllRevisions<'a, Client> {
+    // execute the query against postgres
+    pub(crate) fn query_postgres(&mut self) -> FindAllRevisionsResult<Vec<FindAllRevisionsRow>> {
+        let rows = self
+            .prepare()
+            .query(self.client)
+            .context(TokioPostgresError {
+                msg: "problem with select from revision_view",
+            })?;
+        Self::rows(rows)
+    }
+}
+
+impl<'a> FindAllRevisions<'a, PackratPool> {
+    // execute the query against a connection checked out of the pool for
+    // the duration of the query
+    pub(crate) fn query_pool(&mut self) -> FindAllRevisionsResult<Vec<FindAllRevisionsRow>> {
+        let mut conn = self.client.connection().context(PoolError {
+            msg: "unable to check out connection",
+        })?;
+        FindAllRevisions {
+            client: &mut *conn,
+            id: self.id,
+            transaction_id: self.transaction_id,
+            author: self.author,
+            order_by: self.order_by.clone(),
+            order_direction: self.order_direction,
+            limit: self.limit,
+            after: self.after,
+            page_size: self.page_size,
+        }
+        .query_postgres()
+    }
+}
+
+impl<'a> FindAllRevisions<'a, MemoryStore> {
+    // execute the query against the tables of a MemoryStore
+    pub(crate) fn query_memory(&mut self) -> FindAllRevisionsResult<Vec<FindAllRevisionsRow>> {
+        let mut revisions = self
+            .client
+            .tables()
+            .revisions
+            .iter()
+            .filter(|revision| match self.id {
+                Some(id) => revision.id == id,
+                None => true,
+            })
+            .filter(|revision| match self.transaction_id {
+                Some(transaction_id) => revision.transaction_id == transaction_id,
+                None => true,
+            })
+            .filter(|revision| match self.author {
+                Some(author) => revision.author == author,
+                None => true,
+            })
+            .collect::<Vec<_>>();
+        let descending = self.order_direction == Some(OrderDirection::Desc);
+        if self.is_paged() {
+            if let Some(after) = self.after {
+                revisions.retain(|revision| revision.id > after);
+            }
+            revisions.sort_by_key(|revision| revision.id);
+            if let Some(size) = self.page_size {
+                if size > 0 {
+                    revisions.truncate(size as usize);
+                }
+            }
+        } else if let Some(ref orderby) = self.order_by {
+            revisions.sort_by(|a, b| {
+                orderby.iter().fold(Ordering::Equal, |ordering, attr| {
+                    ordering.then_with(|| {
+                        let ordering = match attr {
+                            OrderRevisionBy::Id => a.id.cmp(&b.id),
+                            OrderRevisionBy::Author => a.author.cmp(&b.author),
+                            OrderRevisionBy::DateTime => a.datetime.cmp(&b.datetime),
+                        };
+                        if descending {
+                            ordering.reverse()
+                        } else {
+                            ordering
+                        }
+                    })
+                })
+            });
+        }
+        let mut result = Vec::new();
+        for revision in revisions {
+            result.push(FindAllRevisionsRow::try_from_parts(
+                revision.id,
+                revision.transaction_id,
+                revision.author.as_str(),
+                revision.datetime,
+                revision.comment.as_str(),
+            )?);
+        }
+        Ok(result)
+    }
+}
+
+impl<'a> FindAllRevisions<'a, SqliteStore> {
+    // execute the query against a SqliteStore
+    pub(crate) fn query_sqlite(&mut self) -> FindAllRevisionsResult<Vec<FindAllRevisionsRow>> {
+        let mut prepared = SqlitePrepared::new(
+            "SELECT id, transaction_id, author, comment, datetime FROM revision_view",
+        );
+        let mut clauses = Vec::new();
+        if let Some(id) = self.id {
+            clauses.push(format!("id = ?{}", prepared.push(id)));
+        }
+        if let Some(transaction_id) = self.transaction_id {
+            clauses.push(format!(
+                "transaction_id = ?{}",
+                prepared.push(transaction_id)
+            ));
+        }
+        if let Some(author) = self.author {
+            clauses.push(format!("author = ?{}", prepared.push(author.to_string())));
+        }
+        if let Some(after) = self.after {
+            clauses.push(format!("id > ?{}", prepared.push(after)));
+        }
+        if !clauses.is_empty() {
+            prepared.sql = format!("{} WHERE {}", prepared.sql, clauses.join(" AND "));
+        }
+        let direction = match self.order_direction {
+            Some(OrderDirection::Desc) => "DESC",
+            _ => "ASC",
+        };
+        // datetimes are stored in utc, so that they sort chronologically
+        // paged queries are ordered by id alone
+        let order_by = if self.is_paged() {
+            None
+        } else {
+            self.order_by.as_ref()
+        };
+        let mut orderby = order_by
+            .into_iter()
+            .flatten()
+            .map(|attr| {
+                let column = match attr {
+                    OrderRevisionBy::Id => "id",
+                    OrderRevisionBy::Author => "author",
+                    OrderRevisionBy::DateTime => "datetime",
+                };
+                format!("{} {}", column, direction)
+            })
+            .collect::<Vec<_>>();
+        orderby.push("id".to_string());
+        prepared.sql = format!("{} ORDER BY {}", prepared.sql, orderby.join(", "));
+        if let Some(size) = self.page_size.filter(|size| *size > 0) {
+            prepared.sql = format!("{} LIMIT {}", prepared.sql, size);
+        }
+        let revisions = prepared
+            .query(self.client, |row| {
+                Ok(FindAllRevisionsRow::new(
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get::<_, String>(2)?,
+                    parse_datetime(row, 4)?,
+                    row.get::<_, String>(3)?,
+                ))
+            })
+            .context(SqliteError {
+                msg: "problem with select from revision_view",
+            })?;
+        Ok(revisions)
+    }
+}
+
+impl<'a> FindAllRevisions<'a, AsyncClient> {
+    pub async fn query(&mut self) -> FindAllRevisionsResult<Vec<FindAllRevisionsRow>> {
+        let rows = self
+            .prepare()
+            .query_async(self.client)
+            .await
+            .context(TokioPostgresError {
+                msg: "problem with select from revision_view",
+            })?;
+        Self::rows(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::query::QueryParam;
+
+    #[test]
+    fn get_query_string_default_works() {
+        let mut store = MemoryStore::new();
+        let fr = FindAllRevisions::new(&mut store);
+        let query = fr.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT id, transaction_id, author, datetime, comment FROM revision_view"
+        );
+        assert!(ps.is_empty());
+    }
+    #[test]
+    fn get_query_string_with_author_and_order() {
+        let mut store = MemoryStore::new();
+        let mut fr = FindAllRevisions::new(&mut store);
+        fr.author("jgerber")
+            .order_by(vec![OrderRevisionBy::DateTime])
+            .order_direction(OrderDirection::Desc);
+        let query = fr.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT id, transaction_id, author, datetime, comment FROM revision_view \
+             WHERE author = $1 ORDER BY datetime DESC"
+        );
+        assert_eq!(ps, &["jgerber"]);
+    }
+    #[test]
+    fn get_query_string_with_page() {
+        let mut store = MemoryStore::new();
+        let mut fr = FindAllRevisions::new(&mut store);
+        fr.transaction_id(3).after(10).page_size(5);
+        let query = fr.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT id, transaction_id, author, datetime, comment FROM revision_view \
+             WHERE transaction_id = $1 AND id > $2 ORDER BY id LIMIT 5"
+        );
+        assert_eq!(ps, &[QueryParam::BigInt(3), QueryParam::Int(10)]);
+    }
+}
@@ -1,18 +1,28 @@
 pub use crate::coords_error::{CoordsError, CoordsResult};
+use crate::db::async_packrat::AsyncClient;
+use crate::db::backend::Backend;
+use crate::db::memory::MemoryStore;
+use crate::db::pool::PackratPool;
+use crate::db::prepared::Prepared;
+use crate::db::query::SelectQuery;
+use crate::db::search_attribute::SearchMode;
 pub use crate::db::search_attribute::{LtreeSearchMode, OrderDirection, SearchAttribute};
+use crate::db::sqlite::{SqlitePrepared, SqliteStore};
+use crate::io::render::{FieldValue, Record};
 use crate::types::IdType;
 pub use crate::Coords;
 pub use crate::Distribution;
 use log;
-use postgres::types::ToSql;
-use postgres::Client;
+use postgres::{Client, Row};
 use serde::Serialize;
 use snafu::{ResultExt, Snafu};
 use std::fmt;
 use std::str::FromStr;
 use strum_macros::{AsRefStr, Display, EnumString, IntoStaticStr};
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr)]
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr, Clone, Copy,
+)]
 pub enum OrderRoleBy {
     #[strum(
         serialize = "name",
@@ -72,6 +82,18 @@ pub enum FindAllRolesError {
         msg: &'static str,
         source: tokio_postgres::error::Error,
     },
+    /// Error from sqlite
+    #[snafu(display("Sqlite Error: {} {}", msg, source))]
+    SqliteError {
+        msg: &'static str,
+        source: rusqlite::Error,
+    },
+    /// Unable to check a connection out of a PackratPool
+    #[snafu(display("Pool Error: {} {}", msg, source))]
+    PoolError {
+        msg: &'static str,
+        source: r2d2::Error,
+    },
 }
 
 /// A row returned from the  FindAllRoles.query
@@ -87,6 +109,16 @@ impl fmt::Display for FindAllRolesRow {
     }
 }
 
+impl Record for FindAllRolesRow {
+    fn field_names() -> &'static [&'static str] {
+        &["role", "category"]
+    }
+
+    fn field_values(&self) -> Vec<FieldValue> {
+        vec![self.role.as_str().into(), self.category.as_str().into()]
+    }
+}
+
 impl FindAllRolesRow {
     /// New up a  FindAllRolesRow instance
     ///
@@ -129,8 +161,8 @@ impl FindAllRolesRow {
     }
 }
 /// Responsible for finding a distribution
-pub struct FindAllRoles<'a> {
-    client: &'a mut Client,
+pub struct FindAllRoles<'a, C = Client> {
+    client: &'a mut C,
     role: Option<&'a str>,
     category: Option<&'a str>,
     order_by: Option<Vec<OrderRoleBy>>,
@@ -138,15 +170,15 @@ pub struct FindAllRoles<'a> {
     limit: Option<IdType>,
 }
 
-impl fmt::Debug for FindAllRoles<'_> {
+impl<C> fmt::Debug for FindAllRoles<'_, C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "FindAllRoles({:?} {:?})", self.role, self.category)
     }
 }
 
-impl<'a> FindAllRoles<'a> {
+impl<'a, C> FindAllRoles<'a, C> {
     /// new up a FIndAllRoles instance.
-    pub fn new(client: &'a mut Client) -> Self {
+    pub fn new(client: &'a mut C) -> Self {
         FindAllRoles {
             client,
             category: None,
@@ -200,14 +232,11 @@ impl<'a> FindAllRoles<'a> {
         self
     }
 
-    pub fn query(&mut self) -> FindAllRolesResult<Vec<FindAllRolesRow>> {
-        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
-        let mut query_str = "SELECT DISTINCT 
-                name,
-                category
-            FROM 
-                role_view WHERE name <> 'any'"
-            .to_string();
+    /// The select statement issued by `query` against postgres, for
+    /// inspection via `SelectQuery::to_sql`
+    pub fn select(&self) -> SelectQuery {
+        let mut query = SelectQuery::new(["name", "category"], "role_view");
+        query.distinct().filter_sql("name <> 'any'");
         let category = self.category.unwrap_or("role");
         if self.category.is_some() {
             let categories = Categories::from_str(category);
@@ -215,29 +244,28 @@ impl<'a> FindAllRoles<'a> {
                 //TODO: have function return custom error
                 log::error!("category specificed is not valid: {}", category);
             } else if category != "any" {
-                query_str = format!("{} AND category = $1", query_str);
-                params.push(&category);
+                query.filter("category", &SearchMode::Equal, category);
             }
         }
 
         //let order_by = self.order_by.as_ref().unwrap_or(&vec![OrderRoleBy::Name]);
 
-        if let Some(ref orderby) = self.order_by {
-            let orderby = orderby.iter().map(|x| x.as_ref()).collect::<Vec<_>>();
-            query_str = format!("{} ORDER BY {}", query_str, orderby.join(","));
-        } else {
-            query_str = format!("{}  ORDER BY name", query_str);
-        }
+        match self.order_by {
+            Some(ref orderby) => query.order_by(orderby),
+            None => query.order_by(["name"]),
+        };
+        query
+    }
+
+    // build the query shared by the blocking and async clients
+    fn prepare(&self) -> Prepared {
+        self.select().into()
+    }
+
+    // convert the rows returned by the query
+    fn rows(rows: Vec<Row>) -> FindAllRolesResult<Vec<FindAllRolesRow>> {
         let mut result = Vec::new();
-        log::info!("SQL\n{}", query_str.as_str());
-        log::info!("Arguments\n{:?}", &params);
-        for row in
-            self.client
-                .query(query_str.as_str(), &params[..])
-                .context(TokioPostgresError {
-                    msg: "problem with select from role_view",
-                })?
-        {
+        for row in rows {
             let role_name = row.get(0);
             let category = row.get(1);
             result.push(FindAllRolesRow::try_from_parts(role_name, category)?);
@@ -245,3 +273,159 @@ impl<'a> FindAllRoles<'a> {
         Ok(result)
     }
 }
+
+impl<'a, C: Backend> FindAllRoles<'a, C> {
+    pub fn query(&mut self) -> FindAllRolesResult<Vec<FindAllRolesRow>> {
+        C::find_all_roles(self)
+    }
+}
+
+impl<'a> FindAllRoles<'a, Client> {
+    // execute the query against postgres
+    pub(crate) fn query_postgres(&mut self) -> FindAllRolesResult<Vec<FindAllRolesRow>> {
+        let rows = self
+            .prepare()
+            .query(self.client)
+            .context(TokioPostgresError {
+                msg: "problem with select from role_view",
+            })?;
+        Self::rows(rows)
+    }
+}
+
+impl<'a> FindAllRoles<'a, PackratPool> {
+    // execute the query against a connection checked out of the pool for
+    // the duration of the query
+    pub(crate) fn query_pool(&mut self) -> FindAllRolesResult<Vec<FindAllRolesRow>> {
+        let mut conn = self.client.connection().context(PoolError {
+            msg: "unable to check out connection",
+        })?;
+        FindAllRoles {
+            client: &mut *conn,
+            role: self.role,
+            category: self.category,
+            order_by: self.order_by.clone(),
+            order_direction: self.order_direction,
+            limit: self.limit,
+        }
+        .query_postgres()
+    }
+}
+
+impl<'a> FindAllRoles<'a, MemoryStore> {
+    // execute the query against the tables of a MemoryStore
+    pub(crate) fn query_memory(&mut self) -> FindAllRolesResult<Vec<FindAllRolesRow>> {
+        let category = self
+            .category
+            .filter(|category| Categories::from_str(category).is_ok() && *category != "any");
+        let mut roles = self
+            .client
+            .tables()
+            .role_view()
+            .into_iter()
+            .filter(|role| role.name != "any")
+            .filter(|role| match category {
+                Some(category) => role.category == category,
+                None => true,
+            })
+            .collect::<Vec<_>>();
+        let orderby = self.order_by.as_deref().unwrap_or(&[OrderRoleBy::Name]);
+        roles.sort_by_cached_key(|role| {
+            orderby
+                .iter()
+                .map(|attr| match attr {
+                    OrderRoleBy::Name => role.name.clone(),
+                    OrderRoleBy::Category => role.category.to_string(),
+                })
+                .collect::<Vec<_>>()
+        });
+        let mut result = Vec::new();
+        for role in roles {
+            result.push(FindAllRolesRow::try_from_parts(&role.name, role.category)?);
+        }
+        Ok(result)
+    }
+}
+
+impl<'a> FindAllRoles<'a, SqliteStore> {
+    // execute the query against a SqliteStore
+    pub(crate) fn query_sqlite(&mut self) -> FindAllRolesResult<Vec<FindAllRolesRow>> {
+        let mut prepared =
+            SqlitePrepared::new("SELECT name, category FROM role_view WHERE name <> 'any'");
+        if let Some(category) = self
+            .category
+            .filter(|category| Categories::from_str(category).is_ok() && *category != "any")
+        {
+            let idx = prepared.push(category.to_string());
+            prepared.sql = format!("{} AND category = ?{}", prepared.sql, idx);
+        }
+        let orderby = self
+            .order_by
+            .as_deref()
+            .unwrap_or(&[OrderRoleBy::Name])
+            .iter()
+            .map(|attr| match attr {
+                OrderRoleBy::Name => "name",
+                OrderRoleBy::Category => "category",
+            })
+            .collect::<Vec<_>>();
+        prepared.sql = format!("{} ORDER BY {}, path", prepared.sql, orderby.join(", "));
+        let roles = prepared
+            .query(self.client, |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .context(SqliteError {
+                msg: "problem with select from role_view",
+            })?;
+        roles
+            .iter()
+            .map(|(name, category)| FindAllRolesRow::try_from_parts(name, category))
+            .collect()
+    }
+}
+
+impl<'a> FindAllRoles<'a, AsyncClient> {
+    pub async fn query(&mut self) -> FindAllRolesResult<Vec<FindAllRolesRow>> {
+        let rows = self
+            .prepare()
+            .query_async(self.client)
+            .await
+            .context(TokioPostgresError {
+                msg: "problem with select from role_view",
+            })?;
+        Self::rows(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_query_string_default_works() {
+        let mut store = MemoryStore::new();
+        let fr = FindAllRoles::new(&mut store);
+        let query = fr.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT DISTINCT name, category FROM role_view WHERE name <> 'any' ORDER BY name"
+        );
+        assert!(ps.is_empty());
+    }
+    #[test]
+    fn get_query_string_with_category_and_order() {
+        let mut store = MemoryStore::new();
+        let mut fr = FindAllRoles::new(&mut store);
+        fr.category("subrole")
+            .order_by(vec![OrderRoleBy::Category, OrderRoleBy::Name]);
+        let query = fr.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT DISTINCT name, category FROM role_view WHERE name <> 'any' \
+             AND category = $1 ORDER BY category, name"
+        );
+        assert_eq!(ps, &["subrole"]);
+    }
+}
@@ -1,10 +1,18 @@
 pub use crate::coords_error::{CoordsError, CoordsResult};
+use crate::db::async_packrat::AsyncClient;
+use crate::db::backend::Backend;
+use crate::db::memory::functions::like;
+use crate::db::memory::MemoryStore;
+use crate::db::pool::PackratPool;
+use crate::db::prepared::Prepared;
+use crate::db::query::SelectQuery;
+use crate::db::search_attribute::SearchMode;
 pub use crate::db::search_attribute::{LtreeSearchMode, OrderDirection, SearchAttribute};
+use crate::db::sqlite::{like_clause, SqlitePrepared, SqliteStore};
+use crate::io::render::{FieldValue, Record};
 pub use crate::Coords;
 pub use crate::Distribution;
-use log;
-use postgres::types::ToSql;
-use postgres::Client;
+use postgres::{Client, Row};
 use serde::Serialize;
 use snafu::{ResultExt, Snafu};
 use std::fmt;
@@ -38,6 +46,18 @@ pub enum FindAllSitesError {
         msg: &'static str,
         source: tokio_postgres::error::Error,
     },
+    /// Error from sqlite
+    #[snafu(display("Sqlite Error: {} {}", msg, source))]
+    SqliteError {
+        msg: &'static str,
+        source: rusqlite::Error,
+    },
+    /// Unable to check a connection out of a PackratPool
+    #[snafu(display("Pool Error: {} {}", msg, source))]
+    PoolError {
+        msg: &'static str,
+        source: r2d2::Error,
+    },
 }
 
 /// A row returned from the  FindAllSites.query
@@ -52,6 +72,16 @@ impl fmt::Display for FindAllSitesRow {
     }
 }
 
+impl Record for FindAllSitesRow {
+    fn field_names() -> &'static [&'static str] {
+        &["name"]
+    }
+
+    fn field_values(&self) -> Vec<FieldValue> {
+        vec![self.name.as_str().into()]
+    }
+}
+
 impl FindAllSitesRow {
     /// New up a  FindAllSitesRow instance
     ///
@@ -92,20 +122,20 @@ impl FindAllSitesRow {
     }
 }
 /// Responsible for finding a distribution
-pub struct FindAllSites<'a> {
-    client: &'a mut Client,
+pub struct FindAllSites<'a, C = Client> {
+    client: &'a mut C,
     name: Option<&'a str>,
 }
 
-impl fmt::Debug for FindAllSites<'_> {
+impl<C> fmt::Debug for FindAllSites<'_, C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "FindAllSites({:?})", self.name)
     }
 }
 
-impl<'a> FindAllSites<'a> {
+impl<'a, C> FindAllSites<'a, C> {
     /// new up a FIndAllSites instance.
-    pub fn new(client: &'a mut Client) -> Self {
+    pub fn new(client: &'a mut C) -> Self {
         FindAllSites { client, name: None }
     }
 
@@ -126,38 +156,171 @@ impl<'a> FindAllSites<'a> {
         self
     }
 
-    pub fn query(&mut self) -> FindAllSitesResult<Vec<FindAllSitesRow>> {
-        //let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
-        let mut op = "=";
-        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
-        let mut query_str = "SELECT DISTINCT 
-                name
-            FROM 
-                site_view WHERE name <> 'any'"
-            .to_string();
-
-        let name = self.name.unwrap_or("any");
-        if self.name.is_some() {
-            if name.contains("%") {
-                op = "LIKE";
-            }
-            params.push(&name);
-            query_str = format!("{} AND name {} $1", query_str, op);
+    /// The select statement issued by `query` against postgres, for
+    /// inspection via `SelectQuery::to_sql`
+    pub fn select(&self) -> SelectQuery {
+        let mut query = SelectQuery::new(["name"], "site_view");
+        query.distinct().filter_sql("name <> 'any'");
+        if let Some(name) = self.name {
+            let op = if name.contains('%') {
+                SearchMode::Like
+            } else {
+                SearchMode::Equal
+            };
+            query.filter("name", &op, name);
         }
-        query_str = format!("{}  ORDER BY name", query_str);
-        log::info!("SQL\n{}", query_str.as_str());
-        log::info!("Arguments:\n{:?}", &params);
+        query.order_by(["name"]);
+        query
+    }
+
+    // build the query shared by the blocking and async clients
+    fn prepare(&self) -> Prepared {
+        self.select().into()
+    }
+
+    // convert the rows returned by the query
+    fn rows(rows: Vec<Row>) -> FindAllSitesResult<Vec<FindAllSitesRow>> {
         let mut result = Vec::new();
-        for row in
-            self.client
-                .query(query_str.as_str(), &params[..])
-                .context(TokioPostgresError {
-                    msg: "problem with select from site_view",
-                })?
-        {
+        for row in rows {
             let name = row.get(0);
             result.push(FindAllSitesRow::try_from_parts(name)?);
         }
         Ok(result)
     }
 }
+
+impl<'a, C: Backend> FindAllSites<'a, C> {
+    pub fn query(&mut self) -> FindAllSitesResult<Vec<FindAllSitesRow>> {
+        C::find_all_sites(self)
+    }
+}
+
+impl<'a> FindAllSites<'a, Client> {
+    // execute the query against postgres
+    pub(crate) fn query_postgres(&mut self) -> FindAllSitesResult<Vec<FindAllSitesRow>> {
+        let rows = self
+            .prepare()
+            .query(self.client)
+            .context(TokioPostgresError {
+                msg: "problem with select from site_view",
+            })?;
+        Self::rows(rows)
+    }
+}
+
+impl<'a> FindAllSites<'a, PackratPool> {
+    // execute the query against a connection checked out of the pool for
+    // the duration of the query
+    pub(crate) fn query_pool(&mut self) -> FindAllSitesResult<Vec<FindAllSitesRow>> {
+        let mut conn = self.client.connection().context(PoolError {
+            msg: "unable to check out connection",
+        })?;
+        FindAllSites {
+            client: &mut *conn,
+            name: self.name,
+        }
+        .query_postgres()
+    }
+}
+
+impl<'a> FindAllSites<'a, MemoryStore> {
+    // execute the query against the tables of a MemoryStore
+    pub(crate) fn query_memory(&mut self) -> FindAllSitesResult<Vec<FindAllSitesRow>> {
+        let mut sites = self
+            .client
+            .tables()
+            .site_view()
+            .into_iter()
+            .filter(|site| site.name != "any")
+            .filter(|site| match self.name {
+                Some(name) if name.contains("%") => like(&site.name, name),
+                Some(name) => site.name == name,
+                None => true,
+            })
+            .collect::<Vec<_>>();
+        sites.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut result = Vec::new();
+        for site in sites {
+            result.push(FindAllSitesRow::try_from_parts(&site.name)?);
+        }
+        Ok(result)
+    }
+}
+
+impl<'a> FindAllSites<'a, SqliteStore> {
+    // execute the query against a SqliteStore
+    pub(crate) fn query_sqlite(&mut self) -> FindAllSitesResult<Vec<FindAllSitesRow>> {
+        let mut prepared = SqlitePrepared::new("SELECT name FROM site_view WHERE name <> 'any'");
+        if let Some(name) = self.name {
+            let idx = prepared.push(name.to_string());
+            prepared.sql = format!("{} AND {}", prepared.sql, like_clause("name", idx, name));
+        }
+        prepared.sql = format!("{} ORDER BY name", prepared.sql);
+        let names = prepared
+            .query(self.client, |row| row.get::<_, String>(0))
+            .context(SqliteError {
+                msg: "problem with select from site_view",
+            })?;
+        names
+            .iter()
+            .map(|name| FindAllSitesRow::try_from_parts(name))
+            .collect()
+    }
+}
+
+impl<'a> FindAllSites<'a, AsyncClient> {
+    pub async fn query(&mut self) -> FindAllSitesResult<Vec<FindAllSitesRow>> {
+        let rows = self
+            .prepare()
+            .query_async(self.client)
+            .await
+            .context(TokioPostgresError {
+                msg: "problem with select from site_view",
+            })?;
+        Self::rows(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_query_string_default_works() {
+        let mut store = MemoryStore::new();
+        let fs = FindAllSites::new(&mut store);
+        let query = fs.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT DISTINCT name FROM site_view WHERE name <> 'any' ORDER BY name"
+        );
+        assert!(ps.is_empty());
+    }
+    #[test]
+    fn get_query_string_with_name() {
+        let mut store = MemoryStore::new();
+        let mut fs = FindAllSites::new(&mut store);
+        fs.name("portland");
+        let query = fs.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT DISTINCT name FROM site_view WHERE name <> 'any' AND name = $1 ORDER BY name"
+        );
+        assert_eq!(ps, &["portland"]);
+    }
+    #[test]
+    fn get_query_string_with_like_name() {
+        let mut store = MemoryStore::new();
+        let mut fs = FindAllSites::new(&mut store);
+        fs.name("port%");
+        let query = fs.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT DISTINCT name FROM site_view WHERE name <> 'any' AND name LIKE $1 ORDER BY name"
+        );
+        assert_eq!(ps, &["port%"]);
+    }
+}
@@ -1,11 +1,18 @@
 pub use crate::coords_error::{CoordsError, CoordsResult};
+use crate::db::async_packrat::AsyncClient;
+use crate::db::backend::Backend;
+use crate::db::memory::MemoryStore;
+use crate::db::pool::PackratPool;
+use crate::db::prepared::Prepared;
+use crate::db::query::SelectQuery;
+use crate::db::search_attribute::SearchMode;
 pub use crate::db::search_attribute::{LtreeSearchMode, OrderDirection, SearchAttribute};
+use crate::db::sqlite::{SqlitePrepared, SqliteStore};
+use crate::io::render::{FieldValue, Record};
 use crate::types::IdType;
 pub use crate::Coords;
 pub use crate::Distribution;
-use log;
-use postgres::types::ToSql;
-use postgres::Client;
+use postgres::{Client, Row};
 use serde::Serialize;
 use snafu::{ResultExt, Snafu};
 use std::fmt;
@@ -27,6 +34,18 @@ pub enum FindAllWithsError {
         msg: &'static str,
         source: tokio_postgres::error::Error,
     },
+    /// Error from sqlite
+    #[snafu(display("Sqlite Error: {} {}", msg, source))]
+    SqliteError {
+        msg: &'static str,
+        source: rusqlite::Error,
+    },
+    /// Unable to check a connection out of a PackratPool
+    #[snafu(display("Pool Error: {} {}", msg, source))]
+    PoolError {
+        msg: &'static str,
+        source: r2d2::Error,
+    },
 }
 
 /// A row returned from the  FindAllWiths.query
@@ -49,6 +68,21 @@ impl fmt::Display for FindAllWithsRow {
     }
 }
 
+impl Record for FindAllWithsRow {
+    fn field_names() -> &'static [&'static str] {
+        &["id", "versionpin_id", "with", "order"]
+    }
+
+    fn field_values(&self) -> Vec<FieldValue> {
+        vec![
+            self.id.into(),
+            self.vpin_id.into(),
+            self.with.as_str().into(),
+            self.order.into(),
+        ]
+    }
+}
+
 impl FindAllWithsRow {
     /// New up a  FindAllWithsRow instance
     ///
@@ -95,32 +129,36 @@ impl FindAllWithsRow {
     }
 }
 /// Responsible for finding a distribution
-pub struct FindAllWiths<'a> {
-    client: &'a mut Client,
+pub struct FindAllWiths<'a, C = Client> {
+    client: &'a mut C,
     vpin_id: IdType,
 }
 
-impl<'a> FindAllWiths<'a> {
-    pub fn new(client: &'a mut Client, vpin_id: IdType) -> Self {
+impl<'a, C> FindAllWiths<'a, C> {
+    pub fn new(client: &'a mut C, vpin_id: IdType) -> Self {
         FindAllWiths { client, vpin_id }
     }
 
-    pub fn query(&mut self) -> Result<Vec<FindAllWithsRow>, FindAllWithsError> {
-        let query_str = "SELECT id, versionpin, package, pinorder
-        FROM withpackage WHERE versionpin = $1 ORDER BY pinorder"
-            .to_string();
+    /// The select statement issued by `query` against postgres, for
+    /// inspection via `SelectQuery::to_sql`
+    pub fn select(&self) -> SelectQuery {
+        let mut query =
+            SelectQuery::new(["id", "versionpin", "package", "pinorder"], "withpackage");
+        query
+            .filter("versionpin", &SearchMode::Equal, self.vpin_id)
+            .order_by(["pinorder"]);
+        query
+    }
+
+    // build the query shared by the blocking and async clients
+    fn prepare(&self) -> Prepared {
+        self.select().into()
+    }
+
+    // convert the rows returned by the query
+    fn rows(rows: Vec<Row>) -> Result<Vec<FindAllWithsRow>, FindAllWithsError> {
         let mut result = Vec::new();
-        let qstr = query_str.as_str();
-        let prepared_args: &[&(dyn ToSql + std::marker::Sync)] = &[&self.vpin_id];
-        log::info!("SQL\n{}", qstr);
-        log::info!("Arguents\n{:?}", prepared_args);
-        for row in self
-            .client
-            .query(qstr, prepared_args)
-            .context(TokioPostgresError {
-                msg: "problem with select from withpackage",
-            })?
-        {
+        for row in rows {
             let id: IdType = row.get(0);
             let vpin_id: IdType = row.get(1);
             let with: String = row.get(2);
@@ -130,3 +168,120 @@ impl<'a> FindAllWiths<'a> {
         Ok(result)
     }
 }
+
+impl<'a, C: Backend> FindAllWiths<'a, C> {
+    pub fn query(&mut self) -> Result<Vec<FindAllWithsRow>, FindAllWithsError> {
+        C::find_all_versionpin_withs(self)
+    }
+}
+
+impl<'a> FindAllWiths<'a, Client> {
+    // execute the query against postgres
+    pub(crate) fn query_postgres(&mut self) -> Result<Vec<FindAllWithsRow>, FindAllWithsError> {
+        let rows = self
+            .prepare()
+            .query(self.client)
+            .context(TokioPostgresError {
+                msg: "problem with select from withpackage",
+            })?;
+        Self::rows(rows)
+    }
+}
+
+impl<'a> FindAllWiths<'a, PackratPool> {
+    // execute the query against a connection checked out of the pool for
+    // the duration of the query
+    pub(crate) fn query_pool(&mut self) -> Result<Vec<FindAllWithsRow>, FindAllWithsError> {
+        let mut conn = self.client.connection().context(PoolError {
+            msg: "unable to check out connection",
+        })?;
+        FindAllWiths {
+            client: &mut *conn,
+            vpin_id: self.vpin_id,
+        }
+        .query_postgres()
+    }
+}
+
+impl<'a> FindAllWiths<'a, MemoryStore> {
+    // execute the query against the tables of a MemoryStore
+    pub(crate) fn query_memory(&mut self) -> Result<Vec<FindAllWithsRow>, FindAllWithsError> {
+        let mut withs = self
+            .client
+            .tables()
+            .withpackages
+            .iter()
+            .filter(|with| with.versionpin == self.vpin_id)
+            .collect::<Vec<_>>();
+        withs.sort_by_key(|with| with.pinorder);
+        let mut result = Vec::new();
+        for with in withs {
+            result.push(FindAllWithsRow::try_from_parts(
+                with.id,
+                with.versionpin,
+                with.package.clone(),
+                with.pinorder,
+            )?);
+        }
+        Ok(result)
+    }
+}
+
+impl<'a> FindAllWiths<'a, SqliteStore> {
+    // execute the query against a SqliteStore
+    pub(crate) fn query_sqlite(&mut self) -> Result<Vec<FindAllWithsRow>, FindAllWithsError> {
+        let mut prepared = SqlitePrepared::new("");
+        let idx = prepared.push(self.vpin_id);
+        prepared.sql = format!(
+            "SELECT id, versionpin, package, pinorder FROM withpackage
+                WHERE versionpin = ?{} ORDER BY pinorder, id",
+            idx
+        );
+        let withs = prepared
+            .query(self.client, |row| {
+                Ok(FindAllWithsRow::new(
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                ))
+            })
+            .context(SqliteError {
+                msg: "problem with select from withpackage",
+            })?;
+        Ok(withs)
+    }
+}
+
+impl<'a> FindAllWiths<'a, AsyncClient> {
+    pub async fn query(&mut self) -> Result<Vec<FindAllWithsRow>, FindAllWithsError> {
+        let rows = self
+            .prepare()
+            .query_async(self.client)
+            .await
+            .context(TokioPostgresError {
+                msg: "problem with select from withpackage",
+            })?;
+        Self::rows(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::query::QueryParam;
+
+    #[test]
+    fn get_query_string_works() {
+        let mut store = MemoryStore::new();
+        let fw = FindAllWiths::new(&mut store, 12);
+        let query = fw.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT id, versionpin, package, pinorder FROM withpackage \
+             WHERE versionpin = $1 ORDER BY pinorder"
+        );
+        assert_eq!(ps, &[QueryParam::Int(12)]);
+    }
+}
@@ -1,11 +1,23 @@
 pub use crate::coords_error::{CoordsError, CoordsResult};
+use crate::db::async_packrat::AsyncClient;
+use crate::db::backend::Backend;
+pub use crate::db::history::AsOf;
+use crate::db::history::{self, HistoryError};
+use crate::db::memory::views::{CoordPaths, VersionPinView};
+use crate::db::memory::MemoryStore;
+use crate::db::pool::PackratPool;
+use crate::db::prepared::Prepared;
+use crate::db::query::SelectQuery;
 pub use crate::db::search_attribute::{LtreeSearchMode, OrderDirection, SearchAttribute};
+use crate::db::sqlite::SqliteStore;
+use crate::io::render::{FieldValue, Record};
+use crate::resolver::coords_contain;
 use crate::types::IdType;
 pub use crate::Coords;
 pub use crate::Distribution;
-use log;
-use postgres::types::ToSql;
-use postgres::Client;
+use itertools::Either;
+use postgres::fallible_iterator::FallibleIterator;
+use postgres::{Client, Row};
 use serde::{Deserialize, Serialize};
 use snafu::{ResultExt, Snafu};
 use std::fmt;
@@ -37,6 +49,21 @@ pub enum FindAllVersionPinsError {
         msg: &'static str,
         source: tokio_postgres::error::Error,
     },
+    /// Error from sqlite
+    #[snafu(display("Sqlite Error: {} {}", msg, source))]
+    SqliteError {
+        msg: &'static str,
+        source: rusqlite::Error,
+    },
+    /// Unable to check a connection out of a PackratPool
+    #[snafu(display("Pool Error: {} {}", msg, source))]
+    PoolError {
+        msg: &'static str,
+        source: r2d2::Error,
+    },
+    /// An error has occured while reconstructing the historical versionpins
+    #[snafu(display("Error reconstructing historical versionpins: {}", source))]
+    AsOfError { source: HistoryError },
 }
 
 /// A row returned from the FindAllVersionPins query
@@ -78,6 +105,40 @@ impl fmt::Display for FindAllVersionPinsRow {
     }
 }
 
+impl Record for FindAllVersionPinsRow {
+    fn field_names() -> &'static [&'static str] {
+        &[
+            "versionpin_id",
+            "distribution_id",
+            "pkgcoord_id",
+            "distribution",
+            "package",
+            "version",
+            "level",
+            "role",
+            "platform",
+            "site",
+            "withs",
+        ]
+    }
+
+    fn field_values(&self) -> Vec<FieldValue> {
+        vec![
+            self.versionpin_id.into(),
+            self.distribution_id.into(),
+            self.pkgcoord_id.into(),
+            self.distribution.distribution().into(),
+            self.distribution.package().into(),
+            self.distribution.version().into(),
+            self.coords.level().to_string().into(),
+            self.coords.role().to_string().into(),
+            self.coords.platform().to_string().into(),
+            self.coords.site().to_string().into(),
+            self.withs.clone().into(),
+        ]
+    }
+}
+
 impl FindAllVersionPinsRow {
     /// New up a  FindAllVersionPinsRow instance
     ///
@@ -144,10 +205,7 @@ impl FindAllVersionPinsRow {
 
         let coords = Coords::try_from_parts(level, role, platform, site).context(
             CoordsTryFromPartsError {
-                coords: format!(
-                    "(level:'{}' role:'{}' platform:'{}' site:'{}')",
-                    level, role, platform, site
-                ),
+                coords: Coords::display_parts(level, role, platform, site),
             },
         )?;
 
@@ -207,8 +265,8 @@ impl FindAllVersionPinsRow {
 }
 /// Responsible for finding all VersionPins which meet certain criteria, which the
 /// struct tracks
-pub struct FindAllVersionPins<'a> {
-    client: &'a mut Client,
+pub struct FindAllVersionPins<'a, C = Client> {
+    client: &'a mut C,
     package: Option<&'a str>,
     version: Option<&'a str>,
     level: Option<&'a str>,
@@ -219,20 +277,23 @@ pub struct FindAllVersionPins<'a> {
     order_by: Option<Vec<SearchAttribute>>,
     order_direction: Option<OrderDirection>,
     limit: Option<IdType>,
+    after: Option<IdType>,
+    page_size: Option<IdType>,
     search_mode: LtreeSearchMode,
+    as_of: Option<AsOf>,
 }
 
-impl<'a> FindAllVersionPins<'a> {
+impl<'a, C> FindAllVersionPins<'a, C> {
     /// New up a FindAllVersionPins instance
     ///
     /// # Arguments
     ///
-    /// * `client` - a mutable reference to a Client instance
+    /// * `client` - a mutable reference to a Client or AsyncClient instance
     ///
     /// # Returns
     ///
     /// * FindAllVersionPins instance
-    pub fn new(client: &'a mut Client) -> Self {
+    pub fn new(client: &'a mut C) -> Self {
         FindAllVersionPins {
             client,
             package: None,
@@ -245,7 +306,10 @@ impl<'a> FindAllVersionPins<'a> {
             order_by: None,
             order_direction: None,
             limit: None,
+            after: None,
+            page_size: None,
             search_mode: LtreeSearchMode::Ancestor,
+            as_of: None,
         }
     }
 
@@ -593,6 +657,36 @@ impl<'a> FindAllVersionPins<'a> {
         self
     }
 
+    /// Return the versionpins whose id is greater than the supplied id. Paged
+    /// queries are ordered by versionpin id, ignoring `order_by` and `limit`,
+    /// so that the id of the last row of one page fetches the next.
+    ///
+    /// # Arguments
+    ///
+    /// * `versionpin_id` - The id of the last versionpin of the previous page
+    ///
+    /// # Returns
+    ///
+    /// * Mutable reference to self
+    pub fn after(&mut self, versionpin_id: IdType) -> &mut Self {
+        self.after = Some(versionpin_id);
+        self
+    }
+
+    /// Return at most `size` versionpins, ordered by versionpin id. See `after`.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - The number of versionpins in a page
+    ///
+    /// # Returns
+    ///
+    /// * Mutable reference to self
+    pub fn page_size(&mut self, size: IdType) -> &mut Self {
+        self.page_size = Some(size);
+        self
+    }
+
     /// Set the search mode of the search
     ///
     /// # Arguments
@@ -622,6 +716,214 @@ impl<'a> FindAllVersionPins<'a> {
         self
     }
 
+    /// Query the versionpins as they stood at an earlier revision or date,
+    /// reconstructed from the audit log, rather than the live tables.
+    ///
+    /// # Arguments
+    ///
+    /// * `as_of` - A revision id or `DateTime<Local>`
+    ///
+    /// # Returns
+    ///
+    /// * Mutable reference to self
+    pub fn as_of<T: Into<AsOf>>(&mut self, as_of: T) -> &mut Self {
+        self.as_of = Some(as_of.into());
+        self
+    }
+
+    /// Optionally query the versionpins as they stood at an earlier revision or date.
+    ///
+    /// # Arguments
+    ///
+    /// * `as_of` - An Option wrapped AsOf instance
+    ///
+    /// # Returns
+    ///
+    /// * Mutable reference to self
+    pub fn as_of_opt(&mut self, as_of: Option<AsOf>) -> &mut Self {
+        self.as_of = as_of;
+        self
+    }
+
+    /// The select statement issued by `query` against postgres, for
+    /// inspection via `SelectQuery::to_sql`
+    pub fn select(&self) -> SelectQuery {
+        let mut query = SelectQuery::function(
+            [
+                "id",
+                "distribution_id",
+                "pkgcoord_id",
+                "distribution",
+                "level_name",
+                "role_name",
+                "site_name",
+                "platform_name",
+                "withs",
+            ],
+            "findall_versionpins",
+        );
+        query
+            .named_arg("role", self.role.unwrap_or("any"))
+            .named_arg("platform", self.platform.unwrap_or("any"))
+            .named_arg("level", self.level.unwrap_or("facility"))
+            .named_arg("site", self.site.unwrap_or("any"))
+            .named_arg("search_mode", self.search_mode.to_string());
+        if let Some(package) = self.package {
+            query.named_arg("package_name", package);
+        }
+        if let Some(version) = self.version {
+            query.named_arg("version_name", version);
+        }
+        if self.isolate_facility {
+            if self.level.unwrap_or("facility") == "facility" {
+                query.filter_sql("level_name = 'facility'");
+            } else {
+                query.filter_sql("level_name <> 'facility'");
+            }
+        }
+        if self.is_paged() {
+            if let Some(after) = self.after {
+                let cnt = query.bind(after);
+                query.filter_sql(format!("id > ${}", cnt));
+            }
+            query.order_by(["id"]).limit(self.page_size);
+        } else {
+            if let Some(ref orderby) = self.order_by {
+                query
+                    .order_by(orderby.iter().map(match_attrib))
+                    .order_direction(self.order_direction);
+            }
+            query.limit(self.limit);
+        }
+        query
+    }
+
+    // build the query shared by the blocking and async clients
+    fn prepare(&self) -> Prepared {
+        self.select().into()
+    }
+
+    fn is_paged(&self) -> bool {
+        self.after.is_some() || self.page_size.is_some()
+    }
+
+    // convert a row returned by the query
+    fn row(row: &Row) -> Result<FindAllVersionPinsRow, FindAllVersionPinsError> {
+        let id: IdType = row.get(0);
+        let dist_id: IdType = row.get(1);
+        let pkgcoord_id: IdType = row.get(2);
+        let distribution: &str = row.get(3);
+        let level_name: &str = row.get(4);
+        let role_name: &str = row.get(5);
+        let site_name: &str = row.get(6);
+        let platform_name: &str = row.get(7);
+        let withs: Option<Vec<String>> = row.get(8);
+        FindAllVersionPinsRow::try_from_parts(
+            id,
+            dist_id,
+            pkgcoord_id,
+            distribution,
+            level_name,
+            role_name,
+            platform_name,
+            site_name,
+            withs,
+        )
+    }
+
+    // convert the rows returned by the query
+    fn rows(&self, rows: Vec<Row>) -> Result<Vec<FindAllVersionPinsRow>, FindAllVersionPinsError> {
+        rows.iter().map(Self::row).collect()
+    }
+
+    // apply the search criteria to the versionpins reconstructed from the audit log
+    fn filter_as_of(
+        &self,
+        rows: Vec<FindAllVersionPinsRow>,
+    ) -> Result<Vec<FindAllVersionPinsRow>, FindAllVersionPinsError> {
+        let coords = history::coords_from_opts(self.level, self.role, self.platform, self.site)
+            .context(AsOfError)?;
+        let is_facility = coords.level.to_string() == "facility";
+        let mut result = rows
+            .into_iter()
+            .filter(|row| match self.package {
+                Some(package) => row.distribution.package() == package,
+                None => true,
+            })
+            .filter(|row| match self.version {
+                Some(version) => row.distribution.version() == version,
+                None => true,
+            })
+            .filter(|row| match self.search_mode {
+                LtreeSearchMode::Ancestor => coords_contain(&row.coords, &coords),
+                LtreeSearchMode::Descendant => coords_contain(&coords, &row.coords),
+                LtreeSearchMode::Exact => row.coords == coords,
+            })
+            .filter(|row| {
+                !self.isolate_facility
+                    || is_facility == (row.coords.level.to_string() == "facility")
+            })
+            .collect::<Vec<_>>();
+        self.arrange(&mut result);
+        Ok(result)
+    }
+
+    // convert the versionpins found by a MemoryStore or SqliteStore into rows
+    fn view_rows(
+        &self,
+        pins: Vec<VersionPinView>,
+    ) -> Result<Vec<FindAllVersionPinsRow>, FindAllVersionPinsError> {
+        let is_facility = self.level.unwrap_or("facility") == "facility";
+        let mut result = Vec::new();
+        for pin in pins {
+            if self.isolate_facility && is_facility != (pin.level == "facility") {
+                continue;
+            }
+            result.push(FindAllVersionPinsRow::try_from_parts(
+                pin.versionpin_id,
+                pin.distribution_id,
+                pin.pkgcoord_id,
+                &pin.distribution,
+                &pin.level,
+                &pin.role,
+                &pin.platform,
+                &pin.site,
+                Some(pin.withs),
+            )?);
+        }
+        self.arrange(&mut result);
+        Ok(result)
+    }
+
+    // order and limit rows which were not ordered and limited by the database
+    fn arrange(&self, rows: &mut Vec<FindAllVersionPinsRow>) {
+        if self.is_paged() {
+            if let Some(after) = self.after {
+                rows.retain(|row| row.versionpin_id > after);
+            }
+            rows.sort_by_key(|row| row.versionpin_id);
+            if let Some(size) = self.page_size {
+                if size > 0 {
+                    rows.truncate(size as usize);
+                }
+            }
+            return;
+        }
+        if let Some(ref orderby) = self.order_by {
+            history::sort_by_attributes(rows, orderby, |row| (&row.distribution, &row.coords));
+        }
+        if let Some(OrderDirection::Desc) = self.order_direction {
+            rows.reverse();
+        }
+        if let Some(limit) = self.limit {
+            if limit > 0 {
+                rows.truncate(limit as usize);
+            }
+        }
+    }
+}
+
+impl<'a, C: Backend> FindAllVersionPins<'a, C> {
     /// perform a database query, returning a Vector of FindAllVersionPinsRow if successful, or
     /// a box dyn Error if not
     ///
@@ -635,100 +937,237 @@ impl<'a> FindAllVersionPins<'a> {
     ///   * Ok  - Vector of FindAllVersionPinsRow
     ///   * Err - Box of dyn Error
     pub fn query(&mut self) -> Result<Vec<FindAllVersionPinsRow>, FindAllVersionPinsError> {
-        let level = self.level.unwrap_or("facility").to_string();
-        let role = self.role.unwrap_or("any").to_string();
-        let platform = self.platform.unwrap_or("any").to_string();
-        let site = self.site.unwrap_or("any").to_string();
-        let mut result = Vec::new();
-        let mut query_str = "SELECT id, 
-        distribution_id,
-        pkgcoord_id,
-        distribution, 
-        level_name, 
-        role_name, 
-        site_name, 
-        platform_name,
-        withs
-    FROM findall_versionpins(
-        role => $1, 
-        platform => $2, 
-        level=>$3, 
-        site => $4,
-        search_mode => $5"
-            .to_string();
-
-        let smode = self.search_mode.to_string();
-        let mut prepared_args: Vec<&(dyn ToSql + Sync)> =
-            vec![&role, &platform, &level, &site, &smode];
-        let mut params_cnt = 6;
-
-        if let Some(ref package) = self.package {
-            query_str = format!("{},\n\tpackage_name => ${}", query_str, params_cnt);
-            params_cnt += 1;
-            prepared_args.push(package);
-        }
-        if let Some(ref version) = self.version {
-            query_str = format!("{},\n\tversion_name => ${}", query_str, params_cnt);
-            // dont need the following, since we are the last param to get optionally added
-            // but uncomment if adding more
-            //params_cnt += 1;
-            prepared_args.push(version);
+        C::find_all_versionpins(self)
+    }
+}
+
+impl<'a> FindAllVersionPins<'a, Client> {
+    /// perform a database query, streaming the versionpins from the database rather
+    /// than collecting them into a Vector. Queries `as_of` an earlier revision are
+    /// reconstructed in memory, and so are not streamed.
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    /// # Returns
+    ///
+    /// * Result
+    ///   * Ok  - Iterator over the versionpins, each of which may fail to convert
+    ///   * Err - FindAllVersionPinsError
+    pub fn query_iter(
+        &mut self,
+    ) -> Result<
+        impl Iterator<Item = Result<FindAllVersionPinsRow, FindAllVersionPinsError>> + '_,
+        FindAllVersionPinsError,
+    > {
+        if self.as_of.is_some() {
+            let rows = self.query_postgres()?;
+            return Ok(Either::Left(rows.into_iter().map(Ok)));
         }
-        query_str = format!("{})", query_str);
-        if let Some(ref orderby) = self.order_by {
-            let orderby = orderby.iter().map(|x| match_attrib(x)).collect::<Vec<_>>();
-            query_str = format!("{} ORDER BY {}", query_str, orderby.join(","));
+        let rows = self
+            .prepare()
+            .query_raw(self.client)
+            .context(TokioPostgresError {
+                msg: "problem with select from find_all_versionpins function",
+            })?;
+        Ok(Either::Right(rows.iterator().map(|row| {
+            row.context(TokioPostgresError {
+                msg: "problem retrieving row from find_all_versionpins function",
+            })
+            .and_then(|row| Self::row(&row))
+        })))
+    }
+
+    // execute the query against postgres
+    pub(crate) fn query_postgres(
+        &mut self,
+    ) -> Result<Vec<FindAllVersionPinsRow>, FindAllVersionPinsError> {
+        if let Some(as_of) = self.as_of.as_ref() {
+            let rows = history::versionpins_as_of(self.client, as_of).context(AsOfError)?;
+            return self.filter_as_of(rows);
         }
-        if let Some(ref orderdir) = self.order_direction {
-            query_str.push_str(&[" ", orderdir.as_ref(), " "].concat());
+        let rows = self
+            .prepare()
+            .query(self.client)
+            .context(TokioPostgresError {
+                msg: "problem with select from find_all_versionpins function",
+            })?;
+        self.rows(rows)
+    }
+}
+
+impl<'a> FindAllVersionPins<'a, PackratPool> {
+    // execute the query against a connection checked out of the pool for
+    // the duration of the query
+    pub(crate) fn query_pool(
+        &mut self,
+    ) -> Result<Vec<FindAllVersionPinsRow>, FindAllVersionPinsError> {
+        let mut conn = self.client.connection().context(PoolError {
+            msg: "unable to check out connection",
+        })?;
+        FindAllVersionPins {
+            client: &mut *conn,
+            package: self.package,
+            version: self.version,
+            level: self.level,
+            isolate_facility: self.isolate_facility,
+            role: self.role,
+            platform: self.platform,
+            site: self.site,
+            order_by: self.order_by.clone(),
+            order_direction: self.order_direction,
+            limit: self.limit,
+            after: self.after,
+            page_size: self.page_size,
+            search_mode: self.search_mode,
+            as_of: self.as_of.clone(),
         }
+        .query_postgres()
+    }
+}
 
-        if let Some(limit) = self.limit {
-            if limit > 0 {
-                query_str.push_str(format!(" LIMIT {}", limit).as_str());
-            }
+impl<'a> FindAllVersionPins<'a, MemoryStore> {
+    // execute the query against the tables of a MemoryStore
+    pub(crate) fn query_memory(
+        &mut self,
+    ) -> Result<Vec<FindAllVersionPinsRow>, FindAllVersionPinsError> {
+        if let Some(as_of) = self.as_of.as_ref() {
+            let rows = history::versionpins_as_of_memory(self.client, as_of).context(AsOfError)?;
+            return self.filter_as_of(rows);
         }
-        let qstr = query_str.as_str();
+        let paths = CoordPaths::from_names(self.level, self.role, self.platform, self.site);
+        let pins = self.client.tables().findall_versionpins(
+            &paths,
+            &self.search_mode,
+            self.package,
+            self.version,
+        );
+        self.view_rows(pins)
+    }
+}
 
-        log::info!("SQL\n{}", qstr);
-        log::info!("Arguents\n{:?}", prepared_args);
-        for row in self
+impl<'a> FindAllVersionPins<'a, SqliteStore> {
+    // execute the query against a SqliteStore
+    pub(crate) fn query_sqlite(
+        &mut self,
+    ) -> Result<Vec<FindAllVersionPinsRow>, FindAllVersionPinsError> {
+        if let Some(as_of) = self.as_of.as_ref() {
+            let rows = history::versionpins_as_of_sqlite(self.client, as_of).context(AsOfError)?;
+            return self.filter_as_of(rows);
+        }
+        let paths = CoordPaths::from_names(self.level, self.role, self.platform, self.site);
+        let pins = self
             .client
-            .query(qstr, &prepared_args[..])
-            .context(TokioPostgresError {
+            .findall_versionpins(&paths, &self.search_mode, self.package, self.version)
+            .context(SqliteError {
                 msg: "problem with select from find_all_versionpins function",
-            })?
-        {
-            let level_name: &str = row.get(4);
-            // this should be done at the query level, but i have to switch this
-            // from using a function to raw sql. Or alter the function
-            if self.isolate_facility == true {
-                if (level == "facility" && level_name != "facility")
-                    || (level != "facility" && level_name == "facility")
-                {
-                    continue;
-                }
-            }
-            let id: IdType = row.get(0);
-            let dist_id: IdType = row.get(1);
-            let pkgcoord_id: IdType = row.get(2);
-            let distribution: &str = row.get(3);
-            let role_name: &str = row.get(5);
-            let site_name: &str = row.get(6);
-            let platform_name: &str = row.get(7);
-            let withs: Option<Vec<String>> = row.get(8);
-            result.push(FindAllVersionPinsRow::try_from_parts(
-                id,
-                dist_id,
-                pkgcoord_id,
-                distribution,
-                level_name,
-                role_name,
-                platform_name,
-                site_name,
-                withs,
-            )?);
+            })?;
+        self.view_rows(pins)
+    }
+}
+
+impl<'a> FindAllVersionPins<'a, AsyncClient> {
+    /// perform a database query, returning a Vector of FindAllVersionPinsRow if successful, or
+    /// a box dyn Error if not
+    ///
+    /// # Arguments
+    ///
+    /// * None
+    ///
+    /// # Returns
+    ///
+    /// * Result
+    ///   * Ok  - Vector of FindAllVersionPinsRow
+    ///   * Err - Box of dyn Error
+    pub async fn query(&mut self) -> Result<Vec<FindAllVersionPinsRow>, FindAllVersionPinsError> {
+        if let Some(as_of) = self.as_of.as_ref() {
+            let rows = history::versionpins_as_of_async(self.client, as_of)
+                .await
+                .context(AsOfError)?;
+            return self.filter_as_of(rows);
         }
-        Ok(result)
+        let rows = self
+            .prepare()
+            .query_async(self.client)
+            .await
+            .context(TokioPostgresError {
+                msg: "problem with select from find_all_versionpins function",
+            })?;
+        self.rows(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::query::QueryParam;
+
+    #[test]
+    fn get_query_string_default_works() {
+        let mut store = MemoryStore::new();
+        let fv = FindAllVersionPins::new(&mut store);
+        let query = fv.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT id, distribution_id, pkgcoord_id, distribution, level_name, role_name, site_name, platform_name, withs \
+             FROM findall_versionpins(role => $1, platform => $2, level => $3, site => $4, search_mode => $5)"
+        );
+        assert_eq!(ps, &["any", "any", "facility", "any", "ancestor"]);
+    }
+    #[test]
+    fn get_query_string_with_package_and_version() {
+        let mut store = MemoryStore::new();
+        let mut fv = FindAllVersionPins::new(&mut store);
+        fv.package("maya")
+            .version("2019.1")
+            .role("model")
+            .search_mode(LtreeSearchMode::Exact);
+        let query = fv.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT id, distribution_id, pkgcoord_id, distribution, level_name, role_name, site_name, platform_name, withs \
+             FROM findall_versionpins(role => $1, platform => $2, level => $3, site => $4, search_mode => $5, \
+             package_name => $6, version_name => $7)"
+        );
+        assert_eq!(
+            ps,
+            &["model", "any", "facility", "any", "exact", "maya", "2019.1"]
+        );
+    }
+    #[test]
+    fn get_query_string_with_isolated_level_order_and_limit() {
+        let mut store = MemoryStore::new();
+        let mut fv = FindAllVersionPins::new(&mut store);
+        fv.level("dev01")
+            .isolate_facility(true)
+            .order_by(vec![SearchAttribute::Package, SearchAttribute::Level])
+            .limit(10);
+        let query = fv.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT id, distribution_id, pkgcoord_id, distribution, level_name, role_name, site_name, platform_name, withs \
+             FROM findall_versionpins(role => $1, platform => $2, level => $3, site => $4, search_mode => $5) \
+             WHERE level_name <> 'facility' ORDER BY distribution, level_name LIMIT 10"
+        );
+        assert_eq!(ps, &["any", "any", "dev01", "any", "ancestor"]);
+    }
+    #[test]
+    fn get_query_string_with_page() {
+        let mut store = MemoryStore::new();
+        let mut fv = FindAllVersionPins::new(&mut store);
+        fv.isolate_facility(true).limit(10).after(4).page_size(2);
+        let query = fv.select();
+        let (qs, ps) = query.to_sql();
+        assert_eq!(
+            qs.as_str(),
+            "SELECT id, distribution_id, pkgcoord_id, distribution, level_name, role_name, site_name, platform_name, withs \
+             FROM findall_versionpins(role => $1, platform => $2, level => $3, site => $4, search_mode => $5) \
+             WHERE level_name = 'facility' AND id > $6 ORDER BY id LIMIT 2"
+        );
+        assert_eq!(&ps[5], &QueryParam::Int(4));
     }
 }
@@ -0,0 +1,790 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! Reconstruct the versionpins as they stood at an earlier point in time,
+//! by replaying the audit log (the data behind `FindAllChanges`) up to and
+//! including a revision. The log is replayed up to the last audit event
+//! recorded by the revisions of interest, as transaction ids need not
+//! increase in the order in which transactions are committed.
+//!
+//! The audit log records the id, the distribution and the withs of each
+//! versionpin, so historical versionpins report those they had at the time.
+//! The ids of their pkgcoords and distributions are looked up in the live
+//! tables, and are 0 for those which have since been deleted.
+use crate::db::async_packrat::AsyncClient;
+use crate::db::backend::Backend;
+use crate::db::find_all::changes::{
+    ChangeAction, ChangeType, FindAllChanges, FindAllChangesError, FindAllChangesRow,
+};
+use crate::db::find_all::versionpins::FindAllVersionPinsRow;
+use crate::db::memory::views::{DistributionView, PkgCoordView};
+use crate::db::memory::{MemoryStore, RevisionRecord};
+use crate::db::prepared::Prepared;
+use crate::db::search_attribute::SearchAttribute;
+use crate::db::sqlite::SqliteStore;
+use crate::resolver::{coords_contain, most_specific};
+use crate::types::{IdType, LongIdType};
+use crate::{Coords, Distribution};
+use chrono::{DateTime, Local};
+use itertools::Itertools;
+use postgres::{Client, Row};
+use snafu::{ResultExt, Snafu};
+use std::collections::{BTreeMap, HashMap};
+
+/// The point in time at which to query the versionpins
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum AsOf {
+    /// The state immediately after the revision with the supplied id
+    Revision(IdType),
+    /// The state at the supplied date and time
+    DateTime(DateTime<Local>),
+}
+
+impl From<IdType> for AsOf {
+    fn from(revision_id: IdType) -> Self {
+        AsOf::Revision(revision_id)
+    }
+}
+
+impl From<DateTime<Local>> for AsOf {
+    fn from(datetime: DateTime<Local>) -> Self {
+        AsOf::DateTime(datetime)
+    }
+}
+
+/// Error type returned when reconstructing historical versionpins
+#[derive(Debug, Snafu)]
+pub enum HistoryError {
+    /// When constructing a query, postgres has thrown an error
+    #[snafu(display("Postgres Error: {} {}", msg, source))]
+    TokioPostgresError {
+        msg: &'static str,
+        source: tokio_postgres::error::Error,
+    },
+    /// Error from sqlite
+    #[snafu(display("Sqlite Error: {} {}", msg, source))]
+    SqliteError {
+        msg: &'static str,
+        source: rusqlite::Error,
+    },
+    /// The revision does not exist
+    #[snafu(display("Revision {} does not exist", revision_id))]
+    RevisionNotFoundError { revision_id: IdType },
+    /// Unable to retrieve the changes to replay
+    #[snafu(display("Problem retrieving changes to replay: {}", source))]
+    ChangesError { source: FindAllChangesError },
+    /// Unable to construct Coords from the supplied parts
+    #[snafu(display("Error calling Coords::try_from_parts with {}: {}", coords, source))]
+    CoordsTryFromPartsError {
+        coords: String,
+        source: crate::coords_error::CoordsError,
+    },
+}
+
+pub type HistoryResult<T, E = HistoryError> = std::result::Result<T, E>;
+
+// Versionpins are uniquely identified by their package and coords
+pub(crate) type PinKey = (String, Coords);
+
+// The pkgcoord and distribution views of a MemoryStore or SqliteStore
+type StoreViews = (Vec<PkgCoordView>, Vec<DistributionView>);
+
+/// The id, distribution and withs of a versionpin, replayed from the audit log
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct PinState {
+    pub versionpin_id: IdType,
+    pub distribution: Distribution,
+    pub withs: Vec<String>,
+}
+
+impl PinState {
+    // a versionpin is inserted without withs
+    fn new(versionpin_id: IdType, distribution: Distribution) -> Self {
+        Self {
+            versionpin_id,
+            distribution,
+            withs: Vec::new(),
+        }
+    }
+}
+
+/// Construct Coords from the optional parts supplied to the find builders,
+/// applying the same defaults as the live queries.
+pub(crate) fn coords_from_opts(
+    level: Option<&str>,
+    role: Option<&str>,
+    platform: Option<&str>,
+    site: Option<&str>,
+) -> HistoryResult<Coords> {
+    let level = level.unwrap_or("facility");
+    let role = role.unwrap_or("any");
+    let platform = platform.unwrap_or("any");
+    let site = site.unwrap_or("any");
+    Coords::try_from_parts(level, role, platform, site).context(CoordsTryFromPartsError {
+        coords: Coords::display_parts(level, role, platform, site),
+    })
+}
+
+/// Select the historical versionpin which wins for `package` at `coords`.
+pub(crate) fn resolve<'r>(
+    rows: &'r [FindAllVersionPinsRow],
+    package: &str,
+    coords: &Coords,
+) -> Option<&'r FindAllVersionPinsRow> {
+    most_specific(rows.iter(), package, coords, |row| {
+        (&row.distribution, &row.coords)
+    })
+}
+
+/// Select the historical versionpins for `package` which are visible from `coords`.
+pub(crate) fn candidates<'r>(
+    rows: &'r [FindAllVersionPinsRow],
+    package: &str,
+    coords: &Coords,
+) -> Vec<&'r FindAllVersionPinsRow> {
+    rows.iter()
+        .filter(|row| row.distribution.package() == package)
+        .filter(|row| coords_contain(&row.coords, coords))
+        .collect()
+}
+
+/// Sort rows by the supplied attributes, in ascending order, mirroring the
+/// `ORDER BY` clauses of the live queries.
+pub(crate) fn sort_by_attributes<T, F>(rows: &mut [T], attributes: &[SearchAttribute], parts: F)
+where
+    F: Fn(&T) -> (&Distribution, &Coords),
+{
+    let key = |row: &T| {
+        let (distribution, coords) = parts(row);
+        attributes
+            .iter()
+            .map(|attribute| match attribute {
+                SearchAttribute::Package => distribution.distribution().to_string(),
+                SearchAttribute::Level => coords.level.to_string(),
+                SearchAttribute::Role => coords.role.to_string(),
+                SearchAttribute::Platform => coords.platform.to_string(),
+                SearchAttribute::Site => coords.site.to_string(),
+                SearchAttribute::Unknown => String::new(),
+            })
+            .collect::<Vec<_>>()
+    };
+    rows.sort_by_cached_key(|row| key(row));
+}
+
+/// Reconstruct the full set of versionpins as they stood at `as_of`.
+///
+/// The changes up to `as_of` are retrieved with a single query, ordered by
+/// event id, and replayed in memory.
+///
+/// # Arguments
+/// * `client` - A mutable reference to a postgres::Client
+/// * `as_of` - The point in time of interest
+///
+/// # Returns
+/// * Result
+///   * `Ok`  - Vector of FindAllVersionPinsRow, ordered by package and coords
+///   * `Err` - HistoryError
+pub fn versionpins_as_of(
+    client: &mut Client,
+    as_of: &AsOf,
+) -> HistoryResult<Vec<FindAllVersionPinsRow>> {
+    let rows = last_event_id_query(as_of)
+        .query(client)
+        .context(TokioPostgresError {
+            msg: "problem with select from revision_view and vpin_change_view",
+        })?;
+    let event_id = match last_event_id(as_of, rows)? {
+        Some(event_id) => event_id,
+        None => return Ok(Vec::new()),
+    };
+    let rows = changes_query(event_id)
+        .query(client)
+        .context(TokioPostgresError {
+            msg: "problem with select from vpin_change_view",
+        })?;
+    let state = replay_changes(changes(rows)?);
+    if state.is_empty() {
+        return Ok(Vec::new());
+    }
+    let packages = packages(&state);
+    let pkgcoords = pkgcoord_ids(pkgcoord_ids_query(&packages).query(client).context(
+        TokioPostgresError {
+            msg: "problem with select from pkgcoord_view",
+        },
+    )?)?;
+    let distributions = distribution_ids(distribution_ids_query(&packages).query(client).context(
+        TokioPostgresError {
+            msg: "problem with select from distribution_view",
+        },
+    )?);
+    Ok(assemble(state, &pkgcoords, &distributions))
+}
+
+/// Reconstruct the full set of versionpins as they stood at `as_of`, using
+/// the async client. See `versionpins_as_of`.
+///
+/// # Arguments
+/// * `client` - A mutable reference to a tokio_postgres::Client
+/// * `as_of` - The point in time of interest
+///
+/// # Returns
+/// * Result
+///   * `Ok`  - Vector of FindAllVersionPinsRow, ordered by package and coords
+///   * `Err` - HistoryError
+pub async fn versionpins_as_of_async(
+    client: &mut AsyncClient,
+    as_of: &AsOf,
+) -> HistoryResult<Vec<FindAllVersionPinsRow>> {
+    let rows = last_event_id_query(as_of)
+        .query_async(client)
+        .await
+        .context(TokioPostgresError {
+            msg: "problem with select from revision_view and vpin_change_view",
+        })?;
+    let event_id = match last_event_id(as_of, rows)? {
+        Some(event_id) => event_id,
+        None => return Ok(Vec::new()),
+    };
+    let rows = changes_query(event_id)
+        .query_async(client)
+        .await
+        .context(TokioPostgresError {
+            msg: "problem with select from vpin_change_view",
+        })?;
+    let state = replay_changes(changes(rows)?);
+    if state.is_empty() {
+        return Ok(Vec::new());
+    }
+    let packages = packages(&state);
+    let pkgcoords = pkgcoord_ids(
+        pkgcoord_ids_query(&packages)
+            .query_async(client)
+            .await
+            .context(TokioPostgresError {
+                msg: "problem with select from pkgcoord_view",
+            })?,
+    )?;
+    let distributions = distribution_ids(
+        distribution_ids_query(&packages)
+            .query_async(client)
+            .await
+            .context(TokioPostgresError {
+                msg: "problem with select from distribution_view",
+            })?,
+    );
+    Ok(assemble(state, &pkgcoords, &distributions))
+}
+
+/// Reconstruct the full set of versionpins as they stood at `as_of`, from
+/// the audit log of a MemoryStore.
+///
+/// # Arguments
+/// * `store` - A mutable reference to a MemoryStore
+/// * `as_of` - The point in time of interest
+///
+/// # Returns
+/// * Result
+///   * `Ok`  - Vector of FindAllVersionPinsRow, ordered by package and coords
+///   * `Err` - HistoryError
+pub(crate) fn versionpins_as_of_memory(
+    store: &mut MemoryStore,
+    as_of: &AsOf,
+) -> HistoryResult<Vec<FindAllVersionPinsRow>> {
+    let revisions = store.tables().revisions.clone();
+    replay(store, as_of, &revisions, |store| {
+        let tables = store.tables();
+        Ok((tables.pkgcoord_view(), tables.distribution_view()))
+    })
+}
+
+/// Reconstruct the full set of versionpins as they stood at `as_of`, from
+/// the changes recorded in a SqliteStore.
+///
+/// # Arguments
+/// * `store` - A mutable reference to a SqliteStore
+/// * `as_of` - The point in time of interest
+///
+/// # Returns
+/// * Result
+///   * `Ok`  - Vector of FindAllVersionPinsRow, ordered by package and coords
+///   * `Err` - HistoryError
+pub(crate) fn versionpins_as_of_sqlite(
+    store: &mut SqliteStore,
+    as_of: &AsOf,
+) -> HistoryResult<Vec<FindAllVersionPinsRow>> {
+    let revisions = store.revisions().context(SqliteError {
+        msg: "problem with select from revision_view",
+    })?;
+    replay(store, as_of, &revisions, |store| {
+        let pkgcoords = store.pkgcoord_view().context(SqliteError {
+            msg: "problem with select from pkgcoord_view",
+        })?;
+        let distributions = store.distribution_view().context(SqliteError {
+            msg: "problem with select from distribution_view",
+        })?;
+        Ok((pkgcoords, distributions))
+    })
+}
+
+// Replay the changes up to `as_of`, and look up the ids of the result in the
+// views of the store
+fn replay<C, F>(
+    store: &mut C,
+    as_of: &AsOf,
+    revisions: &[RevisionRecord],
+    views: F,
+) -> HistoryResult<Vec<FindAllVersionPinsRow>>
+where
+    C: Backend,
+    F: FnOnce(&mut C) -> HistoryResult<StoreViews>,
+{
+    // the changes are ordered by event id
+    let changes = FindAllChanges::new(&mut *store)
+        .query()
+        .context(ChangesError)?;
+    let last = match last_event_id_of(as_of, revisions, &changes)? {
+        Some(last) => last,
+        None => return Ok(Vec::new()),
+    };
+    let state = replay_changes(changes.into_iter().filter(|change| change.event_id <= last));
+    if state.is_empty() {
+        return Ok(Vec::new());
+    }
+    let (coord_views, distribution_views) = views(store)?;
+    let mut pkgcoords = BTreeMap::new();
+    for coord in coord_views {
+        let coords = coords_from_opts(
+            Some(&coord.level_name),
+            Some(&coord.role_name),
+            Some(&coord.platform_name),
+            Some(&coord.site_name),
+        )?;
+        pkgcoords.insert((coord.package, coords), coord.pkgcoord_id);
+    }
+    let distributions = distribution_views
+        .into_iter()
+        .map(|dist| {
+            (
+                Distribution::from_parts_unchecked(&dist.package, &dist.version_name),
+                dist.distribution_id,
+            )
+        })
+        .collect();
+    Ok(assemble(state, &pkgcoords, &distributions))
+}
+
+// The id of the last audit event recorded by a revision up to and including
+// `as_of`. None if no such revision changed a versionpin.
+fn last_event_id_of(
+    as_of: &AsOf,
+    revisions: &[RevisionRecord],
+    changes: &[FindAllChangesRow],
+) -> HistoryResult<Option<LongIdType>> {
+    let included = match as_of {
+        AsOf::Revision(revision_id) => {
+            if !revisions.iter().any(|revision| revision.id == *revision_id) {
+                return RevisionNotFoundError {
+                    revision_id: *revision_id,
+                }
+                .fail();
+            }
+            revisions
+                .iter()
+                .filter(|revision| revision.id <= *revision_id)
+                .map(|revision| revision.transaction_id)
+                .collect::<Vec<_>>()
+        }
+        AsOf::DateTime(datetime) => revisions
+            .iter()
+            .filter(|revision| revision.datetime <= *datetime)
+            .map(|revision| revision.transaction_id)
+            .collect::<Vec<_>>(),
+    };
+    Ok(changes
+        .iter()
+        .filter(|change| included.contains(&change.transaction_id))
+        .map(|change| change.event_id)
+        .max())
+}
+
+/// Replay changes from the audit log, which must be supplied in the order
+/// they were made, returning the versionpins they leave behind.
+pub(crate) fn replay_changes<I>(changes: I) -> BTreeMap<PinKey, PinState>
+where
+    I: IntoIterator<Item = FindAllChangesRow>,
+{
+    let mut state = BTreeMap::new();
+    for change in changes {
+        apply_change(&mut state, change);
+    }
+    state
+}
+
+/// Combine the replayed state with the ids of the pkgcoords and distributions
+/// of the live tables. Those which have since been deleted report an id of 0.
+pub(crate) fn assemble(
+    state: BTreeMap<PinKey, PinState>,
+    pkgcoords: &BTreeMap<PinKey, IdType>,
+    distributions: &HashMap<Distribution, IdType>,
+) -> Vec<FindAllVersionPinsRow> {
+    let mut result = Vec::with_capacity(state.len());
+    for (key, pin) in state {
+        let pkgcoord_id = pkgcoords.get(&key).cloned().unwrap_or(0);
+        let distribution_id = distributions.get(&pin.distribution).cloned().unwrap_or(0);
+        let withs = if pin.withs.is_empty() {
+            None
+        } else {
+            Some(pin.withs)
+        };
+        result.push(FindAllVersionPinsRow::new(
+            pin.versionpin_id,
+            distribution_id,
+            pkgcoord_id,
+            pin.distribution,
+            key.1,
+            withs,
+        ));
+    }
+    result
+}
+
+// The distinct packages present in the state
+fn packages(state: &BTreeMap<PinKey, PinState>) -> Vec<String> {
+    state
+        .keys()
+        .map(|(package, _)| package.clone())
+        .dedup()
+        .collect()
+}
+
+// Query the id of the last audit event recorded by a revision up to and
+// including `as_of`. When querying as of a revision, a row is returned only
+// if the revision exists.
+fn last_event_id_query(as_of: &AsOf) -> Prepared {
+    match as_of {
+        AsOf::Revision(revision_id) => {
+            let mut prepared = Prepared::new(
+                "SELECT max(change.event_id)
+                FROM revision_view AS revision
+                JOIN revision_view AS earlier ON earlier.id <= revision.id
+                LEFT JOIN vpin_change_view AS change
+                    ON change.transaction_id = earlier.transaction_id
+                WHERE revision.id = $1
+                GROUP BY revision.id",
+            );
+            prepared.push(*revision_id);
+            prepared
+        }
+        AsOf::DateTime(datetime) => {
+            let mut prepared = Prepared::new(
+                "SELECT max(change.event_id)
+                FROM vpin_change_view AS change
+                JOIN revision_view AS revision
+                    ON revision.transaction_id = change.transaction_id
+                WHERE revision.datetime <= $1",
+            );
+            prepared.push(*datetime);
+            prepared
+        }
+    }
+}
+
+// Determine the id of the last audit event included in the historical
+// state. None if no versionpins had been changed at that point.
+fn last_event_id(as_of: &AsOf, rows: Vec<Row>) -> HistoryResult<Option<LongIdType>> {
+    match (as_of, rows.first()) {
+        (AsOf::Revision(revision_id), None) => RevisionNotFoundError {
+            revision_id: *revision_id,
+        }
+        .fail(),
+        (_, row) => Ok(row.and_then(|row| row.get(0))),
+    }
+}
+
+// Query every change recorded up to and including the event, in the order
+// they were made
+fn changes_query(event_id: LongIdType) -> Prepared {
+    let columns = FindAllChangesRow::COLUMNS
+        .iter()
+        .chain(FindAllChangesRow::REVISION_COLUMNS.iter())
+        .chain(FindAllChangesRow::WITHS_COLUMNS.iter())
+        .join(", ");
+    let mut prepared = Prepared::new(format!(
+        "SELECT {} FROM vpin_change_view WHERE event_id <= $1 ORDER BY event_id",
+        columns
+    ));
+    prepared.push(event_id);
+    prepared
+}
+
+// Convert the rows returned by the changes query
+fn changes(rows: Vec<Row>) -> HistoryResult<Vec<FindAllChangesRow>> {
+    rows.iter()
+        .map(FindAllChangesRow::from_change_row)
+        .collect::<Result<Vec<_>, _>>()
+        .context(ChangesError)
+}
+
+// Apply a single change from the audit log to the state. Changes to the
+// distribution leave the withs unchanged, and vice versa. A versionpin is
+// inserted without withs.
+fn apply_change(state: &mut BTreeMap<PinKey, PinState>, change: FindAllChangesRow) {
+    let coords = Coords::from_parts(change.level, change.role, change.platform, change.site);
+    let key = (change.package, coords);
+    let versionpin_id = change.id as IdType;
+    match (change.action, change.change) {
+        (ChangeAction::Truncate, _) => state.clear(),
+        (ChangeAction::Delete, _) => {
+            state.remove(&key);
+        }
+        (_, ChangeType::Withs { new, .. }) => {
+            if let Some(pin) = state.get_mut(&key) {
+                pin.withs = new;
+            }
+        }
+        (ChangeAction::Insert, ChangeType::Distribution { new, .. }) => {
+            state.insert(key, PinState::new(versionpin_id, new));
+        }
+        (ChangeAction::Update, ChangeType::Distribution { new, .. }) => match state.get_mut(&key) {
+            Some(pin) => pin.distribution = new,
+            None => {
+                state.insert(key, PinState::new(versionpin_id, new));
+            }
+        },
+    }
+}
+
+// Query the pkgcoords of the supplied packages
+fn pkgcoord_ids_query(packages: &[String]) -> Prepared {
+    let mut prepared = Prepared::new(
+        "SELECT pkgcoord_id, package, level_name, role_name, platform_name, site_name
+        FROM pkgcoord_view WHERE package = ANY($1)",
+    );
+    prepared.push(packages.to_vec());
+    prepared
+}
+
+// Retrieve the ids of the pkgcoords from the rows
+fn pkgcoord_ids(rows: Vec<Row>) -> HistoryResult<BTreeMap<PinKey, IdType>> {
+    let mut result = BTreeMap::new();
+    for row in rows {
+        let id: IdType = row.get(0);
+        let package: &str = row.get(1);
+        let coords = coords_from_opts(row.get(2), row.get(3), row.get(4), row.get(5))?;
+        result.insert((package.to_string(), coords), id);
+    }
+    Ok(result)
+}
+
+// Query the distributions of the supplied packages
+fn distribution_ids_query(packages: &[String]) -> Prepared {
+    let mut prepared = Prepared::new(
+        "SELECT distribution_id, package, version_name FROM distribution_view WHERE package = ANY($1)",
+    );
+    prepared.push(packages.to_vec());
+    prepared
+}
+
+// Retrieve the ids of the distributions from the rows
+fn distribution_ids(rows: Vec<Row>) -> HashMap<Distribution, IdType> {
+    let mut result = HashMap::new();
+    for row in rows {
+        let id: IdType = row.get(0);
+        let package: &str = row.get(1);
+        let version: &str = row.get(2);
+        result.insert(Distribution::from_parts_unchecked(package, version), id);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Level, Platform, Role, Site};
+    use std::convert::TryFrom;
+
+    fn change(id: LongIdType, action: ChangeAction, level: &str, dist: &str) -> FindAllChangesRow {
+        let new = if action == ChangeAction::Delete {
+            Distribution::empty()
+        } else {
+            Distribution::new(dist).unwrap()
+        };
+        FindAllChangesRow::new(
+            id,
+            id,
+            1,
+            action,
+            Level::try_from(level).unwrap(),
+            Role::Any,
+            Platform::Any,
+            Site::Any,
+            "maya",
+            ChangeType::Distribution { old: None, new },
+        )
+    }
+
+    fn replayed(changes: Vec<FindAllChangesRow>) -> Vec<(String, Distribution)> {
+        replay_changes(changes)
+            .into_iter()
+            .map(|((_, coords), pin)| (coords.level.to_string(), pin.distribution))
+            .collect()
+    }
+
+    #[test]
+    fn replay_insert_then_update() {
+        let state = replayed(vec![
+            change(1, ChangeAction::Insert, "facility", "maya-2018.sp3"),
+            change(2, ChangeAction::Update, "facility", "maya-2020.1"),
+        ]);
+        assert_eq!(
+            state,
+            vec![(
+                "facility".to_string(),
+                Distribution::new("maya-2020.1").unwrap()
+            )]
+        );
+    }
+
+    #[test]
+    fn replay_delete_removes_pin() {
+        let state = replayed(vec![
+            change(1, ChangeAction::Insert, "facility", "maya-2018.sp3"),
+            change(2, ChangeAction::Insert, "dev01", "maya-2020.1"),
+            change(3, ChangeAction::Delete, "dev01", ""),
+        ]);
+        assert_eq!(
+            state,
+            vec![(
+                "facility".to_string(),
+                Distribution::new("maya-2018.sp3").unwrap()
+            )]
+        );
+    }
+
+    #[test]
+    fn replay_truncate_clears_state() {
+        let state = replayed(vec![
+            change(1, ChangeAction::Insert, "facility", "maya-2018.sp3"),
+            change(2, ChangeAction::Truncate, "facility", "maya-2018.sp3"),
+        ]);
+        assert!(state.is_empty());
+    }
+
+    #[test]
+    fn replay_withs_follow_the_pin() {
+        let coords = Coords::try_from_parts("facility", "any", "any", "any").unwrap();
+        let withs = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let state = replay_changes(vec![
+            change(1, ChangeAction::Insert, "facility", "maya-2018.sp3"),
+            FindAllChangesRow::from_withs(
+                2,
+                1,
+                1,
+                coords.clone(),
+                "maya",
+                Vec::new(),
+                withs(&["mayapipeline", "gcc"]),
+            ),
+            change(3, ChangeAction::Update, "facility", "maya-2020.1"),
+            FindAllChangesRow::from_withs(
+                4,
+                1,
+                1,
+                coords.clone(),
+                "maya",
+                withs(&["mayapipeline", "gcc"]),
+                withs(&["gcc"]),
+            ),
+        ]);
+        assert_eq!(
+            state.get(&("maya".to_string(), coords)),
+            Some(&PinState {
+                versionpin_id: 1,
+                distribution: Distribution::new("maya-2020.1").unwrap(),
+                withs: withs(&["gcc"]),
+            })
+        );
+    }
+
+    #[test]
+    fn coords_from_opts_applies_defaults() {
+        let coords = coords_from_opts(Some("dev01"), None, None, None).unwrap();
+        assert_eq!(
+            coords,
+            Coords::try_from_parts("dev01", "any", "any", "any").unwrap()
+        );
+    }
+
+    #[test]
+    fn coords_from_opts_reports_invalid_parts() {
+        let err = coords_from_opts(Some("dev01"), Some("model beta"), None, None).unwrap_err();
+        match err {
+            HistoryError::CoordsTryFromPartsError { coords, .. } => {
+                assert_eq!(coords, "level:dev01.role:model beta.platform:any.site:any")
+            }
+            _ => panic!("expected a CoordsTryFromPartsError"),
+        }
+    }
+
+    fn revision(id: IdType, transaction_id: LongIdType, datetime: &str) -> RevisionRecord {
+        RevisionRecord {
+            id,
+            transaction_id,
+            author: "jgerber".to_string(),
+            comment: "change".to_string(),
+            datetime: DateTime::parse_from_rfc3339(datetime)
+                .unwrap()
+                .with_timezone(&Local),
+        }
+    }
+
+    // a change recorded by the transaction
+    fn recorded(event_id: LongIdType, transaction_id: LongIdType) -> FindAllChangesRow {
+        let mut change = change(event_id, ChangeAction::Insert, "facility", "maya-2018.sp3");
+        change.transaction_id = transaction_id;
+        change
+    }
+
+    #[test]
+    fn replays_up_to_the_last_event_of_the_revision() {
+        // transaction 20 started first, but was committed after transaction 10
+        let revisions = vec![
+            revision(1, 10, "2020-01-01T10:00:00Z"),
+            revision(2, 20, "2020-01-01T11:00:00Z"),
+        ];
+        let changes = vec![recorded(1, 10), recorded(2, 10), recorded(3, 20)];
+        let last = |as_of: AsOf| last_event_id_of(&as_of, &revisions, &changes).unwrap();
+        assert_eq!(last(AsOf::Revision(1)), Some(2));
+        assert_eq!(last(AsOf::Revision(2)), Some(3));
+        let between = DateTime::parse_from_rfc3339("2020-01-01T10:30:00Z")
+            .unwrap()
+            .with_timezone(&Local);
+        assert_eq!(last(AsOf::DateTime(between)), Some(2));
+        assert_eq!(
+            last(AsOf::DateTime(between - chrono::Duration::days(1))),
+            None
+        );
+        assert!(matches!(
+            last_event_id_of(&AsOf::Revision(3), &revisions, &changes),
+            Err(HistoryError::RevisionNotFoundError { revision_id: 3 })
+        ));
+    }
+
+    #[test]
+    fn queries_changes_by_event_id() {
+        let prepared = last_event_id_query(&AsOf::Revision(4));
+        assert!(prepared.sql.starts_with("SELECT max(change.event_id)"));
+        assert!(prepared.sql.contains("WHERE revision.id = $1"));
+        assert_eq!(format!("{:?}", prepared.params), "[4]");
+        let prepared = changes_query(12);
+        assert!(prepared
+            .sql
+            .ends_with("FROM vpin_change_view WHERE event_id <= $1 ORDER BY event_id"));
+        assert_eq!(format!("{:?}", prepared.params), "[12]");
+    }
+}
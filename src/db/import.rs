@@ -0,0 +1,676 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! Import the versionpins read from a packages.xml within a single revision:
+//!
+//! * versionpins which do not exist are added
+//! * versionpins which exist with a different distribution are updated
+//! * withs which differ from those in the database are replaced
+//!
+//! The packages, distributions, levels, roles, platforms and sites referenced
+//! by the file must already exist, unless the import is asked to create them. `plan` reports what an
+//! import would do without changing the database.
+use crate::db::add::distributions::{AddDistributions, AddDistributionsError};
+use crate::db::add::levels::{AddLevels, AddLevelsError};
+use crate::db::add::packages::{AddPackages, AddPackagesError};
+use crate::db::add::platforms::{AddPlatforms, AddPlatformsError};
+use crate::db::add::roles::{AddRoles, AddRolesError};
+use crate::db::add::sites::{AddSites, AddSitesError};
+use crate::db::add::versionpins::{AddVersionPins, AddVersionPinsError, ConflictPolicy};
+use crate::db::add::withs::{AddWiths, AddWithsError};
+use crate::db::delete::find_versionpin_id;
+use crate::db::delete::withs::{DeleteWiths, DeleteWithsError};
+use crate::db::update::versionpins::{UpdateVersionPins, UpdateVersionPinsError, VersionPinChange};
+use crate::io::packages_xml::XmlVersionPin;
+use crate::traits::TransactionHandler;
+use crate::types::IdType;
+use crate::Distribution;
+use log;
+use postgres::{Client, Transaction};
+use snafu::{ResultExt, Snafu};
+use std::collections::HashSet;
+use std::fmt;
+
+/// Error type returned from ImportPackagesXml
+#[derive(Debug, Snafu)]
+pub enum ImportPackagesXmlError {
+    /// When constructing a query, postgres has thrown an error
+    #[snafu(display("Postgres Error: {} {:#?}", msg, source))]
+    TokioPostgresError {
+        msg: &'static str,
+        source: tokio_postgres::error::Error,
+    },
+    /// There are no versionpins to import
+    #[snafu(display("No versionpins supplied"))]
+    NoVersionPinsError,
+    /// The file references entities which do not exist, and we have not
+    /// been asked to create them
+    #[snafu(display(
+        "Missing packages: [{}] distributions: [{}] levels: [{}] roles: [{}] platforms: [{}] sites: [{}]",
+        packages.join(", "),
+        distributions.join(", "),
+        levels.join(", "),
+        roles.join(", "),
+        platforms.join(", "),
+        sites.join(", ")
+    ))]
+    MissingEntitiesError {
+        packages: Vec<String>,
+        distributions: Vec<String>,
+        levels: Vec<String>,
+        roles: Vec<String>,
+        platforms: Vec<String>,
+        sites: Vec<String>,
+    },
+    /// Unable to create the missing packages
+    #[snafu(display("Problem creating packages: {}", source))]
+    CreatePackagesError { source: AddPackagesError },
+    /// Unable to create the missing distributions
+    #[snafu(display("Problem creating distributions: {}", source))]
+    CreateDistributionsError { source: AddDistributionsError },
+    /// Unable to create the missing levels
+    #[snafu(display("Problem creating levels: {}", source))]
+    CreateLevelsError { source: AddLevelsError },
+    /// Unable to create the missing roles
+    #[snafu(display("Problem creating roles: {}", source))]
+    CreateRolesError { source: AddRolesError },
+    /// Unable to create the missing platforms
+    #[snafu(display("Problem creating platforms: {}", source))]
+    CreatePlatformsError { source: AddPlatformsError },
+    /// Unable to create the missing sites
+    #[snafu(display("Problem creating sites: {}", source))]
+    CreateSitesError { source: AddSitesError },
+    /// Unable to add a versionpin
+    #[snafu(display("Problem adding versionpin {}: {}", versionpin, source))]
+    AddVersionPinError {
+        versionpin: String,
+        source: AddVersionPinsError,
+    },
+    /// Unable to update a versionpin
+    #[snafu(display("Problem updating versionpin {}: {}", versionpin, source))]
+    UpdateVersionPinError {
+        versionpin: String,
+        source: UpdateVersionPinsError,
+    },
+    /// Unable to set the withs of a versionpin
+    #[snafu(display("Problem setting withs of {}: {}", versionpin, source))]
+    SetWithsError {
+        versionpin: String,
+        source: AddWithsError,
+    },
+    /// Unable to remove the withs of a versionpin
+    #[snafu(display("Problem removing withs of {}: {}", versionpin, source))]
+    RemoveWithsError {
+        versionpin: String,
+        source: DeleteWithsError,
+    },
+    /// The versionpin or distribution could not be found after being added
+    #[snafu(display("Unable to find {} after adding it", entity))]
+    NotFoundError { entity: String },
+}
+
+pub type ImportPackagesXmlResult<T, E = ImportPackagesXmlError> = std::result::Result<T, E>;
+
+/// What importing a versionpin does to the database
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ImportAction {
+    /// The versionpin does not exist, and is added
+    Add,
+    /// The versionpin exists with another distribution, which is replaced
+    Update { old: Distribution },
+    /// The versionpin exists with the same distribution
+    Unchanged,
+}
+
+/// The planned import of a single versionpin
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PlannedVersionPin {
+    pub pin: XmlVersionPin,
+    pub action: ImportAction,
+    /// The withs currently in the database, in order
+    pub old_withs: Vec<String>,
+}
+
+impl PlannedVersionPin {
+    /// Do the withs in the file differ from those in the database?
+    pub fn withs_changed(&self) -> bool {
+        self.pin.withs != self.old_withs
+    }
+
+    /// Does importing the versionpin change the database?
+    pub fn has_changes(&self) -> bool {
+        self.action != ImportAction::Unchanged || self.withs_changed()
+    }
+}
+
+/// The report of an import, describing the entities which are created and
+/// what happens to each versionpin.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct ImportPlan {
+    /// Packages which do not exist in the database
+    pub packages: Vec<String>,
+    /// Distributions which do not exist in the database
+    pub distributions: Vec<String>,
+    /// Levels which do not exist in the database
+    pub levels: Vec<String>,
+    /// Roles which do not exist in the database
+    pub roles: Vec<String>,
+    /// Platforms which do not exist in the database
+    pub platforms: Vec<String>,
+    /// Sites which do not exist in the database
+    pub sites: Vec<String>,
+    pub versionpins: Vec<PlannedVersionPin>,
+}
+
+impl ImportPlan {
+    /// Does the import reference packages, distributions, levels, roles,
+    /// platforms or sites which do not exist?
+    pub fn has_missing(&self) -> bool {
+        !(self.packages.is_empty()
+            && self.distributions.is_empty()
+            && self.levels.is_empty()
+            && self.roles.is_empty()
+            && self.platforms.is_empty()
+            && self.sites.is_empty())
+    }
+
+    /// The number of versionpins changed by the import
+    pub fn change_cnt(&self) -> u64 {
+        self.versionpins
+            .iter()
+            .filter(|planned| planned.has_changes())
+            .count() as u64
+    }
+}
+
+impl fmt::Display for ImportPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for package in &self.packages {
+            writeln!(f, "create package {}", package)?;
+        }
+        for distribution in &self.distributions {
+            writeln!(f, "create distribution {}", distribution)?;
+        }
+        for level in &self.levels {
+            writeln!(f, "create level {}", level)?;
+        }
+        for role in &self.roles {
+            writeln!(f, "create role {}", role)?;
+        }
+        for platform in &self.platforms {
+            writeln!(f, "create platform {}", platform)?;
+        }
+        for site in &self.sites {
+            writeln!(f, "create site {}", site)?;
+        }
+        let mut unchanged = 0;
+        for planned in &self.versionpins {
+            let versionpin = &planned.pin.versionpin;
+            match planned.action {
+                ImportAction::Add => writeln!(f, "add {}", versionpin)?,
+                ImportAction::Update { ref old } => {
+                    writeln!(f, "update {} from {}", versionpin, old.distribution())?
+                }
+                ImportAction::Unchanged if !planned.withs_changed() => unchanged += 1,
+                ImportAction::Unchanged => (),
+            }
+            if planned.withs_changed() {
+                writeln!(
+                    f,
+                    "set withs of {} to [{}] from [{}]",
+                    versionpin,
+                    planned.pin.withs.join(", "),
+                    planned.old_withs.join(", ")
+                )?;
+            }
+        }
+        write!(f, "{} versionpins unchanged", unchanged)
+    }
+}
+
+/// Responsible for importing versionpins read from a packages.xml. Typically
+/// constructed via `PackratDb::import_packages_xml`.
+pub struct ImportPackagesXml<'a> {
+    client: &'a mut Client,
+    pins: Vec<XmlVersionPin>,
+    create_missing: bool,
+}
+
+impl fmt::Debug for ImportPackagesXml<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ImportPackagesXml(pins:{} create_missing:{})",
+            self.pins.len(),
+            self.create_missing
+        )
+    }
+}
+
+impl<'a> ImportPackagesXml<'a> {
+    /// new up an ImportPackagesXml instance
+    ///
+    /// # Arguments
+    /// * `client` - A mutable reference to a postgres::Client
+    /// * `pins` - The versionpins to import, as returned by `io::read_xml`
+    pub fn new(client: &'a mut Client, pins: Vec<XmlVersionPin>) -> Self {
+        Self {
+            client,
+            pins,
+            create_missing: false,
+        }
+    }
+
+    /// Create the packages, distributions, levels, roles, platforms and sites
+    /// referenced by the versionpins which do not exist, rather than failing
+    /// the import.
+    ///
+    /// # Arguments
+    /// * `create_missing` - Whether to create missing entities
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn create_missing(&mut self, create_missing: bool) -> &mut Self {
+        self.create_missing = create_missing;
+        self
+    }
+
+    /// Report what the import would do, without changing the database.
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - ImportPlan
+    ///   * `Err` - ImportPackagesXmlError
+    pub fn plan(&mut self) -> ImportPackagesXmlResult<ImportPlan> {
+        let mut tx = self.client.transaction().context(TokioPostgresError {
+            msg: "failed to create transaction",
+        })?;
+        // the transaction is only read from, and is rolled back when dropped
+        plan_import(&mut tx, &self.pins)
+    }
+
+    /// Import the versionpins in a new transaction, and commit it as a
+    /// revision.
+    ///
+    /// # Arguments
+    /// * `author` - The author of the revision
+    /// * `comment` - The comment of the revision
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - The ImportPlan which was applied
+    ///   * `Err` - ImportPackagesXmlError
+    pub fn import(&mut self, author: &str, comment: &str) -> ImportPackagesXmlResult<ImportPlan> {
+        let mut tx = self.client.transaction().context(TokioPostgresError {
+            msg: "failed to create transaction",
+        })?;
+        let plan = plan_import(&mut tx, &self.pins)?;
+        if plan.has_missing() && !self.create_missing {
+            return MissingEntitiesError {
+                packages: plan.packages,
+                distributions: plan.distributions,
+                levels: plan.levels,
+                roles: plan.roles,
+                platforms: plan.platforms,
+                sites: plan.sites,
+            }
+            .fail();
+        }
+        let mut tx = apply_import(tx, &plan)?;
+        tx.execute(
+            "INSERT INTO REVISION (author, comment) VALUES ($1, $2)",
+            &[&author, &comment],
+        )
+        .context(TokioPostgresError {
+            msg: "failed to insert Revisions",
+        })?;
+        tx.commit().context(TokioPostgresError {
+            msg: "failed to commit transaction",
+        })?;
+        Ok(plan)
+    }
+}
+
+// run a query returning a single text column per row
+fn query_strings(
+    tx: &mut Transaction<'_>,
+    query_str: &str,
+    params: &[&(dyn postgres::types::ToSql + Sync)],
+    msg: &'static str,
+) -> ImportPackagesXmlResult<Vec<String>> {
+    log::info!("SQL\n{}", query_str);
+    log::info!("Prepared\n{:?}", params);
+    let rows = tx
+        .query(query_str, params)
+        .context(TokioPostgresError { msg })?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+// Look up the id of a distribution
+fn find_distribution_id(
+    tx: &mut Transaction<'_>,
+    distribution: &Distribution,
+) -> ImportPackagesXmlResult<Option<IdType>> {
+    let query_str =
+        "SELECT distribution_id FROM distribution_view WHERE package = $1 AND version_name = $2";
+    log::info!("SQL\n{}", query_str);
+    log::info!("Prepared\n{:?}", distribution);
+    let rows = tx
+        .query(
+            query_str,
+            &[&distribution.package(), &distribution.version()],
+        )
+        .context(TokioPostgresError {
+            msg: "failed to look up distribution",
+        })?;
+    Ok(rows.first().map(|row| row.get(0)))
+}
+
+// Record the entity in `missing` if it does not exist. Entities already known
+// to exist, or to be missing, are not looked up again.
+fn check_exists<F>(
+    found: &mut HashSet<(&'static str, String)>,
+    missing: &mut Vec<String>,
+    kind: &'static str,
+    name: String,
+    exists: F,
+) -> ImportPackagesXmlResult<()>
+where
+    F: FnOnce(&str) -> ImportPackagesXmlResult<bool>,
+{
+    let key = (kind, name);
+    if found.contains(&key) || missing.contains(&key.1) {
+        return Ok(());
+    }
+    if exists(&key.1)? {
+        found.insert(key);
+    } else {
+        missing.push(key.1);
+    }
+    Ok(())
+}
+
+// Does the level, platform or site with the name exist? `to_path` is the
+// function converting the name to the path stored in the table.
+fn coord_exists(
+    tx: &mut Transaction<'_>,
+    table: &'static str,
+    to_path: &'static str,
+    name: &str,
+) -> ImportPackagesXmlResult<bool> {
+    let query_str = format!(
+        "SELECT path::TEXT FROM {} WHERE path = {}($1)",
+        table, to_path
+    );
+    let found = query_strings(tx, &query_str, &[&name], "failed to look up coords")?;
+    Ok(!found.is_empty())
+}
+
+// Determine the missing entities, and the action to take for each versionpin
+fn plan_import(
+    tx: &mut Transaction<'_>,
+    pins: &[XmlVersionPin],
+) -> ImportPackagesXmlResult<ImportPlan> {
+    if pins.is_empty() {
+        return NoVersionPinsError.fail();
+    }
+    let mut plan = ImportPlan::default();
+    let mut found = HashSet::new();
+    for pin in pins {
+        let distribution = &pin.versionpin.distribution;
+        let coords = &pin.versionpin.coords;
+        let packages =
+            std::iter::once(distribution.package()).chain(pin.withs.iter().map(|w| w.as_str()));
+        for package in packages {
+            check_exists(
+                &mut found,
+                &mut plan.packages,
+                "package",
+                package.to_string(),
+                |package| {
+                    let rows = query_strings(
+                        tx,
+                        "SELECT name FROM package WHERE name = $1",
+                        &[&package],
+                        "failed to look up package",
+                    )?;
+                    Ok(!rows.is_empty())
+                },
+            )?;
+        }
+        check_exists(
+            &mut found,
+            &mut plan.distributions,
+            "distribution",
+            distribution.distribution().to_string(),
+            |_| Ok(find_distribution_id(tx, distribution)?.is_some()),
+        )?;
+        check_exists(
+            &mut found,
+            &mut plan.levels,
+            "level",
+            coords.level().to_string(),
+            |level| coord_exists(tx, "level", "level_name_to_path", level),
+        )?;
+        if !coords.role().is_any() {
+            check_exists(
+                &mut found,
+                &mut plan.roles,
+                "role",
+                coords.role().to_string(),
+                |role| {
+                    let rows = query_strings(
+                        tx,
+                        "SELECT name FROM role_view WHERE name = $1",
+                        &[&role],
+                        "failed to look up role",
+                    )?;
+                    Ok(!rows.is_empty())
+                },
+            )?;
+        }
+        check_exists(
+            &mut found,
+            &mut plan.platforms,
+            "platform",
+            coords.platform().to_string(),
+            |platform| coord_exists(tx, "platform", "any_name_to_path", platform),
+        )?;
+        check_exists(
+            &mut found,
+            &mut plan.sites,
+            "site",
+            coords.site().to_string(),
+            |site| coord_exists(tx, "site", "any_name_to_path", site),
+        )?;
+        let versionpin_id =
+            find_versionpin_id(tx, distribution.package(), coords).context(TokioPostgresError {
+                msg: "failed to look up versionpin",
+            })?;
+        let (action, old_withs) = match versionpin_id {
+            Some(versionpin_id) => {
+                let query_str = "SELECT package, version_name FROM distribution_view
+                    WHERE distribution_id = (SELECT distribution FROM versionpin WHERE id = $1)";
+                log::info!("SQL\n{}", query_str);
+                log::info!("Prepared\n{:?}", versionpin_id);
+                let rows = tx
+                    .query(query_str, &[&versionpin_id])
+                    .context(TokioPostgresError {
+                        msg: "failed to look up versionpin distribution",
+                    })?;
+                let action = match rows.first() {
+                    Some(row) => {
+                        let package: &str = row.get(0);
+                        let version: &str = row.get(1);
+                        if version == distribution.version() {
+                            ImportAction::Unchanged
+                        } else {
+                            ImportAction::Update {
+                                old: Distribution::from_parts_unchecked(package, version),
+                            }
+                        }
+                    }
+                    None => ImportAction::Add,
+                };
+                let old_withs = query_strings(
+                    tx,
+                    "SELECT package FROM withpackage WHERE versionpin = $1 ORDER BY pinorder",
+                    &[&versionpin_id],
+                    "failed to look up withs",
+                )?;
+                (action, old_withs)
+            }
+            None => (ImportAction::Add, Vec::new()),
+        };
+        plan.versionpins.push(PlannedVersionPin {
+            pin: pin.clone(),
+            action,
+            old_withs,
+        });
+    }
+    Ok(plan)
+}
+
+// Create the missing entities, then add, update and set the withs of the
+// versionpins, returning the transaction so that it may be committed.
+fn apply_import<'a>(
+    mut tx: Transaction<'a>,
+    plan: &ImportPlan,
+) -> ImportPackagesXmlResult<Transaction<'a>> {
+    if !plan.packages.is_empty() {
+        tx = AddPackages::new(tx)
+            .packages(&mut plan.packages.clone())
+            .create()
+            .context(CreatePackagesError)?
+            .take_tx();
+    }
+    if !plan.distributions.is_empty() {
+        tx = AddDistributions::new(tx)
+            .distributions(&mut plan.distributions.clone())
+            .create()
+            .context(CreateDistributionsError)?
+            .take_tx();
+    }
+    if !plan.levels.is_empty() {
+        tx = AddLevels::new(tx)
+            .levels(&mut plan.levels.clone())
+            .create()
+            .context(CreateLevelsError)?
+            .take_tx();
+    }
+    if !plan.roles.is_empty() {
+        tx = AddRoles::new(tx)
+            .roles(&mut plan.roles.clone())
+            .create()
+            .context(CreateRolesError)?
+            .take_tx();
+    }
+    if !plan.platforms.is_empty() {
+        tx = AddPlatforms::new(tx)
+            .platforms(&mut plan.platforms.clone())
+            .create()
+            .context(CreatePlatformsError)?
+            .take_tx();
+    }
+    if !plan.sites.is_empty() {
+        tx = AddSites::new(tx)
+            .sites(&mut plan.sites.clone())
+            .create()
+            .context(CreateSitesError)?
+            .take_tx();
+    }
+    for planned in plan.versionpins.iter().filter(|p| p.has_changes()) {
+        let versionpin = &planned.pin.versionpin;
+        let distribution = &versionpin.distribution;
+        let coords = &versionpin.coords;
+        match planned.action {
+            ImportAction::Add => {
+                // the plan only adds versionpins at unpinned coords, so a
+                // pin appearing since the plan was made is a conflict
+                tx = AddVersionPins::new(
+                    tx,
+                    distribution.package().to_string(),
+                    distribution.version().to_string(),
+                    ConflictPolicy::Fail,
+                )
+                .level(coords.level().clone())
+                .role(coords.role().clone())
+                .platform(coords.platform().clone())
+                .site(coords.site().clone())
+                .create()
+                .context(AddVersionPinError {
+                    versionpin: versionpin.to_string(),
+                })?
+                .take_tx();
+            }
+            ImportAction::Update { .. } => {
+                let versionpin_id = find_versionpin(&mut tx, planned)?;
+                let distribution_id = match find_distribution_id(&mut tx, distribution)? {
+                    Some(id) => id,
+                    None => {
+                        return NotFoundError {
+                            entity: distribution.distribution(),
+                        }
+                        .fail()
+                    }
+                };
+                tx = UpdateVersionPins::new(tx)
+                    .change(VersionPinChange::new(
+                        versionpin_id,
+                        Some(distribution_id),
+                        None,
+                    ))
+                    .update()
+                    .context(UpdateVersionPinError {
+                        versionpin: versionpin.to_string(),
+                    })?
+                    .take_tx();
+            }
+            ImportAction::Unchanged => (),
+        }
+        if !planned.withs_changed() {
+            continue;
+        }
+        let versionpin_id = find_versionpin(&mut tx, planned)?;
+        tx = if planned.pin.withs.is_empty() {
+            DeleteWiths::new(tx)
+                .delete_all(versionpin_id)
+                .context(RemoveWithsError {
+                    versionpin: versionpin.to_string(),
+                })?
+                .take_tx()
+        } else {
+            AddWiths::new(tx)
+                .create(versionpin_id, planned.pin.withs.clone())
+                .context(SetWithsError {
+                    versionpin: versionpin.to_string(),
+                })?
+                .take_tx()
+        };
+    }
+    Ok(tx)
+}
+
+// Look up the id of a planned versionpin, which must exist
+fn find_versionpin(
+    tx: &mut Transaction<'_>,
+    planned: &PlannedVersionPin,
+) -> ImportPackagesXmlResult<IdType> {
+    let versionpin = &planned.pin.versionpin;
+    let id = find_versionpin_id(tx, versionpin.distribution.package(), &versionpin.coords)
+        .context(TokioPostgresError {
+            msg: "failed to look up versionpin",
+        })?;
+    match id {
+        Some(id) => Ok(id),
+        None => NotFoundError {
+            entity: versionpin.to_string(),
+        }
+        .fail(),
+    }
+}
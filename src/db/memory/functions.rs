@@ -0,0 +1,181 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! Counterparts of the sql functions and ltree operators which the packrat
+//! views are built upon (see `migrations/0003_views_and_functions.sql`).
+//!
+//! Paths are stored as `.` separated strings (eg `facility.dev01.rd`), exactly
+//! as ltree prints them.
+use crate::db::search_attribute::LtreeSearchMode;
+
+/// The name of a path; the root for a root, otherwise the path below it
+pub(crate) fn path_name(path: &str) -> &str {
+    match path.find('.') {
+        Some(idx) => &path[idx + 1..],
+        None => path,
+    }
+}
+
+/// The name of a role path, whose pieces are separated by `_`
+pub(crate) fn role_path_name(path: &str) -> String {
+    path_name(path).replace('.', "_")
+}
+
+/// The path of a level, given its name (or path)
+pub(crate) fn level_name_to_path(name: &str) -> String {
+    rooted("facility", name)
+}
+
+/// The path of a role, given its name (or path)
+pub(crate) fn role_name_to_path(name: &str) -> String {
+    if name == "any" || name.starts_with("any.") {
+        name.to_string()
+    } else {
+        format!("any.{}", name.replace('_', "."))
+    }
+}
+
+/// The path of a platform or site, given its name (or path)
+pub(crate) fn any_name_to_path(name: &str) -> String {
+    rooted("any", name)
+}
+
+fn rooted(root: &str, name: &str) -> String {
+    if name == root || (name.starts_with(root) && name[root.len()..].starts_with('.')) {
+        name.to_string()
+    } else {
+        format!("{}.{}", root, name)
+    }
+}
+
+/// The number of labels in a path
+pub(crate) fn nlevel(path: &str) -> usize {
+    path.split('.').count()
+}
+
+/// The ltree `@>` operator. Is `ancestor` equal to, or an ancestor of, `path`?
+pub(crate) fn is_ancestor(ancestor: &str, path: &str) -> bool {
+    path == ancestor || (path.starts_with(ancestor) && path[ancestor.len()..].starts_with('.'))
+}
+
+/// Compare a stored path against the path being searched for, where
+/// `Ancestor` matches stored paths at, or above, the search path and
+/// `Descendant` matches stored paths at, or below, it.
+pub(crate) fn ltree_matches(stored: &str, search: &str, mode: &LtreeSearchMode) -> bool {
+    match mode {
+        LtreeSearchMode::Ancestor => is_ancestor(stored, search),
+        LtreeSearchMode::Descendant => is_ancestor(search, stored),
+        LtreeSearchMode::Exact => stored == search,
+    }
+}
+
+/// The sql `LIKE` operator, where `%` matches any run of characters, `_`
+/// any single character, and `\` escapes the next character.
+pub(crate) fn like(value: &str, pattern: &str) -> bool {
+    let value = value.chars().collect::<Vec<_>>();
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    like_from(&value, &pattern)
+}
+
+fn like_from(value: &[char], pattern: &[char]) -> bool {
+    match pattern.first() {
+        None => value.is_empty(),
+        Some('%') => (0..=value.len()).any(|idx| like_from(&value[idx..], &pattern[1..])),
+        Some('_') => !value.is_empty() && like_from(&value[1..], &pattern[1..]),
+        Some('\\') if pattern.len() > 1 => {
+            value.first() == Some(&pattern[1]) && like_from(&value[1..], &pattern[2..])
+        }
+        Some(c) => value.first() == Some(c) && like_from(&value[1..], &pattern[1..]),
+    }
+}
+
+/// Order versions numerically by their `.` separated pieces, so that
+/// 2018.sp10 sorts after 2018.sp9
+pub(crate) fn version_ordering(version: &str) -> Vec<u64> {
+    version
+        .split('.')
+        .map(|piece| {
+            let digits = piece
+                .chars()
+                .filter(|c| c.is_ascii_digit())
+                .collect::<String>();
+            if digits.is_empty() {
+                0
+            } else {
+                digits.parse().unwrap_or(u64::MAX)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_convert_between_names_and_paths() {
+        assert_eq!(path_name("facility"), "facility");
+        assert_eq!(path_name("facility.dev01.rd"), "dev01.rd");
+        assert_eq!(role_path_name("any.model.beta"), "model_beta");
+        assert_eq!(level_name_to_path("dev01"), "facility.dev01");
+        assert_eq!(level_name_to_path("facility.dev01"), "facility.dev01");
+        assert_eq!(level_name_to_path("facilityx"), "facility.facilityx");
+        assert_eq!(role_name_to_path("model_beta"), "any.model.beta");
+        assert_eq!(any_name_to_path("any"), "any");
+        assert_eq!(any_name_to_path("cent7_64"), "any.cent7_64");
+    }
+
+    #[test]
+    fn ltree_operators_respect_label_boundaries() {
+        assert!(is_ancestor("facility", "facility.dev01"));
+        assert!(is_ancestor("facility.dev01", "facility.dev01"));
+        assert!(!is_ancestor("facility.dev0", "facility.dev01"));
+        assert!(!is_ancestor("facility.dev01", "facility"));
+        assert_eq!(nlevel("facility.dev01.rd"), 3);
+    }
+
+    #[test]
+    fn ltree_matches_follows_search_mode() {
+        let ancestor = LtreeSearchMode::Ancestor;
+        let descendant = LtreeSearchMode::Descendant;
+        assert!(ltree_matches("facility", "facility.dev01", &ancestor));
+        assert!(!ltree_matches(
+            "facility.dev01.rd",
+            "facility.dev01",
+            &ancestor
+        ));
+        assert!(ltree_matches(
+            "facility.dev01.rd",
+            "facility.dev01",
+            &descendant
+        ));
+        assert!(!ltree_matches(
+            "facility.dev01",
+            "facility.dev01.rd",
+            &LtreeSearchMode::Exact
+        ));
+    }
+
+    #[test]
+    fn like_matches_wildcards() {
+        assert!(like("maya", "ma%"));
+        assert!(like("maya", "%"));
+        assert!(like("maya", "m_y_"));
+        assert!(!like("maya", "m_y"));
+        assert!(!like("houdini", "ma%"));
+        assert!(like("50%", "50\\%"));
+        assert!(!like("500", "50\\%"));
+    }
+
+    #[test]
+    fn versions_order_numerically() {
+        assert!(version_ordering("2018.sp10") > version_ordering("2018.sp9"));
+        assert!(version_ordering("1.10.0") > version_ordering("1.9.2"));
+        assert_eq!(version_ordering("beta"), vec![0]);
+    }
+}
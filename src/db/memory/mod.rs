@@ -0,0 +1,1502 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! An in-memory packrat database, for testing code built on packybara
+//! without a connection to postgres.
+//!
+//! The `MemoryStore` is a `Backend`, so a `PackratDb` wrapping one supports
+//! the full `PBFind` api, including ltree ancestor / descendant searches,
+//! versionpin resolution, the audit log and `as_of` queries. Packages,
+//! levels, roles, platforms, sites, distributions, versionpins and withs are
+//! added (and versionpins updated) via the usual builders, which the store
+//! provides through `PBAdd` and `PBUpdate`, using a `MemoryTransaction` in
+//! place of a postgres Transaction:
+//!
+//! ```
+//! use packybara::db::memory::MemoryStore;
+//! use packybara::packrat::PackratDb;
+//! use packybara::traits::*;
+//!
+//! let mut store = MemoryStore::new();
+//! MemoryStore::add_packages(store.transaction())
+//!     .package("maya")
+//!     .create()
+//!     .unwrap()
+//!     .commit("jgerber", "added maya")
+//!     .unwrap();
+//! let mut db = PackratDb::new(&mut store);
+//! let packages = db.find_all_packages().query().unwrap();
+//! assert_eq!(packages[0].name, "maya");
+//! ```
+//!
+//! The store mirrors the constraints of the packrat schema: foreign keys
+//! are checked, duplicate inserts are ignored where the sql ignores them,
+//! and a transaction's changes only become visible once it is committed.
+use crate::db::add::versionpins::ConflictPolicy;
+use crate::db::backend::Backend;
+use crate::db::delete::Cascaded;
+use crate::db::find::pins::{FindPins, FindPinsError, FindPinsRow};
+use crate::db::find::versionpin::{FindVersionPin, FindVersionPinError};
+use crate::db::find::versionpins::{FindVersionPins, FindVersionPinsRow};
+use crate::db::find::withs::{FindWiths, FindWithsError, FindWithsRow};
+use crate::db::find_all::changes::{
+    ChangeAction, FindAllChanges, FindAllChangesError, FindAllChangesRow,
+};
+use crate::db::find_all::distributions::{
+    FindAllDistributions, FindAllDistributionsError, FindAllDistributionsRow,
+};
+use crate::db::find_all::levels::{FindAllLevels, FindAllLevelsError, FindAllLevelsRow};
+use crate::db::find_all::packages::{FindAllPackages, FindAllPackagesError, FindAllPackagesRow};
+use crate::db::find_all::pkgcoords::{
+    FindAllPkgCoords, FindAllPkgCoordsError, FindAllPkgCoordsRow,
+};
+use crate::db::find_all::platforms::{
+    FindAllPlatforms, FindAllPlatformsError, FindAllPlatformsRow,
+};
+use crate::db::find_all::revisions::{
+    FindAllRevisions, FindAllRevisionsError, FindAllRevisionsRow,
+};
+use crate::db::find_all::roles::{FindAllRoles, FindAllRolesError, FindAllRolesRow};
+use crate::db::find_all::sites::{FindAllSites, FindAllSitesError, FindAllSitesRow};
+use crate::db::find_all::versionpin_withs::{FindAllWiths, FindAllWithsError, FindAllWithsRow};
+use crate::db::find_all::versionpins::{
+    FindAllVersionPins, FindAllVersionPinsError, FindAllVersionPinsRow,
+};
+use crate::db::traits::{PBAdd, PBDelete, PBUpdate};
+use crate::db::{add, delete, update};
+use crate::types::{IdType, LongIdType};
+use chrono::{DateTime, Local};
+use snafu::Snafu;
+use std::collections::BTreeSet;
+
+pub(crate) mod functions;
+pub(crate) mod views;
+
+/// Error type returned when a change violates the constraints of the store
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub enum MemoryStoreError {
+    /// The package does not exist
+    #[snafu(display("Package {} does not exist", package))]
+    UnknownPackageError { package: String },
+    /// The distribution does not exist
+    #[snafu(display("Distribution {} does not exist", distribution))]
+    UnknownDistributionError { distribution: String },
+    /// The level, role, platform or site does not exist
+    #[snafu(display("The {} {} does not exist", kind, path))]
+    UnknownPathError { kind: &'static str, path: String },
+    /// The pkgcoord does not exist
+    #[snafu(display("Pkgcoord {} does not exist", id))]
+    UnknownPkgCoordError { id: IdType },
+    /// The versionpin does not exist
+    #[snafu(display("Versionpin {} does not exist", id))]
+    UnknownVersionPinError { id: IdType },
+    /// A versionpin already exists at the pkgcoord
+    #[snafu(display("A versionpin already exists at pkgcoord {}", coord))]
+    DuplicateVersionPinError { coord: IdType },
+}
+
+pub type MemoryStoreResult<T, E = MemoryStoreError> = std::result::Result<T, E>;
+
+/// A row of the distribution table
+#[derive(Debug, Clone)]
+pub(crate) struct DistributionRecord {
+    pub(crate) id: IdType,
+    pub(crate) package: String,
+    pub(crate) version: String,
+}
+
+/// A row of the pkgcoord table. The coordinates are paths
+#[derive(Debug, Clone)]
+pub(crate) struct PkgCoordRecord {
+    pub(crate) id: IdType,
+    pub(crate) package: String,
+    pub(crate) level: String,
+    pub(crate) role: String,
+    pub(crate) platform: String,
+    pub(crate) site: String,
+}
+
+/// A row of the versionpin table
+#[derive(Debug, Clone)]
+pub(crate) struct VersionPinRecord {
+    pub(crate) id: IdType,
+    pub(crate) distribution: IdType,
+    pub(crate) coord: IdType,
+}
+
+/// A row of the withpackage table
+#[derive(Debug, Clone)]
+pub(crate) struct WithPackageRecord {
+    pub(crate) id: IdType,
+    pub(crate) versionpin: IdType,
+    pub(crate) package: String,
+    pub(crate) pinorder: IdType,
+}
+
+/// A row of the revision table
+#[derive(Debug, Clone)]
+pub(crate) struct RevisionRecord {
+    pub(crate) id: IdType,
+    pub(crate) transaction_id: LongIdType,
+    pub(crate) author: String,
+    pub(crate) comment: String,
+    pub(crate) datetime: DateTime<Local>,
+}
+
+/// A change to the versionpin table, as recorded by the audit trigger.
+/// `distribution` and `coord` are those of the row before an update or
+/// delete, and after an insert. The `changed_` fields hold the new values
+/// of the columns changed by an update.
+#[derive(Debug, Clone)]
+pub(crate) struct AuditRecord {
+    pub(crate) versionpin: IdType,
+    pub(crate) transaction_id: LongIdType,
+    pub(crate) action: ChangeAction,
+    pub(crate) distribution: IdType,
+    pub(crate) coord: IdType,
+    pub(crate) changed_distribution: Option<IdType>,
+    pub(crate) changed_coord: Option<IdType>,
+}
+
+/// A change to the withpackage table, as recorded by the audit trigger.
+/// `with` is the row before a delete, or after an insert.
+#[derive(Debug, Clone)]
+pub(crate) struct WithPackageAuditRecord {
+    pub(crate) transaction_id: LongIdType,
+    pub(crate) action: ChangeAction,
+    pub(crate) with: WithPackageRecord,
+}
+
+/// An entry in the audit log. Entries are numbered by their position in
+/// the log, as postgres numbers them by event id.
+#[derive(Debug, Clone)]
+pub(crate) enum AuditEntry {
+    VersionPin(AuditRecord),
+    WithPackage(WithPackageAuditRecord),
+}
+
+/// The contents of the store
+#[derive(Debug, Clone)]
+pub(crate) struct Tables {
+    pub(crate) packages: BTreeSet<String>,
+    pub(crate) distributions: Vec<DistributionRecord>,
+    pub(crate) levels: BTreeSet<String>,
+    pub(crate) roles: BTreeSet<String>,
+    pub(crate) platforms: BTreeSet<String>,
+    pub(crate) sites: BTreeSet<String>,
+    pub(crate) pkgcoords: Vec<PkgCoordRecord>,
+    pub(crate) versionpins: Vec<VersionPinRecord>,
+    pub(crate) withpackages: Vec<WithPackageRecord>,
+    pub(crate) revisions: Vec<RevisionRecord>,
+    pub(crate) audit: Vec<AuditEntry>,
+}
+
+impl Tables {
+    // The tables of a freshly migrated database, with each root seeded
+    fn new() -> Self {
+        let root = |path: &str| {
+            let mut paths = BTreeSet::new();
+            paths.insert(path.to_string());
+            paths
+        };
+        Self {
+            packages: BTreeSet::new(),
+            distributions: Vec::new(),
+            levels: root("facility"),
+            roles: root("any"),
+            platforms: root("any"),
+            sites: root("any"),
+            pkgcoords: Vec::new(),
+            versionpins: Vec::new(),
+            withpackages: Vec::new(),
+            revisions: Vec::new(),
+            audit: Vec::new(),
+        }
+    }
+}
+
+// The next value of a serial column
+fn next_id<T>(records: &[T], id: impl Fn(&T) -> IdType) -> IdType {
+    records.iter().map(id).max().unwrap_or(0) + 1
+}
+
+/// An in-memory packrat database. Wrap it in a `PackratDb` to query it.
+#[derive(Debug, Clone)]
+pub struct MemoryStore {
+    tables: Tables,
+    transaction_id: LongIdType,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryStore {
+    /// New up an empty MemoryStore, holding only the root level
+    /// (`facility`), role, platform and site (`any`).
+    pub fn new() -> Self {
+        Self {
+            tables: Tables::new(),
+            transaction_id: 0,
+        }
+    }
+
+    /// Start a transaction, in which to add or update the contents of the
+    /// store. The changes are discarded unless the transaction is committed.
+    ///
+    /// # Returns
+    /// * MemoryTransaction instance
+    pub fn transaction(&mut self) -> MemoryTransaction<'_> {
+        self.transaction_id += 1;
+        MemoryTransaction {
+            tables: self.tables.clone(),
+            transaction_id: self.transaction_id,
+            started: Local::now(),
+            store: self,
+        }
+    }
+
+    // The committed contents of the store
+    pub(crate) fn tables(&self) -> &Tables {
+        &self.tables
+    }
+}
+
+/// A transaction against a MemoryStore. Changes are made to a copy of the
+/// store's tables, which replaces them when the transaction is committed.
+pub struct MemoryTransaction<'a> {
+    store: &'a mut MemoryStore,
+    tables: Tables,
+    transaction_id: LongIdType,
+    started: DateTime<Local>,
+}
+
+impl<'a> MemoryTransaction<'a> {
+    /// The id of the transaction, recorded against its revision and changes
+    pub fn transaction_id(&self) -> LongIdType {
+        self.transaction_id
+    }
+
+    // The contents of the store, as changed by the transaction
+    pub(crate) fn tables(&self) -> &Tables {
+        &self.tables
+    }
+
+    /// Commit the transaction, recording a revision with the supplied author
+    /// and comment.
+    ///
+    /// # Arguments
+    /// * `author` - The author of the revision
+    /// * `comment` - A description of the changes
+    pub fn commit(mut self, author: &str, comment: &str) {
+        let id = next_id(&self.tables.revisions, |revision| revision.id);
+        self.tables.revisions.push(RevisionRecord {
+            id,
+            transaction_id: self.transaction_id,
+            author: author.to_string(),
+            comment: comment.to_string(),
+            datetime: self.started,
+        });
+        self.store.tables = self.tables;
+    }
+
+    /// Add packages, ignoring those which already exist. Returns the number added
+    pub(crate) fn add_packages(&mut self, names: &[String]) -> u64 {
+        names
+            .iter()
+            .filter(|name| self.tables.packages.insert(name.to_string()))
+            .count() as u64
+    }
+
+    /// Add level paths, ignoring those which already exist. Returns the number added
+    pub(crate) fn add_levels(&mut self, paths: &[String]) -> u64 {
+        insert_paths(&mut self.tables.levels, paths)
+    }
+
+    /// Add role paths, ignoring those which already exist. Returns the number added
+    pub(crate) fn add_roles(&mut self, paths: &[String]) -> u64 {
+        insert_paths(&mut self.tables.roles, paths)
+    }
+
+    /// Add platform paths, ignoring those which already exist. Returns the number added
+    pub(crate) fn add_platforms(&mut self, paths: &[String]) -> u64 {
+        insert_paths(&mut self.tables.platforms, paths)
+    }
+
+    /// Add site paths, ignoring those which already exist. Returns the number added
+    pub(crate) fn add_sites(&mut self, paths: &[String]) -> u64 {
+        insert_paths(&mut self.tables.sites, paths)
+    }
+
+    /// Add distributions of existing packages, ignoring those which already
+    /// exist. Returns the number added
+    pub(crate) fn add_distributions(
+        &mut self,
+        distributions: &[(String, String)],
+    ) -> MemoryStoreResult<u64> {
+        let mut cnt = 0;
+        for (package, version) in distributions {
+            if !self.tables.packages.contains(package) {
+                return UnknownPackageError { package }.fail();
+            }
+            if self.tables.distribution(package, version).is_some() {
+                continue;
+            }
+            let id = next_id(&self.tables.distributions, |dist| dist.id);
+            self.tables.distributions.push(DistributionRecord {
+                id,
+                package: package.clone(),
+                version: version.clone(),
+            });
+            cnt += 1;
+        }
+        Ok(cnt)
+    }
+
+    /// The counterpart of the `INSERT_VERSIONPIN` function. Pin a distribution
+    /// (eg maya-2018.sp3) at the supplied level, site, role and platform
+    /// names, creating the pkgcoord if need be.
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - The id of the new versionpin
+    ///   * `Err` - MemoryStoreError
+    pub(crate) fn insert_versionpin(
+        &mut self,
+        distribution: &str,
+        level: &str,
+        site: &str,
+        role: &str,
+        platform: &str,
+    ) -> MemoryStoreResult<IdType> {
+        let (dist_id, package) = self
+            .tables
+            .distribution_view()
+            .into_iter()
+            .find(|dist| dist.name == distribution)
+            .map(|dist| (dist.distribution_id, dist.package))
+            .ok_or_else(|| MemoryStoreError::UnknownDistributionError {
+                distribution: distribution.to_string(),
+            })?;
+        let coord = self.pkgcoord(
+            &package,
+            functions::level_name_to_path(level),
+            functions::role_name_to_path(role),
+            functions::any_name_to_path(platform),
+            functions::any_name_to_path(site),
+        )?;
+        if self.tables.versionpins.iter().any(|pin| pin.coord == coord) {
+            return DuplicateVersionPinError { coord }.fail();
+        }
+        let id = next_id(&self.tables.versionpins, |pin| pin.id);
+        self.tables.versionpins.push(VersionPinRecord {
+            id,
+            distribution: dist_id,
+            coord,
+        });
+        self.audit(id, ChangeAction::Insert, dist_id, coord, None, None);
+        Ok(id)
+    }
+
+    /// Update the distribution and / or pkgcoord of a versionpin. Returns
+    /// the number of versionpins updated.
+    pub(crate) fn update_versionpin(
+        &mut self,
+        id: IdType,
+        distribution: Option<IdType>,
+        coord: Option<IdType>,
+    ) -> MemoryStoreResult<u64> {
+        if let Some(distribution) = distribution {
+            if !self
+                .tables
+                .distributions
+                .iter()
+                .any(|dist| dist.id == distribution)
+            {
+                return UnknownDistributionError {
+                    distribution: distribution.to_string(),
+                }
+                .fail();
+            }
+        }
+        if let Some(coord) = coord {
+            if self.tables.pkgcoord(coord).is_none() {
+                return UnknownPkgCoordError { id: coord }.fail();
+            }
+            if self
+                .tables
+                .versionpins
+                .iter()
+                .any(|pin| pin.coord == coord && pin.id != id)
+            {
+                return DuplicateVersionPinError { coord }.fail();
+            }
+        }
+        let pin = match self.tables.versionpins.iter_mut().find(|pin| pin.id == id) {
+            Some(pin) => pin,
+            None => return Ok(0),
+        };
+        let old = pin.clone();
+        let changed_distribution = distribution.filter(|dist| *dist != pin.distribution);
+        let changed_coord = coord.filter(|coord| *coord != pin.coord);
+        pin.distribution = distribution.unwrap_or(pin.distribution);
+        pin.coord = coord.unwrap_or(pin.coord);
+        // the audit trigger ignores updates which change nothing
+        if changed_distribution.is_some() || changed_coord.is_some() {
+            self.audit(
+                id,
+                ChangeAction::Update,
+                old.distribution,
+                old.coord,
+                changed_distribution,
+                changed_coord,
+            );
+        }
+        Ok(1)
+    }
+
+    /// Replace the withs of a versionpin. Returns the number of withs added
+    pub(crate) fn set_withs(
+        &mut self,
+        versionpin: IdType,
+        withs: &[String],
+    ) -> MemoryStoreResult<u64> {
+        if !self
+            .tables
+            .versionpins
+            .iter()
+            .any(|pin| pin.id == versionpin)
+        {
+            return UnknownVersionPinError { id: versionpin }.fail();
+        }
+        if let Some(package) = withs
+            .iter()
+            .find(|package| !self.tables.packages.contains(*package))
+        {
+            return UnknownPackageError { package }.fail();
+        }
+        // number the new rows after those removed, so that the audit log can
+        // tell them apart
+        let first_id = next_id(&self.tables.withpackages, |with| with.id);
+        let (removed, kept) = self
+            .tables
+            .withpackages
+            .drain(..)
+            .partition::<Vec<_>, _>(|with| with.versionpin == versionpin);
+        self.tables.withpackages = kept;
+        for with in removed {
+            self.audit_with(ChangeAction::Delete, with);
+        }
+        for (pinorder, package) in withs.iter().enumerate() {
+            let with = WithPackageRecord {
+                id: first_id + pinorder as IdType,
+                versionpin,
+                package: package.clone(),
+                pinorder: pinorder as IdType,
+            };
+            self.tables.withpackages.push(with.clone());
+            self.audit_with(ChangeAction::Insert, with);
+        }
+        Ok(withs.len() as u64)
+    }
+
+    /// Remove the versionpins, along with their withs. Returns the number of
+    /// versionpins and withs removed
+    pub(crate) fn remove_versionpins(&mut self, ids: &[IdType]) -> Cascaded {
+        let withs = self.remove_withs(|with| ids.contains(&with.versionpin));
+        let (removed, kept) = self
+            .tables
+            .versionpins
+            .drain(..)
+            .partition::<Vec<_>, _>(|pin| ids.contains(&pin.id));
+        self.tables.versionpins = kept;
+        for pin in &removed {
+            self.audit(
+                pin.id,
+                ChangeAction::Delete,
+                pin.distribution,
+                pin.coord,
+                None,
+                None,
+            );
+        }
+        Cascaded {
+            versionpins: removed.len() as u64,
+            withs,
+        }
+    }
+
+    /// Remove the withs selected by `matches`. Returns the number removed
+    pub(crate) fn remove_withs(&mut self, matches: impl Fn(&WithPackageRecord) -> bool) -> u64 {
+        let (removed, kept) = self
+            .tables
+            .withpackages
+            .drain(..)
+            .partition::<Vec<_>, _>(|with| matches(with));
+        self.tables.withpackages = kept;
+        let cnt = removed.len() as u64;
+        for with in removed {
+            self.audit_with(ChangeAction::Delete, with);
+        }
+        cnt
+    }
+
+    /// Number the withs of a versionpin from 0, retaining their order
+    pub(crate) fn renumber_withs(&mut self, versionpin: IdType) {
+        let mut withs = self
+            .tables
+            .withpackages
+            .iter_mut()
+            .filter(|with| with.versionpin == versionpin)
+            .collect::<Vec<_>>();
+        withs.sort_by_key(|with| with.pinorder);
+        for (pinorder, with) in withs.into_iter().enumerate() {
+            with.pinorder = pinorder as IdType;
+        }
+    }
+
+    /// Remove the distributions selected by `matches`. Returns the number removed
+    pub(crate) fn remove_distributions(
+        &mut self,
+        matches: impl Fn(&DistributionRecord) -> bool,
+    ) -> u64 {
+        let cnt = self.tables.distributions.len();
+        self.tables.distributions.retain(|dist| !matches(dist));
+        (cnt - self.tables.distributions.len()) as u64
+    }
+
+    /// Remove the pkgcoords selected by `matches`. Returns the number removed
+    pub(crate) fn remove_pkgcoords(&mut self, matches: impl Fn(&PkgCoordRecord) -> bool) -> u64 {
+        let cnt = self.tables.pkgcoords.len();
+        self.tables.pkgcoords.retain(|coord| !matches(coord));
+        (cnt - self.tables.pkgcoords.len()) as u64
+    }
+
+    /// Remove a package. Returns the number removed
+    pub(crate) fn remove_package(&mut self, name: &str) -> u64 {
+        self.tables.packages.remove(name) as u64
+    }
+
+    /// Remove a level path, along with the paths below it. Returns the number removed
+    pub(crate) fn remove_levels(&mut self, path: &str) -> u64 {
+        remove_paths(&mut self.tables.levels, path)
+    }
+
+    /// Remove a role path, along with the paths below it. Returns the number removed
+    pub(crate) fn remove_roles(&mut self, path: &str) -> u64 {
+        remove_paths(&mut self.tables.roles, path)
+    }
+
+    // Retrieve the id of the pkgcoord, creating it if need be
+    fn pkgcoord(
+        &mut self,
+        package: &str,
+        level: String,
+        role: String,
+        platform: String,
+        site: String,
+    ) -> MemoryStoreResult<IdType> {
+        if let Some(coord) = self.tables.pkgcoords.iter().find(|coord| {
+            coord.package == package
+                && coord.level == level
+                && coord.role == role
+                && coord.platform == platform
+                && coord.site == site
+        }) {
+            return Ok(coord.id);
+        }
+        for (kind, paths, path) in &[
+            ("level", &self.tables.levels, &level),
+            ("role", &self.tables.roles, &role),
+            ("platform", &self.tables.platforms, &platform),
+            ("site", &self.tables.sites, &site),
+        ] {
+            if !paths.contains(*path) {
+                return UnknownPathError {
+                    kind: *kind,
+                    path: path.to_string(),
+                }
+                .fail();
+            }
+        }
+        let id = next_id(&self.tables.pkgcoords, |coord| coord.id);
+        self.tables.pkgcoords.push(PkgCoordRecord {
+            id,
+            package: package.to_string(),
+            level,
+            role,
+            platform,
+            site,
+        });
+        Ok(id)
+    }
+
+    // Record a change to the versionpin table in the audit log
+    fn audit(
+        &mut self,
+        versionpin: IdType,
+        action: ChangeAction,
+        distribution: IdType,
+        coord: IdType,
+        changed_distribution: Option<IdType>,
+        changed_coord: Option<IdType>,
+    ) {
+        self.tables.audit.push(AuditEntry::VersionPin(AuditRecord {
+            versionpin,
+            transaction_id: self.transaction_id,
+            action,
+            distribution,
+            coord,
+            changed_distribution,
+            changed_coord,
+        }));
+    }
+
+    // Record a change to the withpackage table in the audit log
+    fn audit_with(&mut self, action: ChangeAction, with: WithPackageRecord) {
+        self.tables
+            .audit
+            .push(AuditEntry::WithPackage(WithPackageAuditRecord {
+                transaction_id: self.transaction_id,
+                action,
+                with,
+            }));
+    }
+}
+
+// Insert paths into a path table, returning the number which were new
+fn insert_paths(table: &mut BTreeSet<String>, paths: &[String]) -> u64 {
+    paths
+        .iter()
+        .filter(|path| table.insert(path.to_string()))
+        .count() as u64
+}
+
+// Remove a path, and the paths below it, from a path table, returning the
+// number removed
+fn remove_paths(table: &mut BTreeSet<String>, path: &str) -> u64 {
+    let cnt = table.len();
+    table.retain(|stored| !functions::is_ancestor(path, stored));
+    (cnt - table.len()) as u64
+}
+
+impl Backend for MemoryStore {
+    fn find_versionpin(
+        find: &mut FindVersionPin<'_, Self>,
+    ) -> Result<FindVersionPinsRow, FindVersionPinError> {
+        find.query_memory()
+    }
+
+    fn find_versionpins(
+        find: &mut FindVersionPins<'_, Self>,
+    ) -> Result<Vec<FindVersionPinsRow>, Box<dyn std::error::Error>> {
+        find.query_memory()
+    }
+
+    fn find_all_versionpins(
+        find: &mut FindAllVersionPins<'_, Self>,
+    ) -> Result<Vec<FindAllVersionPinsRow>, FindAllVersionPinsError> {
+        find.query_memory()
+    }
+
+    fn find_all_roles(
+        find: &mut FindAllRoles<'_, Self>,
+    ) -> Result<Vec<FindAllRolesRow>, FindAllRolesError> {
+        find.query_memory()
+    }
+
+    fn find_all_revisions(
+        find: &mut FindAllRevisions<'_, Self>,
+    ) -> Result<Vec<FindAllRevisionsRow>, FindAllRevisionsError> {
+        find.query_memory()
+    }
+
+    fn find_all_changes(
+        find: &mut FindAllChanges<'_, Self>,
+    ) -> Result<Vec<FindAllChangesRow>, FindAllChangesError> {
+        find.query_memory()
+    }
+
+    fn find_all_platforms(
+        find: &mut FindAllPlatforms<'_, Self>,
+    ) -> Result<Vec<FindAllPlatformsRow>, FindAllPlatformsError> {
+        find.query_memory()
+    }
+
+    fn find_all_sites(
+        find: &mut FindAllSites<'_, Self>,
+    ) -> Result<Vec<FindAllSitesRow>, FindAllSitesError> {
+        find.query_memory()
+    }
+
+    fn find_all_levels(
+        find: &mut FindAllLevels<'_, Self>,
+    ) -> Result<Vec<FindAllLevelsRow>, FindAllLevelsError> {
+        find.query_memory()
+    }
+
+    fn find_all_packages(
+        find: &mut FindAllPackages<'_, Self>,
+    ) -> Result<Vec<FindAllPackagesRow>, FindAllPackagesError> {
+        find.query_memory()
+    }
+
+    fn find_all_versionpin_withs(
+        find: &mut FindAllWiths<'_, Self>,
+    ) -> Result<Vec<FindAllWithsRow>, FindAllWithsError> {
+        find.query_memory()
+    }
+
+    fn find_all_distributions(
+        find: &mut FindAllDistributions<'_, Self>,
+    ) -> Result<Vec<FindAllDistributionsRow>, FindAllDistributionsError> {
+        find.query_memory()
+    }
+
+    fn find_pins(find: &mut FindPins<'_, Self>) -> Result<Vec<FindPinsRow>, FindPinsError> {
+        find.query_memory()
+    }
+
+    fn find_pkgcoords(
+        find: &mut FindAllPkgCoords<'_, Self>,
+    ) -> Result<Vec<FindAllPkgCoordsRow>, FindAllPkgCoordsError> {
+        find.query_memory()
+    }
+
+    fn find_withs(find: &mut FindWiths<'_, Self>) -> Result<Vec<FindWithsRow>, FindWithsError> {
+        find.query_memory()
+    }
+}
+
+impl<'b> PBAdd<'b> for MemoryStore {
+    type TransactionType = MemoryTransaction<'b>;
+
+    /// add packages
+    fn add_packages(
+        tx: Self::TransactionType,
+    ) -> add::packages::AddPackages<'b, Self::TransactionType> {
+        add::packages::AddPackages::new(tx)
+    }
+
+    /// add levels
+    fn add_levels(tx: Self::TransactionType) -> add::levels::AddLevels<'b, Self::TransactionType> {
+        add::levels::AddLevels::new(tx)
+    }
+
+    /// add roles
+    fn add_roles(tx: Self::TransactionType) -> add::roles::AddRoles<'b, Self::TransactionType> {
+        add::roles::AddRoles::new(tx)
+    }
+
+    /// add platforms
+    fn add_platforms(
+        tx: Self::TransactionType,
+    ) -> add::platforms::AddPlatforms<'b, Self::TransactionType> {
+        add::platforms::AddPlatforms::new(tx)
+    }
+
+    /// add sites
+    fn add_sites(tx: Self::TransactionType) -> add::sites::AddSites<'b, Self::TransactionType> {
+        add::sites::AddSites::new(tx)
+    }
+
+    /// add distributions. The packages must already exist.
+    fn add_distributions(
+        tx: Self::TransactionType,
+    ) -> add::distributions::AddDistributions<'b, Self::TransactionType> {
+        add::distributions::AddDistributions::new(tx)
+    }
+
+    /// Add withs to the transaction
+    fn add_withs(tx: Self::TransactionType) -> add::withs::AddWiths<'b, Self::TransactionType> {
+        add::withs::AddWiths::new(tx)
+    }
+
+    fn add_versionpins<I>(
+        tx: Self::TransactionType,
+        package: I,
+        version: I,
+        policy: ConflictPolicy,
+    ) -> add::versionpins::AddVersionPins<'b, Self::TransactionType>
+    where
+        I: Into<String>,
+    {
+        add::versionpins::AddVersionPins::new(tx, package.into(), version.into(), policy)
+    }
+}
+
+impl<'a> PBUpdate<'a> for MemoryStore {
+    type TransactionType = MemoryTransaction<'a>;
+
+    /// update versionpins
+    fn update_versionpins(
+        tx: Self::TransactionType,
+    ) -> update::versionpins::UpdateVersionPins<'a, Self::TransactionType> {
+        update::versionpins::UpdateVersionPins::new(tx)
+    }
+}
+
+impl<'a> PBDelete<'a> for MemoryStore {
+    type TransactionType = MemoryTransaction<'a>;
+
+    /// delete versionpins, along with their withs
+    fn delete_versionpins(
+        tx: Self::TransactionType,
+    ) -> delete::versionpins::DeleteVersionPins<'a, Self::TransactionType> {
+        delete::versionpins::DeleteVersionPins::new(tx)
+    }
+
+    /// delete withs from a versionpin
+    fn delete_withs(
+        tx: Self::TransactionType,
+    ) -> delete::withs::DeleteWiths<'a, Self::TransactionType> {
+        delete::withs::DeleteWiths::new(tx)
+    }
+
+    /// delete distributions
+    fn delete_distributions(
+        tx: Self::TransactionType,
+    ) -> delete::distributions::DeleteDistributions<'a, Self::TransactionType> {
+        delete::distributions::DeleteDistributions::new(tx)
+    }
+
+    /// delete packages
+    fn delete_packages(
+        tx: Self::TransactionType,
+    ) -> delete::packages::DeletePackages<'a, Self::TransactionType> {
+        delete::packages::DeletePackages::new(tx)
+    }
+
+    /// delete levels
+    fn delete_levels(
+        tx: Self::TransactionType,
+    ) -> delete::levels::DeleteLevels<'a, Self::TransactionType> {
+        delete::levels::DeleteLevels::new(tx)
+    }
+
+    /// delete roles
+    fn delete_roles(
+        tx: Self::TransactionType,
+    ) -> delete::roles::DeleteRoles<'a, Self::TransactionType> {
+        delete::roles::DeleteRoles::new(tx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::add::versionpins::{
+        AddVersionPins, AddVersionPinsError, ConflictPolicy, PinAction,
+    };
+    use crate::db::delete::distributions::DeleteDistributionsError;
+    use crate::db::delete::levels::DeleteLevelsError;
+    use crate::db::delete::packages::DeletePackagesError;
+    use crate::db::delete::roles::DeleteRolesError;
+    use crate::db::delete::withs::DeleteWithsError;
+    use crate::db::find_all::changes::{ChangeType, OrderChangeBy};
+    use crate::db::plan::{EntityKind, PlannedChange};
+    use crate::db::search_attribute::{LtreeSearchMode, OrderDirection};
+    use crate::db::update::versionpins::VersionPinChange;
+    use crate::packrat::PackratDb;
+    use crate::traits::{MemoryTransactionHandler, PBFind};
+
+    // maya-2018.sp3 at facility, maya-2019 at dev01 for model, and
+    // houdini-18.0 at facility, with maya using houdini
+    fn store() -> MemoryStore {
+        let mut store = MemoryStore::new();
+        let mut tx = store.transaction();
+        tx.add_packages(&["maya".to_string(), "houdini".to_string()]);
+        tx.add_levels(&["facility.dev01".to_string()]);
+        tx.add_roles(&["any.model".to_string()]);
+        tx.add_distributions(&[
+            ("maya".to_string(), "2018.sp3".to_string()),
+            ("maya".to_string(), "2019".to_string()),
+            ("houdini".to_string(), "18.0".to_string()),
+        ])
+        .unwrap();
+        tx.commit("jgerber", "setup");
+        let tx = store.transaction();
+        let mut pins = MemoryStore::add_versionpins(tx, "maya", "2018.sp3", ConflictPolicy::Fail)
+            .level("facility")
+            .role("any")
+            .platform("any")
+            .site("any")
+            .create()
+            .unwrap();
+        let tx = pins.tx().unwrap();
+        let maya = tx.tables.versionpins[0].id;
+        tx.insert_versionpin("houdini-18.0", "facility", "any", "any", "any")
+            .unwrap();
+        tx.insert_versionpin("maya-2019", "dev01", "any", "model", "any")
+            .unwrap();
+        tx.set_withs(maya, &["houdini".to_string()]).unwrap();
+        pins.commit("jgerber", "pinned").unwrap();
+        store
+    }
+
+    #[test]
+    fn finds_most_specific_versionpin() {
+        let mut store = store();
+        let mut db = PackratDb::new(&mut store);
+        let pin = db
+            .find_versionpin("maya")
+            .level("dev01.rd")
+            .role("model_beta")
+            .query()
+            .unwrap();
+        assert_eq!(pin.distribution.version(), "2019");
+        let pin = db.find_versionpin("maya").role("anim").query().unwrap();
+        assert_eq!(pin.distribution.version(), "2018.sp3");
+        assert_eq!(pin.withs, Some(vec!["houdini".to_string()]));
+        assert!(db.find_versionpin("nuke").query().is_err());
+    }
+
+    #[test]
+    fn searches_versionpins_by_ancestor_and_descendant() {
+        let mut store = store();
+        let mut db = PackratDb::new(&mut store);
+        let pins = db
+            .find_all_versionpins()
+            .package("maya")
+            .level("dev01")
+            .role("model")
+            .query()
+            .unwrap();
+        assert_eq!(pins.len(), 2);
+        let pins = db
+            .find_all_versionpins()
+            .level("facility")
+            .role("any")
+            .search_mode(LtreeSearchMode::Descendant)
+            .query()
+            .unwrap();
+        assert_eq!(pins.len(), 3);
+    }
+
+    #[test]
+    fn discards_uncommitted_transactions() {
+        let mut store = store();
+        {
+            let mut tx = store.transaction();
+            assert_eq!(tx.add_packages(&["nuke".to_string()]), 1);
+        }
+        let mut db = PackratDb::new(&mut store);
+        let packages = db.find_all_packages().query().unwrap();
+        assert_eq!(packages.len(), 2);
+    }
+
+    #[test]
+    fn rejects_versionpins_violating_constraints() {
+        let mut store = store();
+        let mut tx = store.transaction();
+        assert!(tx
+            .insert_versionpin("maya-2019", "facility", "any", "any", "any")
+            .is_err());
+        assert!(tx
+            .insert_versionpin("maya-2019", "dev02", "any", "any", "any")
+            .is_err());
+        assert!(tx
+            .insert_versionpin("nuke-12.0", "facility", "any", "any", "any")
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_and_unknown_versionpin_coords() {
+        let mut store = store();
+        let tx = store.transaction();
+        let result = MemoryStore::add_versionpins(tx, "houdini", "18.0", ConflictPolicy::Fail)
+            .level("dev01")
+            .role("model beta")
+            .platform("any")
+            .site("any")
+            .create();
+        match result.err() {
+            Some(AddVersionPinsError::ValidationError { errors }) => {
+                assert_eq!(errors.len(), 1);
+                assert!(matches!(
+                    errors[0],
+                    AddVersionPinsError::InvalidRoleError { .. }
+                ));
+            }
+            _ => panic!("expected a ValidationError"),
+        }
+
+        let tx = store.transaction();
+        let result = MemoryStore::add_versionpins(tx, "houdini", "19.0", ConflictPolicy::Fail)
+            .level("dev01")
+            .role("model")
+            .role("anim")
+            .platform("cent7_64")
+            .site("any")
+            .create();
+        match result.err() {
+            Some(AddVersionPinsError::ValidationError { errors }) => {
+                let errors = errors.iter().map(ToString::to_string).collect::<Vec<_>>();
+                assert_eq!(
+                    errors,
+                    vec![
+                        "Role anim does not exist",
+                        "Platform cent7_64 does not exist",
+                        "Distribution houdini-19.0 does not exist",
+                    ]
+                );
+            }
+            _ => panic!("expected a ValidationError"),
+        }
+    }
+
+    #[test]
+    fn applies_conflict_policy_to_pinned_coords() {
+        let mut store = store();
+        let maya = store.tables().versionpins[0].clone();
+        fn add(
+            tx: MemoryTransaction<'_>,
+            policy: ConflictPolicy,
+        ) -> Result<AddVersionPins<'_, MemoryTransaction<'_>>, AddVersionPinsError> {
+            MemoryStore::add_versionpins(tx, "maya", "2019", policy)
+                .level("facility")
+                .level("dev01")
+                .role("any")
+                .platform("any")
+                .site("any")
+                .create()
+        }
+
+        match add(store.transaction(), ConflictPolicy::Fail).err() {
+            Some(AddVersionPinsError::ValidationError { errors }) => {
+                assert_eq!(errors.len(), 1);
+                assert_eq!(
+                    errors[0].to_string(),
+                    "Coords level:facility.role:any.platform:any.site:any \
+                     are already pinned to maya-2018.sp3"
+                );
+            }
+            _ => panic!("expected a ValidationError"),
+        }
+
+        let pins = add(store.transaction(), ConflictPolicy::Skip).unwrap();
+        let outcomes = pins.outcomes();
+        assert_eq!(outcomes.len(), 2);
+        assert_eq!(outcomes[0].action, PinAction::Skipped);
+        assert_eq!(outcomes[0].versionpin_id, maya.id);
+        assert_eq!(
+            outcomes[0].previous.as_ref().unwrap().distribution(),
+            "maya-2018.sp3"
+        );
+        assert_eq!(outcomes[1].action, PinAction::Created);
+        assert_eq!(outcomes[1].coords.level.to_string(), "dev01");
+        assert_eq!(outcomes[1].previous, None);
+        assert_ne!(outcomes[1].versionpin_id, maya.id);
+
+        let mut pins = add(store.transaction(), ConflictPolicy::Overwrite).unwrap();
+        assert_eq!(pins.outcomes()[0].action, PinAction::Overwritten);
+        assert_eq!(pins.outcomes()[0].versionpin_id, maya.id);
+        assert_eq!(pins.commit("jgerber", "overwrite maya").unwrap(), 2);
+        let mut db = PackratDb::new(&mut store);
+        let pin = db.find_versionpin("maya").query().unwrap();
+        assert_eq!(pin.versionpin_id, maya.id);
+        assert_eq!(pin.distribution.version(), "2019");
+    }
+
+    #[test]
+    fn previews_changes_without_committing() {
+        let mut store = store();
+        let maya = store.tables().versionpins[0].clone();
+        let tx = store.transaction();
+        let plan = MemoryStore::add_levels(tx)
+            .level("dev01.rd")
+            .preview()
+            .unwrap();
+        assert_eq!(
+            plan.changes(),
+            &[PlannedChange::Add {
+                kind: EntityKind::Level,
+                name: "facility.dev01.rd".to_string()
+            }]
+        );
+
+        let tx = store.transaction();
+        let plan = MemoryStore::update_versionpins(tx)
+            .change(VersionPinChange::new(maya.id, Some(2), None))
+            .preview()
+            .unwrap();
+        match plan.changes() {
+            [PlannedChange::VersionPin {
+                action,
+                versionpin_id,
+                coords,
+                old,
+                new,
+                ..
+            }] => {
+                assert_eq!(*action, ChangeAction::Update);
+                assert_eq!(*versionpin_id, maya.id);
+                assert_eq!(coords.level.to_string(), "facility");
+                assert_eq!(old.as_ref().unwrap().version(), "2018.sp3");
+                assert_eq!(new.as_ref().unwrap().version(), "2019");
+            }
+            changes => panic!("unexpected changes {:?}", changes),
+        }
+
+        let tx = store.transaction();
+        let plan = MemoryStore::add_withs(tx)
+            .preview(maya.id, vec!["houdini".to_string(), "maya".to_string()])
+            .unwrap();
+        assert_eq!(
+            plan.to_string(),
+            format!(
+                "WITHS versionpin {} [houdini] -> [houdini, maya]\n",
+                maya.id
+            )
+        );
+
+        let mut db = PackratDb::new(&mut store);
+        assert_eq!(db.find_all_levels().query().unwrap().len(), 2);
+        let pin = db.find_versionpin("maya").query().unwrap();
+        assert_eq!(pin.distribution.version(), "2018.sp3");
+        assert_eq!(pin.withs, Some(vec!["houdini".to_string()]));
+        assert_eq!(db.find_all_revisions().query().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn pages_through_versionpins_revisions_and_changes() {
+        let mut store = store();
+        let mut db = PackratDb::new(&mut store);
+        let mut pins = db.find_all_versionpins();
+        pins.level("facility")
+            .search_mode(LtreeSearchMode::Descendant)
+            .page_size(2);
+        let first = pins.query().unwrap();
+        assert_eq!(first.len(), 2);
+        let rest = pins.after(first[1].versionpin_id).query().unwrap();
+        assert_eq!(rest.len(), 1);
+        assert!(rest[0].versionpin_id > first[1].versionpin_id);
+
+        let revisions = db.find_all_revisions().after(1).query().unwrap();
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].comment, "pinned");
+
+        let transaction_id = revisions[0].transaction_id;
+        let changes = db
+            .find_all_changes()
+            .transaction_id(transaction_id)
+            .page_size(2)
+            .query()
+            .unwrap();
+        assert_eq!(changes.len(), 2);
+        let rest = db
+            .find_all_changes()
+            .transaction_id(transaction_id)
+            .after(changes[1].event_id)
+            .query()
+            .unwrap();
+        // the three versionpins inserted, and the withs of maya
+        assert_eq!(rest.len(), 2);
+        assert!(rest[0].event_id > changes[1].event_id);
+    }
+
+    #[test]
+    fn records_changes_and_queries_as_of() {
+        let mut store = store();
+        let maya = store.tables().versionpins[0].clone();
+        let tx = store.transaction();
+        let transaction_id = tx.transaction_id();
+        MemoryStore::update_versionpins(tx)
+            .change(VersionPinChange::new(maya.id, Some(2), None))
+            .update()
+            .unwrap()
+            .commit("jgerber", "updated maya")
+            .unwrap();
+        let mut db = PackratDb::new(&mut store);
+        let changes = db
+            .find_all_changes()
+            .transaction_id(transaction_id)
+            .query()
+            .unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].action, ChangeAction::Update);
+        assert_eq!(changes[0].change.distribution().unwrap().version(), "2019");
+        let pin = db.find_versionpin("maya").query().unwrap();
+        assert_eq!(pin.distribution.version(), "2019");
+        let pin = db.find_versionpin("maya").as_of(2).query().unwrap();
+        assert_eq!(pin.distribution.version(), "2018.sp3");
+    }
+
+    #[test]
+    fn records_changes_to_withs() {
+        let mut store = store();
+        let maya = store.tables().versionpins[0].clone();
+        let withs = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        for (comment, new) in &[
+            ("added maya", vec!["houdini", "maya"]),
+            ("reordered", vec!["maya", "houdini"]),
+            ("unchanged", vec!["maya", "houdini"]),
+        ] {
+            let tx = store.transaction();
+            MemoryStore::add_withs(tx)
+                .create(maya.id, withs(new))
+                .unwrap()
+                .commit("jgerber", comment)
+                .unwrap();
+        }
+        let mut db = PackratDb::new(&mut store);
+        let changes = db
+            .find_all_changes()
+            .package("maya")
+            .level("facility")
+            .query()
+            .unwrap()
+            .into_iter()
+            .filter_map(|change| match change.change {
+                ChangeType::Withs { old, new } => {
+                    assert_eq!(change.action, ChangeAction::Update);
+                    assert_eq!(change.id, maya.id as LongIdType);
+                    Some((change.comment.unwrap(), old, new))
+                }
+                ChangeType::Distribution { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            vec![
+                ("pinned".to_string(), withs(&[]), withs(&["houdini"])),
+                (
+                    "added maya".to_string(),
+                    withs(&["houdini"]),
+                    withs(&["houdini", "maya"])
+                ),
+                (
+                    "reordered".to_string(),
+                    withs(&["houdini", "maya"]),
+                    withs(&["maya", "houdini"])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_changes_by_coords_author_and_date() {
+        let mut store = store();
+        let maya = store.tables().versionpins[0].clone();
+        let tx = store.transaction();
+        MemoryStore::update_versionpins(tx)
+            .change(VersionPinChange::new(maya.id, Some(2), None))
+            .update()
+            .unwrap()
+            .commit("fbloggs", "updated maya")
+            .unwrap();
+        let mut db = PackratDb::new(&mut store);
+        let changes = db
+            .find_all_changes()
+            .package("maya")
+            .level("dev01")
+            .search_mode(LtreeSearchMode::Descendant)
+            .query()
+            .unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].change.distribution().unwrap().version(), "2019");
+        assert_eq!(changes[0].author.as_deref(), Some("jgerber"));
+        assert_eq!(changes[0].comment.as_deref(), Some("pinned"));
+        let changes = db
+            .find_all_changes()
+            .package("maya")
+            .level("dev01")
+            .search_mode(LtreeSearchMode::Ancestor)
+            .query()
+            .unwrap();
+        assert_eq!(changes.len(), 4);
+
+        let changes = db.find_all_changes().author("fbloggs").query().unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].action, ChangeAction::Update);
+        let updated = changes[0].datetime.unwrap();
+        let changes = db
+            .find_all_changes()
+            .action(ChangeAction::Insert)
+            .until(updated)
+            .query()
+            .unwrap();
+        assert_eq!(changes.len(), 3);
+        let changes = db.find_all_changes().since(updated).query().unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].comment.as_deref(), Some("updated maya"));
+
+        let changes = db
+            .find_all_changes()
+            .order_by(vec![OrderChangeBy::Author])
+            .order_direction(OrderDirection::Desc)
+            .limit(2)
+            .query()
+            .unwrap();
+        let event_ids = changes
+            .iter()
+            .map(|change| change.event_id)
+            .collect::<Vec<_>>();
+        assert_eq!(event_ids, vec![4, 3]);
+    }
+
+    #[test]
+    fn refuses_to_delete_referenced_entities() {
+        let mut store = store();
+        let tx = store.transaction();
+        let result = MemoryStore::delete_distributions(tx)
+            .distribution("maya-2018.sp3")
+            .delete();
+        assert!(matches!(
+            result.err(),
+            Some(DeleteDistributionsError::DistributionHasDependentsError { versionpins: 1, .. })
+        ));
+        let tx = store.transaction();
+        let result = MemoryStore::delete_packages(tx).package("houdini").delete();
+        assert!(matches!(
+            result.err(),
+            Some(DeletePackagesError::PackageHasDependentsError {
+                distributions: 1,
+                versionpins: 1,
+                withs: 1,
+                ..
+            })
+        ));
+        let tx = store.transaction();
+        let result = MemoryStore::delete_levels(tx).level("dev01").delete();
+        assert!(matches!(
+            result.err(),
+            Some(DeleteLevelsError::LevelHasDependentsError { .. })
+        ));
+        let tx = store.transaction();
+        let result = MemoryStore::delete_levels(tx).level("facility").delete();
+        assert!(matches!(
+            result.err(),
+            Some(DeleteLevelsError::FacilityLevelError)
+        ));
+        let tx = store.transaction();
+        let result = MemoryStore::delete_roles(tx).role("model").delete();
+        assert!(matches!(
+            result.err(),
+            Some(DeleteRolesError::RoleHasDependentsError { .. })
+        ));
+        let tx = store.transaction();
+        let result = MemoryStore::delete_roles(tx).role("any").delete();
+        assert!(matches!(result.err(), Some(DeleteRolesError::AnyRoleError)));
+
+        assert_eq!(store.tables.versionpins.len(), 3);
+        assert_eq!(store.tables.withpackages.len(), 1);
+    }
+
+    #[test]
+    fn cascades_deletes_to_versionpins_and_withs() {
+        let mut store = store();
+        let tx = store.transaction();
+        let mut packages = MemoryStore::delete_packages(tx)
+            .package("houdini")
+            .cascade(true)
+            .delete()
+            .unwrap();
+        assert_eq!(
+            packages.cascaded(),
+            Cascaded {
+                versionpins: 1,
+                withs: 1
+            }
+        );
+        assert_eq!(packages.commit("jgerber", "removed houdini").unwrap(), 1);
+        assert!(!store.tables.packages.contains("houdini"));
+        assert_eq!(store.tables.distributions.len(), 2);
+        assert_eq!(store.tables.versionpins.len(), 2);
+        assert!(store.tables.withpackages.is_empty());
+
+        let mut store = self::store();
+        let tx = store.transaction();
+        let mut levels = MemoryStore::delete_levels(tx)
+            .level("facility.dev01")
+            .cascade(true)
+            .delete()
+            .unwrap();
+        assert_eq!(
+            levels.cascaded(),
+            Cascaded {
+                versionpins: 1,
+                withs: 0
+            }
+        );
+        levels.commit("jgerber", "removed dev01").unwrap();
+        assert!(!store.tables.levels.contains("facility.dev01"));
+        assert_eq!(store.tables.versionpins.len(), 2);
+
+        let mut store = self::store();
+        let tx = store.transaction();
+        let mut distributions = MemoryStore::delete_distributions(tx)
+            .distribution("maya-2018.sp3")
+            .cascade(true)
+            .delete()
+            .unwrap();
+        assert_eq!(
+            distributions.cascaded(),
+            Cascaded {
+                versionpins: 1,
+                withs: 1
+            }
+        );
+        distributions.commit("jgerber", "removed maya").unwrap();
+        assert_eq!(store.tables.versionpins.len(), 2);
+        assert!(store.tables.withpackages.is_empty());
+        let deleted = store
+            .tables
+            .audit
+            .iter()
+            .filter(|entry| match entry {
+                AuditEntry::VersionPin(record) => record.action == ChangeAction::Delete,
+                AuditEntry::WithPackage(record) => record.action == ChangeAction::Delete,
+            })
+            .count();
+        // the versionpin and its with
+        assert_eq!(deleted, 2);
+    }
+
+    #[test]
+    fn renumbers_remaining_withs_after_delete() {
+        let mut store = store();
+        let mut tx = store.transaction();
+        tx.add_packages(&["nuke".to_string(), "katana".to_string()]);
+        let maya = tx.tables.versionpins[0].id;
+        tx.set_withs(
+            maya,
+            &[
+                "houdini".to_string(),
+                "nuke".to_string(),
+                "katana".to_string(),
+            ],
+        )
+        .unwrap();
+        tx.commit("jgerber", "withs");
+
+        let tx = store.transaction();
+        let mut withs = MemoryStore::delete_withs(tx)
+            .delete(maya, vec!["nuke".to_string()])
+            .unwrap();
+        assert_eq!(withs.get_result_cnt(), 1);
+        withs.commit("jgerber", "removed nuke").unwrap();
+        let mut remaining = store
+            .tables
+            .withpackages
+            .iter()
+            .map(|with| (with.pinorder, with.package.as_str()))
+            .collect::<Vec<_>>();
+        remaining.sort();
+        assert_eq!(remaining, vec![(0, "houdini"), (1, "katana")]);
+
+        let tx = store.transaction();
+        assert!(matches!(
+            MemoryStore::delete_withs(tx).delete(maya, Vec::new()).err(),
+            Some(DeleteWithsError::NoWithNamesError)
+        ));
+    }
+}
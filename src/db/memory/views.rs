@@ -0,0 +1,547 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! Counterparts of the views and set returning functions which the find
+//! builders query (see `migrations/0003_views_and_functions.sql`), computed
+//! from the tables of a MemoryStore.
+use super::functions::{
+    any_name_to_path, level_name_to_path, ltree_matches, nlevel, path_name, role_name_to_path,
+    role_path_name, version_ordering,
+};
+use super::{AuditEntry, DistributionRecord, PkgCoordRecord, Tables};
+use crate::db::find_all::changes::ChangeAction;
+use crate::db::search_attribute::LtreeSearchMode;
+use crate::types::{IdType, LongIdType};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+/// A row of `level_view`
+#[derive(Debug, Clone)]
+pub(crate) struct LevelView {
+    pub(crate) name: String,
+    pub(crate) show: String,
+    pub(crate) path: String,
+}
+
+/// A row of `role_view`
+#[derive(Debug, Clone)]
+pub(crate) struct RoleView {
+    pub(crate) name: String,
+    pub(crate) category: &'static str,
+}
+
+/// A row of `platform_view` or `site_view`
+#[derive(Debug, Clone)]
+pub(crate) struct NameView {
+    pub(crate) name: String,
+}
+
+/// A row of `distribution_view`
+#[derive(Debug, Clone)]
+pub(crate) struct DistributionView {
+    pub(crate) distribution_id: IdType,
+    pub(crate) package: String,
+    pub(crate) version_name: String,
+    pub(crate) name: String,
+    pub(crate) version: Vec<u64>,
+}
+
+/// A row of `pkgcoord_view`
+#[derive(Debug, Clone)]
+pub(crate) struct PkgCoordView {
+    pub(crate) pkgcoord_id: IdType,
+    pub(crate) package: String,
+    pub(crate) level_name: String,
+    pub(crate) role_name: String,
+    pub(crate) platform_name: String,
+    pub(crate) site_name: String,
+    pub(crate) level: String,
+    pub(crate) role: String,
+    pub(crate) platform: String,
+    pub(crate) site: String,
+}
+
+/// A row of `versionpin_view`
+#[derive(Debug, Clone)]
+pub(crate) struct VersionPinView {
+    pub(crate) versionpin_id: IdType,
+    pub(crate) distribution_id: IdType,
+    pub(crate) pkgcoord_id: IdType,
+    pub(crate) distribution: String,
+    pub(crate) package: String,
+    pub(crate) version_name: String,
+    pub(crate) level: String,
+    pub(crate) role: String,
+    pub(crate) platform: String,
+    pub(crate) site: String,
+    pub(crate) level_path: String,
+    pub(crate) role_path: String,
+    pub(crate) platform_path: String,
+    pub(crate) site_path: String,
+    pub(crate) withs: Vec<String>,
+}
+
+/// A row returned by `find_distribution_withs`
+#[derive(Debug, Clone)]
+pub(crate) struct WithView {
+    pub(crate) pin: VersionPinView,
+    pub(crate) package: String,
+}
+
+/// A row returned by `find_vpin_audit`, or of `vpin_change_view`
+#[derive(Debug, Clone)]
+pub(crate) struct AuditView {
+    pub(crate) event_id: LongIdType,
+    pub(crate) id: IdType,
+    pub(crate) transaction_id: LongIdType,
+    pub(crate) action: ChangeAction,
+    pub(crate) level_name: String,
+    pub(crate) role_name: String,
+    pub(crate) platform_name: String,
+    pub(crate) site_name: String,
+    pub(crate) package: String,
+    pub(crate) old: String,
+    pub(crate) new: Option<String>,
+    /// The withs before a change to the withs. None for other changes
+    pub(crate) old_withs: Option<Vec<String>>,
+    /// The withs after a change to the withs
+    pub(crate) new_withs: Option<Vec<String>>,
+}
+
+/// A row of `withs_change_view`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct WithsChangeView {
+    pub(crate) event_id: LongIdType,
+    pub(crate) versionpin: IdType,
+    pub(crate) transaction_id: LongIdType,
+    pub(crate) old_withs: Vec<String>,
+    pub(crate) new_withs: Vec<String>,
+}
+
+/// The level, role, platform and site paths searched for by the
+/// versionpin functions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CoordPaths {
+    pub(crate) level: String,
+    pub(crate) role: String,
+    pub(crate) platform: String,
+    pub(crate) site: String,
+}
+
+impl CoordPaths {
+    /// Convert the optional names supplied to a find builder into paths,
+    /// applying the same defaults as the sql functions
+    pub(crate) fn from_names(
+        level: Option<&str>,
+        role: Option<&str>,
+        platform: Option<&str>,
+        site: Option<&str>,
+    ) -> Self {
+        Self {
+            level: level_name_to_path(level.unwrap_or("facility")),
+            role: role_name_to_path(role.unwrap_or("any")),
+            platform: any_name_to_path(platform.unwrap_or("any")),
+            site: any_name_to_path(site.unwrap_or("any")),
+        }
+    }
+
+    // Is the versionpin related to the paths by the search mode
+    fn matches(&self, pin: &VersionPinView, mode: &LtreeSearchMode) -> bool {
+        ltree_matches(&pin.level_path, &self.level, mode)
+            && ltree_matches(&pin.role_path, &self.role, mode)
+            && ltree_matches(&pin.platform_path, &self.platform, mode)
+            && ltree_matches(&pin.site_path, &self.site, mode)
+    }
+}
+
+// Level outranks role, which outranks platform, which outranks site
+fn specificity(pin: &VersionPinView) -> (usize, usize, usize, usize) {
+    (
+        nlevel(&pin.level_path),
+        nlevel(&pin.role_path),
+        nlevel(&pin.platform_path),
+        nlevel(&pin.site_path),
+    )
+}
+
+impl Tables {
+    /// Look up a distribution by package and version
+    pub(crate) fn distribution(&self, package: &str, version: &str) -> Option<&DistributionRecord> {
+        self.distributions
+            .iter()
+            .find(|dist| dist.package == package && dist.version == version)
+    }
+
+    /// Look up a pkgcoord by id
+    pub(crate) fn pkgcoord(&self, id: IdType) -> Option<&PkgCoordRecord> {
+        self.pkgcoords.iter().find(|coord| coord.id == id)
+    }
+
+    /// The rows of `level_view`
+    pub(crate) fn level_view(&self) -> Vec<LevelView> {
+        self.levels
+            .iter()
+            .map(|path| LevelView {
+                name: path_name(path).to_string(),
+                show: path.split('.').nth(1).unwrap_or(path).to_string(),
+                path: path.clone(),
+            })
+            .collect()
+    }
+
+    /// The rows of `role_view`
+    pub(crate) fn role_view(&self) -> Vec<RoleView> {
+        self.roles
+            .iter()
+            .map(|path| RoleView {
+                name: role_path_name(path),
+                category: match nlevel(path) {
+                    1 => "any",
+                    2 => "role",
+                    _ => "subrole",
+                },
+            })
+            .collect()
+    }
+
+    /// The rows of `platform_view`
+    pub(crate) fn platform_view(&self) -> Vec<NameView> {
+        name_view(self.platforms.iter())
+    }
+
+    /// The rows of `site_view`
+    pub(crate) fn site_view(&self) -> Vec<NameView> {
+        name_view(self.sites.iter())
+    }
+
+    /// The rows of `distribution_view`
+    pub(crate) fn distribution_view(&self) -> Vec<DistributionView> {
+        self.distributions
+            .iter()
+            .map(|dist| DistributionView {
+                distribution_id: dist.id,
+                package: dist.package.clone(),
+                version_name: dist.version.clone(),
+                name: format!("{}-{}", dist.package, dist.version),
+                version: version_ordering(&dist.version),
+            })
+            .collect()
+    }
+
+    /// The rows of `pkgcoord_view`
+    pub(crate) fn pkgcoord_view(&self) -> Vec<PkgCoordView> {
+        self.pkgcoords
+            .iter()
+            .map(|coord| PkgCoordView {
+                pkgcoord_id: coord.id,
+                package: coord.package.clone(),
+                level_name: path_name(&coord.level).to_string(),
+                role_name: role_path_name(&coord.role),
+                platform_name: path_name(&coord.platform).to_string(),
+                site_name: path_name(&coord.site).to_string(),
+                level: coord.level.clone(),
+                role: coord.role.clone(),
+                platform: coord.platform.clone(),
+                site: coord.site.clone(),
+            })
+            .collect()
+    }
+
+    /// The rows of `versionpin_view`
+    pub(crate) fn versionpin_view(&self) -> Vec<VersionPinView> {
+        self.versionpins
+            .iter()
+            .filter_map(|pin| {
+                let dist = self
+                    .distributions
+                    .iter()
+                    .find(|dist| dist.id == pin.distribution)?;
+                let coord = self.pkgcoord(pin.coord)?;
+                let mut withs = self
+                    .withpackages
+                    .iter()
+                    .filter(|with| with.versionpin == pin.id)
+                    .collect::<Vec<_>>();
+                withs.sort_by_key(|with| with.pinorder);
+                Some(VersionPinView {
+                    versionpin_id: pin.id,
+                    distribution_id: dist.id,
+                    pkgcoord_id: coord.id,
+                    distribution: format!("{}-{}", dist.package, dist.version),
+                    package: coord.package.clone(),
+                    version_name: dist.version.clone(),
+                    level: path_name(&coord.level).to_string(),
+                    role: role_path_name(&coord.role),
+                    platform: path_name(&coord.platform).to_string(),
+                    site: path_name(&coord.site).to_string(),
+                    level_path: coord.level.clone(),
+                    role_path: coord.role.clone(),
+                    platform_path: coord.platform.clone(),
+                    site_path: coord.site.clone(),
+                    withs: withs.into_iter().map(|with| with.package.clone()).collect(),
+                })
+            })
+            .collect()
+    }
+
+    /// The counterpart of `versionpin_candidates`. The versionpins of a
+    /// package whose paths are equal to, or ancestors of, the supplied
+    /// paths in every dimension.
+    pub(crate) fn versionpin_candidates(
+        &self,
+        package: &str,
+        paths: &CoordPaths,
+    ) -> Vec<VersionPinView> {
+        self.versionpin_view()
+            .into_iter()
+            .filter(|pin| pin.package == package)
+            .filter(|pin| paths.matches(pin, &LtreeSearchMode::Ancestor))
+            .collect()
+    }
+
+    /// The counterpart of `search_distributions`. The candidates from least
+    /// to most specific.
+    pub(crate) fn search_distributions(
+        &self,
+        package: &str,
+        paths: &CoordPaths,
+    ) -> Vec<VersionPinView> {
+        let mut candidates = self.versionpin_candidates(package, paths);
+        candidates.sort_by_key(specificity);
+        candidates
+    }
+
+    /// The counterpart of `find_distribution_and_withs`. The most specific
+    /// candidate, if there is one.
+    pub(crate) fn find_distribution_and_withs(
+        &self,
+        package: &str,
+        paths: &CoordPaths,
+    ) -> Option<VersionPinView> {
+        let mut candidates = self.versionpin_candidates(package, paths);
+        candidates.sort_by_key(|pin| Reverse(specificity(pin)));
+        candidates.into_iter().next()
+    }
+
+    /// The counterpart of `find_distribution_withs`. The most specific
+    /// versionpin of each of the withs of a package, resolved at the same
+    /// paths, in pin order. Withs without a versionpin are left out.
+    pub(crate) fn find_distribution_withs(
+        &self,
+        package: &str,
+        paths: &CoordPaths,
+    ) -> Vec<WithView> {
+        let withs = self
+            .find_distribution_and_withs(package, paths)
+            .map(|pin| pin.withs)
+            .unwrap_or_default();
+        withs
+            .into_iter()
+            .filter_map(|with| {
+                self.find_distribution_and_withs(&with, paths)
+                    .map(|pin| WithView { pin, package: with })
+            })
+            .collect()
+    }
+
+    /// The counterpart of `findall_versionpins`. Every versionpin related to
+    /// the supplied paths by the search mode, optionally restricted to a
+    /// package and version.
+    pub(crate) fn findall_versionpins(
+        &self,
+        paths: &CoordPaths,
+        mode: &LtreeSearchMode,
+        package: Option<&str>,
+        version: Option<&str>,
+    ) -> Vec<VersionPinView> {
+        self.versionpin_view()
+            .into_iter()
+            .filter(|pin| match package {
+                Some(package) => pin.package == package,
+                None => true,
+            })
+            .filter(|pin| match version {
+                Some(version) => pin.version_name == version,
+                None => true,
+            })
+            .filter(|pin| paths.matches(pin, mode))
+            .collect()
+    }
+
+    /// The counterpart of `find_vpin_audit`. The changes made to versionpins
+    /// by a transaction, in the order they were made.
+    pub(crate) fn find_vpin_audit(&self, transaction_id: LongIdType) -> Vec<AuditView> {
+        self.vpin_audit_view()
+            .into_iter()
+            .filter(|change| change.transaction_id == transaction_id)
+            .collect()
+    }
+
+    /// The changes made to versionpins by every transaction, in the order
+    /// they were made.
+    pub(crate) fn vpin_audit_view(&self) -> Vec<AuditView> {
+        let coords = self.pkgcoord_view();
+        let dists = self.distribution_view();
+        let dist_name = |id: Option<IdType>| {
+            id.and_then(|id| dists.iter().find(|dist| dist.distribution_id == id))
+                .map(|dist| dist.name.clone())
+        };
+        // event ids are the position of the record in the audit log
+        self.audit
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, entry)| match entry {
+                AuditEntry::VersionPin(record) => Some((idx, record)),
+                AuditEntry::WithPackage(_) => None,
+            })
+            .filter_map(|(idx, record)| {
+                let coord_id = record.changed_coord.unwrap_or(record.coord);
+                let coord = coords.iter().find(|coord| coord.pkgcoord_id == coord_id)?;
+                let row_dist = dist_name(Some(record.distribution));
+                let (old, new) = match record.action {
+                    ChangeAction::Insert => (String::new(), row_dist),
+                    ChangeAction::Update => (
+                        row_dist.clone().unwrap_or_default(),
+                        dist_name(record.changed_distribution).or(row_dist),
+                    ),
+                    _ => (row_dist.unwrap_or_default(), None),
+                };
+                Some(AuditView {
+                    event_id: idx as LongIdType + 1,
+                    id: record.versionpin,
+                    transaction_id: record.transaction_id,
+                    action: record.action.clone(),
+                    level_name: coord.level_name.clone(),
+                    role_name: coord.role_name.clone(),
+                    platform_name: coord.platform_name.clone(),
+                    site_name: coord.site_name.clone(),
+                    package: coord.package.clone(),
+                    old,
+                    new,
+                    old_withs: None,
+                    new_withs: None,
+                })
+            })
+            .collect()
+    }
+
+    /// The withs of each versionpin before and after each transaction which
+    /// changed them. The rows deleted and inserted by a transaction make up
+    /// the withs before and after it, ignoring those both inserted and
+    /// deleted by it.
+    pub(crate) fn withs_change_view(&self) -> Vec<WithsChangeView> {
+        let mut events = BTreeMap::new();
+        for (idx, entry) in self.audit.iter().enumerate() {
+            if let AuditEntry::WithPackage(record) = entry {
+                events
+                    .entry((record.with.versionpin, record.transaction_id))
+                    .or_insert_with(Vec::new)
+                    .push((idx as LongIdType + 1, record));
+            }
+        }
+        let mut changes = Vec::new();
+        for ((versionpin, transaction_id), events) in events {
+            let net = events
+                .iter()
+                .filter(|(_, record)| {
+                    !events.iter().any(|(_, other)| {
+                        other.with.id == record.with.id && other.action != record.action
+                    })
+                })
+                .collect::<Vec<_>>();
+            let withs = |action: ChangeAction| {
+                let mut withs = net
+                    .iter()
+                    .filter(|(_, record)| record.action == action)
+                    .map(|(_, record)| &record.with)
+                    .collect::<Vec<_>>();
+                withs.sort_by_key(|with| with.pinorder);
+                withs
+                    .into_iter()
+                    .map(|with| with.package.clone())
+                    .collect::<Vec<_>>()
+            };
+            let old_withs = withs(ChangeAction::Delete);
+            let new_withs = withs(ChangeAction::Insert);
+            if old_withs == new_withs {
+                continue;
+            }
+            changes.push(WithsChangeView {
+                event_id: net.iter().map(|(event_id, _)| *event_id).max().unwrap_or(0),
+                versionpin,
+                transaction_id,
+                old_withs,
+                new_withs,
+            });
+        }
+        changes.sort_by_key(|change| change.event_id);
+        changes
+    }
+
+    /// The counterpart of `vpin_change_view`. The changes made to the
+    /// distributions and withs of versionpins by every transaction, in the
+    /// order they were made. The coordinates of a versionpin which has
+    /// since been deleted are taken from its last change.
+    pub(crate) fn vpin_change_view(&self) -> Vec<AuditView> {
+        let coords = self.pkgcoord_view();
+        let coord_id = |versionpin: IdType| {
+            self.versionpins
+                .iter()
+                .find(|pin| pin.id == versionpin)
+                .map(|pin| pin.coord)
+                .or_else(|| {
+                    self.audit.iter().rev().find_map(|entry| match entry {
+                        AuditEntry::VersionPin(record) if record.versionpin == versionpin => {
+                            Some(record.changed_coord.unwrap_or(record.coord))
+                        }
+                        _ => None,
+                    })
+                })
+        };
+        let mut changes = self.vpin_audit_view();
+        for change in self.withs_change_view() {
+            let coord = coord_id(change.versionpin)
+                .and_then(|id| coords.iter().find(|coord| coord.pkgcoord_id == id));
+            let coord = match coord {
+                Some(coord) => coord,
+                None => continue,
+            };
+            changes.push(AuditView {
+                event_id: change.event_id,
+                id: change.versionpin,
+                transaction_id: change.transaction_id,
+                action: ChangeAction::Update,
+                level_name: coord.level_name.clone(),
+                role_name: coord.role_name.clone(),
+                platform_name: coord.platform_name.clone(),
+                site_name: coord.site_name.clone(),
+                package: coord.package.clone(),
+                old: String::new(),
+                new: None,
+                old_withs: Some(change.old_withs),
+                new_withs: Some(change.new_withs),
+            });
+        }
+        changes.sort_by_key(|change| change.event_id);
+        changes
+    }
+}
+
+// The rows of a view over a table of paths rooted at `any`
+fn name_view<'a>(paths: impl Iterator<Item = &'a String>) -> Vec<NameView> {
+    paths
+        .map(|path| NameView {
+            name: path_name(path).to_string(),
+        })
+        .collect()
+}
+
+/// Order ltree paths as postgres does, label by label
+pub(crate) fn ltree_key(path: &str) -> Vec<&str> {
+    path.split('.').collect()
+}
@@ -0,0 +1,488 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! The versioned sql migrations which bring a database up to the schema
+//! expected by this version of packybara, and the schema version check
+//! performed on connect.
+//!
+//! The migrations live in `src/db/migrations` and are embedded in the crate.
+//! Each applied migration is recorded in the `schema_version` table, so
+//! `migrate` only applies those which are pending, within a single
+//! transaction. Migrations are never edited once released; changes to the
+//! schema are made by appending a new migration.
+//!
+//! A database created before the migrations existed has no `schema_version`
+//! table, and its tables can't be created again by `migrate`. Such a database
+//! is adopted with `baseline`, which records the migrations up to the version
+//! its schema matches as applied without running them, after which `migrate`
+//! applies the rest.
+use log;
+use postgres::{Client, Transaction};
+use snafu::{ResultExt, Snafu};
+use std::fmt;
+
+/// A single, versioned, schema migration
+#[derive(Debug, PartialEq, Eq)]
+pub struct Migration {
+    /// The version of the schema once the migration has been applied
+    pub version: i32,
+    /// A short description of the migration
+    pub name: &'static str,
+    /// The sql making up the migration
+    pub sql: &'static str,
+}
+
+impl fmt::Display for Migration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}_{}", self.version, self.name)
+    }
+}
+
+/// The migrations, in the order in which they are applied
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "tables",
+        sql: include_str!("migrations/0001_tables.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "audit",
+        sql: include_str!("migrations/0002_audit.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "views_and_functions",
+        sql: include_str!("migrations/0003_views_and_functions.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "audit_event_id",
+        sql: include_str!("migrations/0004_audit_event_id.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "vpin_change_view",
+        sql: include_str!("migrations/0005_vpin_change_view.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "withs_change_view",
+        sql: include_str!("migrations/0006_withs_change_view.sql"),
+    },
+];
+
+/// The version of the schema expected by this version of packybara
+pub const SCHEMA_VERSION: i32 = 6;
+
+/// Error type returned when migrating the database or checking its schema version
+#[derive(Debug, Snafu)]
+pub enum MigrateError {
+    /// When constructing a query, postgres has thrown an error
+    #[snafu(display("Postgres Error: {} {:#?}", msg, source))]
+    TokioPostgresError {
+        msg: &'static str,
+        source: tokio_postgres::error::Error,
+    },
+    /// Applying a migration failed. None of the pending migrations have been applied
+    #[snafu(display("Unable to apply migration {}: {}", migration, source))]
+    ApplyMigrationError {
+        migration: String,
+        source: tokio_postgres::error::Error,
+    },
+    /// The database has not been migrated at all
+    #[snafu(display(
+        "The database has no schema version. Run migrate to create the schema, or baseline to adopt an existing one"
+    ))]
+    NoSchemaError,
+    /// The database has been migrated, but not to the latest version
+    #[snafu(display(
+        "The database schema is at version {}. Expected {}. Run migrate to update it",
+        version,
+        SCHEMA_VERSION
+    ))]
+    OutdatedSchemaError { version: i32 },
+    /// The database has been migrated by a later version of packybara
+    #[snafu(display(
+        "The database schema is at version {}, which is newer than the expected {}. Upgrade packybara",
+        version,
+        SCHEMA_VERSION
+    ))]
+    UnsupportedSchemaError { version: i32 },
+    /// The database has already been migrated, and may not be baselined
+    #[snafu(display(
+        "The database schema is already at version {}. Run migrate to update it",
+        version
+    ))]
+    AlreadyMigratedError { version: i32 },
+    /// The version to baseline at is not that of a migration
+    #[snafu(display(
+        "Unable to baseline at version {}. Expected a version between 1 and {}",
+        version,
+        SCHEMA_VERSION
+    ))]
+    InvalidBaselineError { version: i32 },
+}
+
+pub type MigrateResult<T, E = MigrateError> = std::result::Result<T, E>;
+
+// creates the table recording the applied migrations, and locks it for the
+// remainder of the transaction
+const CREATE_SCHEMA_VERSION: &str = "CREATE TABLE IF NOT EXISTS schema_version (
+        version INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        applied TIMESTAMPTZ NOT NULL DEFAULT now()
+    );
+    LOCK TABLE schema_version IN EXCLUSIVE MODE";
+
+/// Retrieve the migrations which have yet to be applied to a database whose
+/// schema is at the supplied version.
+///
+/// # Arguments
+/// * `version` - The current version of the schema, or None if the database
+///   has not been migrated
+///
+/// # Returns
+/// * slice of Migrations, in the order in which they should be applied
+pub fn pending(version: Option<i32>) -> &'static [Migration] {
+    let version = version.unwrap_or(0);
+    let idx = MIGRATIONS
+        .iter()
+        .position(|migration| migration.version > version)
+        .unwrap_or_else(|| MIGRATIONS.len());
+    &MIGRATIONS[idx..]
+}
+
+/// Retrieve the migrations which have been applied to a database whose
+/// schema is at the supplied version.
+///
+/// # Arguments
+/// * `version` - The version of the schema
+///
+/// # Returns
+/// * slice of Migrations, in the order in which they were applied
+pub fn applied(version: i32) -> &'static [Migration] {
+    &MIGRATIONS[..MIGRATIONS.len() - pending(Some(version)).len()]
+}
+
+/// Verify that a schema at the supplied version is the one expected by this
+/// version of packybara.
+///
+/// # Arguments
+/// * `version` - The current version of the schema, or None if the database
+///   has not been migrated
+///
+/// # Returns
+/// * Result
+///   * `Ok`  - The schema version
+///   * `Err` - MigrateError
+pub fn verify_version(version: Option<i32>) -> MigrateResult<i32> {
+    match version {
+        None => NoSchemaError.fail(),
+        Some(version) if version < SCHEMA_VERSION => OutdatedSchemaError { version }.fail(),
+        Some(version) if version > SCHEMA_VERSION => UnsupportedSchemaError { version }.fail(),
+        Some(version) => Ok(version),
+    }
+}
+
+/// Retrieve the version of the database schema
+///
+/// # Arguments
+/// * `client` - The client connected to the database
+///
+/// # Returns
+/// * Result
+///   * `Ok`  - The schema version, or None if the database has not been migrated
+///   * `Err` - MigrateError
+pub fn schema_version(client: &mut Client) -> MigrateResult<Option<i32>> {
+    let row = client
+        .query_one("SELECT to_regclass('schema_version') IS NOT NULL", &[])
+        .context(TokioPostgresError {
+            msg: "unable to look up schema_version table",
+        })?;
+    let has_table: bool = row.get(0);
+    if !has_table {
+        return Ok(None);
+    }
+    let row = client
+        .query_one("SELECT max(version) FROM schema_version", &[])
+        .context(TokioPostgresError {
+            msg: "unable to select from schema_version",
+        })?;
+    Ok(row.get(0))
+}
+
+/// Verify that the database schema is at the version expected by this
+/// version of packybara.
+///
+/// # Arguments
+/// * `client` - The client connected to the database
+///
+/// # Returns
+/// * Result
+///   * `Ok`  - The schema version
+///   * `Err` - MigrateError
+pub fn check_schema_version(client: &mut Client) -> MigrateResult<i32> {
+    verify_version(schema_version(client)?)
+}
+
+/// Check the version of the database schema as `check_schema_version` does,
+/// logging a mismatch as a warning rather than failing.
+///
+/// # Arguments
+/// * `client` - The client connected to the database
+///
+/// # Returns
+/// * Result
+///   * `Ok`  - The schema version, or None if the database has not been migrated
+///   * `Err` - MigrateError
+pub fn warn_schema_version(client: &mut Client) -> MigrateResult<Option<i32>> {
+    let version = schema_version(client)?;
+    if let Err(err) = verify_version(version) {
+        log::warn!("{}", err);
+    }
+    Ok(version)
+}
+
+/// Apply the pending migrations within a single transaction. Should any
+/// of them fail, none are applied.
+///
+/// # Arguments
+/// * `client` - The client connected to the database
+///
+/// # Returns
+/// * Result
+///   * `Ok`  - The migrations applied, which may be empty
+///   * `Err` - MigrateError
+pub fn migrate(client: &mut Client) -> MigrateResult<Vec<&'static Migration>> {
+    let mut tx = client.transaction().context(TokioPostgresError {
+        msg: "unable to start transaction",
+    })?;
+    let version = lock_schema_version(&mut tx)?;
+    if let Some(version) = version {
+        if version > SCHEMA_VERSION {
+            return UnsupportedSchemaError { version }.fail();
+        }
+    }
+    let migrations = pending(version);
+    for migration in migrations {
+        log::info!("applying migration {}", migration);
+        tx.batch_execute(migration.sql)
+            .context(ApplyMigrationError {
+                migration: migration.to_string(),
+            })?;
+        record(&mut tx, migration)?;
+    }
+    tx.commit().context(TokioPostgresError {
+        msg: "unable to commit migrations",
+    })?;
+    Ok(migrations.iter().collect())
+}
+
+/// Record the migrations up to, and including, `version` as applied without
+/// running them. This adopts a database whose schema predates the migrations,
+/// and matches the one created by the migrations up to `version`, so that
+/// `migrate` applies only the later migrations.
+///
+/// # Arguments
+/// * `client` - The client connected to the database
+/// * `version` - The version of the schema the database already has
+///
+/// # Returns
+/// * Result
+///   * `Ok`  - The migrations recorded as applied
+///   * `Err` - MigrateError
+pub fn baseline(client: &mut Client, version: i32) -> MigrateResult<Vec<&'static Migration>> {
+    if version < 1 || version > SCHEMA_VERSION {
+        return InvalidBaselineError { version }.fail();
+    }
+    let mut tx = client.transaction().context(TokioPostgresError {
+        msg: "unable to start transaction",
+    })?;
+    if let Some(version) = lock_schema_version(&mut tx)? {
+        return AlreadyMigratedError { version }.fail();
+    }
+    let migrations = applied(version);
+    for migration in migrations {
+        log::info!("recording migration {} as applied", migration);
+        record(&mut tx, migration)?;
+    }
+    tx.commit().context(TokioPostgresError {
+        msg: "unable to commit baseline",
+    })?;
+    Ok(migrations.iter().collect())
+}
+
+// create and lock the schema_version table, returning the current version
+fn lock_schema_version(tx: &mut Transaction<'_>) -> MigrateResult<Option<i32>> {
+    tx.batch_execute(CREATE_SCHEMA_VERSION)
+        .context(TokioPostgresError {
+            msg: "unable to create schema_version table",
+        })?;
+    let row = tx
+        .query_one("SELECT max(version) FROM schema_version", &[])
+        .context(TokioPostgresError {
+            msg: "unable to select from schema_version",
+        })?;
+    Ok(row.get(0))
+}
+
+// record a migration as applied
+fn record(tx: &mut Transaction<'_>, migration: &Migration) -> MigrateResult<()> {
+    tx.execute(
+        "INSERT INTO schema_version (version, name) VALUES ($1, $2)",
+        &[&migration.version, &migration.name],
+    )
+    .context(TokioPostgresError {
+        msg: "unable to insert into schema_version",
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn migrations_are_contiguous_and_end_at_schema_version() {
+        for (idx, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, idx as i32 + 1);
+            assert!(!migration.sql.trim().is_empty());
+        }
+        assert_eq!(MIGRATIONS.last().unwrap().version, SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn can_find_pending_migrations() {
+        assert_eq!(pending(None), MIGRATIONS);
+        assert_eq!(pending(Some(0)), MIGRATIONS);
+        assert_eq!(pending(Some(1)), &MIGRATIONS[1..]);
+        assert!(pending(Some(SCHEMA_VERSION)).is_empty());
+        assert!(pending(Some(SCHEMA_VERSION + 1)).is_empty());
+    }
+
+    #[test]
+    fn can_find_applied_migrations() {
+        assert!(applied(0).is_empty());
+        assert_eq!(applied(1), &MIGRATIONS[..1]);
+        assert_eq!(applied(SCHEMA_VERSION), MIGRATIONS);
+    }
+
+    #[test]
+    fn can_verify_version() {
+        assert_eq!(
+            verify_version(Some(SCHEMA_VERSION)).unwrap(),
+            SCHEMA_VERSION
+        );
+        assert!(matches!(
+            verify_version(None),
+            Err(MigrateError::NoSchemaError)
+        ));
+        assert!(matches!(
+            verify_version(Some(SCHEMA_VERSION - 1)),
+            Err(MigrateError::OutdatedSchemaError { .. })
+        ));
+        assert!(matches!(
+            verify_version(Some(SCHEMA_VERSION + 1)),
+            Err(MigrateError::UnsupportedSchemaError { .. })
+        ));
+    }
+
+    #[test]
+    fn migration_display_matches_file_name() {
+        assert_eq!(MIGRATIONS[0].to_string(), "0001_tables");
+    }
+
+    // the statements of a `pg_dump --schema-only`, less its comments and
+    // settings, and the schema_version table, which only migrate creates
+    fn schema_statements(dump: &str) -> BTreeSet<String> {
+        dump.lines()
+            .filter(|line| !line.starts_with("--"))
+            .collect::<Vec<_>>()
+            .join("\n")
+            .split(";\n")
+            .map(|statement| statement.trim().trim_end_matches(';').to_string())
+            .filter(|statement| {
+                !statement.is_empty()
+                    && !statement.starts_with("SET ")
+                    && !statement.starts_with("SELECT pg_catalog.set_config")
+                    && !statement.contains("schema_version")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn schema_statements_skip_comments_settings_and_schema_version() {
+        let dump = "--\n-- PostgreSQL database dump\n--\n\
+                    SET statement_timeout = 0;\n\
+                    SELECT pg_catalog.set_config('search_path', '', false);\n\
+                    CREATE TABLE public.package (\n    name text NOT NULL\n);\n\
+                    CREATE TABLE public.schema_version (\n    version integer\n);\n";
+        let statements = schema_statements(dump);
+        assert_eq!(
+            statements.into_iter().collect::<Vec<_>>(),
+            vec!["CREATE TABLE public.package (\n    name text NOT NULL\n)".to_string()]
+        );
+    }
+
+    // Applies the migrations to an empty database, and compares the schema they
+    // create with a `pg_dump --schema-only` of the production database. Requires
+    // the name of the empty database, reached as configured by PackratConfig, in
+    // PACKRAT_MIGRATE_TEST_DBNAME, the path of the dump in PACKRAT_SCHEMA_DUMP,
+    // and pg_dump on the PATH. Run with `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn migrations_reproduce_the_production_schema() {
+        let dbname = std::env::var("PACKRAT_MIGRATE_TEST_DBNAME")
+            .expect("PACKRAT_MIGRATE_TEST_DBNAME names an empty database");
+        let dump = std::env::var("PACKRAT_SCHEMA_DUMP")
+            .expect("PACKRAT_SCHEMA_DUMP names a schema dump of the production database");
+        let expected = schema_statements(&std::fs::read_to_string(dump).unwrap());
+
+        let mut config = crate::PackratConfig::load().unwrap();
+        config.dbname = dbname;
+        let mut client = config.connect_unchecked().unwrap();
+        assert_eq!(schema_version(&mut client).unwrap(), None);
+        migrate(&mut client).unwrap();
+
+        let port = config.port.to_string();
+        let mut pg_dump = std::process::Command::new("pg_dump");
+        pg_dump.args(&[
+            "--schema-only",
+            "--no-owner",
+            "--no-privileges",
+            "--host",
+            &config.host,
+            "--port",
+            &port,
+            "--username",
+            &config.user,
+            &config.dbname,
+        ]);
+        if let Some(ref password) = config.password {
+            pg_dump.env("PGPASSWORD", password);
+        }
+        let output = pg_dump.output().unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let actual = schema_statements(&String::from_utf8(output.stdout).unwrap());
+
+        let missing = expected.difference(&actual).collect::<Vec<_>>();
+        let extra = actual.difference(&expected).collect::<Vec<_>>();
+        assert!(
+            missing.is_empty() && extra.is_empty(),
+            "missing from the migrated schema: {:#?}\nnot in the production schema: {:#?}",
+            missing,
+            extra
+        );
+    }
+}
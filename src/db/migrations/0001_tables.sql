@@ -0,0 +1,83 @@
+-- The packrat tables.
+--
+-- Levels, roles, platforms and sites are stored as ltree paths rooted at
+-- `facility` (levels) or `any` (everything else). Each root is seeded here,
+-- as every pkgcoord must reference one.
+CREATE EXTENSION IF NOT EXISTS ltree;
+
+CREATE TABLE package (
+    name TEXT PRIMARY KEY
+);
+
+CREATE TABLE distribution (
+    id SERIAL PRIMARY KEY,
+    package TEXT NOT NULL REFERENCES package (name),
+    version TEXT NOT NULL,
+    UNIQUE (package, version)
+);
+
+CREATE TABLE level (
+    path LTREE PRIMARY KEY
+);
+
+CREATE TABLE role (
+    path LTREE PRIMARY KEY
+);
+
+CREATE TABLE platform (
+    path LTREE PRIMARY KEY
+);
+
+CREATE TABLE site (
+    path LTREE PRIMARY KEY
+);
+
+CREATE INDEX level_path_gist_idx ON level USING GIST (path);
+CREATE INDEX role_path_gist_idx ON role USING GIST (path);
+CREATE INDEX platform_path_gist_idx ON platform USING GIST (path);
+CREATE INDEX site_path_gist_idx ON site USING GIST (path);
+
+INSERT INTO level (path) VALUES ('facility');
+INSERT INTO role (path) VALUES ('any');
+INSERT INTO platform (path) VALUES ('any');
+INSERT INTO site (path) VALUES ('any');
+
+-- A location in package space for a single package
+CREATE TABLE pkgcoord (
+    id SERIAL PRIMARY KEY,
+    package TEXT NOT NULL REFERENCES package (name),
+    level LTREE NOT NULL REFERENCES level (path),
+    role LTREE NOT NULL REFERENCES role (path),
+    platform LTREE NOT NULL REFERENCES platform (path),
+    site LTREE NOT NULL REFERENCES site (path),
+    UNIQUE (package, level, role, platform, site)
+);
+
+-- At most one distribution may be pinned at each pkgcoord
+CREATE TABLE versionpin (
+    id SERIAL PRIMARY KEY,
+    distribution INTEGER NOT NULL REFERENCES distribution (id),
+    coord INTEGER NOT NULL UNIQUE REFERENCES pkgcoord (id)
+);
+
+-- The ordered packages pulled in along with a versionpin
+CREATE TABLE withpackage (
+    id SERIAL PRIMARY KEY,
+    versionpin INTEGER NOT NULL REFERENCES versionpin (id),
+    package TEXT NOT NULL REFERENCES package (name),
+    pinorder INTEGER NOT NULL
+);
+
+CREATE INDEX withpackage_versionpin_idx ON withpackage (versionpin);
+
+-- One row per committed change set. The transaction_id ties a revision to
+-- the audit log entries written by the same transaction.
+CREATE TABLE revision (
+    id SERIAL PRIMARY KEY,
+    transaction_id BIGINT NOT NULL DEFAULT txid_current(),
+    author TEXT NOT NULL,
+    comment TEXT NOT NULL,
+    datetime TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX revision_transaction_id_idx ON revision (transaction_id);
@@ -0,0 +1,160 @@
+-- The audit log. Every change to an audited table is recorded in
+-- audit.logged_actions, along with the id of the transaction which made it,
+-- so that the changes belonging to a revision may be recovered later.
+--
+-- Derived from the 2ndQuadrant audit trigger, recording the full TG_OP
+-- (INSERT, UPDATE, DELETE, TRUNCATE) as the action.
+CREATE EXTENSION IF NOT EXISTS hstore;
+
+CREATE SCHEMA audit;
+
+REVOKE ALL ON SCHEMA audit FROM public;
+
+CREATE TABLE audit.logged_actions (
+    event_id BIGSERIAL PRIMARY KEY,
+    schema_name TEXT NOT NULL,
+    table_name TEXT NOT NULL,
+    relid OID NOT NULL,
+    session_user_name TEXT,
+    action_tstamp_tx TIMESTAMPTZ NOT NULL,
+    action_tstamp_stm TIMESTAMPTZ NOT NULL,
+    action_tstamp_clk TIMESTAMPTZ NOT NULL,
+    transaction_id BIGINT,
+    application_name TEXT,
+    client_addr INET,
+    client_port INTEGER,
+    client_query TEXT,
+    action TEXT NOT NULL CHECK (action IN ('INSERT', 'UPDATE', 'DELETE', 'TRUNCATE')),
+    -- the row as it was before an update or delete, or after an insert
+    row_data HSTORE,
+    -- the new values of the columns changed by an update
+    changed_fields HSTORE,
+    statement_only BOOLEAN NOT NULL
+);
+
+REVOKE ALL ON audit.logged_actions FROM public;
+
+CREATE INDEX logged_actions_relid_idx ON audit.logged_actions (relid);
+CREATE INDEX logged_actions_action_idx ON audit.logged_actions (action);
+CREATE INDEX logged_actions_transaction_id_idx ON audit.logged_actions (transaction_id);
+
+CREATE OR REPLACE FUNCTION audit.if_modified_func() RETURNS TRIGGER AS $body$
+DECLARE
+    audit_row audit.logged_actions;
+    excluded_cols TEXT[] = ARRAY[]::TEXT[];
+BEGIN
+    IF TG_WHEN <> 'AFTER' THEN
+        RAISE EXCEPTION 'audit.if_modified_func() may only run as an AFTER trigger';
+    END IF;
+
+    audit_row = ROW(
+        nextval('audit.logged_actions_event_id_seq'),
+        TG_TABLE_SCHEMA::TEXT,
+        TG_TABLE_NAME::TEXT,
+        TG_RELID,
+        session_user::TEXT,
+        current_timestamp,
+        statement_timestamp(),
+        clock_timestamp(),
+        txid_current(),
+        current_setting('application_name'),
+        inet_client_addr(),
+        inet_client_port(),
+        current_query(),
+        TG_OP,
+        NULL,
+        NULL,
+        'f'
+    );
+
+    IF NOT TG_ARGV[0]::BOOLEAN IS DISTINCT FROM 'f'::BOOLEAN THEN
+        audit_row.client_query = NULL;
+    END IF;
+
+    IF TG_ARGV[1] IS NOT NULL THEN
+        excluded_cols = TG_ARGV[1]::TEXT[];
+    END IF;
+
+    IF TG_OP = 'UPDATE' AND TG_LEVEL = 'ROW' THEN
+        audit_row.row_data = hstore(OLD.*) - excluded_cols;
+        audit_row.changed_fields = (hstore(NEW.*) - audit_row.row_data) - excluded_cols;
+        IF audit_row.changed_fields = hstore('') THEN
+            -- nothing but excluded columns changed
+            RETURN NULL;
+        END IF;
+    ELSIF TG_OP = 'DELETE' AND TG_LEVEL = 'ROW' THEN
+        audit_row.row_data = hstore(OLD.*) - excluded_cols;
+    ELSIF TG_OP = 'INSERT' AND TG_LEVEL = 'ROW' THEN
+        audit_row.row_data = hstore(NEW.*) - excluded_cols;
+    ELSIF TG_LEVEL = 'STATEMENT' AND TG_OP IN ('INSERT', 'UPDATE', 'DELETE', 'TRUNCATE') THEN
+        audit_row.statement_only = 't';
+    ELSE
+        RAISE EXCEPTION '[audit.if_modified_func] - Trigger func added as trigger for unhandled case: %, %', TG_OP, TG_LEVEL;
+    END IF;
+
+    INSERT INTO audit.logged_actions VALUES (audit_row.*);
+    RETURN NULL;
+END;
+$body$
+LANGUAGE plpgsql
+SECURITY DEFINER
+SET search_path = pg_catalog, public;
+
+-- Start auditing a table, replacing any existing audit triggers on it.
+--
+-- target_table     - the table to audit
+-- audit_rows       - record each row changed, rather than just the statement
+-- audit_query_text - record the text of the query making the change
+-- ignored_cols     - columns left out of the row_data and changed_fields
+CREATE OR REPLACE FUNCTION audit.audit_table(
+    target_table REGCLASS,
+    audit_rows BOOLEAN,
+    audit_query_text BOOLEAN,
+    ignored_cols TEXT[]
+) RETURNS VOID AS $body$
+DECLARE
+    stm_targets TEXT = 'INSERT OR UPDATE OR DELETE OR TRUNCATE';
+    query_text TEXT;
+    stm_level TEXT;
+    ignored_cols_snip TEXT = '';
+BEGIN
+    EXECUTE 'DROP TRIGGER IF EXISTS audit_trigger_row ON ' || target_table;
+    EXECUTE 'DROP TRIGGER IF EXISTS audit_trigger_stm ON ' || target_table;
+
+    IF audit_rows THEN
+        IF array_length(ignored_cols, 1) > 0 THEN
+            ignored_cols_snip = ', ' || quote_literal(ignored_cols);
+        END IF;
+        query_text = 'CREATE TRIGGER audit_trigger_row AFTER INSERT OR UPDATE OR DELETE ON '
+            || target_table
+            || ' FOR EACH ROW EXECUTE PROCEDURE audit.if_modified_func('
+            || quote_literal(audit_query_text) || ignored_cols_snip || ');';
+        RAISE NOTICE '%', query_text;
+        EXECUTE query_text;
+        stm_targets = 'TRUNCATE';
+    END IF;
+
+    query_text = 'CREATE TRIGGER audit_trigger_stm AFTER ' || stm_targets || ' ON '
+        || target_table
+        || ' FOR EACH STATEMENT EXECUTE PROCEDURE audit.if_modified_func('
+        || quote_literal(audit_query_text) || ');';
+    RAISE NOTICE '%', query_text;
+    EXECUTE query_text;
+END;
+$body$
+LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION audit.audit_table(target_table REGCLASS) RETURNS VOID AS $body$
+    SELECT audit.audit_table($1, BOOLEAN 't', BOOLEAN 't', ARRAY[]::TEXT[]);
+$body$
+LANGUAGE SQL;
+
+SELECT audit.audit_table('package');
+SELECT audit.audit_table('distribution');
+SELECT audit.audit_table('level');
+SELECT audit.audit_table('role');
+SELECT audit.audit_table('platform');
+SELECT audit.audit_table('site');
+SELECT audit.audit_table('pkgcoord');
+SELECT audit.audit_table('versionpin');
+SELECT audit.audit_table('withpackage');
@@ -0,0 +1,445 @@
+-- The views and functions queried by packybara.
+--
+-- Levels, roles, platforms and sites are addressed by name, which is the
+-- path below the root (`facility` or `any`), or the root itself. Role names
+-- separate their pieces with `_` rather than `.` (eg `model_beta`).
+
+-- The name of a path; the root for a root, otherwise the path below it
+CREATE FUNCTION path_name(path LTREE) RETURNS TEXT AS $$
+    SELECT CASE
+        WHEN nlevel($1) = 1 THEN ltree2text($1)
+        ELSE ltree2text(subpath($1, 1))
+    END
+$$ LANGUAGE SQL IMMUTABLE STRICT;
+
+-- The path of a level, given its name (or path)
+CREATE FUNCTION level_name_to_path(name TEXT) RETURNS LTREE AS $$
+    SELECT CASE
+        WHEN $1 = 'facility' OR $1 LIKE 'facility.%' THEN text2ltree($1)
+        ELSE text2ltree('facility.' || $1)
+    END
+$$ LANGUAGE SQL IMMUTABLE STRICT;
+
+-- The path of a role, given its name (or path)
+CREATE FUNCTION role_name_to_path(name TEXT) RETURNS LTREE AS $$
+    SELECT CASE
+        WHEN $1 = 'any' OR $1 LIKE 'any.%' THEN text2ltree($1)
+        ELSE text2ltree('any.' || replace($1, '_', '.'))
+    END
+$$ LANGUAGE SQL IMMUTABLE STRICT;
+
+-- The path of a platform or site, given its name (or path)
+CREATE FUNCTION any_name_to_path(name TEXT) RETURNS LTREE AS $$
+    SELECT CASE
+        WHEN $1 = 'any' OR $1 LIKE 'any.%' THEN text2ltree($1)
+        ELSE text2ltree('any.' || $1)
+    END
+$$ LANGUAGE SQL IMMUTABLE STRICT;
+
+-- Order versions numerically by their `.` separated pieces, so that
+-- 2018.sp10 sorts after 2018.sp9
+CREATE FUNCTION version_ordering(version TEXT) RETURNS NUMERIC[] AS $$
+    SELECT COALESCE(
+        array_agg(
+            COALESCE(NULLIF(regexp_replace(piece, '[^0-9]', '', 'g'), '')::NUMERIC, 0)
+            ORDER BY ordinal
+        ),
+        ARRAY[]::NUMERIC[]
+    )
+    FROM unnest(string_to_array($1, '.')) WITH ORDINALITY AS pieces(piece, ordinal)
+$$ LANGUAGE SQL IMMUTABLE STRICT;
+
+CREATE VIEW level_view AS
+SELECT
+    path_name(path) AS name,
+    CASE
+        WHEN nlevel(path) = 1 THEN ltree2text(path)
+        ELSE ltree2text(subpath(path, 1, 1))
+    END AS show,
+    path
+FROM level;
+
+CREATE VIEW role_view AS
+SELECT
+    replace(path_name(path), '.', '_') AS name,
+    CASE nlevel(path)
+        WHEN 1 THEN 'any'
+        WHEN 2 THEN 'role'
+        ELSE 'subrole'
+    END AS category,
+    path
+FROM role;
+
+CREATE VIEW platform_view AS
+SELECT path_name(path) AS name, path FROM platform;
+
+CREATE VIEW site_view AS
+SELECT path_name(path) AS name, path FROM site;
+
+CREATE VIEW distribution_view AS
+SELECT
+    id AS distribution_id,
+    package,
+    version AS version_name,
+    package || '-' || version AS name,
+    version_ordering(version) AS version
+FROM distribution;
+
+CREATE VIEW pkgcoord_view AS
+SELECT
+    id AS pkgcoord_id,
+    package,
+    package AS package_name,
+    path_name(level) AS level_name,
+    replace(path_name(role), '.', '_') AS role_name,
+    path_name(platform) AS platform_name,
+    path_name(site) AS site_name,
+    level,
+    role,
+    platform,
+    site
+FROM pkgcoord;
+
+CREATE VIEW versionpin_view AS
+SELECT
+    versionpin.id AS versionpin_id,
+    versionpin.distribution AS distribution_id,
+    versionpin.coord AS pkgcoord_id,
+    distribution_view.name AS distribution,
+    pkgcoord_view.package,
+    distribution_view.version_name,
+    pkgcoord_view.level_name AS level,
+    pkgcoord_view.role_name AS role,
+    pkgcoord_view.platform_name AS platform,
+    pkgcoord_view.site_name AS site,
+    pkgcoord_view.level AS level_path,
+    pkgcoord_view.role AS role_path,
+    pkgcoord_view.platform AS platform_path,
+    pkgcoord_view.site AS site_path,
+    ARRAY(
+        SELECT withpackage.package
+        FROM withpackage
+        WHERE withpackage.versionpin = versionpin.id
+        ORDER BY withpackage.pinorder
+    ) AS withs
+FROM versionpin
+JOIN distribution_view ON versionpin.distribution = distribution_view.distribution_id
+JOIN pkgcoord_view ON versionpin.coord = pkgcoord_view.pkgcoord_id;
+
+CREATE VIEW revision_view AS
+SELECT id, transaction_id, author, comment, datetime FROM revision;
+
+-- The versionpins of a package which apply at the supplied coordinates. That
+-- is, the versionpins whose coordinates are equal to, or ancestors of, the
+-- supplied coordinates in every dimension.
+CREATE FUNCTION versionpin_candidates(
+    package_name TEXT,
+    level_path LTREE,
+    role_path LTREE,
+    platform_path LTREE,
+    site_path LTREE
+) RETURNS SETOF versionpin_view AS $$
+    SELECT *
+    FROM versionpin_view AS candidate
+    WHERE candidate.package = $1
+        AND candidate.level_path @> $2
+        AND candidate.role_path @> $3
+        AND candidate.platform_path @> $4
+        AND candidate.site_path @> $5
+$$ LANGUAGE SQL STABLE;
+
+-- All of the versionpins of a package which apply at the supplied
+-- coordinates, from least to most specific. Level outranks role, which
+-- outranks platform, which outranks site.
+CREATE FUNCTION search_distributions(
+    package_name TEXT,
+    role TEXT DEFAULT 'any',
+    platform TEXT DEFAULT 'any',
+    level TEXT DEFAULT 'facility',
+    site TEXT DEFAULT 'any'
+) RETURNS TABLE (
+    versionpin_id INTEGER,
+    distribution TEXT,
+    level_name TEXT,
+    role_name TEXT,
+    site_name TEXT,
+    platform_name TEXT,
+    withs TEXT[]
+) AS $$
+    SELECT
+        candidate.versionpin_id,
+        candidate.distribution,
+        candidate.level,
+        candidate.role,
+        candidate.site,
+        candidate.platform,
+        candidate.withs
+    FROM versionpin_candidates(
+        $1,
+        level_name_to_path($4),
+        role_name_to_path($2),
+        any_name_to_path($3),
+        any_name_to_path($5)
+    ) AS candidate
+    ORDER BY
+        nlevel(candidate.level_path),
+        nlevel(candidate.role_path),
+        nlevel(candidate.platform_path),
+        nlevel(candidate.site_path)
+$$ LANGUAGE SQL STABLE;
+
+-- The most specific versionpin of a package which applies at the supplied
+-- coordinates, along with its withs
+CREATE FUNCTION find_distribution_and_withs(
+    package_name TEXT,
+    role TEXT DEFAULT 'any',
+    platform TEXT DEFAULT 'any',
+    level TEXT DEFAULT 'facility',
+    site TEXT DEFAULT 'any'
+) RETURNS TABLE (
+    versionpin_id INTEGER,
+    distribution TEXT,
+    level_name TEXT,
+    role_name TEXT,
+    site_name TEXT,
+    platform_name TEXT,
+    withs TEXT[]
+) AS $$
+    SELECT
+        candidate.versionpin_id,
+        candidate.distribution,
+        candidate.level,
+        candidate.role,
+        candidate.site,
+        candidate.platform,
+        candidate.withs
+    FROM versionpin_candidates(
+        $1,
+        level_name_to_path($4),
+        role_name_to_path($2),
+        any_name_to_path($3),
+        any_name_to_path($5)
+    ) AS candidate
+    ORDER BY
+        nlevel(candidate.level_path) DESC,
+        nlevel(candidate.role_path) DESC,
+        nlevel(candidate.platform_path) DESC,
+        nlevel(candidate.site_path) DESC
+    LIMIT 1
+$$ LANGUAGE SQL STABLE;
+
+-- The most specific versionpin of each of the withs of a package, resolved
+-- at the same coordinates as the package, in pin order
+CREATE FUNCTION find_distribution_withs(
+    package_name TEXT,
+    role TEXT DEFAULT 'any',
+    platform TEXT DEFAULT 'any',
+    level TEXT DEFAULT 'facility',
+    site TEXT DEFAULT 'any'
+) RETURNS TABLE (
+    versionpin_id INTEGER,
+    distribution TEXT,
+    level_name TEXT,
+    role_name TEXT,
+    site_name TEXT,
+    platform_name TEXT,
+    package TEXT,
+    pinorder BIGINT
+) AS $$
+    SELECT
+        pin.versionpin_id,
+        pin.distribution,
+        pin.level,
+        pin.role,
+        pin.site,
+        pin.platform,
+        with_package.package,
+        with_package.pinorder
+    FROM unnest(
+        COALESCE(
+            (SELECT found.withs
+             FROM find_distribution_and_withs($1, role => $2, platform => $3, level => $4, site => $5)
+             AS found),
+            ARRAY[]::TEXT[]
+        )
+    ) WITH ORDINALITY AS with_package(package, pinorder)
+    CROSS JOIN LATERAL (
+        SELECT *
+        FROM versionpin_candidates(
+            with_package.package,
+            level_name_to_path($4),
+            role_name_to_path($2),
+            any_name_to_path($3),
+            any_name_to_path($5)
+        ) AS candidate
+        ORDER BY
+            nlevel(candidate.level_path) DESC,
+            nlevel(candidate.role_path) DESC,
+            nlevel(candidate.platform_path) DESC,
+            nlevel(candidate.site_path) DESC
+        LIMIT 1
+    ) AS pin
+    ORDER BY with_package.pinorder
+$$ LANGUAGE SQL STABLE;
+
+-- Every versionpin related to the supplied coordinates by the search mode:
+--   ancestor   - versionpins at, or above, the coordinates
+--   descendant - versionpins at, or below, the coordinates
+--   exact      - versionpins at the coordinates
+-- optionally restricted to a package and version
+CREATE FUNCTION findall_versionpins(
+    role TEXT DEFAULT 'any',
+    platform TEXT DEFAULT 'any',
+    level TEXT DEFAULT 'facility',
+    site TEXT DEFAULT 'any',
+    search_mode TEXT DEFAULT 'ancestor',
+    package_name TEXT DEFAULT NULL,
+    version_name TEXT DEFAULT NULL
+) RETURNS TABLE (
+    id INTEGER,
+    distribution_id INTEGER,
+    pkgcoord_id INTEGER,
+    distribution TEXT,
+    level_name TEXT,
+    role_name TEXT,
+    site_name TEXT,
+    platform_name TEXT,
+    withs TEXT[]
+) AS $$
+#variable_conflict use_column
+DECLARE
+    mode TEXT = lower($5);
+    level_p LTREE = level_name_to_path($3);
+    role_p LTREE = role_name_to_path($1);
+    platform_p LTREE = any_name_to_path($2);
+    site_p LTREE = any_name_to_path($4);
+BEGIN
+    IF mode NOT IN ('ancestor', 'descendant', 'exact') THEN
+        RAISE EXCEPTION 'invalid search_mode: %. expected ancestor, descendant or exact', $5;
+    END IF;
+    RETURN QUERY
+    SELECT
+        vp.versionpin_id,
+        vp.distribution_id,
+        vp.pkgcoord_id,
+        vp.distribution,
+        vp.level,
+        vp.role,
+        vp.site,
+        vp.platform,
+        vp.withs
+    FROM versionpin_view AS vp
+    WHERE ($6 IS NULL OR vp.package = $6)
+        AND ($7 IS NULL OR vp.version_name = $7)
+        AND CASE mode
+            WHEN 'ancestor' THEN
+                vp.level_path @> level_p
+                AND vp.role_path @> role_p
+                AND vp.platform_path @> platform_p
+                AND vp.site_path @> site_p
+            WHEN 'descendant' THEN
+                vp.level_path <@ level_p
+                AND vp.role_path <@ role_p
+                AND vp.platform_path <@ platform_p
+                AND vp.site_path <@ site_p
+            ELSE
+                vp.level_path = level_p
+                AND vp.role_path = role_p
+                AND vp.platform_path = platform_p
+                AND vp.site_path = site_p
+        END;
+END;
+$$ LANGUAGE plpgsql STABLE;
+
+-- Pin a distribution (eg maya-2018.sp3) at the supplied coordinates,
+-- creating the pkgcoord if need be, and returning the id of the new
+-- versionpin
+CREATE FUNCTION INSERT_VERSIONPIN(
+    dist TEXT,
+    level_n TEXT DEFAULT 'facility',
+    site_n TEXT DEFAULT 'any',
+    role_n TEXT DEFAULT 'any',
+    platform_n TEXT DEFAULT 'any'
+) RETURNS INTEGER AS $$
+DECLARE
+    dist_id INTEGER;
+    dist_package TEXT;
+    coord_id INTEGER;
+    level_p LTREE = level_name_to_path(level_n);
+    role_p LTREE = role_name_to_path(role_n);
+    platform_p LTREE = any_name_to_path(platform_n);
+    site_p LTREE = any_name_to_path(site_n);
+    vpin_id INTEGER;
+BEGIN
+    SELECT distribution_id, package INTO dist_id, dist_package
+    FROM distribution_view
+    WHERE name = dist;
+    IF dist_id IS NULL THEN
+        RAISE EXCEPTION 'distribution % does not exist', dist;
+    END IF;
+
+    INSERT INTO pkgcoord (package, level, role, platform, site)
+    VALUES (dist_package, level_p, role_p, platform_p, site_p)
+    ON CONFLICT DO NOTHING;
+
+    SELECT id INTO coord_id
+    FROM pkgcoord
+    WHERE package = dist_package
+        AND level = level_p
+        AND role = role_p
+        AND platform = platform_p
+        AND site = site_p;
+
+    INSERT INTO versionpin (distribution, coord)
+    VALUES (dist_id, coord_id)
+    RETURNING id INTO vpin_id;
+
+    RETURN vpin_id;
+END;
+$$ LANGUAGE plpgsql;
+
+-- The changes made to versionpins by a transaction, in the order they were
+-- made. `old` is empty for an insert, and `new` is null for a delete.
+CREATE FUNCTION find_vpin_audit(txid BIGINT) RETURNS TABLE (
+    id INTEGER,
+    transaction_id BIGINT,
+    action TEXT,
+    level_name TEXT,
+    role_name TEXT,
+    platform_name TEXT,
+    site_name TEXT,
+    package TEXT,
+    "old" TEXT,
+    "new" TEXT
+) AS $$
+    SELECT
+        (logged.row_data -> 'id')::INTEGER,
+        logged.transaction_id,
+        logged.action,
+        coord.level_name,
+        coord.role_name,
+        coord.platform_name,
+        coord.site_name,
+        coord.package,
+        CASE
+            WHEN logged.action = 'INSERT' THEN ''
+            ELSE COALESCE(row_dist.name, '')
+        END,
+        CASE logged.action
+            WHEN 'INSERT' THEN row_dist.name
+            WHEN 'UPDATE' THEN COALESCE(changed_dist.name, row_dist.name)
+            ELSE NULL
+        END
+    FROM audit.logged_actions AS logged
+    JOIN pkgcoord_view AS coord
+        ON coord.pkgcoord_id =
+            COALESCE(logged.changed_fields -> 'coord', logged.row_data -> 'coord')::INTEGER
+    LEFT JOIN distribution_view AS row_dist
+        ON row_dist.distribution_id = (logged.row_data -> 'distribution')::INTEGER
+    LEFT JOIN distribution_view AS changed_dist
+        ON changed_dist.distribution_id = (logged.changed_fields -> 'distribution')::INTEGER
+    WHERE logged.table_name = 'versionpin'
+        AND NOT logged.statement_only
+        AND logged.transaction_id = $1
+    ORDER BY logged.event_id
+$$ LANGUAGE SQL STABLE;
@@ -0,0 +1,52 @@
+-- Expose the event id of each change returned by find_vpin_audit. Event ids
+-- increase with each change, and serve as the key when paging through the
+-- changes made by a transaction.
+DROP FUNCTION find_vpin_audit(BIGINT);
+
+-- The changes made to versionpins by a transaction, in the order they were
+-- made. `old` is empty for an insert, and `new` is null for a delete.
+CREATE FUNCTION find_vpin_audit(txid BIGINT) RETURNS TABLE (
+    event_id BIGINT,
+    id INTEGER,
+    transaction_id BIGINT,
+    action TEXT,
+    level_name TEXT,
+    role_name TEXT,
+    platform_name TEXT,
+    site_name TEXT,
+    package TEXT,
+    "old" TEXT,
+    "new" TEXT
+) AS $$
+    SELECT
+        logged.event_id,
+        (logged.row_data -> 'id')::INTEGER,
+        logged.transaction_id,
+        logged.action,
+        coord.level_name,
+        coord.role_name,
+        coord.platform_name,
+        coord.site_name,
+        coord.package,
+        CASE
+            WHEN logged.action = 'INSERT' THEN ''
+            ELSE COALESCE(row_dist.name, '')
+        END,
+        CASE logged.action
+            WHEN 'INSERT' THEN row_dist.name
+            WHEN 'UPDATE' THEN COALESCE(changed_dist.name, row_dist.name)
+            ELSE NULL
+        END
+    FROM audit.logged_actions AS logged
+    JOIN pkgcoord_view AS coord
+        ON coord.pkgcoord_id =
+            COALESCE(logged.changed_fields -> 'coord', logged.row_data -> 'coord')::INTEGER
+    LEFT JOIN distribution_view AS row_dist
+        ON row_dist.distribution_id = (logged.row_data -> 'distribution')::INTEGER
+    LEFT JOIN distribution_view AS changed_dist
+        ON changed_dist.distribution_id = (logged.changed_fields -> 'distribution')::INTEGER
+    WHERE logged.table_name = 'versionpin'
+        AND NOT logged.statement_only
+        AND logged.transaction_id = $1
+    ORDER BY logged.event_id
+$$ LANGUAGE SQL STABLE;
@@ -0,0 +1,44 @@
+-- The changes made to versionpins by every transaction, along with the paths
+-- of their coordinates, and the author, comment and datetime of the revision
+-- made by the transaction. The revision columns are null for changes made by
+-- a transaction which recorded no revision. `old` is empty for an insert, and
+-- `new` is null for a delete, as with find_vpin_audit.
+CREATE VIEW vpin_change_view AS
+SELECT
+    logged.event_id,
+    (logged.row_data -> 'id')::INTEGER AS id,
+    logged.transaction_id,
+    logged.action,
+    coord.level_name,
+    coord.role_name,
+    coord.platform_name,
+    coord.site_name,
+    coord.package,
+    CASE
+        WHEN logged.action = 'INSERT' THEN ''
+        ELSE COALESCE(row_dist.name, '')
+    END AS "old",
+    CASE logged.action
+        WHEN 'INSERT' THEN row_dist.name
+        WHEN 'UPDATE' THEN COALESCE(changed_dist.name, row_dist.name)
+        ELSE NULL
+    END AS "new",
+    rev.author,
+    rev.comment,
+    rev.datetime,
+    coord.level,
+    coord.role,
+    coord.platform,
+    coord.site
+FROM audit.logged_actions AS logged
+JOIN pkgcoord_view AS coord
+    ON coord.pkgcoord_id =
+        COALESCE(logged.changed_fields -> 'coord', logged.row_data -> 'coord')::INTEGER
+LEFT JOIN distribution_view AS row_dist
+    ON row_dist.distribution_id = (logged.row_data -> 'distribution')::INTEGER
+LEFT JOIN distribution_view AS changed_dist
+    ON changed_dist.distribution_id = (logged.changed_fields -> 'distribution')::INTEGER
+LEFT JOIN revision AS rev
+    ON rev.transaction_id = logged.transaction_id
+WHERE logged.table_name = 'versionpin'
+    AND NOT logged.statement_only;
@@ -0,0 +1,136 @@
+-- Surface the changes made to the withs of versionpins alongside the changes
+-- made to their distributions.
+--
+-- The withpackage table is audited row by row, and the withs of a versionpin
+-- are replaced by deleting and reinserting them, so the rows a transaction
+-- deletes and inserts make up the withs before and after it. Rows which are
+-- both inserted and deleted by the same transaction are ignored, as are
+-- transactions which leave the withs as they were. Reordering the withs is
+-- a change.
+CREATE VIEW withs_change_view AS
+WITH with_event AS (
+    SELECT
+        logged.event_id,
+        logged.transaction_id,
+        logged.action,
+        (logged.row_data -> 'id')::INTEGER AS with_id,
+        (logged.row_data -> 'versionpin')::INTEGER AS versionpin,
+        logged.row_data -> 'package' AS package,
+        (logged.row_data -> 'pinorder')::INTEGER AS pinorder
+    FROM audit.logged_actions AS logged
+    WHERE logged.table_name = 'withpackage'
+        AND NOT logged.statement_only
+        AND logged.action IN ('INSERT', 'DELETE')
+), net_event AS (
+    SELECT event.*
+    FROM with_event AS event
+    WHERE NOT EXISTS (
+        SELECT 1 FROM with_event AS other
+        WHERE other.transaction_id = event.transaction_id
+            AND other.with_id = event.with_id
+            AND other.action <> event.action
+    )
+), withs_change AS (
+    SELECT
+        max(event_id) AS event_id,
+        versionpin,
+        transaction_id,
+        COALESCE(
+            array_agg(package ORDER BY pinorder) FILTER (WHERE action = 'DELETE'),
+            '{}'
+        ) AS old_withs,
+        COALESCE(
+            array_agg(package ORDER BY pinorder) FILTER (WHERE action = 'INSERT'),
+            '{}'
+        ) AS new_withs
+    FROM net_event
+    GROUP BY versionpin, transaction_id
+)
+SELECT event_id, versionpin, transaction_id, old_withs, new_withs
+FROM withs_change
+WHERE old_withs IS DISTINCT FROM new_withs;
+
+-- vpin_change_view gains the withs before and after each change to the withs
+-- of a versionpin. They are null for changes to the distribution. The
+-- coordinates of a versionpin which has since been deleted are taken from
+-- its last change.
+DROP VIEW vpin_change_view;
+
+CREATE VIEW vpin_change_view AS
+SELECT
+    logged.event_id,
+    (logged.row_data -> 'id')::INTEGER AS id,
+    logged.transaction_id,
+    logged.action,
+    coord.level_name,
+    coord.role_name,
+    coord.platform_name,
+    coord.site_name,
+    coord.package,
+    CASE
+        WHEN logged.action = 'INSERT' THEN ''
+        ELSE COALESCE(row_dist.name, '')
+    END AS "old",
+    CASE logged.action
+        WHEN 'INSERT' THEN row_dist.name
+        WHEN 'UPDATE' THEN COALESCE(changed_dist.name, row_dist.name)
+        ELSE NULL
+    END AS "new",
+    rev.author,
+    rev.comment,
+    rev.datetime,
+    NULL::TEXT[] AS old_withs,
+    NULL::TEXT[] AS new_withs,
+    coord.level,
+    coord.role,
+    coord.platform,
+    coord.site
+FROM audit.logged_actions AS logged
+JOIN pkgcoord_view AS coord
+    ON coord.pkgcoord_id =
+        COALESCE(logged.changed_fields -> 'coord', logged.row_data -> 'coord')::INTEGER
+LEFT JOIN distribution_view AS row_dist
+    ON row_dist.distribution_id = (logged.row_data -> 'distribution')::INTEGER
+LEFT JOIN distribution_view AS changed_dist
+    ON changed_dist.distribution_id = (logged.changed_fields -> 'distribution')::INTEGER
+LEFT JOIN revision AS rev
+    ON rev.transaction_id = logged.transaction_id
+WHERE logged.table_name = 'versionpin'
+    AND NOT logged.statement_only
+UNION ALL
+SELECT
+    withs.event_id,
+    withs.versionpin AS id,
+    withs.transaction_id,
+    'UPDATE' AS action,
+    coord.level_name,
+    coord.role_name,
+    coord.platform_name,
+    coord.site_name,
+    coord.package,
+    '' AS "old",
+    NULL AS "new",
+    rev.author,
+    rev.comment,
+    rev.datetime,
+    withs.old_withs,
+    withs.new_withs,
+    coord.level,
+    coord.role,
+    coord.platform,
+    coord.site
+FROM withs_change_view AS withs
+LEFT JOIN versionpin AS pin
+    ON pin.id = withs.versionpin
+JOIN pkgcoord_view AS coord
+    ON coord.pkgcoord_id = COALESCE(pin.coord, (
+        SELECT COALESCE(logged.changed_fields -> 'coord', logged.row_data -> 'coord')::INTEGER
+        FROM audit.logged_actions AS logged
+        WHERE logged.table_name = 'versionpin'
+            AND NOT logged.statement_only
+            AND (logged.row_data -> 'id')::INTEGER = withs.versionpin
+        ORDER BY logged.event_id DESC
+        LIMIT 1
+    ))
+LEFT JOIN revision AS rev
+    ON rev.transaction_id = withs.transaction_id;
@@ -1,10 +1,27 @@
 pub mod add;
+pub mod async_packrat;
+pub mod backend;
+pub mod config;
+pub mod delete;
+pub mod diff;
 pub mod find;
 pub mod find_all;
+pub mod history;
+pub mod import;
+pub mod memory;
+pub mod migrate;
 pub mod packrat;
+pub mod plan;
+pub mod pool;
+pub mod prepared;
+pub mod query;
+pub mod revert;
 pub mod search_attribute;
+pub mod snapshot;
+pub mod sqlite;
 pub mod update;
 pub mod utils;
-pub use find_all::{OrderLevelBy, OrderPlatformBy, OrderRevisionBy, OrderRoleBy};
+pub use find_all::{OrderChangeBy, OrderLevelBy, OrderPlatformBy, OrderRevisionBy, OrderRoleBy};
+pub use history::AsOf;
 pub use search_attribute::{JoinMode, LtreeSearchMode, SearchAttribute, SearchCtrl, SearchMode};
 pub mod traits;
@@ -6,14 +6,22 @@
  * packybara can not be copied and/or distributed without the express
  * permission of Jonathan Gerber
  *******************************************************/
-use crate::db::traits::{PBAdd, PBExport, PBFind, PBUpdate};
-use crate::db::{add, find, find_all, update};
+use crate::db::add::versionpins::ConflictPolicy;
+use crate::db::backend::Backend;
+use crate::db::history::AsOf;
+use crate::db::traits::{PBAdd, PBDelete, PBExport, PBFind, PBUpdate};
+use crate::db::{
+    add, delete, diff, find, find_all, import, migrate, revert, snapshot, sqlite, update,
+};
 use crate::io::packages_xml::xml::write_xml;
-use crate::types::IdType;
+use crate::io::packages_xml::XmlVersionPin;
+use crate::registry;
+use crate::types::{IdType, LongIdType};
 pub use postgres::{Client, NoTls, Transaction};
 use snafu::{ResultExt, Snafu};
 
 #[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
 pub enum PackratDbError {
     /// When constructing a query, postgres has thrown an error
     #[snafu(display("Postgres Error: {} {:#?}", msg, source))]
@@ -23,17 +31,63 @@ pub enum PackratDbError {
     },
     #[snafu(display("No update data supplied"))]
     NoUpdatesError,
+    /// Unable to retrieve the sites from the database
+    #[snafu(display("Problem loading sites: {}", source))]
+    LoadSitesError {
+        source: find_all::sites::FindAllSitesError,
+    },
+    /// Unable to retrieve the platforms from the database
+    #[snafu(display("Problem loading platforms: {}", source))]
+    LoadPlatformsError {
+        source: find_all::platforms::FindAllPlatformsError,
+    },
 }
 
-pub struct PackratDb<'a> {
-    client: &'a mut Client,
+/// The entry point for querying and updating packrat. A PackratDb wraps a
+/// `Backend`, which defaults to a postgres `Client`.
+pub struct PackratDb<'a, C = Client> {
+    client: &'a mut C,
 }
 
-impl<'a> PackratDb<'a> {
-    /// New up a PackratDb instance given a Client
-    pub fn new(client: &'a mut Client) -> Self {
+impl<'a, C> PackratDb<'a, C> {
+    /// New up a PackratDb instance given a Client, or another Backend
+    /// (eg a `MemoryStore`)
+    pub fn new(client: &'a mut C) -> Self {
         PackratDb { client }
     }
+}
+
+impl<'a, C: Backend> PackratDb<'a, C> {
+    /// Register the sites and platforms stored in the database, so that
+    /// those which are not built into `Site` and `Platform` may be used in
+    /// Coords, VersionPins, and the various builders.
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - Unit
+    ///   * `Err` - `PackratDbError`
+    pub fn load_registry(&mut self) -> Result<(), PackratDbError> {
+        let sites = self.find_all_sites().query().context(LoadSitesError)?;
+        registry::register_sites(sites.iter().map(|site| site.name.as_str()));
+        let platforms = self
+            .find_all_platforms()
+            .query()
+            .context(LoadPlatformsError)?;
+        registry::register_platforms(platforms.iter().map(|platform| platform.name.as_str()));
+        Ok(())
+    }
+
+    /// Export the contents of the database into an SQLite file, which may be
+    /// queried via a `SqliteStore` by hosts without access to the database.
+    ///
+    /// # Returns
+    /// * ExportSqlite instance
+    pub fn export_sqlite<'b>(&'b mut self) -> sqlite::export::ExportSqlite<'b, C> {
+        sqlite::export::ExportSqlite::new(self.client)
+    }
+}
+
+impl<'a> PackratDb<'a> {
     pub fn commit(
         mut tx: Transaction<'a>,
         author: &str,
@@ -59,9 +113,120 @@ impl<'a> PackratDb<'a> {
     pub fn transaction(&'a mut self) -> Transaction<'a> {
         self.client.transaction().unwrap()
     }
+
+    /// Revert the changes made by a previous revision. The inverse of the
+    /// revision's changes is applied and committed as a new revision.
+    ///
+    /// # Arguments
+    /// * `transaction_id` - The transaction id of the revision to revert
+    ///
+    /// # Returns
+    /// * RevertRevision instance
+    pub fn revert_revision<'b>(
+        &'b mut self,
+        transaction_id: LongIdType,
+    ) -> revert::RevertRevision<'b> {
+        revert::RevertRevision::new(self.client, transaction_id)
+    }
+
+    /// Compare package space at two points in time, reporting the versionpins
+    /// which were added, removed or changed in between.
+    ///
+    /// # Arguments
+    /// * `from` - The earlier revision id or `DateTime<Local>`
+    /// * `to` - The later revision id or `DateTime<Local>`
+    ///
+    /// # Returns
+    /// * DiffRevisions instance
+    pub fn diff<'b, F: Into<AsOf>, T: Into<AsOf>>(
+        &'b mut self,
+        from: F,
+        to: T,
+    ) -> diff::DiffRevisions<'b> {
+        diff::DiffRevisions::new(self.client, from, to)
+    }
+
+    /// Import versionpins read from a packages.xml (see `io::read_xml`),
+    /// adding and updating versionpins and their withs within a single revision.
+    ///
+    /// # Arguments
+    /// * `pins` - The versionpins to import
+    ///
+    /// # Returns
+    /// * ImportPackagesXml instance
+    pub fn import_packages_xml<'b>(
+        &'b mut self,
+        pins: Vec<XmlVersionPin>,
+    ) -> import::ImportPackagesXml<'b> {
+        import::ImportPackagesXml::new(self.client, pins)
+    }
+
+    /// Take a snapshot of the contents of the database, which may be
+    /// written to json or toml, and restored via `restore_snapshot`.
+    ///
+    /// # Returns
+    /// * TakeSnapshot instance
+    pub fn snapshot<'b>(&'b mut self) -> snapshot::TakeSnapshot<'b> {
+        snapshot::TakeSnapshot::new(self.client)
+    }
+
+    /// Restore a snapshot into an empty database, within a single revision.
+    ///
+    /// # Arguments
+    /// * `snapshot` - The snapshot to restore
+    ///
+    /// # Returns
+    /// * RestoreSnapshot instance
+    pub fn restore_snapshot<'b>(
+        &'b mut self,
+        snapshot: snapshot::Snapshot,
+    ) -> snapshot::RestoreSnapshot<'b> {
+        snapshot::RestoreSnapshot::new(self.client, snapshot)
+    }
+
+    /// Apply the pending schema migrations, bringing the database up to the
+    /// schema expected by this version of packybara.
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - The migrations applied, which may be empty
+    ///   * `Err` - MigrateError
+    pub fn migrate(&mut self) -> migrate::MigrateResult<Vec<&'static migrate::Migration>> {
+        migrate::migrate(self.client)
+    }
+
+    /// Record the migrations up to, and including, `version` as applied,
+    /// without running them. Adopts a database whose schema predates the
+    /// migrations (see `migrate::baseline`).
+    ///
+    /// # Arguments
+    /// * `version` - The version of the schema the database already has
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - The migrations recorded as applied
+    ///   * `Err` - MigrateError
+    pub fn baseline(
+        &mut self,
+        version: i32,
+    ) -> migrate::MigrateResult<Vec<&'static migrate::Migration>> {
+        migrate::baseline(self.client, version)
+    }
+
+    /// Retrieve the version of the database schema
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - The schema version, or None if the database has not been migrated
+    ///   * `Err` - MigrateError
+    pub fn schema_version(&mut self) -> migrate::MigrateResult<Option<i32>> {
+        migrate::schema_version(self.client)
+    }
 }
 
-impl<'a> PBFind for PackratDb<'a> {
+impl<'a, C: Backend> PBFind for PackratDb<'a, C> {
+    type Client = C;
+
     /// Find the most appropriate versionpin for a request. `find_versionpin`
     /// returns an instance of `FindVersionPinBuilder`, which provides
     /// setter methods providing a fluent api.
@@ -77,13 +242,12 @@ impl<'a> PBFind for PackratDb<'a> {
     /// the versionpin. (see documentation for `FindVersionPinBuilder`)
     ///
     /// # Example
-    /// ```rust
-    /// use packybara::packrat::{Client, NoTls, PackratDb};
-    /// let mut client = Client::connect(
-    ///    "host=127.0.0.1 user=postgres dbname=packrat password=example port=5432",
-    ///    NoTls,
-    ///    ).unwrap();
-    /// let mut db = PackratDb::new(client);
+    /// ```rust,no_run
+    /// use packybara::packrat::PackratDb;
+    /// use packybara::traits::PBFind;
+    /// use packybara::PackratConfig;
+    /// let mut client = PackratConfig::load().unwrap().connect().unwrap();
+    /// let mut db = PackratDb::new(&mut client);
     /// let dist = db.find_versionpin("maya")
     ///                     .level("dev01")
     ///                     .role("model")
@@ -91,66 +255,82 @@ impl<'a> PBFind for PackratDb<'a> {
     ///                     .site("portland")
     ///                     .query().unwrap();
     /// ```
-    fn find_versionpin<'b>(&'b mut self, package: &'b str) -> find::versionpin::FindVersionPin {
-        find::versionpin::FindVersionPin::new(&mut self.client, package)
+    fn find_versionpin<'b>(
+        &'b mut self,
+        package: &'b str,
+    ) -> find::versionpin::FindVersionPin<'b, Self::Client> {
+        find::versionpin::FindVersionPin::new(self.client, package)
     }
 
-    fn find_versionpins<'b>(&'b mut self, package: &'b str) -> find::versionpins::FindVersionPins {
-        find::versionpins::FindVersionPins::new(&mut self.client, package)
+    fn find_versionpins<'b>(
+        &'b mut self,
+        package: &'b str,
+    ) -> find::versionpins::FindVersionPins<'b, Self::Client> {
+        find::versionpins::FindVersionPins::new(self.client, package)
     }
 
-    fn find_all_versionpins<'b>(&'b mut self) -> find_all::versionpins::FindAllVersionPins {
-        find_all::versionpins::FindAllVersionPins::new(&mut self.client)
+    fn find_all_versionpins<'b>(
+        &'b mut self,
+    ) -> find_all::versionpins::FindAllVersionPins<'b, Self::Client> {
+        find_all::versionpins::FindAllVersionPins::new(self.client)
     }
 
-    fn find_all_roles<'b>(&'b mut self) -> find_all::roles::FindAllRoles {
-        find_all::roles::FindAllRoles::new(&mut self.client)
+    fn find_all_roles<'b>(&'b mut self) -> find_all::roles::FindAllRoles<'b, Self::Client> {
+        find_all::roles::FindAllRoles::new(self.client)
     }
 
-    fn find_all_revisions<'b>(&'b mut self) -> find_all::revisions::FindAllRevisions {
-        find_all::revisions::FindAllRevisions::new(&mut self.client)
+    fn find_all_revisions<'b>(
+        &'b mut self,
+    ) -> find_all::revisions::FindAllRevisions<'b, Self::Client> {
+        find_all::revisions::FindAllRevisions::new(self.client)
     }
 
-    fn find_all_changes<'b>(&'b mut self) -> find_all::changes::FindAllChanges {
-        find_all::changes::FindAllChanges::new(&mut self.client)
+    fn find_all_changes<'b>(&'b mut self) -> find_all::changes::FindAllChanges<'b, Self::Client> {
+        find_all::changes::FindAllChanges::new(self.client)
     }
 
-    fn find_all_platforms<'b>(&'b mut self) -> find_all::platforms::FindAllPlatforms {
-        find_all::platforms::FindAllPlatforms::new(&mut self.client)
+    fn find_all_platforms<'b>(
+        &'b mut self,
+    ) -> find_all::platforms::FindAllPlatforms<'b, Self::Client> {
+        find_all::platforms::FindAllPlatforms::new(self.client)
     }
 
-    fn find_all_sites<'b>(&'b mut self) -> find_all::sites::FindAllSites {
-        find_all::sites::FindAllSites::new(&mut self.client)
+    fn find_all_sites<'b>(&'b mut self) -> find_all::sites::FindAllSites<'b, Self::Client> {
+        find_all::sites::FindAllSites::new(self.client)
     }
 
-    fn find_all_levels<'b>(&'b mut self) -> find_all::levels::FindAllLevels {
-        find_all::levels::FindAllLevels::new(&mut self.client)
+    fn find_all_levels<'b>(&'b mut self) -> find_all::levels::FindAllLevels<'b, Self::Client> {
+        find_all::levels::FindAllLevels::new(self.client)
     }
 
-    fn find_all_packages<'b>(&'b mut self) -> find_all::packages::FindAllPackages {
-        find_all::packages::FindAllPackages::new(&mut self.client)
+    fn find_all_packages<'b>(
+        &'b mut self,
+    ) -> find_all::packages::FindAllPackages<'b, Self::Client> {
+        find_all::packages::FindAllPackages::new(self.client)
     }
     /// find withs for a particular versionpin
     fn find_all_versionpin_withs<'b>(
         &'b mut self,
         versionpin_id: IdType,
-    ) -> find_all::versionpin_withs::FindAllWiths {
-        find_all::versionpin_withs::FindAllWiths::new(&mut self.client, versionpin_id)
+    ) -> find_all::versionpin_withs::FindAllWiths<'b, Self::Client> {
+        find_all::versionpin_withs::FindAllWiths::new(self.client, versionpin_id)
     }
-    fn find_all_distributions<'b>(&'b mut self) -> find_all::distributions::FindAllDistributions {
-        find_all::distributions::FindAllDistributions::new(&mut self.client)
+    fn find_all_distributions<'b>(
+        &'b mut self,
+    ) -> find_all::distributions::FindAllDistributions<'b, Self::Client> {
+        find_all::distributions::FindAllDistributions::new(self.client)
     }
     /// Find pins that meet a specific criteria
-    fn find_pins<'b>(&'b mut self) -> find::pins::FindPins {
-        find::pins::FindPins::new(&mut self.client)
+    fn find_pins<'b>(&'b mut self) -> find::pins::FindPins<'b, Self::Client> {
+        find::pins::FindPins::new(self.client)
     }
     /// Find pkgcoords that meet a specific criteria
-    fn find_pkgcoords<'b>(&'b mut self) -> find_all::pkgcoords::FindAllPkgCoords {
-        find_all::pkgcoords::FindAllPkgCoords::new(Some(&mut self.client))
+    fn find_pkgcoords<'b>(&'b mut self) -> find_all::pkgcoords::FindAllPkgCoords<'b, Self::Client> {
+        find_all::pkgcoords::FindAllPkgCoords::with_client(Some(self.client))
     }
     /// find withs of a
-    fn find_withs<'b>(&'b mut self, package: &'b str) -> find::withs::FindWiths {
-        find::withs::FindWiths::new(&mut self.client, package)
+    fn find_withs<'b>(&'b mut self, package: &'b str) -> find::withs::FindWiths<'b, Self::Client> {
+        find::withs::FindWiths::new(self.client, package)
     }
 }
 
@@ -158,27 +338,43 @@ impl<'b> PBAdd<'b> for PackratDb<'b> {
     type TransactionType = Transaction<'b>;
 
     /// add packages
-    fn add_packages(tx: Self::TransactionType) -> add::packages::AddPackages<'b> {
+    fn add_packages(
+        tx: Self::TransactionType,
+    ) -> add::packages::AddPackages<'b, Self::TransactionType> {
         add::packages::AddPackages::new(tx)
     }
 
     /// add levels
-    fn add_levels(tx: Self::TransactionType) -> add::levels::AddLevels<'b> {
+    fn add_levels(tx: Self::TransactionType) -> add::levels::AddLevels<'b, Self::TransactionType> {
         add::levels::AddLevels::new(tx)
     }
 
     /// add roles
-    fn add_roles(tx: Self::TransactionType) -> add::roles::AddRoles<'b> {
+    fn add_roles(tx: Self::TransactionType) -> add::roles::AddRoles<'b, Self::TransactionType> {
         add::roles::AddRoles::new(tx)
     }
 
     /// add platforms
-    fn add_platforms(tx: Self::TransactionType) -> add::platforms::AddPlatforms<'b> {
+    fn add_platforms(
+        tx: Self::TransactionType,
+    ) -> add::platforms::AddPlatforms<'b, Self::TransactionType> {
         add::platforms::AddPlatforms::new(tx)
     }
 
+    /// add sites
+    fn add_sites(tx: Self::TransactionType) -> add::sites::AddSites<'b, Self::TransactionType> {
+        add::sites::AddSites::new(tx)
+    }
+
+    /// add distributions. The packages must already exist.
+    fn add_distributions(
+        tx: Self::TransactionType,
+    ) -> add::distributions::AddDistributions<'b, Self::TransactionType> {
+        add::distributions::AddDistributions::new(tx)
+    }
+
     /// Add withs to the transaction
-    fn add_withs(tx: Self::TransactionType) -> add::withs::AddWiths<'b> {
+    fn add_withs(tx: Self::TransactionType) -> add::withs::AddWiths<'b, Self::TransactionType> {
         add::withs::AddWiths::new(tx)
     }
 
@@ -186,11 +382,12 @@ impl<'b> PBAdd<'b> for PackratDb<'b> {
         tx: Self::TransactionType,
         package: I,
         version: I,
-    ) -> add::versionpins::AddVersionPins<'b>
+        policy: ConflictPolicy,
+    ) -> add::versionpins::AddVersionPins<'b, Self::TransactionType>
     where
         I: Into<String>,
     {
-        add::versionpins::AddVersionPins::new(tx, package.into(), version.into())
+        add::versionpins::AddVersionPins::new(tx, package.into(), version.into(), policy)
     }
 }
 
@@ -202,11 +399,59 @@ impl<'a> PBUpdate<'a> for PackratDb<'a> {
     /// # Arguments
     /// * `comment` - A comment describing the update
     /// * `user` - The name of the user making the update
-    fn update_versionpins(tx: Transaction<'a>) -> update::versionpins::UpdateVersionPins<'a> {
+    fn update_versionpins(
+        tx: Transaction<'a>,
+    ) -> update::versionpins::UpdateVersionPins<'a, Self::TransactionType> {
         update::versionpins::UpdateVersionPins::new(tx)
     }
 }
 
+impl<'a> PBDelete<'a> for PackratDb<'a> {
+    type TransactionType = Transaction<'a>;
+
+    /// delete versionpins, along with their withs
+    fn delete_versionpins(
+        tx: Self::TransactionType,
+    ) -> delete::versionpins::DeleteVersionPins<'a, Self::TransactionType> {
+        delete::versionpins::DeleteVersionPins::new(tx)
+    }
+
+    /// delete withs from a versionpin
+    fn delete_withs(
+        tx: Self::TransactionType,
+    ) -> delete::withs::DeleteWiths<'a, Self::TransactionType> {
+        delete::withs::DeleteWiths::new(tx)
+    }
+
+    /// delete distributions
+    fn delete_distributions(
+        tx: Self::TransactionType,
+    ) -> delete::distributions::DeleteDistributions<'a, Self::TransactionType> {
+        delete::distributions::DeleteDistributions::new(tx)
+    }
+
+    /// delete packages
+    fn delete_packages(
+        tx: Self::TransactionType,
+    ) -> delete::packages::DeletePackages<'a, Self::TransactionType> {
+        delete::packages::DeletePackages::new(tx)
+    }
+
+    /// delete levels
+    fn delete_levels(
+        tx: Self::TransactionType,
+    ) -> delete::levels::DeleteLevels<'a, Self::TransactionType> {
+        delete::levels::DeleteLevels::new(tx)
+    }
+
+    /// delete roles
+    fn delete_roles(
+        tx: Self::TransactionType,
+    ) -> delete::roles::DeleteRoles<'a, Self::TransactionType> {
+        delete::roles::DeleteRoles::new(tx)
+    }
+}
+
 impl<'a> PBExport<'a> for PackratDb<'a> {
     type Error = crate::io::packages_xml::xml::PackagesXmlError;
 
@@ -0,0 +1,405 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! The change plans returned by the `preview` methods of the add and update
+//! builders.
+//!
+//! A preview executes the builder's statements within its transaction,
+//! records what they changed, and rolls the transaction back rather than
+//! committing it. Changes to versionpins are read back from the audit log of
+//! the transaction, so the plan reports exactly what a commit would record.
+use crate::db::async_packrat::AsyncTransaction;
+use crate::db::find_all::changes::{
+    ChangeAction, ChangeType, FindAllChangesError, FindAllChangesRow,
+};
+use crate::db::memory::MemoryTransaction;
+use crate::db::prepared::Prepared;
+use crate::types::{IdType, LongIdType};
+use crate::{Coords, Distribution};
+use itertools::Itertools;
+use postgres::{Row, Transaction};
+use snafu::{ResultExt, Snafu};
+use std::fmt;
+use std::hash::Hash;
+use strum_macros::{AsRefStr, Display, EnumString, IntoStaticStr};
+
+/// Error type returned when reading back the changes made by a preview
+#[derive(Debug, Snafu)]
+pub enum ChangePlanError {
+    /// Error from postgres
+    #[snafu(display("Postgres Error: {} {}", msg, source))]
+    TokioPostgresError {
+        msg: &'static str,
+        source: tokio_postgres::error::Error,
+    },
+    /// A change read back from the audit log could not be converted
+    #[snafu(display("Problem converting change: {}", source))]
+    ChangeError { source: FindAllChangesError },
+}
+
+pub type ChangePlanResult<T, E = ChangePlanError> = std::result::Result<T, E>;
+
+/// The kind of entity added by a builder
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr, Clone, Copy,
+)]
+pub enum EntityKind {
+    #[strum(serialize = "package", to_string = "package")]
+    Package,
+    #[strum(serialize = "level", to_string = "level")]
+    Level,
+    #[strum(serialize = "role", to_string = "role")]
+    Role,
+    #[strum(serialize = "platform", to_string = "platform")]
+    Platform,
+    #[strum(serialize = "site", to_string = "site")]
+    Site,
+    #[strum(serialize = "distribution", to_string = "distribution")]
+    Distribution,
+}
+
+impl EntityKind {
+    // The RETURNING clause reporting the name of each entity added by an insert
+    fn returning(self) -> &'static str {
+        match self {
+            Self::Package => " RETURNING name",
+            Self::Distribution => " RETURNING package || '-' || version::TEXT",
+            Self::Level | Self::Role | Self::Platform | Self::Site => " RETURNING path::TEXT",
+        }
+    }
+}
+
+/// A single change made by a builder
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PlannedChange {
+    /// A package, level, role, platform, site or distribution is added. The
+    /// name of a level, role, platform or site is its path.
+    Add { kind: EntityKind, name: String },
+    /// A versionpin is inserted, updated or deleted
+    VersionPin {
+        action: ChangeAction,
+        versionpin_id: IdType,
+        package: String,
+        coords: Coords,
+        /// The distribution before. None if the versionpin is inserted
+        old: Option<Distribution>,
+        /// The distribution after. None if the versionpin is deleted
+        new: Option<Distribution>,
+    },
+    /// The withs of a versionpin are replaced
+    Withs {
+        versionpin_id: IdType,
+        old: Vec<String>,
+        new: Vec<String>,
+    },
+}
+
+impl From<FindAllChangesRow> for PlannedChange {
+    fn from(row: FindAllChangesRow) -> Self {
+        let (old, new) = match row.change {
+            ChangeType::Withs { old, new } => {
+                return Self::Withs {
+                    versionpin_id: row.id as IdType,
+                    old,
+                    new,
+                }
+            }
+            ChangeType::Distribution { old, new } => (old, new),
+        };
+        let new = match row.action {
+            ChangeAction::Delete => None,
+            _ => Some(new),
+        };
+        Self::VersionPin {
+            action: row.action,
+            versionpin_id: row.id as IdType,
+            package: row.package,
+            coords: Coords {
+                level: row.level,
+                role: row.role,
+                platform: row.platform,
+                site: row.site,
+            },
+            old,
+            new,
+        }
+    }
+}
+
+impl fmt::Display for PlannedChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn dist(distribution: &Option<Distribution>) -> &str {
+            distribution
+                .as_ref()
+                .map(|d| d.distribution())
+                .unwrap_or("-")
+        }
+        match self {
+            Self::Add { kind, name } => write!(f, "ADD {} {}", kind, name),
+            Self::VersionPin {
+                action,
+                versionpin_id,
+                package,
+                coords,
+                old,
+                new,
+            } => write!(
+                f,
+                "{} versionpin {} {} {} {} -> {}",
+                action,
+                versionpin_id,
+                package,
+                coords,
+                dist(old),
+                dist(new)
+            ),
+            Self::Withs {
+                versionpin_id,
+                old,
+                new,
+            } => write!(
+                f,
+                "WITHS versionpin {} [{}] -> [{}]",
+                versionpin_id,
+                old.join(", "),
+                new.join(", ")
+            ),
+        }
+    }
+}
+
+/// The changes a builder would make, in the order it makes them
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct ChangePlan {
+    changes: Vec<PlannedChange>,
+}
+
+impl ChangePlan {
+    /// New up an empty ChangePlan
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Retrieve the changes
+    pub fn changes(&self) -> &[PlannedChange] {
+        &self.changes
+    }
+
+    /// The number of changes
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    /// Does the plan make no changes
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Add a change to the plan
+    pub fn push(&mut self, change: PlannedChange) {
+        self.changes.push(change);
+    }
+
+    // a plan adding each of the named entities
+    pub(crate) fn added<I>(kind: EntityKind, names: I) -> Self
+    where
+        I: IntoIterator<Item = String>,
+    {
+        names
+            .into_iter()
+            .map(|name| PlannedChange::Add { kind, name })
+            .collect()
+    }
+}
+
+impl std::iter::FromIterator<PlannedChange> for ChangePlan {
+    fn from_iter<I: IntoIterator<Item = PlannedChange>>(iter: I) -> Self {
+        Self {
+            changes: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl IntoIterator for ChangePlan {
+    type Item = PlannedChange;
+    type IntoIter = std::vec::IntoIter<PlannedChange>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.changes.into_iter()
+    }
+}
+
+impl fmt::Display for ChangePlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+/// Execute the prepared insert of an add builder within the transaction,
+/// returning a plan adding each of the entities of `kind` it inserted
+pub(crate) fn preview_added(
+    tx: &mut Transaction<'_>,
+    mut prepared: Prepared,
+    kind: EntityKind,
+) -> Result<ChangePlan, tokio_postgres::Error> {
+    prepared.sql.push_str(kind.returning());
+    let rows = prepared.query_tx(tx)?;
+    Ok(ChangePlan::added(kind, rows.iter().map(|row| row.get(0))))
+}
+
+/// The async counterpart of `preview_added`
+pub(crate) async fn preview_added_async(
+    tx: &AsyncTransaction<'_>,
+    mut prepared: Prepared,
+    kind: EntityKind,
+) -> Result<ChangePlan, tokio_postgres::Error> {
+    prepared.sql.push_str(kind.returning());
+    let rows = prepared.query_tx_async(tx).await?;
+    Ok(ChangePlan::added(kind, rows.iter().map(|row| row.get(0))))
+}
+
+/// The MemoryStore counterpart of `preview_added`. Returns a plan adding
+/// each of the distinct `items` which does not already `exist`
+pub(crate) fn preview_added_memory<T, I, F>(kind: EntityKind, items: I, exists: F) -> ChangePlan
+where
+    T: fmt::Display + Eq + Hash + Clone,
+    I: IntoIterator<Item = T>,
+    F: Fn(&T) -> bool,
+{
+    ChangePlan::added(
+        kind,
+        items
+            .into_iter()
+            .unique()
+            .filter(|item| !exists(item))
+            .map(|item| item.to_string()),
+    )
+}
+
+// The id of the latest change recorded by the current transaction
+const AUDIT_WATERMARK: &str =
+    "SELECT coalesce(max(event_id), 0) FROM find_vpin_audit(txid_current())";
+
+// The changes recorded by the current transaction after the supplied event
+fn audit_since() -> String {
+    format!(
+        "SELECT {} FROM find_vpin_audit(txid_current()) WHERE event_id > $1 ORDER BY event_id",
+        FindAllChangesRow::COLUMNS.join(", ")
+    )
+}
+
+fn versionpin_changes(rows: Vec<Row>) -> ChangePlanResult<ChangePlan> {
+    rows.iter()
+        .map(|row| {
+            FindAllChangesRow::from_row(row)
+                .map(PlannedChange::from)
+                .context(ChangeError)
+        })
+        .collect()
+}
+
+/// The id of the latest change to a versionpin recorded by the transaction,
+/// from which to read back the changes made by a preview
+pub(crate) fn watermark(tx: &mut Transaction<'_>) -> ChangePlanResult<LongIdType> {
+    let row = tx
+        .query_one(AUDIT_WATERMARK, &[])
+        .context(TokioPostgresError {
+            msg: "problem with select from find_vpin_audit function",
+        })?;
+    Ok(row.get(0))
+}
+
+/// The changes to versionpins recorded by the transaction after the watermark
+pub(crate) fn changes_since(
+    tx: &mut Transaction<'_>,
+    watermark: LongIdType,
+) -> ChangePlanResult<ChangePlan> {
+    let rows = tx
+        .query(audit_since().as_str(), &[&watermark])
+        .context(TokioPostgresError {
+            msg: "problem with select from find_vpin_audit function",
+        })?;
+    versionpin_changes(rows)
+}
+
+/// The async counterpart of `watermark`
+pub(crate) async fn watermark_async(tx: &AsyncTransaction<'_>) -> ChangePlanResult<LongIdType> {
+    let row = tx
+        .query_one(AUDIT_WATERMARK, &[])
+        .await
+        .context(TokioPostgresError {
+            msg: "problem with select from find_vpin_audit function",
+        })?;
+    Ok(row.get(0))
+}
+
+/// The async counterpart of `changes_since`
+pub(crate) async fn changes_since_async(
+    tx: &AsyncTransaction<'_>,
+    watermark: LongIdType,
+) -> ChangePlanResult<ChangePlan> {
+    let rows = tx
+        .query(audit_since().as_str(), &[&watermark])
+        .await
+        .context(TokioPostgresError {
+            msg: "problem with select from find_vpin_audit function",
+        })?;
+    versionpin_changes(rows)
+}
+
+/// The MemoryStore counterpart of `watermark`
+pub(crate) fn watermark_memory(tx: &MemoryTransaction<'_>) -> LongIdType {
+    tx.tables().audit.len() as LongIdType
+}
+
+/// The MemoryStore counterpart of `changes_since`
+pub(crate) fn changes_since_memory(
+    tx: &MemoryTransaction<'_>,
+    watermark: LongIdType,
+) -> ChangePlanResult<ChangePlan> {
+    tx.tables()
+        .find_vpin_audit(tx.transaction_id())
+        .iter()
+        .filter(|change| change.event_id > watermark)
+        .map(|change| {
+            FindAllChangesRow::from_view(change)
+                .map(PlannedChange::from)
+                .context(ChangeError)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn displays_each_change_on_a_line() {
+        let mut plan = ChangePlan::added(EntityKind::Level, vec!["facility.dev01".to_string()]);
+        plan.push(PlannedChange::Withs {
+            versionpin_id: 3,
+            old: vec!["houdini".to_string()],
+            new: vec!["houdini".to_string(), "nuke".to_string()],
+        });
+        assert_eq!(plan.len(), 2);
+        assert_eq!(
+            plan.to_string(),
+            "ADD level facility.dev01\nWITHS versionpin 3 [houdini] -> [houdini, nuke]\n"
+        );
+    }
+
+    #[test]
+    fn preview_added_memory_skips_existing_and_repeated_names() {
+        let names = vec!["maya", "nuke", "maya", "houdini"];
+        let plan = preview_added_memory(EntityKind::Package, names, |name| *name == "houdini");
+        assert_eq!(plan.to_string(), "ADD package maya\nADD package nuke\n");
+    }
+}
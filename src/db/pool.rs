@@ -0,0 +1,482 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! A pool of connections to the packrat database. `PackratPool` is cheap to
+//! clone, `Send` and `Sync`, so that it may be shared between threads.
+//!
+//! `PackratPool` implements `PBFind` itself. Each find builder it returns checks
+//! a connection out of the pool when it is queried, and returns it once the
+//! query completes, so threads never hold on to a connection between queries.
+//!
+//! A transaction must run on a single connection, so adds and updates go
+//! through `PackratPool::get`, which checks a connection out of the pool for
+//! the lifetime of the returned `PooledPackratDb`.
+use crate::db::add::versionpins::ConflictPolicy;
+use crate::db::backend::Backend;
+use crate::db::config::PackratConfig;
+use crate::db::find::pins::{FindPins, FindPinsError, FindPinsRow};
+use crate::db::find::versionpin::{FindVersionPin, FindVersionPinError};
+use crate::db::find::versionpins::{FindVersionPins, FindVersionPinsRow};
+use crate::db::find::withs::{FindWiths, FindWithsError, FindWithsRow};
+use crate::db::find_all::changes::{FindAllChanges, FindAllChangesError, FindAllChangesRow};
+use crate::db::find_all::distributions::{
+    FindAllDistributions, FindAllDistributionsError, FindAllDistributionsRow,
+};
+use crate::db::find_all::levels::{FindAllLevels, FindAllLevelsError, FindAllLevelsRow};
+use crate::db::find_all::packages::{FindAllPackages, FindAllPackagesError, FindAllPackagesRow};
+use crate::db::find_all::pkgcoords::{
+    FindAllPkgCoords, FindAllPkgCoordsError, FindAllPkgCoordsRow,
+};
+use crate::db::find_all::platforms::{
+    FindAllPlatforms, FindAllPlatformsError, FindAllPlatformsRow,
+};
+use crate::db::find_all::revisions::{
+    FindAllRevisions, FindAllRevisionsError, FindAllRevisionsRow,
+};
+use crate::db::find_all::roles::{FindAllRoles, FindAllRolesError, FindAllRolesRow};
+use crate::db::find_all::sites::{FindAllSites, FindAllSitesError, FindAllSitesRow};
+use crate::db::find_all::versionpin_withs::{FindAllWiths, FindAllWithsError, FindAllWithsRow};
+use crate::db::find_all::versionpins::{
+    FindAllVersionPins, FindAllVersionPinsError, FindAllVersionPinsRow,
+};
+use crate::db::migrate::MigrateError;
+use crate::db::traits::{PBAdd, PBDelete, PBFind, PBUpdate};
+use crate::db::{add, delete, find, find_all, update};
+use crate::packrat::PackratDb;
+use crate::types::IdType;
+use postgres::{NoTls, Transaction};
+use r2d2::{Pool, PooledConnection};
+use r2d2_postgres::PostgresConnectionManager;
+use snafu::{ResultExt, Snafu};
+use std::fmt;
+
+/// Error type returned by the PackratPool
+#[derive(Debug, Snafu)]
+pub enum PackratPoolError {
+    /// Unable to create the pool, or to check a connection out of it
+    #[snafu(display("Pool Error: {} {}", msg, source))]
+    PoolError {
+        msg: &'static str,
+        source: r2d2::Error,
+    },
+    /// When constructing a query, postgres has thrown an error
+    #[snafu(display("Postgres Error: {} {}", msg, source))]
+    TokioPostgresError {
+        msg: &'static str,
+        source: tokio_postgres::error::Error,
+    },
+    /// The database schema is not the one expected by this version of packybara
+    #[snafu(display("Schema Error: {}", source))]
+    SchemaVersionError { source: MigrateError },
+}
+
+pub type PackratPoolResult<T, E = PackratPoolError> = std::result::Result<T, E>;
+
+type ConnectionManager = PostgresConnectionManager<NoTls>;
+
+/// A thread safe pool of connections to the packrat database
+#[derive(Clone)]
+pub struct PackratPool {
+    pool: Pool<ConnectionManager>,
+}
+
+impl fmt::Debug for PackratPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PackratPool({:?})", self.pool.state())
+    }
+}
+
+impl PackratPool {
+    /// New up a PackratPool, holding at most `config.pool_size` connections,
+    /// after checking the database schema version as `PackratConfig::connect` does.
+    ///
+    /// # Arguments
+    /// * `config` - The PackratConfig used to connect to the database
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - PackratPool
+    ///   * `Err` - PackratPoolError
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use packybara::db::config::PackratConfig;
+    /// use packybara::db::pool::PackratPool;
+    /// use packybara::traits::PBFind;
+    /// # fn run() -> Result<(), Box<dyn std::error::Error>> {
+    /// let pool = PackratPool::new(&PackratConfig::load()?)?;
+    /// let handles = (0..4)
+    ///     .map(|_| {
+    ///         let mut pool = pool.clone();
+    ///         std::thread::spawn(move || {
+    ///             pool.find_versionpin("maya").level("dev01").query().is_ok()
+    ///         })
+    ///     })
+    ///     .collect::<Vec<_>>();
+    /// for handle in handles {
+    ///     handle.join().unwrap();
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new(config: &PackratConfig) -> PackratPoolResult<Self> {
+        let manager = PostgresConnectionManager::new(config.postgres_config(), NoTls);
+        let pool = Pool::builder()
+            .max_size(config.pool_size)
+            .build(manager)
+            .context(PoolError {
+                msg: "unable to create pool",
+            })?;
+        let mut conn = pool.get().context(PoolError {
+            msg: "unable to check out connection",
+        })?;
+        config
+            .check_schema_version(&mut conn)
+            .context(SchemaVersionError)?;
+        Ok(Self { pool })
+    }
+
+    /// Check a connection out of the pool, blocking until one is available
+    /// or the pool's connection timeout elapses. The connection is held until
+    /// the PooledPackratDb is dropped, so use it for transactions, and query
+    /// the pool itself otherwise.
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - PooledPackratDb
+    ///   * `Err` - PackratPoolError
+    pub fn get(&self) -> PackratPoolResult<PooledPackratDb> {
+        let conn = self.pool.get().context(PoolError {
+            msg: "unable to check out connection",
+        })?;
+        Ok(PooledPackratDb { conn })
+    }
+
+    // check a connection out of the pool for the duration of a query
+    pub(crate) fn connection(&self) -> Result<PooledConnection<ConnectionManager>, r2d2::Error> {
+        self.pool.get()
+    }
+}
+
+/// A connection checked out of a PackratPool. The connection is returned
+/// to the pool when the PooledPackratDb is dropped.
+pub struct PooledPackratDb {
+    conn: PooledConnection<ConnectionManager>,
+}
+
+impl fmt::Debug for PooledPackratDb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PooledPackratDb")
+    }
+}
+
+impl PooledPackratDb {
+    /// Retrieve a PackratDb over the pooled connection, through which all of
+    /// the finds and the rest of PackratDb's api run on that one connection
+    pub fn packrat(&mut self) -> PackratDb<'_> {
+        PackratDb::new(&mut self.conn)
+    }
+
+    /// Generate a transaction for updates and adds
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - Transaction
+    ///   * `Err` - PackratPoolError
+    pub fn transaction(&mut self) -> PackratPoolResult<Transaction<'_>> {
+        self.conn.transaction().context(TokioPostgresError {
+            msg: "unable to start transaction",
+        })
+    }
+}
+
+// Each find checks a connection out of the pool for the duration of its query
+impl Backend for PackratPool {
+    fn find_versionpin(
+        find: &mut FindVersionPin<'_, Self>,
+    ) -> Result<FindVersionPinsRow, FindVersionPinError> {
+        find.query_pool()
+    }
+
+    fn find_versionpins(
+        find: &mut FindVersionPins<'_, Self>,
+    ) -> Result<Vec<FindVersionPinsRow>, Box<dyn std::error::Error>> {
+        find.query_pool()
+    }
+
+    fn find_all_versionpins(
+        find: &mut FindAllVersionPins<'_, Self>,
+    ) -> Result<Vec<FindAllVersionPinsRow>, FindAllVersionPinsError> {
+        find.query_pool()
+    }
+
+    fn find_all_roles(
+        find: &mut FindAllRoles<'_, Self>,
+    ) -> Result<Vec<FindAllRolesRow>, FindAllRolesError> {
+        find.query_pool()
+    }
+
+    fn find_all_revisions(
+        find: &mut FindAllRevisions<'_, Self>,
+    ) -> Result<Vec<FindAllRevisionsRow>, FindAllRevisionsError> {
+        find.query_pool()
+    }
+
+    fn find_all_changes(
+        find: &mut FindAllChanges<'_, Self>,
+    ) -> Result<Vec<FindAllChangesRow>, FindAllChangesError> {
+        find.query_pool()
+    }
+
+    fn find_all_platforms(
+        find: &mut FindAllPlatforms<'_, Self>,
+    ) -> Result<Vec<FindAllPlatformsRow>, FindAllPlatformsError> {
+        find.query_pool()
+    }
+
+    fn find_all_sites(
+        find: &mut FindAllSites<'_, Self>,
+    ) -> Result<Vec<FindAllSitesRow>, FindAllSitesError> {
+        find.query_pool()
+    }
+
+    fn find_all_levels(
+        find: &mut FindAllLevels<'_, Self>,
+    ) -> Result<Vec<FindAllLevelsRow>, FindAllLevelsError> {
+        find.query_pool()
+    }
+
+    fn find_all_packages(
+        find: &mut FindAllPackages<'_, Self>,
+    ) -> Result<Vec<FindAllPackagesRow>, FindAllPackagesError> {
+        find.query_pool()
+    }
+
+    fn find_all_versionpin_withs(
+        find: &mut FindAllWiths<'_, Self>,
+    ) -> Result<Vec<FindAllWithsRow>, FindAllWithsError> {
+        find.query_pool()
+    }
+
+    fn find_all_distributions(
+        find: &mut FindAllDistributions<'_, Self>,
+    ) -> Result<Vec<FindAllDistributionsRow>, FindAllDistributionsError> {
+        find.query_pool()
+    }
+
+    fn find_pins(find: &mut FindPins<'_, Self>) -> Result<Vec<FindPinsRow>, FindPinsError> {
+        find.query_pool()
+    }
+
+    fn find_pkgcoords(
+        find: &mut FindAllPkgCoords<'_, Self>,
+    ) -> Result<Vec<FindAllPkgCoordsRow>, FindAllPkgCoordsError> {
+        find.query_pool()
+    }
+
+    fn find_withs(find: &mut FindWiths<'_, Self>) -> Result<Vec<FindWithsRow>, FindWithsError> {
+        find.query_pool()
+    }
+}
+
+impl PBFind for PackratPool {
+    type Client = PackratPool;
+
+    fn find_versionpin<'b>(
+        &'b mut self,
+        package: &'b str,
+    ) -> find::versionpin::FindVersionPin<'b, Self::Client> {
+        find::versionpin::FindVersionPin::new(self, package)
+    }
+
+    fn find_versionpins<'b>(
+        &'b mut self,
+        package: &'b str,
+    ) -> find::versionpins::FindVersionPins<'b, Self::Client> {
+        find::versionpins::FindVersionPins::new(self, package)
+    }
+
+    fn find_all_versionpins<'b>(
+        &'b mut self,
+    ) -> find_all::versionpins::FindAllVersionPins<'b, Self::Client> {
+        find_all::versionpins::FindAllVersionPins::new(self)
+    }
+
+    fn find_all_roles<'b>(&'b mut self) -> find_all::roles::FindAllRoles<'b, Self::Client> {
+        find_all::roles::FindAllRoles::new(self)
+    }
+
+    fn find_all_revisions<'b>(
+        &'b mut self,
+    ) -> find_all::revisions::FindAllRevisions<'b, Self::Client> {
+        find_all::revisions::FindAllRevisions::new(self)
+    }
+
+    fn find_all_changes<'b>(&'b mut self) -> find_all::changes::FindAllChanges<'b, Self::Client> {
+        find_all::changes::FindAllChanges::new(self)
+    }
+
+    fn find_all_platforms<'b>(
+        &'b mut self,
+    ) -> find_all::platforms::FindAllPlatforms<'b, Self::Client> {
+        find_all::platforms::FindAllPlatforms::new(self)
+    }
+
+    fn find_all_sites<'b>(&'b mut self) -> find_all::sites::FindAllSites<'b, Self::Client> {
+        find_all::sites::FindAllSites::new(self)
+    }
+
+    fn find_all_levels<'b>(&'b mut self) -> find_all::levels::FindAllLevels<'b, Self::Client> {
+        find_all::levels::FindAllLevels::new(self)
+    }
+
+    fn find_all_packages<'b>(
+        &'b mut self,
+    ) -> find_all::packages::FindAllPackages<'b, Self::Client> {
+        find_all::packages::FindAllPackages::new(self)
+    }
+
+    fn find_all_versionpin_withs<'b>(
+        &'b mut self,
+        versionpin_id: IdType,
+    ) -> find_all::versionpin_withs::FindAllWiths<'b, Self::Client> {
+        find_all::versionpin_withs::FindAllWiths::new(self, versionpin_id)
+    }
+
+    fn find_all_distributions<'b>(
+        &'b mut self,
+    ) -> find_all::distributions::FindAllDistributions<'b, Self::Client> {
+        find_all::distributions::FindAllDistributions::new(self)
+    }
+
+    fn find_pins<'b>(&'b mut self) -> find::pins::FindPins<'b, Self::Client> {
+        find::pins::FindPins::new(self)
+    }
+
+    fn find_pkgcoords<'b>(&'b mut self) -> find_all::pkgcoords::FindAllPkgCoords<'b, Self::Client> {
+        find_all::pkgcoords::FindAllPkgCoords::with_client(Some(self))
+    }
+
+    fn find_withs<'b>(&'b mut self, package: &'b str) -> find::withs::FindWiths<'b, Self::Client> {
+        find::withs::FindWiths::new(self, package)
+    }
+}
+
+// The builders take a transaction on a connection checked out with
+// `PackratPool::get`, and are those of PackratDb
+impl<'b> PBAdd<'b> for PackratPool {
+    type TransactionType = Transaction<'b>;
+
+    fn add_packages(
+        tx: Self::TransactionType,
+    ) -> add::packages::AddPackages<'b, Self::TransactionType> {
+        PackratDb::add_packages(tx)
+    }
+
+    fn add_levels(tx: Self::TransactionType) -> add::levels::AddLevels<'b, Self::TransactionType> {
+        PackratDb::add_levels(tx)
+    }
+
+    fn add_roles(tx: Self::TransactionType) -> add::roles::AddRoles<'b, Self::TransactionType> {
+        PackratDb::add_roles(tx)
+    }
+
+    fn add_platforms(
+        tx: Self::TransactionType,
+    ) -> add::platforms::AddPlatforms<'b, Self::TransactionType> {
+        PackratDb::add_platforms(tx)
+    }
+
+    fn add_sites(tx: Self::TransactionType) -> add::sites::AddSites<'b, Self::TransactionType> {
+        PackratDb::add_sites(tx)
+    }
+
+    fn add_distributions(
+        tx: Self::TransactionType,
+    ) -> add::distributions::AddDistributions<'b, Self::TransactionType> {
+        PackratDb::add_distributions(tx)
+    }
+
+    fn add_withs(tx: Self::TransactionType) -> add::withs::AddWiths<'b, Self::TransactionType> {
+        PackratDb::add_withs(tx)
+    }
+
+    fn add_versionpins<I>(
+        tx: Self::TransactionType,
+        package: I,
+        version: I,
+        policy: ConflictPolicy,
+    ) -> add::versionpins::AddVersionPins<'b, Self::TransactionType>
+    where
+        I: Into<String>,
+    {
+        PackratDb::add_versionpins(tx, package, version, policy)
+    }
+}
+
+impl<'a> PBUpdate<'a> for PackratPool {
+    type TransactionType = Transaction<'a>;
+
+    fn update_versionpins(
+        tx: Self::TransactionType,
+    ) -> update::versionpins::UpdateVersionPins<'a, Self::TransactionType> {
+        PackratDb::update_versionpins(tx)
+    }
+}
+
+impl<'a> PBDelete<'a> for PackratPool {
+    type TransactionType = Transaction<'a>;
+
+    fn delete_versionpins(
+        tx: Self::TransactionType,
+    ) -> delete::versionpins::DeleteVersionPins<'a, Self::TransactionType> {
+        PackratDb::delete_versionpins(tx)
+    }
+
+    fn delete_withs(
+        tx: Self::TransactionType,
+    ) -> delete::withs::DeleteWiths<'a, Self::TransactionType> {
+        PackratDb::delete_withs(tx)
+    }
+
+    fn delete_distributions(
+        tx: Self::TransactionType,
+    ) -> delete::distributions::DeleteDistributions<'a, Self::TransactionType> {
+        PackratDb::delete_distributions(tx)
+    }
+
+    fn delete_packages(
+        tx: Self::TransactionType,
+    ) -> delete::packages::DeletePackages<'a, Self::TransactionType> {
+        PackratDb::delete_packages(tx)
+    }
+
+    fn delete_levels(
+        tx: Self::TransactionType,
+    ) -> delete::levels::DeleteLevels<'a, Self::TransactionType> {
+        PackratDb::delete_levels(tx)
+    }
+
+    fn delete_roles(
+        tx: Self::TransactionType,
+    ) -> delete::roles::DeleteRoles<'a, Self::TransactionType> {
+        PackratDb::delete_roles(tx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn pool_is_send_and_sync() {
+        assert_send_sync::<PackratPool>();
+    }
+}
@@ -0,0 +1,160 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! A query string paired with its owned parameters. The builders prepare their
+//! sql once, and hand the result to either the blocking `postgres` client or the
+//! async `tokio_postgres` client, so that both flavors stay in lockstep.
+use crate::db::async_packrat::{AsyncClient, AsyncTransaction};
+use log;
+use postgres::types::ToSql;
+use postgres::{Client, Row, RowIter, Transaction};
+
+/// The owned parameters of a prepared statement
+pub type Params = Vec<Box<dyn ToSql + Sync + Send>>;
+
+/// A sql statement along with the parameters it is to be executed with
+#[derive(Debug)]
+pub struct Prepared {
+    pub sql: String,
+    pub params: Params,
+}
+
+impl Prepared {
+    /// New up a Prepared instance without parameters
+    ///
+    /// # Arguments
+    /// * `sql` - The sql statement
+    ///
+    /// # Returns
+    /// * Prepared instance
+    pub fn new<I: Into<String>>(sql: I) -> Self {
+        Self {
+            sql: sql.into(),
+            params: Vec::new(),
+        }
+    }
+
+    /// Append a parameter, returning its placeholder index (eg 1 for `$1`)
+    ///
+    /// # Arguments
+    /// * `param` - The parameter
+    ///
+    /// # Returns
+    /// * The index of the parameter's placeholder
+    pub fn push<T: ToSql + Sync + Send + 'static>(&mut self, param: T) -> usize {
+        self.params.push(Box::new(param));
+        self.params.len()
+    }
+
+    /// Retrieve the parameters in the form expected by the clients
+    pub fn params(&self) -> Vec<&(dyn ToSql + Sync)> {
+        self.params
+            .iter()
+            .map(|param| param.as_ref() as &(dyn ToSql + Sync))
+            .collect()
+    }
+
+    fn log(&self) {
+        log::info!("SQL\n{}", self.sql.as_str());
+        log::info!("Arguments\n{:?}", &self.params);
+    }
+
+    /// Execute the query using the blocking client
+    pub(crate) fn query(&self, client: &mut Client) -> Result<Vec<Row>, tokio_postgres::Error> {
+        self.log();
+        client.query(self.sql.as_str(), &self.params()[..])
+    }
+
+    /// Execute the query using the blocking client, streaming the rows
+    /// rather than collecting them
+    pub(crate) fn query_raw<'c>(
+        &self,
+        client: &'c mut Client,
+    ) -> Result<RowIter<'c>, tokio_postgres::Error> {
+        self.log();
+        client.query_raw(
+            self.sql.as_str(),
+            self.params.iter().map(|param| param.as_ref() as &dyn ToSql),
+        )
+    }
+
+    /// Execute the query using the async client
+    pub(crate) async fn query_async(
+        &self,
+        client: &AsyncClient,
+    ) -> Result<Vec<Row>, tokio_postgres::Error> {
+        self.log();
+        client.query(self.sql.as_str(), &self.params()[..]).await
+    }
+
+    /// Execute the query within a blocking transaction
+    pub(crate) fn query_tx(
+        &self,
+        tx: &mut Transaction<'_>,
+    ) -> Result<Vec<Row>, tokio_postgres::Error> {
+        self.log();
+        tx.query(self.sql.as_str(), &self.params()[..])
+    }
+
+    /// Execute the query within an async transaction
+    pub(crate) async fn query_tx_async(
+        &self,
+        tx: &AsyncTransaction<'_>,
+    ) -> Result<Vec<Row>, tokio_postgres::Error> {
+        self.log();
+        tx.query(self.sql.as_str(), &self.params()[..]).await
+    }
+
+    /// Execute the query within a blocking transaction, which must return
+    /// exactly one row
+    pub(crate) fn query_one_tx(
+        &self,
+        tx: &mut Transaction<'_>,
+    ) -> Result<Row, tokio_postgres::Error> {
+        self.log();
+        tx.query_one(self.sql.as_str(), &self.params()[..])
+    }
+
+    /// Execute the query within an async transaction, which must return
+    /// exactly one row
+    pub(crate) async fn query_one_tx_async(
+        &self,
+        tx: &AsyncTransaction<'_>,
+    ) -> Result<Row, tokio_postgres::Error> {
+        self.log();
+        tx.query_one(self.sql.as_str(), &self.params()[..]).await
+    }
+
+    /// Execute the statement within a blocking transaction
+    pub(crate) fn execute(&self, tx: &mut Transaction<'_>) -> Result<u64, tokio_postgres::Error> {
+        self.log();
+        tx.execute(self.sql.as_str(), &self.params()[..])
+    }
+
+    /// Execute the statement within an async transaction
+    pub(crate) async fn execute_async(
+        &self,
+        tx: &AsyncTransaction<'_>,
+    ) -> Result<u64, tokio_postgres::Error> {
+        self.log();
+        tx.execute(self.sql.as_str(), &self.params()[..]).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_returns_placeholder_index() {
+        let mut prepared = Prepared::new("SELECT name FROM site_view WHERE name = $1");
+        assert_eq!(prepared.push("portland".to_string()), 1);
+        assert_eq!(prepared.push(2), 2);
+        assert_eq!(prepared.params().len(), 2);
+    }
+}
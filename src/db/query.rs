@@ -0,0 +1,414 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! A typed builder for the select statements issued by the find builders.
+//!
+//! Filters are rendered via `SearchMode::search_clause`, joined with WHERE
+//! and AND as appropriate, and the parameters are numbered in the order they
+//! are bound. `to_sql` returns the statement and its parameters, so that the
+//! generated sql may be inspected without a database, and a `SelectQuery`
+//! converts into the `Prepared` statement handed to the clients.
+//!
+//! ```
+//! use packybara::db::query::SelectQuery;
+//! use packybara::SearchMode;
+//!
+//! let mut query = SelectQuery::new(["name", "show"], "level_view");
+//! query
+//!     .distinct()
+//!     .filter("show", &SearchMode::Equal, "dev01")
+//!     .order_by(["name"]);
+//! let (sql, params) = query.to_sql();
+//! assert_eq!(
+//!     sql,
+//!     "SELECT DISTINCT name, show FROM level_view WHERE show = $1 ORDER BY name"
+//! );
+//! assert_eq!(params, &["dev01"]);
+//! ```
+use crate::db::prepared::Prepared;
+use crate::db::search_attribute::{JoinMode, OrderDirection, SearchMode};
+use crate::types::{IdType, LongIdType};
+use chrono::{DateTime, Local};
+use std::fmt;
+
+/// A parameter bound to a SelectQuery
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryParam {
+    Text(String),
+    Int(IdType),
+    BigInt(LongIdType),
+    DateTime(DateTime<Local>),
+}
+
+impl fmt::Display for QueryParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Text(value) => write!(f, "{}", value),
+            Self::Int(value) => write!(f, "{}", value),
+            Self::BigInt(value) => write!(f, "{}", value),
+            Self::DateTime(value) => write!(f, "{}", value.to_rfc3339()),
+        }
+    }
+}
+
+impl From<String> for QueryParam {
+    fn from(value: String) -> Self {
+        Self::Text(value)
+    }
+}
+
+impl From<&str> for QueryParam {
+    fn from(value: &str) -> Self {
+        Self::Text(value.to_string())
+    }
+}
+
+impl From<IdType> for QueryParam {
+    fn from(value: IdType) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<LongIdType> for QueryParam {
+    fn from(value: LongIdType) -> Self {
+        Self::BigInt(value)
+    }
+}
+
+impl From<DateTime<Local>> for QueryParam {
+    fn from(value: DateTime<Local>) -> Self {
+        Self::DateTime(value)
+    }
+}
+
+impl PartialEq<&str> for QueryParam {
+    fn eq(&self, other: &&str) -> bool {
+        match self {
+            Self::Text(value) => value == other,
+            _ => false,
+        }
+    }
+}
+
+/// A select statement, built up from columns, filters, ordering and a
+/// limit. The source may be a table or view, or a set returning function
+/// whose arguments are bound as parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectQuery {
+    distinct: bool,
+    columns: Vec<String>,
+    from: String,
+    args: Option<Vec<String>>,
+    clauses: Vec<String>,
+    order_by: Vec<String>,
+    order_direction: Option<OrderDirection>,
+    limit: Option<IdType>,
+    params: Vec<QueryParam>,
+}
+
+impl SelectQuery {
+    /// New up a SelectQuery, selecting from a table or view
+    ///
+    /// # Arguments
+    /// * `columns` - The columns to select
+    /// * `from` - The name of the table or view
+    ///
+    /// # Returns
+    /// * SelectQuery instance
+    pub fn new<I, S>(columns: I, from: &str) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self {
+            distinct: false,
+            columns: columns
+                .into_iter()
+                .map(|column| column.as_ref().to_string())
+                .collect(),
+            from: from.to_string(),
+            args: None,
+            clauses: Vec::new(),
+            order_by: Vec::new(),
+            order_direction: None,
+            limit: None,
+            params: Vec::new(),
+        }
+    }
+
+    /// New up a SelectQuery, selecting from a set returning function. Its
+    /// arguments are supplied via `arg` and `named_arg`.
+    ///
+    /// # Arguments
+    /// * `columns` - The columns to select
+    /// * `function` - The name of the function
+    ///
+    /// # Returns
+    /// * SelectQuery instance
+    pub fn function<I, S>(columns: I, function: &str) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut query = Self::new(columns, function);
+        query.args = Some(Vec::new());
+        query
+    }
+
+    /// Select distinct rows
+    ///
+    /// # Returns
+    /// * Mutable reference to self
+    pub fn distinct(&mut self) -> &mut Self {
+        self.distinct = true;
+        self
+    }
+
+    /// Bind a parameter, returning the index of its placeholder (eg 1 for
+    /// `$1`), for use in a clause supplied to `filter_sql`
+    ///
+    /// # Arguments
+    /// * `param` - The parameter
+    ///
+    /// # Returns
+    /// * The index of the parameter's placeholder
+    pub fn bind<P: Into<QueryParam>>(&mut self, param: P) -> IdType {
+        self.params.push(param.into());
+        self.params.len() as IdType
+    }
+
+    /// Supply the next positional argument of the function
+    ///
+    /// # Arguments
+    /// * `param` - The value of the argument
+    ///
+    /// # Returns
+    /// * Mutable reference to self
+    pub fn arg<P: Into<QueryParam>>(&mut self, param: P) -> &mut Self {
+        let cnt = self.bind(param);
+        self.push_arg(format!("${}", cnt))
+    }
+
+    /// Supply a named argument of the function
+    ///
+    /// # Arguments
+    /// * `name` - The name of the argument
+    /// * `param` - The value of the argument
+    ///
+    /// # Returns
+    /// * Mutable reference to self
+    pub fn named_arg<P: Into<QueryParam>>(&mut self, name: &str, param: P) -> &mut Self {
+        let cnt = self.bind(param);
+        self.push_arg(format!("{} => ${}", name, cnt))
+    }
+
+    fn push_arg(&mut self, arg: String) -> &mut Self {
+        self.args.get_or_insert_with(Vec::new).push(arg);
+        self
+    }
+
+    /// Filter the rows by comparing a column against a parameter
+    ///
+    /// # Arguments
+    /// * `column` - The column to compare
+    /// * `mode` - The comparison to perform
+    /// * `param` - The value to compare against
+    ///
+    /// # Returns
+    /// * Mutable reference to self
+    pub fn filter<P: Into<QueryParam>>(
+        &mut self,
+        column: &str,
+        mode: &SearchMode,
+        param: P,
+    ) -> &mut Self {
+        let cnt = self.bind(param);
+        let clause = SearchMode::search_clause(self.join_mode(), column, mode, cnt);
+        self.clauses.push(clause);
+        self
+    }
+
+    /// Filter the rows by an arbitrary predicate. Parameters referenced by
+    /// the predicate are supplied via `bind`.
+    ///
+    /// # Arguments
+    /// * `predicate` - The predicate, eg `name <> 'any'`
+    ///
+    /// # Returns
+    /// * Mutable reference to self
+    pub fn filter_sql<S: AsRef<str>>(&mut self, predicate: S) -> &mut Self {
+        let clause = format!(" {} {}", self.join_mode(), predicate.as_ref());
+        self.clauses.push(clause);
+        self
+    }
+
+    fn join_mode(&self) -> JoinMode {
+        if self.clauses.is_empty() {
+            JoinMode::Where
+        } else {
+            JoinMode::And
+        }
+    }
+
+    /// Order the rows by the supplied columns, after any columns supplied
+    /// previously
+    ///
+    /// # Arguments
+    /// * `columns` - The columns to order by
+    ///
+    /// # Returns
+    /// * Mutable reference to self
+    pub fn order_by<I, S>(&mut self, columns: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.order_by.extend(
+            columns
+                .into_iter()
+                .map(|column| column.as_ref().to_string()),
+        );
+        self
+    }
+
+    /// Set the direction of each of the order by columns. Without
+    /// one, the database's default (ascending) applies.
+    ///
+    /// # Arguments
+    /// * `direction` - The direction, if any
+    ///
+    /// # Returns
+    /// * Mutable reference to self
+    pub fn order_direction(&mut self, direction: Option<OrderDirection>) -> &mut Self {
+        self.order_direction = direction;
+        self
+    }
+
+    /// Limit the number of rows returned. Limits less than 1 are ignored.
+    ///
+    /// # Arguments
+    /// * `limit` - The maximum number of rows, if any
+    ///
+    /// # Returns
+    /// * Mutable reference to self
+    pub fn limit(&mut self, limit: Option<IdType>) -> &mut Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Render the statement
+    ///
+    /// # Returns
+    /// * Tuple of
+    ///   * The sql statement
+    ///   * The parameters, in placeholder order
+    pub fn to_sql(&self) -> (String, &[QueryParam]) {
+        let mut sql = String::from("SELECT ");
+        if self.distinct {
+            sql.push_str("DISTINCT ");
+        }
+        sql.push_str(&self.columns.join(", "));
+        sql.push_str(" FROM ");
+        sql.push_str(&self.from);
+        if let Some(ref args) = self.args {
+            sql.push_str(&format!("({})", args.join(", ")));
+        }
+        for clause in &self.clauses {
+            sql.push_str(clause);
+        }
+        if !self.order_by.is_empty() {
+            let orderby = match self.order_direction {
+                Some(ref direction) => self
+                    .order_by
+                    .iter()
+                    .map(|column| format!("{} {}", column, direction))
+                    .collect::<Vec<_>>(),
+                None => self.order_by.clone(),
+            };
+            sql.push_str(&format!(" ORDER BY {}", orderby.join(", ")));
+        }
+        if let Some(limit) = self.limit {
+            if limit > 0 {
+                sql.push_str(&format!(" LIMIT {}", limit));
+            }
+        }
+        (sql, &self.params)
+    }
+}
+
+impl From<SelectQuery> for Prepared {
+    fn from(query: SelectQuery) -> Self {
+        let mut prepared = Prepared::new(query.to_sql().0);
+        for param in query.params {
+            match param {
+                QueryParam::Text(value) => prepared.push(value),
+                QueryParam::Int(value) => prepared.push(value),
+                QueryParam::BigInt(value) => prepared.push(value),
+                QueryParam::DateTime(value) => prepared.push(value),
+            };
+        }
+        prepared
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::search_attribute::LtreeSearchMode;
+
+    #[test]
+    fn joins_filters_with_where_and_and() {
+        let mut query = SelectQuery::new(["name"], "site_view");
+        query
+            .filter_sql("name <> 'any'")
+            .filter("name", &SearchMode::Like, "port%");
+        let (sql, params) = query.to_sql();
+        assert_eq!(
+            sql,
+            "SELECT name FROM site_view WHERE name <> 'any' AND name LIKE $1"
+        );
+        assert_eq!(params, &["port%"]);
+    }
+
+    #[test]
+    fn renders_function_arguments_and_ltree_filters() {
+        let mut query = SelectQuery::function(["id", "withs"], "findall_versionpins");
+        query
+            .named_arg("role", "any")
+            .named_arg("level", "facility")
+            .filter(
+                "level",
+                &SearchMode::Ltree(LtreeSearchMode::Descendant),
+                "facility.dev01",
+            )
+            .order_by(["distribution", "level"])
+            .order_direction(Some(OrderDirection::Desc))
+            .limit(Some(10));
+        let (sql, params) = query.to_sql();
+        assert_eq!(
+            sql,
+            "SELECT id, withs FROM findall_versionpins(role => $1, level => $2) \
+             WHERE text2ltree($3) @> level ORDER BY distribution DESC, level DESC LIMIT 10"
+        );
+        assert_eq!(params, &["any", "facility", "facility.dev01"]);
+    }
+
+    #[test]
+    fn binds_typed_parameters() {
+        let mut query = SelectQuery::new(["name"], "level_view");
+        let cnt = query.bind(3);
+        query
+            .filter_sql(format!("nlevel(path) = ${}", cnt))
+            .limit(Some(0));
+        let (sql, params) = query.to_sql();
+        assert_eq!(sql, "SELECT name FROM level_view WHERE nlevel(path) = $1");
+        assert_eq!(params, &[QueryParam::Int(3)]);
+        let prepared = Prepared::from(query);
+        assert_eq!(prepared.params().len(), 1);
+    }
+}
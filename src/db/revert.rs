@@ -0,0 +1,693 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! Revert a revision by applying the inverse of its changes, as recorded
+//! in the audit log, within a new revision:
+//!
+//! * inserted versionpins are removed
+//! * updated versionpins are restored to their old distribution
+//! * deleted versionpins are recreated with their old distribution
+//! * changed withs are restored to their old withs, in their old order
+//!
+//! If a later change, as ordered by audit event id, has touched any of the
+//! same versionpins, the revert is refused, unless forced.
+use crate::db::delete::{find_versionpin_id, remove_versionpins};
+use crate::db::find_all::changes::{
+    ChangeAction, ChangeType, FindAllChanges, FindAllChangesError, FindAllChangesRow,
+};
+use crate::db::prepared::Prepared;
+use crate::types::{IdType, LongIdType};
+use crate::{Coords, Distribution};
+use log;
+use postgres::{Client, Transaction};
+use snafu::{ResultExt, Snafu};
+use std::fmt;
+
+/// A versionpin touched by the revision being reverted, which has
+/// subsequently been changed by a later revision. Changes are ordered by
+/// their audit event id, as transaction ids need not increase with commit
+/// order.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RevertConflict {
+    /// The transaction id of the later revision
+    pub transaction_id: LongIdType,
+    pub package: String,
+    pub coords: Coords,
+}
+
+impl fmt::Display for RevertConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {} changed by transaction {}",
+            self.package, self.coords, self.transaction_id
+        )
+    }
+}
+
+/// Error type returned from RevertRevision
+#[derive(Debug, Snafu)]
+pub enum RevertRevisionError {
+    /// When constructing a query, postgres has thrown an error
+    #[snafu(display("Postgres Error: {} {:#?}", msg, source))]
+    TokioPostgresError {
+        msg: &'static str,
+        source: tokio_postgres::error::Error,
+    },
+    /// Unable to retrieve the changes of a revision
+    #[snafu(display(
+        "Problem retrieving changes for transaction {}: {}",
+        transaction_id,
+        source
+    ))]
+    ChangesError {
+        transaction_id: LongIdType,
+        source: FindAllChangesError,
+    },
+    /// The revision did not change any versionpins
+    #[snafu(display("No changes found for transaction {}", transaction_id))]
+    NoChangesError { transaction_id: LongIdType },
+    /// The change may not be inverted
+    #[snafu(display(
+        "Unable to revert transaction {}. {} (change {}) cannot be reverted",
+        transaction_id,
+        action,
+        id
+    ))]
+    IrreversibleChangeError {
+        transaction_id: LongIdType,
+        id: LongIdType,
+        action: ChangeAction,
+    },
+    /// Later revisions have changed the same versionpins
+    #[snafu(display(
+        "Unable to revert transaction {}. Later revisions have changed the same versionpins:\n{}",
+        transaction_id,
+        conflicts.iter().map(|c| format!("  {}", c)).collect::<Vec<_>>().join("\n")
+    ))]
+    ConflictError {
+        transaction_id: LongIdType,
+        conflicts: Vec<RevertConflict>,
+    },
+    /// The distribution to restore no longer exists
+    #[snafu(display("Distribution {} does not exist", distribution))]
+    DistributionNotFoundError { distribution: String },
+    /// The versionpin to remove or restore no longer exists
+    #[snafu(display("No versionpin found for {} at {}", package, coords))]
+    VersionPinNotFoundError { package: String, coords: String },
+}
+
+pub type RevertRevisionResult<T, E = RevertRevisionError> = std::result::Result<T, E>;
+
+/// Responsible for reverting a revision. Typically constructed via
+/// `PackratDb::revert_revision`.
+pub struct RevertRevision<'a> {
+    client: &'a mut Client,
+    transaction_id: LongIdType,
+    force: bool,
+}
+
+impl fmt::Debug for RevertRevision<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "RevertRevision(txid:{} force:{})",
+            self.transaction_id, self.force
+        )
+    }
+}
+
+impl<'a> RevertRevision<'a> {
+    /// new up a RevertRevision instance
+    ///
+    /// # Arguments
+    /// * `client` - A mutable reference to a postgres::Client
+    /// * `transaction_id` - The transaction id of the revision to revert
+    pub fn new(client: &'a mut Client, transaction_id: LongIdType) -> Self {
+        Self {
+            client,
+            transaction_id,
+            force: false,
+        }
+    }
+
+    /// Revert the revision even if later revisions have touched the
+    /// same versionpins. Versionpins which no longer exist are skipped.
+    ///
+    /// # Arguments
+    /// * `force` - Whether to ignore conflicts
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn force(&mut self, force: bool) -> &mut Self {
+        self.force = force;
+        self
+    }
+
+    // retrieve the changes made by the supplied transaction
+    fn changes(
+        &mut self,
+        transaction_id: LongIdType,
+    ) -> RevertRevisionResult<Vec<FindAllChangesRow>> {
+        FindAllChanges::new(self.client)
+            .transaction_id(transaction_id)
+            .query()
+            .context(ChangesError { transaction_id })
+    }
+
+    /// Report the versionpins changed by the revision which have subsequently
+    /// been changed by later revisions.
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - Vector of RevertConflict. Empty if there are none.
+    ///   * `Err` - RevertRevisionError
+    pub fn conflicts(&mut self) -> RevertRevisionResult<Vec<RevertConflict>> {
+        let changes = self.changes(self.transaction_id)?;
+        if changes.is_empty() {
+            return Ok(Vec::new());
+        }
+        // the names of the package and coords of each touched versionpin
+        let touched = changes
+            .iter()
+            .map(|change| (change_names(change), change_coords(change)))
+            .collect::<Vec<_>>();
+        // the last change made by the revision. Any change to the same
+        // versionpins recorded after it conflicts
+        let last_event = changes
+            .iter()
+            .map(|change| change.event_id)
+            .max()
+            .unwrap_or_default();
+        let rows = prepare_conflicts(last_event, &touched)
+            .query(self.client)
+            .context(TokioPostgresError {
+                msg: "problem with select from vpin_change_view",
+            })?;
+        let mut conflicts = Vec::new();
+        for row in rows {
+            let names = (1..6).map(|idx| row.get(idx)).collect::<Vec<String>>();
+            let coords = match touched.iter().find(|(touched, _)| *touched == names) {
+                Some((_, coords)) => coords.clone(),
+                None => continue,
+            };
+            let conflict = RevertConflict {
+                transaction_id: row.get(0),
+                package: names[0].clone(),
+                coords,
+            };
+            if !conflicts.contains(&conflict) {
+                conflicts.push(conflict);
+            }
+        }
+        Ok(conflicts)
+    }
+
+    /// Apply the inverse of the revision's changes in a new transaction, and
+    /// commit it with a comment referencing the reverted revision.
+    ///
+    /// # Arguments
+    /// * `author` - The author of the new revision
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - The number of changes reverted
+    ///   * `Err` - RevertRevisionError
+    pub fn revert(&mut self, author: &str) -> RevertRevisionResult<u64> {
+        let transaction_id = self.transaction_id;
+        let changes = self.changes(transaction_id)?;
+        if changes.is_empty() {
+            return NoChangesError { transaction_id }.fail();
+        }
+        for change in &changes {
+            if change.action == ChangeAction::Truncate {
+                return IrreversibleChangeError {
+                    transaction_id,
+                    id: change.id,
+                    action: change.action.clone(),
+                }
+                .fail();
+            }
+        }
+        if !self.force {
+            refuse_conflicts(transaction_id, self.conflicts()?)?;
+        }
+        let force = self.force;
+        let mut tx = self.client.transaction().context(TokioPostgresError {
+            msg: "failed to create transaction",
+        })?;
+        let mut cnt = 0;
+        for (change, inverse) in inverses(&changes) {
+            if revert_change(&mut tx, change, inverse, force)? {
+                cnt += 1;
+            }
+        }
+        let comment = format!("Revert of revision with transaction id {}", transaction_id);
+        tx.execute(
+            "INSERT INTO REVISION (author, comment) VALUES ($1, $2)",
+            &[&author, &comment],
+        )
+        .context(TokioPostgresError {
+            msg: "failed to insert Revisions",
+        })?;
+        tx.commit().context(TokioPostgresError {
+            msg: "failed to commit transaction",
+        })?;
+        Ok(cnt)
+    }
+}
+
+// Look up the id of the versionpin affected by a change. When forced, a
+// versionpin which no longer exists is None.
+fn changed_versionpin_id(
+    tx: &mut Transaction<'_>,
+    package: &str,
+    coords: &Coords,
+    force: bool,
+) -> RevertRevisionResult<Option<IdType>> {
+    match find_versionpin_id(tx, package, coords).context(TokioPostgresError {
+        msg: "failed to look up versionpin",
+    })? {
+        Some(id) => Ok(Some(id)),
+        None if force => Ok(None),
+        None => VersionPinNotFoundError {
+            package,
+            coords: coords.to_string(),
+        }
+        .fail(),
+    }
+}
+
+// Restore the withs of a versionpin, in order
+fn revert_withs(
+    tx: &mut Transaction<'_>,
+    change: &FindAllChangesRow,
+    old: &[String],
+    force: bool,
+) -> RevertRevisionResult<bool> {
+    let coords = change_coords(change);
+    let versionpin_id = match changed_versionpin_id(tx, &change.package, &coords, force)? {
+        Some(id) => id,
+        None => return Ok(false),
+    };
+    for prepared in prepare_withs(versionpin_id, old) {
+        prepared.execute(tx).context(TokioPostgresError {
+            msg: "failed to restore withs",
+        })?;
+    }
+    Ok(true)
+}
+
+// The coords of the versionpin affected by a change
+fn change_coords(change: &FindAllChangesRow) -> Coords {
+    Coords::from_parts(
+        change.level.clone(),
+        change.role.clone(),
+        change.platform.clone(),
+        change.site.clone(),
+    )
+}
+
+// The names of the package, level, role, platform and site of the versionpin
+// affected by a change, as they appear in vpin_change_view
+fn change_names(change: &FindAllChangesRow) -> Vec<String> {
+    vec![
+        change.package.clone(),
+        change.level.to_string(),
+        change.role.to_string(),
+        change.platform.to_string(),
+        change.site.to_string(),
+    ]
+}
+
+// Look up the id of a distribution
+fn find_distribution_id(
+    tx: &mut Transaction<'_>,
+    distribution: &Distribution,
+) -> RevertRevisionResult<IdType> {
+    let query_str =
+        "SELECT distribution_id FROM distribution_view WHERE package = $1 AND version_name = $2";
+    log::info!("SQL\n{}", query_str);
+    log::info!("Prepared\n{:?}", distribution);
+    let rows = tx
+        .query(
+            query_str,
+            &[&distribution.package(), &distribution.version()],
+        )
+        .context(TokioPostgresError {
+            msg: "failed to look up distribution",
+        })?;
+    match rows.first() {
+        Some(row) => Ok(row.get(0)),
+        None => DistributionNotFoundError {
+            distribution: distribution.distribution(),
+        }
+        .fail(),
+    }
+}
+
+// The inverse of a change to a versionpin
+#[derive(Debug, PartialEq, Eq)]
+enum Inverse<'c> {
+    /// remove the versionpin which was inserted
+    Remove,
+    /// restore the old distribution of the versionpin which was updated
+    Restore(&'c Distribution),
+    /// recreate the versionpin which was deleted, with its old distribution
+    Recreate(&'c Distribution),
+    /// restore the old withs of the versionpin, in order
+    Withs(&'c [String]),
+    /// without an old distribution there is nothing to restore
+    Skip,
+}
+
+// The inverse of a single change
+fn inverse(change: &FindAllChangesRow) -> Inverse<'_> {
+    match (&change.action, &change.change) {
+        (_, ChangeType::Withs { old, .. }) => Inverse::Withs(old),
+        (ChangeAction::Insert, _) => Inverse::Remove,
+        (ChangeAction::Delete, ChangeType::Distribution { old: Some(old), .. }) => {
+            Inverse::Recreate(old)
+        }
+        (_, ChangeType::Distribution { old: Some(old), .. }) => Inverse::Restore(old),
+        (_, ChangeType::Distribution { old: None, .. }) => Inverse::Skip,
+    }
+}
+
+// The inverse of each change, in the opposite order to which the changes
+// were made. A deleted versionpin is thus recreated before its withs, which
+// were deleted ahead of it, are restored.
+fn inverses(changes: &[FindAllChangesRow]) -> Vec<(&FindAllChangesRow, Inverse<'_>)> {
+    let mut changes = changes.iter().collect::<Vec<_>>();
+    changes.sort_by_key(|change| change.event_id);
+    changes
+        .into_iter()
+        .rev()
+        .map(|change| (change, inverse(change)))
+        .collect()
+}
+
+// Refuse to revert a revision whose versionpins have since been changed
+fn refuse_conflicts(
+    transaction_id: LongIdType,
+    conflicts: Vec<RevertConflict>,
+) -> RevertRevisionResult<()> {
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+    ConflictError {
+        transaction_id,
+        conflicts,
+    }
+    .fail()
+}
+
+// Select the changes to the touched versionpins recorded after `last_event`,
+// in the order in which they were made
+fn prepare_conflicts(last_event: LongIdType, touched: &[(Vec<String>, Coords)]) -> Prepared {
+    let mut prepared = Prepared::new(
+        "SELECT transaction_id, package, level_name, role_name, platform_name, site_name
+            FROM vpin_change_view
+            WHERE event_id > $1
+                AND (package, level_name, role_name, platform_name, site_name) IN (
+                    SELECT * FROM unnest($2::text[], $3::text[], $4::text[], $5::text[], $6::text[]))
+            ORDER BY event_id",
+    );
+    prepared.push(last_event);
+    for idx in 0..5 {
+        prepared.push(
+            touched
+                .iter()
+                .map(|(names, _)| names[idx].clone())
+                .collect::<Vec<_>>(),
+        );
+    }
+    prepared
+}
+
+// Build the statement recreating a deleted versionpin
+fn prepare_recreate(old: &Distribution, coords: &Coords) -> Prepared {
+    let mut prepared = Prepared::new("SELECT * from INSERT_VERSIONPIN($1, level_n => $2, site_n => $3, role_n => $4, platform_n => $5)");
+    prepared.push(old.distribution());
+    prepared.push(coords.level.to_string());
+    prepared.push(coords.site.to_string());
+    prepared.push(coords.role.to_string());
+    prepared.push(coords.platform.to_string());
+    prepared
+}
+
+// Build the statement restoring the distribution of an updated versionpin
+fn prepare_restore(distribution_id: IdType, versionpin_id: IdType) -> Prepared {
+    let mut prepared = Prepared::new("UPDATE versionpin SET distribution = $1 WHERE id = $2");
+    prepared.push(distribution_id);
+    prepared.push(versionpin_id);
+    prepared
+}
+
+// Build the statements replacing the withs of a versionpin with the old
+// withs, in order
+fn prepare_withs(versionpin_id: IdType, old: &[String]) -> Vec<Prepared> {
+    let mut delete = Prepared::new("DELETE FROM withpackage WHERE versionpin = $1");
+    delete.push(versionpin_id);
+    let mut statements = vec![delete];
+    for (pinorder, with) in old.iter().enumerate() {
+        let mut insert = Prepared::new(
+            "INSERT INTO withpackage (versionpin, package, pinorder) values ($1,$2,$3)",
+        );
+        insert.push(versionpin_id);
+        insert.push(with.clone());
+        insert.push(pinorder as IdType);
+        statements.push(insert);
+    }
+    statements
+}
+
+// Apply the inverse of a single change, returning whether anything was changed.
+// When forced, versionpins which no longer exist are skipped.
+fn revert_change(
+    tx: &mut Transaction<'_>,
+    change: &FindAllChangesRow,
+    inverse: Inverse<'_>,
+    force: bool,
+) -> RevertRevisionResult<bool> {
+    let coords = change_coords(change);
+    let package = change.package.as_str();
+    match inverse {
+        Inverse::Skip => Ok(false),
+        Inverse::Withs(old) => revert_withs(tx, change, old, force),
+        Inverse::Recreate(old) => {
+            prepare_recreate(old, &coords)
+                .execute(tx)
+                .context(TokioPostgresError {
+                    msg: "failed to insert versionpin",
+                })?;
+            Ok(true)
+        }
+        Inverse::Restore(old) => {
+            let versionpin_id = match changed_versionpin_id(tx, package, &coords, force)? {
+                Some(id) => id,
+                None => return Ok(false),
+            };
+            let distribution_id = find_distribution_id(tx, old)?;
+            prepare_restore(distribution_id, versionpin_id)
+                .execute(tx)
+                .context(TokioPostgresError {
+                    msg: "failed to restore versionpin distribution",
+                })?;
+            Ok(true)
+        }
+        Inverse::Remove => {
+            let versionpin_id = match changed_versionpin_id(tx, package, &coords, force)? {
+                Some(id) => id,
+                None => return Ok(false),
+            };
+            remove_versionpins(tx, "id = $1", versionpin_id).context(TokioPostgresError {
+                msg: "failed to delete versionpin",
+            })?;
+            Ok(true)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Level, Platform, Role, Site};
+    use std::convert::TryFrom;
+
+    // a change made by transaction 7 to the maya versionpin at dev01
+    fn change(event_id: LongIdType, action: ChangeAction, change: ChangeType) -> FindAllChangesRow {
+        FindAllChangesRow::new(
+            event_id,
+            event_id,
+            7,
+            action,
+            Level::try_from("dev01").unwrap(),
+            Role::try_from("model").unwrap(),
+            Platform::Any,
+            Site::Any,
+            "maya",
+            change,
+        )
+    }
+
+    fn dist(name: &str) -> Distribution {
+        Distribution::new(name).unwrap()
+    }
+
+    fn withs(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn params(prepared: &Prepared) -> String {
+        format!("{:?}", prepared.params)
+    }
+
+    #[test]
+    fn reverts_insert_by_removing_the_versionpin() {
+        let changes = vec![change(
+            3,
+            ChangeAction::Insert,
+            ChangeType::Distribution {
+                old: None,
+                new: dist("maya-2019"),
+            },
+        )];
+        let inverses = inverses(&changes);
+        assert_eq!(inverses.len(), 1);
+        assert_eq!(inverses[0].1, Inverse::Remove);
+    }
+
+    #[test]
+    fn reverts_update_by_restoring_the_old_distribution() {
+        let old = dist("maya-2018.sp3");
+        let changes = vec![change(
+            3,
+            ChangeAction::Update,
+            ChangeType::Distribution {
+                old: Some(old.clone()),
+                new: dist("maya-2019"),
+            },
+        )];
+        assert_eq!(inverses(&changes)[0].1, Inverse::Restore(&old));
+        let prepared = prepare_restore(12, 4);
+        assert_eq!(
+            prepared.sql,
+            "UPDATE versionpin SET distribution = $1 WHERE id = $2"
+        );
+        assert_eq!(params(&prepared), "[12, 4]");
+    }
+
+    #[test]
+    fn reverts_delete_by_recreating_the_versionpin_then_its_withs() {
+        let old = dist("maya-2018.sp3");
+        let old_withs = withs(&["houdini", "nuke"]);
+        // the withs of a versionpin are deleted ahead of it
+        let changes = vec![
+            change(
+                5,
+                ChangeAction::Delete,
+                ChangeType::Distribution {
+                    old: Some(old.clone()),
+                    new: Distribution::empty(),
+                },
+            ),
+            change(
+                4,
+                ChangeAction::Delete,
+                ChangeType::Withs {
+                    old: old_withs.clone(),
+                    new: Vec::new(),
+                },
+            ),
+        ];
+        let inverses = inverses(&changes)
+            .into_iter()
+            .map(|(change, inverse)| (change.event_id, inverse))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            inverses,
+            vec![
+                (5, Inverse::Recreate(&old)),
+                (4, Inverse::Withs(&old_withs))
+            ]
+        );
+
+        let prepared = prepare_recreate(&old, &change_coords(&changes[0]));
+        assert_eq!(
+            prepared.sql,
+            "SELECT * from INSERT_VERSIONPIN($1, level_n => $2, site_n => $3, role_n => $4, platform_n => $5)"
+        );
+        assert_eq!(
+            params(&prepared),
+            r#"["maya-2018.sp3", "dev01", "any", "model", "any"]"#
+        );
+
+        let statements = prepare_withs(9, &old_withs);
+        let sql = statements
+            .iter()
+            .map(|prepared| prepared.sql.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sql,
+            vec![
+                "DELETE FROM withpackage WHERE versionpin = $1",
+                "INSERT INTO withpackage (versionpin, package, pinorder) values ($1,$2,$3)",
+                "INSERT INTO withpackage (versionpin, package, pinorder) values ($1,$2,$3)",
+            ]
+        );
+        let params = statements.iter().map(params).collect::<Vec<_>>();
+        assert_eq!(
+            params,
+            vec!["[9]", r#"[9, "houdini", 0]"#, r#"[9, "nuke", 1]"#]
+        );
+    }
+
+    #[test]
+    fn selects_conflicts_after_the_last_event_of_the_revision() {
+        let changes = vec![change(
+            3,
+            ChangeAction::Insert,
+            ChangeType::Distribution {
+                old: None,
+                new: dist("maya-2019"),
+            },
+        )];
+        let touched = changes
+            .iter()
+            .map(|change| (change_names(change), change_coords(change)))
+            .collect::<Vec<_>>();
+        let prepared = prepare_conflicts(3, &touched);
+        assert!(prepared.sql.contains("WHERE event_id > $1"));
+        assert!(prepared.sql.ends_with("ORDER BY event_id"));
+        assert_eq!(
+            params(&prepared),
+            r#"[3, ["maya"], ["dev01"], ["model"], ["any"], ["any"]]"#
+        );
+    }
+
+    #[test]
+    fn refuses_to_revert_conflicting_revisions() {
+        assert!(refuse_conflicts(7, Vec::new()).is_ok());
+        let conflicts = vec![RevertConflict {
+            transaction_id: 5,
+            package: "maya".to_string(),
+            coords: Coords::try_from_parts("dev01", "model", "any", "any").unwrap(),
+        }];
+        match refuse_conflicts(7, conflicts.clone()) {
+            Err(RevertRevisionError::ConflictError {
+                transaction_id,
+                conflicts: refused,
+            }) => {
+                assert_eq!(transaction_id, 7);
+                assert_eq!(refused, conflicts);
+            }
+            _ => panic!("expected a ConflictError"),
+        }
+    }
+}
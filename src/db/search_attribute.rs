@@ -15,7 +15,9 @@ pub enum SearchCtrl {
 }
 
 /// Direction in which to order by when using OrderBy
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr)]
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr, Clone, Copy,
+)]
 pub enum OrderDirection {
     #[strum(
         serialize = "asc",
@@ -32,7 +34,9 @@ pub enum OrderDirection {
     )]
     Desc,
 }
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr)]
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr, Clone, Copy,
+)]
 /// Attributes that one may search and sort on.
 pub enum SearchAttribute {
     #[strum(
@@ -73,7 +77,9 @@ pub enum SearchAttribute {
     Unknown,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr)]
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr, Clone, Copy,
+)]
 pub enum LtreeSearchMode {
     #[strum(
         serialize = "down",
@@ -128,7 +134,7 @@ pub enum JoinMode {
 }
 
 /// Search mode
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum SearchMode {
     Equal,
     // Ilike
@@ -195,31 +201,41 @@ impl SearchMode {
     /// index of the prepared statement parameter, return a search string
     ///
     /// # Arguments
-    /// * `joinval` - WHERE or AND
     /// * `this` - The name of the field that we are comparing against
     /// * `op` - The comparison operation we are performing
     /// * `params_cnt` - The index of the prepared statement parameter we are comparing against
     ///
     /// # Returns
-    /// * String - Statement fragment `this ? that`.
+    /// * String - Statement fragment `this ? that`, joined with WHERE for
+    ///   the first parameter, and AND otherwise.
     pub fn search_string(this: &str, op: &SearchMode, params_cnt: IdType) -> String {
         let joinval = if params_cnt == 1 {
             JoinMode::Where
         } else {
             JoinMode::And
         };
+        Self::search_clause(joinval, this, op, params_cnt)
+    }
+    /// Given the join, the object of the comparison, an operation and the
+    /// index of the prepared statement parameter, return a search string
+    ///
+    /// # Arguments
+    /// * `joinval` - WHERE or AND
+    /// * `this` - The name of the field that we are comparing against
+    /// * `op` - The comparison operation we are performing
+    /// * `params_cnt` - The index of the prepared statement parameter we are comparing against
+    ///
+    /// # Returns
+    /// * String - Statement fragment `this ? that`.
+    pub fn search_clause(
+        joinval: JoinMode,
+        this: &str,
+        op: &SearchMode,
+        params_cnt: IdType,
+    ) -> String {
         match op {
             Self::Like => format!(" {} {} LIKE ${}", joinval, this, params_cnt),
-            //Self::Like => format!(" {} ${} LIKE {}", joinval, params_cnt, this),
             Self::Equal => format!(" {} {} = ${}", joinval, this, params_cnt),
-            //Self::Equal => format!(" {} ${} = {}", joinval, params_cnt, this),
-            // Self::Ltree(op) => format!(
-            //     " {} {} {} text2ltree(${})",
-            //     joinval,
-            //     this,
-            //     op.to_symbol(),
-            //     params_cnt
-            // ),
             Self::Ltree(op) => format!(
                 " {} text2ltree(${}) {} {}",
                 joinval,
@@ -0,0 +1,750 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! Take a snapshot of the contents of the database, which may be written to
+//! json or toml, and restore it into an empty database. This allows us to
+//! back up and seed databases without pg_dump, and to create reproducible
+//! fixture databases.
+//!
+//! The snapshot document is versioned (see `SNAPSHOT_VERSION`). Revisions
+//! may optionally be included for reference. They are not replayed by
+//! `restore`, which commits the restored contents as a single new revision.
+use crate::db::add::distributions::{AddDistributions, AddDistributionsError};
+use crate::db::add::levels::{AddLevels, AddLevelsError};
+use crate::db::add::packages::{AddPackages, AddPackagesError};
+use crate::db::add::platforms::{AddPlatforms, AddPlatformsError};
+use crate::db::add::roles::{AddRoles, AddRolesError};
+use crate::db::add::sites::{AddSites, AddSitesError};
+use crate::db::add::versionpins::{AddVersionPins, AddVersionPinsError, ConflictPolicy};
+use crate::db::add::withs::{AddWiths, AddWithsError};
+use crate::db::delete::find_versionpin_id;
+use crate::db::find_all::distributions::FindAllDistributionsError;
+use crate::db::find_all::levels::FindAllLevelsError;
+use crate::db::find_all::packages::FindAllPackagesError;
+use crate::db::find_all::pkgcoords::FindAllPkgCoordsError;
+use crate::db::find_all::platforms::FindAllPlatformsError;
+use crate::db::find_all::revisions::FindAllRevisionsError;
+use crate::db::find_all::roles::FindAllRolesError;
+use crate::db::find_all::sites::FindAllSitesError;
+use crate::db::find_all::versionpins::FindAllVersionPinsError;
+use crate::db::search_attribute::{LtreeSearchMode, SearchMode};
+use crate::db::utils::prep_query_str;
+use crate::packrat::PackratDb;
+use crate::traits::{PBFind, TransactionHandler};
+use crate::types::LongIdType;
+use crate::{Coords, Distribution};
+use log;
+use postgres::{Client, Transaction};
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::path::Path;
+use strum_macros::{AsRefStr, Display, EnumString};
+
+/// The version of the snapshot document written by this version of packybara.
+/// Snapshots with a later version are refused.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Error type returned when taking, reading, writing or restoring a Snapshot
+#[derive(Debug, Snafu)]
+pub enum SnapshotError {
+    /// When constructing a query, postgres has thrown an error
+    #[snafu(display("Postgres Error: {} {:#?}", msg, source))]
+    TokioPostgresError {
+        msg: &'static str,
+        source: tokio_postgres::error::Error,
+    },
+    #[snafu(display("Problem retrieving packages: {}", source))]
+    FindPackagesError { source: FindAllPackagesError },
+    #[snafu(display("Problem retrieving distributions: {}", source))]
+    FindDistributionsError { source: FindAllDistributionsError },
+    #[snafu(display("Problem retrieving levels: {}", source))]
+    FindLevelsError { source: FindAllLevelsError },
+    #[snafu(display("Problem retrieving roles: {}", source))]
+    FindRolesError { source: FindAllRolesError },
+    #[snafu(display("Problem retrieving platforms: {}", source))]
+    FindPlatformsError { source: FindAllPlatformsError },
+    #[snafu(display("Problem retrieving sites: {}", source))]
+    FindSitesError { source: FindAllSitesError },
+    #[snafu(display("Problem retrieving pkgcoords: {}", source))]
+    FindPkgCoordsError { source: FindAllPkgCoordsError },
+    #[snafu(display("Problem retrieving versionpins: {}", source))]
+    FindVersionPinsError { source: FindAllVersionPinsError },
+    #[snafu(display("Problem retrieving revisions: {}", source))]
+    FindRevisionsError { source: FindAllRevisionsError },
+    #[snafu(display("Problem restoring packages: {}", source))]
+    RestorePackagesError { source: AddPackagesError },
+    #[snafu(display("Problem restoring distributions: {}", source))]
+    RestoreDistributionsError { source: AddDistributionsError },
+    #[snafu(display("Problem restoring levels: {}", source))]
+    RestoreLevelsError { source: AddLevelsError },
+    #[snafu(display("Problem restoring roles: {}", source))]
+    RestoreRolesError { source: AddRolesError },
+    #[snafu(display("Problem restoring platforms: {}", source))]
+    RestorePlatformsError { source: AddPlatformsError },
+    #[snafu(display("Problem restoring sites: {}", source))]
+    RestoreSitesError { source: AddSitesError },
+    #[snafu(display("Problem restoring versionpin {}: {}", versionpin, source))]
+    RestoreVersionPinError {
+        versionpin: String,
+        source: AddVersionPinsError,
+    },
+    #[snafu(display("Problem restoring withs of {}: {}", versionpin, source))]
+    RestoreWithsError {
+        versionpin: String,
+        source: AddWithsError,
+    },
+    /// The versionpin could not be found after being restored
+    #[snafu(display("Unable to find versionpin {} after restoring it", versionpin))]
+    VersionPinNotFoundError { versionpin: String },
+    /// Snapshots may only be restored into an empty database
+    #[snafu(display("Unable to restore into a database with {} packages", packages))]
+    NotEmptyError { packages: usize },
+    /// The snapshot was written by a later version of packybara
+    #[snafu(display(
+        "Unsupported snapshot version {}. Expected {} or earlier",
+        version,
+        SNAPSHOT_VERSION
+    ))]
+    UnsupportedVersionError { version: u32 },
+    /// The format could not be determined from the extension of the path
+    #[snafu(display("Unable to determine snapshot format of {}", path))]
+    UnknownFormatError { path: String },
+    #[snafu(display("Unable to read or write snapshot {}: {}", path, source))]
+    IoError {
+        path: String,
+        source: std::io::Error,
+    },
+    #[snafu(display("Problem with json snapshot: {}", source))]
+    JsonError { source: serde_json::Error },
+    #[snafu(display("Problem writing toml snapshot: {}", source))]
+    TomlSerError { source: toml::ser::Error },
+    #[snafu(display("Problem reading toml snapshot: {}", source))]
+    TomlDeError { source: toml::de::Error },
+}
+
+pub type SnapshotResult<T, E = SnapshotError> = std::result::Result<T, E>;
+
+/// The document formats a Snapshot may be written to
+#[derive(Debug, PartialEq, Eq, Clone, Copy, EnumString, Display, AsRefStr)]
+pub enum SnapshotFormat {
+    #[strum(serialize = "json", serialize = "Json", serialize = "JSON")]
+    Json,
+    #[strum(serialize = "toml", serialize = "Toml", serialize = "TOML")]
+    Toml,
+}
+
+impl SnapshotFormat {
+    /// Determine the format from the extension of the supplied path
+    ///
+    /// # Arguments
+    /// * `path` - The path of the snapshot, ending in .json or .toml
+    ///
+    /// # Returns
+    /// * Some(SnapshotFormat) if the extension is recognized
+    /// * None otherwise
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        path.as_ref()
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| ext.to_lowercase().parse().ok())
+    }
+}
+
+/// A pkgcoord, in the form reported by `find_pkgcoords`
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize)]
+pub struct SnapshotPkgCoord {
+    pub package: String,
+    pub level: String,
+    pub role: String,
+    pub platform: String,
+    pub site: String,
+}
+
+/// A versionpin, along with its withs, in order
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize)]
+pub struct SnapshotVersionPin {
+    pub distribution: String,
+    pub level: String,
+    pub role: String,
+    pub platform: String,
+    pub site: String,
+    #[serde(default)]
+    pub withs: Vec<String>,
+}
+
+impl SnapshotVersionPin {
+    // Is the versionpin located at the supplied pkgcoord?
+    fn is_at(&self, pkgcoord: &SnapshotPkgCoord) -> bool {
+        Distribution::new_unchecked(self.distribution.as_str()).package() == pkgcoord.package
+            && self.level == pkgcoord.level
+            && self.role == pkgcoord.role
+            && self.platform == pkgcoord.platform
+            && self.site == pkgcoord.site
+    }
+}
+
+/// A revision. The datetime is in rfc3339 form
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize)]
+pub struct SnapshotRevision {
+    pub transaction_id: LongIdType,
+    pub author: String,
+    pub comment: String,
+    pub datetime: String,
+}
+
+/// The contents of a database. Fields hold names as reported by the
+/// corresponding find_all queries, with the implicit `any` and `facility`
+/// entries omitted.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    #[serde(default)]
+    pub packages: Vec<String>,
+    #[serde(default)]
+    pub distributions: Vec<String>,
+    #[serde(default)]
+    pub levels: Vec<String>,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    #[serde(default)]
+    pub sites: Vec<String>,
+    #[serde(default)]
+    pub pkgcoords: Vec<SnapshotPkgCoord>,
+    #[serde(default)]
+    pub versionpins: Vec<SnapshotVersionPin>,
+    #[serde(default)]
+    pub revisions: Vec<SnapshotRevision>,
+}
+
+impl Default for Snapshot {
+    fn default() -> Self {
+        Self {
+            version: SNAPSHOT_VERSION,
+            packages: Vec::new(),
+            distributions: Vec::new(),
+            levels: Vec::new(),
+            roles: Vec::new(),
+            platforms: Vec::new(),
+            sites: Vec::new(),
+            pkgcoords: Vec::new(),
+            versionpins: Vec::new(),
+            revisions: Vec::new(),
+        }
+    }
+}
+
+impl Snapshot {
+    /// Serialize the snapshot to a string in the supplied format
+    ///
+    /// # Arguments
+    /// * `format` - The document format
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - The serialized snapshot
+    ///   * `Err` - SnapshotError
+    pub fn to_string(&self, format: SnapshotFormat) -> SnapshotResult<String> {
+        match format {
+            SnapshotFormat::Json => serde_json::to_string_pretty(self).context(JsonError),
+            SnapshotFormat::Toml => toml::to_string(self).context(TomlSerError),
+        }
+    }
+
+    /// Deserialize a snapshot from a string in the supplied format, refusing
+    /// snapshots written by a later version of packybara
+    ///
+    /// # Arguments
+    /// * `input` - The serialized snapshot
+    /// * `format` - The document format
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - Snapshot
+    ///   * `Err` - SnapshotError
+    pub fn from_str(input: &str, format: SnapshotFormat) -> SnapshotResult<Self> {
+        let snapshot: Snapshot = match format {
+            SnapshotFormat::Json => serde_json::from_str(input).context(JsonError)?,
+            SnapshotFormat::Toml => toml::from_str(input).context(TomlDeError)?,
+        };
+        if snapshot.version > SNAPSHOT_VERSION {
+            return UnsupportedVersionError {
+                version: snapshot.version,
+            }
+            .fail();
+        }
+        Ok(snapshot)
+    }
+
+    /// Write the snapshot to the supplied path, in the format determined by
+    /// its extension
+    ///
+    /// # Arguments
+    /// * `path` - The path of the snapshot, ending in .json or .toml
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - Unit
+    ///   * `Err` - SnapshotError
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> SnapshotResult<()> {
+        let path = path.as_ref();
+        let contents = self.to_string(format_of(path)?)?;
+        std::fs::write(path, contents).context(IoError {
+            path: path.display().to_string(),
+        })
+    }
+
+    /// Read a snapshot from the supplied path, in the format determined by
+    /// its extension
+    ///
+    /// # Arguments
+    /// * `path` - The path of the snapshot, ending in .json or .toml
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - Snapshot
+    ///   * `Err` - SnapshotError
+    pub fn read<P: AsRef<Path>>(path: P) -> SnapshotResult<Self> {
+        let path = path.as_ref();
+        let format = format_of(path)?;
+        let contents = std::fs::read_to_string(path).context(IoError {
+            path: path.display().to_string(),
+        })?;
+        Self::from_str(&contents, format)
+    }
+}
+
+fn format_of(path: &Path) -> SnapshotResult<SnapshotFormat> {
+    match SnapshotFormat::from_path(path) {
+        Some(format) => Ok(format),
+        None => UnknownFormatError {
+            path: path.display().to_string(),
+        }
+        .fail(),
+    }
+}
+
+// Are we looking at one of the entries implicitly present in every database?
+fn is_implicit(name: &str) -> bool {
+    name == "any" || name == "facility"
+}
+
+/// Responsible for taking a snapshot of the database. Typically constructed
+/// via `PackratDb::snapshot`.
+pub struct TakeSnapshot<'a> {
+    client: &'a mut Client,
+    revisions: bool,
+}
+
+impl<'a> TakeSnapshot<'a> {
+    /// new up a TakeSnapshot instance
+    ///
+    /// # Arguments
+    /// * `client` - A mutable reference to a postgres::Client
+    pub fn new(client: &'a mut Client) -> Self {
+        Self {
+            client,
+            revisions: false,
+        }
+    }
+
+    /// Include the revisions in the snapshot
+    ///
+    /// # Arguments
+    /// * `revisions` - Whether to include revisions
+    ///
+    /// # Returns
+    /// Mutable reference to Self
+    pub fn revisions(&mut self, revisions: bool) -> &mut Self {
+        self.revisions = revisions;
+        self
+    }
+
+    /// Retrieve the contents of the database
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - Snapshot
+    ///   * `Err` - SnapshotError
+    pub fn take(&mut self) -> SnapshotResult<Snapshot> {
+        let mut db = PackratDb::new(self.client);
+        let packages = db
+            .find_all_packages()
+            .query()
+            .context(FindPackagesError)?
+            .into_iter()
+            .map(|row| row.name)
+            .collect();
+        let mut distributions: Vec<String> = db
+            .find_all_distributions()
+            .query()
+            .context(FindDistributionsError)?
+            .into_iter()
+            .map(|row| format!("{}-{}", row.package, row.version))
+            .collect();
+        distributions.sort();
+        let levels = db
+            .find_all_levels()
+            .query()
+            .context(FindLevelsError)?
+            .into_iter()
+            .map(|row| row.level)
+            .filter(|level| !is_implicit(level))
+            .collect();
+        let roles = db
+            .find_all_roles()
+            .query()
+            .context(FindRolesError)?
+            .into_iter()
+            .map(|row| row.role)
+            .filter(|role| !is_implicit(role))
+            .collect();
+        let platforms = db
+            .find_all_platforms()
+            .query()
+            .context(FindPlatformsError)?
+            .into_iter()
+            .map(|row| row.name)
+            .filter(|platform| !is_implicit(platform))
+            .collect();
+        let sites = db
+            .find_all_sites()
+            .query()
+            .context(FindSitesError)?
+            .into_iter()
+            .map(|row| row.name)
+            .filter(|site| !is_implicit(site))
+            .collect();
+        let mut pkgcoords: Vec<SnapshotPkgCoord> = db
+            .find_pkgcoords()
+            .search_mode(SearchMode::Ltree(LtreeSearchMode::Descendant))
+            .query()
+            .context(FindPkgCoordsError)?
+            .into_iter()
+            .map(|row| SnapshotPkgCoord {
+                package: row.package,
+                level: row.level,
+                role: row.role,
+                platform: row.platform,
+                site: row.site,
+            })
+            .collect();
+        pkgcoords.sort();
+        let mut versionpins: Vec<SnapshotVersionPin> = db
+            .find_all_versionpins()
+            .search_mode(LtreeSearchMode::Descendant)
+            .query()
+            .context(FindVersionPinsError)?
+            .into_iter()
+            .map(|row| SnapshotVersionPin {
+                distribution: row.distribution.distribution().to_string(),
+                level: row.coords.level().to_string(),
+                role: row.coords.role().to_string(),
+                platform: row.coords.platform().to_string(),
+                site: row.coords.site().to_string(),
+                withs: row.withs.unwrap_or_default(),
+            })
+            .collect();
+        versionpins.sort();
+        let mut revisions = Vec::new();
+        if self.revisions {
+            revisions = db
+                .find_all_revisions()
+                .query()
+                .context(FindRevisionsError)?
+                .into_iter()
+                .map(|row| SnapshotRevision {
+                    transaction_id: row.transaction_id,
+                    author: row.author,
+                    comment: row.comment,
+                    datetime: row.datetime.to_rfc3339(),
+                })
+                .collect();
+        }
+        Ok(Snapshot {
+            version: SNAPSHOT_VERSION,
+            packages,
+            distributions,
+            levels,
+            roles,
+            platforms,
+            sites,
+            pkgcoords,
+            versionpins,
+            revisions,
+        })
+    }
+}
+
+/// Responsible for restoring a snapshot into an empty database. Typically
+/// constructed via `PackratDb::restore_snapshot`.
+pub struct RestoreSnapshot<'a> {
+    client: &'a mut Client,
+    snapshot: Snapshot,
+}
+
+impl<'a> RestoreSnapshot<'a> {
+    /// new up a RestoreSnapshot instance
+    ///
+    /// # Arguments
+    /// * `client` - A mutable reference to a postgres::Client
+    /// * `snapshot` - The snapshot to restore
+    pub fn new(client: &'a mut Client, snapshot: Snapshot) -> Self {
+        Self { client, snapshot }
+    }
+
+    /// Restore the snapshot in a new transaction through the add builders,
+    /// and commit it as a single revision.
+    ///
+    /// # Arguments
+    /// * `author` - The author of the revision
+    /// * `comment` - The comment of the revision
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - The number of entities created
+    ///   * `Err` - SnapshotError
+    pub fn restore(&mut self, author: &str, comment: &str) -> SnapshotResult<u64> {
+        let packages = PackratDb::new(self.client)
+            .find_all_packages()
+            .query()
+            .context(FindPackagesError)?;
+        if !packages.is_empty() {
+            return NotEmptyError {
+                packages: packages.len(),
+            }
+            .fail();
+        }
+        let snapshot = &self.snapshot;
+        let mut tx = self.client.transaction().context(TokioPostgresError {
+            msg: "failed to create transaction",
+        })?;
+        let mut cnt = 0;
+        if !snapshot.packages.is_empty() {
+            let mut builder = AddPackages::new(tx)
+                .packages(&mut snapshot.packages.clone())
+                .create()
+                .context(RestorePackagesError)?;
+            cnt += builder.get_result_cnt();
+            tx = builder.take_tx();
+        }
+        if !snapshot.levels.is_empty() {
+            let mut builder = AddLevels::new(tx)
+                .levels(&mut snapshot.levels.clone())
+                .create()
+                .context(RestoreLevelsError)?;
+            cnt += builder.get_result_cnt();
+            tx = builder.take_tx();
+        }
+        if !snapshot.roles.is_empty() {
+            let mut builder = AddRoles::new(tx)
+                .roles(&mut snapshot.roles.clone())
+                .create()
+                .context(RestoreRolesError)?;
+            cnt += builder.get_result_cnt();
+            tx = builder.take_tx();
+        }
+        if !snapshot.platforms.is_empty() {
+            let mut builder = AddPlatforms::new(tx)
+                .platforms(&mut snapshot.platforms.clone())
+                .create()
+                .context(RestorePlatformsError)?;
+            cnt += builder.get_result_cnt();
+            tx = builder.take_tx();
+        }
+        if !snapshot.sites.is_empty() {
+            let mut builder = AddSites::new(tx)
+                .sites(&mut snapshot.sites.clone())
+                .create()
+                .context(RestoreSitesError)?;
+            cnt += builder.get_result_cnt();
+            tx = builder.take_tx();
+        }
+        if !snapshot.distributions.is_empty() {
+            let mut builder = AddDistributions::new(tx)
+                .distributions(&mut snapshot.distributions.clone())
+                .create()
+                .context(RestoreDistributionsError)?;
+            cnt += builder.get_result_cnt();
+            tx = builder.take_tx();
+        }
+        for pin in &snapshot.versionpins {
+            let (builder_cnt, next) = restore_versionpin(tx, pin)?;
+            cnt += builder_cnt;
+            tx = next;
+        }
+        // pkgcoords are created along with their versionpins. Those without
+        // a versionpin are created directly.
+        for pkgcoord in snapshot
+            .pkgcoords
+            .iter()
+            .filter(|pkgcoord| !snapshot.versionpins.iter().any(|pin| pin.is_at(pkgcoord)))
+        {
+            cnt += restore_pkgcoord(&mut tx, pkgcoord)?;
+        }
+        tx.execute(
+            "INSERT INTO REVISION (author, comment) VALUES ($1, $2)",
+            &[&author, &comment],
+        )
+        .context(TokioPostgresError {
+            msg: "failed to insert Revisions",
+        })?;
+        tx.commit().context(TokioPostgresError {
+            msg: "failed to commit transaction",
+        })?;
+        Ok(cnt)
+    }
+}
+
+// Restore a versionpin along with its withs, returning the number of entities
+// created and the transaction
+fn restore_versionpin<'a>(
+    tx: Transaction<'a>,
+    pin: &SnapshotVersionPin,
+) -> SnapshotResult<(u64, Transaction<'a>)> {
+    let versionpin = format!(
+        "{}@{}:{}:{}:{}",
+        pin.distribution, pin.level, pin.role, pin.platform, pin.site
+    );
+    let distribution = Distribution::new_unchecked(pin.distribution.as_str());
+    // the database is empty, so any pinned coords are duplicated in the snapshot
+    let mut builder = AddVersionPins::new(
+        tx,
+        distribution.package().to_string(),
+        distribution.version().to_string(),
+        ConflictPolicy::Fail,
+    )
+    .level(pin.level.as_str())
+    .role(pin.role.as_str())
+    .platform(pin.platform.as_str())
+    .site(pin.site.as_str())
+    .create()
+    .context(RestoreVersionPinError {
+        versionpin: versionpin.as_str(),
+    })?;
+    let mut cnt = builder.get_result_cnt();
+    let mut tx = builder.take_tx();
+    if pin.withs.is_empty() {
+        return Ok((cnt, tx));
+    }
+    let coords = Coords::try_from_parts(
+        pin.level.as_str(),
+        pin.role.as_str(),
+        pin.platform.as_str(),
+        pin.site.as_str(),
+    )
+    .ok();
+    let versionpin_id = match coords {
+        Some(coords) => find_versionpin_id(&mut tx, distribution.package(), &coords).context(
+            TokioPostgresError {
+                msg: "failed to look up versionpin",
+            },
+        )?,
+        None => None,
+    };
+    let versionpin_id = match versionpin_id {
+        Some(id) => id,
+        None => return VersionPinNotFoundError { versionpin }.fail(),
+    };
+    let mut builder = AddWiths::new(tx)
+        .create(versionpin_id, pin.withs.clone())
+        .context(RestoreWithsError {
+            versionpin: versionpin.as_str(),
+        })?;
+    cnt += builder.get_result_cnt();
+    Ok((cnt, builder.take_tx()))
+}
+
+// Create a pkgcoord, returning the number created
+fn restore_pkgcoord(tx: &mut Transaction<'_>, pkgcoord: &SnapshotPkgCoord) -> SnapshotResult<u64> {
+    let insert_str = "INSERT INTO pkgcoord(package, level, role, platform, site)
+        VALUES ($1, text2ltree($2), text2ltree($3), text2ltree($4), text2ltree($5))";
+    let level = prep_query_str("facility", pkgcoord.level.as_str(), false);
+    let role = prep_query_str("any", pkgcoord.role.as_str(), true);
+    let platform = prep_query_str("any", pkgcoord.platform.as_str(), false);
+    let site = prep_query_str("any", pkgcoord.site.as_str(), false);
+    log::info!("SQL\n{}", insert_str);
+    log::info!("Prepared\n{:?}", pkgcoord);
+    tx.execute(
+        insert_str,
+        &[&pkgcoord.package, &level, &role, &platform, &site],
+    )
+    .context(TokioPostgresError {
+        msg: "failed to insert pkgcoord",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        let mut snapshot = Snapshot::default();
+        snapshot.packages = vec!["maya".into(), "xerces".into()];
+        snapshot.distributions = vec!["maya-2018.sp3".into(), "xerces-3.2".into()];
+        snapshot.levels = vec!["dev01".into(), "dev01.rd".into()];
+        snapshot.roles = vec!["model".into(), "model_beta".into()];
+        snapshot.platforms = vec!["cent7_64".into()];
+        snapshot.sites = vec!["portland".into()];
+        snapshot.pkgcoords = vec![SnapshotPkgCoord {
+            package: "maya".into(),
+            level: "dev01".into(),
+            role: "model".into(),
+            platform: "any".into(),
+            site: "any".into(),
+        }];
+        snapshot.versionpins = vec![SnapshotVersionPin {
+            distribution: "maya-2018.sp3".into(),
+            level: "dev01".into(),
+            role: "model".into(),
+            platform: "any".into(),
+            site: "any".into(),
+            withs: vec!["xerces".into()],
+        }];
+        snapshot.revisions = vec![SnapshotRevision {
+            transaction_id: 12,
+            author: "jgerber".into(),
+            comment: "initial".into(),
+            datetime: "2020-03-01T10:00:00-08:00".into(),
+        }];
+        snapshot
+    }
+
+    #[test]
+    fn can_round_trip_json_and_toml() {
+        let snapshot = snapshot();
+        for format in &[SnapshotFormat::Json, SnapshotFormat::Toml] {
+            let contents = snapshot.to_string(*format).unwrap();
+            assert_eq!(Snapshot::from_str(&contents, *format).unwrap(), snapshot);
+        }
+    }
+
+    #[test]
+    fn from_str_refuses_later_versions() {
+        let contents = format!("version = {}\npackages = [\"maya\"]", SNAPSHOT_VERSION + 1);
+        let result = Snapshot::from_str(&contents, SnapshotFormat::Toml);
+        assert!(matches!(
+            result,
+            Err(SnapshotError::UnsupportedVersionError { .. })
+        ));
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(
+            SnapshotFormat::from_path("/tmp/packrat.json"),
+            Some(SnapshotFormat::Json)
+        );
+        assert_eq!(
+            SnapshotFormat::from_path("packrat.TOML"),
+            Some(SnapshotFormat::Toml)
+        );
+        assert_eq!(SnapshotFormat::from_path("packrat.yaml"), None);
+    }
+}
@@ -0,0 +1,345 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! Export the contents of a database into a SqliteStore, which may be
+//! shipped as a single file to hosts without access to postgres.
+//!
+//! The database ids of distributions, pkgcoords, versionpins and revisions
+//! are preserved, as are the changes made by each revision, so that the
+//! exported store answers `find_all_changes` and `as_of` queries.
+use super::views::format_datetime;
+use super::{SqliteStore, SqliteStoreError};
+use crate::db::backend::Backend;
+use crate::db::find_all::changes::{ChangeAction, ChangeType, FindAllChangesError};
+use crate::db::find_all::distributions::FindAllDistributionsError;
+use crate::db::find_all::levels::FindAllLevelsError;
+use crate::db::find_all::packages::FindAllPackagesError;
+use crate::db::find_all::pkgcoords::FindAllPkgCoordsError;
+use crate::db::find_all::platforms::FindAllPlatformsError;
+use crate::db::find_all::revisions::FindAllRevisionsError;
+use crate::db::find_all::roles::FindAllRolesError;
+use crate::db::find_all::sites::FindAllSitesError;
+use crate::db::find_all::versionpins::FindAllVersionPinsError;
+use crate::db::memory::functions::{any_name_to_path, level_name_to_path, role_name_to_path};
+use crate::db::search_attribute::{LtreeSearchMode, SearchMode};
+use crate::packrat::PackratDb;
+use crate::traits::PBFind;
+use crate::types::LongIdType;
+use itertools::Itertools;
+use postgres::Client;
+use rusqlite::params;
+use snafu::{ResultExt, Snafu};
+use std::path::Path;
+
+/// Error type returned when exporting to a SqliteStore
+#[derive(Debug, Snafu)]
+pub enum ExportSqliteError {
+    /// Error from sqlite
+    #[snafu(display("Sqlite Error: {} {}", msg, source))]
+    SqliteError {
+        msg: &'static str,
+        source: rusqlite::Error,
+    },
+    #[snafu(display("Problem opening {}: {}", path, source))]
+    OpenError {
+        path: String,
+        source: SqliteStoreError,
+    },
+    /// Exports are written to a new file
+    #[snafu(display("Unable to export to {}, which already exists", path))]
+    ExistsError { path: String },
+    /// Exports may only be written into an empty store
+    #[snafu(display("Unable to export into a store with {} packages", packages))]
+    NotEmptyError { packages: i64 },
+    #[snafu(display("Problem retrieving packages: {}", source))]
+    FindPackagesError { source: FindAllPackagesError },
+    #[snafu(display("Problem retrieving distributions: {}", source))]
+    FindDistributionsError { source: FindAllDistributionsError },
+    #[snafu(display("Problem retrieving levels: {}", source))]
+    FindLevelsError { source: FindAllLevelsError },
+    #[snafu(display("Problem retrieving roles: {}", source))]
+    FindRolesError { source: FindAllRolesError },
+    #[snafu(display("Problem retrieving platforms: {}", source))]
+    FindPlatformsError { source: FindAllPlatformsError },
+    #[snafu(display("Problem retrieving sites: {}", source))]
+    FindSitesError { source: FindAllSitesError },
+    #[snafu(display("Problem retrieving pkgcoords: {}", source))]
+    FindPkgCoordsError { source: FindAllPkgCoordsError },
+    #[snafu(display("Problem retrieving versionpins: {}", source))]
+    FindVersionPinsError { source: FindAllVersionPinsError },
+    #[snafu(display("Problem retrieving revisions: {}", source))]
+    FindRevisionsError { source: FindAllRevisionsError },
+    #[snafu(display(
+        "Problem retrieving changes for transaction {}: {}",
+        transaction_id,
+        source
+    ))]
+    FindChangesError {
+        transaction_id: LongIdType,
+        source: FindAllChangesError,
+    },
+}
+
+pub type ExportSqliteResult<T, E = ExportSqliteError> = std::result::Result<T, E>;
+
+/// Responsible for exporting the contents of a database into a SqliteStore.
+/// Typically constructed via `PackratDb::export_sqlite`.
+pub struct ExportSqlite<'a, C = Client> {
+    client: &'a mut C,
+}
+
+impl<'a, C: Backend> ExportSqlite<'a, C> {
+    /// new up an ExportSqlite instance
+    ///
+    /// # Arguments
+    /// * `client` - A mutable reference to the client of the database to export
+    pub fn new(client: &'a mut C) -> Self {
+        Self { client }
+    }
+
+    /// Export the database into a new SQLite file
+    ///
+    /// # Arguments
+    /// * `path` - The path of the file, which must not exist
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - The number of rows written
+    ///   * `Err` - ExportSqliteError
+    pub fn export<P: AsRef<Path>>(&mut self, path: P) -> ExportSqliteResult<u64> {
+        let path = path.as_ref();
+        if path.exists() {
+            return ExistsError {
+                path: path.display().to_string(),
+            }
+            .fail();
+        }
+        let mut store = SqliteStore::open(path).context(OpenError {
+            path: path.display().to_string(),
+        })?;
+        self.export_into(&mut store)
+    }
+
+    /// Export the database into an empty SqliteStore, within a single
+    /// sqlite transaction.
+    ///
+    /// # Arguments
+    /// * `store` - The store to export into
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - The number of rows written
+    ///   * `Err` - ExportSqliteError
+    pub fn export_into(&mut self, store: &mut SqliteStore) -> ExportSqliteResult<u64> {
+        let packages: i64 = store
+            .connection()
+            .query_row("SELECT count(*) FROM package", rusqlite::NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .context(SqliteError {
+                msg: "problem with select from package",
+            })?;
+        if packages > 0 {
+            return NotEmptyError { packages }.fail();
+        }
+        let mut db = PackratDb::new(&mut *self.client);
+        let packages = db.find_all_packages().query().context(FindPackagesError)?;
+        let levels = db.find_all_levels().query().context(FindLevelsError)?;
+        let roles = db.find_all_roles().query().context(FindRolesError)?;
+        let platforms = db
+            .find_all_platforms()
+            .query()
+            .context(FindPlatformsError)?;
+        let sites = db.find_all_sites().query().context(FindSitesError)?;
+        let distributions = db
+            .find_all_distributions()
+            .query()
+            .context(FindDistributionsError)?;
+        let pkgcoords = db
+            .find_pkgcoords()
+            .search_mode(SearchMode::Ltree(LtreeSearchMode::Descendant))
+            .query()
+            .context(FindPkgCoordsError)?;
+        let versionpins = db
+            .find_all_versionpins()
+            .search_mode(LtreeSearchMode::Descendant)
+            .query()
+            .context(FindVersionPinsError)?;
+        let revisions = db
+            .find_all_revisions()
+            .query()
+            .context(FindRevisionsError)?;
+        let mut changes = Vec::new();
+        for transaction_id in revisions.iter().map(|row| row.transaction_id).unique() {
+            changes.extend(
+                db.find_all_changes()
+                    .transaction_id(transaction_id)
+                    .query()
+                    .context(FindChangesError { transaction_id })?,
+            );
+        }
+
+        let tx = store.connection().transaction().context(SqliteError {
+            msg: "failed to create transaction",
+        })?;
+        let mut cnt = 0;
+        for row in &packages {
+            cnt += tx
+                .execute("INSERT INTO package (name) VALUES (?1)", params![row.name])
+                .context(SqliteError {
+                    msg: "problem with insert into package",
+                })?;
+        }
+        let paths = levels
+            .iter()
+            .map(|row| ("level", level_name_to_path(&row.level)))
+            .chain(
+                roles
+                    .iter()
+                    .map(|row| ("role", role_name_to_path(&row.role))),
+            )
+            .chain(
+                platforms
+                    .iter()
+                    .map(|row| ("platform", any_name_to_path(&row.name))),
+            )
+            .chain(
+                sites
+                    .iter()
+                    .map(|row| ("site", any_name_to_path(&row.name))),
+            );
+        // the roots are present in every store
+        for (table, path) in paths {
+            cnt += tx
+                .execute(
+                    &format!("INSERT OR IGNORE INTO {} (path) VALUES (?1)", table),
+                    params![path],
+                )
+                .context(SqliteError {
+                    msg: "problem with insert into path table",
+                })?;
+        }
+        for row in &distributions {
+            cnt += tx
+                .execute(
+                    "INSERT INTO distribution (id, package, version) VALUES (?1, ?2, ?3)",
+                    params![row.id, row.package, row.version],
+                )
+                .context(SqliteError {
+                    msg: "problem with insert into distribution",
+                })?;
+        }
+        for row in &pkgcoords {
+            cnt += tx
+                .execute(
+                    "INSERT INTO pkgcoord (id, package, level, role, platform, site)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        row.id,
+                        row.package,
+                        level_name_to_path(&row.level),
+                        role_name_to_path(&row.role),
+                        any_name_to_path(&row.platform),
+                        any_name_to_path(&row.site)
+                    ],
+                )
+                .context(SqliteError {
+                    msg: "problem with insert into pkgcoord",
+                })?;
+        }
+        for row in &versionpins {
+            cnt += tx
+                .execute(
+                    "INSERT INTO versionpin (id, distribution, coord) VALUES (?1, ?2, ?3)",
+                    params![row.versionpin_id, row.distribution_id, row.pkgcoord_id],
+                )
+                .context(SqliteError {
+                    msg: "problem with insert into versionpin",
+                })?;
+            for (pinorder, package) in row.withs.iter().flatten().enumerate() {
+                cnt += tx
+                    .execute(
+                        "INSERT INTO withpackage (versionpin, package, pinorder)
+                            VALUES (?1, ?2, ?3)",
+                        params![row.versionpin_id, package, pinorder as i64],
+                    )
+                    .context(SqliteError {
+                        msg: "problem with insert into withpackage",
+                    })?;
+            }
+        }
+        for row in &revisions {
+            cnt += tx
+                .execute(
+                    "INSERT INTO revision (id, transaction_id, author, comment, datetime)
+                        VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![
+                        row.id,
+                        row.transaction_id,
+                        row.author,
+                        row.comment,
+                        format_datetime(&row.datetime)
+                    ],
+                )
+                .context(SqliteError {
+                    msg: "problem with insert into revision",
+                })?;
+        }
+        for row in &changes {
+            let (old, new, old_withs, new_withs) = match &row.change {
+                ChangeType::Distribution { old, new } => (
+                    old.as_ref()
+                        .map(|old| old.distribution().to_string())
+                        .unwrap_or_default(),
+                    // a deleted versionpin has no new distribution
+                    match row.action {
+                        ChangeAction::Delete => None,
+                        _ => Some(new.distribution().to_string()),
+                    },
+                    None,
+                    None,
+                ),
+                ChangeType::Withs { old, new } => (
+                    String::new(),
+                    None,
+                    Some(old.join(",")),
+                    Some(new.join(",")),
+                ),
+            };
+            cnt += tx
+                .execute(
+                    "INSERT INTO vpin_audit (event_id, id, transaction_id, action, level_name,
+                            role_name, platform_name, site_name, package, old, new, old_withs,
+                            new_withs)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                    params![
+                        row.event_id,
+                        row.id,
+                        row.transaction_id,
+                        row.action.as_ref(),
+                        row.level.to_string(),
+                        row.role.to_string(),
+                        row.platform.to_string(),
+                        row.site.to_string(),
+                        row.package,
+                        old,
+                        new,
+                        old_withs,
+                        new_withs
+                    ],
+                )
+                .context(SqliteError {
+                    msg: "problem with insert into vpin_audit",
+                })?;
+        }
+        tx.commit().context(SqliteError {
+            msg: "failed to commit transaction",
+        })?;
+        Ok(cnt as u64)
+    }
+}
@@ -0,0 +1,607 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! A packrat database held in a single SQLite file, for artists and render
+//! nodes without access to postgres.
+//!
+//! The `SqliteStore` is a `Backend`, so a `PackratDb` wrapping one supports
+//! the full `PBFind` api, including ltree ancestor / descendant searches,
+//! versionpin resolution, the changes made by each transaction and `as_of`
+//! queries. SQLite has no ltree type, so paths are stored as `.` separated
+//! text, and the ltree functions and operators the queries rely on are
+//! registered as sql functions when the store is opened.
+//!
+//! Stores are built from an existing database with `ExportSqlite`, typically
+//! via `PackratDb::export_sqlite`:
+//!
+//! ```no_run
+//! use packybara::db::sqlite::SqliteStore;
+//! use packybara::packrat::{Client, NoTls, PackratDb};
+//! use packybara::traits::*;
+//!
+//! let mut client = Client::connect(
+//!     "host=127.0.0.1 user=postgres dbname=packrat password=example port=5432",
+//!     NoTls,
+//! )
+//! .unwrap();
+//! PackratDb::new(&mut client)
+//!     .export_sqlite()
+//!     .export("packrat.db")
+//!     .unwrap();
+//!
+//! let mut store = SqliteStore::open("packrat.db").unwrap();
+//! let mut db = PackratDb::new(&mut store);
+//! let pin = db.find_versionpin("maya").level("dev01").query().unwrap();
+//! ```
+use crate::db::backend::Backend;
+use crate::db::find::pins::{FindPins, FindPinsError, FindPinsRow};
+use crate::db::find::versionpin::{FindVersionPin, FindVersionPinError};
+use crate::db::find::versionpins::{FindVersionPins, FindVersionPinsRow};
+use crate::db::find::withs::{FindWiths, FindWithsError, FindWithsRow};
+use crate::db::find_all::changes::{FindAllChanges, FindAllChangesError, FindAllChangesRow};
+use crate::db::find_all::distributions::{
+    FindAllDistributions, FindAllDistributionsError, FindAllDistributionsRow,
+};
+use crate::db::find_all::levels::{FindAllLevels, FindAllLevelsError, FindAllLevelsRow};
+use crate::db::find_all::packages::{FindAllPackages, FindAllPackagesError, FindAllPackagesRow};
+use crate::db::find_all::pkgcoords::{
+    FindAllPkgCoords, FindAllPkgCoordsError, FindAllPkgCoordsRow,
+};
+use crate::db::find_all::platforms::{
+    FindAllPlatforms, FindAllPlatformsError, FindAllPlatformsRow,
+};
+use crate::db::find_all::revisions::{
+    FindAllRevisions, FindAllRevisionsError, FindAllRevisionsRow,
+};
+use crate::db::find_all::roles::{FindAllRoles, FindAllRolesError, FindAllRolesRow};
+use crate::db::find_all::sites::{FindAllSites, FindAllSitesError, FindAllSitesRow};
+use crate::db::find_all::versionpin_withs::{FindAllWiths, FindAllWithsError, FindAllWithsRow};
+use crate::db::find_all::versionpins::{
+    FindAllVersionPins, FindAllVersionPinsError, FindAllVersionPinsRow,
+};
+use crate::db::memory::functions::{
+    any_name_to_path, is_ancestor, level_name_to_path, nlevel, path_name, role_name_to_path,
+};
+use crate::db::search_attribute::LtreeSearchMode;
+use log;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::Value;
+use rusqlite::{Connection, Row};
+use snafu::{ResultExt, Snafu};
+use std::path::Path;
+
+pub mod export;
+pub(crate) mod views;
+
+/// The schema of a new store
+const SCHEMA: &str = include_str!("schema.sql");
+
+/// Error type returned when opening a SqliteStore
+#[derive(Debug, Snafu)]
+pub enum SqliteStoreError {
+    /// Error from sqlite
+    #[snafu(display("Sqlite Error: {} {}", msg, source))]
+    SqliteError {
+        msg: &'static str,
+        source: rusqlite::Error,
+    },
+}
+
+pub type SqliteStoreResult<T, E = SqliteStoreError> = std::result::Result<T, E>;
+
+/// A packrat database in an SQLite file. Wrap it in a `PackratDb` to query it.
+#[derive(Debug)]
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    /// Open the store at the supplied path, creating it if it does not exist.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the SQLite file
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - SqliteStore instance
+    ///   * `Err` - SqliteStoreError
+    pub fn open<P: AsRef<Path>>(path: P) -> SqliteStoreResult<Self> {
+        let conn = Connection::open(path).context(SqliteError {
+            msg: "unable to open database",
+        })?;
+        Self::from_connection(conn)
+    }
+
+    /// Open a new store held in memory.
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - SqliteStore instance
+    ///   * `Err` - SqliteStoreError
+    pub fn open_in_memory() -> SqliteStoreResult<Self> {
+        let conn = Connection::open_in_memory().context(SqliteError {
+            msg: "unable to open database",
+        })?;
+        Self::from_connection(conn)
+    }
+
+    // Register the ltree functions, and create the schema if need be
+    fn from_connection(conn: Connection) -> SqliteStoreResult<Self> {
+        register_functions(&conn).context(SqliteError {
+            msg: "unable to register ltree functions",
+        })?;
+        // like is case sensitive in postgres
+        conn.execute_batch("PRAGMA foreign_keys = ON; PRAGMA case_sensitive_like = ON;")
+            .context(SqliteError {
+                msg: "unable to configure database",
+            })?;
+        let tables: i64 = conn
+            .query_row(
+                "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = 'versionpin'",
+                rusqlite::NO_PARAMS,
+                |row| row.get(0),
+            )
+            .context(SqliteError {
+                msg: "unable to inspect schema",
+            })?;
+        if tables == 0 {
+            conn.execute_batch(SCHEMA).context(SqliteError {
+                msg: "unable to create schema",
+            })?;
+        }
+        // stores exported before changes to withs were audited
+        let withs_columns: i64 = conn
+            .query_row(
+                "SELECT count(*) FROM pragma_table_info('vpin_audit') WHERE name = 'old_withs'",
+                rusqlite::NO_PARAMS,
+                |row| row.get(0),
+            )
+            .context(SqliteError {
+                msg: "unable to inspect schema",
+            })?;
+        if withs_columns == 0 {
+            conn.execute_batch(
+                "ALTER TABLE vpin_audit ADD COLUMN old_withs TEXT;
+                 ALTER TABLE vpin_audit ADD COLUMN new_withs TEXT;",
+            )
+            .context(SqliteError {
+                msg: "unable to upgrade schema",
+            })?;
+        }
+        Ok(Self { conn })
+    }
+
+    // The connection to the database
+    pub(crate) fn connection(&mut self) -> &mut Connection {
+        &mut self.conn
+    }
+}
+
+// Register the counterparts of the ltree functions and operators
+fn register_functions(conn: &Connection) -> rusqlite::Result<()> {
+    let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+    conn.create_scalar_function("nlevel", 1, flags, |ctx| {
+        Ok(nlevel(&ctx.get::<String>(0)?) as i64)
+    })?;
+    conn.create_scalar_function("path_name", 1, flags, |ctx| {
+        Ok(path_name(&ctx.get::<String>(0)?).to_string())
+    })?;
+    // subpath(path, offset, len) - the len labels of path starting at offset
+    conn.create_scalar_function("subpath", 3, flags, |ctx| {
+        let path = ctx.get::<String>(0)?;
+        let offset = ctx.get::<i64>(1)?.max(0) as usize;
+        let len = ctx.get::<i64>(2)?.max(0) as usize;
+        Ok(path
+            .split('.')
+            .skip(offset)
+            .take(len)
+            .collect::<Vec<_>>()
+            .join("."))
+    })?;
+    // the `@>` operator. ltree_isparent(a, b) is a @> b
+    conn.create_scalar_function("ltree_isparent", 2, flags, |ctx| {
+        Ok(is_ancestor(&ctx.get::<String>(0)?, &ctx.get::<String>(1)?))
+    })?;
+    // the path of a level, role, platform or site, given its name
+    conn.create_scalar_function("level_name_to_path", 1, flags, |ctx| {
+        Ok(level_name_to_path(&ctx.get::<String>(0)?))
+    })?;
+    conn.create_scalar_function("role_name_to_path", 1, flags, |ctx| {
+        Ok(role_name_to_path(&ctx.get::<String>(0)?))
+    })?;
+    conn.create_scalar_function("any_name_to_path", 1, flags, |ctx| {
+        Ok(any_name_to_path(&ctx.get::<String>(0)?))
+    })?;
+    Ok(())
+}
+
+/// Compare the path held in a column against a parameter, where `Ancestor`
+/// matches stored paths at, or above, the parameter and `Descendant` matches
+/// stored paths at, or below, it.
+///
+/// # Arguments
+/// * `column` - The column holding the stored path
+/// * `idx` - The index of the parameter holding the path searched for
+/// * `mode` - The search mode
+///
+/// # Returns
+/// * The sql expression
+pub(crate) fn ltree_clause(column: &str, idx: usize, mode: &LtreeSearchMode) -> String {
+    match mode {
+        LtreeSearchMode::Ancestor => format!("ltree_isparent({}, ?{})", column, idx),
+        LtreeSearchMode::Descendant => format!("ltree_isparent(?{}, {})", idx, column),
+        LtreeSearchMode::Exact => format!("{} = ?{}", column, idx),
+    }
+}
+
+/// Match the column against a parameter with `LIKE` if it holds a `%`,
+/// otherwise test them for equality.
+///
+/// # Arguments
+/// * `column` - The column to match
+/// * `idx` - The index of the parameter
+/// * `value` - The value of the parameter
+///
+/// # Returns
+/// * The sql expression
+pub(crate) fn like_clause(column: &str, idx: usize, value: &str) -> String {
+    if value.contains('%') {
+        format!("{} LIKE ?{} ESCAPE '\\'", column, idx)
+    } else {
+        format!("{} = ?{}", column, idx)
+    }
+}
+
+/// A sql statement along with the parameters it is to be executed with. The
+/// sqlite counterpart of `Prepared`
+#[derive(Debug)]
+pub(crate) struct SqlitePrepared {
+    pub(crate) sql: String,
+    pub(crate) params: Vec<Value>,
+}
+
+impl SqlitePrepared {
+    /// New up a SqlitePrepared instance without parameters
+    ///
+    /// # Arguments
+    /// * `sql` - The sql statement
+    pub(crate) fn new<I: Into<String>>(sql: I) -> Self {
+        Self {
+            sql: sql.into(),
+            params: Vec::new(),
+        }
+    }
+
+    /// Append a parameter, returning its placeholder index (eg 1 for `?1`)
+    ///
+    /// # Arguments
+    /// * `param` - The parameter
+    ///
+    /// # Returns
+    /// * The index of the parameter's placeholder
+    pub(crate) fn push<T: Into<Value>>(&mut self, param: T) -> usize {
+        self.params.push(param.into());
+        self.params.len()
+    }
+
+    /// Execute the query, converting each row with `f`
+    ///
+    /// # Arguments
+    /// * `store` - The store to query
+    /// * `f` - Converts a row
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - The converted rows
+    ///   * `Err` - rusqlite::Error
+    pub(crate) fn query<T, F>(&self, store: &mut SqliteStore, f: F) -> rusqlite::Result<Vec<T>>
+    where
+        F: FnMut(&Row<'_>) -> rusqlite::Result<T>,
+    {
+        log::info!("SQL\n{}", self.sql.as_str());
+        log::info!("Arguments\n{:?}", &self.params);
+        let mut stmt = store.connection().prepare(&self.sql)?;
+        let rows = stmt.query_map(self.params.iter(), f)?;
+        rows.collect()
+    }
+}
+
+impl Backend for SqliteStore {
+    fn find_versionpin(
+        find: &mut FindVersionPin<'_, Self>,
+    ) -> Result<FindVersionPinsRow, FindVersionPinError> {
+        find.query_sqlite()
+    }
+
+    fn find_versionpins(
+        find: &mut FindVersionPins<'_, Self>,
+    ) -> Result<Vec<FindVersionPinsRow>, Box<dyn std::error::Error>> {
+        find.query_sqlite()
+    }
+
+    fn find_all_versionpins(
+        find: &mut FindAllVersionPins<'_, Self>,
+    ) -> Result<Vec<FindAllVersionPinsRow>, FindAllVersionPinsError> {
+        find.query_sqlite()
+    }
+
+    fn find_all_roles(
+        find: &mut FindAllRoles<'_, Self>,
+    ) -> Result<Vec<FindAllRolesRow>, FindAllRolesError> {
+        find.query_sqlite()
+    }
+
+    fn find_all_revisions(
+        find: &mut FindAllRevisions<'_, Self>,
+    ) -> Result<Vec<FindAllRevisionsRow>, FindAllRevisionsError> {
+        find.query_sqlite()
+    }
+
+    fn find_all_changes(
+        find: &mut FindAllChanges<'_, Self>,
+    ) -> Result<Vec<FindAllChangesRow>, FindAllChangesError> {
+        find.query_sqlite()
+    }
+
+    fn find_all_platforms(
+        find: &mut FindAllPlatforms<'_, Self>,
+    ) -> Result<Vec<FindAllPlatformsRow>, FindAllPlatformsError> {
+        find.query_sqlite()
+    }
+
+    fn find_all_sites(
+        find: &mut FindAllSites<'_, Self>,
+    ) -> Result<Vec<FindAllSitesRow>, FindAllSitesError> {
+        find.query_sqlite()
+    }
+
+    fn find_all_levels(
+        find: &mut FindAllLevels<'_, Self>,
+    ) -> Result<Vec<FindAllLevelsRow>, FindAllLevelsError> {
+        find.query_sqlite()
+    }
+
+    fn find_all_packages(
+        find: &mut FindAllPackages<'_, Self>,
+    ) -> Result<Vec<FindAllPackagesRow>, FindAllPackagesError> {
+        find.query_sqlite()
+    }
+
+    fn find_all_versionpin_withs(
+        find: &mut FindAllWiths<'_, Self>,
+    ) -> Result<Vec<FindAllWithsRow>, FindAllWithsError> {
+        find.query_sqlite()
+    }
+
+    fn find_all_distributions(
+        find: &mut FindAllDistributions<'_, Self>,
+    ) -> Result<Vec<FindAllDistributionsRow>, FindAllDistributionsError> {
+        find.query_sqlite()
+    }
+
+    fn find_pins(find: &mut FindPins<'_, Self>) -> Result<Vec<FindPinsRow>, FindPinsError> {
+        find.query_sqlite()
+    }
+
+    fn find_pkgcoords(
+        find: &mut FindAllPkgCoords<'_, Self>,
+    ) -> Result<Vec<FindAllPkgCoordsRow>, FindAllPkgCoordsError> {
+        find.query_sqlite()
+    }
+
+    fn find_withs(find: &mut FindWiths<'_, Self>) -> Result<Vec<FindWithsRow>, FindWithsError> {
+        find.query_sqlite()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::export::ExportSqlite;
+    use super::*;
+    use crate::db::find_all::changes::OrderChangeBy;
+    use crate::db::memory::MemoryStore;
+    use crate::db::search_attribute::{OrderDirection, SearchMode};
+    use crate::packrat::PackratDb;
+    use crate::traits::PBFind;
+
+    // maya-2018.sp3 at facility, later updated to maya-2019, maya-2019 at
+    // dev01 for model, and houdini-18.0 at facility, with maya using houdini
+    fn memory_store() -> MemoryStore {
+        let mut store = MemoryStore::new();
+        let mut tx = store.transaction();
+        tx.add_packages(&["maya".to_string(), "houdini".to_string()]);
+        tx.add_levels(&["facility.dev01".to_string()]);
+        tx.add_roles(&["any.model".to_string()]);
+        tx.add_distributions(&[
+            ("maya".to_string(), "2018.sp3".to_string()),
+            ("maya".to_string(), "2019".to_string()),
+            ("houdini".to_string(), "18.0".to_string()),
+        ])
+        .unwrap();
+        tx.commit("jgerber", "setup");
+        let mut tx = store.transaction();
+        let maya = tx
+            .insert_versionpin("maya-2018.sp3", "facility", "any", "any", "any")
+            .unwrap();
+        tx.insert_versionpin("houdini-18.0", "facility", "any", "any", "any")
+            .unwrap();
+        tx.insert_versionpin("maya-2019", "dev01", "any", "model", "any")
+            .unwrap();
+        tx.set_withs(maya, &["houdini".to_string()]).unwrap();
+        tx.commit("jgerber", "pinned");
+        let mut tx = store.transaction();
+        tx.update_versionpin(maya, Some(2), None).unwrap();
+        tx.commit("jgerber", "updated maya");
+        store
+    }
+
+    fn stores() -> (MemoryStore, SqliteStore) {
+        let mut memory = memory_store();
+        let mut sqlite = SqliteStore::open_in_memory().unwrap();
+        ExportSqlite::new(&mut memory)
+            .export_into(&mut sqlite)
+            .unwrap();
+        (memory, sqlite)
+    }
+
+    #[test]
+    fn emulates_ltree_functions() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let (depth, name, sub, parent, other): (i64, String, String, bool, bool) = store
+            .connection()
+            .query_row(
+                "SELECT nlevel('facility.dev01.rd'), path_name('facility.dev01.rd'),
+                    subpath('facility.dev01.rd', 1, 1),
+                    ltree_isparent('facility.dev01', 'facility.dev01.rd'),
+                    ltree_isparent('facility.dev0', 'facility.dev01.rd')",
+                rusqlite::NO_PARAMS,
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(depth, 3);
+        assert_eq!(name, "dev01.rd");
+        assert_eq!(sub, "dev01");
+        assert!(parent);
+        assert!(!other);
+    }
+
+    #[test]
+    fn resolves_versionpins_like_the_memory_store() {
+        let (mut memory, mut sqlite) = stores();
+        let mut mem = PackratDb::new(&mut memory);
+        let mut db = PackratDb::new(&mut sqlite);
+        for (level, role) in &[
+            ("facility", "any"),
+            ("dev01.rd", "model_beta"),
+            ("dev02", "anim"),
+        ] {
+            let expected = mem
+                .find_versionpin("maya")
+                .level(level)
+                .role(role)
+                .query()
+                .unwrap();
+            let pin = db
+                .find_versionpin("maya")
+                .level(level)
+                .role(role)
+                .query()
+                .unwrap();
+            assert_eq!(pin.distribution, expected.distribution);
+            assert_eq!(pin.coords, expected.coords);
+            assert_eq!(pin.withs, expected.withs);
+        }
+        assert!(db.find_versionpin("nuke").query().is_err());
+    }
+
+    #[test]
+    fn searches_like_the_memory_store() {
+        let (mut memory, mut sqlite) = stores();
+        let mut mem = PackratDb::new(&mut memory);
+        let mut db = PackratDb::new(&mut sqlite);
+        for mode in &["ancestor", "descendant", "exact"] {
+            let mode = || mode.parse::<LtreeSearchMode>().unwrap();
+            let expected = mem
+                .find_all_versionpins()
+                .level("dev01")
+                .role("model")
+                .search_mode(mode())
+                .query()
+                .unwrap();
+            let pins = db
+                .find_all_versionpins()
+                .level("dev01")
+                .role("model")
+                .search_mode(mode())
+                .query()
+                .unwrap();
+            assert_eq!(pins, expected);
+            let expected = mem
+                .find_pkgcoords()
+                .level("facility")
+                .search_mode(SearchMode::Ltree(mode()))
+                .query()
+                .unwrap();
+            let coords = db
+                .find_pkgcoords()
+                .level("facility")
+                .search_mode(SearchMode::Ltree(mode()))
+                .query()
+                .unwrap();
+            assert_eq!(coords, expected);
+        }
+        assert_eq!(
+            db.find_all_distributions().query().unwrap(),
+            mem.find_all_distributions().query().unwrap()
+        );
+    }
+
+    #[test]
+    fn exports_revisions_and_changes() {
+        let (mut memory, mut sqlite) = stores();
+        let mut mem = PackratDb::new(&mut memory);
+        let mut db = PackratDb::new(&mut sqlite);
+        let revisions = db.find_all_revisions().query().unwrap();
+        assert_eq!(revisions, mem.find_all_revisions().query().unwrap());
+        for revision in &revisions {
+            assert_eq!(
+                db.find_all_changes()
+                    .transaction_id(revision.transaction_id)
+                    .query()
+                    .unwrap(),
+                mem.find_all_changes()
+                    .transaction_id(revision.transaction_id)
+                    .query()
+                    .unwrap()
+            );
+        }
+        let changes = db
+            .find_all_changes()
+            .package("maya")
+            .level("dev01")
+            .search_mode(LtreeSearchMode::Ancestor)
+            .since(revisions[0].datetime)
+            .order_by(vec![OrderChangeBy::Author])
+            .order_direction(OrderDirection::Desc)
+            .query()
+            .unwrap();
+        assert!(!changes.is_empty());
+        assert_eq!(
+            changes,
+            mem.find_all_changes()
+                .package("maya")
+                .level("dev01")
+                .search_mode(LtreeSearchMode::Ancestor)
+                .since(revisions[0].datetime)
+                .order_by(vec![OrderChangeBy::Author])
+                .order_direction(OrderDirection::Desc)
+                .query()
+                .unwrap()
+        );
+        let pin = db.find_versionpin("maya").query().unwrap();
+        assert_eq!(pin.distribution.version(), "2019");
+        let pin = db.find_versionpin("maya").as_of(2).query().unwrap();
+        assert_eq!(pin.distribution.version(), "2018.sp3");
+    }
+
+    #[test]
+    fn refuses_to_export_into_a_populated_store() {
+        let (mut memory, mut sqlite) = stores();
+        assert!(ExportSqlite::new(&mut memory)
+            .export_into(&mut sqlite)
+            .is_err());
+    }
+}
@@ -0,0 +1,181 @@
+-- The packrat schema, for SQLite.
+--
+-- Mirrors the postgres tables and views (see `migrations/`). Ltree paths are
+-- stored as `.` separated text, and the ltree functions used by the views
+-- (`nlevel`, `subpath`, `path_name` and `ltree_isparent`) are registered by the
+-- SqliteStore when the connection is opened.
+--
+-- The audit log is not replicated. Instead, `vpin_audit` holds the rows of
+-- `vpin_change_view` for each exported transaction.
+CREATE TABLE package (
+    name TEXT PRIMARY KEY
+);
+
+CREATE TABLE distribution (
+    id INTEGER PRIMARY KEY,
+    package TEXT NOT NULL REFERENCES package (name),
+    version TEXT NOT NULL,
+    UNIQUE (package, version)
+);
+
+CREATE TABLE level (
+    path TEXT PRIMARY KEY
+);
+
+CREATE TABLE role (
+    path TEXT PRIMARY KEY
+);
+
+CREATE TABLE platform (
+    path TEXT PRIMARY KEY
+);
+
+CREATE TABLE site (
+    path TEXT PRIMARY KEY
+);
+
+INSERT INTO level (path) VALUES ('facility');
+INSERT INTO role (path) VALUES ('any');
+INSERT INTO platform (path) VALUES ('any');
+INSERT INTO site (path) VALUES ('any');
+
+CREATE TABLE pkgcoord (
+    id INTEGER PRIMARY KEY,
+    package TEXT NOT NULL REFERENCES package (name),
+    level TEXT NOT NULL REFERENCES level (path),
+    role TEXT NOT NULL REFERENCES role (path),
+    platform TEXT NOT NULL REFERENCES platform (path),
+    site TEXT NOT NULL REFERENCES site (path),
+    UNIQUE (package, level, role, platform, site)
+);
+
+CREATE TABLE versionpin (
+    id INTEGER PRIMARY KEY,
+    distribution INTEGER NOT NULL REFERENCES distribution (id),
+    coord INTEGER NOT NULL UNIQUE REFERENCES pkgcoord (id)
+);
+
+CREATE TABLE withpackage (
+    id INTEGER PRIMARY KEY,
+    versionpin INTEGER NOT NULL REFERENCES versionpin (id),
+    package TEXT NOT NULL REFERENCES package (name),
+    pinorder INTEGER NOT NULL
+);
+
+CREATE INDEX withpackage_versionpin_idx ON withpackage (versionpin);
+
+-- datetime is stored as rfc3339 text
+CREATE TABLE revision (
+    id INTEGER PRIMARY KEY,
+    transaction_id INTEGER NOT NULL,
+    author TEXT NOT NULL,
+    comment TEXT NOT NULL,
+    datetime TEXT NOT NULL
+);
+
+CREATE INDEX revision_transaction_id_idx ON revision (transaction_id);
+
+-- The changes made to versionpins by each transaction, in the order they
+-- were made (by event_id). The withs before and after a change to the withs
+-- are stored in old_withs and new_withs as comma separated text; both are
+-- NULL for changes to the distribution.
+CREATE TABLE vpin_audit (
+    event_id INTEGER PRIMARY KEY,
+    id INTEGER NOT NULL,
+    transaction_id INTEGER NOT NULL,
+    action TEXT NOT NULL,
+    level_name TEXT NOT NULL,
+    role_name TEXT NOT NULL,
+    platform_name TEXT NOT NULL,
+    site_name TEXT NOT NULL,
+    package TEXT NOT NULL,
+    old TEXT NOT NULL,
+    new TEXT,
+    old_withs TEXT,
+    new_withs TEXT
+);
+
+CREATE INDEX vpin_audit_transaction_id_idx ON vpin_audit (transaction_id);
+
+CREATE VIEW level_view AS
+SELECT
+    path_name(path) AS name,
+    CASE
+        WHEN nlevel(path) = 1 THEN path
+        ELSE subpath(path, 1, 1)
+    END AS show,
+    path
+FROM level;
+
+CREATE VIEW role_view AS
+SELECT
+    replace(path_name(path), '.', '_') AS name,
+    CASE nlevel(path)
+        WHEN 1 THEN 'any'
+        WHEN 2 THEN 'role'
+        ELSE 'subrole'
+    END AS category,
+    path
+FROM role;
+
+CREATE VIEW platform_view AS
+SELECT path_name(path) AS name, path FROM platform;
+
+CREATE VIEW site_view AS
+SELECT path_name(path) AS name, path FROM site;
+
+CREATE VIEW distribution_view AS
+SELECT
+    id AS distribution_id,
+    package,
+    version AS version_name,
+    package || '-' || version AS name
+FROM distribution;
+
+CREATE VIEW pkgcoord_view AS
+SELECT
+    id AS pkgcoord_id,
+    package,
+    package AS package_name,
+    path_name(level) AS level_name,
+    replace(path_name(role), '.', '_') AS role_name,
+    path_name(platform) AS platform_name,
+    path_name(site) AS site_name,
+    level,
+    role,
+    platform,
+    site
+FROM pkgcoord;
+
+-- withs is a comma separated list, in pin order, or null
+CREATE VIEW versionpin_view AS
+SELECT
+    versionpin.id AS versionpin_id,
+    versionpin.distribution AS distribution_id,
+    versionpin.coord AS pkgcoord_id,
+    distribution_view.name AS distribution,
+    pkgcoord_view.package,
+    distribution_view.version_name,
+    pkgcoord_view.level_name AS level,
+    pkgcoord_view.role_name AS role,
+    pkgcoord_view.platform_name AS platform,
+    pkgcoord_view.site_name AS site,
+    pkgcoord_view.level AS level_path,
+    pkgcoord_view.role AS role_path,
+    pkgcoord_view.platform AS platform_path,
+    pkgcoord_view.site AS site_path,
+    (
+        SELECT group_concat(ordered.package, ',')
+        FROM (
+            SELECT withpackage.package
+            FROM withpackage
+            WHERE withpackage.versionpin = versionpin.id
+            ORDER BY withpackage.pinorder
+        ) AS ordered
+    ) AS withs
+FROM versionpin
+JOIN distribution_view ON versionpin.distribution = distribution_view.distribution_id
+JOIN pkgcoord_view ON versionpin.coord = pkgcoord_view.pkgcoord_id;
+
+CREATE VIEW revision_view AS
+SELECT id, transaction_id, author, comment, datetime FROM revision;
@@ -0,0 +1,265 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! Counterparts of the set returning functions which the find builders
+//! query (see `migrations/0003_views_and_functions.sql`), as queries against
+//! the views of a SqliteStore. They return the same rows as their
+//! MemoryStore counterparts.
+use super::{ltree_clause, SqlitePrepared, SqliteStore};
+use crate::db::memory::functions::version_ordering;
+use crate::db::memory::views::{
+    CoordPaths, DistributionView, PkgCoordView, VersionPinView, WithView,
+};
+use crate::db::memory::RevisionRecord;
+use crate::db::search_attribute::LtreeSearchMode;
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use rusqlite::types::Type;
+use rusqlite::Row;
+
+/// The columns of `versionpin_view` read by `versionpin_row`
+pub(crate) const VERSIONPIN_COLUMNS: &str =
+    "versionpin_id, distribution_id, pkgcoord_id, distribution, \
+     package, version_name, level, role, platform, site, level_path, role_path, platform_path, \
+     site_path, withs";
+
+// Level outranks role, which outranks platform, which outranks site
+const SPECIFICITY: &str =
+    "nlevel(level_path), nlevel(role_path), nlevel(platform_path), nlevel(site_path)";
+
+const SPECIFICITY_DESC: &str = "nlevel(level_path) DESC, nlevel(role_path) DESC, \
+     nlevel(platform_path) DESC, nlevel(site_path) DESC";
+
+/// Parse a datetime stored as rfc3339 text
+pub(crate) fn parse_datetime(row: &Row<'_>, idx: usize) -> rusqlite::Result<DateTime<Local>> {
+    let datetime: String = row.get(idx)?;
+    DateTime::parse_from_rfc3339(&datetime)
+        .map(|datetime| datetime.with_timezone(&Local))
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(idx, Type::Text, Box::new(err)))
+}
+
+/// Format a datetime as stored, in utc with a fixed precision, so that
+/// datetimes compare chronologically as text
+pub(crate) fn format_datetime(datetime: &DateTime<Local>) -> String {
+    datetime
+        .with_timezone(&Utc)
+        .to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+/// The columns of `distribution_view` read by `distribution_row`
+pub(crate) const DISTRIBUTION_COLUMNS: &str = "distribution_id, package, version_name, name";
+
+/// The columns of `pkgcoord_view` read by `pkgcoord_row`
+pub(crate) const PKGCOORD_COLUMNS: &str = "pkgcoord_id, package, level_name, role_name, \
+     platform_name, site_name, level, role, platform, site";
+
+/// Read a row of `versionpin_view`
+pub(crate) fn versionpin_row(row: &Row<'_>) -> rusqlite::Result<VersionPinView> {
+    let withs: Option<String> = row.get(14)?;
+    Ok(VersionPinView {
+        versionpin_id: row.get(0)?,
+        distribution_id: row.get(1)?,
+        pkgcoord_id: row.get(2)?,
+        distribution: row.get(3)?,
+        package: row.get(4)?,
+        version_name: row.get(5)?,
+        level: row.get(6)?,
+        role: row.get(7)?,
+        platform: row.get(8)?,
+        site: row.get(9)?,
+        level_path: row.get(10)?,
+        role_path: row.get(11)?,
+        platform_path: row.get(12)?,
+        site_path: row.get(13)?,
+        withs: match withs {
+            Some(withs) => withs.split(',').map(str::to_string).collect(),
+            None => Vec::new(),
+        },
+    })
+}
+
+/// Read a row of `distribution_view`
+pub(crate) fn distribution_row(row: &Row<'_>) -> rusqlite::Result<DistributionView> {
+    let version_name: String = row.get(2)?;
+    Ok(DistributionView {
+        distribution_id: row.get(0)?,
+        package: row.get(1)?,
+        version: version_ordering(&version_name),
+        version_name,
+        name: row.get(3)?,
+    })
+}
+
+/// Read a row of `pkgcoord_view`
+pub(crate) fn pkgcoord_row(row: &Row<'_>) -> rusqlite::Result<PkgCoordView> {
+    Ok(PkgCoordView {
+        pkgcoord_id: row.get(0)?,
+        package: row.get(1)?,
+        level_name: row.get(2)?,
+        role_name: row.get(3)?,
+        platform_name: row.get(4)?,
+        site_name: row.get(5)?,
+        level: row.get(6)?,
+        role: row.get(7)?,
+        platform: row.get(8)?,
+        site: row.get(9)?,
+    })
+}
+
+// The versionpins, optionally of a package and version, whose paths are
+// related to the supplied paths by the search mode in every dimension
+fn versionpins_at(
+    package: Option<&str>,
+    version: Option<&str>,
+    paths: &CoordPaths,
+    mode: &LtreeSearchMode,
+    orderby: &str,
+) -> SqlitePrepared {
+    let mut prepared = SqlitePrepared::new("");
+    let mut clauses = Vec::new();
+    for (column, path) in &[
+        ("level_path", &paths.level),
+        ("role_path", &paths.role),
+        ("platform_path", &paths.platform),
+        ("site_path", &paths.site),
+    ] {
+        let idx = prepared.push(path.to_string());
+        clauses.push(ltree_clause(column, idx, mode));
+    }
+    if let Some(package) = package {
+        let idx = prepared.push(package.to_string());
+        clauses.push(format!("package = ?{}", idx));
+    }
+    if let Some(version) = version {
+        let idx = prepared.push(version.to_string());
+        clauses.push(format!("version_name = ?{}", idx));
+    }
+    prepared.sql = format!(
+        "SELECT {} FROM versionpin_view WHERE {} ORDER BY {}",
+        VERSIONPIN_COLUMNS,
+        clauses.join(" AND "),
+        orderby
+    );
+    prepared
+}
+
+impl SqliteStore {
+    /// The rows of the revision table, in id order
+    pub(crate) fn revisions(&mut self) -> rusqlite::Result<Vec<RevisionRecord>> {
+        SqlitePrepared::new(
+            "SELECT id, transaction_id, author, comment, datetime FROM revision_view ORDER BY id",
+        )
+        .query(self, |row| {
+            Ok(RevisionRecord {
+                id: row.get(0)?,
+                transaction_id: row.get(1)?,
+                author: row.get(2)?,
+                comment: row.get(3)?,
+                datetime: parse_datetime(row, 4)?,
+            })
+        })
+    }
+
+    /// The rows of `distribution_view`
+    pub(crate) fn distribution_view(&mut self) -> rusqlite::Result<Vec<DistributionView>> {
+        SqlitePrepared::new(format!(
+            "SELECT {} FROM distribution_view ORDER BY distribution_id",
+            DISTRIBUTION_COLUMNS
+        ))
+        .query(self, distribution_row)
+    }
+
+    /// The rows of `pkgcoord_view`
+    pub(crate) fn pkgcoord_view(&mut self) -> rusqlite::Result<Vec<PkgCoordView>> {
+        SqlitePrepared::new(format!(
+            "SELECT {} FROM pkgcoord_view ORDER BY pkgcoord_id",
+            PKGCOORD_COLUMNS
+        ))
+        .query(self, pkgcoord_row)
+    }
+
+    /// The rows of `versionpin_view`
+    pub(crate) fn versionpin_view(&mut self) -> rusqlite::Result<Vec<VersionPinView>> {
+        SqlitePrepared::new(format!(
+            "SELECT {} FROM versionpin_view ORDER BY versionpin_id",
+            VERSIONPIN_COLUMNS
+        ))
+        .query(self, versionpin_row)
+    }
+
+    /// The counterpart of `search_distributions`. The versionpins of a
+    /// package whose paths are equal to, or ancestors of, the supplied paths
+    /// in every dimension, from least to most specific.
+    pub(crate) fn search_distributions(
+        &mut self,
+        package: &str,
+        paths: &CoordPaths,
+    ) -> rusqlite::Result<Vec<VersionPinView>> {
+        let orderby = format!("{}, versionpin_id", SPECIFICITY);
+        versionpins_at(
+            Some(package),
+            None,
+            paths,
+            &LtreeSearchMode::Ancestor,
+            &orderby,
+        )
+        .query(self, versionpin_row)
+    }
+
+    /// The counterpart of `find_distribution_and_withs`. The most specific
+    /// candidate, if there is one.
+    pub(crate) fn find_distribution_and_withs(
+        &mut self,
+        package: &str,
+        paths: &CoordPaths,
+    ) -> rusqlite::Result<Option<VersionPinView>> {
+        let orderby = format!("{}, versionpin_id LIMIT 1", SPECIFICITY_DESC);
+        let pins = versionpins_at(
+            Some(package),
+            None,
+            paths,
+            &LtreeSearchMode::Ancestor,
+            &orderby,
+        )
+        .query(self, versionpin_row)?;
+        Ok(pins.into_iter().next())
+    }
+
+    /// The counterpart of `find_distribution_withs`. The most specific
+    /// versionpin of each of the withs of a package, resolved at the same
+    /// paths, in pin order. Withs without a versionpin are left out.
+    pub(crate) fn find_distribution_withs(
+        &mut self,
+        package: &str,
+        paths: &CoordPaths,
+    ) -> rusqlite::Result<Vec<WithView>> {
+        let withs = self
+            .find_distribution_and_withs(package, paths)?
+            .map(|pin| pin.withs)
+            .unwrap_or_default();
+        let mut result = Vec::new();
+        for with in withs {
+            if let Some(pin) = self.find_distribution_and_withs(&with, paths)? {
+                result.push(WithView { pin, package: with });
+            }
+        }
+        Ok(result)
+    }
+
+    /// The counterpart of `findall_versionpins`. Every versionpin related to
+    /// the supplied paths by the search mode, optionally restricted to a
+    /// package and version.
+    pub(crate) fn findall_versionpins(
+        &mut self,
+        paths: &CoordPaths,
+        mode: &LtreeSearchMode,
+        package: Option<&str>,
+        version: Option<&str>,
+    ) -> rusqlite::Result<Vec<VersionPinView>> {
+        versionpins_at(package, version, paths, mode, "versionpin_id").query(self, versionpin_row)
+    }
+}
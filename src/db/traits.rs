@@ -1,67 +1,128 @@
-use crate::db::{add, find, find_all, update};
+use crate::db::add::versionpins::ConflictPolicy;
+use crate::db::{add, delete, find, find_all, update};
 use crate::types::IdType;
 pub use postgres::Transaction;
 
 pub trait PBFind {
-    fn find_versionpin<'b>(&'b mut self, package: &'b str) -> find::versionpin::FindVersionPin;
+    /// The client the find builders query with. Either a `Backend` (a
+    /// postgres::Client or a MemoryStore) or an AsyncClient
+    type Client;
 
-    fn find_all_versionpins<'b>(&'b mut self) -> find_all::versionpins::FindAllVersionPins;
+    fn find_versionpin<'b>(
+        &'b mut self,
+        package: &'b str,
+    ) -> find::versionpin::FindVersionPin<'b, Self::Client>;
 
-    fn find_all_roles<'b>(&'b mut self) -> find_all::roles::FindAllRoles;
+    fn find_all_versionpins<'b>(
+        &'b mut self,
+    ) -> find_all::versionpins::FindAllVersionPins<'b, Self::Client>;
 
-    fn find_all_revisions<'b>(&'b mut self) -> find_all::revisions::FindAllRevisions;
+    fn find_all_roles<'b>(&'b mut self) -> find_all::roles::FindAllRoles<'b, Self::Client>;
 
-    fn find_all_changes<'b>(&'b mut self) -> find_all::changes::FindAllChanges;
+    fn find_all_revisions<'b>(
+        &'b mut self,
+    ) -> find_all::revisions::FindAllRevisions<'b, Self::Client>;
 
-    fn find_all_platforms<'b>(&'b mut self) -> find_all::platforms::FindAllPlatforms;
+    fn find_all_changes<'b>(&'b mut self) -> find_all::changes::FindAllChanges<'b, Self::Client>;
 
-    fn find_all_sites<'b>(&'b mut self) -> find_all::sites::FindAllSites;
+    fn find_all_platforms<'b>(
+        &'b mut self,
+    ) -> find_all::platforms::FindAllPlatforms<'b, Self::Client>;
 
-    fn find_all_levels<'b>(&'b mut self) -> find_all::levels::FindAllLevels;
+    fn find_all_sites<'b>(&'b mut self) -> find_all::sites::FindAllSites<'b, Self::Client>;
 
-    fn find_all_packages<'b>(&'b mut self) -> find_all::packages::FindAllPackages;
+    fn find_all_levels<'b>(&'b mut self) -> find_all::levels::FindAllLevels<'b, Self::Client>;
+
+    fn find_all_packages<'b>(&'b mut self)
+        -> find_all::packages::FindAllPackages<'b, Self::Client>;
 
     fn find_all_versionpin_withs<'b>(
         &'b mut self,
         versionpin_id: IdType,
-    ) -> find_all::versionpin_withs::FindAllWiths;
+    ) -> find_all::versionpin_withs::FindAllWiths<'b, Self::Client>;
 
-    fn find_versionpins<'b>(&'b mut self, package: &'b str) -> find::versionpins::FindVersionPins;
+    fn find_versionpins<'b>(
+        &'b mut self,
+        package: &'b str,
+    ) -> find::versionpins::FindVersionPins<'b, Self::Client>;
 
-    fn find_all_distributions<'b>(&'b mut self) -> find_all::distributions::FindAllDistributions;
+    fn find_all_distributions<'b>(
+        &'b mut self,
+    ) -> find_all::distributions::FindAllDistributions<'b, Self::Client>;
 
-    fn find_pins<'b>(&'b mut self) -> find::pins::FindPins;
+    fn find_pins<'b>(&'b mut self) -> find::pins::FindPins<'b, Self::Client>;
 
-    fn find_pkgcoords<'b>(&'b mut self) -> find_all::pkgcoords::FindAllPkgCoords;
+    fn find_pkgcoords<'b>(&'b mut self) -> find_all::pkgcoords::FindAllPkgCoords<'b, Self::Client>;
 
-    fn find_withs<'b>(&'b mut self, package: &'b str) -> find::withs::FindWiths;
+    fn find_withs<'b>(&'b mut self, package: &'b str) -> find::withs::FindWiths<'b, Self::Client>;
 }
 
 pub trait PBAdd<'b> {
     type TransactionType;
 
-    fn add_packages(tx: Self::TransactionType) -> add::packages::AddPackages<'b>;
+    fn add_packages(
+        tx: Self::TransactionType,
+    ) -> add::packages::AddPackages<'b, Self::TransactionType>;
+
+    fn add_levels(tx: Self::TransactionType) -> add::levels::AddLevels<'b, Self::TransactionType>;
 
-    fn add_levels(tx: Self::TransactionType) -> add::levels::AddLevels<'b>;
+    fn add_roles(tx: Self::TransactionType) -> add::roles::AddRoles<'b, Self::TransactionType>;
 
-    fn add_roles(tx: Self::TransactionType) -> add::roles::AddRoles<'b>;
+    fn add_platforms(
+        tx: Self::TransactionType,
+    ) -> add::platforms::AddPlatforms<'b, Self::TransactionType>;
 
-    fn add_platforms(tx: Self::TransactionType) -> add::platforms::AddPlatforms<'b>;
+    fn add_sites(tx: Self::TransactionType) -> add::sites::AddSites<'b, Self::TransactionType>;
 
-    fn add_withs(tx: Self::TransactionType) -> add::withs::AddWiths<'b>;
+    fn add_distributions(
+        tx: Self::TransactionType,
+    ) -> add::distributions::AddDistributions<'b, Self::TransactionType>;
+
+    fn add_withs(tx: Self::TransactionType) -> add::withs::AddWiths<'b, Self::TransactionType>;
 
     fn add_versionpins<I>(
         tx: Self::TransactionType,
         package: I,
         version: I,
-    ) -> add::versionpins::AddVersionPins<'b>
+        policy: ConflictPolicy,
+    ) -> add::versionpins::AddVersionPins<'b, Self::TransactionType>
     where
         I: Into<String>;
 }
 
 pub trait PBUpdate<'a> {
     type TransactionType;
-    fn update_versionpins(tx: Self::TransactionType) -> update::versionpins::UpdateVersionPins<'a>;
+    fn update_versionpins(
+        tx: Self::TransactionType,
+    ) -> update::versionpins::UpdateVersionPins<'a, Self::TransactionType>;
+}
+
+pub trait PBDelete<'a> {
+    type TransactionType;
+
+    fn delete_versionpins(
+        tx: Self::TransactionType,
+    ) -> delete::versionpins::DeleteVersionPins<'a, Self::TransactionType>;
+
+    fn delete_withs(
+        tx: Self::TransactionType,
+    ) -> delete::withs::DeleteWiths<'a, Self::TransactionType>;
+
+    fn delete_distributions(
+        tx: Self::TransactionType,
+    ) -> delete::distributions::DeleteDistributions<'a, Self::TransactionType>;
+
+    fn delete_packages(
+        tx: Self::TransactionType,
+    ) -> delete::packages::DeletePackages<'a, Self::TransactionType>;
+
+    fn delete_levels(
+        tx: Self::TransactionType,
+    ) -> delete::levels::DeleteLevels<'a, Self::TransactionType>;
+
+    fn delete_roles(
+        tx: Self::TransactionType,
+    ) -> delete::roles::DeleteRoles<'a, Self::TransactionType>;
 }
 
 pub trait PBExport<'a> {
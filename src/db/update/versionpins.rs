@@ -2,12 +2,15 @@
 pbk update versionpins --versionpin 22 --distribution 22 --pkgcoord 84 -v 432 -d 22 -p 32
 */
 //use itertools::Itertools;
-use crate::traits::TransactionHandler;
+use crate::db::async_packrat::AsyncTransaction;
+use crate::db::memory::{MemoryStoreError, MemoryTransaction};
+use crate::db::plan::{self, ChangePlan, ChangePlanError};
+use crate::db::prepared::Prepared;
+use crate::traits::{AsyncTransactionHandler, MemoryTransactionHandler, TransactionHandler};
 use crate::types::IdType;
-use log;
-use postgres::types::ToSql;
 use postgres::Transaction;
 use snafu::{ResultExt, Snafu};
+use std::marker::PhantomData;
 /// Error type returned from FindVersionPinsError
 #[derive(Debug, Snafu)]
 pub enum UpdateVersionPinsError {
@@ -19,6 +22,12 @@ pub enum UpdateVersionPinsError {
     },
     #[snafu(display("No update data supplied"))]
     NoUpdatesError,
+    /// The MemoryStore has rejected a change
+    #[snafu(display("MemoryStore Error: {}", source))]
+    MemoryError { source: MemoryStoreError },
+    /// The changes made by a preview could not be read back
+    #[snafu(display("Problem retrieving change plan: {}", source))]
+    PlanError { source: ChangePlanError },
 }
 /// Models a change to a versionpin as optional new distribution and/or
 /// pkgcoord_ids
@@ -65,11 +74,12 @@ impl VersionPinChange {
 }
 
 /// Responsible for creating packages
-pub struct UpdateVersionPins<'a> {
-    tx: Option<Transaction<'a>>,
+pub struct UpdateVersionPins<'a, T = Transaction<'a>> {
+    tx: Option<T>,
     /// vector of VersionPinChanges which will be applied to the database
     pub changes: Vec<VersionPinChange>,
     result_cnt: u64,
+    phantom: PhantomData<&'a ()>,
 }
 
 impl<'a> TransactionHandler<'a> for UpdateVersionPins<'a> {
@@ -94,17 +104,61 @@ impl<'a> TransactionHandler<'a> for UpdateVersionPins<'a> {
     }
 }
 
-impl<'a> UpdateVersionPins<'a> {
+impl<'a> AsyncTransactionHandler<'a> for UpdateVersionPins<'a, AsyncTransaction<'a>> {
+    type Error = tokio_postgres::error::Error;
+    /// retrieve an Option wrapped mutable reference to the
+    /// transaction
+    fn tx(&mut self) -> Option<&mut AsyncTransaction<'a>> {
+        self.tx.as_mut()
+    }
+    /// Extract the transaction from Self.
+    fn take_tx(&mut self) -> AsyncTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    /// Return the result count to 0
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+    /// Retrieve th result count
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a> MemoryTransactionHandler<'a> for UpdateVersionPins<'a, MemoryTransaction<'a>> {
+    /// retrieve an Option wrapped mutable reference to the
+    /// transaction
+    fn tx(&mut self) -> Option<&mut MemoryTransaction<'a>> {
+        self.tx.as_mut()
+    }
+    /// Extract the transaction from Self.
+    fn take_tx(&mut self) -> MemoryTransaction<'a> {
+        self.tx.take().unwrap()
+    }
+
+    /// Return the result count to 0
+    fn reset_result_cnt(&mut self) {
+        self.result_cnt = 0;
+    }
+    /// Retrieve th result count
+    fn get_result_cnt(&self) -> u64 {
+        self.result_cnt
+    }
+}
+
+impl<'a, T> UpdateVersionPins<'a, T> {
     /// new up an UpdateVersionPins instance
     ///
     /// # Arguments
     ///
     /// * `tx` - A Transaction instance
-    pub fn new(tx: Transaction<'a>) -> Self {
+    pub fn new(tx: T) -> Self {
         Self {
             tx: Some(tx),
             changes: Vec::new(),
             result_cnt: 0,
+            phantom: PhantomData,
         }
     }
 
@@ -158,52 +212,144 @@ impl<'a> UpdateVersionPins<'a> {
         self
     }
 
-    /// Inject updates into the internal transaction. The database update is deferred
-    /// until one calls self.commit(...)
-    pub fn update(mut self) -> Result<Self, UpdateVersionPinsError> {
-        let mut update_cnt = 0;
-        let changes = {
-            let mut empty = Vec::new();
-            std::mem::swap(&mut empty, &mut self.changes);
-            empty
-        };
-        for x in &changes {
+    // build an update statement for each change which has changes
+    fn prepare(changes: &[VersionPinChange]) -> Vec<Prepared> {
+        let mut statements = Vec::new();
+        for x in changes {
             if x.has_changes() {
                 let mut maybe_comma = String::from("");
-                update_cnt += 1;
-                let mut updates_ref: Vec<&(dyn ToSql + Sync)> = Vec::new();
-                let mut prepared_line = "UPDATE versionpin ".to_string();
-                let mut pos_idx = 2;
-                updates_ref.push(&x.versionpin_id);
-                if let Some(ref dist_id) = x.distribution_id {
-                    updates_ref.push(dist_id);
-                    prepared_line.push_str(
+                let mut prepared = Prepared::new("UPDATE versionpin ");
+                prepared.push(x.versionpin_id);
+                if let Some(dist_id) = x.distribution_id {
+                    let pos_idx = prepared.push(dist_id);
+                    prepared.sql.push_str(
                         format!("{}SET distribution = ${}", maybe_comma, pos_idx).as_str(),
                     );
-                    pos_idx += 1;
                     maybe_comma.push_str(",");
                 }
-                if let Some(ref pkgcoord_id) = x.pkgcoord_id {
-                    updates_ref.push(pkgcoord_id);
-                    prepared_line
+                if let Some(pkgcoord_id) = x.pkgcoord_id {
+                    let pos_idx = prepared.push(pkgcoord_id);
+                    prepared
+                        .sql
                         .push_str(format!("{}SET coord = ${}", maybe_comma, pos_idx).as_str());
                 }
-                prepared_line.push_str(" WHERE id = $1");
-                log::info!("SQL\n{}", prepared_line.as_str());
-                log::info!("Prepared\n{:?}", &updates_ref);
-
-                // todo guard against possible emp
-                {
-                    self.tx()
-                        .unwrap()
-                        .execute(prepared_line.as_str(), &updates_ref[..])
-                        .context(TokioPostgresError {
-                            msg: "failed to execute statement in transaction",
-                        })?;
-                }
+                prepared.sql.push_str(" WHERE id = $1");
+                statements.push(prepared);
             }
         }
-        self.result_cnt = update_cnt;
+        statements
+    }
+
+    // take the registered changes, leaving self.changes empty
+    fn take_changes(&mut self) -> Vec<VersionPinChange> {
+        let mut empty = Vec::new();
+        std::mem::swap(&mut empty, &mut self.changes);
+        empty
+    }
+}
+
+impl<'a> UpdateVersionPins<'a> {
+    /// Inject updates into the internal transaction. The database update is deferred
+    /// until one calls self.commit(...)
+    pub fn update(mut self) -> Result<Self, UpdateVersionPinsError> {
+        let statements = Self::prepare(&self.take_changes());
+        self.result_cnt = statements.len() as u64;
+        for prepared in statements {
+            prepared
+                .execute(self.tx().unwrap())
+                .context(TokioPostgresError {
+                    msg: "failed to execute statement in transaction",
+                })?;
+        }
         Ok(self)
     }
+
+    /// Update the versionpins within the transaction, as `update` does, and
+    /// report the old and new distribution and coords of each before rolling
+    /// the transaction back.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(UpdateVersionPinsError)
+    pub fn preview(mut self) -> Result<ChangePlan, UpdateVersionPinsError> {
+        let watermark = plan::watermark(self.tx().unwrap()).context(PlanError)?;
+        let mut pins = self.update()?;
+        let plan = plan::changes_since(pins.tx().unwrap(), watermark).context(PlanError)?;
+        pins.rollback().context(TokioPostgresError {
+            msg: "failed to roll back transaction",
+        })?;
+        Ok(plan)
+    }
+}
+
+impl<'a> UpdateVersionPins<'a, AsyncTransaction<'a>> {
+    /// Inject updates into the internal transaction. The database update is deferred
+    /// until one calls self.commit(...)
+    pub async fn update(mut self) -> Result<Self, UpdateVersionPinsError> {
+        let statements = Self::prepare(&self.take_changes());
+        self.result_cnt = statements.len() as u64;
+        for prepared in statements {
+            prepared
+                .execute_async(self.tx().unwrap())
+                .await
+                .context(TokioPostgresError {
+                    msg: "failed to execute statement in transaction",
+                })?;
+        }
+        Ok(self)
+    }
+
+    /// Update the versionpins within the transaction, as `update` does, and
+    /// report the old and new distribution and coords of each before rolling
+    /// the transaction back.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(UpdateVersionPinsError)
+    pub async fn preview(mut self) -> Result<ChangePlan, UpdateVersionPinsError> {
+        let watermark = plan::watermark_async(self.tx().unwrap())
+            .await
+            .context(PlanError)?;
+        let mut pins = self.update().await?;
+        let plan = plan::changes_since_async(pins.tx().unwrap(), watermark)
+            .await
+            .context(PlanError)?;
+        pins.rollback().await.context(TokioPostgresError {
+            msg: "failed to roll back transaction",
+        })?;
+        Ok(plan)
+    }
+}
+
+impl<'a> UpdateVersionPins<'a, MemoryTransaction<'a>> {
+    /// Inject updates into the internal transaction. The database update is deferred
+    /// until one calls self.commit(...)
+    pub fn update(mut self) -> Result<Self, UpdateVersionPinsError> {
+        let changes = self.take_changes();
+        let tx = self.tx().unwrap();
+        let mut result_cnt = 0;
+        for change in changes.iter().filter(|change| change.has_changes()) {
+            result_cnt += tx
+                .update_versionpin(
+                    change.versionpin_id,
+                    change.distribution_id,
+                    change.pkgcoord_id,
+                )
+                .context(MemoryError)?;
+        }
+        self.result_cnt = result_cnt;
+        Ok(self)
+    }
+
+    /// Update the versionpins within the transaction, as `update` does, and
+    /// report the old and new distribution and coords of each before
+    /// discarding the transaction.
+    ///
+    /// # Returns
+    /// * Ok(ChangePlan) | Err(UpdateVersionPinsError)
+    pub fn preview(mut self) -> Result<ChangePlan, UpdateVersionPinsError> {
+        let watermark = plan::watermark_memory(self.tx().unwrap());
+        let mut pins = self.update()?;
+        let plan = plan::changes_since_memory(pins.tx().unwrap(), watermark).context(PlanError)?;
+        pins.rollback();
+        Ok(plan)
+    }
 }
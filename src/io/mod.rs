@@ -7,4 +7,5 @@
  * permission of Jonathan Gerber
  *******************************************************/
 pub mod packages_xml;
+pub mod render;
 pub use packages_xml::*;
@@ -6,11 +6,16 @@
  * packybara can not be copied and/or distributed without the express
  * permission of Jonathan Gerber
  *******************************************************/
-//! Structures designed to generate packages.xml
+//! Structures designed to generate packages.xml, along with a reader which
+//! parses a packages.xml back into versionpins.
 //!
+use crate::coords_error::CoordsError;
+use crate::{Coords, Distribution, Platform, Site, VersionPin};
 use simple_xml_serialize::XMLElement;
 use simple_xml_serialize_macro::xml_element;
+use snafu::{ResultExt, Snafu};
 use std::mem;
+use std::path::Path;
 /// Determine if an XML element is closed (eg <foo />)
 pub trait IsClosed {
     /// Indicates whether or not a Xml Node has contents or not
@@ -32,6 +37,8 @@ pub struct Show {
     packages: Packages,
     #[sxs_type_element(rename = "roles")]
     roles: Roles,
+    #[sxs_type_element(rename = "sequences")]
+    sequences: Sequences,
 }
 
 impl Show {
@@ -48,6 +55,7 @@ impl Show {
             name: show.into(),
             packages: Packages::new(),
             roles: Roles::new(),
+            sequences: Sequences::new(),
         }
     }
 
@@ -102,6 +110,69 @@ impl Show {
         self.roles.push(role);
         self
     }
+
+    /// Add a Sequence instance to the list of sequences in the show
+    ///
+    /// # Arguments
+    ///
+    /// * `sequence` - A Sequence instance
+    ///
+    /// # Returns
+    /// * None
+    pub fn add_sequence(&mut self, sequence: Sequence) {
+        self.sequences.push(sequence)
+    }
+
+    /// Add a versionpin to the show, placing its package within the
+    /// sequence, shot and role elements matching its coords. Sequences, shots
+    /// and roles are created as needed, and reused by subsequent versionpins.
+    /// The platform and site become the `os` and `site` attributes of the
+    /// package, unless they are any.
+    ///
+    /// # Arguments
+    ///
+    /// * `pin` - The versionpin, along with its withs
+    ///
+    /// # Returns
+    /// * None
+    pub fn add_versionpin(&mut self, pin: &XmlVersionPin) {
+        let coords = &pin.versionpin.coords;
+        let distribution = &pin.versionpin.distribution;
+        let mut package =
+            Package::from_name_and_version(distribution.package(), distribution.version());
+        for with in &pin.withs {
+            package.add_with(With::new(with.as_str()));
+        }
+        if coords.platform() != &Platform::Any {
+            package.set_os(Some(coords.platform().to_string()));
+        }
+        if coords.site() != &Site::Any {
+            package.set_site(Some(coords.site().to_string()));
+        }
+        let role = if coords.role().is_any() {
+            None
+        } else {
+            Some(coords.role().to_string())
+        };
+        // the first piece of the level is the show
+        let level = coords.level().to_string();
+        let mut pieces = level.split('.').skip(1);
+        let (packages, roles) = match (pieces.next(), pieces.next()) {
+            (None, _) => (&mut self.packages, &mut self.roles),
+            (Some(sequence), None) => {
+                let sequence = self.sequences.sequence_mut(sequence);
+                (&mut sequence.packages, &mut sequence.roles)
+            }
+            (Some(sequence), Some(shot)) => {
+                let shot = self.sequences.sequence_mut(sequence).shots.shot_mut(shot);
+                (&mut shot.packages, &mut shot.roles)
+            }
+        };
+        match role {
+            Some(role) => roles.role_mut(role).add_package(package),
+            None => packages.push(package),
+        }
+    }
 }
 
 /// Element which represents a parent tag whose contents is a list of packages.
@@ -332,6 +403,17 @@ impl Roles {
         self.role.push(role)
     }
 
+    // retrieve the role with the supplied name, adding it if it does not exist
+    fn role_mut(&mut self, name: String) -> &mut Role {
+        match self.role.iter().position(|role| role.name == name) {
+            Some(idx) => &mut self.role[idx],
+            None => {
+                self.role.push(Role::new(name));
+                self.role.last_mut().unwrap()
+            }
+        }
+    }
+
     pub fn last_role(&self) -> Option<&str> {
         let role_len = self.role.len();
         if role_len == 0 {
@@ -396,6 +478,220 @@ impl Role {
     }
 }
 
+/// The element whose contents is a list of Sequence instances.
+/// (ie <sequence>...</sequence>)
+#[xml_element("sequence")]
+pub struct Sequences {
+    #[sxs_type_multi_element(rename = "sequence")]
+    sequence: Vec<Sequence>,
+}
+
+impl Default for Sequences {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sequences {
+    /// New up an instance of Sequences
+    pub fn new() -> Sequences {
+        Sequences {
+            sequence: Vec::new(),
+        }
+    }
+
+    /// Add a sequence into the list of child sequences
+    ///
+    /// # Arguments
+    ///
+    /// * `sequence` - An instance of type Sequence
+    pub fn push(&mut self, sequence: Sequence) {
+        self.sequence.push(sequence)
+    }
+
+    // retrieve the sequence with the supplied name, adding it if it does not exist
+    fn sequence_mut(&mut self, name: &str) -> &mut Sequence {
+        match self.sequence.iter().position(|seq| seq.name == name) {
+            Some(idx) => &mut self.sequence[idx],
+            None => {
+                self.sequence.push(Sequence::new(name));
+                self.sequence.last_mut().unwrap()
+            }
+        }
+    }
+}
+
+/// Sequence element contains the packages and roles pinned at a sequence
+/// level (eg dev01.rd), along with its shots
+#[xml_element("sequence")]
+pub struct Sequence {
+    /// Name of the Sequence, without the show (eg rd)
+    #[sxs_type_attr]
+    name: String,
+    /// packages tag
+    #[sxs_type_element(rename = "packages")]
+    packages: Packages,
+    /// roles tag
+    #[sxs_type_element(rename = "roles")]
+    roles: Roles,
+    /// shots tag
+    #[sxs_type_element(rename = "shots")]
+    shots: Shots,
+}
+
+impl Sequence {
+    /// New up a Sequence instance
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the sequence, without the show
+    ///
+    /// # Returns
+    /// * Sequence instance
+    pub fn new<I: Into<String>>(name: I) -> Self {
+        Self {
+            name: name.into(),
+            packages: Packages::new(),
+            roles: Roles::new(),
+            shots: Shots::new(),
+        }
+    }
+
+    /// Add a Package instance to the list of packages in the sequence
+    ///
+    /// # Arguments
+    ///
+    /// * `package` - Package instance
+    ///
+    /// # Returns
+    /// * None
+    pub fn add_package(&mut self, package: Package) {
+        self.packages.push(package);
+    }
+
+    /// Add a Role instance to the list of roles in the sequence
+    ///
+    /// # Arguments
+    ///
+    /// * `role` - A Role instance
+    ///
+    /// # Returns
+    /// * None
+    pub fn add_role(&mut self, role: Role) {
+        self.roles.push(role)
+    }
+
+    /// Add a Shot instance to the list of shots in the sequence
+    ///
+    /// # Arguments
+    ///
+    /// * `shot` - A Shot instance
+    ///
+    /// # Returns
+    /// * None
+    pub fn add_shot(&mut self, shot: Shot) {
+        self.shots.push(shot)
+    }
+}
+
+/// The element whose contents is a list of Shot instances.
+/// (ie <shot>...</shot>)
+#[xml_element("shot")]
+pub struct Shots {
+    #[sxs_type_multi_element(rename = "shot")]
+    shot: Vec<Shot>,
+}
+
+impl Default for Shots {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shots {
+    /// New up an instance of Shots
+    pub fn new() -> Shots {
+        Shots { shot: Vec::new() }
+    }
+
+    /// Add a shot into the list of child shots
+    ///
+    /// # Arguments
+    ///
+    /// * `shot` - An instance of type Shot
+    pub fn push(&mut self, shot: Shot) {
+        self.shot.push(shot)
+    }
+
+    // retrieve the shot with the supplied name, adding it if it does not exist
+    fn shot_mut(&mut self, name: &str) -> &mut Shot {
+        match self.shot.iter().position(|shot| shot.name == name) {
+            Some(idx) => &mut self.shot[idx],
+            None => {
+                self.shot.push(Shot::new(name));
+                self.shot.last_mut().unwrap()
+            }
+        }
+    }
+}
+
+/// Shot element contains the packages and roles pinned at a shot
+/// level (eg dev01.rd.9999)
+#[xml_element("shot")]
+pub struct Shot {
+    /// Name of the Shot, without the show or sequence (eg 9999)
+    #[sxs_type_attr]
+    name: String,
+    /// packages tag
+    #[sxs_type_element(rename = "packages")]
+    packages: Packages,
+    /// roles tag
+    #[sxs_type_element(rename = "roles")]
+    roles: Roles,
+}
+
+impl Shot {
+    /// New up a Shot instance
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the shot, without the show or sequence
+    ///
+    /// # Returns
+    /// * Shot instance
+    pub fn new<I: Into<String>>(name: I) -> Self {
+        Self {
+            name: name.into(),
+            packages: Packages::new(),
+            roles: Roles::new(),
+        }
+    }
+
+    /// Add a Package instance to the list of packages in the shot
+    ///
+    /// # Arguments
+    ///
+    /// * `package` - Package instance
+    ///
+    /// # Returns
+    /// * None
+    pub fn add_package(&mut self, package: Package) {
+        self.packages.push(package);
+    }
+
+    /// Add a Role instance to the list of roles in the shot
+    ///
+    /// # Arguments
+    ///
+    /// * `role` - A Role instance
+    ///
+    /// # Returns
+    /// * None
+    pub fn add_role(&mut self, role: Role) {
+        self.roles.push(role)
+    }
+}
+
 /// Converter which generates an XMLElement Tree from an entry which impls  Into<XMLElement>
 ///
 /// # Example
@@ -466,11 +762,21 @@ impl ToXml {
 
     // Given an XMLElement node, prune its closed contents. This is used
     // to remove empty roles in the case that it is empty in the Snow element.alloc//
+    // Sequences and shots hold packages and roles of their own, so they
+    // are pruned first, in turn.
     //
     // # Arguments
     //
     // * `elem` - mutable reference to an XMLElement
     fn prune_closed_contents(elem: &mut XMLElement) {
+        if let Some(contents) = elem.contents.as_mut() {
+            for child in contents
+                .iter_mut()
+                .filter(|x| ["sequences", "sequence", "shots", "shot"].contains(&x.name.as_str()))
+            {
+                Self::prune_closed_contents(child);
+            }
+        }
         if elem.contents.is_some() {
             let contents = elem.contents.take().unwrap();
             let contents = contents
@@ -487,6 +793,218 @@ impl ToXml {
     }
 }
 
+/// Error type returned when reading a packages.xml
+#[derive(Debug, Snafu)]
+pub enum ReadXmlError {
+    /// Unable to read the file from disk
+    #[snafu(display("Unable to read {}: {}", path, source))]
+    ReadFileError {
+        path: String,
+        source: std::io::Error,
+    },
+    /// The contents are not well formed xml
+    #[snafu(display("Unable to parse packages.xml: {}", source))]
+    XmlSyntaxError { source: roxmltree::Error },
+    /// An element which does not belong in packages.xml, or which is
+    /// in the wrong place
+    #[snafu(display("Unexpected <{}> element in <{}>", tag, parent))]
+    UnexpectedElementError { tag: String, parent: String },
+    /// An element is missing an attribute that it requires
+    #[snafu(display("<{}> element is missing the '{}' attribute", tag, attribute))]
+    MissingAttributeError {
+        tag: String,
+        attribute: &'static str,
+    },
+    /// The show, role, os or site of a package is not valid
+    #[snafu(display("Invalid coords for {}-{}: {}", package, version, source))]
+    InvalidCoordsError {
+        package: String,
+        version: String,
+        source: CoordsError,
+    },
+}
+
+/// A versionpin read from packages.xml, along with its withs, in order
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct XmlVersionPin {
+    pub versionpin: VersionPin,
+    pub withs: Vec<String>,
+}
+
+/// Read the packages.xml at the supplied path, returning a versionpin
+/// for each package element in the file.
+///
+/// # Arguments
+///
+/// * `path` - The path to the packages.xml
+///
+/// # Returns
+/// * Result
+///   * `Ok`  - Vector of XmlVersionPin, in document order
+///   * `Err` - ReadXmlError
+pub fn read_xml<P: AsRef<Path>>(path: P) -> Result<Vec<XmlVersionPin>, ReadXmlError> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path).context(ReadFileError {
+        path: path.display().to_string(),
+    })?;
+    parse_xml(&contents)
+}
+
+/// Parse the contents of a packages.xml, returning a versionpin for each
+/// package element. The level of each versionpin is the show, or the
+/// enclosing sequence or shot beneath it, its role is the enclosing role
+/// (or any), and its platform and site are taken from the `os` and `site`
+/// attributes (or any).
+///
+/// # Arguments
+///
+/// * `xml` - The contents of a packages.xml
+///
+/// # Returns
+/// * Result
+///   * `Ok`  - Vector of XmlVersionPin, in document order
+///   * `Err` - ReadXmlError
+pub fn parse_xml(xml: &str) -> Result<Vec<XmlVersionPin>, ReadXmlError> {
+    let doc = roxmltree::Document::parse(xml).context(XmlSyntaxError)?;
+    let show = doc.root_element();
+    if show.tag_name().name() != "show" {
+        return UnexpectedElementError {
+            tag: show.tag_name().name(),
+            parent: "document",
+        }
+        .fail();
+    }
+    let level = required_attribute(&show, "name")?;
+    let mut pins = Vec::new();
+    parse_level(&show, level, &mut pins)?;
+    Ok(pins)
+}
+
+// Parse the packages and roles of a show, sequence or shot element, along
+// with the sequences of a show or the shots of a sequence.
+fn parse_level(
+    node: &roxmltree::Node<'_, '_>,
+    level: &str,
+    pins: &mut Vec<XmlVersionPin>,
+) -> Result<(), ReadXmlError> {
+    let nested = match node.tag_name().name() {
+        "show" => Some(("sequences", "sequence")),
+        "sequence" => Some(("shots", "shot")),
+        _ => None,
+    };
+    for child in node.children().filter(|node| node.is_element()) {
+        match child.tag_name().name() {
+            "packages" => parse_packages(&child, level, "any", pins)?,
+            "roles" => {
+                for role in child.children().filter(|node| node.is_element()) {
+                    if role.tag_name().name() != "role" {
+                        return unexpected(&role, &child);
+                    }
+                    let role_name = required_attribute(&role, "name")?;
+                    for packages in role.children().filter(|node| node.is_element()) {
+                        if packages.tag_name().name() != "packages" {
+                            return unexpected(&packages, &role);
+                        }
+                        parse_packages(&packages, level, role_name, pins)?;
+                    }
+                }
+            }
+            tag if nested.map(|(parent, _)| parent) == Some(tag) => {
+                for grandchild in child.children().filter(|node| node.is_element()) {
+                    if nested.map(|(_, tag)| tag) != Some(grandchild.tag_name().name()) {
+                        return unexpected(&grandchild, &child);
+                    }
+                    let name = required_attribute(&grandchild, "name")?;
+                    parse_level(&grandchild, &format!("{}.{}", level, name), pins)?;
+                }
+            }
+            _ => return unexpected(&child, node),
+        }
+    }
+    Ok(())
+}
+
+// Retrieve an attribute which the element may not do without
+fn required_attribute<'a>(
+    node: &roxmltree::Node<'a, '_>,
+    attribute: &'static str,
+) -> Result<&'a str, ReadXmlError> {
+    match node.attribute(attribute) {
+        Some(value) => Ok(value),
+        None => MissingAttributeError {
+            tag: node.tag_name().name(),
+            attribute,
+        }
+        .fail(),
+    }
+}
+
+fn unexpected<T>(
+    node: &roxmltree::Node<'_, '_>,
+    parent: &roxmltree::Node<'_, '_>,
+) -> Result<T, ReadXmlError> {
+    UnexpectedElementError {
+        tag: node.tag_name().name(),
+        parent: parent.tag_name().name(),
+    }
+    .fail()
+}
+
+// Parse the package elements within a packages element. A package without
+// withs may be written as a packages element bearing the package's attributes
+// (see `can_serialize_show_no_withs`), so we accept that form as well.
+fn parse_packages(
+    packages: &roxmltree::Node<'_, '_>,
+    level: &str,
+    role: &str,
+    pins: &mut Vec<XmlVersionPin>,
+) -> Result<(), ReadXmlError> {
+    if packages.has_attribute("name") {
+        pins.push(parse_package(packages, level, role)?);
+        return Ok(());
+    }
+    for package in packages.children().filter(|node| node.is_element()) {
+        if package.tag_name().name() != "package" {
+            return unexpected(&package, packages);
+        }
+        pins.push(parse_package(&package, level, role)?);
+    }
+    Ok(())
+}
+
+// Convert a package element into an XmlVersionPin
+fn parse_package(
+    package: &roxmltree::Node<'_, '_>,
+    level: &str,
+    role: &str,
+) -> Result<XmlVersionPin, ReadXmlError> {
+    let name = required_attribute(package, "name")?;
+    let version = required_attribute(package, "version")?;
+    let platform = package.attribute("os").unwrap_or("any");
+    let site = package.attribute("site").unwrap_or("any");
+    let distribution = Distribution::from_parts(name, version).context(InvalidCoordsError {
+        package: name,
+        version,
+    })?;
+    let coords =
+        Coords::try_from_parts(level, role, platform, site).context(InvalidCoordsError {
+            package: name,
+            version,
+        })?;
+    let mut withs = Vec::new();
+    for with in package.children().filter(|node| node.is_element()) {
+        match with.tag_name().name() {
+            // the serializer has been known to write withs rather than with
+            "with" | "withs" => withs.push(required_attribute(&with, "package")?.to_string()),
+            _ => return unexpected(&with, package),
+        }
+    }
+    Ok(XmlVersionPin {
+        versionpin: VersionPin::from_parts(distribution, coords),
+        withs,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -558,6 +1076,175 @@ mod tests {
             "<show name=\"FACILITY\">\n  <packages>\n    <package name=\"maya\" version=\"2018.5.1\">\n      <withs package=\"xerces\"/>\n      <withs package=\"mayapipeline\"/>\n    </package>\n    <package name=\"houdini\" version=\"17.5.432\">\n      <withs package=\"houd_pipeline\"/>\n      <withs package=\"houd_camera\"/>\n    </package>\n  </packages>\n  <roles>\n    <role name=\"model\">\n      <packages>\n        <package name=\"maya\" version=\"2020.1.0\">\n          <withs package=\"xerces\"/>\n          <withs package=\"mayapipeline\"/>\n          <withs package=\"modelpipeline\"/>\n        </package>\n        <package name=\"zbrush\" version=\"14\"/>\n        <package name=\"atomic\" version=\"1.2.3\">\n          <withs package=\"vray\"/>\n          <withs package=\"vray_for_maya\"/>\n        </package>\n      </packages>\n    </role>\n  </roles>\n</show>"
         );
     }
+
+    #[test]
+    fn can_parse_show_with_roles() {
+        let xml = r#"<show name="dev01">
+  <packages>
+    <package name="maya" version="2018.5.1" os="cent7_64">
+      <with package="xerces"/>
+      <with package="mayapipeline"/>
+    </package>
+    <package name="houdini" version="17.5.432"/>
+  </packages>
+  <roles>
+    <role name="model">
+      <packages>
+        <package name="zbrush" version="14" site="portland"/>
+      </packages>
+    </role>
+  </roles>
+</show>"#;
+        let pins = parse_xml(xml).expect("unable to parse xml");
+        let expect = vec![
+            (
+                "maya-2018.5.1@l:dev01.p:cent7_64",
+                vec!["xerces", "mayapipeline"],
+            ),
+            ("houdini-17.5.432@l:dev01", vec![]),
+            ("zbrush-14@l:dev01.r:model.s:portland", vec![]),
+        ];
+        assert_eq!(pins.len(), expect.len());
+        for (pin, (versionpin, withs)) in pins.iter().zip(expect) {
+            assert_eq!(pin.versionpin, versionpin.parse::<VersionPin>().unwrap());
+            assert_eq!(pin.withs, withs);
+        }
+    }
+
+    #[test]
+    fn can_parse_collapsed_packages() {
+        let xml = r#"<show name="facility">
+  <packages name="maya" version="2018.5.1"/>
+  <packages name="houdini" version="17.5.432"/>
+</show>"#;
+        let pins = parse_xml(xml).expect("unable to parse xml");
+        let expect = vec!["maya-2018.5.1", "houdini-17.5.432"]
+            .into_iter()
+            .map(|pin| pin.parse::<VersionPin>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pins.into_iter()
+                .map(|pin| pin.versionpin)
+                .collect::<Vec<_>>(),
+            expect
+        );
+    }
+
+    #[test]
+    fn parse_rejects_unexpected_elements_and_missing_attributes() {
+        let result = parse_xml(r#"<show name="dev01"><pins/></show>"#);
+        assert!(matches!(
+            result,
+            Err(ReadXmlError::UnexpectedElementError { .. })
+        ));
+        let result =
+            parse_xml(r#"<show name="dev01"><packages><package name="maya"/></packages></show>"#);
+        assert!(matches!(
+            result,
+            Err(ReadXmlError::MissingAttributeError { .. })
+        ));
+    }
+
+    #[test]
+    fn can_parse_sequences_and_shots() {
+        let xml = r#"<show name="dev01">
+  <packages>
+    <package name="maya" version="2018.5.1"/>
+  </packages>
+  <sequences>
+    <sequence name="rd">
+      <packages>
+        <package name="maya" version="2019.0.1"/>
+      </packages>
+      <shots>
+        <shot name="9999">
+          <roles>
+            <role name="anim">
+              <packages>
+                <package name="maya" version="2020.1.0">
+                  <with package="animpipeline"/>
+                </package>
+              </packages>
+            </role>
+          </roles>
+        </shot>
+      </shots>
+    </sequence>
+  </sequences>
+</show>"#;
+        let pins = parse_xml(xml).expect("unable to parse xml");
+        let expect = vec![
+            ("maya-2018.5.1@l:dev01", vec![]),
+            ("maya-2019.0.1@l:dev01.rd", vec![]),
+            ("maya-2020.1.0@l:dev01.rd.9999.r:anim", vec!["animpipeline"]),
+        ];
+        assert_eq!(pins.len(), expect.len());
+        for (pin, (versionpin, withs)) in pins.iter().zip(expect) {
+            assert_eq!(pin.versionpin, versionpin.parse::<VersionPin>().unwrap());
+            assert_eq!(pin.withs, withs);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_shots_outside_of_sequences() {
+        let xml = r#"<show name="dev01"><shots><shot name="9999"/></shots></show>"#;
+        assert!(matches!(
+            parse_xml(xml),
+            Err(ReadXmlError::UnexpectedElementError { .. })
+        ));
+    }
+
+    // write the pins to xml beneath the supplied show, and read them back
+    fn round_trip(show: &str, pins: &[XmlVersionPin]) -> Vec<XmlVersionPin> {
+        let mut xml_show = Show::new(show);
+        for pin in pins {
+            xml_show.add_versionpin(pin);
+        }
+        let xml = ToXml::to_pretty_string(&ToXml::new().to_xml(xml_show));
+        let mut result = parse_xml(&xml).expect("unable to parse xml");
+        result.sort();
+        result
+    }
+
+    fn xml_pin(versionpin: &str, withs: &[&str]) -> XmlVersionPin {
+        XmlVersionPin {
+            versionpin: versionpin.parse().unwrap(),
+            withs: withs.iter().map(|w| w.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn can_round_trip_show() {
+        let mut pins = vec![
+            xml_pin(
+                "maya-2018.5.1@l:dev01.p:cent7_64",
+                &["xerces", "mayapipeline"],
+            ),
+            xml_pin("houdini-17.5.432@l:dev01.s:portland", &[]),
+            xml_pin("maya-2020.1.0@l:dev01.r:model", &["modelpipeline"]),
+            xml_pin("zbrush-14@l:dev01.r:model", &[]),
+        ];
+        pins.sort();
+        assert_eq!(round_trip("dev01", &pins), pins);
+    }
+
+    #[test]
+    fn can_round_trip_sequences_and_shots() {
+        let mut pins = vec![
+            xml_pin("maya-2018.5.1@l:dev01", &[]),
+            xml_pin("maya-2019.0.1@l:dev01.rd", &["xerces"]),
+            xml_pin("maya-2019.0.2@l:dev01.rd.r:anim", &[]),
+            xml_pin("maya-2020.1.0@l:dev01.rd.9999", &[]),
+            xml_pin(
+                "maya-2020.1.1@l:dev01.rd.9999.r:anim.p:cent7_64",
+                &["animpipeline"],
+            ),
+            xml_pin("houdini-17.5.432@l:dev01.rd.0001", &[]),
+            xml_pin("nuke-12.0@l:dev01.aa.0010.r:comp", &[]),
+        ];
+        pins.sort();
+        assert_eq!(round_trip("dev01", &pins), pins);
+    }
 }
 
 pub mod xml {
@@ -567,7 +1254,7 @@ pub mod xml {
     use crate::packrat::PackratDb;
     use crate::LtreeSearchMode;
     use crate::SearchAttribute;
-    use crate::{Platform, Role, Site};
+    use crate::VersionPin;
     use log;
     use snafu::{ResultExt, Snafu};
     use std::fs::File;
@@ -608,85 +1295,25 @@ pub mod xml {
             .isolate_facility(true)
             .level(show.as_str())
             .search_mode(LtreeSearchMode::Descendant)
-            .order_by(vec![SearchAttribute::Role, SearchAttribute::Package])
+            .order_by(vec![
+                SearchAttribute::Level,
+                SearchAttribute::Role,
+                SearchAttribute::Package,
+            ])
             .query()
             .context(PackybaraDbQueryError {
                 msg: "Unable to get version pins from db",
             })?;
-        // get a list of withs for the show
-        // iterate through version pins, creating appropriate data structure for outgoing
+        // iterate through version pins, nesting each beneath the sequence, shot
+        // and role elements matching its coords
         let mut show = io::Show::new(show);
-        let mut last_role: Option<Role> = None;
-        let mut role_packages = Vec::new();
         for row in vpins {
-            let package = row.distribution.package();
-            let version = row.distribution.version();
-            let site = row.coords.site();
-            let platform = row.coords.platform();
-            // TODO: do not know how seq/shot is handled in packages.xml
-            //let level = row.coords.level(); // hwo is this handled?
-            let role = row.coords.role();
-            let mut package = io::Package::new(package, version, None, None);
-            if let Some(withs) = row.withs {
-                for with in withs {
-                    package.add_with(io::With::new(with));
-                }
-            }
-            if site != &Site::Any {
-                package.set_site(Some(site.to_string()));
-            }
-            if platform != &Platform::Any {
-                package.set_os(Some(platform.to_string()));
-            }
-            if role != &Role::Any {
-                let role_str = role.to_string();
-                log::debug!("role != Any - role: {}", role_str.as_str());
-                // if our last iter was a role
-                if let Some(ref last) = last_role {
-                    let last_role_str = last.to_string();
-                    log::debug!("extracted last role = {}", last_role_str.as_str());
-                    // if the current role is the same as the last
-                    // role, we add the package into our list
-                    if role == last {
-                        role_packages.push(package);
-                    } else {
-                        // otherwise we drain the list of saved packages,
-                        // adding them in to the current role
-                        let mut role_tag = io::Role::new(last_role_str);
-                        for pkg in role_packages.drain(..) {
-                            role_tag.add_package(pkg);
-                        }
-                        log::debug!("adding {} role to show", &role_tag.name);
-                        show.add_role(role_tag);
-                        // and we push the current package into our list,
-                        // which is now empty
-                        role_packages.push(package);
-                    }
-                } else {
-                    log::debug!("last role was None");
-                    // in the case where our last iter was NOT a role
-                    // role packages should be zero sized
-                    assert_eq!(role_packages.len(), 0);
-                    // add in the package
-                    role_packages.push(package);
-                }
-                log::debug!("setting role: {} to last_role", role.to_string().as_str());
-                last_role = Some(role.clone());
-            } else {
-                // handle remaining
-                if let Some(role) = last_role {
-                    let role_str = role.to_string();
-                    log::debug!("role == Any. last_role = {}", role_str.as_str());
-                    let mut role_tag = io::Role::new(role_str);
-                    for pkg in role_packages.drain(..) {
-                        role_tag.add_package(pkg);
-                    }
-                    log::debug!("adding {} role to show", &role_tag.name);
-                    show.add_role(role_tag);
-                }
-                show.add_package(package);
-                last_role = None;
-            }
+            let pin = io::XmlVersionPin {
+                versionpin: VersionPin::from_parts(row.distribution, row.coords),
+                withs: row.withs.unwrap_or_default(),
+            };
+            log::debug!("adding {} to show", &pin.versionpin);
+            show.add_versionpin(&pin);
         }
         // serialise to disk
         let xml_writer = io::ToXml::new();
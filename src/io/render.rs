@@ -0,0 +1,398 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! Render query results as JSON, JSON lines, CSV, TSV, YAML or a table.
+//!
+//! Each result type implements `Record`, which describes the row as a flat
+//! list of named fields. The field names are stable: they do not follow the
+//! internal representation of the row (eg `Coords` is always flattened into
+//! `level`, `role`, `platform` and `site`), so they may be relied upon by
+//! scripts consuming the output.
+//!
+//! ```rust,no_run
+//! use packybara::io::render::{Format, Render};
+//! use packybara::packrat::PackratDb;
+//! use packybara::traits::PBFind;
+//! use packybara::PackratConfig;
+//! # fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut client = PackratConfig::load()?.connect()?;
+//! let mut db = PackratDb::new(&mut client);
+//! let pins = db.find_all_versionpins().package("maya").query()?;
+//! pins.render(Format::Csv, &mut std::io::stdout())?;
+//! # Ok(())
+//! # }
+//! ```
+use crate::types::{IdType, LongIdType};
+use prettytable::{format, Cell, Row, Table};
+use serde::ser::{SerializeMap, Serializer};
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+use std::io::Write;
+use strum_macros::{AsRefStr, Display, EnumString, IntoStaticStr};
+
+/// Error type returned when rendering records
+#[derive(Debug, Snafu)]
+pub enum RenderError {
+    /// Unable to serialize the records as json
+    #[snafu(display("Error rendering json: {}", source))]
+    JsonError { source: serde_json::Error },
+    /// Unable to serialize the records as yaml
+    #[snafu(display("Error rendering yaml: {}", source))]
+    YamlError { source: serde_yaml::Error },
+    /// Unable to serialize the records as csv or tsv
+    #[snafu(display("Error rendering {}: {}", format, source))]
+    CsvError { format: Format, source: csv::Error },
+    /// Unable to write the rendered records
+    #[snafu(display("Error writing {}: {}", format, source))]
+    IoError {
+        format: Format,
+        source: std::io::Error,
+    },
+}
+
+pub type RenderResult<T, E = RenderError> = std::result::Result<T, E>;
+
+/// The formats which records may be rendered in
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr, Display, IntoStaticStr, Clone, Copy,
+)]
+pub enum Format {
+    #[strum(serialize = "table", serialize = "Table", to_string = "table")]
+    Table,
+    #[strum(
+        serialize = "json",
+        serialize = "Json",
+        serialize = "JSON",
+        to_string = "json"
+    )]
+    Json,
+    #[strum(
+        serialize = "jsonl",
+        serialize = "json-lines",
+        serialize = "ndjson",
+        to_string = "jsonl"
+    )]
+    JsonLines,
+    #[strum(
+        serialize = "csv",
+        serialize = "Csv",
+        serialize = "CSV",
+        to_string = "csv"
+    )]
+    Csv,
+    #[strum(
+        serialize = "tsv",
+        serialize = "Tsv",
+        serialize = "TSV",
+        to_string = "tsv"
+    )]
+    Tsv,
+    #[strum(
+        serialize = "yaml",
+        serialize = "yml",
+        serialize = "Yaml",
+        serialize = "YAML",
+        to_string = "yaml"
+    )]
+    Yaml,
+}
+
+/// The value of a single field of a Record
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum FieldValue {
+    Int(i64),
+    Text(String),
+    List(Vec<String>),
+    Null,
+}
+
+impl FieldValue {
+    /// Retrieve the value as text, as used by the delimited formats and
+    /// tables. Lists are comma separated and Null is empty.
+    pub fn to_text(&self) -> String {
+        match self {
+            Self::Int(value) => value.to_string(),
+            Self::Text(value) => value.clone(),
+            Self::List(values) => values.join(","),
+            Self::Null => String::new(),
+        }
+    }
+}
+
+impl Serialize for FieldValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Int(value) => serializer.serialize_i64(*value),
+            Self::Text(value) => serializer.serialize_str(value),
+            Self::List(values) => values.serialize(serializer),
+            Self::Null => serializer.serialize_none(),
+        }
+    }
+}
+
+impl From<IdType> for FieldValue {
+    fn from(value: IdType) -> Self {
+        Self::Int(value as i64)
+    }
+}
+
+impl From<LongIdType> for FieldValue {
+    fn from(value: LongIdType) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<String> for FieldValue {
+    fn from(value: String) -> Self {
+        Self::Text(value)
+    }
+}
+
+impl From<&str> for FieldValue {
+    fn from(value: &str) -> Self {
+        Self::Text(value.to_string())
+    }
+}
+
+impl From<Vec<String>> for FieldValue {
+    fn from(values: Vec<String>) -> Self {
+        Self::List(values)
+    }
+}
+
+impl<T: Into<FieldValue>> From<Option<T>> for FieldValue {
+    fn from(value: Option<T>) -> Self {
+        value.map(Into::into).unwrap_or(Self::Null)
+    }
+}
+
+/// A query result which may be rendered as a flat list of named fields
+pub trait Record {
+    /// The stable names of the fields, in order
+    fn field_names() -> &'static [&'static str];
+    /// The values of the fields, in the same order as `field_names`
+    fn field_values(&self) -> Vec<FieldValue>;
+}
+
+// serializes a record as a map from field name to value, preserving order
+struct RecordMap<'a, T>(&'a T);
+
+impl<T: Record> Serialize for RecordMap<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let names = T::field_names();
+        let mut map = serializer.serialize_map(Some(names.len()))?;
+        for (name, value) in names.iter().zip(self.0.field_values()) {
+            map.serialize_entry(name, &value)?;
+        }
+        map.end()
+    }
+}
+
+/// Render a collection of records in one of the supported formats
+pub trait Render {
+    /// Render the records to the writer
+    ///
+    /// # Arguments
+    /// * `format` - The Format to render the records in
+    /// * `writer` - The destination of the rendered records
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - Unit
+    ///   * `Err` - RenderError
+    fn render<W: Write>(&self, format: Format, writer: &mut W) -> RenderResult<()>;
+
+    /// Render the records to a String
+    ///
+    /// # Arguments
+    /// * `format` - The Format to render the records in
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - String
+    ///   * `Err` - RenderError
+    fn render_to_string(&self, format: Format) -> RenderResult<String> {
+        let mut buffer = Vec::new();
+        self.render(format, &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+impl<T: Record> Render for [T] {
+    fn render<W: Write>(&self, format: Format, writer: &mut W) -> RenderResult<()> {
+        match format {
+            Format::Table => render_table(self, writer),
+            Format::Json => {
+                let records = self.iter().map(RecordMap).collect::<Vec<_>>();
+                serde_json::to_writer_pretty(&mut *writer, &records).context(JsonError)?;
+                writeln!(writer).context(IoError { format })
+            }
+            Format::JsonLines => {
+                for record in self {
+                    serde_json::to_writer(&mut *writer, &RecordMap(record)).context(JsonError)?;
+                    writeln!(writer).context(IoError { format })?;
+                }
+                Ok(())
+            }
+            Format::Csv => render_delimited(self, b',', format, writer),
+            Format::Tsv => render_delimited(self, b'\t', format, writer),
+            Format::Yaml => {
+                let records = self.iter().map(RecordMap).collect::<Vec<_>>();
+                serde_yaml::to_writer(&mut *writer, &records).context(YamlError)?;
+                writeln!(writer).context(IoError { format })
+            }
+        }
+    }
+}
+
+impl<T: Record> Render for Vec<T> {
+    fn render<W: Write>(&self, format: Format, writer: &mut W) -> RenderResult<()> {
+        self.as_slice().render(format, writer)
+    }
+}
+
+fn render_delimited<T: Record, W: Write>(
+    records: &[T],
+    delimiter: u8,
+    format: Format,
+    writer: &mut W,
+) -> RenderResult<()> {
+    let mut csv_writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(writer);
+    csv_writer
+        .write_record(T::field_names())
+        .context(CsvError { format })?;
+    for record in records {
+        csv_writer
+            .write_record(record.field_values().iter().map(FieldValue::to_text))
+            .context(CsvError { format })?;
+    }
+    csv_writer.flush().context(IoError { format })
+}
+
+fn render_table<T: Record, W: Write>(records: &[T], writer: &mut W) -> RenderResult<()> {
+    fn row<I: Iterator<Item = String>>(values: I) -> Row {
+        Row::new(values.map(|value| Cell::new(&value)).collect())
+    }
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_CLEAN);
+    table.set_titles(row(T::field_names()
+        .iter()
+        .map(|name| name.replace('_', " ").to_uppercase())));
+    for record in records {
+        table.add_row(row(record.field_values().iter().map(FieldValue::to_text)));
+    }
+    table
+        .print(writer)
+        .context(IoError {
+            format: Format::Table,
+        })
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    struct Pin {
+        id: IdType,
+        distribution: &'static str,
+        withs: Option<Vec<String>>,
+    }
+
+    impl Record for Pin {
+        fn field_names() -> &'static [&'static str] {
+            &["id", "distribution", "withs"]
+        }
+        fn field_values(&self) -> Vec<FieldValue> {
+            vec![
+                self.id.into(),
+                self.distribution.into(),
+                self.withs.clone().into(),
+            ]
+        }
+    }
+
+    fn pins() -> Vec<Pin> {
+        vec![
+            Pin {
+                id: 1,
+                distribution: "maya-2018.sp3",
+                withs: Some(vec!["mayapipeline".to_string(), "gpuz".to_string()]),
+            },
+            Pin {
+                id: 2,
+                distribution: "houdini-17.5",
+                withs: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn can_parse_format() {
+        assert_eq!(Format::from_str("ndjson").unwrap(), Format::JsonLines);
+        assert_eq!(Format::from_str("yml").unwrap(), Format::Yaml);
+        assert_eq!(Format::Tsv.to_string(), "tsv");
+    }
+
+    #[test]
+    fn can_render_json() {
+        let json = pins().render_to_string(Format::Json).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value[0]["distribution"], "maya-2018.sp3");
+        assert_eq!(value[0]["withs"][1], "gpuz");
+        assert_eq!(value[1]["id"], 2);
+        assert!(value[1]["withs"].is_null());
+    }
+
+    #[test]
+    fn json_lines_preserve_field_order() {
+        let jsonl = pins().render_to_string(Format::JsonLines).unwrap();
+        let lines = jsonl.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[1],
+            r#"{"id":2,"distribution":"houdini-17.5","withs":null}"#
+        );
+    }
+
+    #[test]
+    fn can_render_csv_and_tsv() {
+        let csv = pins().render_to_string(Format::Csv).unwrap();
+        assert_eq!(
+            csv,
+            "id,distribution,withs\n1,maya-2018.sp3,\"mayapipeline,gpuz\"\n2,houdini-17.5,\n"
+        );
+        let tsv = pins().render_to_string(Format::Tsv).unwrap();
+        assert_eq!(
+            tsv.lines().nth(1).unwrap(),
+            "1\tmaya-2018.sp3\tmayapipeline,gpuz"
+        );
+    }
+
+    #[test]
+    fn versionpin_fields_are_flattened() {
+        let pin = crate::VersionPin::from_str("maya-2018.sp3@l:dev01.r:model").unwrap();
+        let csv = [pin].render_to_string(Format::Csv).unwrap();
+        assert_eq!(
+            csv,
+            "distribution,package,version,level,role,platform,site\n\
+             maya-2018.sp3,maya,2018.sp3,dev01,model,any,any\n"
+        );
+    }
+
+    #[test]
+    fn can_render_yaml() {
+        let yaml = pins().render_to_string(Format::Yaml).unwrap();
+        let value: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(value[0]["distribution"].as_str(), Some("maya-2018.sp3"));
+        assert_eq!(value[1]["id"].as_i64(), Some(2));
+    }
+}
@@ -11,11 +11,16 @@ pub use prelude::*;
 pub mod distribution;
 pub use distribution::Distribution;
 pub mod db;
+pub use db::async_packrat;
+pub use db::config::PackratConfig;
+pub use db::memory::MemoryStore;
 pub use db::packrat;
+pub use db::pool::{PackratPool, PooledPackratDb};
 pub use db::search_attribute::{
     JoinMode, LtreeSearchMode, OrderDirection, SearchAttribute, SearchMode,
 };
-pub use db::{OrderLevelBy, OrderPlatformBy, OrderRevisionBy, OrderRoleBy};
+pub use db::sqlite::SqliteStore;
+pub use db::{AsOf, OrderChangeBy, OrderLevelBy, OrderPlatformBy, OrderRevisionBy, OrderRoleBy};
 pub mod coords;
 pub use coords::Coords;
 pub mod version_pin;
@@ -36,8 +41,11 @@ pub mod site;
 pub use site::Site;
 pub mod role;
 pub use role::Role;
+pub mod resolver;
+pub use resolver::PinResolver;
 pub mod coords_error;
 pub mod io;
+pub mod registry;
 pub mod traits;
 pub mod types;
 pub(crate) mod utils;
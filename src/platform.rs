@@ -7,11 +7,13 @@
  * permission of Jonathan Gerber
  *******************************************************/
 use crate::coords_error::*;
-use serde::{Deserialize, Serialize};
+use crate::registry::{self, RegisteredName};
+use serde::de::{self, Deserialize, Deserializer};
+use serde::{Serialize, Serializer};
 use snafu::ResultExt;
 use std::convert::TryFrom;
+use std::fmt;
 use std::str::FromStr;
-use strum_macros::{AsRefStr, Display, EnumString, IntoStaticStr};
 
 /// Platform models the os variants available to us.
 ///
@@ -53,39 +55,125 @@ use strum_macros::{AsRefStr, Display, EnumString, IntoStaticStr};
 /// let platform_string = format!("{}", platform);
 /// assert_eq!(platform_string.as_str(), "cent7_64");
 /// ```
-#[derive(
-    Debug,
-    Display,
-    EnumString,
-    AsRefStr,
-    IntoStaticStr,
-    PartialEq,
-    Eq,
-    PartialOrd,
-    Ord,
-    Clone,
-    Serialize,
-    Deserialize,
-    Hash,
-)]
+///
+/// # Registered Platforms
+///
+/// Platforms which are not built in may be registered at runtime via the
+/// `registry` module. These are accepted by `from_str` and represented
+/// by the `Registered` variant, which holds the name interned by the registry.
+/// Only the registry may construct that name, so a registered platform can't be
+/// built from an arbitrary string.
+///
+/// Built in platforms serialize by their variant names (eg `"Cent7"`), and
+/// registered platforms by their names.
+///
+/// ## Example
+///
+/// ```
+/// use packybara::{registry, Platform};
+/// use std::str::FromStr;
+///
+/// assert!(Platform::from_str("rocky9_64").is_err());
+/// registry::register_platform("rocky9_64");
+/// let platform = Platform::from_str("rocky9_64").unwrap();
+/// assert!(matches!(platform, Platform::Registered(_)));
+/// assert_eq!(platform.as_ref(), "rocky9_64");
+/// ```
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub enum Platform {
-    #[strum(
-        serialize = "any",
-        serialize = "Any",
-        serialize = "ANY",
-        to_string = "any"
-    )]
     Any,
-    #[strum(serialize = "win_xp", to_string = "win_xp")]
     WinXp,
-    #[strum(serialize = "win_10", to_string = "win_10")]
     Win10,
-    #[strum(serialize = "cent5_64", to_string = "cent5_64")]
     Cent5,
-    #[strum(serialize = "cent6_64", to_string = "cent6_64")]
     Cent6,
-    #[strum(serialize = "cent7_64", to_string = "cent7_64")]
     Cent7,
+    /// A platform registered at runtime
+    Registered(RegisteredName),
+}
+
+impl FromStr for Platform {
+    type Err = strum::ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "any" | "Any" | "ANY" => Ok(Platform::Any),
+            "win_xp" => Ok(Platform::WinXp),
+            "win_10" => Ok(Platform::Win10),
+            "cent5_64" => Ok(Platform::Cent5),
+            "cent6_64" => Ok(Platform::Cent6),
+            "cent7_64" => Ok(Platform::Cent7),
+            _ => registry::platform(input)
+                .map(Platform::Registered)
+                .ok_or(strum::ParseError::VariantNotFound),
+        }
+    }
+}
+
+impl AsRef<str> for Platform {
+    fn as_ref(&self) -> &str {
+        match *self {
+            Platform::Any => "any",
+            Platform::WinXp => "win_xp",
+            Platform::Win10 => "win_10",
+            Platform::Cent5 => "cent5_64",
+            Platform::Cent6 => "cent6_64",
+            Platform::Cent7 => "cent7_64",
+            Platform::Registered(ref name) => name.as_str(),
+        }
+    }
+}
+
+impl From<Platform> for &'static str {
+    fn from(platform: Platform) -> Self {
+        match platform {
+            Platform::Registered(name) => name.as_str(),
+            Platform::Any => "any",
+            Platform::WinXp => "win_xp",
+            Platform::Win10 => "win_10",
+            Platform::Cent5 => "cent5_64",
+            Platform::Cent6 => "cent6_64",
+            Platform::Cent7 => "cent7_64",
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_ref())
+    }
+}
+
+// Built in platforms serialize by their variant names, as they did when Platform
+// derived Serialize, and registered platforms by their registered names
+impl Serialize for Platform {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = match *self {
+            Platform::Any => "Any",
+            Platform::WinXp => "WinXp",
+            Platform::Win10 => "Win10",
+            Platform::Cent5 => "Cent5",
+            Platform::Cent6 => "Cent6",
+            Platform::Cent7 => "Cent7",
+            Platform::Registered(ref name) => name.as_str(),
+        };
+        serializer.serialize_str(name)
+    }
+}
+
+impl<'de> Deserialize<'de> for Platform {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        match name.as_str() {
+            "Any" => Ok(Platform::Any),
+            "WinXp" => Ok(Platform::WinXp),
+            "Win10" => Ok(Platform::Win10),
+            "Cent5" => Ok(Platform::Cent5),
+            "Cent6" => Ok(Platform::Cent6),
+            "Cent7" => Ok(Platform::Cent7),
+            _ => Platform::from_str(&name)
+                .map_err(|_| de::Error::custom(format!("unknown platform '{}'", name))),
+        }
+    }
 }
 
 impl TryFrom<&str> for Platform {
@@ -158,4 +246,50 @@ mod tests {
         assert!(Platform::Cent5 < Platform::Cent6);
         assert!(Platform::Cent6 < Platform::Cent7);
     }
+
+    #[test]
+    fn can_construct_registered_platform() {
+        assert!(Platform::from_str("platform_test_rocky9_64").is_err());
+        registry::register_platform("platform_test_rocky9_64");
+        let platform = Platform::from_str("platform_test_rocky9_64").unwrap();
+        assert_eq!(
+            platform,
+            Platform::Registered(registry::platform("platform_test_rocky9_64").unwrap())
+        );
+        assert!(Platform::Cent7 < platform);
+        let pstr: &'static str = platform.into();
+        assert_eq!(pstr, "platform_test_rocky9_64");
+    }
+
+    #[test]
+    fn registered_platform_round_trips_through_serde() {
+        registry::register_platform("platform_test_rocky8_64");
+        let platform = Platform::from_str("platform_test_rocky8_64").unwrap();
+        let json = serde_json::to_string(&platform).unwrap();
+        assert_eq!(json, "\"platform_test_rocky8_64\"");
+        let restored: Platform = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, platform);
+    }
+
+    #[test]
+    fn built_in_platforms_serialize_by_variant_name() {
+        let platforms = vec![
+            Platform::Any,
+            Platform::WinXp,
+            Platform::Win10,
+            Platform::Cent5,
+            Platform::Cent6,
+            Platform::Cent7,
+        ];
+        let json = serde_json::to_string(&platforms).unwrap();
+        assert_eq!(json, r#"["Any","WinXp","Win10","Cent5","Cent6","Cent7"]"#);
+        let restored: Vec<Platform> = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, platforms);
+    }
+
+    #[test]
+    fn unregistered_platform_fails_to_deserialize() {
+        let platform: Result<Platform, _> = serde_json::from_str("\"platform_test_unregistered\"");
+        assert!(platform.is_err());
+    }
 }
@@ -0,0 +1,250 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! The registry keeps track of the sites and platforms which are known
+//! at runtime, in addition to those built into the `Site` and `Platform`
+//! enums. Registered names are accepted by `Site::from_str` and
+//! `Platform::from_str`, and are represented by the `Registered` variant.
+//!
+//! The registry may be populated from the database (see
+//! `PackratDb::load_registry`), from a toml config file of the form
+//!
+//! ```toml
+//! sites = ["london", "sydney"]
+//! platforms = ["rocky9_64"]
+//! ```
+//!
+//! or directly via `register_sites` and `register_platforms`.
+//!
+//! Registered names are interned by leaking them, so that `Site` and `Platform`
+//! may continue to convert into `&'static str`. Each distinct name is leaked
+//! once; registering a name again hands back the name interned the first time,
+//! so reloading the registry does not grow memory.
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use snafu::{ResultExt, Snafu};
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use std::sync::RwLock;
+
+lazy_static! {
+    // Registered names are leaked, once each, so that Site and Platform
+    // may continue to convert into &'static str.
+    static ref SITES: RwLock<HashSet<&'static str>> = RwLock::new(HashSet::new());
+    static ref PLATFORMS: RwLock<HashSet<&'static str>> = RwLock::new(HashSet::new());
+}
+
+/// Error type returned from the registry
+#[derive(Debug, Snafu)]
+pub enum RegistryError {
+    /// Unable to read the config file
+    #[snafu(display("Unable to read registry config {}: {}", path, source))]
+    ReadConfigError {
+        path: String,
+        source: std::io::Error,
+    },
+    /// Unable to parse the config file
+    #[snafu(display("Unable to parse registry config {}: {}", path, source))]
+    ParseConfigError {
+        path: String,
+        source: toml::de::Error,
+    },
+}
+
+pub type RegistryResult<T, E = RegistryError> = std::result::Result<T, E>;
+
+/// The name of a registered site or platform, as interned by the registry.
+/// Only the registry constructs a RegisteredName, so a `Site::Registered` or
+/// `Platform::Registered` always names a site or platform which has been
+/// registered.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub struct RegisteredName(&'static str);
+
+impl RegisteredName {
+    /// Retrieve the interned name
+    pub fn as_str(&self) -> &'static str {
+        self.0
+    }
+}
+
+impl AsRef<str> for RegisteredName {
+    fn as_ref(&self) -> &str {
+        self.0
+    }
+}
+
+impl fmt::Display for RegisteredName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// The contents of a registry config file
+#[derive(Debug, PartialEq, Eq, Default, Deserialize)]
+pub struct RegistryConfig {
+    #[serde(default)]
+    pub sites: Vec<String>,
+    #[serde(default)]
+    pub platforms: Vec<String>,
+}
+
+// insert a name into one of the registries, returning the interned name.
+// The name is only leaked if it has not been registered before
+fn register(registry: &RwLock<HashSet<&'static str>>, name: &str) -> RegisteredName {
+    if let Some(existing) = lookup(registry, name) {
+        return existing;
+    }
+    let mut registry = registry.write().unwrap();
+    // we may have lost a race between releasing the read lock and
+    // acquiring the write lock
+    if let Some(existing) = registry.get(name) {
+        return RegisteredName(existing);
+    }
+    let interned: &'static str = Box::leak(name.to_string().into_boxed_str());
+    registry.insert(interned);
+    RegisteredName(interned)
+}
+
+fn lookup(registry: &RwLock<HashSet<&'static str>>, name: &str) -> Option<RegisteredName> {
+    registry
+        .read()
+        .unwrap()
+        .get(name)
+        .copied()
+        .map(RegisteredName)
+}
+
+fn names(registry: &RwLock<HashSet<&'static str>>) -> Vec<String> {
+    let mut names = registry
+        .read()
+        .unwrap()
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+/// Register a site, returning the interned name
+pub fn register_site(name: &str) -> RegisteredName {
+    register(&SITES, name)
+}
+
+/// Register a platform, returning the interned name
+pub fn register_platform(name: &str) -> RegisteredName {
+    register(&PLATFORMS, name)
+}
+
+/// Register a number of sites at once
+pub fn register_sites<I, S>(names: I)
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    for name in names {
+        register_site(name.as_ref());
+    }
+}
+
+/// Register a number of platforms at once
+pub fn register_platforms<I, S>(names: I)
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    for name in names {
+        register_platform(name.as_ref());
+    }
+}
+
+/// Retrieve the interned name of a registered site, if it has been registered
+pub fn site(name: &str) -> Option<RegisteredName> {
+    lookup(&SITES, name)
+}
+
+/// Retrieve the interned name of a registered platform, if it has been registered
+pub fn platform(name: &str) -> Option<RegisteredName> {
+    lookup(&PLATFORMS, name)
+}
+
+/// Retrieve a sorted list of the registered sites
+pub fn sites() -> Vec<String> {
+    names(&SITES)
+}
+
+/// Retrieve a sorted list of the registered platforms
+pub fn platforms() -> Vec<String> {
+    names(&PLATFORMS)
+}
+
+/// Register the sites and platforms described by a RegistryConfig
+pub fn load_config(config: &RegistryConfig) {
+    register_sites(&config.sites);
+    register_platforms(&config.platforms);
+}
+
+/// Register the sites and platforms described by the toml config file
+/// at `path`.
+///
+/// # Arguments
+/// * `path` - The path to the config file
+///
+/// # Returns
+/// * Result
+///   * `Ok`  - The RegistryConfig which was loaded
+///   * `Err` - `RegistryError`
+pub fn load_config_file<P: AsRef<Path>>(path: P) -> RegistryResult<RegistryConfig> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path).context(ReadConfigError {
+        path: path.display().to_string(),
+    })?;
+    let config: RegistryConfig = toml::from_str(&contents).context(ParseConfigError {
+        path: path.display().to_string(),
+    })?;
+    load_config(&config);
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_register_site() {
+        assert_eq!(site("registry_test_site"), None);
+        let name = register_site("registry_test_site");
+        assert_eq!(name.as_str(), "registry_test_site");
+        assert_eq!(site("registry_test_site"), Some(name));
+        assert!(sites().contains(&"registry_test_site".to_string()));
+        // registering twice hands back the same interned str
+        assert!(std::ptr::eq(
+            name.as_str(),
+            register_site("registry_test_site").as_str()
+        ));
+    }
+
+    #[test]
+    fn can_parse_config() {
+        let config: RegistryConfig =
+            toml::from_str("sites = [\"registry_test_london\"]\n").unwrap();
+        assert_eq!(
+            config,
+            RegistryConfig {
+                sites: vec!["registry_test_london".to_string()],
+                platforms: Vec::new(),
+            }
+        );
+        load_config(&config);
+        assert_eq!(
+            site("registry_test_london").map(|name| name.as_str()),
+            Some("registry_test_london")
+        );
+        assert_eq!(platform("registry_test_london"), None);
+    }
+}
@@ -0,0 +1,437 @@
+/*******************************************************
+ * Copyright (C) 2019,2020 Jonathan Gerber <jlgerber@gmail.com>
+ *
+ * This file is part of packybara.
+ *
+ * packybara can not be copied and/or distributed without the express
+ * permission of Jonathan Gerber
+ *******************************************************/
+//! The PinResolver is an in-memory counterpart to the `find_distribution_and_withs`
+//! database function. Given a set of VersionPins (and their withs), it answers
+//! the question "which distribution wins at these coords" without a connection
+//! to postgres.
+//!
+//! A pin is a candidate for a request if each of its coordinates is equal to,
+//! or an ancestor of, the requested coordinate (eg `facility` contains `dev01`,
+//! `model` contains `model_beta`, `any` contains `cent7_64`). Amongst the candidates,
+//! the most specific pin wins, with specificity compared by level first,
+//! then role, then platform, and finally site.
+use crate::db::find_all::versionpins::FindAllVersionPinsRow;
+use crate::{Coords, Distribution, Level, Platform, Role, Site, VersionPin};
+use snafu::Snafu;
+
+/// Error type returned from the PinResolver
+#[derive(Debug, Snafu)]
+pub enum PinResolverError {
+    /// No pin for the package is visible from the requested coords
+    #[snafu(display("No versionpin found for {} at {}", package, coords))]
+    NoMatchingPin { package: String, coords: String },
+}
+
+pub type PinResolverResult<T, E = PinResolverError> = std::result::Result<T, E>;
+
+/// A VersionPin paired with its ordered list of with packages
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PinEntry {
+    pub versionpin: VersionPin,
+    pub withs: Vec<String>,
+}
+
+impl PinEntry {
+    /// New up a PinEntry
+    ///
+    /// # Arguments
+    /// * `versionpin` - The VersionPin
+    /// * `withs` - The with packages of the VersionPin, in order
+    ///
+    /// # Returns
+    /// * PinEntry instance
+    pub fn new(versionpin: VersionPin, withs: Vec<String>) -> Self {
+        Self { versionpin, withs }
+    }
+}
+
+/// Resolves packages against an in-memory set of VersionPins, using the same
+/// specificity rules as the `find_distribution_and_withs` database function.
+///
+/// # Example
+///
+/// ```rust
+/// use packybara::{Coords, Distribution, PinResolver, VersionPin};
+///
+/// let mut resolver = PinResolver::new();
+/// resolver
+///     .add_pin(
+///         VersionPin::new(Distribution::new("maya-2018.sp3").unwrap())
+///             .build()
+///             .unwrap(),
+///         vec![],
+///     )
+///     .add_pin(
+///         VersionPin::new(Distribution::new("maya-2020.1").unwrap())
+///             .level("dev01")
+///             .unwrap()
+///             .build()
+///             .unwrap(),
+///         vec!["mayapipeline".to_string()],
+///     );
+/// let coords = Coords::new().level("dev01.rd.0001").unwrap().build();
+/// let pin = resolver.resolve("maya", &coords).unwrap();
+/// assert_eq!(pin.distribution.distribution(), "maya-2020.1");
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct PinResolver {
+    pins: Vec<PinEntry>,
+}
+
+impl PinResolver {
+    /// New up an empty PinResolver
+    pub fn new() -> Self {
+        Self { pins: Vec::new() }
+    }
+
+    /// New up a PinResolver from the rows returned by `FindAllVersionPins::query`,
+    /// which makes it easy to snapshot the database once and resolve offline.
+    ///
+    /// # Arguments
+    /// * `rows` - A vector of FindAllVersionPinsRow
+    ///
+    /// # Returns
+    /// * PinResolver instance
+    pub fn from_rows(rows: Vec<FindAllVersionPinsRow>) -> Self {
+        let pins = rows
+            .into_iter()
+            .map(|row| {
+                PinEntry::new(
+                    VersionPin::from_parts(row.distribution, row.coords),
+                    row.withs.unwrap_or_default(),
+                )
+            })
+            .collect();
+        Self { pins }
+    }
+
+    /// Add a VersionPin, along with its withs, to the resolver
+    ///
+    /// # Arguments
+    /// * `versionpin` - The VersionPin
+    /// * `withs` - The with packages of the VersionPin, in order
+    ///
+    /// # Returns
+    /// * A mutable reference to Self
+    pub fn add_pin(&mut self, versionpin: VersionPin, withs: Vec<String>) -> &mut Self {
+        self.pins.push(PinEntry::new(versionpin, withs));
+        self
+    }
+
+    /// Retrieve the pins known to the resolver
+    pub fn pins(&self) -> &[PinEntry] {
+        &self.pins
+    }
+
+    /// The number of pins known to the resolver
+    pub fn len(&self) -> usize {
+        self.pins.len()
+    }
+
+    /// Indicates whether the resolver has any pins
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty()
+    }
+
+    /// Find the VersionPin which wins for `package` at `coords`.
+    ///
+    /// # Arguments
+    /// * `package` - The name of the package
+    /// * `coords` - The location in package space we are resolving from
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - Reference to the winning VersionPin
+    ///   * `Err` - `PinResolverError`
+    pub fn resolve(&self, package: &str, coords: &Coords) -> PinResolverResult<&VersionPin> {
+        self.resolve_entry(package, coords)
+            .map(|entry| &entry.versionpin)
+    }
+
+    /// Find the winning VersionPin, along with its withs, for `package` at `coords`.
+    ///
+    /// # Arguments
+    /// * `package` - The name of the package
+    /// * `coords` - The location in package space we are resolving from
+    ///
+    /// # Returns
+    /// * Result
+    ///   * `Ok`  - Reference to the winning PinEntry
+    ///   * `Err` - `PinResolverError`
+    pub fn resolve_entry(&self, package: &str, coords: &Coords) -> PinResolverResult<&PinEntry> {
+        most_specific(self.pins.iter(), package, coords, |entry| {
+            (&entry.versionpin.distribution, &entry.versionpin.coords)
+        })
+        .ok_or_else(|| PinResolverError::NoMatchingPin {
+            package: package.to_string(),
+            coords: coords.to_string(),
+        })
+    }
+}
+
+/// Select the most specific of `items` for `package` which is visible from `coords`.
+/// Shared by the PinResolver and the historical (`as_of`) versionpin queries.
+///
+/// # Arguments
+/// * `items` - The candidates
+/// * `package` - The name of the package
+/// * `coords` - The location in package space we are resolving from
+/// * `parts` - Retrieves the distribution and coords of a candidate
+///
+/// # Returns
+/// * The winning candidate, if any
+pub(crate) fn most_specific<'r, T, I, F>(
+    items: I,
+    package: &str,
+    coords: &Coords,
+    parts: F,
+) -> Option<&'r T>
+where
+    I: Iterator<Item = &'r T>,
+    F: Fn(&T) -> (&Distribution, &Coords),
+{
+    let mut winner: Option<(&T, Specificity)> = None;
+    for item in items {
+        let (distribution, pin_coords) = parts(item);
+        if distribution.package() != package || !coords_contain(pin_coords, coords) {
+            continue;
+        }
+        let specificity = Specificity::from_coords(pin_coords);
+        // strictly greater, so that the first of two identical pins wins
+        let is_better = match winner {
+            Some((_, ref best)) => specificity > *best,
+            None => true,
+        };
+        if is_better {
+            winner = Some((item, specificity));
+        }
+    }
+    winner.map(|(item, _)| item)
+}
+
+// The ranking of a pin. Fields are compared in declaration order, so level
+// outranks role, which outranks platform, which outranks site.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Specificity {
+    level: usize,
+    role: usize,
+    platform: usize,
+    site: usize,
+}
+
+impl Specificity {
+    fn from_coords(coords: &Coords) -> Self {
+        Self {
+            level: level_parts(&coords.level).len(),
+            role: role_parts(&coords.role).len(),
+            platform: if coords.platform == Platform::Any {
+                0
+            } else {
+                1
+            },
+            site: if coords.site == Site::Any { 0 } else { 1 },
+        }
+    }
+}
+
+// Break a level into its components. Facility is the root, and has none.
+fn level_parts(level: &Level) -> Vec<String> {
+    match level {
+        Level::Facility => Vec::new(),
+        Level::LevelSpec(_) => level.to_string().split('.').map(String::from).collect(),
+    }
+}
+
+// Break a role into its components. Any is the root, and has none.
+fn role_parts(role: &Role) -> Vec<String> {
+    match role {
+        Role::Any => Vec::new(),
+        Role::Named { name } => name.split('_').map(String::from).collect(),
+    }
+}
+
+// Is `pin` equal to, or an ancestor of, `target` in every dimension?
+pub(crate) fn coords_contain(pin: &Coords, target: &Coords) -> bool {
+    level_parts(&target.level).starts_with(&level_parts(&pin.level))
+        && role_parts(&target.role).starts_with(&role_parts(&pin.role))
+        && (pin.platform == Platform::Any || pin.platform == target.platform)
+        && (pin.site == Site::Any || pin.site == target.site)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pin(dist: &str, level: &str, role: &str, platform: &str, site: &str) -> VersionPin {
+        VersionPin::from_parts(
+            Distribution::new(dist).unwrap(),
+            Coords::try_from_parts(level, role, platform, site).unwrap(),
+        )
+    }
+
+    fn coords(level: &str, role: &str, platform: &str, site: &str) -> Coords {
+        Coords::try_from_parts(level, role, platform, site).unwrap()
+    }
+
+    fn resolver() -> PinResolver {
+        let mut resolver = PinResolver::new();
+        resolver
+            .add_pin(
+                pin("maya-2018.sp3", "facility", "any", "any", "any"),
+                vec![],
+            )
+            .add_pin(
+                pin("maya-2019.1", "dev01", "any", "any", "any"),
+                vec!["mayapipeline".to_string()],
+            )
+            .add_pin(pin("maya-2019.2", "dev01", "model", "any", "any"), vec![])
+            .add_pin(
+                pin("maya-2020.1", "facility", "model_beta", "any", "any"),
+                vec![],
+            )
+            .add_pin(pin("maya-2019.3", "dev01.rd", "any", "any", "any"), vec![])
+            .add_pin(
+                pin("maya-2019.4", "dev01", "any", "cent7_64", "any"),
+                vec![],
+            )
+            .add_pin(
+                pin("maya-2019.5", "dev01", "any", "any", "portland"),
+                vec![],
+            )
+            .add_pin(pin("houdini-17.5", "facility", "any", "any", "any"), vec![]);
+        resolver
+    }
+
+    #[test]
+    fn can_resolve_facility() {
+        let resolver = resolver();
+        let result = resolver
+            .resolve("maya", &coords("facility", "any", "any", "any"))
+            .unwrap();
+        assert_eq!(result.distribution.distribution(), "maya-2018.sp3");
+    }
+
+    #[test]
+    fn can_resolve_show_from_shot() {
+        let resolver = resolver();
+        let result = resolver
+            .resolve_entry("maya", &coords("dev01.ab.0001", "any", "any", "any"))
+            .unwrap();
+        assert_eq!(result.versionpin.distribution.distribution(), "maya-2019.1");
+        assert_eq!(result.withs, vec!["mayapipeline".to_string()]);
+    }
+
+    #[test]
+    fn can_resolve_sequence_from_shot() {
+        let resolver = resolver();
+        let result = resolver
+            .resolve("maya", &coords("dev01.rd.0001", "any", "any", "any"))
+            .unwrap();
+        assert_eq!(result.distribution.distribution(), "maya-2019.3");
+    }
+
+    #[test]
+    fn level_outranks_role() {
+        let resolver = resolver();
+        let result = resolver
+            .resolve("maya", &coords("dev01", "model_beta", "any", "any"))
+            .unwrap();
+        assert_eq!(result.distribution.distribution(), "maya-2019.2");
+    }
+
+    #[test]
+    fn can_resolve_subrole_at_facility() {
+        let resolver = resolver();
+        let result = resolver
+            .resolve("maya", &coords("facility", "model_beta", "any", "any"))
+            .unwrap();
+        assert_eq!(result.distribution.distribution(), "maya-2020.1");
+    }
+
+    #[test]
+    fn role_prefix_must_be_whole_component() {
+        let resolver = resolver();
+        let result = resolver
+            .resolve("maya", &coords("dev01", "modeling", "any", "any"))
+            .unwrap();
+        assert_eq!(result.distribution.distribution(), "maya-2019.1");
+    }
+
+    #[test]
+    fn platform_outranks_site() {
+        let resolver = resolver();
+        let result = resolver
+            .resolve("maya", &coords("dev01", "any", "cent7_64", "portland"))
+            .unwrap();
+        assert_eq!(result.distribution.distribution(), "maya-2019.4");
+    }
+
+    #[test]
+    fn can_resolve_site() {
+        let resolver = resolver();
+        let result = resolver
+            .resolve("maya", &coords("dev01", "any", "cent6_64", "portland"))
+            .unwrap();
+        assert_eq!(result.distribution.distribution(), "maya-2019.5");
+    }
+
+    #[test]
+    fn resolve_filters_by_package() {
+        let resolver = resolver();
+        let result = resolver
+            .resolve("houdini", &coords("dev01", "model", "any", "any"))
+            .unwrap();
+        assert_eq!(result.distribution.distribution(), "houdini-17.5");
+    }
+
+    #[test]
+    fn resolve_unknown_package_is_err() {
+        let resolver = resolver();
+        let result = resolver.resolve("nuke", &coords("dev01", "any", "any", "any"));
+        assert!(result.is_err());
+    }
+
+    // Requires a packrat database, configured via PackratConfig. Run with
+    // `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn resolver_matches_find_distribution_and_withs() {
+        use crate::db::traits::PBFind;
+        use crate::packrat::PackratDb;
+
+        let mut client = crate::PackratConfig::load().unwrap().connect().unwrap();
+        let mut db = PackratDb::new(&mut client);
+        let rows = db.find_all_versionpins().query().unwrap();
+        let pins = rows
+            .iter()
+            .map(|row| (row.distribution.package().to_string(), row.coords.clone()))
+            .collect::<Vec<_>>();
+        let resolver = PinResolver::from_rows(rows);
+        for (package, coords) in pins {
+            let (level, role, platform, site) = (
+                coords.level.to_string(),
+                coords.role.to_string(),
+                coords.platform.to_string(),
+                coords.site.to_string(),
+            );
+            let expected = db
+                .find_versionpin(&package)
+                .level(&level)
+                .role(&role)
+                .platform(&platform)
+                .site(&site)
+                .query()
+                .unwrap();
+            let result = resolver.resolve_entry(&package, &coords).unwrap();
+            assert_eq!(result.versionpin.distribution, expected.distribution);
+            assert_eq!(result.versionpin.coords, expected.coords);
+            assert_eq!(result.withs, expected.withs.unwrap_or_default());
+        }
+    }
+}
@@ -7,11 +7,13 @@
  * permission of Jonathan Gerber
  *******************************************************/
 use crate::coords_error::*;
-use serde::{Deserialize, Serialize};
+use crate::registry::{self, RegisteredName};
+use serde::de::{self, Deserialize, Deserializer};
+use serde::{Serialize, Serializer};
 use snafu::ResultExt;
 use std::convert::TryFrom;
+use std::fmt;
 use std::str::FromStr;
-use strum_macros::{AsRefStr, Display, EnumString, IntoStaticStr};
 
 /// Site models our facility locations recognized by package management
 // # Converting from &str
@@ -52,66 +54,119 @@ use strum_macros::{AsRefStr, Display, EnumString, IntoStaticStr};
 /// let site_string = format!("{}", site);
 /// assert_eq!(site_string.as_str(), "vancouver");
 /// ```
-#[derive(
-    Debug,
-    Display,
-    EnumString,
-    AsRefStr,
-    IntoStaticStr,
-    PartialEq,
-    Eq,
-    PartialOrd,
-    Ord,
-    Clone,
-    Serialize,
-    Deserialize,
-    Hash,
-)]
+///
+/// # Registered Sites
+///
+/// Sites which are not built in may be registered at runtime via the
+/// `registry` module. These are accepted by `from_str` and represented
+/// by the `Registered` variant, which holds the name interned by the registry.
+/// Only the registry may construct that name, so a registered site can't be
+/// built from an arbitrary string.
+///
+/// Built in sites serialize by their variant names (eg `"Playa"`), and
+/// registered sites by their names. Both deserialize via `from_str`.
+///
+/// ## Example
+///
+/// ```
+/// use packybara::{registry, Site};
+/// use std::str::FromStr;
+///
+/// assert!(Site::from_str("london").is_err());
+/// registry::register_site("london");
+/// let site = Site::from_str("london").unwrap();
+/// assert!(matches!(site, Site::Registered(_)));
+/// assert_eq!(site.as_ref(), "london");
+/// ```
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub enum Site {
-    #[strum(
-        serialize = "any",
-        serialize = "Any",
-        serialize = "ANY",
-        to_string = "any"
-    )]
     Any,
-    #[strum(
-        serialize = "playa",
-        serialize = "Playa",
-        serialize = "venice",
-        serialize = "Venice",
-        serialize = "pv",
-        to_string = "playa"
-    )]
     Playa,
-    #[strum(
-        serialize = "vancouver",
-        serialize = "Vancouver",
-        serialize = "bc",
-        to_string = "vancouver"
-    )]
     Vancouver,
-    #[strum(
-        serialize = "portland",
-        serialize = "Portland",
-        serialize = "pd",
-        to_string = "portland"
-    )]
     Portland,
-    #[strum(
-        serialize = "hyderabad",
-        serialize = "Hyderabad",
-        serialize = "hb",
-        to_string = "hyderabad"
-    )]
     Hyderabad,
-    #[strum(
-        serialize = "montreal",
-        serialize = "Montreal",
-        serialize = "mt",
-        to_string = "montreal"
-    )]
     Montreal,
+    /// A site registered at runtime
+    Registered(RegisteredName),
+}
+
+impl FromStr for Site {
+    type Err = strum::ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "any" | "Any" | "ANY" => Ok(Site::Any),
+            "playa" | "Playa" | "venice" | "Venice" | "pv" => Ok(Site::Playa),
+            "vancouver" | "Vancouver" | "bc" => Ok(Site::Vancouver),
+            "portland" | "Portland" | "pd" => Ok(Site::Portland),
+            "hyderabad" | "Hyderabad" | "hb" => Ok(Site::Hyderabad),
+            "montreal" | "Montreal" | "mt" => Ok(Site::Montreal),
+            _ => registry::site(input)
+                .map(Site::Registered)
+                .ok_or(strum::ParseError::VariantNotFound),
+        }
+    }
+}
+
+impl AsRef<str> for Site {
+    fn as_ref(&self) -> &str {
+        match *self {
+            Site::Any => "any",
+            Site::Playa => "playa",
+            Site::Vancouver => "vancouver",
+            Site::Portland => "portland",
+            Site::Hyderabad => "hyderabad",
+            Site::Montreal => "montreal",
+            Site::Registered(ref name) => name.as_str(),
+        }
+    }
+}
+
+impl From<Site> for &'static str {
+    fn from(site: Site) -> Self {
+        match site {
+            Site::Registered(name) => name.as_str(),
+            Site::Any => "any",
+            Site::Playa => "playa",
+            Site::Vancouver => "vancouver",
+            Site::Portland => "portland",
+            Site::Hyderabad => "hyderabad",
+            Site::Montreal => "montreal",
+        }
+    }
+}
+
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_ref())
+    }
+}
+
+// Built in sites serialize by their variant names, as they did when Site
+// derived Serialize, and registered sites by their registered names
+impl Serialize for Site {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = match *self {
+            Site::Any => "Any",
+            Site::Playa => "Playa",
+            Site::Vancouver => "Vancouver",
+            Site::Portland => "Portland",
+            Site::Hyderabad => "Hyderabad",
+            Site::Montreal => "Montreal",
+            Site::Registered(ref name) => name.as_str(),
+        };
+        serializer.serialize_str(name)
+    }
+}
+
+// `from_str` accepts the variant names, so both built in and registered sites
+// deserialize through it
+
+impl<'de> Deserialize<'de> for Site {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Site::from_str(&name).map_err(|_| de::Error::custom(format!("unknown site '{}'", name)))
+    }
 }
 
 impl TryFrom<&str> for Site {
@@ -269,4 +324,53 @@ mod tests {
         assert!(Site::Hyderabad < Site::Montreal);
         assert!(Site::Montreal > Site::Playa);
     }
+
+    #[test]
+    fn can_construct_registered_site() {
+        assert!(Site::from_str("site_test_sydney").is_err());
+        registry::register_site("site_test_sydney");
+        let site = Site::from_str("site_test_sydney").unwrap();
+        assert_eq!(
+            site,
+            Site::Registered(registry::site("site_test_sydney").unwrap())
+        );
+        assert_eq!(format!("{}", site).as_str(), "site_test_sydney");
+        let pstr: &'static str = site.into();
+        assert_eq!(pstr, "site_test_sydney");
+    }
+
+    #[test]
+    fn registered_site_round_trips_through_serde() {
+        registry::register_site("site_test_perth");
+        let site = Site::from_str("site_test_perth").unwrap();
+        let json = serde_json::to_string(&site).unwrap();
+        assert_eq!(json, "\"site_test_perth\"");
+        let restored: Site = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, site);
+    }
+
+    #[test]
+    fn built_in_sites_serialize_by_variant_name() {
+        let sites = vec![
+            Site::Any,
+            Site::Playa,
+            Site::Vancouver,
+            Site::Portland,
+            Site::Hyderabad,
+            Site::Montreal,
+        ];
+        let json = serde_json::to_string(&sites).unwrap();
+        assert_eq!(
+            json,
+            r#"["Any","Playa","Vancouver","Portland","Hyderabad","Montreal"]"#
+        );
+        let restored: Vec<Site> = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, sites);
+    }
+
+    #[test]
+    fn unregistered_site_fails_to_deserialize() {
+        let site: Result<Site, _> = serde_json::from_str("\"site_test_unregistered\"");
+        assert!(site.is_err());
+    }
 }
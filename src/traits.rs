@@ -7,9 +7,13 @@
  * permission of Jonathan Gerber
  *******************************************************/
 //! reexport the db traits
+use crate::db::async_packrat::AsyncTransaction;
+use crate::db::memory::{MemoryStoreError, MemoryTransaction};
 pub use crate::db::traits::*;
 pub use crate::packrat::PackratDbError;
 use postgres::Transaction;
+use std::future::Future;
+use std::pin::Pin;
 
 /// Transaction handler provides default implementation of commit trait, along
 /// with helper functions.
@@ -51,4 +55,105 @@ pub trait TransactionHandler<'a> {
         self.reset_result_cnt();
         Ok(result)
     }
+
+    /// Roll back the internal transaction, discarding its changes. Used by
+    /// the builders' `preview` methods in place of `commit`.
+    fn rollback(&mut self) -> Result<(), Self::Error> {
+        self.take_tx().rollback()?;
+        self.reset_result_cnt();
+        Ok(())
+    }
+}
+
+/// The async counterpart of TransactionHandler, implemented by the builders
+/// constructed from an AsyncPackratDb transaction.
+pub trait AsyncTransactionHandler<'a>: Send {
+    type Error: std::convert::From<tokio_postgres::error::Error> + Send;
+
+    /// retrieve an Option<&AsyncTransaction>. The expectation is that the
+    /// implementer will have a tx: Option<AsyncTransaction> field
+    fn tx(&mut self) -> Option<&mut AsyncTransaction<'a>>;
+
+    /// take the transaction from the impl, via self.tx.take()
+    fn take_tx(&mut self) -> AsyncTransaction<'a>;
+
+    /// Retrieve the number of results of the operation.
+    fn get_result_cnt(&self) -> u64;
+
+    /// zero out the result count
+    fn reset_result_cnt(&mut self);
+
+    /// Given a user and comment, commit the internal transaction, returning the
+    /// number of results, if successful, or an error if not.
+    /// The default implementation, as provided, should suffice.
+    fn commit<'s>(
+        &'s mut self,
+        author: &'s str,
+        comment: &'s str,
+    ) -> Pin<Box<dyn Future<Output = Result<u64, Self::Error>> + Send + 's>>
+    where
+        'a: 's,
+    {
+        Box::pin(async move {
+            let tx = self.take_tx();
+            tx.execute(
+                "INSERT INTO REVISION (author, comment) VALUES ($1, $2)",
+                &[&author, &comment],
+            )
+            .await?;
+            tx.commit().await?;
+
+            let result = self.get_result_cnt();
+            self.reset_result_cnt();
+            Ok(result)
+        })
+    }
+
+    /// Roll back the internal transaction, discarding its changes.
+    fn rollback<'s>(
+        &'s mut self,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Self::Error>> + Send + 's>>
+    where
+        'a: 's,
+    {
+        Box::pin(async move {
+            self.take_tx().rollback().await?;
+            self.reset_result_cnt();
+            Ok(())
+        })
+    }
+}
+
+/// The MemoryStore counterpart of TransactionHandler, implemented by the
+/// builders constructed from a MemoryTransaction.
+pub trait MemoryTransactionHandler<'a> {
+    /// retrieve an Option<&mut MemoryTransaction>. The expectation is that the
+    /// implementer will have a tx: Option<MemoryTransaction> field
+    fn tx(&mut self) -> Option<&mut MemoryTransaction<'a>>;
+
+    /// take the transaction from the impl, via self.tx.take()
+    fn take_tx(&mut self) -> MemoryTransaction<'a>;
+
+    /// Retrieve the number of results of the operation.
+    fn get_result_cnt(&self) -> u64;
+
+    /// zero out the result count
+    fn reset_result_cnt(&mut self);
+
+    /// Given a user and comment, commit the internal transaction, returning the
+    /// number of results. The MemoryStore has no failure modes at commit time,
+    /// but a Result is returned for parity with TransactionHandler.
+    fn commit(&mut self, author: &str, comment: &str) -> Result<u64, MemoryStoreError> {
+        self.take_tx().commit(author, comment);
+        let result = self.get_result_cnt();
+        self.reset_result_cnt();
+        Ok(result)
+    }
+
+    /// Roll back the internal transaction. Its changes are discarded along
+    /// with the transaction.
+    fn rollback(&mut self) {
+        self.take_tx();
+        self.reset_result_cnt();
+    }
 }
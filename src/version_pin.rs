@@ -13,8 +13,11 @@
 use crate::coords::Coords;
 use crate::coords_error::*;
 use crate::distribution::Distribution;
+use crate::io::render::{FieldValue, Record};
 use crate::{Level, Platform, Role, Site};
 use std::convert::{From, TryInto};
+use std::fmt;
+use std::str::FromStr;
 
 pub struct VersionPinBuilder {
     distribution: Distribution,
@@ -107,6 +110,32 @@ pub struct VersionPin {
     pub coords: Coords,
 }
 
+impl Record for VersionPin {
+    fn field_names() -> &'static [&'static str] {
+        &[
+            "distribution",
+            "package",
+            "version",
+            "level",
+            "role",
+            "platform",
+            "site",
+        ]
+    }
+
+    fn field_values(&self) -> Vec<FieldValue> {
+        vec![
+            self.distribution.distribution().into(),
+            self.distribution.package().into(),
+            self.distribution.version().into(),
+            self.coords.level().to_string().into(),
+            self.coords.role().to_string().into(),
+            self.coords.platform().to_string().into(),
+            self.coords.site().to_string().into(),
+        ]
+    }
+}
+
 impl VersionPin {
     /// Construct a VersionPin from a Distribution and a  Coords
     pub fn from_parts(distribution: Distribution, coords: Coords) -> Self {
@@ -124,6 +153,34 @@ impl VersionPin {
     }
 }
 
+impl fmt::Display for VersionPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.distribution, self.coords)
+    }
+}
+
+impl FromStr for VersionPin {
+    type Err = CoordsError;
+
+    /// Parse a VersionPin from the notation `<distribution>@<coords>`,
+    /// where coords are described by `Coords::from_str`
+    /// (eg `maya-2018.sp3@l:dev01.r:model`). The coords, along with the
+    /// `@`, may be omitted entirely, in which case the pin is
+    /// located at facility / any.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut pieces = input.trim().splitn(2, '@');
+        let distribution = Distribution::new(pieces.next().unwrap_or(""))?;
+        let coords = match pieces.next() {
+            Some(coords) => Coords::from_str(coords)?,
+            None => Coords::new().build(),
+        };
+        Ok(VersionPin {
+            distribution,
+            coords,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -175,4 +232,41 @@ mod tests {
         let expect = exp().unwrap();
         assert_eq!(vp, expect);
     }
+
+    #[test]
+    fn can_parse_from_str() {
+        let vp =
+            VersionPin::from_str("maya-2018.sp3@l:bayou.r:model.p:cent7_64.s:portland").unwrap();
+        let expect = VersionPin::from_parts(
+            Distribution::new("maya-2018.sp3").unwrap(),
+            Coords::try_from_parts("bayou", "model", "cent7_64", "portland").unwrap(),
+        );
+        assert_eq!(vp, expect);
+    }
+
+    #[test]
+    fn can_parse_from_str_with_defaults() {
+        let vp = VersionPin::from_str("maya-2018.sp3").unwrap();
+        assert_eq!(
+            vp.to_string(),
+            "maya-2018.sp3@level:facility.role:any.platform:any.site:any"
+        );
+        let vp = VersionPin::from_str("maya-2018.sp3@l:bayou").unwrap();
+        assert_eq!(
+            vp.to_string(),
+            "maya-2018.sp3@level:bayou.role:any.platform:any.site:any"
+        );
+    }
+
+    #[test]
+    fn can_round_trip_through_display() {
+        let vp = VersionPin::from_str("maya-2018.sp3@site:portland.level:dev01.rd.9999").unwrap();
+        assert_eq!(VersionPin::from_str(&vp.to_string()).unwrap(), vp);
+    }
+
+    #[test]
+    fn parse_bad_distribution_is_err() {
+        let vp = VersionPin::from_str("maya2018@l:bayou");
+        assert!(vp.is_err());
+    }
 }